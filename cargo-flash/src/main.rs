@@ -12,18 +12,19 @@ use std::{
     time::Instant,
 };
 use structopt::StructOpt;
+use tracing_subscriber::EnvFilter;
 
 use probe_rs::{
+    config::Config,
     coresight::access_ports::AccessPortError,
     probe::{
-        daplink,
-        debug_probe::{DebugProbe, DebugProbeError, DebugProbeType, MasterProbe},
+        debug_probe::{open_probe, DebugProbeError, MasterProbe},
         flash::{
             download::{FileDownloader, Format},
             flasher::AlgorithmSelectionError,
         },
-        protocol::WireProtocol,
-        stlink,
+        protocol::{AttachMethod, WireProtocol},
+        watch,
     },
     session::Session,
     target::{info::ChipInfo, Target},
@@ -64,7 +65,10 @@ struct Opt {
 }
 
 fn main() {
-    pretty_env_logger::init();
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
     match main_try() {
         Ok(_) => (),
         Err(e) => {
@@ -172,29 +176,15 @@ fn main_try() -> Result<(), failure::Error> {
 
     println!("    {} {}", "Flashing".green().bold(), path_str);
 
-    let mut list = daplink::tools::list_daplink_devices();
-    list.extend(stlink::tools::list_stlink_devices());
+    let mut list = watch::list_probes();
 
     let device = list
         .pop()
         .ok_or_else(|| format_err!("no supported probe was found"))?;
 
-    let mut probe = match device.probe_type {
-        DebugProbeType::DAPLink => {
-            let mut link = daplink::DAPLink::new_from_probe_info(&device)?;
-
-            link.attach(Some(WireProtocol::Swd))?;
-
-            MasterProbe::from_specific_probe(link)
-        }
-        DebugProbeType::STLink => {
-            let mut link = stlink::STLink::new_from_probe_info(&device)?;
-
-            link.attach(Some(WireProtocol::Swd))?;
-
-            MasterProbe::from_specific_probe(link)
-        }
-    };
+    let mut link = open_probe(&device)?;
+    link.attach(Some(WireProtocol::Swd), AttachMethod::NoReset)?;
+    let mut probe = MasterProbe::from_specific_probe(link);
 
     let target_override = opt
         .chip_description_path
@@ -210,7 +200,13 @@ fn main_try() -> Result<(), failure::Error> {
         })
         .transpose()?;
 
-    let strategy = if let Some(name) = opt.chip {
+    // The chip can also be given by the probe-rs configuration or `PROBE_RS_CHIP`.
+    let chip = match opt.chip {
+        Some(chip) => Some(chip),
+        None => Config::load()?.target,
+    };
+
+    let strategy = if let Some(name) = chip {
         SelectionStrategy::Name(name)
     } else {
         SelectionStrategy::ChipInfo(ChipInfo::read_from_rom_table(&mut probe)?)
@@ -218,7 +214,18 @@ fn main_try() -> Result<(), failure::Error> {
     let target = if let Some(target) = target_override {
         target
     } else {
-        select_target(&strategy)?
+        let target = select_target(&strategy)?;
+        if let SelectionStrategy::Name(name) = &strategy {
+            if !target.name.eq_ignore_ascii_case(name) {
+                println!(
+                    "    {} There is no definition for {}, using the definition of {}.",
+                    "Note".cyan().bold(),
+                    name,
+                    target.name
+                );
+            }
+        }
+        target
     };
 
     let flash_algorithm = match target.flash_algorithm.clone() {
@@ -233,13 +240,14 @@ fn main_try() -> Result<(), failure::Error> {
 
     let mm = session.target.memory_map.clone();
     let fd = FileDownloader::new();
-    fd.download_file(
-        &mut session,
-        std::path::Path::new(&path_str.to_string().as_str()),
-        Format::Elf,
-        &mm,
-    )
-    .map_err(|e| format_err!("failed to flash {}: {}", path_str, e))?;
+    let report = fd
+        .download_file(
+            &mut session,
+            std::path::Path::new(&path_str.to_string().as_str()),
+            Format::Elf,
+            &mm,
+        )
+        .map_err(|e| format_err!("failed to flash {}: {}", path_str, e))?;
 
     // Stop timer.
     let elapsed = instant.elapsed();
@@ -248,8 +256,9 @@ fn main_try() -> Result<(), failure::Error> {
         "Finished".green().bold(),
         elapsed.as_millis() as f32 / 1000.0
     );
+    println!("    {} {}", "Summary".cyan().bold(), report);
 
-    session.target.core.reset(&mut session.probe)?;
+    session.target_reset(session.target.reset_type)?;
 
     Ok(())
 }
@@ -261,29 +270,11 @@ pub fn with_device<F>(n: usize, target: Target, f: F) -> Result<(), DownloadErro
 where
     for<'a> F: FnOnce(Session) -> Result<(), DownloadError>,
 {
-    let device = {
-        let mut list = daplink::tools::list_daplink_devices();
-        list.extend(stlink::tools::list_stlink_devices());
-
-        list.remove(n)
-    };
-
-    let probe = match device.probe_type {
-        DebugProbeType::DAPLink => {
-            let mut link = daplink::DAPLink::new_from_probe_info(&device)?;
-
-            link.attach(Some(WireProtocol::Swd))?;
+    let device = watch::list_probes().remove(n);
 
-            MasterProbe::from_specific_probe(link)
-        }
-        DebugProbeType::STLink => {
-            let mut link = stlink::STLink::new_from_probe_info(&device)?;
-
-            link.attach(Some(WireProtocol::Swd))?;
-
-            MasterProbe::from_specific_probe(link)
-        }
-    };
+    let mut link = open_probe(&device)?;
+    link.attach(Some(WireProtocol::Swd), AttachMethod::NoReset)?;
+    let probe = MasterProbe::from_specific_probe(link);
 
     let flash_algorithm = match target.flash_algorithm.clone() {
         Some(name) => select_algorithm(name)?,
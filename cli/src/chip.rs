@@ -0,0 +1,219 @@
+use crate::common::CliError;
+
+use probe_rs::{
+    collection,
+    probe::flash::memory::MemoryRegion,
+    target::{Endian, Target},
+};
+use probe_rs_targets::{get_built_in_targets, select_algorithm, select_target, SelectionStrategy};
+
+use colored::*;
+
+/// Lists the built-in and the registered targets, optionally only those matching `filter`.
+///
+/// Registered targets replace built-in targets of the same name. Targets containing the
+/// filter are listed first, followed by targets containing its characters in order.
+pub(crate) fn list(filter: Option<String>) -> Result<(), CliError> {
+    let mut targets: Vec<(Target, &str)> = get_built_in_targets()
+        .into_iter()
+        .map(|target| (target, "built-in"))
+        .collect();
+
+    for target in collection::get_targets() {
+        targets.retain(|(t, _)| !t.name.eq_ignore_ascii_case(&target.name));
+        targets.push((target, "registered"));
+    }
+
+    let mut targets: Vec<_> = targets
+        .into_iter()
+        .filter_map(|(target, source)| match &filter {
+            Some(filter) => fuzzy_match(filter, &target.name).map(|score| (score, target, source)),
+            None => Some((0, target, source)),
+        })
+        .collect();
+    targets.sort_by(|(a, ta, _), (b, tb, _)| a.cmp(b).then_with(|| ta.name.cmp(&tb.name)));
+
+    if targets.is_empty() {
+        println!("No targets were found.");
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:<5} {:>10} {:>10}  {}",
+        "Name", "Core", "Flash", "RAM", "Source"
+    );
+    for (_, target, source) in targets {
+        let (flash, ram) = memory_sizes(&target);
+        println!(
+            "{:<20} {:<5} {:>10} {:>10}  {}",
+            target.name,
+            format!("{:?}", target.core),
+            size(flash),
+            size(ram),
+            source
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the memory map, the core and the flash algorithm of a target.
+pub(crate) fn info(name: &str) -> Result<(), CliError> {
+    let target = select_target(&SelectionStrategy::Name(name.to_string()))?;
+
+    println!("{}", target.name.bold());
+
+    let manufacturer = match target.manufacturer.get() {
+        Some(name) => name.to_string(),
+        None => format!(
+            "<unknown manufacturer (cc={:2x}, id={:2x})>",
+            target.manufacturer.cc, target.manufacturer.id
+        ),
+    };
+    println!(
+        "  Manufacturer: {}, part 0x{:04x}",
+        manufacturer, target.part
+    );
+    println!("  Core: {:?}", target.core);
+    if target.endian == Endian::Big {
+        println!("  Byte order: big endian");
+    }
+    if let Some(target_sel) = target.target_sel {
+        println!("  TARGETSEL: 0x{:08x}", target_sel);
+    }
+    for target_sel in &target.secondary_cores {
+        println!("  TARGETSEL of another core: 0x{:08x}", target_sel);
+    }
+    if !target.quirks.is_empty() {
+        println!("  Quirks: {:?}", target.quirks);
+    }
+
+    println!("  Memory map:");
+    for region in &target.memory_map {
+        match region {
+            MemoryRegion::Flash(flash) => println!(
+                "    Flash  0x{:08x}..0x{:08x} {:>10}  sectors 0x{:x}, pages 0x{:x}{}",
+                flash.range.start,
+                flash.range.end,
+                size(flash.range.end - flash.range.start),
+                flash.sector_size,
+                flash.page_size,
+                if flash.is_boot_memory { ", boot" } else { "" }
+            ),
+            MemoryRegion::Ram(ram) => {
+                print!(
+                    "    RAM    0x{:08x}..0x{:08x} {:>10}{}",
+                    ram.range.start,
+                    ram.range.end,
+                    size(ram.range.end - ram.range.start),
+                    if ram.is_boot_memory { "  boot" } else { "" }
+                );
+                match ram.bus_address {
+                    Some(bus_address) => println!("  TCM, on the bus at 0x{:08x}", bus_address),
+                    None => println!(),
+                }
+            }
+            MemoryRegion::Rom(rom) => println!(
+                "    ROM    0x{:08x}..0x{:08x} {:>10}",
+                rom.range.start,
+                rom.range.end,
+                size(rom.range.end - rom.range.start)
+            ),
+            MemoryRegion::Device(device) => println!(
+                "    Device 0x{:08x}..0x{:08x} {:>10}",
+                device.range.start,
+                device.range.end,
+                size(device.range.end - device.range.start)
+            ),
+        }
+    }
+
+    let algorithm_name = match &target.flash_algorithm {
+        Some(name) => name,
+        None => {
+            println!("  Flash algorithm: none");
+            return Ok(());
+        }
+    };
+
+    println!("  Flash algorithm: {}", algorithm_name);
+    let algorithm = select_algorithm(algorithm_name)?;
+    let address = |address: Option<u32>| match address {
+        Some(address) => format!("0x{:08x}", address),
+        None => "-".to_string(),
+    };
+
+    println!(
+        "    Load address:    0x{:08x} ({} bytes)",
+        algorithm.load_address,
+        algorithm.instructions.len() * 4
+    );
+    println!("    Init:            {}", address(algorithm.pc_init));
+    println!("    UnInit:          {}", address(algorithm.pc_uninit));
+    println!("    ProgramPage:     0x{:08x}", algorithm.pc_program_page);
+    println!("    EraseSector:     0x{:08x}", algorithm.pc_erase_sector);
+    println!("    EraseAll:        {}", address(algorithm.pc_erase_all));
+    println!(
+        "    Progress word:   {}",
+        address(algorithm.progress_address)
+    );
+    println!("    Static base:     0x{:08x}", algorithm.static_base);
+    println!("    Stack:           0x{:08x}", algorithm.begin_stack);
+    let page_buffers: Vec<_> = algorithm
+        .page_buffers
+        .iter()
+        .map(|buffer| format!("0x{:08x}", buffer))
+        .collect();
+    println!("    Page buffers:    {}", page_buffers.join(", "));
+    if algorithm.analyzer_supported {
+        println!("    Analyzer:        0x{:08x}", algorithm.analyzer_address);
+    } else {
+        println!("    Analyzer:        not supported");
+    }
+
+    Ok(())
+}
+
+/// Returns the total size of the flash and the RAM of a target.
+fn memory_sizes(target: &Target) -> (u32, u32) {
+    target
+        .memory_map
+        .iter()
+        .fold((0, 0), |(flash, ram), region| match region {
+            MemoryRegion::Flash(region) => (flash + region.range.end - region.range.start, ram),
+            MemoryRegion::Ram(region) => (flash, ram + region.range.end - region.range.start),
+            _ => (flash, ram),
+        })
+}
+
+fn size(bytes: u32) -> String {
+    if bytes >= 1024 && bytes % 1024 == 0 {
+        format!("{} KiB", bytes / 1024)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Returns how well `name` matches `filter`, lower being better, or `None` if it does not match.
+///
+/// Names containing the filter are scored by the position of the match. Names containing the
+/// characters of the filter in order are scored after them, by how far the characters spread.
+fn fuzzy_match(filter: &str, name: &str) -> Option<usize> {
+    let filter = filter.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+
+    if let Some(position) = name.find(&filter) {
+        return Some(position);
+    }
+
+    let mut characters = name.char_indices();
+    let mut first = None;
+    let mut last = 0;
+    for c in filter.chars() {
+        let (position, _) = characters.find(|(_, n)| *n == c)?;
+        first.get_or_insert(position);
+        last = position;
+    }
+
+    Some(name.len() + last - first.unwrap_or(0))
+}
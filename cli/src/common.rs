@@ -1,27 +1,101 @@
 use crate::SharedOptions;
 
 use probe_rs::{
-    collection::cores::m0::FakeM0,
+    collection::cores::{dwt::DwtError, fault::FaultStatus, m0::FakeM0, mtb::MtbError},
+    config::{Config, ConfigError, ConsoleConfig, ProbeConfig, RttConfig},
     coresight::access_ports::AccessPortError,
+    debug::{ConditionError, DebugInfo, StaticVariable},
+    defmt::DefmtError,
+    memory::core_access::CoreAccessError,
     probe::{
-        daplink,
-        debug_probe::{DebugProbe, DebugProbeError, DebugProbeType, FakeProbe, MasterProbe},
-        flash::{download::FileDownloadError, flasher::AlgorithmSelectionError},
-        protocol::WireProtocol,
-        stlink,
+        cancellation::CancellationToken,
+        debug_probe::{
+            open_probe as open_probe_driver, DebugProbe, DebugProbeError, DebugProbeInfo,
+            FakeProbe, MasterProbe, ProbeConnection,
+        },
+        flash::{
+            checksum::ChecksumError,
+            config_region::ConfigRegionError,
+            download::FileDownloadError,
+            flasher::{AlgorithmSelectionError, FlasherError},
+            protection::ProtectionError,
+        },
+        protocol::{AttachMethod, WireProtocol},
+        recording::RecordingProbe,
+        watch,
     },
+    rtos::RtosError,
+    rtt::RttError,
     session::Session,
-    target::info::{self, ChipInfo},
-    target::TargetSelectionError,
+    session_builder::SessionBuilderError,
+    swo::SwoError,
+    target::info,
+    target::{quirks::Quirk, Architecture, CoreStatus, TargetSelectionError},
 };
-use probe_rs_targets::{select_algorithm, select_target, SelectionStrategy};
+use probe_rs_targets::{select_target, BuiltInTargets, SelectionStrategy};
 
+use colored::*;
+use lazy_static::lazy_static;
 use ron;
 
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the Ctrl-C handler, so long running commands can stop and detach cleanly.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Aborts the blocking operations of all sessions, on any thread, like flashing a target
+    /// which does not finish, when Ctrl-C aborts the command.
+    static ref CANCELLATION: CancellationToken = CancellationToken::new();
+}
+
+/// How a command reacts to Ctrl-C.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum InterruptMode {
+    /// The command polls `interrupted()` and stops cleanly. A second Ctrl-C aborts what
+    /// still blocks.
+    Poll,
+    /// The command does not poll, so the first Ctrl-C aborts the blocking operations of its
+    /// sessions right away.
+    Abort,
+}
+
+/// Installs a Ctrl-C handler which requests the command to stop, and aborts the blocking
+/// operations of its sessions through the cancellation token.
+///
+/// The CLI is never exited from the handler, as that would leave the target halted with stale
+/// breakpoints: the aborted command returns and its sessions detach.
+pub(crate) fn install_interrupt_handler(mode: InterruptMode) {
+    let cancellation = CANCELLATION.clone();
+
+    if let Err(e) = ctrlc::set_handler(move || {
+        if !INTERRUPTED.swap(true, Ordering::SeqCst) && mode == InterruptMode::Poll {
+            return;
+        }
+        if !cancellation.is_cancelled() {
+            eprintln!("Aborting, the target is detached once the command stopped.");
+            cancellation.cancel();
+        }
+    }) {
+        eprintln!("Failed to install the Ctrl-C handler: {}", e);
+    }
+}
+
+/// Returns whether Ctrl-C was pressed since the handler was installed.
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Returns whether Ctrl-C was pressed, and resets the request, for commands which handle it
+/// without stopping the CLI.
+pub(crate) fn take_interrupted() -> bool {
+    CANCELLATION.reset();
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
 
 #[derive(Debug)]
 pub enum CliError {
@@ -32,8 +106,32 @@ pub enum CliError {
     StdIO(std::io::Error),
     FlashAlgorithm(AlgorithmSelectionError),
     FileDownload(FileDownloadError),
+    Flasher(FlasherError),
+    ConfigRegion(ConfigRegionError),
+    Checksum(ChecksumError),
+    Protection(ProtectionError),
+    Dwt(DwtError),
+    Mtb(MtbError),
+    Rtt(RttError),
+    CoreAccess(CoreAccessError),
+    Rtos(RtosError),
+    Defmt(DefmtError),
+    Swo(SwoError),
+    Condition(ConditionError),
+    Config(ConfigError),
+    Manifest(toml::de::Error),
+    Session(SessionBuilderError),
     MissingArgument,
+    InvalidArgument(String),
+    /// A target description has the given number of problems.
+    InvalidTargetDescription(usize),
+    UnknownCommand(String),
+    UnknownSymbol(String),
     UnableToOpenProbe,
+    /// The given number of targets could not be programmed.
+    ProgrammingFailed(usize),
+    /// The memory at the given address differs from the image programmed.
+    VerificationFailed(u32),
 }
 
 impl Error for CliError {
@@ -47,9 +145,30 @@ impl Error for CliError {
             TargetSelectionError(ref e) => Some(e),
             StdIO(ref e) => Some(e),
             MissingArgument => None,
+            InvalidArgument(_) => None,
+            InvalidTargetDescription(_) => None,
+            UnknownCommand(_) => None,
+            UnknownSymbol(_) => None,
             UnableToOpenProbe => None,
+            ProgrammingFailed(_) => None,
+            VerificationFailed(_) => None,
             FlashAlgorithm(ref e) => Some(e),
             FileDownload(ref e) => Some(e),
+            Flasher(ref e) => Some(e),
+            ConfigRegion(ref e) => Some(e),
+            Checksum(ref e) => Some(e),
+            Protection(ref e) => Some(e),
+            Dwt(ref e) => Some(e),
+            Mtb(ref e) => Some(e),
+            Rtt(ref e) => Some(e),
+            CoreAccess(ref e) => Some(e),
+            Rtos(ref e) => Some(e),
+            Defmt(ref e) => Some(e),
+            Swo(ref e) => Some(e),
+            Condition(ref e) => Some(e),
+            Config(ref e) => Some(e),
+            Manifest(ref e) => Some(e),
+            Session(ref e) => Some(e),
         }
     }
 }
@@ -66,8 +185,33 @@ impl fmt::Display for CliError {
             StdIO(ref e) => e.fmt(f),
             FlashAlgorithm(ref e) => e.fmt(f),
             FileDownload(ref e) => e.fmt(f),
+            Flasher(ref e) => e.fmt(f),
+            ConfigRegion(ref e) => e.fmt(f),
+            Checksum(ref e) => e.fmt(f),
+            Protection(ref e) => e.fmt(f),
+            Dwt(ref e) => e.fmt(f),
+            Mtb(ref e) => e.fmt(f),
+            Rtt(ref e) => e.fmt(f),
+            CoreAccess(ref e) => e.fmt(f),
+            Rtos(ref e) => e.fmt(f),
+            Defmt(ref e) => e.fmt(f),
+            Swo(ref e) => e.fmt(f),
+            Condition(ref e) => e.fmt(f),
+            Config(ref e) => e.fmt(f),
+            Manifest(ref e) => write!(f, "Invalid manifest: {}", e),
+            Session(ref e) => e.fmt(f),
             MissingArgument => write!(f, "Command expected more arguments."),
+            InvalidArgument(ref argument) => write!(f, "Invalid argument '{}'.", argument),
+            InvalidTargetDescription(problems) => {
+                write!(f, "The target description has {} problems.", problems)
+            }
+            UnknownCommand(ref command) => write!(f, "Unknown command '{}'.", command),
+            UnknownSymbol(ref symbol) => write!(f, "No variable named '{}' found.", symbol),
             UnableToOpenProbe => write!(f, "Unable to open probe."),
+            ProgrammingFailed(count) => write!(f, "Programming {} target(s) failed.", count),
+            VerificationFailed(address) => {
+                write!(f, "Verification failed at address {:#010x}.", address)
+            }
         }
     }
 }
@@ -114,13 +258,163 @@ impl From<FileDownloadError> for CliError {
     }
 }
 
-pub(crate) fn open_probe(index: Option<usize>) -> Result<MasterProbe, CliError> {
-    let mut list = daplink::tools::list_daplink_devices();
-    list.extend(stlink::tools::list_stlink_devices());
+impl From<FlasherError> for CliError {
+    fn from(error: FlasherError) -> Self {
+        CliError::Flasher(error)
+    }
+}
+
+impl From<ConfigRegionError> for CliError {
+    fn from(error: ConfigRegionError) -> Self {
+        CliError::ConfigRegion(error)
+    }
+}
+
+impl From<ChecksumError> for CliError {
+    fn from(error: ChecksumError) -> Self {
+        CliError::Checksum(error)
+    }
+}
+
+impl From<ProtectionError> for CliError {
+    fn from(error: ProtectionError) -> Self {
+        CliError::Protection(error)
+    }
+}
 
-    let device = match index {
-        Some(index) => list.get(index).ok_or(CliError::UnableToOpenProbe)?,
-        None => {
+impl From<DwtError> for CliError {
+    fn from(error: DwtError) -> Self {
+        CliError::Dwt(error)
+    }
+}
+
+impl From<MtbError> for CliError {
+    fn from(error: MtbError) -> Self {
+        CliError::Mtb(error)
+    }
+}
+
+impl From<CoreAccessError> for CliError {
+    fn from(error: CoreAccessError) -> Self {
+        CliError::CoreAccess(error)
+    }
+}
+
+impl From<RttError> for CliError {
+    fn from(error: RttError) -> Self {
+        CliError::Rtt(error)
+    }
+}
+
+impl From<RtosError> for CliError {
+    fn from(error: RtosError) -> Self {
+        CliError::Rtos(error)
+    }
+}
+
+impl From<DefmtError> for CliError {
+    fn from(error: DefmtError) -> Self {
+        CliError::Defmt(error)
+    }
+}
+
+impl From<ConfigError> for CliError {
+    fn from(error: ConfigError) -> Self {
+        CliError::Config(error)
+    }
+}
+
+impl From<toml::de::Error> for CliError {
+    fn from(error: toml::de::Error) -> Self {
+        CliError::Manifest(error)
+    }
+}
+
+impl From<SwoError> for CliError {
+    fn from(error: SwoError) -> Self {
+        CliError::Swo(error)
+    }
+}
+
+impl From<SessionBuilderError> for CliError {
+    fn from(error: SessionBuilderError) -> Self {
+        CliError::Session(error)
+    }
+}
+
+impl From<ConditionError> for CliError {
+    fn from(error: ConditionError) -> Self {
+        CliError::Condition(error)
+    }
+}
+
+/// Targets with a supply voltage below this value are considered unpowered.
+const MIN_TARGET_VOLTAGE: f32 = 1.5;
+
+/// Warns if the target voltage read from the probe shows that the target is unpowered.
+fn check_target_voltage(voltage: Option<f32>) -> Option<f32> {
+    if let Some(voltage) = voltage {
+        if voltage < MIN_TARGET_VOLTAGE {
+            eprintln!(
+                "{}: Target voltage is {:.2} V, the target appears to be unpowered.",
+                "Warning".yellow(),
+                voltage
+            );
+        }
+    }
+
+    voltage
+}
+
+/// Returns the configuration from the config files, with the options given on the command
+/// line taking precedence.
+pub(crate) fn configuration(shared_options: &SharedOptions) -> Result<Config, CliError> {
+    let command_line = Config {
+        target: shared_options.target.clone(),
+        probe: ProbeConfig {
+            index: shared_options.n,
+            serial: shared_options.serial.clone(),
+            protocol: shared_options.protocol,
+            speed: shared_options.speed,
+            target_sel: shared_options.target_sel,
+            reset_type: shared_options.reset_type,
+            record: shared_options.record.clone(),
+            recover_lockup: if shared_options.recover_lockup {
+                Some(true)
+            } else {
+                None
+            },
+        },
+        rtt: RttConfig::default(),
+        console: ConsoleConfig::default(),
+    };
+
+    Ok(Config::load()?.merge(command_line))
+}
+
+/// Opens the probe selected by serial number or index, or the only connected one,
+/// and attaches to the target.
+pub(crate) fn open_probe(config: &ProbeConfig) -> Result<MasterProbe, CliError> {
+    open_probe_for(config, Architecture::Arm, &[])
+}
+
+/// Opens the probe and attaches to a core of the given architecture. Cores which are not
+/// debugged through an ARM debug port are attached to through the raw JTAG scan chain.
+/// The quirks of the debug port of the target are worked around while attaching.
+pub(crate) fn open_probe_for(
+    config: &ProbeConfig,
+    architecture: Architecture,
+    quirks: &[Quirk],
+) -> Result<MasterProbe, CliError> {
+    let list = watch::list_probes();
+
+    let device = match (&config.serial, config.index) {
+        (Some(serial), _) => list
+            .iter()
+            .find(|device| device.serial_number.as_ref() == Some(serial))
+            .ok_or(CliError::UnableToOpenProbe)?,
+        (None, Some(index)) => list.get(index).ok_or(CliError::UnableToOpenProbe)?,
+        (None, None) => {
             // open the default probe, if only one probe was found
             if list.len() == 1 {
                 &list[0]
@@ -130,24 +424,67 @@ pub(crate) fn open_probe(index: Option<usize>) -> Result<MasterProbe, CliError>
         }
     };
 
-    let probe = match device.probe_type {
-        DebugProbeType::DAPLink => {
-            let mut link = daplink::DAPLink::new_from_probe_info(&device)?;
+    let mut link = open_probe_driver(device)?;
+    if let Some(path) = &config.record {
+        link = Box::new(RecordingProbe::create(link, path)?);
+    }
+    attach(&mut *link, config, architecture, quirks)?;
 
-            link.attach(Some(WireProtocol::Swd))?;
+    let mut probe = MasterProbe::from_specific_probe(link);
 
-            MasterProbe::from_specific_probe(link)
-        }
-        DebugProbeType::STLink => {
-            let mut link = stlink::STLink::new_from_probe_info(&device)?;
+    let protocol = match architecture {
+        Architecture::Arm => config.protocol.unwrap_or(WireProtocol::Swd),
+        Architecture::Xtensa => WireProtocol::Jtag,
+    };
+    probe.set_architecture(architecture);
+    probe.set_connection(ProbeConnection {
+        info: device.clone(),
+        protocol,
+        speed_khz: config.speed,
+        target_sel: config.target_sel,
+        quirks: quirks.to_vec(),
+    });
 
-            link.attach(Some(WireProtocol::Swd))?;
+    Ok(probe)
+}
 
-            MasterProbe::from_specific_probe(link)
+fn attach(
+    link: &mut dyn DebugProbe,
+    config: &ProbeConfig,
+    architecture: Architecture,
+    quirks: &[Quirk],
+) -> Result<(), CliError> {
+    let span = tracing::info_span!("probe", attach = link.get_name());
+    let _enter = span.enter();
+
+    check_target_voltage(link.get_target_voltage().ok().and_then(|v| v));
+    link.set_multidrop_target(config.target_sel)?;
+    link.set_quirks(quirks)?;
+
+    if let Some(speed) = config.speed {
+        let actual = link.set_speed(speed)?;
+        if actual != speed {
+            eprintln!(
+                "The probe does not support {} kHz, using {} kHz instead.",
+                speed, actual
+            );
         }
-    };
+    }
 
-    Ok(probe)
+    match architecture {
+        Architecture::Arm => {
+            link.attach(
+                Some(config.protocol.unwrap_or(WireProtocol::Swd)),
+                AttachMethod::NoReset,
+            )?;
+        }
+        Architecture::Xtensa => link
+            .jtag_access()
+            .ok_or(DebugProbeError::JTAGNotSupportedOnProbe)?
+            .attach_jtag()?,
+    }
+
+    Ok(())
 }
 
 /// Takes a closure that is handed an `DAPLink` instance and then executed.
@@ -157,33 +494,103 @@ pub(crate) fn with_device<F>(shared_options: &SharedOptions, f: F) -> Result<(),
 where
     for<'a> F: FnOnce(Session) -> Result<(), CliError>,
 {
-    let mut probe = open_probe(shared_options.n)?;
+    let config = configuration(shared_options)?;
 
-    let selection_strategy = if let Some(ref target_name) = shared_options.target {
-        SelectionStrategy::Name(target_name.clone())
-    } else {
-        let chip_info = ChipInfo::read_from_rom_table(&mut probe)?;
-        SelectionStrategy::ChipInfo(chip_info)
-    };
+    f(open_session(&config)?)
+}
 
-    let target = select_target(&selection_strategy)?;
+/// Opens the probe and the target selected by the configuration.
+pub(crate) fn open_session(config: &Config) -> Result<Session, CliError> {
+    // A target selected by name is known before attaching,
+    // so the builder uses its multi-drop configuration and quirks to open the probe.
+    let mut builder = Session::builder()
+        .targets(BuiltInTargets)
+        .probe_config(&config.probe);
+    if let Some(ref target_name) = config.target {
+        builder = builder.target(target_name.as_str());
+    }
 
-    let flash_algorithm = match target.flash_algorithm {
-        Some(ref name) => select_algorithm(name),
-        None => Err(AlgorithmSelectionError::NoAlgorithmSuggested),
-    };
+    let mut session = builder.open()?;
+    check_target_voltage(session.probe.get_target_voltage().ok().and_then(|v| v));
+    if let Some(ref target_name) = config.target {
+        if !session.target.name.eq_ignore_ascii_case(target_name) {
+            eprintln!(
+                "    {} There is no definition for {}, using the definition of {}.",
+                "Note".cyan().bold(),
+                target_name,
+                session.target.name
+            );
+        }
+    }
+    session.probe.set_cancellation_token(CANCELLATION.clone());
+    // Only Cortex-M cores have a CPUID to identify them by.
+    if session.target.core.architecture() != Architecture::Arm {
+        return Ok(session);
+    }
+    if let Err(e) = session.identify_core() {
+        tracing::warn!(
+            "Failed to identify the core, using the one of {}: {}",
+            session.target.name,
+            e
+        );
+    }
+    check_lockup(&mut session, config.probe.recover_lockup == Some(true))?;
 
-    let flash_algorithm = match flash_algorithm {
-        Ok(flash_algorithm) => Some(flash_algorithm),
-        Err(error) => {
-            println!("{:?}", error);
+    Ok(session)
+}
+
+/// Warns if the core locked up, and resets the target if `recover` is set.
+///
+/// The core is only halted to read its registers when recovering, so commands like `status`
+/// still see it locked up otherwise.
+fn check_lockup(session: &mut Session, recover: bool) -> Result<(), CliError> {
+    if session.target.core.status(&mut session.probe)? != CoreStatus::LockedUp {
+        return Ok(());
+    }
+
+    if !recover {
+        let is_baseline = session
+            .core_info
+            .map_or(false, |info| info.core_type.is_baseline());
+        let fault = if is_baseline {
             None
-        }
-    };
+        } else {
+            FaultStatus::read(&mut session.probe).ok()
+        };
+
+        eprintln!(
+            "{}: The core is locked up{}. Pass --recover-lockup to reset the target.",
+            "Warning".yellow(),
+            fault_causes(fault)
+        );
+        return Ok(());
+    }
 
-    let session = Session::new(target, probe, flash_algorithm);
+    if let Some(lockup) = session.check_lockup()? {
+        eprintln!(
+            "{}: The core was locked up at PC 0x{:08x}{}.",
+            "Warning".yellow(),
+            lockup.pc,
+            fault_causes(lockup.fault)
+        );
+        eprintln!(
+            "         LR 0x{:08x}, SP 0x{:08x}, xPSR 0x{:08x}",
+            lockup.lr, lockup.sp, lockup.xpsr
+        );
+    }
+    session.recover_lockup()?;
+    eprintln!(
+        "    {} Reset the target, the core is halted at the reset vector.",
+        "Note".cyan().bold()
+    );
 
-    f(session)
+    Ok(())
+}
+
+fn fault_causes(fault: Option<FaultStatus>) -> String {
+    fault
+        .map(|fault| format!(": {}", fault))
+        .unwrap_or_default()
 }
 
 pub(crate) fn with_dump<F>(shared_options: &SharedOptions, p: &Path, f: F) -> Result<(), CliError>
@@ -199,8 +606,8 @@ where
 
     let probe = MasterProbe::from_specific_probe(Box::new(fake_probe));
 
-    let selection_strategy = if let Some(ref target_name) = shared_options.target {
-        SelectionStrategy::Name(target_name.clone())
+    let selection_strategy = if let Some(target_name) = configuration(shared_options)?.target {
+        SelectionStrategy::Name(target_name)
     } else {
         unimplemented!();
     };
@@ -213,3 +620,20 @@ where
 
     f(session)
 }
+
+/// Looks up the variables named `symbols` in the debug info of the ELF file `exe`.
+pub(crate) fn find_statics(
+    exe: &Path,
+    symbols: &[String],
+) -> Result<Vec<StaticVariable>, CliError> {
+    let debug_info = DebugInfo::from_raw(&fs::read(exe)?);
+
+    symbols
+        .iter()
+        .map(|symbol| {
+            debug_info
+                .find_static(symbol)
+                .ok_or_else(|| CliError::UnknownSymbol(symbol.clone()))
+        })
+        .collect()
+}
@@ -0,0 +1,88 @@
+use crate::common::{configuration, find_statics, interrupted, with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::{
+    debug::StaticVariable,
+    memory::console::{MemoryConsole, RingIndex},
+};
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Time to wait before polling again when the target did not write anything.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The symbols of the ring buffer, given on the command line or in the `[console]` section of
+/// the configuration.
+pub(crate) struct ConsoleSymbols {
+    pub buffer: Option<String>,
+    pub write_index: Option<String>,
+    pub read_index: Option<String>,
+}
+
+/// Streams the output the target writes into a ring buffer to stdout, until Ctrl-C is pressed.
+///
+/// This gives printf-style output on targets which do not use RTT.
+pub(crate) fn console(
+    shared_options: &SharedOptions,
+    exe: &Path,
+    symbols: ConsoleSymbols,
+) -> Result<(), CliError> {
+    // The command line takes precedence over the configuration files.
+    let config = configuration(shared_options)?.console;
+    let buffer = symbols
+        .buffer
+        .or(config.buffer)
+        .ok_or(CliError::MissingArgument)?;
+    let write_index = symbols
+        .write_index
+        .or(config.write_index)
+        .ok_or(CliError::MissingArgument)?;
+    let read_index = symbols.read_index.or(config.read_index);
+
+    let mut names = vec![buffer, write_index];
+    names.extend(read_index);
+    let variables = find_statics(exe, &names)?;
+
+    let index = |variable: &StaticVariable| RingIndex {
+        address: variable.address,
+        size: variable.size(),
+    };
+
+    with_device(shared_options, |mut session| {
+        let buffer = &variables[0];
+        let mut console = MemoryConsole::attach(
+            &mut session.probe,
+            buffer.address,
+            buffer.size(),
+            index(&variables[1]),
+            variables.get(2).map(index),
+        )?;
+
+        eprintln!(
+            "Reading the console in {} at 0x{:08x} ({} bytes).",
+            buffer.name,
+            buffer.address,
+            buffer.size()
+        );
+
+        let stdout = io::stdout();
+
+        while !interrupted() {
+            let data = console.read_available(&mut session.probe)?;
+
+            if data.is_empty() {
+                sleep(POLL_INTERVAL);
+                continue;
+            }
+
+            let mut stdout = stdout.lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+
+        Ok(())
+    })
+}
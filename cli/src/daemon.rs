@@ -0,0 +1,370 @@
+//! A daemon which keeps the session with the target open and serves the requests of several
+//! local tools over a unix socket, so they neither pay for attaching every time nor fight over
+//! the USB device of the probe.
+//!
+//! Clients send one JSON request per line, like `{"command": "read", "address": 536870912,
+//! "length": 4}`, and receive one JSON response per line, like `{"data": [0, 16, 0, 32]}`.
+//! The requests of all clients are executed one after another by the thread owning the probe.
+
+use crate::common::{interrupted, with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::{memory::MI, session::Session};
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// How often the daemon checks for Ctrl-C while no request is pending.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The most bytes a single `read` request may ask for, so a client cannot make the daemon
+/// allocate gigabytes.
+const MAX_READ_LENGTH: u32 = 1024 * 1024;
+
+/// The most bytes a request may have, enough for a `write` of as much data as a `read` may ask
+/// for, so a client which never sends a newline cannot make the daemon buffer without bound.
+const MAX_REQUEST_LENGTH: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub(crate) enum Request {
+    /// Returns the name of the target.
+    Target,
+    Read {
+        address: u32,
+        length: u32,
+    },
+    Write {
+        address: u32,
+        data: Vec<u8>,
+    },
+    Halt,
+    Run,
+    Step,
+    /// Resets the target with its reset type.
+    Reset,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Response {
+    Done,
+    Target(String),
+    Data(Vec<u8>),
+    Halted { pc: u32 },
+    Error(String),
+}
+
+/// A request of a client, with the channel its response is sent back on.
+type Job = (Request, Sender<Response>);
+
+/// Returns the socket the daemon listens on if none is given, in the runtime directory of the
+/// user.
+pub(crate) fn default_socket() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("probe-rs.sock")
+}
+
+/// Opens the session and serves the requests of the clients connecting to `socket`, until
+/// Ctrl-C is pressed.
+#[cfg(unix)]
+pub(crate) fn serve(shared_options: &SharedOptions, socket: &Path) -> Result<(), CliError> {
+    // A socket left behind by a daemon which did not exit cleanly would keep it from binding.
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(CliError::InvalidArgument(format!(
+                "A daemon is already listening on {}.",
+                socket.display()
+            )));
+        }
+        remove_socket(socket)?;
+    }
+
+    with_device(shared_options, |mut session| {
+        let listener = UnixListener::bind(socket)?;
+        let (jobs, queue) = mpsc::channel();
+        thread::spawn(move || accept(listener, jobs));
+
+        eprintln!(
+            "Serving {} on {}, press Ctrl-C to stop.",
+            session.target.name,
+            socket.display()
+        );
+
+        run_jobs(&mut session, &queue);
+        remove_socket(socket)?;
+
+        Ok(())
+    })
+}
+
+/// Removes the socket at `path`, but nothing else, in case the path of a file was given for the
+/// socket by mistake.
+#[cfg(unix)]
+fn remove_socket(path: &Path) -> Result<(), CliError> {
+    if !fs::symlink_metadata(path)?.file_type().is_socket() {
+        return Err(CliError::InvalidArgument(format!(
+            "{} is not a socket.",
+            path.display()
+        )));
+    }
+
+    fs::remove_file(path)?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn serve(_shared_options: &SharedOptions, _socket: &Path) -> Result<(), CliError> {
+    Err(CliError::InvalidArgument(
+        "The daemon is only supported on unix systems.".to_string(),
+    ))
+}
+
+/// Sends a request, given as JSON, to the daemon listening on `socket` and prints its response.
+#[cfg(unix)]
+pub(crate) fn request(socket: &Path, request: &str) -> Result<(), CliError> {
+    let request: Request =
+        serde_json::from_str(request).map_err(|e| CliError::InvalidArgument(e.to_string()))?;
+
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{}", to_json(&request))?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    print!("{}", response);
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn request(_socket: &Path, _request: &str) -> Result<(), CliError> {
+    Err(CliError::InvalidArgument(
+        "The daemon is only supported on unix systems.".to_string(),
+    ))
+}
+
+/// Executes the requests of the clients one after another, until Ctrl-C is pressed.
+fn run_jobs(session: &mut Session, queue: &Receiver<Job>) {
+    while !interrupted() {
+        match queue.recv_timeout(POLL_INTERVAL) {
+            Ok((request, reply)) => {
+                let response =
+                    execute(session, request).unwrap_or_else(|e| Response::Error(e.to_string()));
+
+                // The client may have disconnected in the meantime.
+                let _ = reply.send(response);
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+fn execute(session: &mut Session, request: Request) -> Result<Response, CliError> {
+    match request {
+        Request::Target => Ok(Response::Target(session.target.name.clone())),
+        Request::Read { length, .. } if length > MAX_READ_LENGTH => Ok(Response::Error(format!(
+            "Cannot read {} bytes at once, the limit is {} bytes.",
+            length, MAX_READ_LENGTH
+        ))),
+        Request::Read { address, length } => {
+            let mut data = vec![0; length as usize];
+            session.probe.read_block8(address, &mut data)?;
+
+            Ok(Response::Data(data))
+        }
+        Request::Write { address, data } => {
+            session.probe.write_block8(address, &data)?;
+
+            Ok(Response::Done)
+        }
+        Request::Halt => Ok(Response::Halted {
+            pc: session.halt()?.pc,
+        }),
+        Request::Run => {
+            session.target.core.run(&mut session.probe)?;
+
+            Ok(Response::Done)
+        }
+        Request::Step => Ok(Response::Halted {
+            pc: session.step()?.pc,
+        }),
+        Request::Reset => {
+            session.target_reset(session.target.reset_type)?;
+
+            Ok(Response::Done)
+        }
+    }
+}
+
+/// Accepts clients, each of which is served by a thread of its own.
+#[cfg(unix)]
+fn accept(listener: UnixListener, jobs: Sender<Job>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let jobs = jobs.clone();
+
+                thread::spawn(move || {
+                    if let Err(e) = serve_client(stream, &jobs) {
+                        tracing::debug!("Lost a client of the daemon: {}", e);
+                    }
+                });
+            }
+            Err(e) => tracing::warn!("Failed to accept a client of the daemon: {}", e),
+        }
+    }
+}
+
+/// Passes the requests of a client on to the thread owning the session and writes back the
+/// responses.
+#[cfg(unix)]
+fn serve_client(stream: UnixStream, jobs: &Sender<Job>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let length = (&mut reader)
+            .take(MAX_REQUEST_LENGTH)
+            .read_line(&mut line)?;
+        if length == 0 {
+            break;
+        }
+        // The rest of the request would be taken for the next one, so the client is dropped.
+        if !line.ends_with('\n') && length as u64 == MAX_REQUEST_LENGTH {
+            let error = format!("The request is longer than {} bytes.", MAX_REQUEST_LENGTH);
+            writeln!(writer, "{}", to_json(&Response::Error(error)))?;
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if jobs.send((request, reply)).is_err() {
+                    break;
+                }
+
+                response
+                    .recv()
+                    .unwrap_or_else(|_| Response::Error("The daemon stopped.".to_string()))
+            }
+            Err(e) => Response::Error(format!("Invalid request: {}", e)),
+        };
+
+        writeln!(writer, "{}", to_json(&response))?;
+    }
+
+    Ok(())
+}
+
+fn to_json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).expect("Failed to serialize a message of the daemon.")
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(unix)]
+    use super::{accept, remove_socket};
+    use super::{Request, Response};
+
+    #[cfg(unix)]
+    use std::{
+        env, fs,
+        io::{BufRead, BufReader, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        process,
+        sync::mpsc,
+        thread,
+    };
+
+    #[test]
+    fn parses_requests() {
+        let request: Request =
+            serde_json::from_str(r#"{"command": "read", "address": 536870912, "length": 4}"#)
+                .unwrap();
+        assert_eq!(
+            request,
+            Request::Read {
+                address: 0x2000_0000,
+                length: 4
+            }
+        );
+
+        let request: Request = serde_json::from_str(r#"{"command": "halt"}"#).unwrap();
+        assert_eq!(request, Request::Halt);
+
+        assert_eq!(
+            serde_json::to_string(&Response::Halted { pc: 0x100 }).unwrap(),
+            r#"{"halted":{"pc":256}}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serves_requests_over_the_socket() {
+        let path = env::temp_dir().join(format!("probe-rs-daemon-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let (jobs, queue) = mpsc::channel();
+        thread::spawn(move || accept(listener, jobs));
+
+        // Answers in place of the thread owning the session.
+        thread::spawn(move || {
+            for (request, reply) in queue {
+                let response = match request {
+                    Request::Target => Response::Target("nRF52832".to_string()),
+                    _ => Response::Done,
+                };
+                let _ = reply.send(response);
+            }
+        });
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        let mut responses = BufReader::new(stream.try_clone().unwrap()).lines();
+        writeln!(stream, r#"{{"command": "target"}}"#).unwrap();
+        writeln!(stream, "not a request").unwrap();
+
+        assert_eq!(
+            responses.next().unwrap().unwrap(),
+            r#"{"target":"nRF52832"}"#
+        );
+        assert!(responses
+            .next()
+            .unwrap()
+            .unwrap()
+            .starts_with(r#"{"error":"Invalid request"#));
+
+        remove_socket(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removes_only_sockets() {
+        let path = env::temp_dir().join(format!("probe-rs-daemon-{}.txt", process::id()));
+        fs::write(&path, "not a socket").unwrap();
+
+        assert!(remove_socket(&path).is_err());
+        assert!(path.exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
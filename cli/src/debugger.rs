@@ -1,11 +1,44 @@
-use crate::common::CliError;
-
-use probe_rs::{collection::cores::CortexDump, debug::DebugInfo, memory::MI, session::Session};
+use crate::common::{interrupted, take_interrupted, CliError};
+use crate::dump;
+use crate::saved_session::{Breakpoint, Location, SavedSession, Watchpoint};
+
+use probe_rs::{
+    collection::cores::{dwt, dwt::DwtCounters, mtb, mtb::Mtb, CortexDump, WatchKind},
+    debug::{Condition, DebugInfo},
+    memory::MI,
+    probe::debug_probe::DebugProbeError,
+    rtos::{RtosProvider, Thread, ThreadState},
+    session::Session,
+    target::CoreRegisterAddress,
+};
 
 use capstone::Capstone;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::{Context, Helper};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Name of the init file which is executed from the current directory on startup.
+pub const INIT_FILE_NAME: &str = ".proberc";
+
+/// The file the values logged at tracepoints are appended to, relative to the current directory.
+pub const TRACEPOINT_LOG_FILE_NAME: &str = ".probe-rs/tracepoints.log";
 
-use std::fs::File;
-use std::io::prelude::*;
+/// Returns the location of the REPL history file, `~/.config/probe-rs/history`.
+///
+/// The containing directory is created if it does not exist yet.
+pub fn history_path() -> Option<PathBuf> {
+    let dir = dirs::home_dir()?.join(".config/probe-rs");
+
+    fs::create_dir_all(&dir).ok()?;
+
+    Some(dir.join("history"))
+}
 
 pub struct DebugCli {
     commands: Vec<Command>,
@@ -29,6 +62,8 @@ impl DebugCli {
                     .step(&mut cli_data.session.probe)?;
                 println!("Core stopped at address 0x{:08x}", cpu_info.pc);
 
+                cli_data.show_displays()?;
+
                 Ok(CliState::Continue)
             },
         });
@@ -45,6 +80,8 @@ impl DebugCli {
                     .halt(&mut cli_data.session.probe)?;
                 println!("Core stopped at address 0x{:08x}", cpu_info.pc);
 
+                cli_data.report_cycles()?;
+
                 let mut code = [0u8; 16 * 2];
 
                 cli_data.session.probe.read_block8(cpu_info.pc, &mut code)?;
@@ -58,6 +95,8 @@ impl DebugCli {
                     println!("{}", i);
                 }
 
+                cli_data.show_displays()?;
+
                 Ok(CliState::Continue)
             },
         });
@@ -77,6 +116,17 @@ impl DebugCli {
             },
         });
 
+        cli.add_command(Command {
+            name: "continue",
+            help_text: "Resume execution until the core stops at a breakpoint whose condition holds, or Ctrl-C is pressed",
+
+            function: |cli_data, _args| {
+                cli_data.continue_until_stop()?;
+
+                Ok(CliState::Continue)
+            },
+        });
+
         cli.add_command(Command {
             name: "quit",
             help_text: "Exit the program",
@@ -86,13 +136,27 @@ impl DebugCli {
 
         cli.add_command(Command {
             name: "read",
-            help_text: "Read 32bit value from memory",
+            help_text: "Read 32bit values from memory. 'read <address> [words]' reads from an address prefixed with 0x, 'read <variable>' reads a static variable and decodes it according to its type",
 
             function: |cli_data, args| {
                 let address_str = args.get(0).ok_or(CliError::MissingArgument)?;
 
-                let address = u32::from_str_radix(address_str, 16).unwrap();
-                //println!("Would read from address 0x{:08x}", address);
+                // Names like `dead` are valid hex numbers, so addresses need the prefix.
+                let address = match address_str.get(..2) {
+                    Some("0x") | Some("0X") => parse_argument(args.get(0))?,
+                    _ => {
+                        let variable = cli_data
+                            .debug_info
+                            .as_ref()
+                            .and_then(|di| di.find_static(address_str))
+                            .ok_or_else(|| CliError::UnknownSymbol(address_str.to_string()))?;
+
+                        let value = variable.read(&mut cli_data.session.probe)?;
+                        println!("{} @ 0x{:08x} = {}", variable.name, variable.address, value);
+
+                        return Ok(CliState::Continue);
+                    }
+                };
 
                 let num_words = args
                     .get(1)
@@ -100,11 +164,22 @@ impl DebugCli {
                     .unwrap_or(1);
 
                 let mut buff = vec![0u32; num_words];
+                let memory_map = cli_data.session.target.memory_map.clone();
+                let regions = dump::readable_regions(
+                    &memory_map,
+                    address..address + 4 * num_words as u32,
+                    false,
+                )?;
 
-                cli_data.session.probe.read_block32(address, &mut buff)?;
+                cli_data.session.read_memory32(address, &mut buff)?;
 
                 for (offset, word) in buff.iter().enumerate() {
-                    println!("0x{:08x} = 0x{:08x}", address + (offset * 4) as u32, word);
+                    let word_address = address + (offset * 4) as u32;
+
+                    if let Some(header) = dump::region_header(&regions, word_address) {
+                        println!("{}", header);
+                    }
+                    println!("0x{:08x} = 0x{:08x}", word_address, word);
                 }
 
                 Ok(CliState::Continue)
@@ -112,24 +187,336 @@ impl DebugCli {
         });
 
         cli.add_command(Command {
-            name: "break",
-            help_text: "Set a breakpoint at a specifc address",
+            name: "cycles",
+            help_text: "Show the DWT cycle and event counters, and their increments since the last halt. 'cycles reset' resets them",
 
             function: |cli_data, args| {
-                let address_str = args.get(0).ok_or(CliError::MissingArgument)?;
-                let address = u32::from_str_radix(address_str, 16).unwrap();
-                //println!("Would read from address 0x{:08x}", address);
+                let probe = &mut cli_data.session.probe;
+
+                if cli_data.cycle_counters.is_none() {
+                    dwt::enable_counters(probe)?;
+                }
+
+                if args.get(0) == Some(&"reset") {
+                    dwt::reset_counters(probe)?;
+                }
+
+                let counters = dwt::read_counters(probe)?;
+
+                println!("Counters:        {}", counters);
+
+                match cli_data.cycle_counters {
+                    Some(previous) => println!("Since last halt: {}", counters.delta(&previous)),
+                    None => cli_data.cycle_counters = Some(counters),
+                }
+
+                if args.get(0) == Some(&"reset") {
+                    cli_data.cycle_counters = Some(counters);
+                }
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "trace",
+            help_text: "Record the executed instructions with the Micro Trace Buffer of a Cortex-M0+. 'trace start [size]' starts recording into the first <size> bytes (default 1024) of the trace SRAM, 'trace instructions [count]' prints the last <count> (default 50) recorded instructions and restarts recording",
+
+            function: |cli_data, args| match args.get(0) {
+                Some(&"start") => {
+                    let size = match args.get(1) {
+                        Some(_) => parse_argument(args.get(1))?,
+                        None => DEFAULT_TRACE_BUFFER_SIZE,
+                    };
+
+                    let mtb = match cli_data.mtb {
+                        Some(mtb) => mtb,
+                        None => Mtb::find(&mut cli_data.session.probe)?,
+                    };
+
+                    mtb.start(&mut cli_data.session.probe, size)?;
+                    cli_data.mtb = Some(mtb);
+
+                    let buffer_address = mtb.buffer_address(&mut cli_data.session.probe)?;
+                    println!(
+                        "Recording trace into 0x{:08x}..0x{:08x}",
+                        buffer_address,
+                        buffer_address + size
+                    );
+
+                    Ok(CliState::Continue)
+                }
+                Some(&"instructions") => {
+                    let count = match args.get(1) {
+                        Some(_) => parse_argument(args.get(1))? as usize,
+                        None => DEFAULT_TRACE_INSTRUCTIONS,
+                    };
+
+                    cli_data.print_trace(count)?;
+
+                    Ok(CliState::Continue)
+                }
+                Some(other) => Err(CliError::InvalidArgument(other.to_string())),
+                None => Err(CliError::MissingArgument),
+            },
+        });
+
+        cli.add_command(Command {
+            name: "dpread",
+            help_text: "Read a raw DP register: dpread <addr>",
+
+            function: |cli_data, args| {
+                let address = parse_argument(args.get(0))?;
+
+                let value = cli_data.session.probe.read_register_dp(address as u16)?;
+
+                println!("DP 0x{:02x} = 0x{:08x}", address, value);
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "dpwrite",
+            help_text: "Write a raw DP register: dpwrite <addr> <value>",
+
+            function: |cli_data, args| {
+                let address = parse_argument(args.get(0))?;
+                let value = parse_argument(args.get(1))?;
 
                 cli_data
                     .session
-                    .target
-                    .core
-                    .enable_breakpoints(&mut cli_data.session.probe, true)?;
+                    .probe
+                    .write_register_dp(address as u16, value)?;
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "apread",
+            help_text: "Read a raw AP register: apread <ap> <addr>",
+
+            function: |cli_data, args| {
+                let port = parse_argument(args.get(0))?;
+                let address = parse_argument(args.get(1))?;
+
+                let value = cli_data
+                    .session
+                    .probe
+                    .read_register_ap_raw(port as u8, address as u8)?;
+
+                println!("AP {} 0x{:02x} = 0x{:08x}", port, address, value);
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "apwrite",
+            help_text: "Write a raw AP register: apwrite <ap> <addr> <value>",
+
+            function: |cli_data, args| {
+                let port = parse_argument(args.get(0))?;
+                let address = parse_argument(args.get(1))?;
+                let value = parse_argument(args.get(2))?;
+
                 cli_data
                     .session
-                    .target
-                    .core
-                    .set_breakpoint(&mut cli_data.session.probe, address)?;
+                    .probe
+                    .write_register_ap_raw(port as u8, address as u8, value)?;
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "break",
+            help_text: "Set a breakpoint at a hex address or at the start of a function: 'break <address|function> [if <condition>]', e.g. 'break main if COUNT > 10'",
+
+            function: |cli_data, args| {
+                let (args, condition) = split_condition(args)?;
+                let location = cli_data.location(args.get(0))?;
+
+                if let Some(argument) = args.get(1) {
+                    return Err(CliError::InvalidArgument(argument.to_string()));
+                }
+
+                cli_data.set_breakpoint(Breakpoint {
+                    condition,
+                    ..Breakpoint::new(location)
+                })?;
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "tracepoint",
+            help_text: "Log static variables or registers whenever 'continue' passes a location, without stopping there: 'tracepoint <address|function> <variable|register>... [if <condition>]'",
+
+            function: |cli_data, args| {
+                let (args, condition) = split_condition(args)?;
+                let location = cli_data.location(args.get(0))?;
+
+                let trace: Vec<String> = args.iter().skip(1).map(|arg| arg.to_string()).collect();
+                if trace.is_empty() {
+                    return Err(CliError::MissingArgument);
+                }
+
+                for expression in &trace {
+                    let known = register(expression).is_some()
+                        || cli_data
+                            .debug_info
+                            .as_ref()
+                            .and_then(|di| di.find_static(expression))
+                            .is_some();
+
+                    if !known {
+                        return Err(CliError::UnknownSymbol(expression.clone()));
+                    }
+                }
+
+                cli_data.set_breakpoint(Breakpoint {
+                    condition,
+                    trace,
+                    ..Breakpoint::new(location)
+                })?;
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "ignore",
+            help_text: "Let 'continue' pass a breakpoint the given number of times before it stops there: 'ignore <address|function> <count>'",
+
+            function: |cli_data, args| {
+                let location = cli_data.location(args.get(0))?;
+                let count = parse_argument(args.get(1))?;
+                let address = cli_data.resolve(&location)?;
+
+                let debug_info = cli_data.debug_info.as_ref();
+                let breakpoint = cli_data
+                    .breakpoints
+                    .iter_mut()
+                    .find(|breakpoint| breakpoint.location.resolve(debug_info) == Some(address))
+                    .ok_or_else(|| CliError::InvalidArgument(location.to_string()))?;
+
+                breakpoint.ignore_count = count;
+                breakpoint.hits = 0;
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "breakpoints",
+            help_text: "List the breakpoints with their conditions and hit counts",
+
+            function: |cli_data, _args| {
+                let debug_info = cli_data.debug_info.as_ref();
+
+                for (index, breakpoint) in cli_data.breakpoints.iter().enumerate() {
+                    let address = match breakpoint.location.resolve(debug_info) {
+                        Some(address) => format!("0x{:08x}", address),
+                        None => "<not found>".to_string(),
+                    };
+                    print!("{}: {} at {}", index, breakpoint.location, address);
+
+                    if let Some(condition) = &breakpoint.condition {
+                        print!(", if {}", condition);
+                    }
+                    if breakpoint.ignore_count > 0 {
+                        print!(", ignoring {} hits", breakpoint.ignore_count);
+                    }
+                    if !breakpoint.trace.is_empty() {
+                        print!(", logging {}", breakpoint.trace.join(", "));
+                    }
+                    println!(", hit {} times", breakpoint.hits);
+                }
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "clear",
+            help_text: "Remove a breakpoint set with 'break': 'clear <address|function>'",
+
+            function: |cli_data, args| {
+                let location = cli_data.location(args.get(0))?;
+                let address = cli_data.resolve(&location)?;
+
+                cli_data.session.clear_breakpoint(address)?;
+
+                let debug_info = cli_data.debug_info.as_ref();
+                cli_data
+                    .breakpoints
+                    .retain(|breakpoint| breakpoint.location.resolve(debug_info) != Some(address));
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "watch",
+            help_text: "Halt when a static variable or a word of memory is accessed: 'watch <variable|address> [read|write|readwrite]', writes are watched by default",
+
+            function: |cli_data, args| {
+                let kind = match args.get(1) {
+                    None | Some(&"write") => WatchKind::Write,
+                    Some(&"read") => WatchKind::Read,
+                    Some(&"readwrite") => WatchKind::ReadWrite,
+                    Some(other) => return Err(CliError::InvalidArgument(other.to_string())),
+                };
+
+                let target = args.get(0).ok_or(CliError::MissingArgument)?;
+                let watchpoint = match u32::from_str_radix(target, 16) {
+                    Ok(address) => Watchpoint {
+                        location: Location::from_address(address, None),
+                        len: 4,
+                        kind,
+                    },
+                    Err(_) => {
+                        let variable = cli_data
+                            .debug_info
+                            .as_ref()
+                            .and_then(|di| di.find_static(target))
+                            .ok_or_else(|| CliError::UnknownSymbol(target.to_string()))?;
+
+                        Watchpoint {
+                            location: Location::from_symbol(target),
+                            len: variable.size().next_power_of_two(),
+                            kind,
+                        }
+                    }
+                };
+
+                cli_data.set_watchpoint(watchpoint)?;
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "display",
+            help_text: "Show a static variable whenever the core halts: 'display <variable>', 'display' alone shows them all",
+
+            function: |cli_data, args| {
+                if let Some(name) = args.get(0) {
+                    cli_data
+                        .debug_info
+                        .as_ref()
+                        .and_then(|di| di.find_static(name))
+                        .ok_or_else(|| CliError::UnknownSymbol(name.to_string()))?;
+
+                    if !cli_data.displays.iter().any(|display| display == name) {
+                        cli_data.displays.push(name.to_string());
+                    }
+                }
+
+                cli_data.show_displays()?;
 
                 Ok(CliState::Continue)
             },
@@ -140,6 +527,8 @@ impl DebugCli {
             help_text: "Show backtrace",
 
             function: |cli_data, _args| {
+                let thread_registers = cli_data.thread_registers()?;
+
                 let regs = cli_data.session.target.core.registers();
                 let program_counter = cli_data
                     .session
@@ -148,7 +537,10 @@ impl DebugCli {
                     .read_core_reg(&mut cli_data.session.probe, regs.PC)?;
 
                 if let Some(di) = &cli_data.debug_info {
-                    let frames = di.try_unwind(&mut cli_data.session, u64::from(program_counter));
+                    let frames = match thread_registers {
+                        Some(registers) => di.try_unwind_thread(&mut cli_data.session, registers),
+                        None => di.try_unwind(&mut cli_data.session, u64::from(program_counter)),
+                    };
 
                     for frame in frames {
                         println!("{}", frame);
@@ -159,6 +551,57 @@ impl DebugCli {
             },
         });
 
+        cli.add_command(Command {
+            name: "threads",
+            help_text: "List the threads of the RTOS the program uses, the selected one is marked",
+
+            function: |cli_data, _args| {
+                for thread in cli_data.threads()? {
+                    let marker = if cli_data.thread == Some(thread.id) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    let priority = thread
+                        .priority
+                        .map(|priority| priority.to_string())
+                        .unwrap_or_default();
+
+                    println!(
+                        "{} 0x{:08x} {:<24} {:<10} {}",
+                        marker, thread.id, thread.name, thread.state, priority
+                    );
+                }
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "thread",
+            help_text: "Select the thread whose stack 'bt' shows. 'thread <id>' selects a thread listed by 'threads', 'thread' alone selects the core again",
+
+            function: |cli_data, args| {
+                if args.is_empty() {
+                    cli_data.thread = None;
+                    println!("Showing the stack of the core.");
+                    return Ok(CliState::Continue);
+                }
+
+                let id = parse_argument(args.get(0))?;
+                let thread = cli_data
+                    .threads()?
+                    .into_iter()
+                    .find(|thread| thread.id == id)
+                    .ok_or_else(|| CliError::InvalidArgument(args[0].to_string()))?;
+
+                println!("Selected thread {} ({}).", thread.name, thread.state);
+                cli_data.thread = Some(id);
+
+                Ok(CliState::Continue)
+            },
+        });
+
         cli.add_command(Command {
             name: "regs",
             help_text: "Show CPU register values",
@@ -178,6 +621,13 @@ impl DebugCli {
                     println!("Register {}: {:#08x}", i, val);
                 }
 
+                if let Some(fp_regs) = cli_data.session.read_fp_registers()? {
+                    for (i, val) in fp_regs.s.iter().enumerate() {
+                        println!("S{}: {:#010x} ({})", i, val, f32::from_bits(*val));
+                    }
+                    println!("FPSCR: {:#010x}", fp_regs.fpscr);
+                }
+
                 Ok(CliState::Continue)
             },
         });
@@ -230,6 +680,7 @@ impl DebugCli {
                     .core
                     .read_core_reg(&mut cli_data.session.probe, regs.LR)?;
                 dump.regs[15] = pc;
+                dump.fp_regs = cli_data.session.read_fp_registers()?;
 
                 let serialized = ron::ser::to_string(&dump).expect("Failed to serialize dump");
 
@@ -243,6 +694,18 @@ impl DebugCli {
             },
         });
 
+        cli.add_command(Command {
+            name: "reattach",
+
+            help_text: "Open the probe again after it was disconnected",
+
+            function: |cli_data, _args| {
+                cli_data.session.reattach()?;
+
+                Ok(CliState::Continue)
+            },
+        });
+
         cli.add_command(Command {
             name: "reset",
 
@@ -257,11 +720,8 @@ impl DebugCli {
 
                 // Enable vector catch after reset (set bit 1 in DEMCR register)
                 cli_data.session.probe.write32(0xE000_EDFC, 1)?;
-                cli_data
-                    .session
-                    .target
-                    .core
-                    .reset(&mut cli_data.session.probe)?;
+                let reset_type = cli_data.session.target.reset_type;
+                cli_data.session.target_reset(reset_type)?;
 
                 Ok(CliState::Continue)
             },
@@ -274,6 +734,45 @@ impl DebugCli {
         self.commands.push(command)
     }
 
+    /// Names of all available commands, including the inbuilt `help`.
+    pub fn command_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.commands.iter().map(|c| c.name).collect();
+        names.push("help");
+        names
+    }
+
+    fn print_help(&self, command: Option<&str>) -> Result<(), CliError> {
+        match command {
+            Some(name) => {
+                let cmd = self
+                    .commands
+                    .iter()
+                    .find(|c| c.name == name)
+                    .ok_or_else(|| CliError::UnknownCommand(name.to_owned()))?;
+
+                println!("{} - {}", cmd.name, cmd.help_text);
+            }
+            None => {
+                println!("The following commands are available:");
+
+                let width = self
+                    .commands
+                    .iter()
+                    .map(|c| c.name.len())
+                    .max()
+                    .unwrap_or(0);
+
+                for cmd in &self.commands {
+                    println!(" - {:width$}  {}", cmd.name, cmd.help_text, width = width);
+                }
+
+                println!("Enter 'help <command>' for help on a single command.");
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn handle_line(&self, line: &str, cli_data: &mut CliData) -> Result<CliState, CliError> {
         let mut command_parts = line.split_whitespace();
 
@@ -281,11 +780,7 @@ impl DebugCli {
             // Special case for inbuilt help
 
             if command == "help" {
-                println!("The following commands are available:");
-
-                for cmd in &self.commands {
-                    println!(" - {}", cmd.name);
-                }
+                self.print_help(command_parts.next())?;
 
                 return Ok(CliState::Continue);
             }
@@ -297,21 +792,506 @@ impl DebugCli {
 
                 (cmd.function)(cli_data, &remaining_args)
             } else {
-                println!("Unknown command '{}'", command);
-                println!("Enter 'help' for a list of commands");
-
-                Ok(CliState::Continue)
+                Err(CliError::UnknownCommand(command.to_owned()))
             }
         } else {
             Ok(CliState::Continue)
         }
     }
+
+    /// Executes a list of commands without user interaction.
+    ///
+    /// Empty lines and lines starting with `#` are skipped. Each command is echoed
+    /// before it is run, and execution stops at the first failing command.
+    pub fn run_script(&self, lines: &[String], cli_data: &mut CliData) -> Result<(), CliError> {
+        for (number, line) in lines.iter().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if interrupted() {
+                println!("Interrupted, skipping the remaining commands.");
+                return Ok(());
+            }
+
+            println!(">> {}", line);
+
+            match self.handle_line(line, cli_data) {
+                Ok(CliState::Continue) => (),
+                Ok(CliState::Stop) => return Ok(()),
+                Err(e) => {
+                    eprintln!("Command {} ('{}') failed.", number + 1, line);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits the arguments of `break` and `tracepoint` into those before `if` and the condition
+/// after it, which is checked to be valid.
+fn split_condition<'a, 'b>(
+    args: &'a [&'b str],
+) -> Result<(&'a [&'b str], Option<String>), CliError> {
+    match args.iter().position(|arg| *arg == "if") {
+        None => Ok((args, None)),
+        Some(index) if index + 1 < args.len() => {
+            let condition = args[index + 1..].join(" ");
+            condition.parse::<Condition>()?;
+            Ok((&args[..index], Some(condition)))
+        }
+        Some(_) => Err(CliError::MissingArgument),
+    }
+}
+
+/// Parses the name of a core register, `r0` to `r15`, `sp`, `lr` or `pc`.
+fn register(name: &str) -> Option<CoreRegisterAddress> {
+    let index = match name.to_lowercase().as_str() {
+        "sp" => 13,
+        "lr" => 14,
+        "pc" => 15,
+        name if name.starts_with('r') => name[1..].parse().ok().filter(|index| *index < 16)?,
+        _ => return None,
+    };
+
+    Some(CoreRegisterAddress(index))
+}
+
+/// Parses a numeric command argument.
+///
+/// Numbers prefixed with `0x` are hexadecimal, all others decimal.
+/// Underscores can be used as separators, e.g. `0x2000_0000`.
+fn parse_argument(argument: Option<&&str>) -> Result<u32, CliError> {
+    let argument = argument.ok_or(CliError::MissingArgument)?;
+    let digits = argument.replace('_', "");
+
+    let result = if digits.starts_with("0x") || digits.starts_with("0X") {
+        u32::from_str_radix(&digits[2..], 16)
+    } else {
+        digits.parse()
+    };
+
+    result.map_err(|_| CliError::InvalidArgument(argument.to_string()))
 }
 
 pub struct CliData {
     pub session: Session,
     pub debug_info: Option<DebugInfo>,
     pub capstone: Capstone,
+    /// DWT counters at the last halt, set once the `cycles` command enabled the counters.
+    pub cycle_counters: Option<DwtCounters>,
+    /// The Micro Trace Buffer, set once the `trace start` command found it.
+    pub mtb: Option<Mtb>,
+    /// The RTOS the program uses, whose threads the `threads` command lists.
+    pub rtos: Option<Box<dyn RtosProvider>>,
+    /// The thread selected with the `thread` command, whose stack `bt` shows instead of that
+    /// of the core.
+    pub thread: Option<u32>,
+    /// The breakpoints set with the `break` command.
+    pub breakpoints: Vec<Breakpoint>,
+    /// The watchpoints set with the `watch` command, each using the DWT comparator of its
+    /// index.
+    pub watchpoints: Vec<Watchpoint>,
+    /// The static variables shown whenever the core halts.
+    pub displays: Vec<String>,
+    pub tracepoint_log: TracepointLog,
+}
+
+/// The values logged at tracepoints, printed and appended to `TRACEPOINT_LOG_FILE_NAME`.
+pub struct TracepointLog {
+    /// Entries are stamped with the time since the debugger started.
+    start: Instant,
+    /// The log file, opened on the first entry.
+    file: Option<File>,
+}
+
+impl TracepointLog {
+    pub fn new() -> Self {
+        TracepointLog {
+            start: Instant::now(),
+            file: None,
+        }
+    }
+
+    fn write(&mut self, entry: &str) -> io::Result<()> {
+        let line = format!("[{:>10.3}] {}", self.start.elapsed().as_secs_f64(), entry);
+        println!("{}", line);
+
+        if self.file.is_none() {
+            if let Some(dir) = Path::new(TRACEPOINT_LOG_FILE_NAME).parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(TRACEPOINT_LOG_FILE_NAME)?;
+            self.file = Some(file);
+        }
+
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Default size of the MTB trace buffer, in bytes.
+const DEFAULT_TRACE_BUFFER_SIZE: u32 = 1024;
+
+/// Default number of instructions shown by `trace instructions`.
+const DEFAULT_TRACE_INSTRUCTIONS: usize = 50;
+
+impl CliData {
+    /// Parses a location given as a hex address, or as the name of a function or a static
+    /// variable.
+    fn location(&self, argument: Option<&&str>) -> Result<Location, CliError> {
+        let argument = argument.ok_or(CliError::MissingArgument)?;
+
+        match u32::from_str_radix(argument, 16) {
+            Ok(address) => Ok(Location::from_address(address, self.debug_info.as_ref())),
+            Err(_) => Ok(Location::from_symbol(argument)),
+        }
+    }
+
+    /// Returns the address of a location in the program.
+    fn resolve(&self, location: &Location) -> Result<u32, CliError> {
+        location
+            .resolve(self.debug_info.as_ref())
+            .ok_or_else(|| CliError::UnknownSymbol(location.to_string()))
+    }
+
+    /// Sets a breakpoint, replacing the condition and ignore count of one at the same location.
+    fn set_breakpoint(&mut self, breakpoint: Breakpoint) -> Result<(), CliError> {
+        let address = self.resolve(&breakpoint.location)?;
+        self.session.set_breakpoint(address)?;
+
+        match self
+            .breakpoints
+            .iter_mut()
+            .find(|existing| existing.location == breakpoint.location)
+        {
+            Some(existing) => *existing = breakpoint,
+            None => self.breakpoints.push(breakpoint),
+        }
+
+        Ok(())
+    }
+
+    /// Resumes the core until it stops at a breakpoint which is not ignored and whose
+    /// condition holds, or for another reason. Other breakpoint hits are stepped over silently.
+    fn continue_until_stop(&mut self) -> Result<(), CliError> {
+        // A Ctrl-C pressed before should not stop the core right away.
+        take_interrupted();
+
+        loop {
+            self.session.target.core.run(&mut self.session.probe)?;
+
+            loop {
+                match self
+                    .session
+                    .target
+                    .core
+                    .wait_for_core_halted(&mut self.session.probe)
+                {
+                    Ok(()) => break,
+                    Err(DebugProbeError::Timeout) if take_interrupted() => {
+                        let cpu_info = self.session.target.core.halt(&mut self.session.probe)?;
+                        println!("Interrupted at address 0x{:08x}", cpu_info.pc);
+                        return self.show_displays();
+                    }
+                    Err(DebugProbeError::Timeout) => (),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            let pc_register = self.session.target.core.registers().PC;
+            let pc = self
+                .session
+                .target
+                .core
+                .read_core_reg(&mut self.session.probe, pc_register)?;
+
+            if self.stops_at(pc)? {
+                println!("Core stopped at address 0x{:08x}", pc);
+                return self.show_displays();
+            }
+
+            // The breakpoint would halt the core again right away.
+            let core = &self.session.target.core;
+            core.disable_breakpoint(&mut self.session.probe, pc)?;
+            core.step(&mut self.session.probe)?;
+            core.enable_breakpoint(&mut self.session.probe, pc)?;
+        }
+    }
+
+    /// Decides whether the core stays halted at `pc`, counting the hit if it halted at a
+    /// breakpoint whose condition holds. Tracepoints are logged and never stop the core.
+    ///
+    /// Like in GDB, the ignore count only applies to hits where the condition holds. If the
+    /// condition can not be evaluated, the core stays halted.
+    fn stops_at(&mut self, pc: u32) -> Result<bool, CliError> {
+        let debug_info = self.debug_info.as_ref();
+        let breakpoint = match self
+            .breakpoints
+            .iter_mut()
+            .find(|breakpoint| breakpoint.location.resolve(debug_info) == Some(pc))
+        {
+            Some(breakpoint) => breakpoint,
+            None => return Ok(true),
+        };
+
+        if let Some(condition) = &breakpoint.condition {
+            let holds = condition
+                .parse::<Condition>()
+                .and_then(|c| c.evaluate(debug_info, &mut self.session.probe));
+
+            match holds {
+                Ok(true) => (),
+                Ok(false) => return Ok(false),
+                Err(e) => {
+                    eprintln!("Failed to evaluate the condition '{}': {}", condition, e);
+                    return Ok(true);
+                }
+            }
+        }
+
+        breakpoint.hits += 1;
+        if breakpoint.hits <= breakpoint.ignore_count {
+            return Ok(false);
+        }
+
+        if breakpoint.trace.is_empty() {
+            return Ok(true);
+        }
+
+        let location = breakpoint.location.to_string();
+        let trace = breakpoint.trace.clone();
+        self.log_tracepoint(&location, &trace)?;
+
+        Ok(false)
+    }
+
+    /// Reads the variables and registers of a tracepoint and appends them to the log.
+    fn log_tracepoint(&mut self, location: &str, trace: &[String]) -> Result<(), CliError> {
+        let mut values = Vec::with_capacity(trace.len());
+
+        for expression in trace {
+            let value = match register(expression) {
+                Some(register) => {
+                    let value = self
+                        .session
+                        .target
+                        .core
+                        .read_core_reg(&mut self.session.probe, register)?;
+                    format!("0x{:08x}", value)
+                }
+                None => match self
+                    .debug_info
+                    .as_ref()
+                    .and_then(|di| di.find_static(expression))
+                {
+                    Some(variable) => variable.read(&mut self.session.probe)?,
+                    None => "<not found>".to_string(),
+                },
+            };
+
+            values.push(format!("{} = {}", expression, value));
+        }
+
+        self.tracepoint_log
+            .write(&format!("{}: {}", location, values.join(", ")))?;
+
+        Ok(())
+    }
+
+    fn set_watchpoint(&mut self, watchpoint: Watchpoint) -> Result<(), CliError> {
+        let address = self.resolve(&watchpoint.location)?;
+        let unit = self.watchpoints.len();
+
+        dwt::set_watchpoint(
+            &mut self.session.probe,
+            unit,
+            address,
+            watchpoint.len,
+            watchpoint.kind,
+        )?;
+        self.watchpoints.push(watchpoint);
+
+        Ok(())
+    }
+
+    /// Disables the watchpoints, which are not removed when detaching from the core.
+    pub fn clear_watchpoints(&mut self) -> Result<(), CliError> {
+        for unit in 0..self.watchpoints.len() {
+            dwt::clear_watchpoint(&mut self.session.probe, unit)?;
+        }
+        self.watchpoints.clear();
+
+        Ok(())
+    }
+
+    /// Prints the values of the variables selected with the `display` command.
+    fn show_displays(&mut self) -> Result<(), CliError> {
+        for name in &self.displays {
+            match self.debug_info.as_ref().and_then(|di| di.find_static(name)) {
+                Some(variable) => {
+                    let value = variable.read(&mut self.session.probe)?;
+                    println!("{} = {}", variable.name, value);
+                }
+                None => println!("{} = <not found>", name),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the breakpoints and watchpoints of a saved session again and displays its
+    /// variables. Those whose symbol does not exist anymore are dropped.
+    pub fn restore(&mut self, saved: SavedSession) {
+        for breakpoint in saved.breakpoints {
+            let description = breakpoint.location.to_string();
+            if let Err(e) = self.set_breakpoint(breakpoint) {
+                eprintln!("Dropping the breakpoint at {}: {}", description, e);
+            }
+        }
+
+        for watchpoint in saved.watchpoints {
+            let description = watchpoint.location.to_string();
+            if let Err(e) = self.set_watchpoint(watchpoint) {
+                eprintln!("Dropping the watchpoint at {}: {}", description, e);
+            }
+        }
+
+        self.displays = saved.displays;
+    }
+
+    /// Returns the breakpoints, watchpoints and displayed variables, to be saved for `elf`.
+    pub fn saved_session(&self, elf: &Path) -> SavedSession {
+        SavedSession {
+            displays: self.displays.clone(),
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            ..SavedSession::new(elf)
+        }
+    }
+
+    /// Lists the threads of the RTOS the program uses.
+    fn threads(&mut self) -> Result<Vec<Thread>, CliError> {
+        match (&self.rtos, &self.debug_info) {
+            (Some(rtos), Some(debug_info)) => Ok(rtos.threads(debug_info, &mut self.session)?),
+            _ => {
+                println!("The program does not use a supported RTOS.");
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Returns the registers the selected thread saved, or `None` if those of the core are
+    /// to be used.
+    fn thread_registers(&mut self) -> Result<Option<[u32; 16]>, CliError> {
+        let id = match self.thread {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        match self.threads()?.into_iter().find(|thread| thread.id == id) {
+            Some(thread) => {
+                if thread.registers.is_none() && thread.state != ThreadState::Running {
+                    println!(
+                        "The registers of thread {} are not known, showing the stack of the core.",
+                        thread.name
+                    );
+                }
+                Ok(thread.registers)
+            }
+            None => {
+                println!("The selected thread does not exist anymore.");
+                self.thread = None;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Prints the last `count` instructions recorded by the MTB and restarts recording.
+    fn print_trace(&mut self, count: usize) -> Result<(), CliError> {
+        let mtb = match self.mtb {
+            Some(mtb) => mtb,
+            None => {
+                println!("Tracing is not running, start it with 'trace start'.");
+                return Ok(());
+            }
+        };
+
+        let probe = &mut self.session.probe;
+
+        mtb.stop(probe)?;
+        let packets = mtb.read_packets(probe)?;
+
+        // The PC can only be read while the core is halted, which is when the trace is most useful.
+        let regs = self.session.target.core.registers();
+        let pc = self.session.target.core.read_core_reg(probe, regs.PC).ok();
+
+        let mut lines = Vec::new();
+        let mut current_function = None;
+
+        for range in mtb::reconstruct(&packets, pc) {
+            let mut code = vec![0u8; (range.end - range.start + 4) as usize];
+            probe.read_block8(range.start, &mut code)?;
+
+            let instructions = match self.capstone.disasm_all(&code, u64::from(range.start)) {
+                Ok(instructions) => instructions,
+                Err(_) => continue,
+            };
+
+            if range.exception {
+                lines.push("  <exception>".to_string());
+            }
+
+            for instruction in instructions
+                .iter()
+                .filter(|i| i.address() <= u64::from(range.end))
+            {
+                let function = self
+                    .debug_info
+                    .as_ref()
+                    .and_then(|di| di.function_name(instruction.address()));
+
+                if function.is_some() && function != current_function {
+                    lines.push(format!("{}:", function.as_ref().unwrap()));
+                    current_function = function;
+                }
+
+                lines.push(format!("  {}", instruction));
+            }
+        }
+
+        for line in lines.iter().skip(lines.len().saturating_sub(count)) {
+            println!("{}", line);
+        }
+
+        mtb.restart(probe)?;
+
+        Ok(())
+    }
+
+    /// Prints the DWT counter increments since the last halt, if counting is enabled.
+    fn report_cycles(&mut self) -> Result<(), CliError> {
+        if let Some(previous) = self.cycle_counters {
+            let counters = dwt::read_counters(&mut self.session.probe)?;
+
+            println!("Since last halt: {}", counters.delta(&previous));
+
+            self.cycle_counters = Some(counters);
+        }
+
+        Ok(())
+    }
 }
 
 pub enum CliState {
@@ -325,3 +1305,79 @@ struct Command {
 
     pub function: fn(&mut CliData, args: &[&str]) -> Result<CliState, CliError>,
 }
+
+/// Register names offered for completion in command arguments.
+const REGISTER_NAMES: &[&str] = &[
+    "R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "R8", "R9", "R10", "R11", "R12", "SP", "LR",
+    "PC", "XPSR",
+];
+
+/// Line editor helper for the debugger REPL.
+///
+/// The first word of a line is completed from the command table, any further
+/// words from the register names and the function names in the debug info.
+pub struct CliHelper {
+    commands: Vec<&'static str>,
+    symbols: Vec<String>,
+}
+
+impl CliHelper {
+    pub fn new(cli: &DebugCli, debug_info: Option<&DebugInfo>) -> Self {
+        CliHelper {
+            commands: cli.command_names(),
+            symbols: debug_info
+                .map(DebugInfo::function_names)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Completer for CliHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+
+        let to_pair = |candidate: &str| Pair {
+            display: candidate.to_owned(),
+            replacement: candidate.to_owned(),
+        };
+
+        let candidates = if line[..start].trim().is_empty() {
+            self.commands
+                .iter()
+                .filter(|c| c.starts_with(word))
+                .map(|c| to_pair(c))
+                .collect()
+        } else {
+            let upper = word.to_uppercase();
+
+            REGISTER_NAMES
+                .iter()
+                .filter(|r| r.starts_with(&upper))
+                .map(|r| to_pair(r))
+                .chain(
+                    self.symbols
+                        .iter()
+                        .filter(|s| s.starts_with(word))
+                        .map(|s| to_pair(s)),
+                )
+                .collect()
+        };
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for CliHelper {}
+
+impl Highlighter for CliHelper {}
+
+impl Helper for CliHelper {}
@@ -0,0 +1,121 @@
+//! Advice on the failures users run into most, printed below the error.
+//!
+//! The errors are recognized by their type anywhere in the chain of sources, so e.g. a target
+//! which does not answer gets the same advice while flashing as while debugging.
+
+use crate::common::CliError;
+
+use probe_rs::{
+    coresight::access_ports::AccessPortError,
+    probe::{
+        debug_probe::{DapError, DebugProbeError},
+        flash::{algorithm_fault::FlashAlgorithmFault, protection::ProtectionError},
+    },
+};
+
+use std::error::Error;
+
+const NO_PROBE: &str = "No probe was found, or more than one. List the probes with `probe-rs-cli list` and select one with `--probe-index` or `--probe-serial`.";
+const PROBE_IN_USE: &str = "The probe could not be opened. It may be in use by another process, e.g. OpenOCD or a GDB server, or you may lack the permission to access it. On Linux, install the udev rules of the probe.";
+const NO_ACK: &str = "The target does not answer. Check that it is powered, and that SWDIO, SWCLK and GND are connected to the probe.";
+const WAIT: &str = "The target keeps its bus busy, e.g. in a low power mode. Try a lower `--speed`, or reset the target to wake it up.";
+const CORRUPTED: &str = "Transfers are corrupted on the wire. Try a lower `--speed`, or shorter wires between the probe and the target.";
+const AP_LOCKED: &str = "The target refused an access. The address may not be mapped, or the access port is locked because the device is read-protected.";
+const READ_PROTECTED: &str = "The device is read-protected. Only a mass erase unlocks it, e.g. the `erase-all` step of a `provision` manifest, which erases the firmware as well.";
+const POWER_UP: &str =
+    "The debug port of the target did not power up. Check the supply voltage of the target.";
+const TIMEOUT: &str = "The core did not respond in time. It may be sleeping or locked up, which `probe-rs-cli status` tells. Pass `--recover-lockup` to reset a locked up core.";
+const ALGORITHM_FAULT: &str = "The flash algorithm of the target description failed. The registers show where it stopped; `probe-rs-cli test-algorithm` runs each of its routines on its own.";
+const NO_JTAG: &str = "The probe does not support JTAG, connect with `--protocol swd`.";
+const GPIO: &str =
+    "Check the lines named by `PROBE_RS_GPIO`, and that you may access the GPIO chip.";
+
+/// Returns advice on how to fix `error`, if it is a common failure.
+pub(crate) fn advice(error: &CliError) -> Option<&'static str> {
+    if let CliError::UnableToOpenProbe = error {
+        return Some(NO_PROBE);
+    }
+
+    let mut source: Option<&(dyn Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        let advice = if let Some(error) = error.downcast_ref::<DebugProbeError>() {
+            probe_advice(error)
+        } else if let Some(error) = error.downcast_ref::<DapError>() {
+            Some(dap_advice(*error))
+        } else if let Some(error) = error.downcast_ref::<AccessPortError>() {
+            access_port_advice(error)
+        } else if let Some(ProtectionError::Enabled(_)) = error.downcast_ref::<ProtectionError>() {
+            Some(READ_PROTECTED)
+        } else if error.downcast_ref::<FlashAlgorithmFault>().is_some() {
+            Some(ALGORITHM_FAULT)
+        } else {
+            None
+        };
+
+        if advice.is_some() {
+            return advice;
+        }
+        source = error.source();
+    }
+
+    None
+}
+
+fn probe_advice(error: &DebugProbeError) -> Option<&'static str> {
+    match error {
+        DebugProbeError::ProbeCouldNotBeCreated | DebugProbeError::USBError => Some(PROBE_IN_USE),
+        DebugProbeError::TargetPowerUpFailed => Some(POWER_UP),
+        DebugProbeError::Timeout => Some(TIMEOUT),
+        DebugProbeError::JTAGNotSupportedOnProbe => Some(NO_JTAG),
+        DebugProbeError::Gpio(_) => Some(GPIO),
+        DebugProbeError::Dap(error) => Some(dap_advice(*error)),
+        _ => None,
+    }
+}
+
+fn dap_advice(error: DapError) -> &'static str {
+    match error {
+        DapError::NoAcknowledge => NO_ACK,
+        DapError::Wait => WAIT,
+        DapError::Parity | DapError::WriteData | DapError::Overrun => CORRUPTED,
+        DapError::Sticky | DapError::Fault => AP_LOCKED,
+    }
+}
+
+fn access_port_advice(error: &AccessPortError) -> Option<&'static str> {
+    match error {
+        AccessPortError::RegisterReadError { .. } | AccessPortError::RegisterWriteError { .. } => {
+            Some(AP_LOCKED)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{advice, NO_ACK, NO_PROBE, WAIT};
+    use crate::common::CliError;
+
+    use probe_rs::probe::{
+        debug_probe::{DapError, DebugProbeError},
+        flash::flasher::FlasherError,
+    };
+
+    #[test]
+    fn advises_on_nested_errors() {
+        assert_eq!(advice(&CliError::UnableToOpenProbe), Some(NO_PROBE));
+        assert_eq!(
+            advice(&CliError::DebugProbe(DebugProbeError::Dap(
+                DapError::NoAcknowledge
+            ))),
+            Some(NO_ACK)
+        );
+        assert_eq!(
+            advice(&CliError::Flasher(FlasherError::DebugProbe(
+                DebugProbeError::Dap(DapError::Wait)
+            ))),
+            Some(WAIT)
+        );
+        assert_eq!(advice(&CliError::MissingArgument), None);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::common::{configuration, CliError};
+use crate::{address_translation, file_format, SharedOptions};
+
+use probe_rs::probe::flash::{
+    download::{read_allocations, read_flash_image, FileDownloadError, Format},
+    plan::FlashPlan,
+    usage::MemoryUsage,
+};
+use probe_rs_targets::{select_target, SelectionStrategy};
+
+use std::ops::Range;
+use std::path::Path;
+
+/// Prints what downloading a file would erase and program, without connecting to the target.
+///
+/// The target is not detected, so it has to be named with `--target`.
+pub(crate) fn show_plan(
+    shared_options: &SharedOptions,
+    path: &Path,
+    format: Option<Format>,
+    offset: Option<u32>,
+    bootloader: Option<Range<u32>>,
+    json: bool,
+) -> Result<(), CliError> {
+    let config = configuration(shared_options)?;
+    let target_name = config.target.ok_or_else(|| {
+        CliError::InvalidArgument(
+            "--dry-run needs --target, as the target is not detected without connecting to it"
+                .to_string(),
+        )
+    })?;
+    let target = select_target(&SelectionStrategy::Name(target_name))?;
+
+    let format = file_format(path, format)?;
+    let image = read_flash_image(path, format.clone(), &target.memory_map)?;
+    let translation = address_translation(&target.memory_map, offset, bootloader);
+    let plan = FlashPlan::new(&target.memory_map, &image, translation.as_ref())
+        .map_err(FileDownloadError::from)?;
+    let usage = MemoryUsage::new(
+        &target.memory_map,
+        &read_allocations(path, format)?,
+        translation.as_ref(),
+    );
+
+    if json {
+        warn_about_usage(&usage);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&plan).expect("Failed to serialize the plan.")
+        );
+        return Ok(());
+    }
+
+    println!("Downloading {} to {} would:", path.display(), target.name);
+    for sector in &plan.sectors {
+        let data: u32 = sector.pages.iter().map(|page| page.data_bytes).sum();
+        let fill: u32 = sector.pages.iter().map(|page| page.fill_bytes).sum();
+
+        print!(
+            "  Erase 0x{:08x}..0x{:08x} and program {} pages with {} bytes",
+            sector.address,
+            sector.address + sector.size,
+            sector.pages.len(),
+            data
+        );
+        if fill > 0 {
+            print!(" and {} fill bytes of 0x{:02x}", fill, sector.fill_value);
+        }
+        println!();
+    }
+
+    let pages = plan.pages().count();
+    let data: u64 = plan.pages().map(|page| u64::from(page.data_bytes)).sum();
+    let fill: u64 = plan.pages().map(|page| u64::from(page.fill_bytes)).sum();
+    println!(
+        "{} sectors with {} bytes erased, {} pages programmed with {} bytes of data and {} fill bytes.",
+        plan.sectors.len(),
+        plan.erased_bytes(),
+        pages,
+        data,
+        fill
+    );
+
+    println!("Memory usage:");
+    for region in usage.used_regions() {
+        println!(
+            "  {:<10} 0x{:08x}..0x{:08x} {:>10} of {:>10} bytes ({:.1}%)",
+            region.kind,
+            region.range.start,
+            region.range.end,
+            region.used,
+            region.size(),
+            region.percent()
+        );
+    }
+    warn_about_usage(&usage);
+
+    Ok(())
+}
+
+/// Prints the warnings about the memory usage of an image, which does not fit the target.
+pub(crate) fn warn_about_usage(usage: &MemoryUsage) {
+    for warning in &usage.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::common::{interrupted, with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::probe::flash::memory::{regions_in, MemoryRegion};
+
+use colored::*;
+use serde::{Deserialize, Serialize};
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Memory is read in chunks of this many words, which are retried as a whole.
+const CHUNK_WORDS: usize = 1024;
+
+pub(crate) struct DumpOptions {
+    /// Whether words which fault are skipped instead of aborting the dump.
+    pub skip_faults: bool,
+    /// How often a chunk which faulted is read again before it is narrowed down to the
+    /// faulting words.
+    pub retries: u32,
+    /// The file the memory is written to, instead of printing it.
+    pub output: Option<PathBuf>,
+    /// Whether to continue the dump in `output` where it stopped.
+    pub resume: bool,
+    /// Whether to read regions marked as not readable in the memory map.
+    pub force: bool,
+}
+
+/// The parts of a range of memory, with the regions of the memory map they are in.
+pub(crate) type Regions<'a> = Vec<(Range<u32>, Option<&'a MemoryRegion>)>;
+
+/// What was dumped into a file, stored next to it with the extension `.json` appended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct DumpMetadata {
+    address: u32,
+    words: u32,
+    /// The address ranges which faulted, written as zeros.
+    faults: Vec<Range<u32>>,
+    /// The kinds of the regions of the memory map the dump covers.
+    #[serde(default)]
+    regions: Vec<RegionAnnotation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RegionAnnotation {
+    range: Range<u32>,
+    kind: String,
+}
+
+/// Dumps `words` words of memory starting at `address`.
+///
+/// The memory is read in chunks, so a dump into a file which was interrupted, by Ctrl-C or a
+/// failing read, can be resumed.
+pub(crate) fn dump_memory(
+    shared_options: &SharedOptions,
+    address: u32,
+    words: u32,
+    options: DumpOptions,
+) -> Result<(), CliError> {
+    let mut metadata = DumpMetadata {
+        address,
+        words,
+        faults: vec![],
+        regions: vec![],
+    };
+    let mut done = 0;
+    let mut file = match &options.output {
+        Some(path) if options.resume && path.exists() => {
+            let previous: DumpMetadata =
+                serde_json::from_slice(&fs::read(metadata_path(path))?).map_err(io::Error::from)?;
+            if (previous.address, previous.words) != (address, words) {
+                return Err(CliError::InvalidArgument(format!(
+                    "{} holds {} words at 0x{:08x}, which can not be resumed as {} words at 0x{:08x}.",
+                    path.display(),
+                    previous.words,
+                    previous.address,
+                    words,
+                    address
+                )));
+            }
+            metadata = previous;
+
+            // A word which was only partially written is read again.
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            done = (file.metadata()?.len() / 4).min(u64::from(words)) as u32;
+            file.set_len(u64::from(done) * 4)?;
+            file.seek(SeekFrom::End(0))?;
+
+            Some(file)
+        }
+        Some(path) => {
+            let file = File::create(path)?;
+            write_metadata(path, &metadata)?;
+
+            Some(file)
+        }
+        None => None,
+    };
+    let resumed_at = done;
+
+    with_device(shared_options, |mut session| {
+        let memory_map = session.target.memory_map.clone();
+        let regions = readable_regions(&memory_map, address..address + 4 * words, options.force)?;
+        metadata.regions = regions
+            .iter()
+            .map(|(range, region)| RegionAnnotation {
+                range: range.clone(),
+                kind: kind(*region).to_string(),
+            })
+            .collect();
+
+        let start = Instant::now();
+        let mut buffer = vec![0; CHUNK_WORDS];
+        let endian = session.target.endian;
+
+        while done < words && !interrupted() {
+            let count = (words - done).min(CHUNK_WORDS as u32) as usize;
+            let chunk_address = address + 4 * done;
+            let chunk = &mut buffer[..count];
+
+            let read = read_chunk(
+                &mut |address, data| session.read_memory32(address, data),
+                chunk_address,
+                chunk,
+                &options,
+                &mut metadata.faults,
+            );
+
+            if let Err(e) = read {
+                if let Some(path) = &options.output {
+                    eprintln!(
+                        "Reading 0x{:08x} failed, continue the dump with `--resume --output {}`.",
+                        chunk_address,
+                        path.display()
+                    );
+                }
+                return Err(e.into());
+            }
+
+            match (&mut file, &options.output) {
+                (Some(file), Some(path)) => {
+                    for word in chunk.iter() {
+                        file.write_all(&endian.u32_to_bytes(*word))?;
+                    }
+                    file.flush()?;
+                    write_metadata(path, &metadata)?;
+                }
+                _ => {
+                    for (index, word) in chunk.iter().enumerate() {
+                        let word_address = chunk_address + 4 * index as u32;
+
+                        if let Some(header) = region_header(&regions, word_address) {
+                            println!("{}", header);
+                        }
+                        if is_fault(&metadata.faults, word_address) {
+                            println!("Addr 0x{:08x?}: fault", word_address);
+                        } else {
+                            println!("Addr 0x{:08x?}: 0x{:08x}", word_address, word);
+                        }
+                    }
+                }
+            }
+
+            done += count as u32;
+        }
+
+        println!(
+            "Read {:?} words in {:?}",
+            done - resumed_at,
+            start.elapsed()
+        );
+
+        if !metadata.faults.is_empty() {
+            let faulting: u32 = metadata
+                .faults
+                .iter()
+                .map(|range| (range.end - range.start) / 4)
+                .sum();
+            println!("Skipped {} faulting words:", faulting);
+            for range in &metadata.faults {
+                println!("    0x{:08x}..0x{:08x}", range.start, range.end);
+            }
+        }
+
+        if done < words {
+            if let Some(path) = &options.output {
+                println!(
+                    "Interrupted, continue the dump with `--resume --output {}`.",
+                    path.display()
+                );
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Splits the memory to read at the regions of the memory map of the target.
+///
+/// Refuses to read regions marked as not readable unless `force` is set, as reading some
+/// peripherals locks up the target, and warns about memory outside of the memory map.
+pub(crate) fn readable_regions(
+    memory_map: &[MemoryRegion],
+    range: Range<u32>,
+    force: bool,
+) -> Result<Regions, CliError> {
+    let regions = regions_in(memory_map, range);
+
+    for (range, region) in &regions {
+        match region {
+            Some(region) if !region.is_readable() && !force => {
+                return Err(CliError::InvalidArgument(format!(
+                    "0x{:08x}..0x{:08x} is in a {} region marked as not readable",
+                    range.start,
+                    range.end,
+                    region.kind()
+                )));
+            }
+            None => eprintln!(
+                "    {} 0x{:08x}..0x{:08x} is not in the memory map of the target.",
+                "Warning".yellow().bold(),
+                range.start,
+                range.end
+            ),
+            _ => (),
+        }
+    }
+
+    Ok(regions)
+}
+
+/// Returns the line printed before the memory read from the part of `regions` starting at
+/// `address`, naming the kind of its region.
+pub(crate) fn region_header(regions: &Regions, address: u32) -> Option<String> {
+    regions
+        .iter()
+        .find(|(range, _)| range.start == address)
+        .map(|(range, region)| {
+            format!(
+                "{} 0x{:08x}..0x{:08x}",
+                kind(*region).bold(),
+                range.start,
+                range.end
+            )
+        })
+}
+
+fn kind(region: Option<&MemoryRegion>) -> &'static str {
+    region.map_or("Reserved", MemoryRegion::kind)
+}
+
+/// Reads a chunk of memory, retrying it as a whole first.
+///
+/// If skipping faults, a chunk which still fails is split in halves until the faulting words
+/// are found, which are zeroed and added to `faults`.
+fn read_chunk<E>(
+    read: &mut impl FnMut(u32, &mut [u32]) -> Result<(), E>,
+    address: u32,
+    data: &mut [u32],
+    options: &DumpOptions,
+    faults: &mut Vec<Range<u32>>,
+) -> Result<(), E> {
+    let mut result = read(address, data);
+    for _ in 0..options.retries {
+        if result.is_ok() {
+            break;
+        }
+        result = read(address, data);
+    }
+
+    match result {
+        Err(_) if options.skip_faults => {
+            skip_faults(read, address, data, faults);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Narrows a read which failed down to the words which fault.
+fn skip_faults<E>(
+    read: &mut impl FnMut(u32, &mut [u32]) -> Result<(), E>,
+    address: u32,
+    data: &mut [u32],
+    faults: &mut Vec<Range<u32>>,
+) {
+    if data.len() == 1 {
+        data[0] = 0;
+
+        match faults.last_mut() {
+            Some(last) if last.end == address => last.end += 4,
+            _ => faults.push(address..address + 4),
+        }
+        return;
+    }
+
+    let (first, second) = data.split_at_mut(data.len() / 2);
+    let second_address = address + 4 * first.len() as u32;
+
+    for (address, data) in vec![(address, first), (second_address, second)] {
+        if read(address, data).is_err() {
+            skip_faults(read, address, data, faults);
+        }
+    }
+}
+
+fn is_fault(faults: &[Range<u32>], address: u32) -> bool {
+    faults.iter().any(|range| range.contains(&address))
+}
+
+fn write_metadata(path: &Path, metadata: &DumpMetadata) -> io::Result<()> {
+    fs::write(
+        metadata_path(path),
+        serde_json::to_vec_pretty(metadata).map_err(io::Error::from)?,
+    )
+}
+
+fn metadata_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".json");
+
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_chunk, DumpOptions};
+
+    use probe_rs::coresight::access_ports::AccessPortError;
+
+    #[test]
+    fn skips_faulting_words() {
+        let options = DumpOptions {
+            skip_faults: true,
+            retries: 1,
+            output: None,
+            resume: false,
+            force: false,
+        };
+        // The words at 0x1008 to 0x1010 fault.
+        let mut read = |address: u32, data: &mut [u32]| {
+            let end = address + 4 * data.len() as u32;
+            if address < 0x1010 && end > 0x1008 {
+                return Err(AccessPortError::InvalidAccessPortNumber);
+            }
+            for (index, word) in data.iter_mut().enumerate() {
+                *word = address + 4 * index as u32;
+            }
+            Ok(())
+        };
+
+        let mut data = [0xffff_ffff; 8];
+        let mut faults = vec![];
+        read_chunk(&mut read, 0x1000, &mut data, &options, &mut faults).unwrap();
+
+        assert_eq!(faults, vec![0x1008..0x1010]);
+        assert_eq!(data, [0x1000, 0x1004, 0, 0, 0x1010, 0x1014, 0x1018, 0x101c]);
+    }
+}
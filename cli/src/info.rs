@@ -1,88 +1,211 @@
-use crate::common::open_probe;
+use crate::common::{configuration, open_probe};
 use crate::{common::CliError, SharedOptions};
 
+use jep106::JEP106Code;
 use probe_rs::{
+    collection::cores::{cpuid::CoreInfo, security::DebugAuthentication},
     coresight::{
         access_ports::{
-            generic_ap::{APClass, IDR},
-            memory_ap::{BaseaddrFormat, MemoryAP, BASE, BASE2},
+            generic_ap::{APClass, APType, GenericAP, IDR},
+            memory_ap::{MemoryAP, CSW},
         },
-        ap_access::{valid_access_ports, APAccess},
+        ap_access::{valid_access_ports, APAccess, AccessPort},
+        debug_port::{DebugPortId, MinDpSupport, DPIDR},
     },
-    memory::romtable::CSComponent,
+    memory::romtable::{rom_table_address, CSComponent},
+    probe::{debug_probe::MasterProbe, flash::protection::ReadoutProtection},
 };
+use probe_rs_targets::{select_target, SelectionStrategy};
 
+/// Prints everything known about the probe and the target without halting it: the debug port,
+/// the access ports with their ROM tables, the core and whether it may be debugged.
 pub(crate) fn show_info_of_device(shared_options: &SharedOptions) -> Result<(), CliError> {
-    let mut probe = open_probe(shared_options.n)?;
+    let config = configuration(shared_options)?;
+    let mut probe = open_probe(&config.probe)?;
 
-    /*
-        The following code only works with debug port v2,
-        which might not necessarily be present.
-
-        Once the typed interface for the debug port is done, it
-        can be enabled again.
-
-    println!("Device information:");
-
-
-    let target_info = link
-        .read_register(Port::DebugPort, 0x4)?;
+    // Not all probes are able to measure the voltage, which is no reason to stop.
+    match probe.get_target_voltage() {
+        Ok(Some(voltage)) => println!("Target voltage: {:.2} V", voltage),
+        Ok(None) | Err(_) => println!("Target voltage: unknown"),
+    }
 
-    let target_info = parse_target_id(target_info);
-    println!("\nTarget Identification Register (TARGETID):");
+    // The DPIDR is read before the ROM table, as some STM32 require it.
+    let dpidr = probe.read_register_dp(0x0)?;
+    let dp_id = DebugPortId::from(DPIDR::from(dpidr));
     println!(
-        "\tRevision = {}, Part Number = {}, Designer = {}",
-        target_info.0, target_info.3, target_info.2
+        "Debug port: {:?} r{}, designed by {}{} (DPIDR {:#010x})",
+        dp_id.version,
+        dp_id.revision,
+        designer_name(dp_id.designer),
+        if dp_id.min_dp_support == MinDpSupport::Implemented {
+            ", minimal"
+        } else {
+            ""
+        },
+        dpidr
     );
 
-    */
+    println!("\nAccess ports:");
+    for access_port in valid_access_ports(&mut probe) {
+        show_access_port(&mut probe, access_port)?;
+    }
 
-    // Note: Temporary read to ensure the DP information is read at
-    //       least once before reading the ROM table
-    //       (necessary according to STM manual).
-    //
-    // TODO: Move to proper place somewhere in init code
-    //
+    println!();
+    let core_info = match CoreInfo::read(&mut probe) {
+        Ok(core_info) => {
+            println!("Core: {}", core_info);
+            Some(core_info)
+        }
+        Err(e) => {
+            println!("Core: unknown ({})", e);
+            None
+        }
+    };
+
+    // Only ARMv8-M cores tell whether debugging them is allowed.
+    match core_info {
+        Some(core_info) if core_info.core_type.is_armv8m() => {
+            match DebugAuthentication::read(&mut probe) {
+                Ok(authentication) => println!("Debug authentication: {}", authentication),
+                Err(e) => println!("Debug authentication: unknown ({})", e),
+            }
+        }
+        Some(core_info) => println!(
+            "Debug authentication: not implemented by the {}",
+            core_info.core_type
+        ),
+        None => {}
+    }
 
-    let target_info = probe.read_register_dp(0x0)?;
-    println!("DP info: {:#08x}", target_info);
+    // The readout protection is specific to the chip, so it is only known if a target is given.
+    if let Some(target_name) = &config.target {
+        let target = select_target(&SelectionStrategy::Name(target_name.clone()))?;
+        match ReadoutProtection::for_target(&target) {
+            Some(protection) => match protection.is_enabled(&mut probe) {
+                Ok(true) => println!("Readout protection: {} enabled", protection),
+                Ok(false) => println!("Readout protection: {} disabled", protection),
+                Err(e) => println!("Readout protection: unknown ({})", e),
+            },
+            None => println!("Readout protection: not supported for {}", target.name),
+        }
+    }
 
-    println!("\nAvailable Access Ports:");
+    Ok(())
+}
 
-    for access_port in valid_access_ports(&mut probe) {
-        let idr = probe.read_register_ap(access_port, IDR::default())?;
-        println!("{:#x?}", idr);
+fn show_access_port(probe: &mut MasterProbe, access_port: GenericAP) -> Result<(), CliError> {
+    let idr = probe.read_register_ap(access_port, IDR::default())?;
+    let class = match idr.CLASS {
+        APClass::MEMAP => "MEM-AP",
+        APClass::COMAP => "COM-AP",
+        APClass::Undefined => match idr.TYPE {
+            APType::JTAG_COM_AP => "JTAG-AP",
+            _ => "AP",
+        },
+    };
+    println!(
+        "  {} {}: {}, designed by {}, revision {}, variant {} (IDR {:#010x})",
+        class,
+        access_port.get_port_number(),
+        ap_type_name(idr.TYPE),
+        designer_name(idr.designer()),
+        idr.REVISION,
+        idr.VARIANT,
+        u32::from(idr),
+    );
 
-        if idr.CLASS == APClass::MEMAP {
-            let access_port: MemoryAP = access_port.into();
+    if idr.CLASS != APClass::MEMAP {
+        return Ok(());
+    }
+    let access_port: MemoryAP = access_port.into();
 
-            let base_register = probe.read_register_ap(access_port, BASE::default())?;
+    let csw = probe.read_register_ap(access_port, CSW::default())?;
+    println!(
+        "    Debug access {}, secure access {}",
+        enabled(csw.DeviceEn),
+        enabled(csw.SPIDEN)
+    );
 
-            let mut baseaddr = if BaseaddrFormat::ADIv5 == base_register.Format {
-                let base2 = probe.read_register_ap(access_port, BASE2::default())?;
-                (u64::from(base2.BASEADDR) << 32)
-            } else {
-                0
-            };
-            baseaddr |= u64::from(base_register.BASEADDR << 12);
+    let baseaddr = match rom_table_address(probe, access_port)? {
+        Some(baseaddr) => baseaddr,
+        None => {
+            println!("    No ROM table");
+            return Ok(());
+        }
+    };
 
-            let link_ref = &mut probe;
+    match CSComponent::try_parse(&probe.into(), baseaddr) {
+        Ok(component) => show_component(&component, 2),
+        Err(e) => println!("    ROM table at {:#010x} is unreadable: {}", baseaddr, e),
+    }
 
-            let component_table = CSComponent::try_parse(&link_ref.into(), baseaddr as u64);
+    Ok(())
+}
 
-            component_table
-                .iter()
-                .for_each(|entry| println!("{:#08x?}", entry));
+/// Prints a component and, if it is a ROM table, the components it lists below it.
+fn show_component(component: &CSComponent, depth: usize) {
+    let id = match component.id() {
+        Some(id) => id,
+        None => return,
+    };
+    let kind = match component {
+        CSComponent::GenericVerificationComponent(_) => "Verification component",
+        CSComponent::Class1RomTable(..) => "ROM table",
+        CSComponent::Class9RomTable(_) => "CoreSight component",
+        CSComponent::PeripheralTestBlock(_) => "Peripheral test block",
+        CSComponent::GenericIPComponent(_) => "Generic IP component",
+        CSComponent::CoreLinkOrPrimeCellOrSystemComponent(_) => "CoreLink or PrimeCell component",
+        CSComponent::None => return,
+    };
+    let peripheral_id = &id.peripheral_id;
+    let part = match (peripheral_id.arm_part_name(), peripheral_id.JEP106) {
+        (Some(name), _) => name.to_owned(),
+        (None, Some(designer)) => format!(
+            "part {:#05x} by {}",
+            peripheral_id.PART,
+            designer_name(designer)
+        ),
+        (None, None) => format!("part {:#05x}", peripheral_id.PART),
+    };
 
-            // let mut reader = crate::memory::romtable::RomTableReader::new(&link_ref, baseaddr as u64);
+    println!(
+        "{:indent$}{:#010x} {}: {}",
+        "",
+        id.base_address(),
+        kind,
+        part,
+        indent = depth * 2
+    );
 
-            // for e in reader.entries() {
-            //     if let Ok(e) = e {
-            //         println!("ROM Table Entry: Component @ 0x{:08x}", e.component_addr());
-            //     }
-            // }
+    if let CSComponent::Class1RomTable(_, table) = component {
+        for component in table.components() {
+            show_component(component, depth + 1);
         }
     }
+}
 
-    Ok(())
+fn ap_type_name(ap_type: APType) -> &'static str {
+    match ap_type {
+        APType::JTAG_COM_AP => "JTAG or COM",
+        APType::AMBA_AHB3 => "AMBA AHB3",
+        APType::AMBA_APB2_APB3 => "AMBA APB2 or APB3",
+        APType::AMBA_AXI3_AXI4 => "AMBA AXI3 or AXI4",
+        APType::AMBA_AHB5 => "AMBA AHB5",
+        APType::AMBA_AHB4 => "AMBA AHB4",
+    }
+}
+
+fn designer_name(code: JEP106Code) -> String {
+    match code.get() {
+        Some(name) => name.to_owned(),
+        None => format!("unknown designer (cc={:#x}, id={:#x})", code.cc, code.id),
+    }
+}
+
+fn enabled(bit: u8) -> &'static str {
+    if bit != 0 {
+        "enabled"
+    } else {
+        "disabled"
+    }
 }
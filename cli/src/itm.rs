@@ -0,0 +1,174 @@
+use crate::common::{interrupted, with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::{
+    itm::{
+        analysis::{exception_name, ExceptionAnalysis},
+        Decoder,
+    },
+    swo::{self, ProbeSwoReader, SerialSwoReader, SwoConfig, SwoReader},
+};
+
+use std::fs;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Time to wait before polling again when no SWO data arrived.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Options of the `itm` command.
+pub(crate) struct ItmOptions {
+    /// A file with a captured stream. Without it, SWO is captured from the target.
+    pub input: Option<PathBuf>,
+    pub analyze: bool,
+    /// Frequency of the timestamp clock in Hz.
+    pub timestamp_frequency: Option<u32>,
+    /// Capture through this serial port instead of the probe.
+    pub swo_serial: Option<PathBuf>,
+    pub swo_baud: u32,
+    /// Frequency of the trace clock of the target in Hz.
+    pub trace_clock: Option<u32>,
+}
+
+/// Decodes an ITM stream and prints its packets, or the timing of the exceptions
+/// with `analyze`.
+///
+/// The stream is read from a file, or captured through SWO until Ctrl-C is pressed.
+/// Times are printed in microseconds if the frequency of the timestamp clock is given,
+/// in timestamp cycles otherwise.
+pub(crate) fn itm(shared_options: &SharedOptions, options: ItmOptions) -> Result<(), CliError> {
+    let mut stream = Stream {
+        decoder: Decoder::new(),
+        analysis: if options.analyze {
+            Some(ExceptionAnalysis::new())
+        } else {
+            None
+        },
+    };
+
+    match &options.input {
+        Some(input) => stream.received(&fs::read(input)?),
+        None => capture(shared_options, &options, &mut stream)?,
+    }
+
+    if let Some(analysis) = &mut stream.analysis {
+        analysis.finish();
+        print_analysis(analysis, options.timestamp_frequency);
+    }
+
+    Ok(())
+}
+
+/// Configures the target to output the ITM stream through SWO and captures it,
+/// with the probe or through a serial port.
+fn capture(
+    shared_options: &SharedOptions,
+    options: &ItmOptions,
+    stream: &mut Stream,
+) -> Result<(), CliError> {
+    let trace_clock = options.trace_clock.ok_or(CliError::MissingArgument)?;
+
+    with_device(shared_options, |mut session| {
+        swo::configure_target(
+            &mut session.probe,
+            &SwoConfig {
+                trace_clock,
+                baud_rate: options.swo_baud,
+                exception_trace: options.analyze,
+            },
+        )?;
+
+        let mut reader: Box<dyn SwoReader + '_> = match &options.swo_serial {
+            Some(path) => Box::new(SerialSwoReader::open(path, options.swo_baud)?),
+            None => Box::new(ProbeSwoReader::start(&mut session.probe, options.swo_baud)?),
+        };
+
+        eprintln!(
+            "Capturing SWO at {} baud, press Ctrl-C to stop.",
+            options.swo_baud
+        );
+
+        while !interrupted() {
+            let data = reader.read()?;
+
+            if data.is_empty() {
+                sleep(POLL_INTERVAL);
+            } else {
+                stream.received(&data);
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Decodes the stream and either prints its packets or feeds them to the analysis.
+struct Stream {
+    decoder: Decoder,
+    analysis: Option<ExceptionAnalysis>,
+}
+
+impl Stream {
+    fn received(&mut self, data: &[u8]) {
+        self.decoder.received(data);
+
+        while let Some(packet) = self.decoder.decode() {
+            match (packet, &mut self.analysis) {
+                (Ok(packet), Some(analysis)) => analysis.process(&packet),
+                (Ok(packet), None) => println!("{}", packet),
+                (Err(e), _) => eprintln!("{}", e),
+            }
+        }
+    }
+}
+
+fn print_analysis(analysis: &ExceptionAnalysis, timestamp_frequency: Option<u32>) {
+    let time = |cycles: Option<u64>| match (cycles, timestamp_frequency) {
+        (Some(cycles), Some(frequency)) => {
+            format!("{:.1}", cycles as f64 * 1e6 / f64::from(frequency))
+        }
+        (Some(cycles), None) => cycles.to_string(),
+        (None, _) => "-".to_string(),
+    };
+
+    let unit = if timestamp_frequency.is_some() {
+        "µs"
+    } else {
+        "cycles"
+    };
+
+    println!("Capture window: {} {}", time(Some(analysis.window())), unit);
+
+    if analysis.overflows() > 0 {
+        println!(
+            "The ITM overflowed {} times, the results are missing events.",
+            analysis.overflows()
+        );
+    }
+
+    println!(
+        "{:<16} {:>8} {:>10} {:>10} {:>10} {:>12} {:>12} {:>7}",
+        "Exception", "Count", "Min", "Avg", "Max", "Min period", "Max period", "Load"
+    );
+
+    for (number, statistics) in analysis.statistics() {
+        let (min, max) = if statistics.exits > 0 {
+            (Some(statistics.min_duration), Some(statistics.max_duration))
+        } else {
+            (None, None)
+        };
+
+        println!(
+            "{:<16} {:>8} {:>10} {:>10} {:>10} {:>12} {:>12} {:>6.2}%",
+            exception_name(*number),
+            statistics.count,
+            time(min),
+            time(statistics.average_duration()),
+            time(max),
+            time(statistics.min_interval),
+            time(statistics.max_interval),
+            analysis.load(*number) * 100.0
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Logging to the console and, with `--log-file`, into a file for bug reports.
+//!
+//! The console shows the events enabled by `RUST_LOG`, only errors if it is not set. The log
+//! file gets all events down to the trace level, regardless of `RUST_LOG`, along with the spans
+//! of the subsystems they happened in: `probe` for opening and attaching to probes, `dap` for
+//! the transfers with the debug port, `flash` and `debug`.
+//!
+//! probe-rs logs through the `log` crate, which is forwarded to `tracing` here.
+
+use crate::common::CliError;
+
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, EnvFilter};
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Sets up the logging, writing the log file at `log_file` if given.
+pub(crate) fn init(log_file: Option<&Path>) -> Result<(), CliError> {
+    let console = fmt::layer()
+        .with_writer(io::stderr)
+        .with_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error")));
+
+    let file = match log_file {
+        Some(path) => Some(
+            fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(File::create(path)?))
+                .with_filter(LevelFilter::TRACE),
+        ),
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .init();
+
+    Ok(())
+}
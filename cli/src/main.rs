@@ -1,36 +1,97 @@
+mod chip;
 mod common;
+mod console;
+mod daemon;
 mod debugger;
+mod diagnostics;
+mod dry_run;
+mod dump;
 mod info;
+mod itm;
+mod logging;
+mod monitor;
+mod profile;
+mod provision;
+mod read_flash;
+mod rtt;
+mod saved_session;
+mod targets;
+mod test_algorithm;
+mod trace;
+mod uicr;
+mod verify;
 
-use common::{with_device, with_dump, CliError};
+use common::{
+    configuration, find_statics, install_interrupt_handler, interrupted, open_session, with_device,
+    with_dump, CliError, InterruptMode,
+};
 use debugger::CliState;
+use saved_session::SavedSession;
 
 use probe_rs::{
     debug::DebugInfo,
-    memory::MI,
+    defmt::Level,
+    memory::{watcher::WatchEntry, MI},
     probe::{
-        daplink,
         debug_probe::DebugProbeInfo,
-        flash::download::{FileDownloader, Format},
-        stlink,
+        flash::{
+            checksum::{checksum, ChecksumAlgorithm},
+            download::{
+                flash_many, read_allocations, read_flash_image, run_from_ram, FileDownloader,
+                FlashStage, Format,
+            },
+            journal::{self, Journal},
+            loader::AddressTranslation,
+            memory::MemoryRegion,
+            patch::{self, PatchBase},
+            protection::{ProtectionError, ReadoutProtection},
+            usage::MemoryUsage,
+        },
+        protocol::{ResetType, WireProtocol},
+        watch::{self, ProbeEvent},
     },
+    rtos,
+    rtt::ChannelMode,
+    session::Session,
 };
 
-use capstone::{arch::arm::ArchMode, prelude::*, Capstone, Endian};
+use capstone::{
+    arch::arm::{ArchExtraMode, ArchMode},
+    prelude::*,
+    Capstone, Endian,
+};
 use colored::*;
 use memmap;
 use rustyline::Editor;
+use serde::Serialize;
 use structopt::StructOpt;
 
 use std::fs;
 use std::num::ParseIntError;
-use std::path::PathBuf;
-use std::time::Instant;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
     u32::from_str_radix(src, 16)
 }
 
+/// Parses an address range in hex, like `0x08000000..0x08040000`.
+fn parse_range(src: &str) -> Result<Range<u32>, String> {
+    let parse = |address: &str| parse_hex(address.trim_start_matches("0x"));
+
+    match src.find("..") {
+        Some(separator) => match (parse(&src[..separator]), parse(&src[separator + 2..])) {
+            (Ok(start), Ok(end)) if start < end => Ok(start..end),
+            _ => Err(format!("Invalid range '{}'.", src)),
+        },
+        None => Err(format!(
+            "Expected a range like 0x08000000..0x08040000, not '{}'.",
+            src
+        )),
+    }
+}
+
 #[derive(StructOpt)]
 #[structopt(
     name = "Probe-rs CLI",
@@ -40,21 +101,68 @@ fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
 enum CLI {
     /// List all connected debug probes
     #[structopt(name = "list")]
-    List {},
+    List {
+        /// Keep running and report probes being connected or disconnected
+        #[structopt(long = "watch", raw(conflicts_with = "\"json\""))]
+        watch: bool,
+        /// Print the probes as a JSON array, for use in scripts
+        #[structopt(long = "json")]
+        json: bool,
+    },
     /// Gets infos about the selected debug probe and connected target
     #[structopt(name = "info")]
     Info {
         #[structopt(flatten)]
         shared: SharedOptions,
     },
+    /// Prints whether the core is running, halted, sleeping or locked up, without halting it
+    #[structopt(name = "status")]
+    Status {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+    },
     /// Resets the target attached to the selected debug probe
     #[structopt(name = "reset")]
     Reset {
         #[structopt(flatten)]
         shared: SharedOptions,
 
-        /// Whether the reset pin should be asserted or deasserted. If left open, just pulse it
-        assert: Option<bool>,
+        /// Hold the target in reset by asserting nRESET, instead of pulsing it
+        #[structopt(long, raw(conflicts_with = "\"deassert\""))]
+        assert: bool,
+
+        /// Release the target from reset by deasserting nRESET
+        #[structopt(long)]
+        deassert: bool,
+    },
+    /// Compute the checksum of a memory range, on the target if its flash algorithm supports it
+    #[structopt(name = "checksum")]
+    Checksum {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The range (in hex), e.g. 0x08000000..0x08040000
+        #[structopt(long, parse(try_from_str = "parse_range"))]
+        range: Range<u32>,
+
+        /// The checksum algorithm, crc32 or sum32
+        #[structopt(long, default_value = "crc32")]
+        algo: ChecksumAlgorithm,
+
+        /// Read the range back and compute the checksum on the host
+        #[structopt(long)]
+        host: bool,
+    },
+    /// Enable the readout protection of the target, which blocks the debugger from reading the
+    /// flash until the target is unlocked by a mass erase
+    #[structopt(name = "protect")]
+    Protect {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// Reset the target afterwards, so the protection takes effect right away
+        #[structopt(long)]
+        reset: bool,
     },
     #[structopt(name = "debug")]
     Debug {
@@ -68,6 +176,25 @@ enum CLI {
         #[structopt(long, parse(from_os_str))]
         /// Binary to debug
         exe: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str))]
+        /// File containing debugger commands to execute, one per line.
+        /// The interactive prompt is skipped if this or `--ex` is given.
+        script: Option<PathBuf>,
+
+        #[structopt(long = "ex")]
+        /// A debugger command to execute. Can be given multiple times,
+        /// the commands are executed after the ones from `--script`.
+        commands: Vec<String>,
+
+        #[structopt(long = "no-resume")]
+        /// Leave the core halted when the debugger exits
+        no_resume: bool,
+
+        #[structopt(long = "no-cache")]
+        /// Read the memory again for every command while the core is halted,
+        /// e.g. if it is changed by DMA.
+        no_cache: bool,
     },
     /// Dump memory from attached target
     #[structopt(name = "dump")]
@@ -76,10 +203,42 @@ enum CLI {
         shared: SharedOptions,
 
         /// The address of the memory to dump from the target (in hexadecimal without 0x prefix)
-        #[structopt(parse(try_from_str = "parse_hex"))]
-        loc: u32,
+        #[structopt(parse(try_from_str = "parse_hex"), raw(required_unless = "\"symbol\""))]
+        loc: Option<u32>,
         /// The amount of memory (in words) to dump
-        words: u32,
+        #[structopt(raw(required_unless = "\"symbol\""))]
+        words: Option<u32>,
+
+        /// Dump the variable with this name instead, decoded according to its type.
+        /// Requires `--exe`
+        #[structopt(long, raw(requires = "\"exe\""))]
+        symbol: Option<String>,
+
+        /// Binary running on the target, used to look up `--symbol`
+        #[structopt(long, parse(from_os_str))]
+        exe: Option<PathBuf>,
+
+        /// Skip words which fault, e.g. holes between peripherals, instead of aborting.
+        /// They are listed at the end, and in the metadata of `--output`
+        #[structopt(long = "skip-faults")]
+        skip_faults: bool,
+
+        /// How often a chunk of memory which faulted is read again
+        #[structopt(long, default_value = "2")]
+        retries: u32,
+
+        /// Write the memory into this file instead of printing it, with the dumped range and
+        /// the skipped faults in the file with `.json` appended
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Continue an interrupted dump into `--output`
+        #[structopt(long, raw(requires = "\"output\""))]
+        resume: bool,
+
+        /// Read peripheral regions marked as not readable in the memory map of the target
+        #[structopt(long)]
+        force: bool,
     },
     /// Download memory to attached target
     #[structopt(name = "download")]
@@ -89,15 +248,407 @@ enum CLI {
 
         /// The path to the file to be downloaded to the flash
         path: String,
+
+        /// The format of the file: 'elf', 'hex', 'srec', 'uf2' or 'bin'. Detected from its
+        /// contents or extension if left open. Binary files are placed at 0 and moved with --offset
+        #[structopt(long)]
+        format: Option<Format>,
+
+        /// Program the targets of all connected probes at once, e.g. for small production runs
+        #[structopt(long = "all-probes")]
+        all_probes: bool,
+
+        /// Only program the sectors which differ from the flash contents, which are read back
+        #[structopt(long)]
+        patch: bool,
+
+        /// Only program the sectors which differ from this previously programmed file,
+        /// instead of reading back the flash
+        #[structopt(long, parse(from_os_str))]
+        previous: Option<PathBuf>,
+
+        /// Load an ELF file linked to RAM and run it, without using the flash
+        #[structopt(long = "to-ram")]
+        to_ram: bool,
+
+        /// Move the image by this offset in hex, e.g. behind a bootloader at 08004000.
+        /// The flash before it is protected unless --bootloader is given
+        #[structopt(long, parse(try_from_str = "parse_hex"))]
+        offset: Option<u32>,
+
+        /// The region of the bootloader, which the image must not overlap,
+        /// like 0x08000000..0x08004000
+        #[structopt(long, parse(try_from_str = "parse_range"))]
+        bootloader: Option<Range<u32>>,
+
+        /// Halt the core and stop or service the known watchdogs of the target before flashing,
+        /// so they do not reset it in the middle of programming
+        #[structopt(long = "watchdog-safe")]
+        watchdog_safe: bool,
+
+        /// Record the programmed sectors in a journal next to the file, and skip those recorded
+        /// by an interrupted download of the same file
+        #[structopt(long)]
+        resume: bool,
+
+        /// Reset the target afterwards, so the new firmware runs. This is the default
+        #[structopt(long = "reset-run")]
+        reset_run: bool,
+
+        /// Leave the target as the flash algorithm left it, without resetting it
+        #[structopt(
+            long = "no-reset",
+            raw(conflicts_with_all = "&[\"reset_run\", \"halt_after\"]")
+        )]
+        no_reset: bool,
+
+        /// Reset the target afterwards and halt the core at the reset vector, e.g. to debug it
+        #[structopt(long = "halt-after", raw(conflicts_with = "\"reset_run\""))]
+        halt_after: bool,
+
+        /// Print the sectors which would be erased and the pages which would be programmed,
+        /// without connecting to the target. Needs --target
+        #[structopt(
+            long = "dry-run",
+            raw(conflicts_with_all = "&[\"to_ram\", \"all_probes\"]")
+        )]
+        dry_run: bool,
+
+        /// Print the plan of --dry-run as JSON
+        #[structopt(long, raw(requires = "\"dry_run\""))]
+        json: bool,
+    },
+    /// Read back the memory a file places data in and compare it with the file, e.g. to check
+    /// whether a device already runs a release
+    #[structopt(name = "verify", raw(alias = "\"compare\""))]
+    Verify {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The file to compare the target memory with
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// The format of the file: 'elf', 'hex', 'srec', 'uf2' or 'bin'. Detected from its
+        /// contents or extension if left open
+        #[structopt(long)]
+        format: Option<Format>,
+
+        /// The number of mismatching bytes to list
+        #[structopt(long = "max-mismatches", default_value = "10")]
+        max_mismatches: usize,
     },
+    /// Read all flash of the target into a binary file, e.g. to back up a device before an
+    /// update. Flash regions which are not adjacent are written to one file each
+    #[structopt(name = "read-flash")]
+    ReadFlash {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The file to write the flash contents to
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Periodically read u32 values from the target
     #[structopt(name = "trace")]
     Trace {
         #[structopt(flatten)]
         shared: SharedOptions,
 
-        /// The address of the memory to dump from the target (in hexadecimal without 0x prefix)
+        /// The addresses of the values to read (in hexadecimal without 0x prefix)
+        #[structopt(parse(try_from_str = "parse_hex"), raw(required_unless = "\"symbol\""))]
+        locs: Vec<u32>,
+
+        /// Read the variable with this name, can be given multiple times. Requires `--exe`
+        #[structopt(long, raw(requires = "\"exe\""))]
+        symbol: Vec<String>,
+
+        /// Binary running on the target, used to look up `--symbol`
+        #[structopt(long, parse(from_os_str))]
+        exe: Option<PathBuf>,
+
+        /// Time between two reads, in milliseconds
+        #[structopt(long, default_value = "50")]
+        interval: u64,
+
+        /// Output encoding: 'binary' (for update_plot.py), 'csv' or 'json' (one object per line)
+        #[structopt(long, default_value = "binary")]
+        format: trace::TraceFormat,
+
+        /// Write the values to this file instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Leave the core halted when tracing is stopped
+        #[structopt(long = "no-resume")]
+        no_resume: bool,
+    },
+    /// Print the contents of memory regions whenever they change
+    #[structopt(name = "monitor")]
+    Monitor {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The regions to watch, as `address[:size]` (in hexadecimal without 0x prefix).
+        /// The size defaults to 4 bytes
+        #[structopt(
+            parse(try_from_str = "monitor::parse_watch_entry"),
+            raw(required = "true")
+        )]
+        entries: Vec<WatchEntry>,
+
+        /// Number of reads per second
+        #[structopt(long, default_value = "10")]
+        frequency: u32,
+    },
+    /// Stream the data the target sends over an RTT up channel and forward stdin to a down channel
+    #[structopt(name = "rtt")]
+    Rtt {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// Binary running on the target, used to find the RTT control block and to decode defmt messages.
+        /// Without it, the RAM of the target is searched for the control block
+        #[structopt(long, parse(from_os_str))]
+        exe: Option<PathBuf>,
+
+        /// The up channel to read, 0 by default
+        #[structopt(long)]
+        channel: Option<usize>,
+
+        /// The down channel stdin is forwarded to, 0 by default
+        #[structopt(long)]
+        down_channel: Option<usize>,
+
+        /// Decode the data as defmt log messages. Requires `--exe`
+        #[structopt(long, raw(requires = "\"exe\""))]
+        defmt: bool,
+
+        /// Only show defmt messages of this level or higher: 'trace', 'debug', 'info', 'warn' or 'error'
+        #[structopt(long, default_value = "trace")]
+        level: Level,
+
+        /// Split the data into SEGGER's virtual terminals and prefix each line with its terminal
+        #[structopt(long, raw(conflicts_with = "\"defmt\""))]
+        virtual_terminals: bool,
+
+        /// Set what the target does when an up channel is full: 'skip', 'trim' or 'block'.
+        /// Prefix the mode with a channel number to set it for another channel than the read one, e.g. `1=block`
+        #[structopt(long, parse(try_from_str = "rtt::parse_channel_mode"))]
+        mode: Vec<(Option<usize>, ChannelMode)>,
+    },
+    /// Print the output a target without RTT writes into a ring buffer in RAM
+    #[structopt(name = "console")]
+    Console {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// Binary running on the target, used to look up the symbols of the ring buffer
+        #[structopt(long, parse(from_os_str))]
+        exe: PathBuf,
+
+        /// The array holding the output. Defaults to `buffer` in the `[console]` section of the configuration
+        #[structopt(long)]
+        buffer: Option<String>,
+
+        /// The index the target writes the next byte to
+        #[structopt(long = "write-index")]
+        write_index: Option<String>,
+
+        /// The index the host reads the next byte from, if the target waits for the host to
+        /// read its output
+        #[structopt(long = "read-index")]
+        read_index: Option<String>,
+    },
+    /// Keep the session with the target open and serve the requests of other tools over a unix socket
+    #[structopt(name = "daemon")]
+    Daemon {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The socket to listen on. Defaults to `probe-rs.sock` in the runtime directory of the user
+        #[structopt(long, parse(from_os_str))]
+        socket: Option<PathBuf>,
+    },
+    /// Send a request, like '{"command": "halt"}', to a running daemon and print its response
+    #[structopt(name = "request")]
+    Request {
+        /// The socket the daemon listens on. Defaults to `probe-rs.sock` in the runtime directory of the user
+        #[structopt(long, parse(from_os_str))]
+        socket: Option<PathBuf>,
+
+        /// The request as JSON
+        request: String,
+    },
+    /// Decode an ITM stream, captured through SWO or from a file, or analyze the timing of the exceptions in it
+    #[structopt(name = "itm")]
+    Itm {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// File with a raw ITM stream. Without it, SWO is captured from the target until Ctrl-C is pressed
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Report entry and exit timing and CPU load of each exception instead of printing packets.
+        /// Enables exception trace on the target when capturing
+        #[structopt(long)]
+        analyze: bool,
+
+        /// Frequency of the timestamp clock in Hz, used to print times in microseconds
+        #[structopt(long)]
+        timestamp_frequency: Option<u32>,
+
+        /// Capture SWO through this serial port instead of the probe, for probes without SWO support
+        #[structopt(long, parse(from_os_str), raw(conflicts_with = "\"input\""))]
+        swo_serial: Option<PathBuf>,
+
+        /// Baud rate of the SWO output
+        #[structopt(long, default_value = "2000000")]
+        swo_baud: u32,
+
+        /// Frequency of the trace clock of the target in Hz, usually the core clock. Required for capturing
+        #[structopt(long, raw(required_unless = "\"input\""))]
+        trace_clock: Option<u32>,
+    },
+    /// Test the flash algorithm of the target by erasing, programming and verifying a scratch sector
+    #[structopt(name = "test-algorithm")]
+    TestAlgorithm {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// An address (in hex) in the sector to test. Defaults to the last sector of the boot flash.
+        /// The contents of the sector are lost
+        #[structopt(long, parse(try_from_str = "parse_hex"))]
+        address: Option<u32>,
+    },
+    /// Sample the program counter to find out where the target spends its time
+    #[structopt(name = "profile")]
+    Profile {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        #[structopt(long, parse(from_os_str))]
+        /// Binary running on the target, used to resolve function names
+        exe: Option<PathBuf>,
+
+        #[structopt(long, default_value = "5")]
+        /// How long to sample, in seconds
+        duration: u64,
+
+        #[structopt(long, parse(from_os_str))]
+        /// Write the samples as folded stacks for flamegraph tools to this file,
+        /// instead of printing the hottest functions
+        folded: Option<PathBuf>,
+
+        #[structopt(long = "halt-sampling")]
+        /// Halt the core for every sample instead of using DWT_PCSR.
+        /// Slower, but records the whole call stack.
+        halt_sampling: bool,
+    },
+    /// Program a production unit as described by a manifest and report the result as JSON
+    #[structopt(name = "provision")]
+    Provision {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The manifest listing the images and the commands to run before and after them
+        #[structopt(parse(from_os_str))]
+        manifest: PathBuf,
+
+        /// The serial number of the unit. Defaults to the serial counter of the manifest
+        #[structopt(long = "unit-serial")]
+        unit_serial: Option<u32>,
+
+        /// Append the result to this file, one JSON object per line
+        #[structopt(long, parse(from_os_str))]
+        results: Option<PathBuf>,
+    },
+    /// List the supported chips and show their details
+    #[structopt(name = "chip")]
+    Chip {
+        #[structopt(subcommand)]
+        command: ChipCommand,
+    },
+    /// Work with target description files
+    #[structopt(name = "targets")]
+    Targets {
+        #[structopt(subcommand)]
+        command: TargetsCommand,
+    },
+    /// Read and write the configuration region holding device unique data,
+    /// the UICR of nRF5 or the OTP area of STM32F4 chips
+    #[structopt(name = "uicr")]
+    Uicr {
+        #[structopt(subcommand)]
+        command: UicrCommand,
+    },
+}
+
+#[derive(StructOpt)]
+enum ChipCommand {
+    /// List the built-in and registered chips
+    #[structopt(name = "list")]
+    List {
+        /// Only list the chips matching this filter, e.g. `nrf`
+        filter: Option<String>,
+    },
+    /// Show the memory map, core and flash algorithm of a chip
+    #[structopt(name = "info")]
+    Info {
+        /// The name of the chip
+        name: String,
+    },
+}
+
+#[derive(StructOpt)]
+enum TargetsCommand {
+    /// Check a target description file for unknown fields, overlapping memory regions,
+    /// missing flash algorithms and invalid addresses
+    #[structopt(name = "validate")]
+    Validate {
+        /// The target description file
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+enum UicrCommand {
+    /// Print the configuration region, or a part of it
+    #[structopt(name = "read")]
+    Read {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The address (in hex) to start at. Defaults to the start of the region
+        #[structopt(long, parse(try_from_str = "parse_hex"))]
+        address: Option<u32>,
+
+        /// The number of bytes to read. Defaults to the rest of the region
+        #[structopt(long)]
+        length: Option<u32>,
+    },
+    /// Program words (in hex) into the configuration region. The UICR is erased first if
+    /// the words set bits which are cleared, keeping its other contents
+    #[structopt(name = "write")]
+    Write {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The address (in hex) of the first word
         #[structopt(parse(try_from_str = "parse_hex"))]
-        loc: u32,
+        address: u32,
+
+        /// The words to program
+        #[structopt(parse(try_from_str = "parse_hex"), raw(required = "true"))]
+        words: Vec<u32>,
+    },
+    /// Erase the configuration region, which only the UICR supports
+    #[structopt(name = "erase")]
+    Erase {
+        #[structopt(flatten)]
+        shared: SharedOptions,
     },
 }
 
@@ -111,43 +662,432 @@ struct SharedOptions {
     /// The target to be selected.
     #[structopt(short, long)]
     target: Option<String>,
+
+    /// The TARGETSEL value (in hex) of the target on a multi-drop SWD bus.
+    #[structopt(long = "target-sel", parse(try_from_str = "parse_hex"))]
+    target_sel: Option<u32>,
+
+    /// The serial number of the debug probe to use
+    #[structopt(long = "probe-serial")]
+    serial: Option<String>,
+
+    /// The protocol used to connect to the target: 'swd' or 'jtag'
+    #[structopt(long)]
+    protocol: Option<WireProtocol>,
+
+    /// The clock of the protocol in kHz
+    #[structopt(long)]
+    speed: Option<u32>,
+
+    /// How the target is reset: 'hardware', 'sysresetreq', 'vectreset' or 'core'
+    #[structopt(long = "reset-type")]
+    reset_type: Option<ResetType>,
+
+    /// Record the traffic with the debug port into a file, for replaying it in tests
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// Write the full trace of the command into a file, for attaching it to bug reports
+    #[structopt(long = "log-file", parse(from_os_str))]
+    log_file: Option<PathBuf>,
+
+    /// Reset the target if its core locked up, halting it at the reset vector
+    #[structopt(long = "recover-lockup")]
+    recover_lockup: bool,
 }
 
-fn main() {
-    // Initialize the logging backend.
-    pretty_env_logger::init();
+impl CLI {
+    /// Returns how the command reacts to Ctrl-C. Commands which run until interrupted poll
+    /// for it, all others are aborted.
+    fn interrupt_mode(&self) -> InterruptMode {
+        match self {
+            CLI::List { watch: true, .. }
+            | CLI::Debug { .. }
+            | CLI::Dump { .. }
+            | CLI::Trace { .. }
+            | CLI::Monitor { .. }
+            | CLI::Rtt { .. }
+            | CLI::Console { .. }
+            | CLI::Daemon { .. }
+            | CLI::Itm { input: None, .. }
+            | CLI::Profile { .. } => InterruptMode::Poll,
+            _ => InterruptMode::Abort,
+        }
+    }
+
+    /// Returns the shared options of the command, if it uses a probe.
+    fn shared(&self) -> Option<&SharedOptions> {
+        match self {
+            CLI::Info { shared }
+            | CLI::Status { shared }
+            | CLI::Reset { shared, .. }
+            | CLI::Checksum { shared, .. }
+            | CLI::Protect { shared, .. }
+            | CLI::Debug { shared, .. }
+            | CLI::Dump { shared, .. }
+            | CLI::Download { shared, .. }
+            | CLI::Verify { shared, .. }
+            | CLI::ReadFlash { shared, .. }
+            | CLI::Trace { shared, .. }
+            | CLI::Monitor { shared, .. }
+            | CLI::Rtt { shared, .. }
+            | CLI::Console { shared, .. }
+            | CLI::Daemon { shared, .. }
+            | CLI::Itm { shared, .. }
+            | CLI::TestAlgorithm { shared, .. }
+            | CLI::Profile { shared, .. }
+            | CLI::Provision { shared, .. } => Some(shared),
+            CLI::Uicr { command } => match command {
+                UicrCommand::Read { shared, .. }
+                | UicrCommand::Write { shared, .. }
+                | UicrCommand::Erase { shared } => Some(shared),
+            },
+            CLI::List { .. } | CLI::Request { .. } | CLI::Chip { .. } | CLI::Targets { .. } => None,
+        }
+    }
+}
 
+fn main() {
     let matches = CLI::from_args();
 
+    install_interrupt_handler(matches.interrupt_mode());
+
+    let log_file = matches.shared().and_then(|shared| shared.log_file.clone());
+    if let Err(e) = logging::init(log_file.as_ref().map(PathBuf::as_path)) {
+        eprintln!("Failed to create the log file: {}", e);
+        std::process::exit(1);
+    }
+
     let cli_result = match matches {
-        CLI::List {} => list_connected_devices(),
+        CLI::List { watch: true, .. } => watch_connected_devices(),
+        CLI::List { json, .. } => list_connected_devices(json),
         CLI::Info { shared } => crate::info::show_info_of_device(&shared),
-        CLI::Reset { shared, assert } => reset_target_of_device(&shared, assert),
-        CLI::Debug { shared, exe, dump } => debug(&shared, exe, dump),
-        CLI::Dump { shared, loc, words } => dump_memory(&shared, loc, words),
-        CLI::Download { shared, path } => download_program_fast(&shared, &path),
-        CLI::Trace { shared, loc } => trace_u32_on_target(&shared, loc),
+        CLI::Status { shared } => show_status_of_core(&shared),
+        CLI::Reset {
+            shared,
+            assert,
+            deassert,
+        } => {
+            let assert = if assert {
+                Some(true)
+            } else if deassert {
+                Some(false)
+            } else {
+                None
+            };
+            reset_target_of_device(&shared, assert)
+        }
+        CLI::Checksum {
+            shared,
+            range,
+            algo,
+            host,
+        } => checksum_of_range(&shared, range, algo, !host),
+        CLI::Protect { shared, reset } => protect_target(&shared, reset),
+        CLI::Debug {
+            shared,
+            exe,
+            dump,
+            script,
+            commands,
+            no_resume,
+            no_cache,
+        } => debug(&shared, exe, dump, script, commands, !no_resume, !no_cache),
+        CLI::Dump {
+            shared,
+            loc,
+            words,
+            symbol,
+            exe,
+            skip_faults,
+            retries,
+            output,
+            resume,
+            force,
+        } => match (symbol, exe) {
+            (Some(symbol), Some(exe)) => dump_symbol(&shared, &exe, symbol),
+            _ => dump::dump_memory(
+                &shared,
+                loc.unwrap_or(0),
+                words.unwrap_or(0),
+                dump::DumpOptions {
+                    skip_faults,
+                    retries,
+                    output,
+                    resume,
+                    force,
+                },
+            ),
+        },
+        CLI::Download {
+            shared,
+            path,
+            format,
+            all_probes,
+            patch,
+            previous,
+            to_ram,
+            offset,
+            bootloader,
+            watchdog_safe,
+            resume,
+            reset_run: _,
+            no_reset,
+            halt_after,
+            dry_run,
+            json,
+        } => {
+            let after = if no_reset {
+                AfterDownload::Keep
+            } else if halt_after {
+                AfterDownload::Halt
+            } else {
+                AfterDownload::Run
+            };
+
+            if dry_run {
+                dry_run::show_plan(&shared, Path::new(&path), format, offset, bootloader, json)
+            } else if to_ram {
+                download_to_ram(&shared, Path::new(&path))
+            } else if all_probes {
+                download_all_probes(&shared, &path, format, after)
+            } else if resume {
+                download_resumable(&shared, Path::new(&path), format, watchdog_safe, after)
+            } else if patch || previous.is_some() {
+                download_patch(
+                    &shared,
+                    Path::new(&path),
+                    previous.as_ref().map(PathBuf::as_path),
+                    format,
+                    watchdog_safe,
+                    after,
+                )
+            } else {
+                download_program_fast(
+                    &shared,
+                    &path,
+                    format,
+                    offset,
+                    bootloader,
+                    watchdog_safe,
+                    after,
+                )
+            }
+        }
+        CLI::Verify {
+            shared,
+            path,
+            format,
+            max_mismatches,
+        } => verify::verify(&shared, &path, format, max_mismatches),
+        CLI::ReadFlash { shared, output } => read_flash::read_flash(&shared, &output),
+        CLI::Trace {
+            shared,
+            mut locs,
+            symbol,
+            exe,
+            interval,
+            format,
+            output,
+            no_resume,
+        } => {
+            let variables = match exe {
+                Some(exe) => find_statics(&exe, &symbol),
+                None => Ok(vec![]),
+            };
+
+            variables.and_then(|variables| {
+                locs.extend(variables.iter().map(|variable| variable.address));
+
+                trace::trace_u32_on_target(
+                    &shared,
+                    &locs,
+                    Duration::from_millis(interval),
+                    format,
+                    output,
+                    !no_resume,
+                )
+            })
+        }
+        CLI::Monitor {
+            shared,
+            entries,
+            frequency,
+        } => monitor::monitor(&shared, entries, frequency),
+        CLI::Rtt {
+            shared,
+            exe,
+            channel,
+            down_channel,
+            defmt,
+            level,
+            virtual_terminals,
+            mode,
+        } => rtt::rtt(
+            &shared,
+            exe,
+            rtt::RttOptions {
+                channel,
+                down_channel,
+                defmt,
+                level,
+                virtual_terminals,
+                modes: mode,
+            },
+        ),
+        CLI::Console {
+            shared,
+            exe,
+            buffer,
+            write_index,
+            read_index,
+        } => console::console(
+            &shared,
+            &exe,
+            console::ConsoleSymbols {
+                buffer,
+                write_index,
+                read_index,
+            },
+        ),
+        CLI::Daemon { shared, socket } => {
+            daemon::serve(&shared, &socket.unwrap_or_else(daemon::default_socket))
+        }
+        CLI::Request { socket, request } => {
+            daemon::request(&socket.unwrap_or_else(daemon::default_socket), &request)
+        }
+        CLI::Itm {
+            shared,
+            input,
+            analyze,
+            timestamp_frequency,
+            swo_serial,
+            swo_baud,
+            trace_clock,
+        } => itm::itm(
+            &shared,
+            itm::ItmOptions {
+                input,
+                analyze,
+                timestamp_frequency,
+                swo_serial,
+                swo_baud,
+                trace_clock,
+            },
+        ),
+        CLI::TestAlgorithm { shared, address } => test_algorithm::run(&shared, address),
+        CLI::Profile {
+            shared,
+            exe,
+            duration,
+            folded,
+            halt_sampling,
+        } => crate::profile::profile(
+            &shared,
+            exe,
+            Duration::from_secs(duration),
+            folded,
+            halt_sampling,
+        ),
+        CLI::Provision {
+            shared,
+            manifest,
+            unit_serial,
+            results,
+        } => configuration(&shared).and_then(|config| {
+            provision::provision(
+                &config,
+                &manifest,
+                unit_serial,
+                results.as_ref().map(PathBuf::as_path),
+            )
+        }),
+        CLI::Chip { command } => match command {
+            ChipCommand::List { filter } => chip::list(filter),
+            ChipCommand::Info { name } => chip::info(&name),
+        },
+        CLI::Targets { command } => match command {
+            TargetsCommand::Validate { path } => targets::validate(&path),
+        },
+        CLI::Uicr { command } => match command {
+            UicrCommand::Read {
+                shared,
+                address,
+                length,
+            } => uicr::read(&shared, address, length),
+            UicrCommand::Write {
+                shared,
+                address,
+                words,
+            } => uicr::write(&shared, address, &words),
+            UicrCommand::Erase { shared } => uicr::erase(&shared),
+        },
     };
 
     if let Err(e) = cli_result {
-        if let CliError::TargetSelectionError(e) = e {
+        if let CliError::TargetSelectionError(e) = &e {
             eprintln!("    {} {}", "Error".red().bold(), e);
         } else {
             eprintln!("Error processing command: {}", e);
         }
+        if let Some(advice) = diagnostics::advice(&e) {
+            eprintln!("    {} {}", "Hint".cyan().bold(), advice);
+        }
+        if let Some(path) = &log_file {
+            eprintln!(
+                "The full log is in {}, please attach it when reporting a bug.",
+                path.display()
+            );
+        }
         std::process::exit(1);
     }
 }
 
-fn list_connected_devices() -> Result<(), CliError> {
+/// A connected probe, as printed by `list --json`.
+#[derive(Serialize)]
+struct ProbeListing {
+    index: usize,
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    probe_type: String,
+    vendor_id: u16,
+    product_id: u16,
+    serial_number: Option<String>,
+    firmware_version: Option<String>,
+    capabilities: Vec<&'static str>,
+}
+
+fn list_connected_devices(json: bool) -> Result<(), CliError> {
     let links = get_connected_devices();
 
-    if !links.is_empty() {
+    if json {
+        let listings: Vec<_> = links
+            .iter()
+            .enumerate()
+            .map(|(index, link)| ProbeListing {
+                index,
+                id: link.stable_id(),
+                name: link.identifier.clone(),
+                probe_type: link.probe_type.to_string(),
+                vendor_id: link.vendor_id,
+                product_id: link.product_id,
+                serial_number: link.serial_number.clone(),
+                firmware_version: link.firmware_version.clone(),
+                capabilities: link.capabilities.names(),
+            })
+            .collect();
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&listings).expect("Failed to serialize the probes.")
+        );
+    } else if !links.is_empty() {
         println!("The following devices were found:");
         links
             .iter()
             .enumerate()
-            .for_each(|(num, link)| println!("[{}]: {:?}", num, link));
+            .for_each(|(num, link)| println!("[{}]: {}", num, link));
     } else {
         println!("No devices were found.");
     }
@@ -155,43 +1095,326 @@ fn list_connected_devices() -> Result<(), CliError> {
     Ok(())
 }
 
-fn dump_memory(shared_options: &SharedOptions, loc: u32, words: u32) -> Result<(), CliError> {
+/// Reports the connected probes, and then probes being connected or disconnected until
+/// interrupted.
+fn watch_connected_devices() -> Result<(), CliError> {
+    println!("Watching for probes being connected or disconnected, press Ctrl-C to stop.");
+
+    watch::watch_probes(|event| {
+        match event {
+            Some(ProbeEvent::Attached(probe)) => println!("{} {}", "Connected:".green(), probe),
+            Some(ProbeEvent::Removed(probe)) => println!("{} {}", "Disconnected:".red(), probe),
+            None => {}
+        }
+        !interrupted()
+    })?;
+
+    Ok(())
+}
+
+fn dump_symbol(shared_options: &SharedOptions, exe: &Path, symbol: String) -> Result<(), CliError> {
+    let variable = find_statics(exe, &[symbol])?.remove(0);
+
     with_device(shared_options, |mut session| {
-        let mut data = vec![0 as u32; words as usize];
+        let value = variable.read(&mut session.probe)?;
+
+        println!("{} @ 0x{:08x} = {}", variable.name, variable.address, value);
 
+        Ok(())
+    })
+}
+
+fn download_program_fast(
+    shared_options: &SharedOptions,
+    path: &str,
+    format: Option<Format>,
+    offset: Option<u32>,
+    bootloader: Option<Range<u32>>,
+    watchdog_safe: bool,
+    after: AfterDownload,
+) -> Result<(), CliError> {
+    let format = file_format(Path::new(path), format)?;
+
+    with_device(shared_options, |mut session| {
         // Start timer.
+        // let instant = Instant::now();
+
+        if watchdog_safe {
+            prepare_watchdogs(&mut session)?;
+        }
+
+        let mm = session.target.memory_map.clone();
+        let translation = address_translation(&mm, offset, bootloader);
+        let allocations = read_allocations(Path::new(path), format.clone())?;
+        dry_run::warn_about_usage(&MemoryUsage::new(&mm, &allocations, translation.as_ref()));
+
+        let fd = match translation {
+            Some(translation) => FileDownloader::with_translation(translation),
+            None => FileDownloader::new(),
+        };
+
+        let report = fd.download_file(&mut session, std::path::Path::new(&path), format, &mm)?;
+        println!("{}", report);
+
+        finish_download(&mut session, after)
+    })
+}
+
+fn download_to_ram(shared_options: &SharedOptions, path: &Path) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let entry = run_from_ram(&mut session, path)?;
+
+        println!(
+            "Loaded {} into RAM and started it at 0x{:08x}.",
+            path.display(),
+            entry
+        );
+
+        Ok(())
+    })
+}
+
+/// Returns how `--offset` and `--bootloader` move an image, if they are given.
+fn address_translation(
+    memory_map: &[MemoryRegion],
+    offset: Option<u32>,
+    bootloader: Option<Range<u32>>,
+) -> Option<AddressTranslation> {
+    match (offset, bootloader) {
+        (None, None) => None,
+        (offset, bootloader) => {
+            let offset = offset.unwrap_or(0);
+            let mut translation = AddressTranslation::behind_bootloader(memory_map, offset);
+            if let Some(bootloader) = bootloader {
+                translation.protected = bootloader;
+            }
+            Some(translation)
+        }
+    }
+}
+
+/// Returns the format given on the command line, or the one detected from the file.
+fn file_format(path: &Path, format: Option<Format>) -> Result<Format, CliError> {
+    match format {
+        Some(format) => Ok(format),
+        None => Ok(Format::detect(path)?),
+    }
+}
+
+/// What becomes of the target after a download.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum AfterDownload {
+    /// Reset it, so the new firmware runs.
+    Run,
+    /// Reset it and halt the core at the reset vector.
+    Halt,
+    /// Leave it as the flash algorithm left it.
+    Keep,
+}
+
+/// Resets the target after a download with its reset type, unless it is to be kept as it is.
+fn finish_download(session: &mut Session, after: AfterDownload) -> Result<(), CliError> {
+    reset_after_download(session, after)?;
+    if after == AfterDownload::Halt {
+        println!("The core is halted at the reset vector.");
+    }
+
+    Ok(())
+}
+
+/// Resets the target like `finish_download`, without telling where the core is.
+fn reset_after_download(session: &mut Session, after: AfterDownload) -> Result<(), CliError> {
+    let reset_type = session.target.reset_type;
+
+    match after {
+        AfterDownload::Run => session.target_reset(reset_type)?,
+        AfterDownload::Halt => session.target_reset_and_halt(reset_type)?,
+        AfterDownload::Keep => {}
+    }
+
+    Ok(())
+}
+
+/// Stops the watchdogs of the target before flashing, or warns if none are known.
+fn prepare_watchdogs(session: &mut Session) -> Result<(), CliError> {
+    if !session.prepare_watchdog_safe_flashing()? {
+        eprintln!(
+            "{}: No watchdogs of {} are known, only the core is halted.",
+            "Warning".yellow(),
+            session.target.name
+        );
+    }
+
+    Ok(())
+}
+
+/// Programs the sectors of the file which differ from the previous file, or from the flash.
+fn download_patch(
+    shared_options: &SharedOptions,
+    path: &Path,
+    previous: Option<&Path>,
+    format: Option<Format>,
+    watchdog_safe: bool,
+    after: AfterDownload,
+) -> Result<(), CliError> {
+    let path_format = file_format(path, format.clone())?;
+    let previous_format = match previous {
+        Some(previous) => Some(file_format(previous, format)?),
+        None => None,
+    };
+
+    with_device(shared_options, |mut session| {
+        let memory_map = &session.target.memory_map;
+        let image = read_flash_image(path, path_format, memory_map)?;
+        let previous = match (previous, previous_format) {
+            (Some(previous), Some(format)) => Some(read_flash_image(previous, format, memory_map)?),
+            _ => None,
+        };
+        let base = match &previous {
+            Some(previous) => PatchBase::Previous(previous),
+            None => PatchBase::ReadBack,
+        };
+
+        if watchdog_safe {
+            prepare_watchdogs(&mut session)?;
+        }
+
         let instant = Instant::now();
+        let summary = patch::download_patch(&mut session, &image, base)?;
 
-        // let loc = 220 * 1024;
+        println!(
+            "Programmed {} of {} sectors in {:.2?}.",
+            summary.changed,
+            summary.sectors,
+            instant.elapsed()
+        );
 
-        session.probe.read_block32(loc, &mut data.as_mut_slice())?;
-        // Stop timer.
-        let elapsed = instant.elapsed();
+        finish_download(&mut session, after)
+    })
+}
 
-        // Print read values.
-        for word in 0..words {
+/// Programs the file with a journal of the programmed sectors, skipping those an interrupted
+/// download of the same file recorded.
+fn download_resumable(
+    shared_options: &SharedOptions,
+    path: &Path,
+    format: Option<Format>,
+    watchdog_safe: bool,
+    after: AfterDownload,
+) -> Result<(), CliError> {
+    let format = file_format(path, format)?;
+    let mut journal_path = path.as_os_str().to_owned();
+    journal_path.push(".journal");
+
+    with_device(shared_options, |mut session| {
+        let image = read_flash_image(path, format, &session.target.memory_map)?;
+        let mut journal = Journal::open(Path::new(&journal_path), &session.target.name, &image)?;
+        if journal.completed() > 0 {
             println!(
-                "Addr 0x{:08x?}: 0x{:08x}",
-                loc + 4 * word,
-                data[word as usize]
+                "Resuming the download, {} sectors were programmed before.",
+                journal.completed()
             );
         }
-        // Print stats.
-        println!("Read {:?} words in {:?}", words, elapsed);
 
-        Ok(())
+        if watchdog_safe {
+            prepare_watchdogs(&mut session)?;
+        }
+
+        let instant = Instant::now();
+        let summary = journal::download_resumable(&mut session, &image, &mut journal)?;
+        journal.finish()?;
+
+        println!(
+            "Programmed {} of {} sectors in {:.2?}.",
+            summary.changed,
+            summary.sectors,
+            instant.elapsed()
+        );
+
+        finish_download(&mut session, after)
     })
 }
 
-fn download_program_fast(shared_options: &SharedOptions, path: &str) -> Result<(), CliError> {
-    with_device(shared_options, |mut session| {
-        // Start timer.
-        // let instant = Instant::now();
+/// Programs the targets of all connected probes at once, resets them as told by `after`, and
+/// prints the result of each.
+fn download_all_probes(
+    shared_options: &SharedOptions,
+    path: &str,
+    format: Option<Format>,
+    after: AfterDownload,
+) -> Result<(), CliError> {
+    let format = file_format(Path::new(path), format)?;
+    let config = configuration(shared_options)?;
+    let devices = get_connected_devices();
+    if devices.is_empty() {
+        return Err(CliError::UnableToOpenProbe);
+    }
 
-        let fd = FileDownloader::new();
-        let mm = session.target.memory_map.clone();
+    let names: Vec<String> = devices
+        .iter()
+        .map(|device| match &device.serial_number {
+            Some(serial) => format!("{} ({})", device.identifier, serial),
+            None => device.identifier.clone(),
+        })
+        .collect();
+
+    let sessions: Vec<_> = devices
+        .iter()
+        .enumerate()
+        .map(|(index, device)| {
+            let mut config = config.clone();
+            config.probe.index = Some(index);
+            config.probe.serial = device.serial_number.clone();
+
+            move || open_session(&config)
+        })
+        .collect();
+
+    let finish = move |session: &mut Session| reset_after_download(session, after);
+    let results = flash_many(sessions, Path::new(path), format, finish, |i, stage| {
+        let label = format!("{:?}", stage);
+        let label = format!("{:>12}", label);
+        let label = if stage == FlashStage::Failed {
+            label.red().bold()
+        } else {
+            label.green().bold()
+        };
+        println!("{} {}", label, names[i]);
+    });
+
+    println!();
+    println!("{:<40} {:<8} Time", "Probe", "Result");
+    let mut failed = 0;
+    for (name, result) in names.iter().zip(&results) {
+        match result {
+            Ok(duration) => println!(
+                "{:<40} {} {:.2}s",
+                name,
+                format!("{:<8}", "ok").green(),
+                duration.as_millis() as f32 / 1000.0
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("{:<40} {} {}", name, format!("{:<8}", "failed").red(), e);
+            }
+        }
+    }
+
+    if after == AfterDownload::Halt && failed < results.len() {
+        println!("The cores of the programmed targets are halted at the reset vector.");
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(CliError::ProgrammingFailed(failed))
+    }
+}
 
-        fd.download_file(&mut session, std::path::Path::new(&path), Format::Elf, &mm)?;
+fn show_status_of_core(shared_options: &SharedOptions) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let status = session.target.core.status(&mut session.probe)?;
+        println!("{}", status);
 
         Ok(())
     })
@@ -199,78 +1422,112 @@ fn download_program_fast(shared_options: &SharedOptions, path: &str) -> Result<(
 
 fn reset_target_of_device(
     shared_options: &SharedOptions,
-    _assert: Option<bool>,
+    assert: Option<bool>,
 ) -> Result<(), CliError> {
     with_device(shared_options, |mut session| {
-        session.probe.target_reset()?;
+        match assert {
+            Some(asserted) => session.probe.set_reset_asserted(asserted)?,
+            None => session.target_reset(session.target.reset_type)?,
+        }
 
         Ok(())
     })
 }
 
-fn trace_u32_on_target(shared_options: &SharedOptions, loc: u32) -> Result<(), CliError> {
-    use scroll::Pwrite;
-    use std::io::prelude::*;
-    use std::thread::sleep;
-    use std::time::Duration;
+fn checksum_of_range(
+    shared_options: &SharedOptions,
+    range: Range<u32>,
+    algorithm: ChecksumAlgorithm,
+    on_target: bool,
+) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let instant = Instant::now();
+        let result = checksum(&mut session, range.clone(), algorithm, on_target)?;
 
-    let mut xs = vec![];
-    let mut ys = vec![];
+        println!(
+            "{} of 0x{:08x}..0x{:08x}: 0x{:08x}",
+            algorithm, range.start, range.end, result.value
+        );
+        println!(
+            "Computed in {:.2?}, {} of {} bytes on the target.",
+            instant.elapsed(),
+            result.on_target,
+            range.end - range.start
+        );
 
-    let start = Instant::now();
+        Ok(())
+    })
+}
 
+fn protect_target(shared_options: &SharedOptions, reset: bool) -> Result<(), CliError> {
     with_device(shared_options, |mut session| {
-        loop {
-            // Prepare read.
-            let elapsed = start.elapsed();
-            let instant = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
-
-            // Read data.
-            let value: u32 = session.probe.read32(loc)?;
-
-            xs.push(instant);
-            ys.push(value);
-
-            // Send value to plot.py.
-            // Unwrap is safe as there is always an stdin in our case!
-            let mut buf = [0 as u8; 8];
-            // Unwrap is safe!
-            buf.pwrite(instant, 0).unwrap();
-            buf.pwrite(value, 4).unwrap();
-            std::io::stdout().write_all(&buf)?;
-
-            std::io::stdout().flush()?;
-
-            // Schedule next read.
-            let elapsed = start.elapsed();
-            let instant = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
-            let poll_every_ms = 50;
-            let time_to_wait = poll_every_ms - instant % poll_every_ms;
-            sleep(Duration::from_millis(time_to_wait));
+        let protection = ReadoutProtection::for_target(&session.target)
+            .ok_or_else(|| ProtectionError::Unsupported(session.target.name.clone()))?;
+
+        protection.enable(&mut session)?;
+
+        if reset {
+            session.target_reset(session.target.reset_type)?;
+            println!("Enabled {} of {}.", protection, session.target.name);
+        } else {
+            println!(
+                "Enabled {} of {}. It takes effect after the next reset.",
+                protection, session.target.name
+            );
         }
+
+        Ok(())
     })
 }
 
 fn get_connected_devices() -> Vec<DebugProbeInfo> {
-    let mut links = daplink::tools::list_daplink_devices();
-    links.extend(stlink::tools::list_stlink_devices());
-    links
+    watch::list_probes()
 }
 
 fn debug(
     shared_options: &SharedOptions,
     exe: Option<PathBuf>,
     dump: Option<PathBuf>,
+    script: Option<PathBuf>,
+    commands: Vec<String>,
+    resume: bool,
+    cache: bool,
 ) -> Result<(), CliError> {
     // try to load debug information
     let debug_data = exe
+        .as_ref()
         .and_then(|p| fs::File::open(&p).ok())
         .and_then(|file| unsafe { memmap::Mmap::map(&file).ok() });
 
-    let runner = |session| {
+    // Collect the commands for a non-interactive run, script file first.
+    let mut script_lines = match script {
+        Some(path) => fs::read_to_string(path)?
+            .lines()
+            .map(str::to_owned)
+            .collect::<Vec<_>>(),
+        None => vec![],
+    };
+    script_lines.extend(commands);
+
+    let runner = |mut session: Session| {
+        if cache {
+            session.enable_memory_cache();
+        }
+
+        // The ARMv8-M cores have instructions of their own, like the ones of TrustZone.
+        let mut extra_modes = vec![ArchExtraMode::MClass];
+        if let Some(core_info) = &session.core_info {
+            if core_info.core_type.is_armv8m() {
+                extra_modes.push(ArchExtraMode::V8);
+            }
+        }
+
+        // Big-endian Arm cores use BE-8, where only the data is big endian. Their instructions
+        // are stored little endian like the ones of all other targets.
         let cs = Capstone::new()
             .arm()
             .mode(ArchMode::Thumb)
+            .extra_mode(extra_modes.into_iter())
             .endian(Endian::Little)
             .build()
             .unwrap();
@@ -279,42 +1536,72 @@ fn debug(
 
         let cli = debugger::DebugCli::new();
 
+        let rtos = di.as_ref().and_then(rtos::detect);
+        if let Some(rtos) = &rtos {
+            println!("Found {}, list its threads with 'threads'.", rtos.name());
+        }
+
         let mut cli_data = debugger::CliData {
             session,
             debug_info: di,
             capstone: cs,
+            cycle_counters: None,
+            mtb: None,
+            rtos,
+            thread: None,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            displays: vec![],
+            tracepoint_log: debugger::TracepointLog::new(),
         };
 
-        let mut rl = Editor::<()>::new();
+        // Restore the breakpoints of the last session debugging the same program.
+        if let Some(saved) = exe.as_ref().and_then(|exe| SavedSession::load(exe)) {
+            if saved != SavedSession::new(&saved.elf) {
+                println!(
+                "Restoring {} breakpoint(s), {} watchpoint(s) and {} displayed variable(s) from {}.",
+                saved.breakpoints.len(),
+                saved.watchpoints.len(),
+                saved.displays.len(),
+                saved_session::SESSION_FILE
+                );
+            }
+            cli_data.restore(saved);
+        }
 
-        loop {
-            let readline = rl.readline(">> ");
-            match readline {
-                Ok(line) => {
-                    let history_entry: &str = line.as_ref();
-                    rl.add_history_entry(history_entry);
-                    let cli_state = cli.handle_line(&line, &mut cli_data)?;
+        // Run the per-project init file, similar to a `.gdbinit`.
+        if let Ok(init_script) = fs::read_to_string(debugger::INIT_FILE_NAME) {
+            let init_lines: Vec<_> = init_script.lines().map(str::to_owned).collect();
 
-                    match cli_state {
-                        CliState::Continue => (),
-                        CliState::Stop => return Ok(()),
-                    }
-                }
-                Err(e) => {
-                    use rustyline::error::ReadlineError;
-
-                    match e {
-                        // For end of file and ctrl-c, we just quit
-                        ReadlineError::Eof | ReadlineError::Interrupted => return Ok(()),
-                        actual_error => {
-                            // Show error message and quit
-                            println!("Error handling input: {:?}", actual_error);
-                            return Ok(());
-                        }
-                    }
-                }
+            if let Err(e) = cli.run_script(&init_lines, &mut cli_data) {
+                eprintln!("Error executing {}: {}", debugger::INIT_FILE_NAME, e);
             }
         }
+
+        let result = if !script_lines.is_empty() {
+            cli.run_script(&script_lines, &mut cli_data)
+        } else {
+            run_repl(&cli, &mut cli_data)
+        };
+
+        if let Some(exe) = &exe {
+            if let Err(e) = cli_data.saved_session(exe).save() {
+                eprintln!(
+                    "Failed to save the session to {}: {}",
+                    saved_session::SESSION_FILE,
+                    e
+                );
+            }
+        }
+
+        // Leave the target in a clean state, even if a command failed.
+        if let Err(e) = cli_data.clear_watchpoints() {
+            eprintln!("Failed to clear the watchpoints: {}", e);
+        }
+        let detach_result = cli_data.session.detach(resume);
+
+        result?;
+        detach_result.map_err(From::from)
     };
 
     match dump {
@@ -322,3 +1609,70 @@ fn debug(
         Some(p) => with_dump(shared_options, &p, &runner),
     }
 }
+
+/// Runs the interactive debugger prompt until the user quits.
+fn run_repl(cli: &debugger::DebugCli, cli_data: &mut debugger::CliData) -> Result<(), CliError> {
+    let mut rl = Editor::<debugger::CliHelper>::new();
+    rl.set_helper(Some(debugger::CliHelper::new(
+        cli,
+        cli_data.debug_info.as_ref(),
+    )));
+
+    let history_path = debugger::history_path();
+
+    if let Some(path) = &history_path {
+        // A missing history file is expected on the first start.
+        let _ = rl.load_history(path);
+    }
+
+    loop {
+        let readline = rl.readline(">> ");
+        match readline {
+            Ok(line) => {
+                let history_entry: &str = line.as_ref();
+                rl.add_history_entry(history_entry);
+
+                if let Some(path) = &history_path {
+                    if let Err(e) = rl.save_history(path) {
+                        eprintln!("Failed to save history to {}: {}", path.display(), e);
+                    }
+                }
+
+                let cli_state = match cli.handle_line(&line, cli_data) {
+                    Err(CliError::UnknownCommand(command)) => {
+                        println!("Unknown command '{}'", command);
+                        println!("Enter 'help' for a list of commands");
+
+                        CliState::Continue
+                    }
+                    Err(e @ CliError::MissingArgument)
+                    | Err(e @ CliError::InvalidArgument(_))
+                    | Err(e @ CliError::UnknownSymbol(_)) => {
+                        println!("{}", e);
+
+                        CliState::Continue
+                    }
+                    result => result?,
+                };
+
+                match cli_state {
+                    CliState::Continue => (),
+                    CliState::Stop => return Ok(()),
+                }
+            }
+            Err(e) => {
+                use rustyline::error::ReadlineError;
+
+                match e {
+                    // For end of file and ctrl-c, we just quit
+                    ReadlineError::Eof | ReadlineError::Interrupted => return Ok(()),
+                    actual_error => {
+                        // Show error message and quit
+                        println!("Error handling input: {:?}", actual_error);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
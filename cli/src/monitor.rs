@@ -0,0 +1,67 @@
+use crate::common::{configuration, interrupted, open_probe, CliError};
+use crate::SharedOptions;
+
+use probe_rs::memory::watcher::{MemoryWatcher, WatchEntry, WatchEvent};
+
+use std::num::ParseIntError;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+/// Parses a watch entry given as `address[:size]`, both in hexadecimal without 0x prefix.
+pub(crate) fn parse_watch_entry(src: &str) -> Result<WatchEntry, ParseIntError> {
+    let mut parts = src.splitn(2, ':');
+
+    let address = u32::from_str_radix(parts.next().unwrap_or(""), 16)?;
+    let size = match parts.next() {
+        Some(size) => u32::from_str_radix(size, 16)?,
+        None => 4,
+    };
+
+    Ok(WatchEntry { address, size })
+}
+
+/// Prints the contents of `entries` whenever they change, until Ctrl-C is pressed.
+pub(crate) fn monitor(
+    shared_options: &SharedOptions,
+    entries: Vec<WatchEntry>,
+    frequency: u32,
+) -> Result<(), CliError> {
+    let probe_config = configuration(shared_options)?.probe;
+
+    let (watcher, events) = MemoryWatcher::start(
+        move || open_probe(&probe_config),
+        entries.clone(),
+        frequency,
+    );
+
+    while !interrupted() {
+        match events.recv_timeout(Duration::from_millis(100)) {
+            Ok(WatchEvent::Changed { index, data }) => {
+                print!("0x{:08x}:", entries[index].address);
+
+                for byte in &data {
+                    print!(" {:02x}", byte);
+                }
+
+                if data.len() == 4 {
+                    print!(
+                        " ({})",
+                        u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+                    );
+                }
+
+                println!();
+            }
+            Ok(WatchEvent::ReadFailed { index, error }) => {
+                eprintln!("0x{:08x}: {}", entries[index].address, error);
+            }
+            Ok(WatchEvent::OpenFailed(e)) => return Err(e),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    watcher.stop();
+
+    Ok(())
+}
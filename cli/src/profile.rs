@@ -0,0 +1,191 @@
+use crate::common::{interrupted, with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::{collection::cores::dwt, debug::DebugInfo, session::Session, target::CoreStatus};
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Number of functions shown in the hot function table.
+const TABLE_SIZE: usize = 20;
+
+/// Samples the program counter for `duration` and reports where the time was spent.
+///
+/// The PC is sampled through DWT_PCSR, which does not disturb the target. If the core
+/// does not implement it, or `halt_sampling` is set, the core is halted for every sample
+/// instead, which also allows recording the whole call stack. A core halted before profiling
+/// stays halted.
+pub(crate) fn profile(
+    shared_options: &SharedOptions,
+    exe: Option<PathBuf>,
+    duration: Duration,
+    folded: Option<PathBuf>,
+    halt_sampling: bool,
+) -> Result<(), CliError> {
+    let debug_data = match exe {
+        Some(path) => Some(fs::read(path)?),
+        None => None,
+    };
+
+    with_device(shared_options, |mut session| {
+        let debug_info = debug_data.as_ref().map(|data| DebugInfo::from_raw(data));
+
+        let use_pcsr = !halt_sampling && pcsr_available(&mut session)?;
+        let was_halted = matches!(
+            session.target.core.status(&mut session.probe)?,
+            CoreStatus::Halted(_)
+        );
+
+        if !use_pcsr {
+            println!("Sampling by halting the core, this slows down the target.");
+        }
+
+        let mut symbolizer = Symbolizer::new(debug_info.as_ref());
+        let mut stacks: HashMap<String, u64> = HashMap::new();
+        let mut sample_count = 0;
+
+        let start = Instant::now();
+
+        while start.elapsed() < duration && !interrupted() {
+            let stack = if use_pcsr {
+                match dwt::read_pc_sample(&mut session.probe)? {
+                    Some(pc) => symbolizer.function_name(pc),
+                    None => continue,
+                }
+            } else {
+                sample_halted(&mut session, &mut symbolizer, !was_halted)?
+            };
+
+            *stacks.entry(stack).or_insert(0) += 1;
+            sample_count += 1;
+        }
+
+        println!(
+            "Collected {} samples in {:.1} s.",
+            sample_count,
+            start.elapsed().as_secs_f32()
+        );
+
+        match folded {
+            Some(path) => write_folded(&path, &stacks)?,
+            None => print_hot_functions(&stacks, sample_count),
+        }
+
+        Ok(())
+    })
+}
+
+/// Enables the DWT and checks whether DWT_PCSR delivers samples.
+///
+/// A DWT_PCSR which is not implemented always reads as zero, while a running core is unlikely
+/// to be sampled at address 0 ten times in a row.
+fn pcsr_available(session: &mut Session) -> Result<bool, CliError> {
+    dwt::enable_pc_sampling(&mut session.probe)?;
+
+    for _ in 0..10 {
+        match dwt::read_pc_sample(&mut session.probe)? {
+            Some(0) | None => continue,
+            Some(_) => return Ok(true),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Halts the core, records its call stack in folded format and, if `resume` is set, resumes it.
+fn sample_halted(
+    session: &mut Session,
+    symbolizer: &mut Symbolizer,
+    resume: bool,
+) -> Result<String, CliError> {
+    let cpu_info = session.target.core.halt(&mut session.probe)?;
+
+    let stack = match symbolizer.debug_info {
+        Some(debug_info) => {
+            let mut frames: Vec<String> = debug_info
+                .try_unwind(session, u64::from(cpu_info.pc))
+                .map(|frame| frame.function_name)
+                .collect();
+
+            // Folded stacks start with the outermost frame.
+            frames.reverse();
+            frames.join(";")
+        }
+        None => symbolizer.function_name(cpu_info.pc),
+    };
+
+    if resume {
+        session.target.core.run(&mut session.probe)?;
+    }
+
+    Ok(stack)
+}
+
+/// Resolves addresses to function names and caches the results.
+struct Symbolizer<'a> {
+    debug_info: Option<&'a DebugInfo>,
+    cache: HashMap<u32, String>,
+}
+
+impl<'a> Symbolizer<'a> {
+    fn new(debug_info: Option<&'a DebugInfo>) -> Self {
+        Symbolizer {
+            debug_info,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn function_name(&mut self, pc: u32) -> String {
+        let debug_info = self.debug_info;
+
+        self.cache
+            .entry(pc)
+            .or_insert_with(|| {
+                debug_info
+                    .and_then(|di| di.function_name(u64::from(pc)))
+                    .unwrap_or_else(|| format!("0x{:08x}", pc))
+            })
+            .clone()
+    }
+}
+
+/// Writes the stacks in the folded format understood by `flamegraph.pl` and `inferno`.
+fn write_folded(path: &Path, stacks: &HashMap<String, u64>) -> Result<(), CliError> {
+    let mut file = File::create(path)?;
+
+    for (stack, count) in stacks {
+        writeln!(file, "{} {}", stack, count)?;
+    }
+
+    println!("Folded stacks written to {}.", path.display());
+
+    Ok(())
+}
+
+/// Prints the functions in which most samples were taken.
+fn print_hot_functions(stacks: &HashMap<String, u64>, sample_count: u64) {
+    // Attribute each sample to the innermost function of its stack.
+    let mut functions: HashMap<&str, u64> = HashMap::new();
+
+    for (stack, count) in stacks {
+        let function = stack.rsplit(';').next().unwrap_or(stack);
+        *functions.entry(function).or_insert(0) += count;
+    }
+
+    let mut functions: Vec<_> = functions.into_iter().collect();
+    functions.sort_by(|a, b| b.1.cmp(&a.1));
+
+    println!("{:>8} {:>7}  Function", "Samples", "%");
+
+    for (function, count) in functions.iter().take(TABLE_SIZE) {
+        println!(
+            "{:>8} {:>6.2}%  {}",
+            count,
+            *count as f64 * 100.0 / sample_count.max(1) as f64,
+            function
+        );
+    }
+}
@@ -0,0 +1,388 @@
+//! Programming of production units as described by a manifest.
+//!
+//! A manifest lists the images to program and the commands to run before and after them:
+//!
+//! ```toml
+//! serial_counter = "serial.txt"
+//!
+//! [[pre]]
+//! command = "erase-all"
+//!
+//! [[image]]
+//! path = "bootloader.elf"
+//!
+//! [[image]]
+//! path = "settings.bin"
+//! address = 0x7f000
+//!
+//! [[post]]
+//! command = "write-serial"
+//! address = 0x10001080
+//!
+//! [[post]]
+//! command = "verify"
+//!
+//! [[post]]
+//! command = "protect"
+//! ```
+//!
+//! The steps are run in order until one fails. The result of each unit is printed as a JSON
+//! object, so it can be collected by the tools of the production line.
+
+use crate::common::{open_session, CliError};
+
+use probe_rs::{
+    config::Config,
+    memory::MI,
+    probe::flash::{
+        config_region::ConfigRegion,
+        download::{read_image, BinOptions, FileDownloadError, Format},
+        flasher::{AlgorithmSelectionError, Flasher, FlasherError},
+        loader::FlashLoader,
+        memory::MemoryRegion,
+        protection::{ProtectionError, ReadoutProtection},
+    },
+    session::Session,
+};
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    /// The file holding the serial number of the next unit, which is incremented for each
+    /// unit that passes.
+    serial_counter: Option<PathBuf>,
+    #[serde(default)]
+    pre: Vec<Command>,
+    /// The images to program, each into the memory region at its address.
+    #[serde(default, rename = "image")]
+    images: Vec<Image>,
+    #[serde(default)]
+    post: Vec<Command>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Image {
+    /// The path of the image, relative to the manifest.
+    path: PathBuf,
+    /// The address of a binary image. Without it, the image is read as an ELF file.
+    address: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Command {
+    /// Erases the whole flash.
+    EraseAll,
+    /// Programs the serial number of the unit as a little endian word, e.g. into the UICR.
+    WriteSerial {
+        address: u32,
+    },
+    /// Programs the given bytes.
+    Write {
+        address: u32,
+        data: Vec<u8>,
+    },
+    /// Compares the flash with the images.
+    Verify,
+    /// Enables the readout protection.
+    Protect,
+    Reset,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::EraseAll => write!(f, "erase-all"),
+            Command::WriteSerial { address } => write!(f, "write-serial {:#010x}", address),
+            Command::Write { address, data } => {
+                write!(f, "write {} bytes {:#010x}", data.len(), address)
+            }
+            Command::Verify => write!(f, "verify"),
+            Command::Protect => write!(f, "protect"),
+            Command::Reset => write!(f, "reset"),
+        }
+    }
+}
+
+/// The result of provisioning one unit.
+#[derive(Debug, Serialize)]
+struct UnitResult {
+    serial: Option<u32>,
+    target: Option<String>,
+    passed: bool,
+    steps: Vec<StepResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct StepResult {
+    step: String,
+    passed: bool,
+    error: Option<String>,
+    duration_ms: u128,
+}
+
+/// Runs the steps of the manifest on the unit connected to the probe.
+///
+/// The result is printed as JSON and appended to `results` as a line, if given.
+pub(crate) fn provision(
+    config: &Config,
+    manifest_path: &Path,
+    serial: Option<u32>,
+    results: Option<&Path>,
+) -> Result<(), CliError> {
+    let manifest: Manifest = toml::from_str(&fs::read_to_string(manifest_path)?)?;
+    let root = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+
+    // A serial number given on the command line leaves the counter as it is.
+    let counter = match serial {
+        Some(_) => None,
+        None => manifest.serial_counter.as_ref().map(|path| root.join(path)),
+    };
+    let serial = match &counter {
+        Some(counter) => Some(read_counter(counter)?),
+        None => serial,
+    };
+
+    let mut images = vec![];
+    for image in &manifest.images {
+        let format = match image.address {
+            Some(address) => Format::Bin(BinOptions::new(Some(address), 0)),
+            None => Format::Elf,
+        };
+        images.extend(read_image(&root.join(&image.path), format)?);
+    }
+
+    let mut result = UnitResult {
+        serial,
+        target: None,
+        passed: false,
+        steps: vec![],
+    };
+
+    let mut session = run_step(&mut result, "connect", || open_session(config));
+    if let Some(session) = &mut session {
+        result.target = Some(session.target.name.clone());
+
+        let steps = manifest
+            .pre
+            .iter()
+            .map(Some)
+            .chain(std::iter::once(None))
+            .chain(manifest.post.iter().map(Some));
+
+        result.passed = true;
+        for command in steps {
+            let passed = match command {
+                Some(command) => run_step(&mut result, &command.to_string(), || {
+                    run_command(session, command, serial, &images)
+                }),
+                None if images.is_empty() => continue,
+                None => run_step(&mut result, "program", || write(session, &images)),
+            };
+
+            if passed.is_none() {
+                result.passed = false;
+                break;
+            }
+        }
+    }
+
+    let json = serde_json::to_string(&result).expect("Failed to serialize the result.");
+    println!("{}", json);
+
+    if let Some(results) = results {
+        let mut file = OpenOptions::new().create(true).append(true).open(results)?;
+        writeln!(file, "{}", json)?;
+    }
+
+    if !result.passed {
+        return Err(CliError::ProgrammingFailed(1));
+    }
+
+    if let (Some(serial), Some(counter)) = (serial, counter) {
+        fs::write(counter, format!("{}\n", serial + 1))?;
+    }
+
+    Ok(())
+}
+
+/// Runs a step and records its result. Returns the value of the step if it passed.
+fn run_step<T>(
+    result: &mut UnitResult,
+    step: &str,
+    f: impl FnOnce() -> Result<T, CliError>,
+) -> Option<T> {
+    let instant = Instant::now();
+    let outcome = f();
+
+    result.steps.push(StepResult {
+        step: step.to_string(),
+        passed: outcome.is_ok(),
+        error: outcome.as_ref().err().map(ToString::to_string),
+        duration_ms: instant.elapsed().as_millis(),
+    });
+
+    outcome.ok()
+}
+
+fn run_command(
+    session: &mut Session,
+    command: &Command,
+    serial: Option<u32>,
+    images: &[(u32, Vec<u8>)],
+) -> Result<(), CliError> {
+    match command {
+        Command::EraseAll => erase_all(session),
+        Command::WriteSerial { address } => {
+            let serial = serial.ok_or(CliError::MissingArgument)?;
+            write(session, &[(*address, serial.to_le_bytes().to_vec())])
+        }
+        Command::Write { address, data } => write(session, &[(*address, data.clone())]),
+        Command::Verify => verify(session, images),
+        Command::Protect => protect(session),
+        Command::Reset => Ok(session.target_reset(session.target.reset_type)?),
+    }
+}
+
+fn read_counter(path: &Path) -> Result<u32, CliError> {
+    let counter = fs::read_to_string(path)?;
+
+    counter.trim().parse().map_err(|_| {
+        CliError::InvalidArgument(format!(
+            "The serial counter {} does not contain a number.",
+            path.display()
+        ))
+    })
+}
+
+fn erase_all(session: &mut Session) -> Result<(), CliError> {
+    let algorithm = session
+        .flash_algorithm
+        .as_ref()
+        .ok_or(AlgorithmSelectionError::NoAlgorithmSuggested)?;
+    let region = session
+        .target
+        .memory_map
+        .iter()
+        .find_map(|region| match region {
+            MemoryRegion::Flash(flash) => Some(flash),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            CliError::InvalidArgument(format!("{} has no flash.", session.target.name))
+        })?;
+
+    // The results are printed to stdout, so the progress of algorithms reporting it goes to
+    // stderr.
+    let mut reported = false;
+    let result = Flasher::new(&session.target, &mut session.probe, algorithm, region).run_erase(
+        |active| -> Result<(), FlasherError> {
+            active.erase_all_with_progress(&mut |percent| {
+                eprint!("\rErasing the flash: {:>3}%", percent);
+                reported = true;
+            })
+        },
+    );
+    if reported {
+        eprintln!();
+    }
+    result?;
+
+    Ok(())
+}
+
+/// Programs blocks of data into the flash, or into the configuration region if they are
+/// inside it.
+fn write(session: &mut Session, blocks: &[(u32, Vec<u8>)]) -> Result<(), CliError> {
+    let memory_map = session.target.memory_map.clone();
+    let config_region = ConfigRegion::for_target(&session.target);
+    let mut loader = FlashLoader::new(&memory_map, false, false, false);
+
+    for (address, data) in blocks {
+        match &config_region {
+            Some(region) if region.contains(*address, data.len()) => {
+                region.write(&mut session.probe, *address, data)?
+            }
+            _ => loader
+                .add_data(*address, data)
+                .map_err(FileDownloadError::from)?,
+        }
+    }
+
+    loader.commit(session).map_err(FileDownloadError::from)?;
+
+    Ok(())
+}
+
+fn verify(session: &mut Session, images: &[(u32, Vec<u8>)]) -> Result<(), CliError> {
+    for (address, data) in images {
+        let mut memory = vec![0; data.len()];
+        session.probe.read_block8(*address, &mut memory)?;
+
+        if let Some(offset) = memory.iter().zip(data).position(|(a, b)| a != b) {
+            return Err(CliError::VerificationFailed(address + offset as u32));
+        }
+    }
+
+    Ok(())
+}
+
+/// Enables the readout protection, which blocks the debugger after a reset.
+fn protect(session: &mut Session) -> Result<(), CliError> {
+    let protection = ReadoutProtection::for_target(&session.target)
+        .ok_or_else(|| ProtectionError::Unsupported(session.target.name.clone()))?;
+
+    Ok(protection.enable(session)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Command, Manifest};
+
+    #[test]
+    fn parses_manifest() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            serial_counter = "serial.txt"
+
+            [[pre]]
+            command = "erase-all"
+
+            [[image]]
+            path = "firmware.elf"
+
+            [[image]]
+            path = "settings.bin"
+            address = 0x7f000
+
+            [[post]]
+            command = "write-serial"
+            address = 0x10001080
+
+            [[post]]
+            command = "verify"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.images.len(), 2);
+        assert_eq!(manifest.images[1].address, Some(0x7f000));
+        match &manifest.post[..] {
+            [Command::WriteSerial {
+                address: 0x1000_1080,
+            }, Command::Verify] => (),
+            post => panic!("Unexpected post commands {:?}", post),
+        }
+
+        assert!(toml::from_str::<Manifest>("[[pre]]\ncommand = \"format\"\n").is_err());
+    }
+}
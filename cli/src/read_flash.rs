@@ -0,0 +1,148 @@
+use crate::common::{with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::{
+    memory::MI,
+    probe::flash::{
+        memory::MemoryRegion,
+        protection::{ProtectionError, ReadoutProtection},
+    },
+};
+
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The flash is read in blocks of this size, so a failing read reports an address close to
+/// the protected memory.
+const READ_BLOCK_SIZE: usize = 4096;
+
+/// Reads all flash regions of the target and writes them to `output`, e.g. to back up a device
+/// before an update or to clone a gold unit.
+///
+/// Flash regions which are not contiguous, like the program and data flash of some chips, are
+/// written to one file each, named after their start address like `backup_10000000.bin`.
+pub(crate) fn read_flash(shared_options: &SharedOptions, output: &Path) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        if let Some(protection) = ReadoutProtection::for_target(&session.target) {
+            if protection.is_enabled(&mut session.probe)? {
+                return Err(ProtectionError::Enabled(protection).into());
+            }
+        }
+
+        let ranges =
+            contiguous_ranges(
+                session
+                    .target
+                    .memory_map
+                    .iter()
+                    .filter_map(|region| match region {
+                        MemoryRegion::Flash(flash) => Some(flash.range.clone()),
+                        _ => None,
+                    }),
+            );
+
+        if ranges.is_empty() {
+            return Err(CliError::InvalidArgument(format!(
+                "{} has no flash.",
+                session.target.name
+            )));
+        }
+
+        for range in &ranges {
+            let mut data = vec![0; (range.end - range.start) as usize];
+
+            for (index, block) in data.chunks_mut(READ_BLOCK_SIZE).enumerate() {
+                let address = range.start + (index * READ_BLOCK_SIZE) as u32;
+
+                if let Err(e) = session.probe.read_block8(address, block) {
+                    eprintln!(
+                        "Reading the flash at 0x{:08x} failed, it may be read protected.",
+                        address
+                    );
+                    return Err(e.into());
+                }
+            }
+
+            let path = output_path(output, range, ranges.len() == 1);
+            fs::write(&path, &data)?;
+
+            println!(
+                "Saved the flash at 0x{:08x}..0x{:08x} to {}.",
+                range.start,
+                range.end,
+                path.display()
+            );
+        }
+
+        Ok(())
+    })
+}
+
+/// Sorts the ranges and joins those which are adjacent.
+fn contiguous_ranges(ranges: impl Iterator<Item = Range<u32>>) -> Vec<Range<u32>> {
+    let mut ranges: Vec<_> = ranges.collect();
+    ranges.sort_by_key(|range| range.start);
+
+    let mut joined: Vec<Range<u32>> = vec![];
+    for range in ranges {
+        match joined.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => joined.push(range),
+        }
+    }
+
+    joined
+}
+
+/// Returns the file a range is written to, `output` itself if it is the only range.
+fn output_path(output: &Path, range: &Range<u32>, only: bool) -> PathBuf {
+    if only {
+        return output.to_path_buf();
+    }
+
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut name = format!("{}_{:08x}", stem, range.start);
+
+    if let Some(extension) = output.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+
+    output.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{contiguous_ranges, output_path};
+
+    use std::path::Path;
+
+    #[test]
+    fn splits_the_flash_into_files() {
+        let ranges = contiguous_ranges(
+            vec![
+                0x1000_0000..0x1002_0000,
+                0x0004_0000..0x0008_0000,
+                0x0000_0000..0x0004_0000,
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(
+            ranges,
+            vec![0x0000_0000..0x0008_0000, 0x1000_0000..0x1002_0000]
+        );
+        assert_eq!(
+            output_path(Path::new("out/backup.bin"), &ranges[1], false),
+            Path::new("out/backup_10000000.bin")
+        );
+        assert_eq!(
+            output_path(Path::new("backup.bin"), &ranges[0], true),
+            Path::new("backup.bin")
+        );
+    }
+}
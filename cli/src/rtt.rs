@@ -0,0 +1,325 @@
+use crate::common::{configuration, interrupted, with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::{
+    debug::DebugInfo,
+    defmt::{Frame, Level, StreamDecoder, Table},
+    memory::MI,
+    probe::flash::memory::MemoryRegion,
+    rtt::{self, virtual_terminal::Demultiplexer, Channel, ChannelMode, Rtt},
+};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+/// Time to wait before polling again when the target did not send anything.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Options of the RTT terminal.
+pub(crate) struct RttOptions {
+    /// The up channel to read, if not configured otherwise.
+    pub channel: Option<usize>,
+    /// The down channel stdin is forwarded to, if not configured otherwise.
+    pub down_channel: Option<usize>,
+    /// Decode the data as defmt log messages.
+    pub defmt: bool,
+    /// The lowest level of defmt messages to show.
+    pub level: Level,
+    /// Split the data into the output of SEGGER's virtual terminals.
+    pub virtual_terminals: bool,
+    /// Modes to set for up channels before streaming. Without a number, the read channel is meant.
+    pub modes: Vec<(Option<usize>, ChannelMode)>,
+}
+
+/// Parses a channel mode, optionally preceded by the number of the channel, e.g. `1=block`.
+pub(crate) fn parse_channel_mode(input: &str) -> Result<(Option<usize>, ChannelMode), String> {
+    let mut parts = input.splitn(2, '=');
+
+    match (parts.next(), parts.next()) {
+        (Some(mode), None) => Ok((None, mode.parse()?)),
+        (Some(channel), Some(mode)) => {
+            let channel = channel
+                .parse()
+                .map_err(|_| format!("Invalid channel number '{}'.", channel))?;
+
+            Ok((Some(channel), mode.parse()?))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Streams the data of an RTT up channel to stdout and forwards stdin to a down channel,
+/// until Ctrl-C is pressed.
+///
+/// With `defmt`, the data is decoded into log messages using the format strings
+/// from `exe`, and messages below `level` are dropped.
+pub(crate) fn rtt(
+    shared_options: &SharedOptions,
+    exe: Option<PathBuf>,
+    options: RttOptions,
+) -> Result<(), CliError> {
+    let RttOptions {
+        channel,
+        down_channel,
+        defmt,
+        level,
+        virtual_terminals,
+        modes,
+    } = options;
+
+    // The command line takes precedence over the configuration files.
+    let config = configuration(shared_options)?.rtt;
+    let channel = channel.or(config.channel).unwrap_or(0);
+    let down_channel = down_channel.or(config.down_channel).unwrap_or(0);
+    let defmt = defmt || (config.defmt == Some(true) && !virtual_terminals);
+
+    let elf = match exe {
+        Some(path) => Some(fs::read(path)?),
+        None => None,
+    };
+
+    let defmt = match (&elf, defmt) {
+        (Some(elf), true) => Some((Table::parse(elf)?, DebugInfo::from_raw(elf))),
+        _ => None,
+    };
+
+    let control_block = elf.as_ref().and_then(|elf| rtt::control_block_address(elf));
+
+    with_device(shared_options, |mut session| {
+        let rtt = match control_block {
+            Some(address) => Rtt::attach(&mut session.probe, address)?,
+            None => {
+                let ram: Vec<_> = session
+                    .target
+                    .memory_map
+                    .iter()
+                    .filter_map(|region| match region {
+                        MemoryRegion::Ram(ram) => Some(ram.range.clone()),
+                        _ => None,
+                    })
+                    .collect();
+
+                Rtt::find(&mut session.probe, &ram)?
+            }
+        };
+
+        for (number, mode) in modes {
+            let up_channel = rtt.up_channel(number.unwrap_or(channel))?;
+            up_channel.set_mode(&mut session.probe, mode)?;
+
+            eprintln!(
+                "Set the mode of RTT channel {} to '{}'.",
+                up_channel.number(),
+                mode
+            );
+        }
+
+        let channel = rtt.up_channel(channel)?.clone();
+
+        eprintln!(
+            "Reading RTT channel {} ({}) from the control block at 0x{:08x}.",
+            channel.number(),
+            channel.name().unwrap_or("unnamed"),
+            rtt.address()
+        );
+
+        // Targets without down channels can still be read, stdin is just not forwarded then.
+        let input = if rtt.down_channels().is_empty() {
+            None
+        } else {
+            let down_channel = rtt.down_channel(down_channel)?.clone();
+
+            eprintln!(
+                "Forwarding stdin to RTT down channel {} ({}).",
+                down_channel.number(),
+                down_channel.name().unwrap_or("unnamed"),
+            );
+
+            Some((down_channel, read_stdin()))
+        };
+
+        let mut pending_input = vec![];
+
+        let mut terminals = if virtual_terminals {
+            Some(TerminalOutput::new())
+        } else {
+            None
+        };
+
+        let mut decoder = defmt
+            .as_ref()
+            .map(|(table, debug_info)| (StreamDecoder::new(table), debug_info));
+        let mut locations = HashMap::new();
+
+        let stdout = io::stdout();
+
+        while !interrupted() {
+            let written = match &input {
+                Some((down_channel, stdin)) => {
+                    write_input(&mut session.probe, down_channel, stdin, &mut pending_input)?
+                }
+                None => 0,
+            };
+
+            let data = channel.read_available(&mut session.probe)?;
+
+            if data.is_empty() {
+                if written == 0 {
+                    sleep(POLL_INTERVAL);
+                }
+                continue;
+            }
+
+            match &mut decoder {
+                Some((decoder, debug_info)) => {
+                    decoder.received(&data);
+
+                    while let Some(frame) = decoder.decode() {
+                        match frame {
+                            Ok(frame) => {
+                                if frame.level.map_or(true, |l| l >= level) {
+                                    print_frame(&frame, debug_info, &mut locations);
+                                }
+                            }
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
+                }
+                None => {
+                    let mut stdout = stdout.lock();
+
+                    match &mut terminals {
+                        Some(terminals) => terminals.write(&mut stdout, &data)?,
+                        None => stdout.write_all(&data)?,
+                    }
+
+                    stdout.flush()?;
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Prints a log message followed by the location of the log statement, if it is known.
+fn print_frame(
+    frame: &Frame,
+    debug_info: &DebugInfo,
+    locations: &mut HashMap<u16, Option<(String, u64)>>,
+) {
+    println!("{}", frame);
+
+    // The index is the address of the symbol the log statement placed in the `.defmt` section.
+    let location = locations
+        .entry(frame.index)
+        .or_insert_with(|| debug_info.static_declaration(u32::from(frame.index)));
+
+    if let Some((file, line)) = location {
+        println!("└─ {}:{}", file, line);
+    }
+}
+
+/// Reads stdin on a separate thread, as reading it blocks.
+fn read_stdin() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut buffer = [0u8; 256];
+
+        loop {
+            match stdin.lock().read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(length) => {
+                    if sender.send(buffer[..length].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Writes the input read from stdin so far to a down channel.
+///
+/// Input that does not fit into the buffer of the channel is kept in `pending` and
+/// written on the next call. Returns the number of bytes written.
+fn write_input(
+    mi: &mut impl MI,
+    channel: &Channel,
+    stdin: &Receiver<Vec<u8>>,
+    pending: &mut Vec<u8>,
+) -> Result<usize, CliError> {
+    for input in stdin.try_iter() {
+        pending.extend(input);
+    }
+
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let written = channel.write(mi, pending)?;
+    pending.drain(..written);
+
+    Ok(written)
+}
+
+/// Prints the output of virtual terminals, prefixing each line with the number of its terminal.
+struct TerminalOutput {
+    demultiplexer: Demultiplexer,
+    /// The terminal of the last printed data.
+    terminal: Option<u8>,
+    line_start: bool,
+}
+
+impl TerminalOutput {
+    fn new() -> Self {
+        TerminalOutput {
+            demultiplexer: Demultiplexer::new(),
+            terminal: None,
+            line_start: true,
+        }
+    }
+
+    fn write(&mut self, output: &mut impl Write, data: &[u8]) -> io::Result<()> {
+        for (terminal, data) in self.demultiplexer.process(data) {
+            // A line interrupted by output of another terminal is ended, so lines are never mixed.
+            if self.terminal != Some(terminal) {
+                if !self.line_start {
+                    output.write_all(b"\n")?;
+                    self.line_start = true;
+                }
+
+                self.terminal = Some(terminal);
+            }
+
+            let mut rest = &data[..];
+
+            while !rest.is_empty() {
+                if self.line_start {
+                    write!(output, "[{}] ", terminal)?;
+                }
+
+                let end = rest
+                    .iter()
+                    .position(|b| *b == b'\n')
+                    .map_or(rest.len(), |i| i + 1);
+
+                output.write_all(&rest[..end])?;
+
+                self.line_start = rest[end - 1] == b'\n';
+                rest = &rest[end..];
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! The breakpoints, watchpoints and displayed variables of a debug session, which are saved to
+//! the project when the debugger exits and restored when it debugs the same program again.
+//!
+//! Locations are saved relative to the symbol they are in, so they move along with the symbol
+//! when the program is rebuilt.
+
+use probe_rs::{collection::cores::WatchKind, debug::DebugInfo};
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The file the session is saved to, relative to the current directory.
+pub const SESSION_FILE: &str = ".probe-rs/session.toml";
+
+/// A location in the program, by the symbol it is in if there is one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// The function or static variable the location is in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    /// The offset from the start of the symbol, or the address if there is no symbol.
+    #[serde(default)]
+    pub offset: u32,
+}
+
+impl Location {
+    /// Describes `address`, by the function containing it if the debug info knows it.
+    pub fn from_address(address: u32, debug_info: Option<&DebugInfo>) -> Self {
+        let function = debug_info.and_then(|di| {
+            let name = di.function_name(u64::from(address))?;
+            let start = di.function_address(&name)? as u32;
+            Some((name, start))
+        });
+
+        match function {
+            Some((name, start)) if start <= address => Location {
+                symbol: Some(name),
+                offset: address - start,
+            },
+            _ => Location {
+                symbol: None,
+                offset: address,
+            },
+        }
+    }
+
+    /// Describes the start of a function or static variable.
+    pub fn from_symbol(symbol: &str) -> Self {
+        Location {
+            symbol: Some(symbol.to_string()),
+            offset: 0,
+        }
+    }
+
+    /// Returns the address of the location in the program described by `debug_info`, or `None`
+    /// if its symbol does not exist anymore.
+    pub fn resolve(&self, debug_info: Option<&DebugInfo>) -> Option<u32> {
+        let symbol = match &self.symbol {
+            Some(symbol) => symbol,
+            None => return Some(self.offset),
+        };
+
+        let debug_info = debug_info?;
+        let start = match debug_info.function_address(symbol) {
+            Some(address) => address as u32,
+            None => debug_info.find_static(symbol)?.address,
+        };
+
+        Some(start + self.offset)
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.symbol, self.offset) {
+            (Some(symbol), 0) => write!(f, "{}", symbol),
+            (Some(symbol), offset) => write!(f, "{}+0x{:x}", symbol, offset),
+            (None, address) => write!(f, "0x{:08x}", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    #[serde(flatten)]
+    pub location: Location,
+    /// The core only stops if this condition holds, e.g. `count > 10`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    /// The number of hits before the core stops at the breakpoint.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub ignore_count: u32,
+    /// The static variables and registers logged when the core passes a tracepoint, which
+    /// does not stop the core. Empty for breakpoints.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<String>,
+    /// The number of times the breakpoint was hit in this session.
+    #[serde(skip)]
+    pub hits: u32,
+}
+
+impl Breakpoint {
+    pub fn new(location: Location) -> Self {
+        Breakpoint {
+            location,
+            condition: None,
+            ignore_count: 0,
+            trace: vec![],
+            hits: 0,
+        }
+    }
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watchpoint {
+    #[serde(flatten)]
+    pub location: Location,
+    /// The number of bytes watched, a power of two.
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedSession {
+    /// The program the session was saved for.
+    pub elf: PathBuf,
+    /// The static variables shown whenever the core halts.
+    #[serde(default)]
+    pub displays: Vec<String>,
+    #[serde(default)]
+    pub breakpoints: Vec<Breakpoint>,
+    #[serde(default)]
+    pub watchpoints: Vec<Watchpoint>,
+}
+
+impl SavedSession {
+    pub fn new(elf: &Path) -> Self {
+        SavedSession {
+            elf: elf.to_path_buf(),
+            displays: vec![],
+            breakpoints: vec![],
+            watchpoints: vec![],
+        }
+    }
+
+    /// Loads the session saved for the program `elf`, if there is one.
+    pub fn load(elf: &Path) -> Option<Self> {
+        let text = fs::read_to_string(SESSION_FILE).ok()?;
+
+        let session: SavedSession = match toml::from_str(&text) {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("Ignoring {}: {}", SESSION_FILE, e);
+                return None;
+            }
+        };
+
+        if same_file(&session.elf, elf) {
+            Some(session)
+        } else {
+            None
+        }
+    }
+
+    /// Saves the session, replacing the one saved before, which may be for another program.
+    pub fn save(&self) -> io::Result<()> {
+        let text = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        if let Some(dir) = Path::new(SESSION_FILE).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(SESSION_FILE, text)
+    }
+}
+
+/// Checks whether the paths name the same file, also if one of them is relative.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Breakpoint, Location, SavedSession, Watchpoint};
+    use probe_rs::collection::cores::WatchKind;
+
+    use std::path::Path;
+
+    #[test]
+    fn round_trips_through_toml() {
+        let mut session = SavedSession::new(Path::new("target/app"));
+        session.displays.push("COUNTER".to_string());
+        session.breakpoints.push(Breakpoint {
+            condition: Some("COUNTER > 10".to_string()),
+            ignore_count: 2,
+            trace: vec!["COUNTER".to_string(), "r0".to_string()],
+            ..Breakpoint::new(Location {
+                symbol: Some("main".to_string()),
+                offset: 4,
+            })
+        });
+        session.breakpoints.push(Breakpoint::new(Location {
+            symbol: None,
+            offset: 0x0800_0100,
+        }));
+        session.watchpoints.push(Watchpoint {
+            location: Location::from_symbol("COUNTER"),
+            len: 4,
+            kind: WatchKind::Write,
+        });
+
+        let text = toml::to_string(&session).unwrap();
+        assert_eq!(toml::from_str::<SavedSession>(&text).unwrap(), session);
+
+        // Locations without a symbol are absolute addresses.
+        assert_eq!(
+            session.breakpoints[1].location.resolve(None),
+            Some(0x0800_0100)
+        );
+        assert_eq!(session.breakpoints[0].location.resolve(None), None);
+    }
+}
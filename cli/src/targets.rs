@@ -0,0 +1,64 @@
+use crate::common::CliError;
+
+use probe_rs::{collection, target::description::TargetDescription};
+use probe_rs_targets::{get_built_in_algorithm, get_built_in_base};
+
+use colored::*;
+
+use std::fs;
+use std::path::Path;
+
+/// Checks a target description file and prints the problems found in it.
+///
+/// The flash algorithm and the base it refers to are searched in the `algorithms` and `bases`
+/// directories next to the `targets` directory of the file, and among the registered and the
+/// built-in ones.
+pub(crate) fn validate(path: &Path) -> Result<(), CliError> {
+    let description = TargetDescription::new(fs::read_to_string(path)?);
+
+    let root = path.parent().and_then(Path::parent);
+    let algorithms = root.map(|root| root.join("algorithms"));
+    let bases = root.map(|root| root.join("bases"));
+
+    let diagnostics = description.validate(
+        |name| {
+            get_built_in_algorithm(name).is_ok()
+                || collection::get_algorithm(name).is_some()
+                || algorithms
+                    .as_ref()
+                    .map_or(false, |algorithms| algorithms.join(name).is_file())
+        },
+        |name| {
+            bases
+                .as_ref()
+                .and_then(|bases| fs::read_to_string(bases.join(format!("{}.yaml", name))).ok())
+                .or_else(|| collection::get_base(name))
+                .or_else(|| get_built_in_base(name).map(ToString::to_string))
+        },
+    );
+
+    for diagnostic in &diagnostics {
+        match diagnostic.line {
+            Some(line) => eprintln!(
+                "{}:{}: {} {}",
+                path.display(),
+                line,
+                "error:".red().bold(),
+                diagnostic.message
+            ),
+            None => eprintln!(
+                "{}: {} {}",
+                path.display(),
+                "error:".red().bold(),
+                diagnostic.message
+            ),
+        }
+    }
+
+    if diagnostics.is_empty() {
+        println!("{} is valid.", path.display());
+        Ok(())
+    } else {
+        Err(CliError::InvalidTargetDescription(diagnostics.len()))
+    }
+}
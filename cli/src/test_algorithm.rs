@@ -0,0 +1,115 @@
+use crate::common::{with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::probe::flash::{
+    flasher::AlgorithmSelectionError,
+    memory::{FlashRegion, MemoryRegion},
+    test_algorithm, AlgorithmTestReport,
+};
+
+use colored::*;
+
+use std::time::Duration;
+
+/// Runs the self-test of the flash algorithm of the target and prints a report.
+///
+/// Without `address`, the last sector of the boot flash is used, which is the least likely
+/// to hold the firmware.
+pub(crate) fn run(shared_options: &SharedOptions, address: Option<u32>) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let algorithm = session
+            .flash_algorithm
+            .clone()
+            .ok_or(AlgorithmSelectionError::NoAlgorithmSuggested)?;
+
+        let regions: Vec<&FlashRegion> = session
+            .target
+            .memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Flash(flash) => Some(flash),
+                _ => None,
+            })
+            .collect();
+
+        let (region, address) = match address {
+            Some(address) => match regions.iter().find(|r| r.range.contains(&address)) {
+                Some(region) => (*region, address),
+                None => return Err(CliError::InvalidArgument(format!("{:08x}", address))),
+            },
+            None => match regions
+                .iter()
+                .find(|r| r.is_boot_memory)
+                .or_else(|| regions.first())
+            {
+                Some(region) => (*region, region.range.end - region.sector_size),
+                None => return Err(CliError::InvalidArgument("address".to_string())),
+            },
+        };
+        let region = region.clone();
+
+        println!(
+            "Testing the flash algorithm on the sector at 0x{:08x}. Its contents will be erased.",
+            address
+        );
+
+        let report = test_algorithm(
+            &session.target,
+            &mut session.probe,
+            &algorithm,
+            &region,
+            address,
+        )?;
+
+        print_report(&report);
+
+        Ok(())
+    })
+}
+
+fn print_report(report: &AlgorithmTestReport) {
+    let ms = |duration: Duration| format!("{:.1} ms", duration.as_micros() as f64 / 1000.0);
+
+    // The text is padded before it is colored, as the escape codes would count towards the width.
+    let check = |passed: Option<bool>| match passed {
+        Some(true) => format!("{:>8}", "ok").green(),
+        Some(false) => format!("{:>8}", "FAILED").red(),
+        None => format!("{:>8}", "-").normal(),
+    };
+
+    println!(
+        "Sector 0x{:08x} ({} bytes)",
+        report.sector_address, report.sector_size
+    );
+    println!("Init:   {}", ms(report.init_time));
+    println!("UnInit: {}", ms(report.uninit_time));
+    println!();
+    println!(
+        "{:<14} {:>10} {:>10} {:>8} {:>8} {:>8}",
+        "Pattern", "Erase", "Program", "Erased", "Verify", "CRC"
+    );
+
+    for result in &report.patterns {
+        println!(
+            "{:<14} {:>10} {:>10} {} {} {}",
+            result.pattern,
+            ms(result.erase_time),
+            ms(result.program_time),
+            check(Some(result.erased)),
+            check(Some(result.first_mismatch.is_none())),
+            check(result.crc_matches),
+        );
+
+        if let Some(address) = result.first_mismatch {
+            println!("    First mismatch at 0x{:08x}", address);
+        }
+    }
+
+    println!();
+
+    if report.passed() {
+        println!("{}", "The flash algorithm passed all tests.".green().bold());
+    } else {
+        println!("{}", "The flash algorithm failed.".red().bold());
+    }
+}
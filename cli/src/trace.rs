@@ -0,0 +1,131 @@
+use crate::common::{interrupted, with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::memory::MI;
+
+use std::fs::File;
+use std::io::{self, prelude::*, BufWriter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// The encoding of the values written by `probe-rs trace`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum TraceFormat {
+    /// Records of a little endian `u32` timestamp in milliseconds followed by the `u32` value,
+    /// one record per address. This is the format `update_plot.py` reads.
+    Binary,
+    /// A header line with the addresses, then one line per poll with the timestamp and all values.
+    Csv,
+    /// One JSON object per poll, e.g. `{"time_ms":50,"values":{"0x20000000":42}}`.
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(TraceFormat::Binary),
+            "csv" => Ok(TraceFormat::Csv),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!(
+                "Unknown format '{}', use 'binary', 'csv' or 'json'.",
+                s
+            )),
+        }
+    }
+}
+
+/// Polls the `u32` values at `locations` every `interval` until Ctrl-C is pressed.
+///
+/// The values are written to `output`, or to stdout if no file is given.
+pub(crate) fn trace_u32_on_target(
+    shared_options: &SharedOptions,
+    locations: &[u32],
+    interval: Duration,
+    format: TraceFormat,
+    output: Option<PathBuf>,
+    resume: bool,
+) -> Result<(), CliError> {
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+
+    if format == TraceFormat::Csv {
+        write!(writer, "time_ms")?;
+        for location in locations {
+            write!(writer, ",0x{:08x}", location)?;
+        }
+        writeln!(writer)?;
+    }
+
+    let mut values = vec![0u32; locations.len()];
+
+    let start = Instant::now();
+
+    with_device(shared_options, |mut session| {
+        let mut next_poll = start;
+
+        while !interrupted() {
+            let instant = start.elapsed().as_millis() as u32;
+
+            for (value, location) in values.iter_mut().zip(locations) {
+                *value = session.probe.read32(*location)?;
+            }
+
+            write_values(&mut writer, format, instant, locations, &values)?;
+            writer.flush()?;
+
+            // Schedule the next read, skipping polls which were missed because reading took too long.
+            next_poll += interval;
+            let now = Instant::now();
+            while next_poll < now {
+                next_poll += interval;
+            }
+            sleep(next_poll - now);
+        }
+
+        session.detach(resume)?;
+
+        Ok(())
+    })
+}
+
+fn write_values(
+    writer: &mut dyn Write,
+    format: TraceFormat,
+    instant: u32,
+    locations: &[u32],
+    values: &[u32],
+) -> io::Result<()> {
+    match format {
+        TraceFormat::Binary => {
+            for value in values {
+                writer.write_all(&instant.to_le_bytes())?;
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        TraceFormat::Csv => {
+            write!(writer, "{}", instant)?;
+            for value in values {
+                write!(writer, ",{}", value)?;
+            }
+            writeln!(writer)?;
+        }
+        TraceFormat::Json => {
+            write!(writer, "{{\"time_ms\":{},\"values\":{{", instant)?;
+            for (i, (location, value)) in locations.iter().zip(values).enumerate() {
+                if i > 0 {
+                    write!(writer, ",")?;
+                }
+                write!(writer, "\"0x{:08x}\":{}", location, value)?;
+            }
+            writeln!(writer, "}}}}")?;
+        }
+    }
+
+    Ok(())
+}
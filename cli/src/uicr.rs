@@ -0,0 +1,82 @@
+use crate::common::{with_device, CliError};
+use crate::SharedOptions;
+
+use probe_rs::{probe::flash::config_region::ConfigRegion, session::Session};
+
+use colored::*;
+
+/// Returns the configuration region of the target of the session.
+fn config_region(session: &Session) -> Result<ConfigRegion, CliError> {
+    ConfigRegion::for_target(&session.target).ok_or_else(|| {
+        CliError::InvalidArgument(format!(
+            "{} has no supported configuration region.",
+            session.target.name
+        ))
+    })
+}
+
+/// Prints the words of the configuration region, or of `length` bytes of it at `address`.
+pub(crate) fn read(
+    shared_options: &SharedOptions,
+    address: Option<u32>,
+    length: Option<u32>,
+) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let region = config_region(&session)?;
+        let address = address.unwrap_or(region.range.start);
+        let length = length.unwrap_or(region.range.end.saturating_sub(address));
+
+        let mut data = vec![0; length as usize];
+        region.read(&mut session.probe, address, &mut data)?;
+
+        println!(
+            "{} of {}",
+            region.kind.to_string().bold(),
+            session.target.name
+        );
+        for (i, word) in data.chunks(4).enumerate() {
+            let bytes: Vec<_> = word.iter().map(|byte| format!("{:02x}", byte)).collect();
+            println!("0x{:08x}: {}", address + i as u32 * 4, bytes.join(" "));
+        }
+
+        Ok(())
+    })
+}
+
+/// Programs little endian words into the configuration region.
+pub(crate) fn write(
+    shared_options: &SharedOptions,
+    address: u32,
+    words: &[u32],
+) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let region = config_region(&session)?;
+        let data: Vec<u8> = words
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect();
+
+        region.write(&mut session.probe, address, &data)?;
+
+        println!(
+            "Wrote {} bytes to the {} at 0x{:08x}.",
+            data.len(),
+            region.kind,
+            address
+        );
+
+        Ok(())
+    })
+}
+
+pub(crate) fn erase(shared_options: &SharedOptions) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let region = config_region(&session)?;
+
+        region.erase(&mut session.probe)?;
+
+        println!("Erased the {}.", region.kind);
+
+        Ok(())
+    })
+}
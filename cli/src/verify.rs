@@ -0,0 +1,121 @@
+use crate::common::{with_device, CliError};
+use crate::{file_format, SharedOptions};
+
+use probe_rs::{
+    memory::MI,
+    probe::flash::download::{read_flash_image, Format},
+};
+
+use std::path::Path;
+
+/// Memory is read back in blocks of this size, so mismatches are reported before all of a
+/// large image is read.
+const READ_BLOCK_SIZE: usize = 4096;
+
+/// The bytes of the target memory which differ from a file.
+#[derive(Debug, Default, PartialEq)]
+struct Mismatches {
+    /// The first mismatching bytes, as their address, the byte of the file and the byte read.
+    listed: Vec<(u32, u8, u8)>,
+    /// The address of the first mismatching byte, also if none are listed.
+    first: Option<u32>,
+    /// The number of mismatching bytes, including those not listed.
+    count: usize,
+}
+
+impl Mismatches {
+    /// Compares the data read at `address` with the data of the file, listing at most `max`
+    /// mismatching bytes.
+    fn compare(&mut self, address: u32, expected: &[u8], actual: &[u8], max: usize) {
+        for (offset, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+            if expected != actual {
+                let address = address + offset as u32;
+
+                if self.listed.len() < max {
+                    self.listed.push((address, *expected, *actual));
+                }
+                self.first.get_or_insert(address);
+                self.count += 1;
+            }
+        }
+    }
+}
+
+/// Reads back the memory the file at `path` places data in and compares it with the file,
+/// e.g. to check whether a device already runs a release.
+///
+/// The first `max_mismatches` differing bytes are listed.
+pub(crate) fn verify(
+    shared_options: &SharedOptions,
+    path: &Path,
+    format: Option<Format>,
+    max_mismatches: usize,
+) -> Result<(), CliError> {
+    let format = file_format(path, format)?;
+
+    with_device(shared_options, |mut session| {
+        let image = read_flash_image(path, format, &session.target.memory_map)?;
+        let size: usize = image.iter().map(|(_, data)| data.len()).sum();
+        let mut mismatches = Mismatches::default();
+        let mut buffer = vec![0; READ_BLOCK_SIZE];
+
+        for (address, data) in &image {
+            for (index, expected) in data.chunks(READ_BLOCK_SIZE).enumerate() {
+                let block_address = address + (index * READ_BLOCK_SIZE) as u32;
+                let actual = &mut buffer[..expected.len()];
+
+                session.probe.read_block8(block_address, actual)?;
+                mismatches.compare(block_address, expected, actual, max_mismatches);
+            }
+        }
+
+        for (address, expected, actual) in &mismatches.listed {
+            println!(
+                "0x{:08x}: expected 0x{:02x}, read 0x{:02x}",
+                address, expected, actual
+            );
+        }
+
+        match mismatches.first {
+            None => {
+                println!(
+                    "The {} bytes of {} match the target memory.",
+                    size,
+                    path.display()
+                );
+                Ok(())
+            }
+            Some(address) => {
+                println!(
+                    "{} of the {} bytes of {} differ from the target memory.",
+                    mismatches.count,
+                    size,
+                    path.display()
+                );
+                Err(CliError::VerificationFailed(address))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::Mismatches;
+
+    #[test]
+    fn lists_the_first_mismatches() {
+        let mut mismatches = Mismatches::default();
+
+        mismatches.compare(0x100, &[1, 2, 3, 4], &[1, 0, 3, 0], 3);
+        mismatches.compare(0x200, &[5, 6], &[0, 0], 3);
+
+        assert_eq!(
+            mismatches,
+            Mismatches {
+                listed: vec![(0x101, 2, 0), (0x103, 4, 0), (0x200, 5, 0)],
+                first: Some(0x101),
+                count: 4,
+            }
+        );
+    }
+}
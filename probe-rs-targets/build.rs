@@ -3,7 +3,11 @@ use std::fs::{read_dir, read_to_string, File};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
-use probe_rs::{probe::flash::FlashAlgorithm, target::Target};
+use flate2::{write::DeflateEncoder, Compression};
+use probe_rs::{
+    probe::flash::FlashAlgorithm,
+    target::{description::TargetDescription, inheritance, quirks, Target},
+};
 
 fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
@@ -47,38 +51,101 @@ fn main() {
     dbg!(&algorithm_names);
     dbg!(&algorithm_files);
 
+    // BASES
+    let mut files = vec![];
+    visit_dirs(Path::new("bases"), &mut files).unwrap();
+
+    let mut bases = vec![];
+
+    for file in files {
+        let string = read_to_string(&file)
+            .expect("Base definition file could not be read. This is a bug. Please report it.");
+        let name = file
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .expect("Non UTF-8 Filename!")
+            .to_owned();
+
+        bases.push((
+            name,
+            string,
+            root_dir.join(&file).to_str().unwrap().to_owned(),
+        ));
+    }
+
+    let base = |name: &str| {
+        bases
+            .iter()
+            .find(|(base, _, _)| base == name)
+            .map(|(_, definition, _)| definition.clone())
+    };
+    let base_names: Vec<_> = bases.iter().map(|(name, _, _)| name).collect();
+    let base_files: Vec<_> = bases.iter().map(|(_, _, file)| file).collect();
+
     // TARGETS
+    // The targets are stored with their bases merged in, as JSON compressed one by one, so
+    // only the selected target is decompressed and parsed. The index tells them apart by
+    // name and by the IDs read from the ROM table without parsing them.
     let mut files = vec![];
     visit_dirs(Path::new("targets"), &mut files).unwrap();
 
-    let mut target_names = vec![];
-    let mut target_files = vec![];
+    let mut blob = vec![];
+    let mut index = vec![];
 
     for file in files {
         let string = read_to_string(&file)
             .expect("Chip definition file could not be read. This is a bug. Please report it.");
-        match Target::new(&string) {
-            Ok(target) => {
-                if let Some(algo) = target.flash_algorithm {
-                    assert!(
-                        algorithm_names.contains(&algo),
-                        "Algorithm {} does not exist.",
-                        algo
-                    );
-                }
-
-                target_files.push(root_dir.join(file).to_str().unwrap().to_owned());
-
-                target_names.push(target.name.to_ascii_lowercase());
-            }
-            Err(e) => {
-                panic!("Failed to parse target file: {:?} because:\n{}", file, e);
-            }
+        let diagnostics = TargetDescription::new(string.as_str()).validate(
+            |algorithm| algorithm_names.iter().any(|name| name == algorithm),
+            &base,
+        );
+        if !diagnostics.is_empty() {
+            let diagnostics: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+            panic!(
+                "Invalid target file: {:?}:\n{}",
+                file,
+                diagnostics.join("\n")
+            );
         }
+
+        // The stored JSON is parsed the way the crate parses it, so it is known to work.
+        let json = inheritance::resolve(&string, &base)
+            .map(|description| serde_json::to_string(&description).unwrap())
+            .unwrap_or_else(|e| panic!("Failed to parse target file: {:?} because:\n{}", file, e));
+        let target = Target::new(&json)
+            .unwrap_or_else(|e| panic!("Failed to parse target file: {:?} because:\n{}", file, e));
+
+        let mut encoder = DeflateEncoder::new(vec![], Compression::best());
+        encoder.write_all(json.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let name = target.name.to_ascii_lowercase();
+        let cc = target.manufacturer.cc;
+        let id = target.manufacturer.id;
+        let part = target.part;
+        let dpidr = match quirks::dpidr(&target.quirks) {
+            Some(dpidr) => quote::quote!(Some(#dpidr)),
+            None => quote::quote!(None),
+        };
+        let offset = blob.len();
+        let length = compressed.len();
+        index.push(quote::quote! {
+            TargetEntry {
+                name: #name,
+                manufacturer: (#cc, #id),
+                part: #part,
+                dpidr: #dpidr,
+                offset: #offset,
+                length: #length,
+            }
+        });
+
+        blob.extend(compressed);
     }
 
-    dbg!(&target_names);
-    dbg!(&target_files);
+    File::create(Path::new(&out_dir).join("targets.bin"))
+        .and_then(|mut file| file.write_all(&blob))
+        .expect("Writing the targets failed.");
 
     let stream: String = format!(
         "{}",
@@ -89,10 +156,14 @@ fn main() {
                     #((#algorithm_names, include_str!(#algorithm_files)),)*
                 ].into_iter().collect();
 
-                static ref TARGETS: HashMap<&'static str, &'static str> = vec![
-                    #((#target_names, include_str!(#target_files)),)*
+                static ref BASES: HashMap<&'static str, &'static str> = vec![
+                    #((#base_names, include_str!(#base_files)),)*
                 ].into_iter().collect();
             }
+
+            static TARGET_INDEX: &[TargetEntry] = &[#(#index),*];
+
+            static TARGETS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/targets.bin"));
         // END QUOTE
         }
     );
@@ -1,30 +1,87 @@
 use std::collections::HashMap;
+use std::io::Read;
 
+use flate2::read::DeflateDecoder;
 use probe_rs::{
     collection,
     probe::flash::flasher::{AlgorithmSelectionError, FlashAlgorithm},
-    target::{info::ChipInfo, Target, TargetSelectionError},
+    session_builder::TargetSource,
+    target::{info::ChipInfo, Target, TargetParseError, TargetSelectionError},
 };
 
+/// A built-in target in the index generated by the build script.
+///
+/// The targets are stored with their bases merged in, as JSON compressed one by one at
+/// `offset` in `TARGETS`. The IDs read from the ROM table are in the index, so detecting a
+/// chip only decompresses and parses its target.
+struct TargetEntry {
+    /// The name of the target in lowercase.
+    name: &'static str,
+    /// The JEP106 continuation code and ID of the manufacturer.
+    manufacturer: (u8, u8),
+    part: u16,
+    /// The DPIDR of `Quirk::Dpidr`, if the target has the quirk.
+    dpidr: Option<u32>,
+    offset: usize,
+    length: usize,
+}
+
+impl TargetEntry {
+    fn parse(&self) -> Result<Target, TargetParseError> {
+        let mut definition = String::new();
+        DeflateDecoder::new(&TARGETS[self.offset..self.offset + self.length])
+            .read_to_string(&mut definition)
+            .expect("The built-in targets are corrupted. This is a bug. Please report it.");
+
+        // JSON is YAML, so the definition is parsed like the YAML files.
+        Target::new(&definition)
+    }
+
+    fn is_chip(&self, chip_info: &ChipInfo) -> bool {
+        self.manufacturer == (chip_info.manufacturer.cc, chip_info.manufacturer.id)
+            && self.part == chip_info.part
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/targets.rs"));
 
+/// Returns the built-in base definition `name`, which targets can inherit from.
+pub fn get_built_in_base(name: impl AsRef<str>) -> Option<&'static str> {
+    BASES.get(name.as_ref()).cloned()
+}
+
 pub fn get_built_in_target(name: impl AsRef<str>) -> Result<Target, TargetSelectionError> {
     let name = name.as_ref().to_string().to_ascii_lowercase();
-    TARGETS
-        .get(&name[..])
+    TARGET_INDEX
+        .iter()
+        .find(|entry| entry.name == name)
         .ok_or(TargetSelectionError::TargetNotFound(name))
-        .and_then(|target| Target::new(target).map_err(From::from))
+        .and_then(|entry| entry.parse().map_err(From::from))
+}
+
+/// Returns all built-in targets.
+pub fn get_built_in_targets() -> Vec<Target> {
+    TARGET_INDEX
+        .iter()
+        .map(|entry| entry.parse().unwrap())
+        .collect()
 }
 
+/// Returns the built-in target of the chip. Clones which copy the ROM table of the part they
+/// are a clone of are told apart by the DPIDR of their debug port, see `Quirk::Dpidr`.
 pub fn get_built_in_target_by_chip_id(chip_info: &ChipInfo) -> Option<Target> {
-    for target in TARGETS.values() {
-        let target = Target::new(target).unwrap();
-        if target.manufacturer == chip_info.manufacturer && target.part == chip_info.part {
-            return Some(target);
+    let mut found = None;
+
+    for entry in TARGET_INDEX.iter().filter(|entry| entry.is_chip(chip_info)) {
+        match entry.dpidr {
+            Some(dpidr) if Some(dpidr) == chip_info.dpidr => return Some(entry.parse().unwrap()),
+            Some(_) => (),
+            None if found.is_none() => found = Some(entry),
+            None => (),
         }
     }
 
-    None
+    found.map(|entry| entry.parse().unwrap())
 }
 
 pub enum SelectionStrategy {
@@ -36,7 +93,9 @@ pub fn select_target(strategy: &SelectionStrategy) -> Result<Target, TargetSelec
     match strategy {
         SelectionStrategy::Name(name) => match collection::get_target(name) {
             Some(target) => Ok(target),
-            None => get_built_in_target(name),
+            None => {
+                get_built_in_target(name).or_else(|error| get_target_by_family(name).ok_or(error))
+            }
         },
         SelectionStrategy::ChipInfo(chip_info) => {
             get_built_in_target_by_chip_id(&chip_info).ok_or(TargetSelectionError::TargetNotFound(
@@ -46,6 +105,62 @@ pub fn select_target(strategy: &SelectionStrategy) -> Result<Target, TargetSelec
     }
 }
 
+/// Returns the registered or built-in target that covers the chip `name` most specifically.
+///
+/// This finds the definition of a family when there is none for the exact variant, e.g.
+/// `STM32F429xI` for `stm32f429zi`. The name of the returned target tells which was chosen.
+pub fn get_target_by_family(name: impl AsRef<str>) -> Option<Target> {
+    let mut best: Option<(usize, Target)> = None;
+
+    for target in collection::get_targets() {
+        if let Some(score) = family_match(&target.name, name.as_ref()) {
+            if best.as_ref().map_or(true, |(best, _)| score > *best) {
+                best = Some((score, target));
+            }
+        }
+    }
+
+    // Only the best matching built-in target is parsed.
+    let mut best_built_in: Option<(usize, &TargetEntry)> = None;
+    for entry in TARGET_INDEX {
+        if let Some(score) = family_match(entry.name, name.as_ref()) {
+            if best_built_in.map_or(true, |(best, _)| score > best) {
+                best_built_in = Some((score, entry));
+            }
+        }
+    }
+
+    match (best, best_built_in) {
+        (Some((score, _)), Some((built_in, entry))) if built_in > score => entry.parse().ok(),
+        (None, Some((_, entry))) => entry.parse().ok(),
+        (best, _) => best.map(|(_, target)| target),
+    }
+}
+
+/// Returns how specifically the target definition `definition` matches the chip `name`,
+/// as the number of characters matched literally, or `None` if it does not match.
+///
+/// Vendors name the variants of a chip by appending package, temperature range and memory
+/// size codes to the name of the family. Definitions covering several variants use `x` for
+/// the characters that vary. So a definition matches a chip if it is a prefix of the chip name,
+/// with `x` matching any character, or no character at the end of the name.
+fn family_match(definition: &str, name: &str) -> Option<usize> {
+    let definition = definition.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+    let mut name = name.chars();
+    let mut literal = 0;
+
+    for d in definition.chars() {
+        match name.next() {
+            Some(n) if n == d => literal += 1,
+            _ if d == 'x' => (),
+            _ => return None,
+        }
+    }
+
+    Some(literal)
+}
+
 pub fn get_built_in_algorithm(
     name: impl AsRef<str>,
 ) -> Result<FlashAlgorithm, AlgorithmSelectionError> {
@@ -66,3 +181,54 @@ pub fn select_algorithm(name: impl AsRef<str>) -> Result<FlashAlgorithm, Algorit
         None => get_built_in_algorithm(name),
     }
 }
+
+/// The registered and the built-in targets and flash algorithms, for opening sessions with
+/// `Session::builder().targets(BuiltInTargets)`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BuiltInTargets;
+
+impl TargetSource for BuiltInTargets {
+    fn target(&self, name: &str) -> Result<Target, TargetSelectionError> {
+        select_target(&SelectionStrategy::Name(name.to_string()))
+    }
+
+    fn detect(&self, chip_info: &ChipInfo) -> Result<Target, TargetSelectionError> {
+        get_built_in_target_by_chip_id(chip_info).ok_or_else(|| {
+            TargetSelectionError::TargetNotFound(format!(
+                "No target info found for device: {}",
+                chip_info
+            ))
+        })
+    }
+
+    fn algorithm(&self, name: &str) -> Result<FlashAlgorithm, AlgorithmSelectionError> {
+        select_algorithm(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{family_match, TARGET_INDEX};
+
+    #[test]
+    fn matches_families() {
+        assert_eq!(family_match("STM32F103xx", "stm32f103c8"), Some(9));
+        assert_eq!(family_match("STM32F429xI", "STM32F429ZI"), Some(10));
+        assert_eq!(family_match("nRF52832", "nrf52832_xxaa"), Some(8));
+        assert_eq!(family_match("STM32F429xI", "stm32f429zg"), None);
+        assert_eq!(family_match("nRF52832", "nrf52"), None);
+    }
+
+    #[test]
+    fn indexes_the_stored_targets() {
+        for entry in TARGET_INDEX {
+            let target = entry.parse().unwrap();
+
+            assert_eq!(target.name.to_ascii_lowercase(), entry.name);
+            assert_eq!(
+                (target.manufacturer.cc, target.manufacturer.id, target.part),
+                (entry.manufacturer.0, entry.manufacturer.1, entry.part)
+            );
+        }
+    }
+}
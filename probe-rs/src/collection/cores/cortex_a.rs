@@ -0,0 +1,603 @@
+//! ARMv7-A cores, like the Cortex-A7 and A9 of the i.MX6 and Zynq-7000, and ARMv7-R cores,
+//! like the Cortex-R4 and R5 of TI Hercules chips and of the RPU of the Zynq UltraScale+,
+//! debugged through the memory mapped debug registers of the core.
+//!
+//! Unlike the debug registers of Cortex-M cores, these are not in the memory of the system but
+//! behind an APB-AP, at an address which depends on the chip. Once the core is halted, it
+//! executes ARM instructions written to DBGITR, and exchanges data with the debugger through
+//! the DCC registers DBGDTRRX and DBGDTRTX. Registers and the memory as seen by the core are
+//! accessed that way, with R0 and R1 as scratch registers which are restored afterwards.
+//!
+//! Both profiles share the ARMv7 debug architecture. Neither has the FPB and DWT of Cortex-M
+//! cores, breakpoints and stepping use the breakpoint register pairs of the debug registers.
+//! The tightly-coupled memories of Cortex-R cores are only seen by the core at their local
+//! addresses, so regions of them without an alias on the system bus are read through the core.
+
+use crate::event::HaltReason;
+use crate::memory::adi_v5_memory_interface::ADIMemoryInterface;
+use crate::probe::debug_probe::{CpuInformation, DebugProbeError, MasterProbe};
+use crate::probe::protocol::ResetType;
+use crate::target::{Architecture, BasicRegisterAddresses, Core, CoreRegisterAddress, CoreStatus};
+
+use std::time::{Duration, Instant};
+
+// Offsets of the debug registers.
+const DBGDIDR: u32 = 0x000;
+const DBGDTRRX: u32 = 0x080;
+const DBGITR: u32 = 0x084;
+const DBGDSCR: u32 = 0x088;
+const DBGDTRTX: u32 = 0x08C;
+const DBGDRCR: u32 = 0x090;
+const DBGBVR0: u32 = 0x100;
+const DBGBCR0: u32 = 0x140;
+const DBGOSLAR: u32 = 0x300;
+const DBGPRCR: u32 = 0x310;
+const DBGLAR: u32 = 0xFB0;
+
+/// Unlocks the debug registers for writes through DBGLAR.
+const LAR_KEY: u32 = 0xC5AC_CE55;
+
+const DSCR_HALTED: u32 = 1 << 0;
+const DSCR_RESTARTED: u32 = 1 << 1;
+/// The Method Of Entry field, why the core halted.
+const DSCR_MOE_SHIFT: u32 = 2;
+const DSCR_MOE_MASK: u32 = 0xF;
+const DSCR_SDABORT: u32 = 1 << 6;
+const DSCR_ADABORT: u32 = 1 << 7;
+const DSCR_UND: u32 = 1 << 8;
+const DSCR_ITREN: u32 = 1 << 13;
+const DSCR_HDBGEN: u32 = 1 << 14;
+const DSCR_INSTRCOMPL: u32 = 1 << 24;
+const DSCR_TXFULL: u32 = 1 << 29;
+
+const DRCR_HRQ: u32 = 1 << 0;
+const DRCR_RRQ: u32 = 1 << 1;
+const DRCR_CSE: u32 = 1 << 2;
+
+const PRCR_CORENPDRQ: u32 = 1 << 0;
+const PRCR_CWRR: u32 = 1 << 1;
+const PRCR_HCWR: u32 = 1 << 2;
+
+/// Breaks on an instruction at the address of DBGBVR in any mode, with all bytes of the word
+/// selected.
+const BCR_MATCH: u32 = (0xF << 5) | (0b11 << 1) | 1;
+/// Breaks on any instruction which is not at the address of DBGBVR, which steps the core.
+const BCR_MISMATCH: u32 = (0b0100 << 20) | BCR_MATCH;
+const BCR_ENABLE: u32 = 1;
+
+/// The Thumb state bit of the CPSR.
+const CPSR_T: u32 = 1 << 5;
+
+/// How long an instruction executed through DBGITR may take.
+const INSTRUCTION_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// `mcr p14, 0, rt, c0, c5, 0`, writes `rt` to DBGDTRTX.
+fn mcr_dtrtx(rt: u8) -> u32 {
+    0xEE00_0E15 | u32::from(rt) << 12
+}
+
+/// `mrc p14, 0, rt, c0, c5, 0`, reads DBGDTRRX into `rt`.
+fn mrc_dtrrx(rt: u8) -> u32 {
+    0xEE10_0E15 | u32::from(rt) << 12
+}
+
+// Instructions using the scratch registers.
+const MOV_R0_PC: u32 = 0xE1A0_000F;
+const MOV_PC_R0: u32 = 0xE1A0_F000;
+const MRS_R0_CPSR: u32 = 0xE10F_0000;
+const MSR_CPSR_R0: u32 = 0xE12F_F000;
+/// `ldr r1, [r0], #4`
+const LDR_R1_R0_POST: u32 = 0xE490_1004;
+/// `str r1, [r0], #4`
+const STR_R1_R0_POST: u32 = 0xE480_1004;
+/// `ldrb r1, [r0], #1`
+const LDRB_R1_R0_POST: u32 = 0xE4D0_1001;
+/// `strb r1, [r0], #1`
+const STRB_R1_R0_POST: u32 = 0xE4C0_1001;
+
+/// The CPSR, numbered after the general purpose registers.
+pub const CPSR: CoreRegisterAddress = CoreRegisterAddress(16);
+
+pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
+    R0: CoreRegisterAddress(0),
+    R1: CoreRegisterAddress(1),
+    R2: CoreRegisterAddress(2),
+    R3: CoreRegisterAddress(3),
+    R4: CoreRegisterAddress(4),
+    R9: CoreRegisterAddress(9),
+    PC: CoreRegisterAddress(15),
+    LR: CoreRegisterAddress(14),
+    SP: CoreRegisterAddress(13),
+    XPSR: CPSR,
+};
+
+/// The profile of an ARMv7 core.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Profile {
+    /// Application cores with an MMU.
+    A,
+    /// Real-time cores with an MPU and tightly-coupled memories.
+    R,
+}
+
+/// An ARMv7-A or ARMv7-R core with its debug registers at `debug_base` on the MEM-AP `ap`,
+/// usually an APB-AP.
+#[derive(Debug, Clone)]
+pub struct CortexA {
+    profile: Profile,
+    ap: u8,
+    debug_base: u32,
+}
+
+impl CortexA {
+    pub fn new(ap: u8, debug_base: u32) -> Self {
+        Self {
+            profile: Profile::A,
+            ap,
+            debug_base,
+        }
+    }
+
+    /// A Cortex-R4 or R5 core.
+    pub fn cortex_r(ap: u8, debug_base: u32) -> Self {
+        Self {
+            profile: Profile::R,
+            ap,
+            debug_base,
+        }
+    }
+
+    fn read_debug(&self, mi: &mut MasterProbe, offset: u32) -> Result<u32, DebugProbeError> {
+        Ok(ADIMemoryInterface::new(self.ap).read32(mi, self.debug_base + offset)?)
+    }
+
+    fn write_debug(
+        &self,
+        mi: &mut MasterProbe,
+        offset: u32,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        Ok(ADIMemoryInterface::new(self.ap).write32(mi, self.debug_base + offset, value)?)
+    }
+
+    /// Unlocks the debug registers and enables halting debug.
+    fn enable_debug(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        self.write_debug(mi, DBGLAR, LAR_KEY)?;
+        self.write_debug(mi, DBGOSLAR, 0)?;
+        self.write_debug(mi, DBGPRCR, PRCR_CORENPDRQ)?;
+
+        let dscr = self.read_debug(mi, DBGDSCR)?;
+        self.write_debug(mi, DBGDSCR, dscr | DSCR_HDBGEN)
+    }
+
+    /// Enables executing instructions through DBGITR, after the core halted.
+    fn set_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        let dscr = self.read_debug(mi, DBGDSCR)?;
+        self.write_debug(mi, DBGDSCR, dscr | DSCR_ITREN)?;
+        mi.set_core_halted(true);
+
+        Ok(())
+    }
+
+    /// Executes `instruction` on the halted core and waits until it completed.
+    fn execute(&self, mi: &mut MasterProbe, instruction: u32) -> Result<(), DebugProbeError> {
+        self.write_debug(mi, DBGITR, instruction)?;
+
+        let start = Instant::now();
+        loop {
+            let dscr = self.read_debug(mi, DBGDSCR)?;
+            if dscr & DSCR_INSTRCOMPL != 0 {
+                return Ok(());
+            }
+            if start.elapsed() > INSTRUCTION_TIMEOUT {
+                return Err(DebugProbeError::Timeout);
+            }
+        }
+    }
+
+    /// Checks whether the instructions executed since the last check aborted or were
+    /// undefined, and clears the sticky flags.
+    fn check_exceptions(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        let dscr = self.read_debug(mi, DBGDSCR)?;
+        if dscr & (DSCR_SDABORT | DSCR_ADABORT | DSCR_UND) != 0 {
+            self.write_debug(mi, DBGDRCR, DRCR_CSE)?;
+            return Err(DebugProbeError::InstructionFailed);
+        }
+
+        Ok(())
+    }
+
+    fn read_gpr(&self, mi: &mut MasterProbe, register: u8) -> Result<u32, DebugProbeError> {
+        self.execute(mi, mcr_dtrtx(register))?;
+
+        if self.read_debug(mi, DBGDSCR)? & DSCR_TXFULL == 0 {
+            return Err(DebugProbeError::InstructionFailed);
+        }
+        self.read_debug(mi, DBGDTRTX)
+    }
+
+    fn write_gpr(
+        &self,
+        mi: &mut MasterProbe,
+        register: u8,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.write_debug(mi, DBGDTRRX, value)?;
+        self.execute(mi, mrc_dtrrx(register))
+    }
+
+    /// Runs `f`, which uses R0 to move a value, and restores R0 afterwards.
+    fn with_r0<T>(
+        &self,
+        mi: &mut MasterProbe,
+        f: impl FnOnce(&mut MasterProbe) -> Result<T, DebugProbeError>,
+    ) -> Result<T, DebugProbeError> {
+        let r0 = self.read_gpr(mi, 0)?;
+        let result = f(mi);
+        self.write_gpr(mi, 0, r0)?;
+
+        result
+    }
+
+    /// The number of breakpoint register pairs. The last one is used for stepping.
+    fn breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError> {
+        Ok(((self.read_debug(mi, DBGDIDR)? >> 24) & 0xF) + 1)
+    }
+
+    fn find_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<u32, DebugProbeError> {
+        for unit in 0..self.get_available_breakpoint_units(mi)? {
+            if self.read_debug(mi, DBGBVR0 + unit * 4)? == addr & !3 {
+                return Ok(unit);
+            }
+        }
+
+        Err(DebugProbeError::BreakpointAddressUnsupported(addr))
+    }
+
+    /// Reads or writes the memory through the core, which sees it through its MMU and caches,
+    /// and sees the tightly-coupled memories of Cortex-R cores. The data is moved word by word
+    /// if `word` is set, byte by byte otherwise.
+    fn access_memory(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u32],
+        word: bool,
+        write: bool,
+    ) -> Result<(), DebugProbeError> {
+        let r0 = self.read_gpr(mi, 0)?;
+        let r1 = self.read_gpr(mi, 1)?;
+
+        let result = (|| {
+            self.write_gpr(mi, 0, address)?;
+            for value in data.iter_mut() {
+                match (write, word) {
+                    (false, true) => self.execute(mi, LDR_R1_R0_POST)?,
+                    (false, false) => self.execute(mi, LDRB_R1_R0_POST)?,
+                    (true, _) => self.write_gpr(mi, 1, *value)?,
+                }
+                match (write, word) {
+                    (false, _) => *value = self.read_gpr(mi, 1)?,
+                    (true, true) => self.execute(mi, STR_R1_R0_POST)?,
+                    (true, false) => self.execute(mi, STRB_R1_R0_POST)?,
+                }
+            }
+            self.check_exceptions(mi)
+        })();
+
+        self.write_gpr(mi, 0, r0)?;
+        self.write_gpr(mi, 1, r1)?;
+        result
+    }
+
+    /// Reads words from the memory as seen by the halted core.
+    pub fn read_block32(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u32],
+    ) -> Result<(), DebugProbeError> {
+        if address % 4 != 0 {
+            return Err(DebugProbeError::DataAlignmentError);
+        }
+
+        self.access_memory(mi, address, data, true, false)
+    }
+
+    /// Writes words to the memory as seen by the halted core.
+    pub fn write_block32(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        if address % 4 != 0 {
+            return Err(DebugProbeError::DataAlignmentError);
+        }
+
+        let mut data = data.to_vec();
+        self.access_memory(mi, address, &mut data, true, true)
+    }
+}
+
+impl Core for CortexA {
+    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        let start = Instant::now();
+        loop {
+            if self.read_debug(mi, DBGDSCR)? & DSCR_HALTED != 0 {
+                return self.set_halted(mi);
+            }
+            if start.elapsed() > mi.retry_policy().halt_timeout {
+                return Err(DebugProbeError::Timeout);
+            }
+            mi.cancellation_token().check()?;
+        }
+    }
+
+    fn status(&self, mi: &mut MasterProbe) -> Result<CoreStatus, DebugProbeError> {
+        let dscr = self.read_debug(mi, DBGDSCR)?;
+        if dscr & DSCR_HALTED == 0 {
+            return Ok(CoreStatus::Running);
+        }
+
+        let reason = match (dscr >> DSCR_MOE_SHIFT) & DSCR_MOE_MASK {
+            0b0000 => HaltReason::Request,
+            // Breakpoints of the debug registers and BKPT instructions.
+            0b0001 | 0b0011 => HaltReason::Breakpoint,
+            // Asynchronous and synchronous watchpoints.
+            0b0010 | 0b1010 => HaltReason::Watchpoint,
+            0b0100 => HaltReason::External,
+            0b0101 => HaltReason::VectorCatch,
+            _ => HaltReason::Unknown,
+        };
+
+        Ok(CoreStatus::Halted(reason))
+    }
+
+    fn halt(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
+        self.enable_debug(mi)?;
+        self.write_debug(mi, DBGDRCR, DRCR_HRQ)?;
+        self.wait_for_core_halted(mi)?;
+
+        let pc = self.read_core_reg(mi, REGISTERS.PC)?;
+        Ok(CpuInformation { pc })
+    }
+
+    fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.set_core_halted(false);
+
+        let dscr = self.read_debug(mi, DBGDSCR)?;
+        self.write_debug(mi, DBGDSCR, dscr & !DSCR_ITREN)?;
+        self.write_debug(mi, DBGDRCR, DRCR_RRQ | DRCR_CSE)?;
+
+        let start = Instant::now();
+        while self.read_debug(mi, DBGDSCR)? & DSCR_RESTARTED == 0 {
+            if start.elapsed() > INSTRUCTION_TIMEOUT {
+                return Err(DebugProbeError::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Requests a warm reset of the core, which does not reset the debug logic.
+    fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.set_core_halted(false);
+        self.write_debug(mi, DBGPRCR, PRCR_CORENPDRQ | PRCR_CWRR)
+    }
+
+    fn reset_with(
+        &self,
+        mi: &mut MasterProbe,
+        reset_type: ResetType,
+    ) -> Result<(), DebugProbeError> {
+        match reset_type {
+            ResetType::Hardware => {
+                mi.set_core_halted(false);
+                mi.target_reset()
+            }
+            ResetType::SysResetReq => self.reset(mi),
+            ResetType::VectReset | ResetType::Core => {
+                Err(DebugProbeError::ResetTypeUnsupported(reset_type))
+            }
+        }
+    }
+
+    /// Holds the core in reset while requesting it to halt, so it halts at its first
+    /// instruction when the reset is released.
+    fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.set_core_halted(false);
+        self.enable_debug(mi)?;
+
+        self.write_debug(mi, DBGPRCR, PRCR_CORENPDRQ | PRCR_HCWR | PRCR_CWRR)?;
+        self.write_debug(mi, DBGDRCR, DRCR_HRQ)?;
+        self.write_debug(mi, DBGPRCR, PRCR_CORENPDRQ)?;
+
+        self.wait_for_core_halted(mi)
+    }
+
+    fn step(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
+        let pc = self.read_core_reg(mi, REGISTERS.PC)?;
+        let unit = self.breakpoint_units(mi)? - 1;
+
+        self.write_debug(mi, DBGBVR0 + unit * 4, pc & !3)?;
+        self.write_debug(mi, DBGBCR0 + unit * 4, BCR_MISMATCH)?;
+        self.run(mi)?;
+        let halted = self.wait_for_core_halted(mi);
+        self.write_debug(mi, DBGBCR0 + unit * 4, 0)?;
+        halted?;
+
+        let pc = self.read_core_reg(mi, REGISTERS.PC)?;
+        Ok(CpuInformation { pc })
+    }
+
+    fn read_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+    ) -> Result<u32, DebugProbeError> {
+        match addr.0 {
+            register @ 0..=14 => self.read_gpr(mi, register),
+            // Reading the PC returns the address of the instruction plus 8 in ARM state, or
+            // plus 4 in Thumb state.
+            15 => {
+                let (pc, cpsr) = self.with_r0(mi, |mi| {
+                    self.execute(mi, MOV_R0_PC)?;
+                    let pc = self.read_gpr(mi, 0)?;
+                    self.execute(mi, MRS_R0_CPSR)?;
+                    Ok((pc, self.read_gpr(mi, 0)?))
+                })?;
+                Ok(if cpsr & CPSR_T != 0 {
+                    pc.wrapping_sub(4)
+                } else {
+                    pc.wrapping_sub(8)
+                })
+            }
+            16 => self.with_r0(mi, |mi| {
+                self.execute(mi, MRS_R0_CPSR)?;
+                self.read_gpr(mi, 0)
+            }),
+            _ => Err(DebugProbeError::UnknownError),
+        }
+    }
+
+    fn write_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        match addr.0 {
+            register @ 0..=14 => self.write_gpr(mi, register, value),
+            15 => {
+                let cpsr = self.read_core_reg(mi, CPSR)?;
+                let pc = if cpsr & CPSR_T != 0 {
+                    value | 1
+                } else {
+                    value & !3
+                };
+                self.with_r0(mi, |mi| {
+                    self.write_gpr(mi, 0, pc)?;
+                    self.execute(mi, MOV_PC_R0)
+                })
+            }
+            16 => self.with_r0(mi, |mi| {
+                self.write_gpr(mi, 0, value)?;
+                self.execute(mi, MSR_CPSR_R0)
+            }),
+            _ => Err(DebugProbeError::UnknownError),
+        }
+    }
+
+    fn get_available_breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError> {
+        Ok(self.breakpoint_units(mi)? - 1)
+    }
+
+    /// The breakpoints are always active while halting debug is enabled, only disabling
+    /// clears them.
+    fn enable_breakpoints(&self, mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError> {
+        if !state {
+            for unit in 0..self.breakpoint_units(mi)? {
+                self.write_debug(mi, DBGBCR0 + unit * 4, 0)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        for unit in 0..self.get_available_breakpoint_units(mi)? {
+            if self.read_debug(mi, DBGBCR0 + unit * 4)? & BCR_ENABLE == 0 {
+                self.write_debug(mi, DBGBVR0 + unit * 4, addr & !3)?;
+                return self.write_debug(mi, DBGBCR0 + unit * 4, BCR_MATCH);
+            }
+        }
+
+        Err(DebugProbeError::BreakpointUnitsExhausted)
+    }
+
+    fn enable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        let unit = self.find_breakpoint(mi, addr)?;
+        self.write_debug(mi, DBGBCR0 + unit * 4, BCR_MATCH)
+    }
+
+    fn disable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        let unit = self.find_breakpoint(mi, addr)?;
+        self.write_debug(mi, DBGBCR0 + unit * 4, BCR_MATCH & !BCR_ENABLE)
+    }
+
+    fn read_block8(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), DebugProbeError> {
+        let mut values = vec![0; data.len()];
+        self.access_memory(mi, address, &mut values, false, false)?;
+        for (byte, value) in data.iter_mut().zip(values) {
+            *byte = value as u8;
+        }
+
+        Ok(())
+    }
+
+    fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
+        &REGISTERS
+    }
+
+    fn read_block32_through_core(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u32],
+    ) -> Option<Result<(), DebugProbeError>> {
+        Some((|| {
+            let running = self.read_debug(mi, DBGDSCR)? & DSCR_HALTED == 0;
+            if running {
+                self.halt(mi)?;
+            }
+
+            let result = self.read_block32(mi, address, data);
+            if running {
+                self.run(mi)?;
+            }
+            result
+        })())
+    }
+
+    fn detach(
+        &self,
+        mi: &mut MasterProbe,
+        _vector_catch: Option<u32>,
+        resume: bool,
+    ) -> Result<(), DebugProbeError> {
+        self.enable_breakpoints(mi, false)?;
+
+        if resume {
+            if self.read_debug(mi, DBGDSCR)? & DSCR_HALTED != 0 {
+                self.run(mi)?;
+            }
+
+            let dscr = self.read_debug(mi, DBGDSCR)?;
+            self.write_debug(mi, DBGDSCR, dscr & !DSCR_HDBGEN)?;
+        }
+
+        Ok(())
+    }
+
+    fn architecture(&self) -> Architecture {
+        Architecture::Arm
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{mcr_dtrtx, mrc_dtrrx};
+
+    #[test]
+    fn encodes_dcc_transfers() {
+        assert_eq!(mcr_dtrtx(0), 0xEE00_0E15);
+        assert_eq!(mcr_dtrtx(1), 0xEE00_1E15);
+        assert_eq!(mrc_dtrrx(13), 0xEE10_DE15);
+    }
+}
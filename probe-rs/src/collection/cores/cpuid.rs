@@ -0,0 +1,220 @@
+//! Identification of the Cortex-M core of a target by its CPUID register.
+//!
+//! Target descriptions only name a core implementation, and the M4 one is used for most
+//! targets. The CPUID tells which core is actually present, and a few more registers tell
+//! which optional features it was built with.
+
+use super::fpb;
+use super::mtb::Mtb;
+use crate::collection::get_core;
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+use crate::target::Core;
+
+use std::error::Error;
+use std::fmt;
+
+const CPUID: u32 = 0xE000_ED00;
+/// ID_ISAR3, whose SIMD field tells whether the DSP extension is present.
+const ID_ISAR3: u32 = 0xE000_ED6C;
+/// MVFR0, which reads as zero without a floating point unit.
+const MVFR0: u32 = 0xE000_EF40;
+
+const IMPLEMENTER_ARM: u32 = 0x41;
+
+#[derive(Debug)]
+pub enum CpuidError {
+    Access(AccessPortError),
+    DebugProbe(DebugProbeError),
+    /// The CPUID does not belong to a known Cortex-M core.
+    UnknownCore(u32),
+}
+
+impl Error for CpuidError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CpuidError::Access(e) => Some(e),
+            CpuidError::DebugProbe(e) => Some(e),
+            CpuidError::UnknownCore(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for CpuidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuidError::Access(e) => e.fmt(f),
+            CpuidError::DebugProbe(e) => e.fmt(f),
+            CpuidError::UnknownCore(cpuid) => {
+                write!(
+                    f,
+                    "The CPUID 0x{:08x} is not of a known Cortex-M core.",
+                    cpuid
+                )
+            }
+        }
+    }
+}
+
+impl From<AccessPortError> for CpuidError {
+    fn from(error: AccessPortError) -> Self {
+        CpuidError::Access(error)
+    }
+}
+
+impl From<DebugProbeError> for CpuidError {
+    fn from(error: DebugProbeError) -> Self {
+        CpuidError::DebugProbe(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CoreType {
+    M0,
+    M0Plus,
+    M3,
+    M4,
+    M7,
+    M23,
+    M33,
+}
+
+impl CoreType {
+    /// Returns the core with the given CPUID, which has to be one of ARM.
+    pub fn from_cpuid(cpuid: u32) -> Option<Self> {
+        if cpuid >> 24 != IMPLEMENTER_ARM {
+            return None;
+        }
+
+        match (cpuid >> 4) & 0xFFF {
+            0xC20 => Some(CoreType::M0),
+            0xC60 => Some(CoreType::M0Plus),
+            0xC23 => Some(CoreType::M3),
+            0xC24 => Some(CoreType::M4),
+            0xC27 => Some(CoreType::M7),
+            0xD20 => Some(CoreType::M23),
+            0xD21 => Some(CoreType::M33),
+            _ => None,
+        }
+    }
+
+    /// Whether the core implements the ARMv8-M architecture.
+    pub fn is_armv8m(self) -> bool {
+        matches!(self, CoreType::M23 | CoreType::M33)
+    }
+
+    /// Whether the core implements the baseline architecture of ARMv6-M or ARMv8-M, which
+    /// lacks many instructions and debug features of the mainline.
+    pub fn is_baseline(self) -> bool {
+        matches!(self, CoreType::M0 | CoreType::M0Plus | CoreType::M23)
+    }
+
+    /// Returns the implementation of the core.
+    pub fn core(self) -> Box<dyn Core> {
+        let name = if self.is_baseline() { "M0" } else { "M4" };
+
+        get_core(name).expect("The cores are registered in the collection.")
+    }
+}
+
+impl fmt::Display for CoreType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CoreType::M0 => "Cortex-M0",
+            CoreType::M0Plus => "Cortex-M0+",
+            CoreType::M3 => "Cortex-M3",
+            CoreType::M4 => "Cortex-M4",
+            CoreType::M7 => "Cortex-M7",
+            CoreType::M23 => "Cortex-M23",
+            CoreType::M33 => "Cortex-M33",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// The optional features a core was built with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Capabilities {
+    /// A single or double precision floating point unit, with the registers S0 to S31.
+    pub fpu: bool,
+    /// The DSP extension.
+    pub dsp: bool,
+    /// The number of hardware breakpoints.
+    pub breakpoints: u32,
+    /// A Micro Trace Buffer.
+    pub mtb: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CoreInfo {
+    pub core_type: CoreType,
+    /// The variant and the revision of the core, as in r0p1.
+    pub revision: (u8, u8),
+    pub capabilities: Capabilities,
+}
+
+impl CoreInfo {
+    /// Reads the CPUID and the feature registers of the core.
+    pub fn read(probe: &mut MasterProbe) -> Result<Self, CpuidError> {
+        let cpuid = probe.read32(CPUID)?;
+        let core_type = CoreType::from_cpuid(cpuid).ok_or(CpuidError::UnknownCore(cpuid))?;
+
+        let fpu = !core_type.is_baseline() && probe.read32(MVFR0)? != 0;
+        let dsp = match core_type {
+            CoreType::M4 | CoreType::M7 => true,
+            CoreType::M33 => (probe.read32(ID_ISAR3)? >> 4) & 0xF == 0b0011,
+            _ => false,
+        };
+        let breakpoints = fpb::available_units(probe)?;
+        let mtb = Mtb::find(probe).is_ok();
+
+        Ok(CoreInfo {
+            core_type,
+            revision: (((cpuid >> 20) & 0xF) as u8, (cpuid & 0xF) as u8),
+            capabilities: Capabilities {
+                fpu,
+                dsp,
+                breakpoints,
+                mtb,
+            },
+        })
+    }
+}
+
+impl fmt::Display for CoreInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} r{}p{}",
+            self.core_type, self.revision.0, self.revision.1
+        )?;
+
+        let features = [
+            (self.capabilities.fpu, "FPU"),
+            (self.capabilities.dsp, "DSP"),
+            (self.capabilities.mtb, "MTB"),
+        ];
+        for (_, name) in features.iter().filter(|(present, _)| *present) {
+            write!(f, ", {}", name)?;
+        }
+
+        write!(f, ", {} breakpoints", self.capabilities.breakpoints)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CoreType;
+
+    #[test]
+    fn identifies_cores() {
+        assert_eq!(CoreType::from_cpuid(0x410C_C601), Some(CoreType::M0Plus));
+        assert_eq!(CoreType::from_cpuid(0x410F_C241), Some(CoreType::M4));
+        assert_eq!(CoreType::from_cpuid(0x411F_D210), Some(CoreType::M33));
+        assert_eq!(CoreType::from_cpuid(0x410F_C271), Some(CoreType::M7));
+        assert_eq!(CoreType::from_cpuid(0x4100_C240), Some(CoreType::M4));
+        assert_eq!(CoreType::from_cpuid(0x690F_C241), None);
+    }
+}
@@ -0,0 +1,291 @@
+//! Access to the cycle and event counters of the Data Watchpoint and Trace unit (DWT).
+//!
+//! The counters run without any support from the target firmware,
+//! which makes them usable as a lightweight profiling tool.
+//!
+//! The comparators of the DWT are used as watchpoints, which halt the core when it accesses
+//! the watched memory.
+
+use super::WatchKind;
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::target::CoreRegister;
+
+use bitfield::bitfield;
+use std::error::Error;
+use std::fmt;
+
+bitfield! {
+    #[derive(Copy, Clone)]
+    pub struct DwtCtrl(u32);
+    impl Debug;
+    /// Set if the cycle counter is not implemented.
+    pub nocyccnt, _: 25;
+    /// Set if the profiling counters are not implemented.
+    pub noprfcnt, _: 24;
+    /// Enables the folded instruction counter.
+    pub foldevtena, set_foldevtena: 21;
+    /// Enables the load store unit counter.
+    pub lsuevtena, set_lsuevtena: 20;
+    /// Enables the sleep counter.
+    pub sleepevtena, set_sleepevtena: 19;
+    /// Enables the exception overhead counter.
+    pub excevtena, set_excevtena: 18;
+    /// Enables the CPI counter.
+    pub cpievtena, set_cpievtena: 17;
+    /// Enables exception trace packets.
+    pub exctrcena, set_exctrcena: 16;
+    /// Enables the cycle counter.
+    pub cyccntena, set_cyccntena: 0;
+}
+
+impl From<u32> for DwtCtrl {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DwtCtrl> for u32 {
+    fn from(value: DwtCtrl) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for DwtCtrl {
+    const ADDRESS: u32 = 0xE000_1000;
+    const NAME: &'static str = "DWT_CTRL";
+}
+
+/// Address of the debug exception and monitor control register.
+pub(crate) const DEMCR: u32 = 0xE000_EDFC;
+
+/// Global enable for the DWT and ITM, bit 24 of DEMCR.
+pub(crate) const DEMCR_TRCENA: u32 = 1 << 24;
+
+const DWT_CYCCNT: u32 = 0xE000_1004;
+const DWT_CPICNT: u32 = 0xE000_1008;
+const DWT_EXCCNT: u32 = 0xE000_100C;
+const DWT_SLEEPCNT: u32 = 0xE000_1010;
+const DWT_LSUCNT: u32 = 0xE000_1014;
+const DWT_FOLDCNT: u32 = 0xE000_1018;
+const DWT_PCSR: u32 = 0xE000_101C;
+
+/// The registers of comparator 0, those of the other comparators follow every 16 bytes.
+const DWT_COMP0: u32 = 0xE000_1020;
+const DWT_MASK0: u32 = 0xE000_1024;
+const DWT_FUNCTION0: u32 = 0xE000_1028;
+const DWT_COMPARATOR_STRIDE: u32 = 0x10;
+
+#[derive(Debug)]
+pub enum DwtError {
+    /// The core does not implement the cycle counter, e.g. on a Cortex-M0.
+    CycleCounterNotImplemented,
+    /// The DWT has fewer comparators than the given one.
+    ComparatorNotImplemented(usize),
+    /// The address of a watchpoint is not aligned to its size, or the size is not a power of
+    /// two.
+    UnalignedWatchpoint(u32),
+    AccessPort(AccessPortError),
+}
+
+impl Error for DwtError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DwtError::AccessPort(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DwtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DwtError::CycleCounterNotImplemented => {
+                write!(f, "The core does not implement the DWT cycle counter.")
+            }
+            DwtError::ComparatorNotImplemented(unit) => {
+                write!(f, "The DWT has no comparator {}.", unit)
+            }
+            DwtError::UnalignedWatchpoint(address) => write!(
+                f,
+                "The watchpoint at 0x{:08x} is not aligned to its size, or its size is not a power of two.",
+                address
+            ),
+            DwtError::AccessPort(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<AccessPortError> for DwtError {
+    fn from(e: AccessPortError) -> Self {
+        DwtError::AccessPort(e)
+    }
+}
+
+/// A snapshot of the DWT counters.
+///
+/// The cycle counter is 32 bits wide, all other counters are 8 bits wide.
+/// All of them wrap around silently.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct DwtCounters {
+    pub cycles: u32,
+    /// Additional cycles spent on multi-cycle instructions.
+    pub cpi: u8,
+    /// Cycles spent on exception entry and exit.
+    pub exception: u8,
+    /// Cycles spent sleeping.
+    pub sleep: u8,
+    /// Additional cycles spent on load and store instructions.
+    pub lsu: u8,
+    /// Instructions which took zero cycles.
+    pub folded: u8,
+}
+
+impl DwtCounters {
+    /// Returns the counter increments since `earlier`, taking wrap-around into account.
+    pub fn delta(&self, earlier: &DwtCounters) -> DwtCounters {
+        DwtCounters {
+            cycles: self.cycles.wrapping_sub(earlier.cycles),
+            cpi: self.cpi.wrapping_sub(earlier.cpi),
+            exception: self.exception.wrapping_sub(earlier.exception),
+            sleep: self.sleep.wrapping_sub(earlier.sleep),
+            lsu: self.lsu.wrapping_sub(earlier.lsu),
+            folded: self.folded.wrapping_sub(earlier.folded),
+        }
+    }
+}
+
+impl fmt::Display for DwtCounters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cycles: {}, cpi: {}, exception: {}, sleep: {}, lsu: {}, folded: {}",
+            self.cycles, self.cpi, self.exception, self.sleep, self.lsu, self.folded
+        )
+    }
+}
+
+/// Enables the cycle counter and, if implemented, the event counters.
+pub fn enable_counters(mi: &mut impl MI) -> Result<(), DwtError> {
+    let demcr = mi.read32(DEMCR)?;
+    mi.write32(DEMCR, demcr | DEMCR_TRCENA)?;
+
+    let mut ctrl = DwtCtrl(mi.read32(DwtCtrl::ADDRESS)?);
+
+    if ctrl.nocyccnt() {
+        return Err(DwtError::CycleCounterNotImplemented);
+    }
+
+    ctrl.set_cyccntena(true);
+
+    if !ctrl.noprfcnt() {
+        ctrl.set_cpievtena(true);
+        ctrl.set_excevtena(true);
+        ctrl.set_sleepevtena(true);
+        ctrl.set_lsuevtena(true);
+        ctrl.set_foldevtena(true);
+    }
+
+    mi.write32(DwtCtrl::ADDRESS, ctrl.into())?;
+
+    Ok(())
+}
+
+/// Reads the current values of all counters.
+pub fn read_counters(mi: &mut impl MI) -> Result<DwtCounters, DwtError> {
+    Ok(DwtCounters {
+        cycles: mi.read32(DWT_CYCCNT)?,
+        cpi: mi.read32(DWT_CPICNT)? as u8,
+        exception: mi.read32(DWT_EXCCNT)? as u8,
+        sleep: mi.read32(DWT_SLEEPCNT)? as u8,
+        lsu: mi.read32(DWT_LSUCNT)? as u8,
+        folded: mi.read32(DWT_FOLDCNT)? as u8,
+    })
+}
+
+/// Resets all counters to zero.
+pub fn reset_counters(mi: &mut impl MI) -> Result<(), DwtError> {
+    for address in &[
+        DWT_CYCCNT,
+        DWT_CPICNT,
+        DWT_EXCCNT,
+        DWT_SLEEPCNT,
+        DWT_LSUCNT,
+        DWT_FOLDCNT,
+    ] {
+        mi.write32(*address, 0)?;
+    }
+
+    Ok(())
+}
+
+/// Enables the DWT, which DWT_PCSR only delivers samples with.
+pub fn enable_pc_sampling(mi: &mut impl MI) -> Result<(), DwtError> {
+    let demcr = mi.read32(DEMCR)?;
+    mi.write32(DEMCR, demcr | DEMCR_TRCENA)?;
+
+    Ok(())
+}
+
+/// Samples the program counter of the running core through DWT_PCSR.
+///
+/// Returns `None` if no sample is available, for example because the core is halted. A
+/// sampling register which is not implemented reads as zero, which is also a valid sample.
+pub fn read_pc_sample(mi: &mut impl MI) -> Result<Option<u32>, DwtError> {
+    match mi.read32(DWT_PCSR)? {
+        0xFFFF_FFFF => Ok(None),
+        pc => Ok(Some(pc)),
+    }
+}
+
+/// Returns the number of comparators of the DWT.
+pub fn comparators(mi: &mut impl MI) -> Result<usize, DwtError> {
+    Ok((mi.read32(DwtCtrl::ADDRESS)? >> 28) as usize)
+}
+
+/// Sets comparator `unit` to halt the core on accesses of `kind` to the `len` bytes at
+/// `address`. `len` is a power of two, and `address` has to be aligned to it.
+///
+/// This uses the comparators as they are laid out on ARMv6-M and ARMv7-M cores.
+pub fn set_watchpoint(
+    mi: &mut impl MI,
+    unit: usize,
+    address: u32,
+    len: u32,
+    kind: WatchKind,
+) -> Result<(), DwtError> {
+    if unit >= comparators(mi)? {
+        return Err(DwtError::ComparatorNotImplemented(unit));
+    }
+    if !len.is_power_of_two() || address % len != 0 {
+        return Err(DwtError::UnalignedWatchpoint(address));
+    }
+
+    let demcr = mi.read32(DEMCR)?;
+    mi.write32(DEMCR, demcr | DEMCR_TRCENA)?;
+
+    let function = match kind {
+        WatchKind::Read => 0b0101,
+        WatchKind::Write => 0b0110,
+        WatchKind::ReadWrite => 0b0111,
+    };
+
+    let offset = unit as u32 * DWT_COMPARATOR_STRIDE;
+    mi.write32(DWT_COMP0 + offset, address)?;
+    // The mask is the number of low address bits which are ignored.
+    mi.write32(DWT_MASK0 + offset, len.trailing_zeros())?;
+    mi.write32(DWT_FUNCTION0 + offset, function)?;
+
+    Ok(())
+}
+
+/// Disables comparator `unit`.
+pub fn clear_watchpoint(mi: &mut impl MI, unit: usize) -> Result<(), DwtError> {
+    if unit >= comparators(mi)? {
+        return Err(DwtError::ComparatorNotImplemented(unit));
+    }
+
+    mi.write32(DWT_FUNCTION0 + unit as u32 * DWT_COMPARATOR_STRIDE, 0)?;
+
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! The fault status of Cortex-M cores, which tells why a core locked up.
+//!
+//! A core locks up when it faults while handling a HardFault or an NMI, or when it can not
+//! fetch the vector of the HardFault handler. It stops executing until it is reset, and looks
+//! unresponsive to everything but the debugger. ARMv6-M and ARMv8-M baseline cores only have
+//! the HardFault, and no registers telling its cause.
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::probe::debug_probe::MasterProbe;
+
+use std::fmt;
+
+/// The Configurable Fault Status Register, made of MMFSR, BFSR and UFSR.
+const CFSR: u32 = 0xE000_ED28;
+const HFSR: u32 = 0xE000_ED2C;
+const MMFAR: u32 = 0xE000_ED34;
+const BFAR: u32 = 0xE000_ED38;
+
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+/// The bits of CFSR, with the cause of the fault they stand for.
+const CFSR_CAUSES: [(u32, &str); 18] = [
+    (
+        1 << 0,
+        "instruction fetch from a protected address (IACCVIOL)",
+    ),
+    (1 << 1, "data access to a protected address (DACCVIOL)"),
+    (1 << 3, "MemManage fault while unstacking (MUNSTKERR)"),
+    (1 << 4, "MemManage fault while stacking (MSTKERR)"),
+    (
+        1 << 5,
+        "MemManage fault while preserving the FPU state (MLSPERR)",
+    ),
+    (1 << 8, "bus error on an instruction fetch (IBUSERR)"),
+    (1 << 9, "precise bus error on a data access (PRECISERR)"),
+    (
+        1 << 10,
+        "imprecise bus error on a data access (IMPRECISERR)",
+    ),
+    (1 << 11, "bus error while unstacking (UNSTKERR)"),
+    (1 << 12, "bus error while stacking (STKERR)"),
+    (1 << 13, "bus error while preserving the FPU state (LSPERR)"),
+    (1 << 16, "undefined instruction (UNDEFINSTR)"),
+    (
+        1 << 17,
+        "invalid state, e.g. a branch to an even address (INVSTATE)",
+    ),
+    (1 << 18, "invalid EXC_RETURN value (INVPC)"),
+    (
+        1 << 19,
+        "coprocessor access, e.g. to the disabled FPU (NOCP)",
+    ),
+    (1 << 20, "stack overflow (STKOF)"),
+    (1 << 24, "unaligned access (UNALIGNED)"),
+    (1 << 25, "division by zero (DIVBYZERO)"),
+];
+
+/// The bits of HFSR, with the cause of the HardFault they stand for.
+const HFSR_CAUSES: [(u32, &str); 3] = [
+    (1 << 1, "bus error on a vector table read (VECTTBL)"),
+    (1 << 30, "fault escalated to a HardFault (FORCED)"),
+    (1 << 31, "breakpoint with halting debug disabled (DEBUGEVT)"),
+];
+
+/// The fault status registers of an ARMv7-M or ARMv8-M mainline core.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FaultStatus {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    /// The address of the access which faulted, from MMFAR or BFAR, if the fault recorded it.
+    pub address: Option<u32>,
+}
+
+impl FaultStatus {
+    pub fn read(probe: &mut MasterProbe) -> Result<Self, AccessPortError> {
+        let cfsr = probe.read32(CFSR)?;
+        let hfsr = probe.read32(HFSR)?;
+
+        let address = if cfsr & CFSR_MMARVALID != 0 {
+            Some(probe.read32(MMFAR)?)
+        } else if cfsr & CFSR_BFARVALID != 0 {
+            Some(probe.read32(BFAR)?)
+        } else {
+            None
+        };
+
+        Ok(FaultStatus {
+            cfsr,
+            hfsr,
+            address,
+        })
+    }
+
+    /// Returns the causes of the fault, the one of the HardFault first.
+    pub fn causes(&self) -> Vec<&'static str> {
+        let hfsr = HFSR_CAUSES.iter().filter(|(bit, _)| self.hfsr & bit != 0);
+        let cfsr = CFSR_CAUSES.iter().filter(|(bit, _)| self.cfsr & bit != 0);
+
+        hfsr.chain(cfsr).map(|(_, cause)| *cause).collect()
+    }
+}
+
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let causes = self.causes();
+        if causes.is_empty() {
+            f.write_str("no fault recorded")?;
+        } else {
+            f.write_str(&causes.join(", "))?;
+        }
+
+        if let Some(address) = self.address {
+            write!(f, " at 0x{:08x}", address)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The state of a core which locked up, read after halting it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lockup {
+    pub pc: u32,
+    pub lr: u32,
+    pub sp: u32,
+    pub xpsr: u32,
+    /// The fault status, unless the core has no fault status registers.
+    pub fault: Option<FaultStatus>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::FaultStatus;
+
+    #[test]
+    fn lists_causes() {
+        let status = FaultStatus {
+            cfsr: (1 << 9) | (1 << 15),
+            hfsr: 1 << 30,
+            address: Some(0x2002_0000),
+        };
+        assert_eq!(
+            status.to_string(),
+            "fault escalated to a HardFault (FORCED), \
+             precise bus error on a data access (PRECISERR) at 0x20020000"
+        );
+
+        let status = FaultStatus {
+            cfsr: 0,
+            hfsr: 0,
+            address: None,
+        };
+        assert_eq!(status.to_string(), "no fault recorded");
+    }
+}
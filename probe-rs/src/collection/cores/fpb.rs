@@ -0,0 +1,157 @@
+//! Breakpoints with the Flash Patch and Breakpoint unit, shared by the Cortex-M cores.
+//!
+//! The comparators of the first revision of the unit, found on the ARMv6-M and ARMv7-M cores
+//! except the Cortex-M7, only match addresses in the code region below 0x2000_0000, and select
+//! the halfword of the word they match. The second revision, found on the Cortex-M7 and the
+//! ARMv8-M cores, matches any halfword address.
+
+use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+use crate::target::CoreRegister;
+use bitfield::bitfield;
+
+bitfield! {
+    #[derive(Copy, Clone)]
+    pub struct FpCtrl(u32);
+    impl Debug;
+    /// 0 for the first revision of the unit, 1 for the second.
+    pub rev, _: 31, 28;
+    /// NUM_CODE is split into FP_CTRL[14:12] and FP_CTRL[7:4].
+    num_code_1, _: 14, 12;
+    num_code_0, _: 7, 4;
+    /// Writes to the register are ignored unless the key is set.
+    pub _, set_key: 1;
+    pub enable, set_enable: 0;
+}
+
+impl FpCtrl {
+    /// The number of code comparators, each of which can hold one breakpoint.
+    pub fn num_code(&self) -> u32 {
+        (self.num_code_1() << 4) | self.num_code_0()
+    }
+}
+
+impl From<u32> for FpCtrl {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<FpCtrl> for u32 {
+    fn from(value: FpCtrl) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for FpCtrl {
+    const ADDRESS: u32 = 0xE000_2000;
+    const NAME: &'static str = "FP_CTRL";
+}
+
+/// The address of the first code comparator, FP_COMP0.
+const FP_COMP0: u32 = 0xE000_2008;
+const FP_COMP_ENABLE: u32 = 1;
+
+pub fn available_units(mi: &mut MasterProbe) -> Result<u32, DebugProbeError> {
+    Ok(FpCtrl(mi.read32(FpCtrl::ADDRESS)?).num_code())
+}
+
+pub fn enable(mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError> {
+    let mut value = FpCtrl(0);
+    value.set_key(true);
+    value.set_enable(state);
+
+    Ok(mi.write32(FpCtrl::ADDRESS, value.into())?)
+}
+
+/// Sets a breakpoint on the first free comparator, unless one is set at `address` already.
+pub fn set_breakpoint(mi: &mut MasterProbe, address: u32) -> Result<(), DebugProbeError> {
+    let fp_ctrl = FpCtrl(mi.read32(FpCtrl::ADDRESS)?);
+    let value = comparator_value(fp_ctrl.rev(), address)?;
+
+    let mut free = None;
+    for i in 0..fp_ctrl.num_code() {
+        let current = mi.read32(FP_COMP0 + 4 * i)?;
+
+        if current == value {
+            return Ok(());
+        }
+        if current & FP_COMP_ENABLE == 0 && free.is_none() {
+            free = Some(i);
+        }
+    }
+
+    let i = free.ok_or(DebugProbeError::BreakpointUnitsExhausted)?;
+    log::debug!(
+        "Setting breakpoint on address 0x{:08x} with FP_COMP{}",
+        address,
+        i
+    );
+
+    Ok(mi.write32(FP_COMP0 + 4 * i, value)?)
+}
+
+/// Enables or disables the comparator of the breakpoint at `address`, if there is one.
+pub fn set_breakpoint_enabled(
+    mi: &mut MasterProbe,
+    address: u32,
+    enabled: bool,
+) -> Result<(), DebugProbeError> {
+    let fp_ctrl = FpCtrl(mi.read32(FpCtrl::ADDRESS)?);
+    let value = comparator_value(fp_ctrl.rev(), address)?;
+
+    for i in 0..fp_ctrl.num_code() {
+        let current = mi.read32(FP_COMP0 + 4 * i)?;
+
+        if current & !FP_COMP_ENABLE == value & !FP_COMP_ENABLE {
+            let current = if enabled {
+                current | FP_COMP_ENABLE
+            } else {
+                current & !FP_COMP_ENABLE
+            };
+            mi.write32(FP_COMP0 + 4 * i, current)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Disables the unit and clears all comparators.
+pub fn clear_all(mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+    let num_comparators = available_units(mi)?;
+
+    enable(mi, false)?;
+
+    for i in 0..num_comparators {
+        mi.write32(FP_COMP0 + 4 * i, 0)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the enabled comparator value which breaks at `address`.
+fn comparator_value(revision: u32, address: u32) -> Result<u32, DebugProbeError> {
+    match revision {
+        0 if address < 0x2000_0000 => {
+            // REPLACE selects the lower or the upper halfword of the word.
+            let replace = if address & 2 == 0 { 0b01 } else { 0b10 };
+
+            Ok((replace << 30) | (address & 0x1FFF_FFFC) | FP_COMP_ENABLE)
+        }
+        0 => Err(DebugProbeError::BreakpointAddressUnsupported(address)),
+        _ => Ok((address & !1) | FP_COMP_ENABLE),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::comparator_value;
+
+    #[test]
+    fn encodes_comparators_per_revision() {
+        assert_eq!(comparator_value(0, 0x0000_1234).unwrap(), 0x4000_1235);
+        assert_eq!(comparator_value(0, 0x0000_1236).unwrap(), 0x8000_1235);
+        assert!(comparator_value(0, 0x2000_0000).is_err());
+        assert_eq!(comparator_value(1, 0x2000_1236).unwrap(), 0x2000_1237);
+    }
+}
@@ -1,9 +1,14 @@
 use crate::memory::MI;
 use crate::probe::debug_probe::{CpuInformation, DebugProbeError, MasterProbe};
-use crate::target::{BasicRegisterAddresses, Core, CoreRegister, CoreRegisterAddress};
+use crate::probe::protocol::ResetType;
+use crate::target::{
+    Architecture, BasicRegisterAddresses, Core, CoreRegister, CoreRegisterAddress, CoreStatus,
+};
 use bitfield::bitfield;
+use std::cell::RefCell;
+use std::time::Instant;
 
-use super::CortexDump;
+use super::{emulate_reset, fpb, register_cache::RegisterCache, CortexDump};
 use log::debug;
 
 bitfield! {
@@ -96,78 +101,6 @@ impl CoreRegister for Dcrdr {
     const NAME: &'static str = "DCRDR";
 }
 
-bitfield! {
-    #[derive(Copy, Clone)]
-    pub struct BpCtrl(u32);
-    impl Debug;
-    /// The number of breakpoint comparators. If NUM_CODE is zero, the implementation does not support any comparators
-    pub numcode, _: 7, 4;
-    /// RAZ on reads, SBO, for writes. If written as zero, the write to the register is ignored.
-    pub key, set_key: 1;
-    /// Enables the BPU:
-    /// 0 BPU is disabled.
-    /// 1 BPU is enabled.
-    /// This bit is set to 0 on a power-on reset
-    pub _, set_enable: 0;
-}
-
-impl From<u32> for BpCtrl {
-    fn from(value: u32) -> Self {
-        Self(value)
-    }
-}
-
-impl From<BpCtrl> for u32 {
-    fn from(value: BpCtrl) -> Self {
-        value.0
-    }
-}
-
-impl CoreRegister for BpCtrl {
-    const ADDRESS: u32 = 0xE000_2000;
-    const NAME: &'static str = "BP_CTRL";
-}
-
-bitfield! {
-    #[derive(Copy, Clone)]
-    pub struct BpCompx(u32);
-    impl Debug;
-    /// BP_MATCH defines the behavior when the COMP address is matched:
-    /// - 00 no breakpoint matching.
-    /// - 01 breakpoint on lower halfword, upper is unaffected.
-    /// - 10 breakpoint on upper halfword, lower is unaffected.
-    /// - 11 breakpoint on both lower and upper halfwords.
-    /// - The field is UNKNOWN on reset.
-    pub _, set_bp_match: 31,30;
-    /// Stores bits [28:2] of the comparison address. The comparison address is
-    /// compared with the address from the Code memory region. Bits [31:29] and
-    /// [1:0] of the comparison address are zero.
-    /// The field is UNKNOWN on power-on reset.
-    pub _, set_comp: 28,2;
-    /// Enables the comparator:
-    /// 0 comparator is disabled.
-    /// 1 comparator is enabled.
-    /// This bit is set to 0 on a power-on reset.
-    pub _, set_enable: 0;
-}
-
-impl From<u32> for BpCompx {
-    fn from(value: u32) -> Self {
-        Self(value)
-    }
-}
-
-impl From<BpCompx> for u32 {
-    fn from(value: BpCompx) -> Self {
-        value.0
-    }
-}
-
-impl CoreRegister for BpCompx {
-    const ADDRESS: u32 = 0xE000_2008;
-    const NAME: &'static str = "BP_CTRL0";
-}
-
 bitfield! {
     #[derive(Copy, Clone)]
     pub struct Aircr(u32);
@@ -250,8 +183,10 @@ pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
 pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b01001);
 pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b01010);
 
-#[derive(Debug, Default, Copy, Clone)]
-pub struct M0;
+#[derive(Debug, Default, Clone)]
+pub struct M0 {
+    registers: RefCell<RegisterCache>,
+}
 
 impl M0 {
     fn wait_for_core_register_transfer(&self, mi: &mut impl MI) -> Result<(), DebugProbeError> {
@@ -266,22 +201,9 @@ impl M0 {
         }
         Err(DebugProbeError::Timeout)
     }
-}
 
-impl Core for M0 {
-    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
-        // Wait until halted state is active again.
-        for _ in 0..100 {
-            let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
-
-            if dhcsr_val.s_halt() {
-                return Ok(());
-            }
-        }
-        Err(DebugProbeError::Timeout)
-    }
-
-    fn read_core_reg(
+    /// Reads a core register from the core, bypassing the cache.
+    fn read_register(
         &self,
         mi: &mut MasterProbe,
         addr: CoreRegisterAddress,
@@ -298,7 +220,8 @@ impl Core for M0 {
         mi.read32(Dcrdr::ADDRESS).map_err(From::from)
     }
 
-    fn write_core_reg(
+    /// Writes a core register to the core, bypassing the cache.
+    fn write_register(
         &self,
         mi: &mut MasterProbe,
         addr: CoreRegisterAddress,
@@ -318,6 +241,79 @@ impl Core for M0 {
         self.wait_for_core_register_transfer(mi)
     }
 
+    /// Enables caching the registers and the memory, as the core is halted.
+    fn set_halted(&self, mi: &mut MasterProbe) {
+        self.registers.borrow_mut().set_halted();
+        mi.set_core_halted(true);
+    }
+
+    /// Drops the cached registers and memory, as the core resumes or is reset.
+    fn set_running(&self, mi: &mut MasterProbe) {
+        self.registers.borrow_mut().invalidate();
+        mi.set_core_halted(false);
+    }
+
+    /// Writes the registers changed while the core was halted, before it resumes.
+    fn flush_registers(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        let dirty = self.registers.borrow_mut().take_dirty();
+        for (addr, value) in dirty {
+            self.write_register(mi, addr, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Core for M0 {
+    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        // Wait until halted state is active again.
+        let start = Instant::now();
+        loop {
+            let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
+
+            if dhcsr_val.s_halt() {
+                self.set_halted(mi);
+                return Ok(());
+            }
+            if start.elapsed() > mi.retry_policy().halt_timeout {
+                return Err(DebugProbeError::Timeout);
+            }
+            mi.cancellation_token().check()?;
+        }
+    }
+
+    fn status(&self, mi: &mut MasterProbe) -> Result<CoreStatus, DebugProbeError> {
+        super::cortex_m_status(mi)
+    }
+
+    fn read_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+    ) -> Result<u32, DebugProbeError> {
+        if let Some(value) = self.registers.borrow().get(addr) {
+            return Ok(value);
+        }
+
+        let value = self.read_register(mi, addr)?;
+        self.registers.borrow_mut().insert(addr, value);
+
+        Ok(value)
+    }
+
+    fn write_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        if self.registers.borrow_mut().write(addr, value) {
+            return Ok(());
+        }
+
+        self.write_register(mi, addr, value)
+    }
+
     fn halt(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
         // TODO: Generic halt support
 
@@ -327,6 +323,7 @@ impl Core for M0 {
         value.enable_write();
 
         mi.write32(Dhcsr::ADDRESS, value.into())?;
+        self.set_halted(mi);
 
         // try to read the program counter
         let pc_value = self.read_core_reg(mi, REGISTERS.PC)?;
@@ -336,6 +333,9 @@ impl Core for M0 {
     }
 
     fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        self.flush_registers(mi)?;
+        self.set_running(mi);
+
         let mut value = Dhcsr(0);
         value.set_c_halt(false);
         value.set_c_debugen(true);
@@ -345,6 +345,9 @@ impl Core for M0 {
     }
 
     fn step(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
+        self.flush_registers(mi)?;
+        self.set_running(mi);
+
         let mut value = Dhcsr(0);
         // Leave halted state.
         // Step one instruction.
@@ -366,6 +369,8 @@ impl Core for M0 {
     }
 
     fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        self.set_running(mi);
+
         // Set THE AIRCR.SYSRESETREQ control bit to 1 to request a reset. (ARM V6 ARM, B1.5.16)
 
         let reset_val = (0x05FA << 16) | (1 << 2);
@@ -377,7 +382,26 @@ impl Core for M0 {
         Ok(())
     }
 
+    fn reset_with(
+        &self,
+        mi: &mut MasterProbe,
+        reset_type: ResetType,
+    ) -> Result<(), DebugProbeError> {
+        match reset_type {
+            ResetType::Hardware => {
+                self.set_running(mi);
+                mi.target_reset()
+            }
+            ResetType::SysResetReq => self.reset(mi),
+            // The baseline architecture has no VECTRESET.
+            ResetType::VectReset => Err(DebugProbeError::ResetTypeUnsupported(reset_type)),
+            ResetType::Core => emulate_reset(self, mi),
+        }
+    }
+
     fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        self.set_running(mi);
+
         let demcr_val = Demcr(mi.read32(Demcr::ADDRESS)?);
         if !demcr_val.vc_corereset() {
             let mut demcr_enabled = demcr_val;
@@ -410,42 +434,24 @@ impl Core for M0 {
     }
 
     fn get_available_breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError> {
-        let result = mi.read32(BpCtrl::ADDRESS)?;
-
-        self.wait_for_core_register_transfer(mi)?;
-
-        Ok(result)
+        fpb::available_units(mi)
     }
 
     fn enable_breakpoints(&self, mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError> {
         debug!("Enabling breakpoints: {:?}", state);
-        let mut value = BpCtrl(0);
-        value.set_key(true);
-        value.set_enable(state);
-
-        mi.write32(BpCtrl::ADDRESS, value.into())?;
-
-        self.wait_for_core_halted(mi)
+        fpb::enable(mi, state)
     }
 
     fn set_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
-        debug!("Setting breakpoint on address 0x{:08x}", addr);
-        let mut value = BpCompx(0);
-        value.set_bp_match(0b11);
-        value.set_comp((addr >> 2) & 0x00FF_FFFF);
-        value.set_enable(true);
-
-        mi.write32(BpCompx::ADDRESS, value.into())?;
-
-        self.wait_for_core_halted(mi)
+        fpb::set_breakpoint(mi, addr)
     }
 
-    fn enable_breakpoint(&self, _mi: &mut MasterProbe, _addr: u32) -> Result<(), DebugProbeError> {
-        unimplemented!();
+    fn enable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        fpb::set_breakpoint_enabled(mi, addr, true)
     }
 
-    fn disable_breakpoint(&self, _mi: &mut MasterProbe, _addr: u32) -> Result<(), DebugProbeError> {
-        unimplemented!();
+    fn disable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        fpb::set_breakpoint_enabled(mi, addr, false)
     }
 
     fn read_block8(
@@ -460,6 +466,46 @@ impl Core for M0 {
     fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
         &REGISTERS
     }
+
+    fn vector_catch(&self, mi: &mut MasterProbe) -> Result<Option<u32>, DebugProbeError> {
+        Ok(Some(mi.read32(Demcr::ADDRESS)?))
+    }
+
+    fn detach(
+        &self,
+        mi: &mut MasterProbe,
+        vector_catch: Option<u32>,
+        resume: bool,
+    ) -> Result<(), DebugProbeError> {
+        self.flush_registers(mi)?;
+        self.set_running(mi);
+
+        // Disable the breakpoint unit and clear all comparators.
+        fpb::clear_all(mi)?;
+
+        // Restore the vector catches the core had before the session.
+        if let Some(demcr) = vector_catch {
+            mi.write32(Demcr::ADDRESS, demcr)?;
+        }
+
+        if resume {
+            // Clearing C_DEBUGEN also clears C_HALT and C_STEP.
+            let mut value = Dhcsr(0);
+            value.enable_write();
+
+            mi.write32(Dhcsr::ADDRESS, value.into())?;
+        }
+
+        Ok(())
+    }
+
+    fn forget_state(&self) {
+        self.registers.borrow_mut().invalidate();
+    }
+
+    fn architecture(&self) -> Architecture {
+        Architecture::Arm
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -478,6 +524,11 @@ impl Core for FakeM0 {
         unimplemented!();
     }
 
+    /// A dump does not record what the core was doing.
+    fn status(&self, _mi: &mut MasterProbe) -> Result<CoreStatus, DebugProbeError> {
+        Ok(CoreStatus::Unknown)
+    }
+
     fn halt(&self, _mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
         unimplemented!()
     }
@@ -495,6 +546,14 @@ impl Core for FakeM0 {
         unimplemented!()
     }
 
+    fn reset_with(
+        &self,
+        _mi: &mut MasterProbe,
+        _reset_type: ResetType,
+    ) -> Result<(), DebugProbeError> {
+        unimplemented!()
+    }
+
     fn reset_and_halt(&self, _mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
         unimplemented!()
     }
@@ -505,12 +564,15 @@ impl Core for FakeM0 {
         addr: CoreRegisterAddress,
     ) -> Result<u32, DebugProbeError> {
         let index: u32 = addr.into();
+        let fp_regs = self.dump.fp_regs.as_ref();
 
-        self.dump
-            .regs
-            .get(index as usize)
-            .copied()
-            .ok_or(DebugProbeError::UnknownError)
+        // The FPSCR and S0 to S31 are taken from the FPU registers of the dump.
+        match index {
+            0x21 => fp_regs.map(|fp_regs| fp_regs.fpscr),
+            0x40..=0x5F => fp_regs.map(|fp_regs| fp_regs.s[index as usize - 0x40]),
+            _ => self.dump.regs.get(index as usize).copied(),
+        }
+        .ok_or(DebugProbeError::UnknownError)
     }
 
     fn write_core_reg(
@@ -577,4 +639,35 @@ impl Core for FakeM0 {
     fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
         &REGISTERS
     }
+
+    fn detach(
+        &self,
+        _mi: &mut MasterProbe,
+        _vector_catch: Option<u32>,
+        _resume: bool,
+    ) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn architecture(&self) -> Architecture {
+        Architecture::Arm
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{M0, REGISTERS};
+    use crate::target::Core;
+
+    #[test]
+    fn forgets_dirty_registers() {
+        let core = M0::default();
+        core.registers.borrow_mut().set_halted();
+        assert!(core.registers.borrow_mut().write(REGISTERS.PC, 0x100));
+
+        // After reattaching the core may run other code, so the value must not be flushed.
+        core.forget_state();
+        assert!(core.registers.borrow_mut().take_dirty().is_empty());
+        assert_eq!(core.registers.borrow().get(REGISTERS.PC), None);
+    }
 }
@@ -1,7 +1,15 @@
 use crate::memory::MI;
 use crate::probe::debug_probe::{CpuInformation, DebugProbeError, MasterProbe};
-use crate::target::{BasicRegisterAddresses, Core, CoreRegister, CoreRegisterAddress};
+use crate::probe::protocol::ResetType;
+use crate::target::{
+    Architecture, BasicRegisterAddresses, Core, CoreRegister, CoreRegisterAddress, CoreStatus,
+};
 use bitfield::bitfield;
+use std::cell::RefCell;
+use std::time::Instant;
+
+use super::register_cache::RegisterCache;
+use super::{emulate_reset, fpb};
 
 bitfield! {
     #[derive(Copy, Clone)]
@@ -186,6 +194,41 @@ impl CoreRegister for Demcr {
     const NAME: &'static str = "DEMCR";
 }
 
+bitfield! {
+    /// Debug Fault Status Register, why the core halted. The bits are cleared by writing
+    /// ones to them.
+    #[derive(Copy, Clone)]
+    pub struct Dfsr(u32);
+    impl Debug;
+    /// An external debug request
+    pub external, _: 4;
+    /// A vector catch
+    pub vcatch, _: 3;
+    /// A match of a DWT comparator
+    pub dwttrap, _: 2;
+    /// A breakpoint of the FPB or a BKPT instruction
+    pub bkpt, _: 1;
+    /// A halt request or a step
+    pub halted, _: 0;
+}
+
+impl From<u32> for Dfsr {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Dfsr> for u32 {
+    fn from(value: Dfsr) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for Dfsr {
+    const ADDRESS: u32 = 0xE000_ED30;
+    const NAME: &'static str = "DFSR";
+}
+
 pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
     R0: CoreRegisterAddress(0b000_0000),
     R1: CoreRegisterAddress(0b000_0001),
@@ -202,8 +245,10 @@ pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
 pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b000_1001);
 pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b000_1010);
 
-#[derive(Debug, Default, Copy, Clone)]
-pub struct M4;
+#[derive(Debug, Default, Clone)]
+pub struct M4 {
+    registers: RefCell<RegisterCache>,
+}
 
 impl M4 {
     fn wait_for_core_register_transfer(&self, mi: &mut impl MI) -> Result<(), DebugProbeError> {
@@ -218,21 +263,9 @@ impl M4 {
         }
         Err(DebugProbeError::Timeout)
     }
-}
-
-impl Core for M4 {
-    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
-        // Wait until halted state is active again.
-        for _ in 0..100 {
-            let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
-            if dhcsr_val.s_halt() {
-                return Ok(());
-            }
-        }
-        Err(DebugProbeError::Timeout)
-    }
 
-    fn read_core_reg(
+    /// Reads a core register from the core, bypassing the cache.
+    fn read_register(
         &self,
         mi: &mut MasterProbe,
         addr: CoreRegisterAddress,
@@ -249,7 +282,8 @@ impl Core for M4 {
         mi.read32(Dcrdr::ADDRESS).map_err(From::from)
     }
 
-    fn write_core_reg(
+    /// Writes a core register to the core, bypassing the cache.
+    fn write_register(
         &self,
         mi: &mut MasterProbe,
         addr: CoreRegisterAddress,
@@ -269,6 +303,78 @@ impl Core for M4 {
         self.wait_for_core_register_transfer(mi)
     }
 
+    /// Enables caching the registers and the memory, as the core is halted.
+    fn set_halted(&self, mi: &mut MasterProbe) {
+        self.registers.borrow_mut().set_halted();
+        mi.set_core_halted(true);
+    }
+
+    /// Drops the cached registers and memory, as the core resumes or is reset.
+    fn set_running(&self, mi: &mut MasterProbe) {
+        self.registers.borrow_mut().invalidate();
+        mi.set_core_halted(false);
+    }
+
+    /// Writes the registers changed while the core was halted, before it resumes.
+    fn flush_registers(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        let dirty = self.registers.borrow_mut().take_dirty();
+        for (addr, value) in dirty {
+            self.write_register(mi, addr, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Core for M4 {
+    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        // Wait until halted state is active again.
+        let start = Instant::now();
+        loop {
+            let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
+            if dhcsr_val.s_halt() {
+                self.set_halted(mi);
+                return Ok(());
+            }
+            if start.elapsed() > mi.retry_policy().halt_timeout {
+                return Err(DebugProbeError::Timeout);
+            }
+            mi.cancellation_token().check()?;
+        }
+    }
+
+    fn status(&self, mi: &mut MasterProbe) -> Result<CoreStatus, DebugProbeError> {
+        super::cortex_m_status(mi)
+    }
+
+    fn read_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+    ) -> Result<u32, DebugProbeError> {
+        if let Some(value) = self.registers.borrow().get(addr) {
+            return Ok(value);
+        }
+
+        let value = self.read_register(mi, addr)?;
+        self.registers.borrow_mut().insert(addr, value);
+
+        Ok(value)
+    }
+
+    fn write_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        if self.registers.borrow_mut().write(addr, value) {
+            return Ok(());
+        }
+
+        self.write_register(mi, addr, value)
+    }
+
     fn halt(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
         // TODO: Generic halt support
 
@@ -278,6 +384,7 @@ impl Core for M4 {
         value.enable_write();
 
         mi.write32(Dhcsr::ADDRESS, value.into())?;
+        self.set_halted(mi);
 
         // try to read the program counter
         let pc_value = self.read_core_reg(mi, REGISTERS.PC)?;
@@ -287,6 +394,9 @@ impl Core for M4 {
     }
 
     fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        self.flush_registers(mi)?;
+        self.set_running(mi);
+
         let mut value = Dhcsr(0);
         value.set_c_halt(false);
         value.set_c_debugen(true);
@@ -296,6 +406,9 @@ impl Core for M4 {
     }
 
     fn step(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
+        self.flush_registers(mi)?;
+        self.set_running(mi);
+
         let mut value = Dhcsr(0);
         // Leave halted state.
         // Step one instruction.
@@ -317,6 +430,8 @@ impl Core for M4 {
     }
 
     fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        self.set_running(mi);
+
         // Set THE AIRCR.SYSRESETREQ control bit to 1 to request a reset. (ARM V6 ARM, B1.5.16)
         let mut value = Aircr(0);
         value.vectkey();
@@ -327,7 +442,35 @@ impl Core for M4 {
         Ok(())
     }
 
+    fn reset_with(
+        &self,
+        mi: &mut MasterProbe,
+        reset_type: ResetType,
+    ) -> Result<(), DebugProbeError> {
+        match reset_type {
+            ResetType::Hardware => {
+                self.set_running(mi);
+                mi.target_reset()
+            }
+            ResetType::SysResetReq => self.reset(mi),
+            ResetType::VectReset => {
+                self.set_running(mi);
+
+                let mut value = Aircr(0);
+                value.vectkey();
+                value.set_vectreset(true);
+
+                mi.write32(Aircr::ADDRESS, value.into())?;
+
+                Ok(())
+            }
+            ResetType::Core => emulate_reset(self, mi),
+        }
+    }
+
     fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        self.set_running(mi);
+
         let demcr_val = Demcr(mi.read32(Demcr::ADDRESS)?);
         if !demcr_val.vc_corereset() {
             let mut demcr_enabled = demcr_val;
@@ -359,31 +502,24 @@ impl Core for M4 {
         Ok(())
     }
 
-    fn get_available_breakpoint_units(
-        &self,
-        _mi: &mut MasterProbe,
-    ) -> Result<u32, DebugProbeError> {
-        unimplemented!();
+    fn get_available_breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError> {
+        fpb::available_units(mi)
     }
 
-    fn enable_breakpoints(
-        &self,
-        _mi: &mut MasterProbe,
-        _state: bool,
-    ) -> Result<(), DebugProbeError> {
-        unimplemented!();
+    fn enable_breakpoints(&self, mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError> {
+        fpb::enable(mi, state)
     }
 
-    fn set_breakpoint(&self, _mi: &mut MasterProbe, _addr: u32) -> Result<(), DebugProbeError> {
-        unimplemented!();
+    fn set_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        fpb::set_breakpoint(mi, addr)
     }
 
-    fn enable_breakpoint(&self, _mi: &mut MasterProbe, _addr: u32) -> Result<(), DebugProbeError> {
-        unimplemented!();
+    fn enable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        fpb::set_breakpoint_enabled(mi, addr, true)
     }
 
-    fn disable_breakpoint(&self, _mi: &mut MasterProbe, _addr: u32) -> Result<(), DebugProbeError> {
-        unimplemented!();
+    fn disable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        fpb::set_breakpoint_enabled(mi, addr, false)
     }
 
     fn read_block8(
@@ -398,4 +534,44 @@ impl Core for M4 {
     fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
         &REGISTERS
     }
+
+    fn vector_catch(&self, mi: &mut MasterProbe) -> Result<Option<u32>, DebugProbeError> {
+        Ok(Some(mi.read32(Demcr::ADDRESS)?))
+    }
+
+    fn detach(
+        &self,
+        mi: &mut MasterProbe,
+        vector_catch: Option<u32>,
+        resume: bool,
+    ) -> Result<(), DebugProbeError> {
+        self.flush_registers(mi)?;
+        self.set_running(mi);
+
+        // Disable the flash patch and breakpoint unit and clear all code comparators.
+        fpb::clear_all(mi)?;
+
+        // Restore the vector catches the core had before the session.
+        if let Some(demcr) = vector_catch {
+            mi.write32(Demcr::ADDRESS, demcr)?;
+        }
+
+        if resume {
+            // Clearing C_DEBUGEN also clears C_HALT and C_STEP.
+            let mut value = Dhcsr(0);
+            value.enable_write();
+
+            mi.write32(Dhcsr::ADDRESS, value.into())?;
+        }
+
+        Ok(())
+    }
+
+    fn forget_state(&self) {
+        self.registers.borrow_mut().invalidate();
+    }
+
+    fn architecture(&self) -> Architecture {
+        Architecture::Arm
+    }
 }
@@ -1,9 +1,122 @@
+pub mod cortex_a;
+pub mod cpuid;
+pub mod dwt;
+pub mod fault;
+pub mod fpb;
 pub mod m0;
 pub mod m4;
+pub mod mtb;
+pub mod register_cache;
+pub mod security;
+pub mod xtensa;
+
+use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+use crate::target::{Core, CoreRegister, CoreRegisterAddress, CoreStatus};
+
+use self::m4::{Dfsr, Dhcsr};
+
+/// The FPSCR, as selected in DCRSR.
+pub const FPSCR: CoreRegisterAddress = CoreRegisterAddress(0b010_0001);
+
+/// Returns the address of the single precision register S0 to S31, as selected in DCRSR.
+///
+/// These are numbered the same as the DWARF registers 64 to 95.
+pub fn s_register(n: u8) -> CoreRegisterAddress {
+    CoreRegisterAddress(0b100_0000 | n)
+}
+
+/// Which accesses a watchpoint breaks on.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// The Vector Table Offset Register, which reads as zero if the core has none.
+const VTOR: u32 = 0xE000_ED08;
+const DEMCR: u32 = 0xE000_EDFC;
+const DEMCR_VC_CORERESET: u32 = 1 << 0;
+
+/// Emulates a reset of the core, without resetting the peripherals.
+///
+/// The core is halted and its stack pointer and program counter are loaded from the vector
+/// table, as on a reset. The other special registers, e.g. PRIMASK and CONTROL, are kept. The
+/// core stays halted if the reset vector is caught.
+pub(crate) fn emulate_reset(core: &dyn Core, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+    const XPSR_THUMB: u32 = 1 << 24;
+
+    core.halt(mi)?;
+
+    let vector_table = mi.read32(VTOR)?;
+    let stack_pointer = mi.read32(vector_table)?;
+    let reset_vector = mi.read32(vector_table + 4)?;
+
+    let registers = core.registers();
+    core.write_core_reg(mi, registers.SP, stack_pointer)?;
+    core.write_core_reg(mi, registers.PC, reset_vector & !1)?;
+    core.write_core_reg(mi, registers.LR, 0xFFFF_FFFF)?;
+    core.write_core_reg(mi, registers.XPSR, XPSR_THUMB)?;
+
+    if mi.read32(DEMCR)? & DEMCR_VC_CORERESET == 0 {
+        core.run(mi)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the status of a Cortex-M core from DHCSR, and the reason of a halt from DFSR.
+pub(crate) fn cortex_m_status(mi: &mut MasterProbe) -> Result<CoreStatus, DebugProbeError> {
+    let dhcsr = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
+
+    // Halting a locked up core gets it out of the lockup, so a halted core is reported as such.
+    let status = if dhcsr.s_halt() {
+        CoreStatus::Halted(Dfsr(mi.read32(Dfsr::ADDRESS)?).into())
+    } else if dhcsr.s_lockup() {
+        CoreStatus::LockedUp
+    } else if dhcsr.s_sleep() {
+        CoreStatus::Sleeping
+    } else {
+        CoreStatus::Running
+    };
+
+    Ok(status)
+}
+
+/// The registers of the floating point unit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FpRegisters {
+    pub s: [u32; 32],
+    pub fpscr: u32,
+}
+
+impl FpRegisters {
+    pub fn read(core: &dyn Core, mi: &mut MasterProbe) -> Result<Self, DebugProbeError> {
+        let mut s = [0; 32];
+        for (n, value) in s.iter_mut().enumerate() {
+            *value = core.read_core_reg(mi, s_register(n as u8))?;
+        }
+
+        Ok(FpRegisters {
+            s,
+            fpscr: core.read_core_reg(mi, FPSCR)?,
+        })
+    }
+
+    /// Returns the double precision register D0 to D15, which overlays S(2n) and S(2n + 1).
+    pub fn d(&self, n: usize) -> u64 {
+        u64::from(self.s[2 * n]) | (u64::from(self.s[2 * n + 1]) << 32)
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CortexDump {
     pub regs: [u32; 16],
+    /// The registers of the floating point unit, if the core has one.
+    #[serde(default)]
+    pub fp_regs: Option<FpRegisters>,
     stack_addr: u32,
     stack: Vec<u8>,
 }
@@ -12,6 +125,7 @@ impl CortexDump {
     pub fn new(stack_addr: u32, stack: Vec<u8>) -> CortexDump {
         CortexDump {
             regs: [0u32; 16],
+            fp_regs: None,
             stack_addr,
             stack,
         }
@@ -0,0 +1,277 @@
+//! Instruction trace using the Micro Trace Buffer (MTB) of the Cortex-M0+.
+//!
+//! The MTB records every non-sequential change of the program counter into a region of
+//! the target SRAM. Each record, or packet, holds the address of the branch instruction
+//! and the address of the branch target. The instructions executed in between can be
+//! recovered from the program image, see [`reconstruct`](fn.reconstruct.html).
+
+use crate::coresight::{
+    access_ports::{
+        generic_ap::{APClass, IDR},
+        memory_ap::MemoryAP,
+        AccessPortError,
+    },
+    ap_access::{valid_access_ports, APAccess},
+};
+use crate::memory::romtable::{rom_table_address, CSComponent, RomTableError};
+use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+
+use std::error::Error;
+use std::fmt;
+
+/// Part number of the ARM MTB-M0+ in the CoreSight peripheral ID.
+const MTB_PART_NUMBER: u16 = 0x932;
+
+const MTB_POSITION: u32 = 0x000;
+const MTB_MASTER: u32 = 0x004;
+const MTB_FLOW: u32 = 0x008;
+const MTB_BASE: u32 = 0x00C;
+
+/// Enables trace recording, bit 31 of MTB_MASTER.
+const MASTER_EN: u32 = 1 << 31;
+
+/// Set in MTB_POSITION once the write pointer wrapped around.
+const POSITION_WRAP: u32 = 1 << 2;
+
+/// The smallest trace buffer supported by the MTB, in bytes.
+pub const MIN_BUFFER_SIZE: u32 = 16;
+
+#[derive(Debug)]
+pub enum MtbError {
+    /// No MTB was found in the ROM tables of the target.
+    NotFound,
+    /// The buffer size is not a power of two of at least 16 bytes.
+    InvalidBufferSize(u32),
+    RomTable(RomTableError),
+    DebugProbe(DebugProbeError),
+    AccessPort(AccessPortError),
+}
+
+impl Error for MtbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MtbError::RomTable(ref e) => Some(e),
+            MtbError::DebugProbe(ref e) => Some(e),
+            MtbError::AccessPort(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MtbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MtbError::NotFound => write!(f, "The target does not have a Micro Trace Buffer."),
+            MtbError::InvalidBufferSize(size) => write!(
+                f,
+                "Invalid trace buffer size of {} bytes, it must be a power of two of at least {} bytes.",
+                size, MIN_BUFFER_SIZE
+            ),
+            MtbError::RomTable(ref e) => e.fmt(f),
+            MtbError::DebugProbe(ref e) => e.fmt(f),
+            MtbError::AccessPort(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<RomTableError> for MtbError {
+    fn from(e: RomTableError) -> Self {
+        MtbError::RomTable(e)
+    }
+}
+
+impl From<DebugProbeError> for MtbError {
+    fn from(e: DebugProbeError) -> Self {
+        MtbError::DebugProbe(e)
+    }
+}
+
+impl From<AccessPortError> for MtbError {
+    fn from(e: AccessPortError) -> Self {
+        MtbError::AccessPort(e)
+    }
+}
+
+/// A single record of the trace buffer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MtbPacket {
+    /// Address of the instruction which caused the branch.
+    pub source: u32,
+    /// Address of the branch target.
+    pub destination: u32,
+    /// Set if the branch was caused by an exception entry or return.
+    pub exception: bool,
+    /// Set for the first packet after tracing was (re)started.
+    pub start: bool,
+}
+
+impl MtbPacket {
+    fn from_raw(source: u32, destination: u32) -> Self {
+        MtbPacket {
+            source: source & !1,
+            destination: destination & !1,
+            exception: source & 1 == 1,
+            start: destination & 1 == 1,
+        }
+    }
+}
+
+/// A range of instructions which were executed sequentially.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ExecutedRange {
+    /// Address of the first instruction.
+    pub start: u32,
+    /// Address of the last instruction.
+    pub end: u32,
+    /// Set if the range was entered through an exception entry or return.
+    pub exception: bool,
+}
+
+/// The Micro Trace Buffer of a Cortex-M0+.
+#[derive(Debug, Copy, Clone)]
+pub struct Mtb {
+    base_address: u32,
+}
+
+impl Mtb {
+    /// Creates a handle for an MTB whose registers are located at `base_address`.
+    pub fn new(base_address: u32) -> Self {
+        Mtb { base_address }
+    }
+
+    /// Searches the ROM tables of all memory APs for an MTB.
+    pub fn find(probe: &mut MasterProbe) -> Result<Self, MtbError> {
+        for access_port in valid_access_ports(probe) {
+            let idr = probe.read_register_ap(access_port, IDR::default())?;
+
+            if idr.CLASS != APClass::MEMAP {
+                continue;
+            }
+
+            let access_port: MemoryAP = access_port.into();
+
+            let baseaddr = match rom_table_address(probe, access_port)? {
+                Some(baseaddr) => baseaddr,
+                None => continue,
+            };
+
+            let component_table = CSComponent::try_parse(&probe.into(), baseaddr)?;
+
+            for component in component_table.iter() {
+                if let Some(id) = component.id() {
+                    if id.peripheral_id.PART == MTB_PART_NUMBER {
+                        return Ok(Mtb::new(id.base_address() as u32));
+                    }
+                }
+            }
+        }
+
+        Err(MtbError::NotFound)
+    }
+
+    /// Returns the address of the SRAM region the MTB writes its packets to.
+    pub fn buffer_address(&self, mi: &mut impl MI) -> Result<u32, MtbError> {
+        Ok(mi.read32(self.base_address + MTB_BASE)?)
+    }
+
+    /// Starts recording into the first `buffer_size` bytes of the trace SRAM.
+    ///
+    /// The firmware must not use this part of the SRAM while tracing.
+    pub fn start(&self, mi: &mut impl MI, buffer_size: u32) -> Result<(), MtbError> {
+        if buffer_size < MIN_BUFFER_SIZE || !buffer_size.is_power_of_two() {
+            return Err(MtbError::InvalidBufferSize(buffer_size));
+        }
+
+        // The buffer holds 2^(MASK + 4) bytes.
+        let mask = buffer_size.trailing_zeros() - 4;
+
+        mi.write32(self.base_address + MTB_MASTER, 0)?;
+        mi.write32(self.base_address + MTB_POSITION, 0)?;
+        mi.write32(self.base_address + MTB_FLOW, 0)?;
+        mi.write32(self.base_address + MTB_MASTER, MASTER_EN | mask)?;
+
+        Ok(())
+    }
+
+    /// Stops recording, keeping the contents of the trace buffer.
+    pub fn stop(&self, mi: &mut impl MI) -> Result<(), MtbError> {
+        let master = mi.read32(self.base_address + MTB_MASTER)?;
+        mi.write32(self.base_address + MTB_MASTER, master & !MASTER_EN)?;
+
+        Ok(())
+    }
+
+    /// Clears the trace buffer and resumes recording with the previous buffer size.
+    pub fn restart(&self, mi: &mut impl MI) -> Result<(), MtbError> {
+        let master = mi.read32(self.base_address + MTB_MASTER)?;
+
+        mi.write32(self.base_address + MTB_POSITION, 0)?;
+        mi.write32(self.base_address + MTB_MASTER, master | MASTER_EN)?;
+
+        Ok(())
+    }
+
+    /// Reads the recorded packets, oldest first.
+    ///
+    /// Recording should be stopped before reading the buffer.
+    pub fn read_packets(&self, mi: &mut impl MI) -> Result<Vec<MtbPacket>, MtbError> {
+        let master = mi.read32(self.base_address + MTB_MASTER)?;
+        let position = mi.read32(self.base_address + MTB_POSITION)?;
+        let buffer_address = self.buffer_address(mi)?;
+
+        let buffer_size = 1 << ((master & 0x1F) + 4);
+        let offset = position & !0x7 & (buffer_size - 1);
+
+        let mut words = vec![0u32; buffer_size as usize / 4];
+        mi.read_block32(buffer_address, &mut words)?;
+
+        // Once the write pointer wrapped around, the oldest packet is the one it points to.
+        let (start, count) = if position & POSITION_WRAP != 0 {
+            (offset / 4, words.len() / 2)
+        } else {
+            (0, offset as usize / 8)
+        };
+
+        Ok((0..count)
+            .map(|i| {
+                let index = (start as usize + 2 * i) % words.len();
+                MtbPacket::from_raw(words[index], words[index + 1])
+            })
+            .collect())
+    }
+}
+
+/// Reconstructs the sequentially executed instruction ranges from the trace packets.
+///
+/// Execution continues at the destination of each packet and runs up to the source of
+/// the next one. The last range ends at `pc`, the current program counter of the halted
+/// core, if it is known.
+pub fn reconstruct(packets: &[MtbPacket], pc: Option<u32>) -> Vec<ExecutedRange> {
+    let mut ranges = Vec::new();
+
+    for (current, next) in packets.iter().zip(packets.iter().skip(1)) {
+        // Tracing was interrupted, so the flow between these packets is unknown.
+        if next.start || next.source < current.destination {
+            continue;
+        }
+
+        ranges.push(ExecutedRange {
+            start: current.destination,
+            end: next.source,
+            exception: current.exception,
+        });
+    }
+
+    if let (Some(last), Some(pc)) = (packets.last(), pc) {
+        if pc >= last.destination {
+            ranges.push(ExecutedRange {
+                start: last.destination,
+                end: pc,
+                exception: last.exception,
+            });
+        }
+    }
+
+    ranges
+}
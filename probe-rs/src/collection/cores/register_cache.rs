@@ -0,0 +1,104 @@
+//! Cache of the core registers of a halted core.
+
+use crate::target::CoreRegisterAddress;
+
+use std::collections::BTreeMap;
+
+/// The values of the core registers read or written since the core halted.
+///
+/// The registers of a halted core only change when the debugger writes them, so each of them
+/// only has to be transferred through DCRSR and DCRDR once per halt. Writes are kept in the
+/// cache and only transferred when the core is about to resume.
+#[derive(Debug, Default, Clone)]
+pub struct RegisterCache {
+    halted: bool,
+    /// The value of each cached register, and whether it still has to be written to the core.
+    values: BTreeMap<u8, (u32, bool)>,
+}
+
+impl RegisterCache {
+    /// Returns the cached value of a register, if the core is known to be halted.
+    pub fn get(&self, addr: CoreRegisterAddress) -> Option<u32> {
+        if self.halted {
+            self.values.get(&addr.0).map(|(value, _)| *value)
+        } else {
+            None
+        }
+    }
+
+    /// Remembers a value read from the core, if it is known to be halted.
+    pub fn insert(&mut self, addr: CoreRegisterAddress, value: u32) {
+        if self.halted {
+            self.values.insert(addr.0, (value, false));
+        }
+    }
+
+    /// Remembers a value to write to the core before it resumes.
+    ///
+    /// Returns `false` if the core is not known to be halted, so the value has to be written
+    /// immediately.
+    pub fn write(&mut self, addr: CoreRegisterAddress, value: u32) -> bool {
+        if self.halted {
+            self.values.insert(addr.0, (value, true));
+        }
+
+        self.halted
+    }
+
+    /// Marks the core as halted, keeping the cached values if it already was.
+    pub fn set_halted(&mut self) {
+        if !self.halted {
+            self.values.clear();
+            self.halted = true;
+        }
+    }
+
+    /// Returns the registers written since the core halted, and marks them as written.
+    pub fn take_dirty(&mut self) -> Vec<(CoreRegisterAddress, u32)> {
+        self.values
+            .iter_mut()
+            .filter(|(_, (_, dirty))| *dirty)
+            .map(|(addr, (value, dirty))| {
+                *dirty = false;
+                (CoreRegisterAddress(*addr), *value)
+            })
+            .collect()
+    }
+
+    /// Forgets all values, because the core resumes or is reset.
+    pub fn invalidate(&mut self) {
+        self.values.clear();
+        self.halted = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RegisterCache;
+    use crate::target::CoreRegisterAddress;
+
+    #[test]
+    fn caches_while_halted() {
+        let mut cache = RegisterCache::default();
+        let pc = CoreRegisterAddress(15);
+        let r0 = CoreRegisterAddress(0);
+
+        cache.insert(pc, 0x100);
+        assert_eq!(cache.get(pc), None);
+        assert!(!cache.write(r0, 1));
+
+        cache.set_halted();
+        cache.insert(pc, 0x100);
+        assert!(cache.write(r0, 1));
+        assert_eq!(cache.get(pc), Some(0x100));
+        assert_eq!(cache.get(r0), Some(1));
+
+        // Halting again must not lose the values not written yet.
+        cache.set_halted();
+        assert_eq!(cache.take_dirty().len(), 1);
+        assert!(cache.take_dirty().is_empty());
+
+        cache.invalidate();
+        assert_eq!(cache.get(pc), None);
+    }
+}
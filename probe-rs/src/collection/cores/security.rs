@@ -0,0 +1,110 @@
+//! The debug authentication of ARMv8-M cores, which tells whether secure code can be debugged.
+//!
+//! Devices with the Security Extension can disable debugging of the secure state, or of the
+//! whole core, through their authentication interface. ARMv6-M and ARMv7-M cores have no such
+//! interface and can always be debugged once the debug port is accessible.
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::probe::debug_probe::MasterProbe;
+
+use std::fmt;
+
+/// DAUTHSTATUS, which reports the state of the authentication interface.
+const DAUTHSTATUS: u32 = 0xE000_EFB8;
+
+/// Whether a kind of debugging is allowed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Authentication {
+    /// The core has no Security Extension, so it has no secure state to debug.
+    NotImplemented,
+    Disabled,
+    Enabled,
+}
+
+impl Authentication {
+    fn from_field(field: u32) -> Self {
+        match field & 0b11 {
+            0b11 => Authentication::Enabled,
+            0b10 => Authentication::Disabled,
+            _ => Authentication::NotImplemented,
+        }
+    }
+}
+
+impl fmt::Display for Authentication {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Authentication::NotImplemented => "not implemented",
+            Authentication::Disabled => "disabled",
+            Authentication::Enabled => "enabled",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// Which kinds of debugging the authentication interface allows.
+///
+/// Invasive debugging halts the core and accesses its registers and memory, non-invasive
+/// debugging only traces it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DebugAuthentication {
+    pub non_secure_invasive: Authentication,
+    pub non_secure_non_invasive: Authentication,
+    pub secure_invasive: Authentication,
+    pub secure_non_invasive: Authentication,
+}
+
+impl DebugAuthentication {
+    pub fn from_dauthstatus(dauthstatus: u32) -> Self {
+        DebugAuthentication {
+            non_secure_invasive: Authentication::from_field(dauthstatus),
+            non_secure_non_invasive: Authentication::from_field(dauthstatus >> 2),
+            secure_invasive: Authentication::from_field(dauthstatus >> 4),
+            secure_non_invasive: Authentication::from_field(dauthstatus >> 6),
+        }
+    }
+
+    /// Reads DAUTHSTATUS, which only ARMv8-M cores implement.
+    pub fn read(probe: &mut MasterProbe) -> Result<Self, AccessPortError> {
+        Ok(Self::from_dauthstatus(probe.read32(DAUTHSTATUS)?))
+    }
+}
+
+impl fmt::Display for DebugAuthentication {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "non-secure debug {}, secure debug {}",
+            self.non_secure_invasive, self.secure_invasive
+        )?;
+        if self.secure_non_invasive != self.secure_invasive {
+            write!(f, ", secure trace {}", self.secure_non_invasive)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Authentication, DebugAuthentication};
+
+    #[test]
+    fn decodes_dauthstatus() {
+        let authentication = DebugAuthentication::from_dauthstatus(0b1110_1111);
+        assert_eq!(authentication.non_secure_invasive, Authentication::Enabled);
+        assert_eq!(authentication.secure_invasive, Authentication::Disabled);
+        assert_eq!(authentication.secure_non_invasive, Authentication::Enabled);
+        assert_eq!(
+            authentication.to_string(),
+            "non-secure debug enabled, secure debug disabled, secure trace enabled"
+        );
+
+        let authentication = DebugAuthentication::from_dauthstatus(0b0000_1111);
+        assert_eq!(
+            authentication.secure_invasive,
+            Authentication::NotImplemented
+        );
+    }
+}
@@ -0,0 +1,763 @@
+//! The Xtensa LX6 and LX7 cores of the ESP32 and ESP32-S3, debugged through the On-Chip Debug
+//! module (OCD) behind their JTAG TAP.
+//!
+//! The OCD module is controlled through its Nexus registers. Once the core is halted, it
+//! executes instructions written to the Debug Instruction Register, which exchange data with
+//! the debugger through the Debug Data Register DDR. Registers and memory are accessed that way,
+//! with `a3` as scratch register which is restored after each access.
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::event::HaltReason;
+use crate::probe::debug_probe::{CpuInformation, DebugProbeError, MasterProbe};
+use crate::probe::jtag::{JtagAccess, JtagChain, Scan};
+use crate::probe::protocol::ResetType;
+use crate::target::{Architecture, BasicRegisterAddresses, Core, CoreRegisterAddress, CoreStatus};
+
+use std::time::{Duration, Instant};
+
+/// The chain of the ESP32 and ESP32-S3, which have a TAP for each core. The first core is
+/// debugged, the second one is left alone.
+const CHAIN: JtagChain = JtagChain {
+    ir_lengths: &[5, 5],
+    tap: 0,
+};
+
+// Instructions of the TAP, and the lengths of their data registers.
+const TAPINS_PWRCTL: u32 = 0x08;
+const TAPINS_NARSEL: u32 = 0x1C;
+const PWRCTL_LEN: u8 = 8;
+const NAR_LEN: u8 = 8;
+const NDR_LEN: u8 = 32;
+
+// The Nexus registers of the OCD module.
+const NARADR_DCRCLR: u8 = 0x42;
+const NARADR_DCRSET: u8 = 0x43;
+const NARADR_DSR: u8 = 0x44;
+const NARADR_DDR: u8 = 0x45;
+const NARADR_DDREXEC: u8 = 0x46;
+const NARADR_DIR0EXEC: u8 = 0x47;
+const NARADR_DIR0: u8 = 0x48;
+
+const DCR_ENABLEOCD: u32 = 1 << 0;
+const DCR_DEBUGINTERRUPT: u32 = 1 << 1;
+
+const DSR_EXECDONE: u32 = 1 << 0;
+const DSR_EXECEXCEPTION: u32 = 1 << 1;
+const DSR_EXECBUSY: u32 = 1 << 2;
+const DSR_EXECOVERRUN: u32 = 1 << 3;
+const DSR_STOPPED: u32 = 1 << 4;
+
+const PWRCTL_COREWAKEUP: u64 = 1 << 0;
+const PWRCTL_MEMWAKEUP: u64 = 1 << 1;
+const PWRCTL_DEBUGWAKEUP: u64 = 1 << 2;
+const PWRCTL_CORERESET: u64 = 1 << 4;
+const PWRCTL_JTAGDEBUGUSE: u64 = 1 << 7;
+
+/// Keeps the core and the debug module powered while the debugger is attached.
+const PWRCTL_ATTACHED: u64 =
+    PWRCTL_JTAGDEBUGUSE | PWRCTL_DEBUGWAKEUP | PWRCTL_MEMWAKEUP | PWRCTL_COREWAKEUP;
+
+/// The interrupt level of debug exceptions, which save PC and PS in EPC6 and EPS6.
+const DEBUG_LEVEL: u8 = 6;
+
+// Special registers.
+const SR_IBREAKENABLE: u8 = 96;
+const SR_DDR: u8 = 104;
+const SR_IBREAKA0: u8 = 128;
+const SR_DBREAKA0: u8 = 144;
+const SR_DBREAKC0: u8 = 160;
+const SR_EPC_DEBUG: u8 = 176 + DEBUG_LEVEL;
+const SR_EPS_DEBUG: u8 = 192 + DEBUG_LEVEL;
+const SR_ICOUNT: u8 = 236;
+const SR_ICOUNTLEVEL: u8 = 237;
+
+const DBREAKC_STORE: u32 = 1 << 30;
+const DBREAKC_LOAD: u32 = 1 << 31;
+
+/// The number of instruction and data breakpoints of the cores.
+const BREAKPOINT_UNITS: u32 = 2;
+pub const WATCHPOINT_UNITS: usize = 2;
+
+/// The address register used to move data between DDR and special registers or memory.
+const SCRATCH: u8 = 3;
+
+/// How long an instruction executed by the OCD module may take.
+const EXECUTION_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Returns from the debug exception, which resumes the core.
+const RFDO: u32 = 0xF1_E000;
+
+/// `rsr at, sr`, reads special register `sr` into `at`.
+fn rsr(sr: u8, at: u8) -> u32 {
+    0x03_0000 | u32::from(sr) << 8 | u32::from(at) << 4
+}
+
+/// `wsr at, sr`, writes `at` into special register `sr`.
+fn wsr(sr: u8, at: u8) -> u32 {
+    0x13_0000 | u32::from(sr) << 8 | u32::from(at) << 4
+}
+
+/// `lddr32.p as`, loads the word at `as` into DDR and increments `as` by 4.
+fn lddr32_p(address: u8) -> u32 {
+    0x00_70E0 | u32::from(address) << 8
+}
+
+/// `sddr32.p as`, stores DDR at `as` and increments `as` by 4.
+fn sddr32_p(address: u8) -> u32 {
+    0x00_70F0 | u32::from(address) << 8
+}
+
+/// The program counter and processor state the core returns to from the debug exception.
+pub const PC: CoreRegisterAddress = CoreRegisterAddress(16);
+pub const PS: CoreRegisterAddress = CoreRegisterAddress(17);
+
+/// The address registers `a0` to `a15` are numbered 0 to 15. The arguments of the windowed
+/// ABI are passed in `a2` to `a7`, and `a0` holds the return address.
+pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
+    R0: CoreRegisterAddress(2),
+    R1: CoreRegisterAddress(3),
+    R2: CoreRegisterAddress(4),
+    R3: CoreRegisterAddress(5),
+    R4: CoreRegisterAddress(6),
+    R9: CoreRegisterAddress(11),
+    PC,
+    LR: CoreRegisterAddress(0),
+    SP: CoreRegisterAddress(1),
+    XPSR: PS,
+};
+
+pub use super::WatchKind;
+
+/// Shifts the scans through the TAP of the debugged core.
+fn scan(jtag: &mut dyn JtagAccess, scans: &[Scan]) -> Result<Vec<u64>, DebugProbeError> {
+    CHAIN.scan(jtag, scans)
+}
+
+/// The scans which select a Nexus register and read or write it, after the TAP was
+/// switched to NARSEL.
+fn nexus_scans(register: u8, write: Option<u32>) -> [Scan; 2] {
+    [
+        Scan::Dr {
+            data: u64::from(register) << 1 | u64::from(write.is_some()),
+            len: NAR_LEN,
+        },
+        Scan::Dr {
+            data: u64::from(write.unwrap_or(0)),
+            len: NDR_LEN,
+        },
+    ]
+}
+
+fn read_nexus(jtag: &mut dyn JtagAccess, register: u8) -> Result<u32, DebugProbeError> {
+    let mut scans = vec![Scan::Ir(TAPINS_NARSEL)];
+    scans.extend_from_slice(&nexus_scans(register, None));
+
+    Ok(scan(jtag, &scans)?[1] as u32)
+}
+
+fn write_nexus(jtag: &mut dyn JtagAccess, register: u8, value: u32) -> Result<(), DebugProbeError> {
+    let mut scans = vec![Scan::Ir(TAPINS_NARSEL)];
+    scans.extend_from_slice(&nexus_scans(register, Some(value)));
+
+    scan(jtag, &scans)?;
+    Ok(())
+}
+
+fn write_pwrctl(jtag: &mut dyn JtagAccess, value: u64) -> Result<(), DebugProbeError> {
+    scan(
+        jtag,
+        &[
+            Scan::Ir(TAPINS_PWRCTL),
+            Scan::Dr {
+                data: value,
+                len: PWRCTL_LEN,
+            },
+        ],
+    )?;
+    Ok(())
+}
+
+/// Executes `instruction` on the halted core.
+fn execute(jtag: &mut dyn JtagAccess, instruction: u32) -> Result<(), DebugProbeError> {
+    write_nexus(jtag, NARADR_DIR0EXEC, instruction)
+}
+
+/// Checks that the instructions executed since the last check completed without an
+/// exception, and clears their status.
+fn check_execution(jtag: &mut dyn JtagAccess) -> Result<(), DebugProbeError> {
+    let start = Instant::now();
+    let dsr = loop {
+        let dsr = read_nexus(jtag, NARADR_DSR)?;
+        if dsr & DSR_EXECBUSY == 0 {
+            break dsr;
+        }
+        if start.elapsed() > EXECUTION_TIMEOUT {
+            return Err(DebugProbeError::Timeout);
+        }
+    };
+
+    // The status bits are cleared by writing ones.
+    write_nexus(
+        jtag,
+        NARADR_DSR,
+        DSR_EXECDONE | DSR_EXECEXCEPTION | DSR_EXECOVERRUN,
+    )?;
+
+    if dsr & (DSR_EXECEXCEPTION | DSR_EXECOVERRUN) != 0 {
+        return Err(DebugProbeError::InstructionFailed);
+    }
+
+    Ok(())
+}
+
+fn read_address_register(jtag: &mut dyn JtagAccess, register: u8) -> Result<u32, DebugProbeError> {
+    execute(jtag, wsr(SR_DDR, register))?;
+    read_nexus(jtag, NARADR_DDR)
+}
+
+fn write_address_register(
+    jtag: &mut dyn JtagAccess,
+    register: u8,
+    value: u32,
+) -> Result<(), DebugProbeError> {
+    write_nexus(jtag, NARADR_DDR, value)?;
+    execute(jtag, rsr(SR_DDR, register))
+}
+
+fn read_special_register(jtag: &mut dyn JtagAccess, register: u8) -> Result<u32, DebugProbeError> {
+    let saved = read_address_register(jtag, SCRATCH)?;
+    execute(jtag, rsr(register, SCRATCH))?;
+    let value = read_address_register(jtag, SCRATCH)?;
+    write_address_register(jtag, SCRATCH, saved)?;
+
+    check_execution(jtag)?;
+    Ok(value)
+}
+
+fn write_special_register(
+    jtag: &mut dyn JtagAccess,
+    register: u8,
+    value: u32,
+) -> Result<(), DebugProbeError> {
+    let saved = read_address_register(jtag, SCRATCH)?;
+    write_address_register(jtag, SCRATCH, value)?;
+    execute(jtag, wsr(register, SCRATCH))?;
+    write_address_register(jtag, SCRATCH, saved)?;
+
+    check_execution(jtag)
+}
+
+/// Reads words from the memory of the halted core.
+fn read_words(
+    jtag: &mut dyn JtagAccess,
+    address: u32,
+    data: &mut [u32],
+) -> Result<(), DebugProbeError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let saved = read_address_register(jtag, SCRATCH)?;
+    write_address_register(jtag, SCRATCH, address)?;
+
+    // Executing the load puts the first word into DDR. Reading DDREXEC executes the load
+    // again, so the next word is read with each word but the last one.
+    let mut scans = vec![Scan::Ir(TAPINS_NARSEL)];
+    scans.extend_from_slice(&nexus_scans(NARADR_DIR0EXEC, Some(lddr32_p(SCRATCH))));
+    for i in 0..data.len() {
+        let register = if i + 1 == data.len() {
+            NARADR_DDR
+        } else {
+            NARADR_DDREXEC
+        };
+        scans.extend_from_slice(&nexus_scans(register, None));
+    }
+    let values = scan(jtag, &scans)?;
+
+    // Each Nexus access has a scan to select the register and one with the data.
+    for (word, value) in data.iter_mut().zip(values.iter().skip(3).step_by(2)) {
+        *word = *value as u32;
+    }
+
+    write_address_register(jtag, SCRATCH, saved)?;
+    check_execution(jtag)
+}
+
+/// Writes words to the memory of the halted core.
+fn write_words(
+    jtag: &mut dyn JtagAccess,
+    address: u32,
+    data: &[u32],
+) -> Result<(), DebugProbeError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let saved = read_address_register(jtag, SCRATCH)?;
+    write_address_register(jtag, SCRATCH, address)?;
+
+    // Writing DDREXEC executes the store in DIR0 after each word.
+    let mut scans = vec![Scan::Ir(TAPINS_NARSEL)];
+    scans.extend_from_slice(&nexus_scans(NARADR_DIR0, Some(sddr32_p(SCRATCH))));
+    for word in data {
+        scans.extend_from_slice(&nexus_scans(NARADR_DDREXEC, Some(*word)));
+    }
+    scan(jtag, &scans)?;
+
+    write_address_register(jtag, SCRATCH, saved)?;
+    check_execution(jtag)
+}
+
+/// Accesses the memory through the OCD module, which requires the core to be halted.
+///
+/// The accesses are done in words, bytes are read and written within the words around them.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct XtensaMemoryInterface;
+
+impl XtensaMemoryInterface {
+    fn jtag(probe: &mut MasterProbe) -> Result<&mut dyn JtagAccess, AccessPortError> {
+        probe
+            .jtag()
+            .map_err(|_| AccessPortError::RegisterReadError {
+                addr: NARADR_DDR,
+                name: "DDR",
+            })
+    }
+
+    pub fn read32(&self, probe: &mut MasterProbe, address: u32) -> Result<u32, AccessPortError> {
+        let mut data = [0];
+        self.read_block32(probe, address, &mut data)?;
+        Ok(data[0])
+    }
+
+    pub fn read8(&self, probe: &mut MasterProbe, address: u32) -> Result<u8, AccessPortError> {
+        let mut data = [0];
+        self.read_block8(probe, address, &mut data)?;
+        Ok(data[0])
+    }
+
+    pub fn read_block32(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: &mut [u32],
+    ) -> Result<(), AccessPortError> {
+        if address % 4 != 0 {
+            return Err(AccessPortError::MemoryNotAligned);
+        }
+
+        read_words(Self::jtag(probe)?, address, data).map_err(|_| {
+            AccessPortError::RegisterReadError {
+                addr: NARADR_DDR,
+                name: "DDR",
+            }
+        })
+    }
+
+    pub fn read_block8(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), AccessPortError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let start = address & !3;
+        let end = address.wrapping_add(data.len() as u32).wrapping_add(3) & !3;
+        let mut words = vec![0; ((end - start) / 4) as usize];
+        self.read_block32(probe, start, &mut words)?;
+
+        let bytes: Vec<u8> = words
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect();
+        let offset = (address - start) as usize;
+        data.copy_from_slice(&bytes[offset..offset + data.len()]);
+
+        Ok(())
+    }
+
+    pub fn write32(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: u32,
+    ) -> Result<(), AccessPortError> {
+        self.write_block32(probe, address, &[data])
+    }
+
+    pub fn write8(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: u8,
+    ) -> Result<(), AccessPortError> {
+        self.write_block8(probe, address, &[data])
+    }
+
+    pub fn write_block32(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: &[u32],
+    ) -> Result<(), AccessPortError> {
+        if address % 4 != 0 {
+            return Err(AccessPortError::MemoryNotAligned);
+        }
+
+        write_words(Self::jtag(probe)?, address, data).map_err(|_| {
+            AccessPortError::RegisterWriteError {
+                addr: NARADR_DDR,
+                name: "DDR",
+            }
+        })
+    }
+
+    pub fn write_block8(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), AccessPortError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // The words at both ends are read first, so the bytes around the data are kept.
+        let start = address & !3;
+        let end = address.wrapping_add(data.len() as u32).wrapping_add(3) & !3;
+        let mut bytes = vec![0; (end - start) as usize];
+        let offset = (address - start) as usize;
+        if offset != 0 {
+            self.read_block8(probe, start, &mut bytes[..4])?;
+        }
+        if (address as usize + data.len()) % 4 != 0 {
+            let last = bytes.len() - 4;
+            self.read_block8(probe, end - 4, &mut bytes[last..])?;
+        }
+        bytes[offset..offset + data.len()].copy_from_slice(data);
+
+        let words: Vec<u32> = bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        self.write_block32(probe, start, &words)
+    }
+}
+
+/// Sets data breakpoint `unit` to halt the core on accesses of `kind` to the `len` bytes at
+/// `address`. `len` is a power of two up to 64, and `address` has to be aligned to it.
+pub fn set_watchpoint(
+    mi: &mut MasterProbe,
+    unit: usize,
+    address: u32,
+    len: u32,
+    kind: WatchKind,
+) -> Result<(), DebugProbeError> {
+    if unit >= WATCHPOINT_UNITS {
+        return Err(DebugProbeError::BreakpointUnitsExhausted);
+    }
+    if !len.is_power_of_two() || len > 64 || address % len != 0 {
+        return Err(DebugProbeError::BreakpointAddressUnsupported(address));
+    }
+
+    // The mask selects the address bits which are compared.
+    let mut control = 0x3F ^ (len - 1);
+    control |= match kind {
+        WatchKind::Read => DBREAKC_LOAD,
+        WatchKind::Write => DBREAKC_STORE,
+        WatchKind::ReadWrite => DBREAKC_LOAD | DBREAKC_STORE,
+    };
+
+    let jtag = mi.jtag()?;
+    write_special_register(jtag, SR_DBREAKA0 + unit as u8, address)?;
+    write_special_register(jtag, SR_DBREAKC0 + unit as u8, control)
+}
+
+/// Disables data breakpoint `unit`.
+pub fn clear_watchpoint(mi: &mut MasterProbe, unit: usize) -> Result<(), DebugProbeError> {
+    if unit >= WATCHPOINT_UNITS {
+        return Err(DebugProbeError::BreakpointUnitsExhausted);
+    }
+
+    write_special_register(mi.jtag()?, SR_DBREAKC0 + unit as u8, 0)
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Xtensa;
+
+impl Xtensa {
+    /// Finds the instruction breakpoint set to `addr`.
+    fn find_breakpoint(
+        &self,
+        jtag: &mut dyn JtagAccess,
+        addr: u32,
+    ) -> Result<Option<u8>, DebugProbeError> {
+        for unit in 0..BREAKPOINT_UNITS as u8 {
+            if read_special_register(jtag, SR_IBREAKA0 + unit)? == addr {
+                return Ok(Some(unit));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn set_breakpoint_enabled(
+        &self,
+        mi: &mut MasterProbe,
+        addr: u32,
+        enabled: bool,
+    ) -> Result<(), DebugProbeError> {
+        let jtag = mi.jtag()?;
+        let unit = self
+            .find_breakpoint(jtag, addr)?
+            .ok_or(DebugProbeError::BreakpointAddressUnsupported(addr))?;
+
+        let mut enable = read_special_register(jtag, SR_IBREAKENABLE)?;
+        if enabled {
+            enable |= 1 << unit;
+        } else {
+            enable &= !(1 << unit);
+        }
+        write_special_register(jtag, SR_IBREAKENABLE, enable)
+    }
+}
+
+impl Core for Xtensa {
+    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        let start = Instant::now();
+        loop {
+            let dsr = read_nexus(mi.jtag()?, NARADR_DSR)?;
+            if dsr & DSR_STOPPED != 0 {
+                // The interrupt stays pending otherwise, and halts the core again right after
+                // it resumes.
+                write_nexus(mi.jtag()?, NARADR_DCRCLR, DCR_DEBUGINTERRUPT)?;
+                mi.set_core_halted(true);
+                return Ok(());
+            }
+            if start.elapsed() > mi.retry_policy().halt_timeout {
+                return Err(DebugProbeError::Timeout);
+            }
+            mi.cancellation_token().check()?;
+        }
+    }
+
+    /// The OCD does not record why the core stopped.
+    fn status(&self, mi: &mut MasterProbe) -> Result<CoreStatus, DebugProbeError> {
+        let dsr = read_nexus(mi.jtag()?, NARADR_DSR)?;
+
+        Ok(if dsr & DSR_STOPPED != 0 {
+            CoreStatus::Halted(HaltReason::Unknown)
+        } else {
+            CoreStatus::Running
+        })
+    }
+
+    fn halt(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
+        write_nexus(
+            mi.jtag()?,
+            NARADR_DCRSET,
+            DCR_ENABLEOCD | DCR_DEBUGINTERRUPT,
+        )?;
+        // Nothing can be executed before the core is halted.
+        self.wait_for_core_halted(mi)?;
+
+        let pc = self.read_core_reg(mi, PC)?;
+        Ok(CpuInformation { pc })
+    }
+
+    fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.set_core_halted(false);
+        execute(mi.jtag()?, RFDO)
+    }
+
+    fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.set_core_halted(false);
+
+        let jtag = mi.jtag()?;
+        write_pwrctl(jtag, PWRCTL_ATTACHED | PWRCTL_CORERESET)?;
+        write_pwrctl(jtag, PWRCTL_ATTACHED)
+    }
+
+    /// The core is reset through the power control register of the TAP, which is the only
+    /// software reset available over JTAG.
+    fn reset_with(
+        &self,
+        mi: &mut MasterProbe,
+        reset_type: ResetType,
+    ) -> Result<(), DebugProbeError> {
+        match reset_type {
+            ResetType::Hardware => {
+                mi.set_core_halted(false);
+                mi.target_reset()
+            }
+            ResetType::SysResetReq => self.reset(mi),
+            ResetType::VectReset | ResetType::Core => {
+                Err(DebugProbeError::ResetTypeUnsupported(reset_type))
+            }
+        }
+    }
+
+    fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.set_core_halted(false);
+
+        // The debug interrupt is requested while the core is held in reset, so it halts before
+        // executing the first instruction.
+        let jtag = mi.jtag()?;
+        write_pwrctl(jtag, PWRCTL_ATTACHED | PWRCTL_CORERESET)?;
+        write_nexus(jtag, NARADR_DCRSET, DCR_ENABLEOCD | DCR_DEBUGINTERRUPT)?;
+        write_pwrctl(jtag, PWRCTL_ATTACHED)?;
+
+        self.wait_for_core_halted(mi)
+    }
+
+    fn step(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError> {
+        let jtag = mi.jtag()?;
+        // ICOUNT raises the debug exception when it overflows, after one instruction. It
+        // is incremented by RFDO as well, which makes it start at -2.
+        write_special_register(jtag, SR_ICOUNTLEVEL, u32::from(DEBUG_LEVEL))?;
+        write_special_register(jtag, SR_ICOUNT, 0xFFFF_FFFE)?;
+
+        self.run(mi)?;
+        self.wait_for_core_halted(mi)?;
+
+        write_special_register(mi.jtag()?, SR_ICOUNTLEVEL, 0)?;
+
+        let pc = self.read_core_reg(mi, PC)?;
+        Ok(CpuInformation { pc })
+    }
+
+    fn read_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+    ) -> Result<u32, DebugProbeError> {
+        let jtag = mi.jtag()?;
+        match addr.0 {
+            register @ 0..=15 => {
+                let value = read_address_register(jtag, register)?;
+                check_execution(jtag)?;
+                Ok(value)
+            }
+            16 => read_special_register(jtag, SR_EPC_DEBUG),
+            17 => read_special_register(jtag, SR_EPS_DEBUG),
+            _ => Err(DebugProbeError::UnknownError),
+        }
+    }
+
+    fn write_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        let jtag = mi.jtag()?;
+        match addr.0 {
+            register @ 0..=15 => {
+                write_address_register(jtag, register, value)?;
+                check_execution(jtag)
+            }
+            16 => write_special_register(jtag, SR_EPC_DEBUG, value),
+            17 => write_special_register(jtag, SR_EPS_DEBUG, value),
+            _ => Err(DebugProbeError::UnknownError),
+        }
+    }
+
+    fn get_available_breakpoint_units(
+        &self,
+        _mi: &mut MasterProbe,
+    ) -> Result<u32, DebugProbeError> {
+        Ok(BREAKPOINT_UNITS)
+    }
+
+    /// The instruction breakpoints are always active while the OCD module is enabled, only
+    /// disabling clears them.
+    fn enable_breakpoints(&self, mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError> {
+        if !state {
+            write_special_register(mi.jtag()?, SR_IBREAKENABLE, 0)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        let jtag = mi.jtag()?;
+        let enable = read_special_register(jtag, SR_IBREAKENABLE)?;
+        let unit = (0..BREAKPOINT_UNITS as u8)
+            .find(|unit| enable & (1 << unit) == 0)
+            .ok_or(DebugProbeError::BreakpointUnitsExhausted)?;
+
+        write_special_register(jtag, SR_IBREAKA0 + unit, addr)?;
+        write_special_register(jtag, SR_IBREAKENABLE, enable | 1 << unit)
+    }
+
+    fn enable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        self.set_breakpoint_enabled(mi, addr, true)
+    }
+
+    fn disable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        self.set_breakpoint_enabled(mi, addr, false)
+    }
+
+    fn read_block8(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), DebugProbeError> {
+        Ok(XtensaMemoryInterface.read_block8(mi, address, data)?)
+    }
+
+    fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
+        &REGISTERS
+    }
+
+    fn detach(
+        &self,
+        mi: &mut MasterProbe,
+        _vector_catch: Option<u32>,
+        resume: bool,
+    ) -> Result<(), DebugProbeError> {
+        let jtag = mi.jtag()?;
+        let halted = read_nexus(jtag, NARADR_DSR)? & DSR_STOPPED != 0;
+
+        if halted {
+            write_special_register(jtag, SR_IBREAKENABLE, 0)?;
+            for unit in 0..WATCHPOINT_UNITS as u8 {
+                write_special_register(jtag, SR_DBREAKC0 + unit, 0)?;
+            }
+        }
+
+        if resume {
+            mi.set_core_halted(false);
+
+            let jtag = mi.jtag()?;
+            if halted {
+                execute(jtag, RFDO)?;
+            }
+            write_nexus(jtag, NARADR_DCRCLR, DCR_ENABLEOCD)?;
+        }
+
+        Ok(())
+    }
+
+    fn architecture(&self) -> Architecture {
+        Architecture::Xtensa
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lddr32_p, rsr, sddr32_p, wsr, SCRATCH, SR_DDR, SR_EPC_DEBUG};
+
+    #[test]
+    fn encodes_debug_instructions() {
+        assert_eq!(rsr(SR_DDR, SCRATCH), 0x03_6830);
+        assert_eq!(wsr(SR_DDR, SCRATCH), 0x13_6830);
+        assert_eq!(rsr(SR_EPC_DEBUG, 2), 0x03_B620);
+        assert_eq!(lddr32_p(SCRATCH), 0x00_73E0);
+        assert_eq!(sddr32_p(SCRATCH), 0x00_73F0);
+    }
+}
@@ -12,6 +12,20 @@ use crate::target::Core;
 use crate::target::Target;
 
 pub fn get_target(name: impl AsRef<str>) -> Option<Target> {
+    let name: String = name.as_ref().into();
+
+    load_registered_targets().remove(&name.to_ascii_lowercase())
+}
+
+/// Returns the targets registered in `~/.config/probe-rs/targets`.
+pub fn get_targets() -> Vec<Target> {
+    load_registered_targets()
+        .into_iter()
+        .map(|(_, target)| target)
+        .collect()
+}
+
+fn load_registered_targets() -> HashMap<String, Target> {
     let mut map: HashMap<String, Target> = HashMap::new();
 
     load_targets(
@@ -22,9 +36,16 @@ pub fn get_target(name: impl AsRef<str>) -> Option<Target> {
         &mut map,
     );
 
-    let name: String = name.as_ref().into();
+    map
+}
 
-    map.get(&name.to_ascii_lowercase()).cloned()
+/// Returns the base definition `name` registered in `~/.config/probe-rs/bases`.
+pub fn get_base(name: impl AsRef<str>) -> Option<String> {
+    let path = dirs::home_dir()?
+        .join(".config/probe-rs/bases")
+        .join(format!("{}.yaml", name.as_ref()));
+
+    fs::read_to_string(path).ok()
 }
 
 pub fn get_algorithm(name: impl AsRef<str>) -> Option<FlashAlgorithm> {
@@ -49,13 +70,28 @@ pub fn get_algorithm(name: impl AsRef<str>) -> Option<FlashAlgorithm> {
 
 pub fn get_core(name: impl AsRef<str>) -> Option<Box<dyn Core>> {
     let map: HashMap<&'static str, Box<dyn Core>> = hashmap! {
-        "M0" => Box::new(self::cores::m0::M0) as _,
-        "M4" => Box::new(self::cores::m4::M4) as _,
+        "M0" => Box::new(self::cores::m0::M0::default()) as _,
+        "M4" => Box::new(self::cores::m4::M4::default()) as _,
+        "Xtensa" => Box::new(self::cores::xtensa::Xtensa::default()) as _,
     };
 
     map.get(name.as_ref()).cloned()
 }
 
+/// Returns a core whose debug registers are at `debug_base` on the MEM-AP `ap`, which differs
+/// between chips with the same core, unlike those of Cortex-M cores.
+pub fn get_core_at(name: impl AsRef<str>, ap: u8, debug_base: u32) -> Option<Box<dyn Core>> {
+    match name.as_ref() {
+        "CortexA" => Some(Box::new(self::cores::cortex_a::CortexA::new(
+            ap, debug_base,
+        ))),
+        "CortexR" => Some(Box::new(self::cores::cortex_a::CortexA::cortex_r(
+            ap, debug_base,
+        ))),
+        _ => None,
+    }
+}
+
 pub fn load_targets(root: Option<&Path>, map: &mut HashMap<String, Target>) {
     if let Some(root) = root {
         visit_dirs(root, map, &load_targets_from_dir).unwrap();
@@ -65,19 +101,13 @@ pub fn load_targets(root: Option<&Path>, map: &mut HashMap<String, Target>) {
 }
 
 pub fn load_targets_from_dir(dir: &DirEntry, map: &mut HashMap<String, Target>) {
-    match File::open(dir.path()) {
-        Ok(file) => {
-            let reader = BufReader::new(file);
-
-            // Read the JSON contents of the file as an instance of `User`.
-            match serde_yaml::from_reader(reader) as serde_yaml::Result<Target> {
-                Ok(mut target) => {
-                    target.name.make_ascii_lowercase();
-                    map.insert(target.name.clone(), target);
-                }
-                Err(e) => log::warn!("Error loading chip definition: {}", e),
+    match fs::read_to_string(dir.path()) {
+        Ok(definition) => match Target::with_bases(&definition, |name| get_base(name)) {
+            Ok(target) => {
+                map.insert(target.name.to_ascii_lowercase(), target);
             }
-        }
+            Err(e) => log::warn!("Error loading chip definition: {}", e),
+        },
         Err(e) => {
             log::info!("Unable to load file {:?}.", dir.path());
             log::info!("Reason: {:?}", e);
@@ -0,0 +1,312 @@
+//! Settings that would otherwise have to be given to every command.
+//!
+//! The user configuration is read from `~/.config/probe-rs/config.toml`. A project can override
+//! it with a `.probe-rs.toml` in its directory or any of its parents, e.g.
+//!
+//! ```toml
+//! target = "nRF52832"
+//!
+//! [probe]
+//! serial = "0671FF545654887067152135"
+//! protocol = "swd"
+//! speed = 4000
+//!
+//! [rtt]
+//! channel = 1
+//!
+//! [console]
+//! buffer = "LOG_BUFFER"
+//! write_index = "LOG_HEAD"
+//! ```
+//!
+//! Environment variables take precedence over both files, for CI pipelines which cannot easily
+//! edit them: `PROBE_RS_CHIP`, `PROBE_RS_PROBE` (the serial number), `PROBE_RS_PROBE_INDEX`,
+//! `PROBE_RS_PROTOCOL`, `PROBE_RS_SPEED`, `PROBE_RS_TARGET_SEL` (in hex), `PROBE_RS_RESET_TYPE`,
+//! `PROBE_RS_RECORD`, `PROBE_RS_RECOVER_LOCKUP` (`true` or `false`), `PROBE_RS_RTT_CHANNEL`,
+//! `PROBE_RS_RTT_DOWN_CHANNEL` and `PROBE_RS_RTT_DEFMT`.
+
+use crate::probe::protocol::{ResetType, WireProtocol};
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Name of the configuration file of a project.
+pub const PROJECT_CONFIG: &str = ".probe-rs.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// An environment variable, given by name, has an invalid value.
+    InvalidVariable(&'static str, String),
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(_, e) => Some(e),
+            ConfigError::Parse(_, e) => Some(e),
+            ConfigError::InvalidVariable(..) => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "Invalid config {}: {}", path.display(), e),
+            ConfigError::InvalidVariable(name, value) => {
+                write!(f, "Invalid value '{}' of {}.", value, name)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The target to use if none is given.
+    pub target: Option<String>,
+    pub probe: ProbeConfig,
+    pub rtt: RttConfig,
+    pub console: ConsoleConfig,
+}
+
+/// Which probe to use and how it connects to the target.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeConfig {
+    /// The number of the probe in the list of connected probes.
+    pub index: Option<usize>,
+    /// The serial number of the probe, which stays the same when other probes are connected.
+    pub serial: Option<String>,
+    pub protocol: Option<WireProtocol>,
+    /// The clock of the wire protocol in kHz.
+    pub speed: Option<u32>,
+    /// The TARGETSEL value of the target on a multi-drop SWD bus.
+    pub target_sel: Option<u32>,
+    /// How the target is reset, instead of the default of the target.
+    pub reset_type: Option<ResetType>,
+    /// The file the traffic with the debug port is recorded to, see `probe::recording`.
+    pub record: Option<PathBuf>,
+    /// Whether to reset a core which locked up, see `Session::recover_lockup`.
+    pub recover_lockup: Option<bool>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RttConfig {
+    /// The up channel to read.
+    pub channel: Option<usize>,
+    /// The down channel input is forwarded to.
+    pub down_channel: Option<usize>,
+    /// Whether to decode the data as defmt log messages.
+    pub defmt: Option<bool>,
+}
+
+/// The symbols of the ring buffer a target without RTT writes its output to.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsoleConfig {
+    /// The array holding the output.
+    pub buffer: Option<String>,
+    /// The index the target writes the next byte to.
+    pub write_index: Option<String>,
+    /// The index the host reads the next byte from, if the target has one.
+    pub read_index: Option<String>,
+}
+
+impl Config {
+    /// Loads the user configuration, the configuration of the project the current directory
+    /// is in and the environment variables, each taking precedence over the previous ones.
+    /// Missing files are skipped.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+
+        if let Some(path) = dirs::home_dir().map(|home| home.join(".config/probe-rs/config.toml")) {
+            if path.is_file() {
+                config = config.merge(Config::from_file(&path)?);
+            }
+        }
+
+        if let Some(path) = env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_config(&dir))
+        {
+            config = config.merge(Config::from_file(&path)?);
+        }
+
+        Ok(config.merge(Config::from_env()?))
+    }
+
+    /// Reads the settings made with `PROBE_RS_*` environment variables.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Config::from_variables(|name| env::var(name).ok())
+    }
+
+    fn from_variables(variable: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        fn parse<T: FromStr>(
+            variable: &impl Fn(&str) -> Option<String>,
+            name: &'static str,
+        ) -> Result<Option<T>, ConfigError> {
+            match variable(name) {
+                Some(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| ConfigError::InvalidVariable(name, value)),
+                None => Ok(None),
+            }
+        }
+
+        let target_sel = match variable("PROBE_RS_TARGET_SEL") {
+            Some(value) => Some(
+                u32::from_str_radix(value.trim_start_matches("0x"), 16)
+                    .map_err(|_| ConfigError::InvalidVariable("PROBE_RS_TARGET_SEL", value))?,
+            ),
+            None => None,
+        };
+
+        Ok(Config {
+            target: variable("PROBE_RS_CHIP"),
+            probe: ProbeConfig {
+                index: parse(&variable, "PROBE_RS_PROBE_INDEX")?,
+                serial: variable("PROBE_RS_PROBE"),
+                protocol: parse(&variable, "PROBE_RS_PROTOCOL")?,
+                speed: parse(&variable, "PROBE_RS_SPEED")?,
+                target_sel,
+                reset_type: parse(&variable, "PROBE_RS_RESET_TYPE")?,
+                record: variable("PROBE_RS_RECORD").map(PathBuf::from),
+                recover_lockup: parse(&variable, "PROBE_RS_RECOVER_LOCKUP")?,
+            },
+            rtt: RttConfig {
+                channel: parse(&variable, "PROBE_RS_RTT_CHANNEL")?,
+                down_channel: parse(&variable, "PROBE_RS_RTT_DOWN_CHANNEL")?,
+                defmt: parse(&variable, "PROBE_RS_RTT_DEFMT")?,
+            },
+            console: ConsoleConfig::default(),
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Returns this configuration with the settings made in `other` replacing its own.
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            target: other.target.or(self.target),
+            probe: ProbeConfig {
+                index: other.probe.index.or(self.probe.index),
+                serial: other.probe.serial.or(self.probe.serial),
+                protocol: other.probe.protocol.or(self.probe.protocol),
+                speed: other.probe.speed.or(self.probe.speed),
+                target_sel: other.probe.target_sel.or(self.probe.target_sel),
+                reset_type: other.probe.reset_type.or(self.probe.reset_type),
+                record: other.probe.record.or(self.probe.record),
+                recover_lockup: other.probe.recover_lockup.or(self.probe.recover_lockup),
+            },
+            rtt: RttConfig {
+                channel: other.rtt.channel.or(self.rtt.channel),
+                down_channel: other.rtt.down_channel.or(self.rtt.down_channel),
+                defmt: other.rtt.defmt.or(self.rtt.defmt),
+            },
+            console: ConsoleConfig {
+                buffer: other.console.buffer.or(self.console.buffer),
+                write_index: other.console.write_index.or(self.console.write_index),
+                read_index: other.console.read_index.or(self.console.read_index),
+            },
+        }
+    }
+}
+
+/// Returns the project configuration in `dir` or the closest of its parents.
+fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+    use crate::probe::protocol::{ResetType, WireProtocol};
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn project_overrides_user() {
+        let user: Config = toml::from_str(
+            r#"
+            target = "nRF52832"
+
+            [probe]
+            protocol = "swd"
+            speed = 1000
+            "#,
+        )
+        .unwrap();
+        let project: Config = toml::from_str(
+            r#"
+            [probe]
+            speed = 4000
+
+            [rtt]
+            channel = 1
+            "#,
+        )
+        .unwrap();
+
+        let config = user.merge(project);
+
+        assert_eq!(config.target.as_ref().map(String::as_str), Some("nRF52832"));
+        assert_eq!(config.probe.protocol, Some(WireProtocol::Swd));
+        assert_eq!(config.probe.speed, Some(4000));
+        assert_eq!(config.rtt.channel, Some(1));
+    }
+
+    #[test]
+    fn reads_environment() {
+        let variables: HashMap<&str, &str> = [
+            ("PROBE_RS_CHIP", "STM32F042"),
+            ("PROBE_RS_PROTOCOL", "jtag"),
+            ("PROBE_RS_TARGET_SEL", "01002927"),
+            ("PROBE_RS_RESET_TYPE", "SysResetReq"),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let config =
+            Config::from_variables(|name| variables.get(name).map(|value| value.to_string()))
+                .unwrap();
+
+        assert_eq!(
+            config.target.as_ref().map(String::as_str),
+            Some("STM32F042")
+        );
+        assert_eq!(config.probe.protocol, Some(WireProtocol::Jtag));
+        assert_eq!(config.probe.target_sel, Some(0x0100_2927));
+        assert_eq!(config.probe.reset_type, Some(ResetType::SysResetReq));
+        assert_eq!(config.probe.speed, None);
+
+        assert!(Config::from_variables(|name| match name {
+            "PROBE_RS_SPEED" => Some("fast".to_string()),
+            _ => None,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(toml::from_str::<Config>("[probe]\nsped = 4000\n").is_err());
+    }
+}
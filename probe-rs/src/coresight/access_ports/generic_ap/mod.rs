@@ -4,6 +4,7 @@ use super::APRegister;
 use crate::coresight::ap_access::AccessPort;
 use crate::coresight::common::Register;
 use enum_primitive_derive::Primitive;
+use jep106::JEP106Code;
 use num_traits::cast::{FromPrimitive, ToPrimitive};
 
 #[allow(non_camel_case_types)]
@@ -72,3 +73,10 @@ define_ap_register!(
         | (u32::from(value.VARIANT) << 4)
         | (value.TYPE.to_u32().unwrap())
 );
+
+impl IDR {
+    /// Returns the designer of the AP, whose JEP106 code is stored in `DESIGNER`.
+    pub fn designer(&self) -> JEP106Code {
+        JEP106Code::new((self.DESIGNER >> 7) as u8, (self.DESIGNER & 0x7F) as u8)
+    }
+}
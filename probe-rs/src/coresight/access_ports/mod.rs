@@ -14,9 +14,17 @@ use crate::coresight::common::Register;
 pub enum AccessPortError {
     InvalidAccessPortNumber,
     MemoryNotAligned,
-    RegisterReadError { addr: u8, name: &'static str },
-    RegisterWriteError { addr: u8, name: &'static str },
+    RegisterReadError {
+        addr: u8,
+        name: &'static str,
+    },
+    RegisterWriteError {
+        addr: u8,
+        name: &'static str,
+    },
     OutOfBoundsError,
+    /// The byte written at the given address reads back differently.
+    VerifyError(u32),
 }
 
 impl Error for AccessPortError {}
@@ -39,6 +47,11 @@ impl fmt::Display for AccessPortError {
                 name, addr
             ),
             OutOfBoundsError => write!(f, "Out of bounds access"),
+            VerifyError(address) => write!(
+                f,
+                "The memory at 0x{:08x} reads back differently than written",
+                address
+            ),
         }
     }
 }
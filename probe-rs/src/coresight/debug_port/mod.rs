@@ -146,6 +146,38 @@ impl Register for Select {
     const NAME: &'static str = "SELECT";
 }
 
+bitfield! {
+    /// Selects a target on a multi-drop SWD bus.
+    #[derive(Clone)]
+    pub struct TargetSel(u32);
+    impl Debug;
+    pub u8, tinstance, set_tinstance: 31, 28;
+    pub u16, tpartno, set_tpartno: 27, 12;
+    pub u16, tdesigner, set_tdesigner: 11, 1;
+}
+
+impl From<u32> for TargetSel {
+    fn from(raw: u32) -> Self {
+        TargetSel(raw)
+    }
+}
+
+impl From<TargetSel> for u32 {
+    fn from(raw: TargetSel) -> Self {
+        // Bit 0 is reserved and must be written as one.
+        raw.0 | 1
+    }
+}
+
+impl DPRegister<DPv2> for TargetSel {
+    const DP_BANK: DPBankSel = DPBankSel::DontCare;
+}
+
+impl Register for TargetSel {
+    const ADDRESS: u8 = 0xC;
+    const NAME: &'static str = "TARGETSEL";
+}
+
 bitfield! {
     #[derive(Clone)]
     pub struct DPIDR(u32);
@@ -0,0 +1,253 @@
+//! Conditions of breakpoints, like `count > 10`, evaluated on the host whenever the breakpoint
+//! halts the core.
+//!
+//! A condition compares two operands with `==`, `!=`, `<`, `<=`, `>` or `>=`, or is a single
+//! operand which holds if it is not zero. Operands are integers, in decimal or in hex with a
+//! `0x` prefix, or static variables of an integer, boolean, enum or pointer type.
+
+use super::statics::{read_uint, sign_extend};
+use super::{DebugInfo, Encoding, Layout};
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// The comparison operators, longest first so `<=` is not taken for `<`.
+const OPERATORS: &[(&str, Comparison)] = &[
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+#[derive(Debug)]
+pub enum ConditionError {
+    /// The condition is not a comparison of two operands.
+    Syntax(String),
+    UnknownVariable(String),
+    /// The variable has a type which can not be compared, like a struct.
+    NotAnInteger(String),
+    AccessPort(AccessPortError),
+}
+
+impl Error for ConditionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConditionError::AccessPort(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConditionError::Syntax(condition) => {
+                write!(f, "'{}' is not a valid condition.", condition)
+            }
+            ConditionError::UnknownVariable(name) => {
+                write!(f, "No variable named '{}' found.", name)
+            }
+            ConditionError::NotAnInteger(name) => {
+                write!(f, "The variable '{}' is not an integer.", name)
+            }
+            ConditionError::AccessPort(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<AccessPortError> for ConditionError {
+    fn from(e: AccessPortError) -> Self {
+        ConditionError::AccessPort(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Literal(i64),
+    Variable(String),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Result<Self, ConditionError> {
+        let text = text.trim();
+        let digits = text.replace('_', "");
+
+        let literal = if digits.starts_with("0x") || digits.starts_with("0X") {
+            i64::from_str_radix(&digits[2..], 16).ok()
+        } else {
+            digits.parse().ok()
+        };
+
+        match literal {
+            Some(value) => Ok(Operand::Literal(value)),
+            None if is_identifier(text) => Ok(Operand::Variable(text.to_string())),
+            None => Err(ConditionError::Syntax(text.to_string())),
+        }
+    }
+
+    fn evaluate(
+        &self,
+        debug_info: Option<&DebugInfo>,
+        mi: &mut impl MI,
+    ) -> Result<i64, ConditionError> {
+        let name = match self {
+            Operand::Literal(value) => return Ok(*value),
+            Operand::Variable(name) => name,
+        };
+
+        let variable = debug_info
+            .and_then(|di| di.find_static(name))
+            .ok_or_else(|| ConditionError::UnknownVariable(name.clone()))?;
+
+        let mut data = vec![0u8; variable.size() as usize];
+        mi.read_block8(variable.address, &mut data)?;
+
+        integer(&variable.layout, &data).ok_or_else(|| ConditionError::NotAnInteger(name.clone()))
+    }
+}
+
+/// A condition of a breakpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    left: Operand,
+    /// The comparison with the right operand, or `None` if the left operand must not be zero.
+    right: Option<(Comparison, Operand)>,
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(condition: &str) -> Result<Self, Self::Err> {
+        for (operator, comparison) in OPERATORS {
+            if let Some(index) = condition.find(operator) {
+                let right = &condition[index + operator.len()..];
+                return Ok(Condition {
+                    left: Operand::parse(&condition[..index])?,
+                    right: Some((*comparison, Operand::parse(right)?)),
+                });
+            }
+        }
+
+        Ok(Condition {
+            left: Operand::parse(condition)?,
+            right: None,
+        })
+    }
+}
+
+impl Condition {
+    /// Reads the variables of the condition from the target and checks whether it holds.
+    ///
+    /// Without debug info, only conditions on literals can be evaluated.
+    pub fn evaluate(
+        &self,
+        debug_info: Option<&DebugInfo>,
+        mi: &mut impl MI,
+    ) -> Result<bool, ConditionError> {
+        let left = self.left.evaluate(debug_info, mi)?;
+
+        let (comparison, right) = match &self.right {
+            Some((comparison, right)) => (comparison, right.evaluate(debug_info, mi)?),
+            None => return Ok(left != 0),
+        };
+
+        Ok(match comparison {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        })
+    }
+}
+
+/// Variable names, which may include the namespaces, like `firmware::COUNT`.
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with(|c: char| c.is_ascii_digit())
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Decodes a value of an integer-like type, sign extending signed integers.
+fn integer(layout: &Layout, data: &[u8]) -> Option<i64> {
+    let (size, signed) = match layout {
+        Layout::Base { encoding, size, .. } => match encoding {
+            Encoding::Boolean | Encoding::Unsigned => (*size, false),
+            Encoding::Signed => (*size, true),
+            Encoding::Float => return None,
+        },
+        Layout::Pointer { size } => (*size, false),
+        Layout::Enum { size, signed, .. } => (*size, *signed),
+        _ => return None,
+    };
+
+    if size == 0 || size > 8 || data.len() < size as usize {
+        return None;
+    }
+
+    let size = size as usize;
+    let value = read_uint(&data[..size]);
+
+    Some(if signed {
+        sign_extend(value, size)
+    } else {
+        value as i64
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{integer, Comparison, Condition, Operand};
+    use crate::debug::{Encoding, Layout};
+
+    #[test]
+    fn parses_conditions() {
+        let condition: Condition = "count > 10".parse().unwrap();
+        assert_eq!(condition.left, Operand::Variable("count".to_string()));
+        assert_eq!(
+            condition.right,
+            Some((Comparison::Greater, Operand::Literal(10)))
+        );
+
+        let condition: Condition = "app::STATE<=0x1f".parse().unwrap();
+        assert_eq!(
+            condition.right,
+            Some((Comparison::LessOrEqual, Operand::Literal(0x1F)))
+        );
+
+        let condition: Condition = "ready".parse().unwrap();
+        assert_eq!(condition.right, None);
+
+        assert!("count > ".parse::<Condition>().is_err());
+        assert!("count + 1".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn decodes_signed_integers() {
+        let layout = Layout::Base {
+            name: "i16".to_string(),
+            encoding: Encoding::Signed,
+            size: 2,
+        };
+
+        assert_eq!(integer(&layout, &[0xFE, 0xFF]), Some(-2));
+    }
+}
@@ -1,6 +1,10 @@
+pub mod condition;
+pub mod statics;
 pub mod typ;
 pub mod variable;
 
+pub use condition::*;
+pub use statics::*;
 pub use typ::*;
 pub use variable::*;
 
@@ -11,7 +15,9 @@ use std::rc::Rc;
 use crate::memory::MI;
 use object::read::Object;
 
+use crate::collection::cores::s_register;
 use crate::session::Session;
+use crate::target::CoreRegisterAddress;
 
 #[derive(Debug, Copy, Clone)]
 pub enum ColumnType {
@@ -66,8 +72,11 @@ impl std::fmt::Display for StackFrame {
         for variable in &self.variables {
             writeln!(
                 f,
-                "\t\t{}: {}:{} = 0x{:08x}",
-                variable.name, variable.file, variable.line, variable.value
+                "\t\t{}: {}:{} = {}",
+                variable.name,
+                variable.file,
+                variable.line,
+                variable.format_value()
             )?;
         }
         write!(f, "")
@@ -159,6 +168,27 @@ impl<'a> StackFrameIterator<'a> {
             registers,
         }
     }
+
+    /// Unwinds from the given values of the registers R0 to R15 instead of those of the core,
+    /// like the registers an RTOS saved for a thread which is not running.
+    pub fn from_registers(
+        debug_info: &'a DebugInfo,
+        session: &'a mut Session,
+        values: [u32; 16],
+    ) -> Self {
+        let mut registers = Registers([None; 16]);
+        for (register, value) in registers.0.iter_mut().zip(values.iter()) {
+            *register = Some(*value);
+        }
+
+        Self {
+            debug_info,
+            session,
+            frame_count: 0,
+            pc: Some(u64::from(values[15] & !1)),
+            registers,
+        }
+    }
 }
 
 impl<'a> Iterator for StackFrameIterator<'a> {
@@ -191,6 +221,9 @@ impl<'a> Iterator for StackFrameIterator<'a> {
             gimli::CfaRule::Expression(_) => unimplemented!(),
         };
 
+        // The variables of the frame are located with its own registers, not the unwound ones.
+        let frame_registers = self.registers.clone();
+
         // generate previous registers
         for i in 0..16 {
             if i == 13 {
@@ -211,7 +244,7 @@ impl<'a> Iterator for StackFrameIterator<'a> {
                         .read_block8(&mut self.session.probe, addr as u32, &mut buff)
                         .unwrap();
 
-                    let val = u32::from_le_bytes(buff);
+                    let val = self.session.target.endian.u32_from_bytes(buff);
 
                     Some(val)
                 }
@@ -225,7 +258,8 @@ impl<'a> Iterator for StackFrameIterator<'a> {
             &mut self.session,
             pc,
             self.frame_count,
-            self.registers.clone(),
+            frame_registers,
+            current_cfa,
         ));
 
         self.frame_count += 1;
@@ -238,18 +272,18 @@ impl<'a> Iterator for StackFrameIterator<'a> {
     }
 }
 
-type R = gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>;
-type DwarfReader = gimli::read::EndianRcSlice<gimli::LittleEndian>;
+type R = gimli::EndianReader<gimli::RunTimeEndian, std::rc::Rc<[u8]>>;
+type DwarfReader = gimli::read::EndianRcSlice<gimli::RunTimeEndian>;
 type FunctionDie<'a, 'u> = gimli::DebuggingInformationEntry<
     'a,
     'u,
-    gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>,
+    gimli::EndianReader<gimli::RunTimeEndian, std::rc::Rc<[u8]>>,
     usize,
 >;
 type EntriesCursor<'a, 'u> =
-    gimli::EntriesCursor<'a, 'u, gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>>;
+    gimli::EntriesCursor<'a, 'u, gimli::EndianReader<gimli::RunTimeEndian, std::rc::Rc<[u8]>>>;
 type UnitIter =
-    gimli::CompilationUnitHeadersIter<gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>>;
+    gimli::CompilationUnitHeadersIter<gimli::EndianReader<gimli::RunTimeEndian, std::rc::Rc<[u8]>>>;
 
 pub struct DebugInfo {
     dwarf: gimli::Dwarf<DwarfReader>,
@@ -259,6 +293,13 @@ pub struct DebugInfo {
 impl DebugInfo {
     pub fn from_raw(data: &[u8]) -> Self {
         let object = object::File::parse(data).unwrap();
+        // The byte order of the debug info is the one of the file, which big-endian targets
+        // are built with.
+        let endian = if object.is_little_endian() {
+            gimli::RunTimeEndian::Little
+        } else {
+            gimli::RunTimeEndian::Big
+        };
 
         // Load a section and return as `Cow<[u8]>`.
         let load_section = |id: gimli::SectionId| -> Result<DwarfReader, gimli::Error> {
@@ -266,17 +307,14 @@ impl DebugInfo {
                 .section_data_by_name(id.name())
                 .unwrap_or_else(|| borrow::Cow::Borrowed(&[][..]));
 
-            Ok(gimli::read::EndianRcSlice::new(
-                Rc::from(&*data),
-                gimli::LittleEndian,
-            ))
+            Ok(gimli::read::EndianRcSlice::new(Rc::from(&*data), endian))
         };
         // Load a supplementary section. We don't have a supplementary object file,
         // so always return an empty slice.
         let load_section_sup = |_| {
             Ok(gimli::read::EndianRcSlice::new(
                 Rc::from(&*borrow::Cow::Borrowed(&[][..])),
-                gimli::LittleEndian,
+                endian,
             ))
         };
 
@@ -411,6 +449,7 @@ impl DebugInfo {
         address: u64,
         frame_count: u64,
         registers: Registers,
+        frame_base: Option<u32>,
     ) -> StackFrame {
         let mut units = self.get_units();
         let unknown_function = format!("<unknown_function_{}>", frame_count);
@@ -423,7 +462,8 @@ impl DebugInfo {
                 let variables = unit_info.get_variables(
                     session,
                     die_cursor_state,
-                    u64::from(registers.get_call_frame_address().unwrap()),
+                    u64::from(frame_base.unwrap()),
+                    &registers,
                 );
 
                 // dbg!(&variables);
@@ -449,6 +489,66 @@ impl DebugInfo {
         }
     }
 
+    /// Returns the name of the innermost function containing `address`.
+    pub fn function_name(&self, address: u64) -> Option<String> {
+        let mut units = self.get_units();
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            if let Some(die_cursor_state) = unit_info.get_function_die(address) {
+                return unit_info.get_function_name(&die_cursor_state.function_die);
+            }
+        }
+        None
+    }
+
+    /// Returns the address of the first instruction of the function `name`.
+    pub fn function_address(&self, name: &str) -> Option<u64> {
+        let mut units = self.get_units();
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            let mut entries_cursor = unit_info.unit.entries();
+
+            while let Ok(Some((_, current))) = entries_cursor.next_dfs() {
+                if current.tag() != gimli::DW_TAG_subprogram
+                    || unit_info
+                        .get_function_name(current)
+                        .map_or(true, |n| n != name)
+                {
+                    continue;
+                }
+
+                // Declarations and inlined functions have no address of their own.
+                if let Ok(Some(gimli::AttributeValue::Addr(address))) =
+                    current.attr_value(gimli::DW_AT_low_pc)
+                {
+                    return Some(address);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the sorted, deduplicated names of all functions described in the debug info.
+    pub fn function_names(&self) -> Vec<String> {
+        let mut names = vec![];
+
+        let mut units = self.get_units();
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            let mut entries_cursor = unit_info.unit.entries();
+
+            while let Ok(Some((_, current))) = entries_cursor.next_dfs() {
+                if current.tag() == gimli::DW_TAG_subprogram {
+                    if let Some(name) = unit_info.get_function_name(current) {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
     pub fn try_unwind<'b>(
         &'b self,
         session: &'b mut Session,
@@ -456,6 +556,16 @@ impl DebugInfo {
     ) -> StackFrameIterator<'b> {
         StackFrameIterator::new(&self, session, address)
     }
+
+    /// Unwinds the stack of a thread which is not running, from the values of the registers
+    /// R0 to R15 its RTOS saved.
+    pub fn try_unwind_thread<'b>(
+        &'b self,
+        session: &'b mut Session,
+        registers: [u32; 16],
+    ) -> StackFrameIterator<'b> {
+        StackFrameIterator::from_registers(&self, session, registers)
+    }
 }
 
 pub struct DieCursorState<'a, 'u> {
@@ -466,7 +576,7 @@ pub struct DieCursorState<'a, 'u> {
 
 pub struct UnitInfo<'a> {
     debug_info: &'a DebugInfo,
-    unit: gimli::Unit<gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>, usize>,
+    unit: gimli::Unit<gimli::EndianReader<gimli::RunTimeEndian, std::rc::Rc<[u8]>>, usize>,
 }
 
 impl<'a> UnitInfo<'a> {
@@ -540,16 +650,13 @@ impl<'a> UnitInfo<'a> {
                             .resume_with_memory(gimli::Value::U8(buff[0]))
                             .unwrap(),
                         2 => {
-                            let val = (u16::from(buff[0]) << 8) | (u16::from(buff[1]) as u16);
+                            let val = session.target.endian.uint_from_bytes(&buff) as u16;
                             evaluation
                                 .resume_with_memory(gimli::Value::U16(val))
                                 .unwrap()
                         }
                         4 => {
-                            let val = (u32::from(buff[0]) << 24)
-                                | (u32::from(buff[1]) << 16)
-                                | (u32::from(buff[2]) << 8)
-                                | u32::from(buff[3]);
+                            let val = session.target.endian.uint_from_bytes(&buff) as u32;
                             evaluation
                                 .resume_with_memory(gimli::Value::U32(val))
                                 .unwrap()
@@ -573,6 +680,7 @@ impl<'a> UnitInfo<'a> {
         session: &mut Session,
         die_cursor_state: &mut DieCursorState,
         frame_base: u64,
+        registers: &Registers,
     ) -> Vec<Variable> {
         let mut variables = vec![];
 
@@ -613,9 +721,14 @@ impl<'a> UnitInfo<'a> {
                                 });
                         }
                         gimli::DW_AT_location => {
-                            variable.value =
-                                extract_location(&self, session, frame_base, attr.value())
-                                    .unwrap_or_else(u64::max_value);
+                            variable.value = extract_location(
+                                &self,
+                                session,
+                                frame_base,
+                                registers,
+                                attr.value(),
+                            )
+                            .unwrap_or_else(u64::max_value);
                         }
                         _ => (),
                     }
@@ -632,14 +745,24 @@ fn extract_location(
     unit_info: &UnitInfo,
     session: &mut Session,
     frame_base: u64,
+    registers: &Registers,
     attribute_value: gimli::AttributeValue<R>,
 ) -> Option<u64> {
     match attribute_value {
         gimli::AttributeValue::Exprloc(expression) => {
-            let piece = unit_info.expr_to_piece(session, expression, frame_base);
+            let pieces = unit_info.expr_to_piece(session, expression, frame_base);
+
+            // A value can be split over several pieces, like an f64 over two S registers.
+            let mut value = 0;
+            let mut offset = 0;
+            for piece in &pieces {
+                if offset < 64 {
+                    value |= get_piece_value(session, registers, piece)? << offset;
+                }
+                offset += piece.size_in_bits.unwrap_or(32);
+            }
 
-            let value = get_piece_value(session, &piece[0]);
-            value.map(u64::from)
+            Some(value)
         }
         _ => None,
     }
@@ -653,6 +776,18 @@ fn extract_type(unit_info: &UnitInfo, attribute_value: gimli::AttributeValue<R>)
 
                 // Examine the entry attributes.
                 let entry = node.entry();
+                if let gimli::DW_TAG_base_type = entry.tag() {
+                    let type_name = entry
+                        .attr(gimli::DW_AT_name)
+                        .ok()?
+                        .and_then(|attr| extract_name(&unit_info.debug_info, attr.value()));
+
+                    return Some(Type {
+                        name: type_name.unwrap_or_else(|| "<unnamed type>".to_string()),
+                        named_children: None,
+                        indexed_children: None,
+                    });
+                }
                 if let gimli::DW_TAG_structure_type = entry.tag() {
                     let type_name = extract_name(
                         &unit_info.debug_info,
@@ -735,21 +870,45 @@ fn extract_name(
     }
 }
 
-fn get_piece_value(session: &mut Session, p: &gimli::Piece<DwarfReader>) -> Option<u32> {
+/// Reads the value of a piece.
+///
+/// Floating point values are passed in the FPU registers under the hard float variant of the
+/// AAPCS. DWARF numbers these 64 to 95 for S0 to S31 and 256 to 271 for D0 to D15.
+/// The core registers are taken from the frame, where known.
+fn get_piece_value(
+    session: &mut Session,
+    registers: &Registers,
+    p: &gimli::Piece<DwarfReader>,
+) -> Option<u64> {
     use gimli::Location;
 
+    let mut read_register = |address: CoreRegisterAddress| {
+        let val = session
+            .target
+            .core
+            .read_core_reg(&mut session.probe, address)
+            .expect("Failed to read register from target");
+        u64::from(val)
+    };
+
     match &p.location {
         Location::Empty => None,
-        Location::Address { address } => Some(*address as u32),
-        Location::Value { value } => Some(value.to_u64(0xff_ff_ff_ff).unwrap() as u32),
-        Location::Register { register } => {
-            let val = session
-                .target
-                .core
-                .read_core_reg(&mut session.probe, (register.0 as u8).into())
-                .expect("Failed to read register from target");
-            Some(val)
-        }
+        Location::Address { address } => Some(u64::from(*address as u32)),
+        Location::Value { value } => Some(u64::from(value.to_u64(0xff_ff_ff_ff).unwrap() as u32)),
+        Location::Register { register } => match register.0 {
+            0..=15 => Some(match registers[register.0 as usize] {
+                Some(value) => u64::from(value),
+                None => read_register((register.0 as u8).into()),
+            }),
+            64..=95 => Some(read_register(s_register((register.0 - 64) as u8))),
+            256..=271 => {
+                let n = (register.0 - 256) as u8;
+                let low = read_register(s_register(2 * n));
+                let high = read_register(s_register(2 * n + 1));
+                Some(low | (high << 32))
+            }
+            _ => None,
+        },
         l => unimplemented!("Location {:?} not implemented", l),
     }
 }
@@ -800,16 +959,15 @@ pub fn print_all_attributes(
                                     .resume_with_memory(gimli::Value::U8(buff[0]))
                                     .unwrap(),
                                 2 => {
-                                    let val = u16::from(buff[0]) << 8 | u16::from(buff[1]);
+                                    let endian = session.target.endian;
+                                    let val = endian.uint_from_bytes(&buff) as u16;
                                     evaluation
                                         .resume_with_memory(gimli::Value::U16(val))
                                         .unwrap()
                                 }
                                 4 => {
-                                    let val = u32::from(buff[0]) << 24
-                                        | u32::from(buff[1]) << 16
-                                        | u32::from(buff[2]) << 8
-                                        | u32::from(buff[3]);
+                                    let endian = session.target.endian;
+                                    let val = endian.uint_from_bytes(&buff) as u32;
                                     evaluation
                                         .resume_with_memory(gimli::Value::U32(val))
                                         .unwrap()
@@ -0,0 +1,646 @@
+use super::*;
+
+use crate::coresight::access_ports::AccessPortError;
+
+/// Type references are followed at most this deep, which guards against cyclic type graphs.
+const MAX_TYPE_DEPTH: u32 = 32;
+
+/// The encoding of a base type.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    Boolean,
+    Signed,
+    Unsigned,
+    Float,
+}
+
+/// The memory layout of a value, as described by its DWARF type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    Base {
+        name: String,
+        encoding: Encoding,
+        size: u32,
+    },
+    Pointer {
+        size: u32,
+    },
+    /// A struct or a union.
+    Struct {
+        name: String,
+        members: Vec<Member>,
+        size: u32,
+    },
+    Array {
+        element: Box<Layout>,
+        count: u32,
+    },
+    Enum {
+        name: String,
+        size: u32,
+        /// Whether the underlying integer type is signed, which the values are sign extended by.
+        signed: bool,
+        enumerators: Vec<(i64, String)>,
+    },
+    /// A type which is not decoded, its values are shown as raw bytes.
+    Unknown {
+        name: String,
+        size: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    /// Offset of the member from the start of the struct, in bytes.
+    pub offset: u32,
+    pub layout: Layout,
+}
+
+impl Layout {
+    /// Returns the member of a struct by its name.
+    pub fn member(&self, name: &str) -> Option<&Member> {
+        match self {
+            Layout::Struct { members, .. } => members.iter().find(|member| member.name == name),
+            _ => None,
+        }
+    }
+
+    /// Returns the size of a value in bytes.
+    pub fn size(&self) -> u32 {
+        match self {
+            Layout::Base { size, .. }
+            | Layout::Pointer { size }
+            | Layout::Struct { size, .. }
+            | Layout::Enum { size, .. }
+            | Layout::Unknown { size, .. } => *size,
+            Layout::Array { element, count } => element.size() * count,
+        }
+    }
+
+    /// Formats a value read from the target.
+    pub fn format(&self, data: &[u8]) -> String {
+        match self {
+            Layout::Base { encoding, size, .. } => {
+                let data = &data[..(*size as usize).min(data.len())];
+
+                if data.is_empty() || data.len() > 8 {
+                    return format_bytes(data);
+                }
+
+                match encoding {
+                    Encoding::Boolean => (read_uint(data) != 0).to_string(),
+                    Encoding::Unsigned => read_uint(data).to_string(),
+                    Encoding::Signed => sign_extend(read_uint(data), data.len()).to_string(),
+                    Encoding::Float if data.len() == 4 => {
+                        f32::from_bits(read_uint(data) as u32).to_string()
+                    }
+                    Encoding::Float if data.len() == 8 => {
+                        f64::from_bits(read_uint(data)).to_string()
+                    }
+                    Encoding::Float => format_bytes(data),
+                }
+            }
+            Layout::Pointer { .. } => format!("0x{:08x}", read_uint(data)),
+            Layout::Struct { name, members, .. } => {
+                let members: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        let start = (member.offset as usize).min(data.len());
+                        let end = (start + member.layout.size() as usize).min(data.len());
+
+                        format!(
+                            "{}: {}",
+                            member.name,
+                            member.layout.format(&data[start..end])
+                        )
+                    })
+                    .collect();
+
+                format!("{} {{ {} }}", name, members.join(", "))
+            }
+            Layout::Array { element, .. } => {
+                let element_size = element.size() as usize;
+
+                if element_size == 0 {
+                    return "[..]".to_string();
+                }
+
+                let elements: Vec<String> = data
+                    .chunks(element_size)
+                    .map(|chunk| element.format(chunk))
+                    .collect();
+
+                format!("[{}]", elements.join(", "))
+            }
+            Layout::Enum {
+                name,
+                size,
+                signed,
+                enumerators,
+            } => {
+                let data = &data[..(*size as usize).min(data.len()).min(8)];
+                let value = if *signed {
+                    sign_extend(read_uint(data), data.len())
+                } else {
+                    read_uint(data) as i64
+                };
+
+                match enumerators.iter().find(|(v, _)| *v == value) {
+                    Some((_, variant)) => format!("{}::{}", name, variant),
+                    None => format!("{}({})", name, value),
+                }
+            }
+            Layout::Unknown { .. } => format_bytes(data),
+        }
+    }
+}
+
+/// A variable with a fixed address, e.g. a `static` or a C global.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticVariable {
+    pub name: String,
+    pub address: u32,
+    pub layout: Layout,
+}
+
+impl StaticVariable {
+    /// Returns the size of the variable in bytes.
+    ///
+    /// Variables without a known type are assumed to be a single word.
+    pub fn size(&self) -> u32 {
+        match self.layout.size() {
+            0 => 4,
+            size => size,
+        }
+    }
+
+    /// Reads the current value from the target and formats it according to its type.
+    pub fn read(&self, mi: &mut impl MI) -> Result<String, AccessPortError> {
+        let mut data = vec![0u8; self.size() as usize];
+        mi.read_block8(self.address, &mut data)?;
+
+        Ok(self.layout.format(&data))
+    }
+}
+
+impl DebugInfo {
+    /// Looks up a variable with a fixed address by its name.
+    ///
+    /// Both the plain name and the path including the namespaces,
+    /// e.g. `firmware::SENSOR_VALUE`, are accepted.
+    pub fn find_static(&self, name: &str) -> Option<StaticVariable> {
+        let mut units = self.get_units();
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            if let Some(variable) = unit_info.find_static(name) {
+                return Some(variable);
+            }
+        }
+
+        None
+    }
+
+    /// Looks up the layout of a struct, a union or a typedef by its name.
+    pub fn find_type(&self, name: &str) -> Option<Layout> {
+        let mut units = self.get_units();
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            let mut entries_cursor = unit_info.unit.entries();
+
+            while let Ok(Some((_, current))) = entries_cursor.next_dfs() {
+                let is_type = matches!(
+                    current.tag(),
+                    gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type | gimli::DW_TAG_typedef
+                );
+                // Declarations of incomplete types have no members.
+                let is_declaration = current
+                    .attr_value(gimli::DW_AT_declaration)
+                    .ok()
+                    .map_or(false, |value| value.is_some());
+
+                if !is_type || is_declaration {
+                    continue;
+                }
+
+                let entry_name = current
+                    .attr_value(gimli::DW_AT_name)
+                    .ok()
+                    .and_then(|value| value.and_then(|value| extract_name(self, value)));
+
+                if entry_name.as_ref().map(String::as_str) == Some(name) {
+                    return Some(unit_info.layout_of(current.offset(), 0));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns whether the debug info contains a namespace, like the crate of a library.
+    pub fn has_namespace(&self, name: &str) -> bool {
+        let mut units = self.get_units();
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            let mut entries_cursor = unit_info.unit.entries();
+
+            while let Ok(Some((_, current))) = entries_cursor.next_dfs() {
+                if current.tag() != gimli::DW_TAG_namespace {
+                    continue;
+                }
+
+                let entry_name = current
+                    .attr_value(gimli::DW_AT_name)
+                    .ok()
+                    .and_then(|value| value.and_then(|value| extract_name(self, value)));
+
+                if entry_name.as_ref().map(String::as_str) == Some(name) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Returns the file and line where the variable located at `address` is declared.
+    pub fn static_declaration(&self, address: u32) -> Option<(String, u64)> {
+        let mut units = self.get_units();
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            let mut entries_cursor = unit_info.unit.entries();
+
+            while let Ok(Some((_, current))) = entries_cursor.next_dfs() {
+                if current.tag() != gimli::DW_TAG_variable
+                    || unit_info.static_address(current) != Some(address)
+                {
+                    continue;
+                }
+
+                let file = current.attr_value(gimli::DW_AT_decl_file).ok()??;
+                let line = current.attr_value(gimli::DW_AT_decl_line).ok()??;
+
+                return Some((
+                    extract_file(self, &unit_info.unit, file)?,
+                    extract_line(self, line)?,
+                ));
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> UnitInfo<'a> {
+    fn find_static(&self, name: &str) -> Option<StaticVariable> {
+        let mut entries_cursor = self.unit.entries();
+
+        let mut depth = 0;
+        // Names of the namespaces enclosing the current entry, indexed by depth.
+        let mut namespaces: Vec<Option<String>> = vec![];
+
+        while let Ok(Some((delta, current))) = entries_cursor.next_dfs() {
+            depth += delta;
+            namespaces.truncate(depth as usize);
+
+            let entry_name = current
+                .attr_value(gimli::DW_AT_name)
+                .ok()
+                .and_then(|value| value.and_then(|value| extract_name(self.debug_info, value)));
+
+            let is_namespace = current.tag() == gimli::DW_TAG_namespace;
+            namespaces.push(if is_namespace {
+                entry_name.clone()
+            } else {
+                None
+            });
+
+            if current.tag() != gimli::DW_TAG_variable {
+                continue;
+            }
+
+            let entry_name = match entry_name {
+                Some(entry_name) => entry_name,
+                None => continue,
+            };
+
+            let mut path: Vec<&str> = namespaces[..depth as usize]
+                .iter()
+                .filter_map(|namespace| namespace.as_ref().map(String::as_str))
+                .collect();
+            path.push(&entry_name);
+            let path = path.join("::");
+
+            if entry_name != name && path != name {
+                continue;
+            }
+
+            // Variables on the stack or in registers do not have a fixed address.
+            let address = match self.static_address(current) {
+                Some(address) => address,
+                None => continue,
+            };
+
+            let layout = match type_offset(current) {
+                Some(offset) => self.layout_of(offset, 0),
+                None => unknown_layout(),
+            };
+
+            return Some(StaticVariable {
+                name: path,
+                address,
+                layout,
+            });
+        }
+
+        None
+    }
+
+    /// Returns the address of a variable located with a single `DW_OP_addr`.
+    fn static_address(&self, entry: &FunctionDie) -> Option<u32> {
+        match entry.attr_value(gimli::DW_AT_location).ok()?? {
+            gimli::AttributeValue::Exprloc(expression) => {
+                let mut operations = expression.operations(self.unit.encoding());
+
+                match operations.next() {
+                    Ok(Some(gimli::Operation::Address { address })) => Some(address as u32),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn layout_of(&self, offset: gimli::UnitOffset, depth: u32) -> Layout {
+        if depth > MAX_TYPE_DEPTH {
+            return unknown_layout();
+        }
+
+        let mut tree = match self.unit.entries_tree(Some(offset)) {
+            Ok(tree) => tree,
+            Err(_) => return unknown_layout(),
+        };
+
+        let node = match tree.root() {
+            Ok(node) => node,
+            Err(_) => return unknown_layout(),
+        };
+
+        let entry = node.entry();
+        let tag = entry.tag();
+        let name = entry
+            .attr_value(gimli::DW_AT_name)
+            .ok()
+            .and_then(|value| value.and_then(|value| extract_name(self.debug_info, value)))
+            .unwrap_or_else(|| "<unnamed type>".to_string());
+        let size = udata_attr(entry, gimli::DW_AT_byte_size).unwrap_or(0) as u32;
+        let inner_type = type_offset(entry);
+
+        match tag {
+            gimli::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding) {
+                    Ok(Some(gimli::AttributeValue::Encoding(encoding))) => encoding,
+                    _ => return Layout::Unknown { name, size },
+                };
+
+                let encoding = match encoding {
+                    gimli::DW_ATE_boolean => Encoding::Boolean,
+                    gimli::DW_ATE_signed | gimli::DW_ATE_signed_char => Encoding::Signed,
+                    gimli::DW_ATE_unsigned | gimli::DW_ATE_unsigned_char => Encoding::Unsigned,
+                    gimli::DW_ATE_float => Encoding::Float,
+                    _ => return Layout::Unknown { name, size },
+                };
+
+                Layout::Base {
+                    name,
+                    encoding,
+                    size,
+                }
+            }
+            gimli::DW_TAG_pointer_type | gimli::DW_TAG_reference_type => Layout::Pointer {
+                size: if size == 0 { 4 } else { size },
+            },
+            gimli::DW_TAG_typedef | gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => {
+                match inner_type {
+                    Some(inner_type) => self.layout_of(inner_type, depth + 1),
+                    None => unknown_layout(),
+                }
+            }
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type => {
+                let mut members = vec![];
+
+                let mut children = node.children();
+                while let Ok(Some(child)) = children.next() {
+                    let entry = child.entry();
+
+                    if entry.tag() != gimli::DW_TAG_member {
+                        continue;
+                    }
+
+                    let member_name = entry
+                        .attr_value(gimli::DW_AT_name)
+                        .ok()
+                        .and_then(|value| {
+                            value.and_then(|value| extract_name(self.debug_info, value))
+                        })
+                        .unwrap_or_else(|| "<unnamed>".to_string());
+
+                    let layout = match type_offset(entry) {
+                        Some(member_type) => self.layout_of(member_type, depth + 1),
+                        None => unknown_layout(),
+                    };
+
+                    members.push(Member {
+                        name: member_name,
+                        offset: self.member_offset(entry),
+                        layout,
+                    });
+                }
+
+                Layout::Struct {
+                    name,
+                    members,
+                    size,
+                }
+            }
+            gimli::DW_TAG_array_type => {
+                let element = match inner_type {
+                    Some(inner_type) => self.layout_of(inner_type, depth + 1),
+                    None => unknown_layout(),
+                };
+
+                let mut count = 0;
+
+                let mut children = node.children();
+                while let Ok(Some(child)) = children.next() {
+                    let entry = child.entry();
+
+                    if entry.tag() == gimli::DW_TAG_subrange_type {
+                        count = udata_attr(entry, gimli::DW_AT_count)
+                            .or_else(|| {
+                                udata_attr(entry, gimli::DW_AT_upper_bound).map(|bound| bound + 1)
+                            })
+                            .unwrap_or(0) as u32;
+                        break;
+                    }
+                }
+
+                Layout::Array {
+                    element: Box::new(element),
+                    count,
+                }
+            }
+            gimli::DW_TAG_enumeration_type => {
+                let signed = match inner_type.map(|inner| self.layout_of(inner, depth + 1)) {
+                    Some(Layout::Base { encoding, .. }) => encoding == Encoding::Signed,
+                    _ => false,
+                };
+                let mut enumerators = vec![];
+
+                let mut children = node.children();
+                while let Ok(Some(child)) = children.next() {
+                    let entry = child.entry();
+
+                    if entry.tag() != gimli::DW_TAG_enumerator {
+                        continue;
+                    }
+
+                    let variant = entry.attr_value(gimli::DW_AT_name).ok().and_then(|value| {
+                        value.and_then(|value| extract_name(self.debug_info, value))
+                    });
+                    let value = entry
+                        .attr_value(gimli::DW_AT_const_value)
+                        .ok()
+                        .and_then(|value| value.and_then(|value| enumerator_value(value, signed)));
+
+                    if let (Some(variant), Some(value)) = (variant, value) {
+                        enumerators.push((value, variant));
+                    }
+                }
+
+                Layout::Enum {
+                    name,
+                    size,
+                    signed,
+                    enumerators,
+                }
+            }
+            _ => Layout::Unknown { name, size },
+        }
+    }
+
+    /// Returns the offset of a struct member, given either as a constant or as `DW_OP_plus_uconst`.
+    fn member_offset(&self, entry: &FunctionDie) -> u32 {
+        match entry.attr_value(gimli::DW_AT_data_member_location) {
+            Ok(Some(gimli::AttributeValue::Exprloc(expression))) => {
+                match expression.operations(self.unit.encoding()).next() {
+                    Ok(Some(gimli::Operation::PlusConstant { value })) => value as u32,
+                    _ => 0,
+                }
+            }
+            Ok(Some(value)) => value.udata_value().unwrap_or(0) as u32,
+            _ => 0,
+        }
+    }
+}
+
+fn type_offset(entry: &FunctionDie) -> Option<gimli::UnitOffset> {
+    match entry.attr_value(gimli::DW_AT_type) {
+        Ok(Some(gimli::AttributeValue::UnitRef(offset))) => Some(offset),
+        _ => None,
+    }
+}
+
+fn udata_attr(entry: &FunctionDie, name: gimli::DwAt) -> Option<u64> {
+    entry
+        .attr_value(name)
+        .ok()
+        .and_then(|value| value.and_then(|value| value.udata_value()))
+}
+
+fn unknown_layout() -> Layout {
+    Layout::Unknown {
+        name: "<unknown type>".to_string(),
+        size: 0,
+    }
+}
+
+/// Decodes the value of an enumerator. Constants of a fixed size do not tell their signedness,
+/// they are extended according to the underlying type of the enumeration.
+fn enumerator_value(value: gimli::AttributeValue<R>, signed: bool) -> Option<i64> {
+    let (value, bytes) = match value {
+        gimli::AttributeValue::Sdata(value) => return Some(value),
+        gimli::AttributeValue::Udata(value) => return Some(value as i64),
+        gimli::AttributeValue::Data1(value) => (u64::from(value), 1),
+        gimli::AttributeValue::Data2(value) => (u64::from(value), 2),
+        gimli::AttributeValue::Data4(value) => (u64::from(value), 4),
+        gimli::AttributeValue::Data8(value) => (value, 8),
+        _ => return None,
+    };
+
+    Some(if signed {
+        sign_extend(value, bytes)
+    } else {
+        value as i64
+    })
+}
+
+/// Sign extends the lowest `bytes` bytes of `value` to 64 bits.
+///
+/// Values of no bytes or of more than 8 bytes are 0.
+pub(super) fn sign_extend(value: u64, bytes: usize) -> i64 {
+    if bytes == 0 || bytes > 8 {
+        return 0;
+    }
+
+    let shift = 64 - 8 * bytes as u32;
+    ((value << shift) as i64) >> shift
+}
+
+/// Reads a little endian unsigned integer of up to 8 bytes.
+pub(super) fn read_uint(data: &[u8]) -> u64 {
+    data.iter()
+        .take(8)
+        .rev()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte))
+}
+
+fn format_bytes(data: &[u8]) -> String {
+    let bytes: Vec<String> = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    format!("[{}]", bytes.join(" "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{enumerator_value, sign_extend, Layout};
+
+    #[test]
+    fn formats_negative_enumerators() {
+        let layout = Layout::Enum {
+            name: "Level".to_string(),
+            size: 1,
+            signed: true,
+            enumerators: vec![(-1, "Off".to_string()), (1, "On".to_string())],
+        };
+
+        assert_eq!(layout.format(&[0xFF]), "Level::Off");
+        assert_eq!(layout.format(&[0xFE]), "Level(-2)");
+    }
+
+    #[test]
+    fn extends_enumerators_by_the_underlying_type() {
+        let value = || gimli::AttributeValue::Data1(0xFF);
+
+        assert_eq!(enumerator_value(value(), true), Some(-1));
+        assert_eq!(enumerator_value(value(), false), Some(0xFF));
+        assert_eq!(
+            enumerator_value(gimli::AttributeValue::Sdata(-2), false),
+            Some(-2)
+        );
+    }
+
+    #[test]
+    fn sign_extends_only_up_to_8_bytes() {
+        assert_eq!(sign_extend(0xFFFF, 2), -1);
+        assert_eq!(sign_extend(u64::max_value(), 8), -1);
+        assert_eq!(sign_extend(0xFF, 0), 0);
+        assert_eq!(sign_extend(0xFF, 9), 0);
+    }
+}
@@ -8,3 +8,38 @@ pub struct Variable {
     pub value: u64,
     pub typ: Type,
 }
+
+impl Variable {
+    /// Formats the value, as a float if the variable has a floating point type.
+    pub fn format_value(&self) -> String {
+        match &self.typ.name[..] {
+            "f32" | "float" => f32::from_bits(self.value as u32).to_string(),
+            "f64" | "double" => f64::from_bits(self.value).to_string(),
+            _ => format!("0x{:08x}", self.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Type, Variable};
+
+    #[test]
+    fn formats_floats() {
+        let variable = |name: &str, value| Variable {
+            typ: Type {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            value,
+            ..Default::default()
+        };
+
+        assert_eq!(variable("f32", 0x3FC0_0000).format_value(), "1.5");
+        assert_eq!(
+            variable("double", 0xC004_0000_0000_0000).format_value(),
+            "-2.5"
+        );
+        assert_eq!(variable("u32", 0x1234).format_value(), "0x00001234");
+    }
+}
@@ -0,0 +1,639 @@
+//! Decoding of log messages produced by the [defmt](https://github.com/knurling-rs/defmt)
+//! logging framework.
+//!
+//! defmt does not send format strings to the host. They are stored as symbol names in the
+//! `.defmt` section of the ELF file instead, and the target only sends the address of the
+//! symbol, called the index, followed by the binary encoded arguments.
+
+mod rzcobs;
+
+use object::read::{Object, ObjectSection};
+use serde_derive::Deserialize;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Nested formatting is followed at most this deep, which guards against corrupt data.
+const MAX_NESTING: u32 = 16;
+
+#[derive(Debug)]
+pub enum DefmtError {
+    /// The ELF file could not be parsed.
+    InvalidElf,
+    /// The ELF file does not contain a `.defmt` section.
+    MissingSection,
+    /// The frame ended before all arguments were decoded.
+    UnexpectedEof,
+    /// The frame references an index which is not in the table.
+    UnknownIndex(u16),
+    /// A format string uses a parameter type which is not supported.
+    UnsupportedType(String),
+    /// A frame could not be decoded from the stream.
+    MalformedFrame,
+}
+
+impl Error for DefmtError {}
+
+impl fmt::Display for DefmtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefmtError::InvalidElf => write!(f, "The ELF file could not be parsed."),
+            DefmtError::MissingSection => {
+                write!(f, "The ELF file does not contain defmt format strings.")
+            }
+            DefmtError::UnexpectedEof => write!(f, "The defmt frame is incomplete."),
+            DefmtError::UnknownIndex(index) => {
+                write!(f, "The defmt index {} is not in the ELF file.", index)
+            }
+            DefmtError::UnsupportedType(ref typ) => {
+                write!(f, "The defmt parameter type '{}' is not supported.", typ)
+            }
+            DefmtError::MalformedFrame => write!(f, "Malformed defmt frame."),
+        }
+    }
+}
+
+/// The level of a log message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trace" => Ok(Level::Trace),
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _ => Err(format!(
+                "Unknown level '{}', use 'trace', 'debug', 'info', 'warn' or 'error'.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// How frames are delimited in the byte stream.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Encoding {
+    /// Frames follow each other without delimiters.
+    Raw,
+    /// Frames are rzCOBS encoded and terminated by a zero byte.
+    Rzcobs,
+}
+
+/// A symbol name in the `.defmt` section.
+#[derive(Debug, Deserialize)]
+struct Symbol {
+    tag: String,
+    data: String,
+}
+
+/// A format string from the `.defmt` section.
+#[derive(Debug)]
+struct Entry {
+    level: Option<Level>,
+    format: String,
+}
+
+/// A decoded log message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The index of the format string, which is also the address of its symbol.
+    pub index: u16,
+    /// The level of the message, `None` for `println!`.
+    pub level: Option<Level>,
+    /// The formatted timestamp, if the firmware defines one.
+    pub timestamp: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(timestamp) = &self.timestamp {
+            write!(f, "{} ", timestamp)?;
+        }
+
+        if let Some(level) = self.level {
+            write!(f, "{:<5} ", level)?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The format strings of a firmware, read from its ELF file.
+#[derive(Debug)]
+pub struct Table {
+    entries: HashMap<u16, Entry>,
+    timestamp: Option<String>,
+    encoding: Encoding,
+}
+
+impl Table {
+    /// Reads the format strings from the `.defmt` section of an ELF file.
+    pub fn parse(elf: &[u8]) -> Result<Table, DefmtError> {
+        let file = object::File::parse(elf).map_err(|_| DefmtError::InvalidElf)?;
+
+        let section = match file.section_by_name(".defmt") {
+            Some(section) => section,
+            None => return Err(DefmtError::MissingSection),
+        };
+        let range = section.address()..section.address() + section.size();
+
+        let mut entries = HashMap::new();
+        let mut timestamp = None;
+        let mut encoding = Encoding::Raw;
+
+        for (_, symbol) in file.symbols() {
+            let name = match symbol.name() {
+                Some(name) => name,
+                None => continue,
+            };
+
+            if name.starts_with("_defmt_encoding_ = ") {
+                if name.ends_with("rzcobs") {
+                    encoding = Encoding::Rzcobs;
+                }
+                continue;
+            }
+
+            if !range.contains(&symbol.address()) {
+                continue;
+            }
+
+            // Other symbols, e.g. section start and end markers, are not JSON.
+            let symbol_data: Symbol = match serde_json::from_str(name) {
+                Ok(symbol_data) => symbol_data,
+                Err(_) => continue,
+            };
+
+            let level = match symbol_data.tag.as_str() {
+                "defmt_trace" => Some(Level::Trace),
+                "defmt_debug" => Some(Level::Debug),
+                "defmt_info" => Some(Level::Info),
+                "defmt_warn" => Some(Level::Warn),
+                "defmt_error" => Some(Level::Error),
+                "defmt_timestamp" => {
+                    timestamp = Some(symbol_data.data);
+                    continue;
+                }
+                _ => None,
+            };
+
+            entries.insert(
+                (symbol.address() - range.start) as u16,
+                Entry {
+                    level,
+                    format: symbol_data.data,
+                },
+            );
+        }
+
+        Ok(Table {
+            entries,
+            timestamp,
+            encoding,
+        })
+    }
+
+    /// Decodes a single frame, returning it and the number of bytes it occupied.
+    fn decode(&self, data: &[u8]) -> Result<(Frame, usize), DefmtError> {
+        let mut reader = Reader { data, position: 0 };
+
+        let index = reader.u16()?;
+        let entry = self
+            .entries
+            .get(&index)
+            .ok_or(DefmtError::UnknownIndex(index))?;
+
+        let timestamp = match &self.timestamp {
+            Some(format) => Some(self.format(format, &mut reader, 0)?),
+            None => None,
+        };
+
+        let message = self.format(&entry.format, &mut reader, 0)?;
+
+        let frame = Frame {
+            index,
+            level: entry.level,
+            timestamp,
+            message,
+        };
+
+        Ok((frame, reader.position))
+    }
+
+    /// Decodes the arguments of `format` and returns the formatted string.
+    fn format(&self, format: &str, reader: &mut Reader, depth: u32) -> Result<String, DefmtError> {
+        if depth > MAX_NESTING {
+            return Err(DefmtError::MalformedFrame);
+        }
+
+        let segments = parse_format(format);
+
+        // Arguments are encoded once each, ordered by their position.
+        let mut parameters: Vec<(usize, &str)> = segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Parameter { index, typ, .. } => Some((*index, *typ)),
+                Segment::Literal(_) => None,
+            })
+            .collect();
+        parameters.sort_by_key(|(index, _)| *index);
+        parameters.dedup_by_key(|(index, _)| *index);
+
+        let mut values = HashMap::new();
+        for (index, typ) in parameters {
+            values.insert(index, self.decode_value(typ, reader, depth)?);
+        }
+
+        let mut output = String::new();
+
+        for segment in &segments {
+            match segment {
+                Segment::Literal(text) => output.push_str(text),
+                Segment::Parameter { index, hint, .. } => {
+                    output.push_str(&values[index].format(hint))
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn decode_value(
+        &self,
+        typ: &str,
+        reader: &mut Reader,
+        depth: u32,
+    ) -> Result<Value, DefmtError> {
+        let value = match typ {
+            "u8" => Value::Unsigned(u128::from(reader.u8()?)),
+            "u16" => Value::Unsigned(u128::from(reader.u16()?)),
+            "u32" | "usize" => Value::Unsigned(u128::from(reader.u32()?)),
+            "u64" => Value::Unsigned(u128::from(reader.u64()?)),
+            "u128" => Value::Unsigned(reader.u128()?),
+            "i8" => Value::Signed(i128::from(reader.u8()? as i8), 8),
+            "i16" => Value::Signed(i128::from(reader.u16()? as i16), 16),
+            "i32" | "isize" => Value::Signed(i128::from(reader.u32()? as i32), 32),
+            "i64" => Value::Signed(i128::from(reader.u64()? as i64), 64),
+            "i128" => Value::Signed(reader.u128()? as i128, 128),
+            "f32" => Value::Float(f64::from(f32::from_bits(reader.u32()?))),
+            "f64" => Value::Float(f64::from_bits(reader.u64()?)),
+            "bool" => Value::Bool(reader.u8()? != 0),
+            "char" => Value::Str(
+                std::char::from_u32(reader.u32()?)
+                    .ok_or(DefmtError::MalformedFrame)?
+                    .to_string(),
+            ),
+            "str" => {
+                let length = reader.u32()? as usize;
+                Value::Str(String::from_utf8_lossy(reader.bytes(length)?).to_string())
+            }
+            "istr" => {
+                let index = reader.u16()?;
+                let entry = self
+                    .entries
+                    .get(&index)
+                    .ok_or(DefmtError::UnknownIndex(index))?;
+                Value::Str(entry.format.clone())
+            }
+            "[u8]" => {
+                let length = reader.u32()? as usize;
+                Value::Bytes(reader.bytes(length)?.to_vec())
+            }
+            // Values implementing `Format` send the index of their format string and its arguments.
+            "" | "?" => {
+                let index = reader.u16()?;
+                let entry = self
+                    .entries
+                    .get(&index)
+                    .ok_or(DefmtError::UnknownIndex(index))?;
+                Value::Str(self.format(&entry.format, reader, depth + 1)?)
+            }
+            _ if typ.starts_with("[u8;") && typ.ends_with(']') => {
+                let length = typ[4..typ.len() - 1]
+                    .trim()
+                    .parse()
+                    .map_err(|_| DefmtError::UnsupportedType(typ.to_string()))?;
+                Value::Bytes(reader.bytes(length)?.to_vec())
+            }
+            _ => return Err(DefmtError::UnsupportedType(typ.to_string())),
+        };
+
+        Ok(value)
+    }
+}
+
+/// Splits a byte stream into frames and decodes them.
+pub struct StreamDecoder<'a> {
+    table: &'a Table,
+    buffer: Vec<u8>,
+}
+
+impl<'a> StreamDecoder<'a> {
+    pub fn new(table: &'a Table) -> Self {
+        StreamDecoder {
+            table,
+            buffer: vec![],
+        }
+    }
+
+    /// Adds bytes received from the target.
+    pub fn received(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Decodes the next complete frame, if one was received.
+    ///
+    /// After an error, the decoder skips ahead to the next frame boundary if the encoding has
+    /// frame boundaries. With the raw encoding, all buffered data is dropped instead.
+    pub fn decode(&mut self) -> Option<Result<Frame, DefmtError>> {
+        match self.table.encoding {
+            Encoding::Raw => match self.table.decode(&self.buffer) {
+                Ok((frame, length)) => {
+                    self.buffer.drain(..length);
+                    Some(Ok(frame))
+                }
+                Err(DefmtError::UnexpectedEof) => None,
+                Err(e) => {
+                    self.buffer.clear();
+                    Some(Err(e))
+                }
+            },
+            Encoding::Rzcobs => {
+                let end = self.buffer.iter().position(|b| *b == 0)?;
+                let frame: Vec<u8> = self.buffer.drain(..=end).collect();
+
+                let frame = match rzcobs::decode(&frame[..end]) {
+                    Ok(frame) => frame,
+                    Err(()) => return Some(Err(DefmtError::MalformedFrame)),
+                };
+
+                Some(self.table.decode(&frame).map(|(frame, _)| frame))
+            }
+        }
+    }
+}
+
+/// A part of a format string.
+#[derive(Debug, PartialEq)]
+enum Segment<'f> {
+    Literal(String),
+    /// A parameter such as `{=u8:x}` or `{0=str}`.
+    Parameter {
+        index: usize,
+        typ: &'f str,
+        hint: &'f str,
+    },
+}
+
+/// Splits a format string into literals and parameters.
+fn parse_format(format: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut literal = String::new();
+    let mut next_index = 0;
+
+    let mut rest = format;
+
+    while let Some(position) = rest.find(|c| c == '{' || c == '}') {
+        literal.push_str(&rest[..position]);
+        let escaped = rest[position + 1..].starts_with(&rest[position..=position]);
+
+        if escaped {
+            literal.push_str(&rest[position..=position]);
+            rest = &rest[position + 2..];
+            continue;
+        }
+
+        if &rest[position..=position] == "}" {
+            literal.push('}');
+            rest = &rest[position + 1..];
+            continue;
+        }
+
+        let end = match rest[position..].find('}') {
+            Some(end) => position + end,
+            None => break,
+        };
+        let parameter = &rest[position + 1..end];
+        rest = &rest[end + 1..];
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(std::mem::replace(
+                &mut literal,
+                String::new(),
+            )));
+        }
+
+        // The parameter is `[index][=type][:hint]`.
+        let (parameter, hint) = match parameter.find(':') {
+            Some(colon) => (&parameter[..colon], &parameter[colon + 1..]),
+            None => (parameter, ""),
+        };
+        let (index, typ) = match parameter.find('=') {
+            Some(equals) => (&parameter[..equals], &parameter[equals + 1..]),
+            None => (parameter, ""),
+        };
+
+        let index = match index.parse() {
+            Ok(index) => index,
+            Err(_) => {
+                next_index += 1;
+                next_index - 1
+            }
+        };
+
+        segments.push(Segment::Parameter { index, typ, hint });
+    }
+
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+
+    segments
+}
+
+/// A decoded argument.
+#[derive(Debug)]
+enum Value {
+    Unsigned(u128),
+    /// A signed integer and its width in bits.
+    Signed(i128, u32),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Formats the value according to a display hint such as `x` or `#b`.
+    fn format(&self, hint: &str) -> String {
+        match self {
+            Value::Unsigned(value) => format_integer(*value, hint),
+            Value::Signed(value, _) if hint.is_empty() => value.to_string(),
+            Value::Signed(value, bits) => {
+                // Hex and binary show the two's complement of the original width.
+                let mask = u128::max_value() >> (128 - bits);
+                format_integer(*value as u128 & mask, hint)
+            }
+            Value::Float(value) => value.to_string(),
+            Value::Bool(value) => value.to_string(),
+            Value::Str(value) => value.clone(),
+            Value::Bytes(bytes) if hint == "a" => {
+                format!("b\"{}\"", String::from_utf8_lossy(bytes))
+            }
+            Value::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes
+                    .iter()
+                    .map(|b| format_integer(u128::from(*b), hint))
+                    .collect();
+                format!("[{}]", bytes.join(", "))
+            }
+        }
+    }
+}
+
+fn format_integer(value: u128, hint: &str) -> String {
+    match hint {
+        "x" => format!("{:x}", value),
+        "#x" => format!("{:#x}", value),
+        "X" => format!("{:X}", value),
+        "#X" => format!("{:#X}", value),
+        "b" => format!("{:b}", value),
+        "#b" => format!("{:#b}", value),
+        _ => value.to_string(),
+    }
+}
+
+/// Reads little endian values from a frame.
+struct Reader<'d> {
+    data: &'d [u8],
+    position: usize,
+}
+
+impl<'d> Reader<'d> {
+    fn bytes(&mut self, length: usize) -> Result<&'d [u8], DefmtError> {
+        let end = self.position + length;
+
+        if end > self.data.len() {
+            return Err(DefmtError::UnexpectedEof);
+        }
+
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, length: usize) -> Result<u128, DefmtError> {
+        Ok(self
+            .bytes(length)?
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 8) | u128::from(*byte)))
+    }
+
+    fn u8(&mut self) -> Result<u8, DefmtError> {
+        Ok(self.uint(1)? as u8)
+    }
+
+    fn u16(&mut self) -> Result<u16, DefmtError> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, DefmtError> {
+        Ok(self.uint(4)? as u32)
+    }
+
+    fn u64(&mut self) -> Result<u64, DefmtError> {
+        Ok(self.uint(8)? as u64)
+    }
+
+    fn u128(&mut self) -> Result<u128, DefmtError> {
+        self.uint(16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(entries: &[(u16, Option<Level>, &str)]) -> Table {
+        Table {
+            entries: entries
+                .iter()
+                .map(|(index, level, format)| {
+                    (
+                        *index,
+                        Entry {
+                            level: *level,
+                            format: format.to_string(),
+                        },
+                    )
+                })
+                .collect(),
+            timestamp: None,
+            encoding: Encoding::Raw,
+        }
+    }
+
+    #[test]
+    fn decode_arguments() {
+        let table = table(&[
+            (1, Some(Level::Info), "x={=u8}, y={=i16:x}, {=str}!"),
+            (2, None, "{{literal}}"),
+        ]);
+
+        let mut decoder = StreamDecoder::new(&table);
+        decoder.received(&[1, 0, 42, 0xff, 0xff, 3, 0, 0, 0, b'a', b'b', b'c', 2, 0]);
+
+        let frame = decoder.decode().unwrap().unwrap();
+        assert_eq!(frame.level, Some(Level::Info));
+        assert_eq!(frame.message, "x=42, y=ffff, abc!");
+
+        let frame = decoder.decode().unwrap().unwrap();
+        assert_eq!(frame.message, "{literal}");
+
+        assert!(decoder.decode().is_none());
+    }
+
+    #[test]
+    fn decode_nested_format() {
+        let table = table(&[
+            (1, None, "point: {}"),
+            (2, None, "Point {{ x: {=u8}, y: {=u8} }}"),
+        ]);
+
+        let (frame, length) = table.decode(&[1, 0, 2, 0, 3, 4]).unwrap();
+
+        assert_eq!(frame.message, "point: Point { x: 3, y: 4 }");
+        assert_eq!(length, 6);
+    }
+}
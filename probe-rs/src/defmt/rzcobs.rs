@@ -0,0 +1,44 @@
+//! Decoding of rzCOBS, the "reverse zero-compressing COBS" encoding used by defmt.
+//!
+//! The encoder works from the end of the frame to its start, so the decoder walks the
+//! encoded bytes backwards. Each control byte describes where the zeroes of the following
+//! bytes are, which removes all zeroes from the encoded data.
+
+/// Decodes a frame without its terminating zero byte.
+pub(super) fn decode(data: &[u8]) -> Result<Vec<u8>, ()> {
+    let mut output = vec![];
+    let mut data = data.iter().rev().cloned();
+
+    while let Some(control) = data.next() {
+        match control {
+            0 => return Err(()),
+            // Each bit describes one of the next 7 bytes: 1 for a zero, 0 for a data byte.
+            0x01..=0x7f => {
+                for i in 0..7 {
+                    if control & (1 << (6 - i)) == 0 {
+                        output.push(data.next().ok_or(())?);
+                    } else {
+                        output.push(0);
+                    }
+                }
+            }
+            // A zero followed by a run of 7 to 133 data bytes.
+            0x80..=0xfe => {
+                output.push(0);
+                for _ in 0..(control & 0x7f) + 7 {
+                    output.push(data.next().ok_or(())?);
+                }
+            }
+            // A run of 134 data bytes.
+            0xff => {
+                for _ in 0..134 {
+                    output.push(data.next().ok_or(())?);
+                }
+            }
+        }
+    }
+
+    output.reverse();
+
+    Ok(output)
+}
@@ -0,0 +1,126 @@
+//! Events of a debug session, which GUIs and other tools subscribe to instead of polling the
+//! core, the RTT channels and the flash loader each on their own.
+//!
+//! `Session::subscribe` returns the receiving end of a channel, which gets every event the
+//! session emits from then on. The session forgets receivers which were dropped.
+
+use crate::collection::cores::m4::Dfsr;
+
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Why the core halted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HaltReason {
+    /// The debugger halted the core.
+    Request,
+    /// The core executed a single instruction.
+    Step,
+    Breakpoint,
+    /// A DWT comparator matched, e.g. a watchpoint.
+    Watchpoint,
+    /// The core caught a reset or a fault it was set to halt on.
+    VectorCatch,
+    /// An external debug request, e.g. by another core.
+    External,
+    /// The core does not tell, like cores other than Cortex-M when not halted at a breakpoint.
+    Unknown,
+}
+
+impl From<Dfsr> for HaltReason {
+    fn from(dfsr: Dfsr) -> Self {
+        // Several bits are set if the core halted for several reasons at once, the more
+        // specific ones are reported.
+        if dfsr.bkpt() {
+            HaltReason::Breakpoint
+        } else if dfsr.dwttrap() {
+            HaltReason::Watchpoint
+        } else if dfsr.vcatch() {
+            HaltReason::VectorCatch
+        } else if dfsr.external() {
+            HaltReason::External
+        } else if dfsr.halted() {
+            HaltReason::Request
+        } else {
+            HaltReason::Unknown
+        }
+    }
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HaltReason::Request => "halt request",
+            HaltReason::Step => "step",
+            HaltReason::Breakpoint => "breakpoint",
+            HaltReason::Watchpoint => "watchpoint",
+            HaltReason::VectorCatch => "vector catch",
+            HaltReason::External => "external debug request",
+            HaltReason::Unknown => "unknown reason",
+        };
+
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    CoreHalted {
+        pc: u32,
+        reason: HaltReason,
+    },
+    /// The core halted at a breakpoint set with `Session::set_breakpoint`, at the given address.
+    /// Follows the `CoreHalted` event of the halt.
+    BreakpointHit(u32),
+    /// The target sent data over the RTT up channel with the given number.
+    RttData {
+        channel: usize,
+        data: Vec<u8>,
+    },
+    /// The flash loader programmed the sector at the given address.
+    SectorProgrammed(u32),
+    /// Talking to the probe failed, with the message of the error.
+    ProbeError(String),
+}
+
+/// The receivers of the events of a session.
+#[derive(Debug, Default)]
+pub struct Subscribers {
+    senders: Vec<Sender<SessionEvent>>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&mut self) -> Receiver<SessionEvent> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+
+        receiver
+    }
+
+    /// Sends the event to all receivers which were not dropped yet.
+    pub fn emit(&mut self, event: SessionEvent) {
+        self.senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SessionEvent, Subscribers};
+
+    #[test]
+    fn forgets_dropped_receivers() {
+        let mut subscribers = Subscribers::default();
+        let first = subscribers.subscribe();
+        let second = subscribers.subscribe();
+
+        drop(second);
+        subscribers.emit(SessionEvent::SectorProgrammed(0x0800_0000));
+
+        assert_eq!(
+            first.try_recv(),
+            Ok(SessionEvent::SectorProgrammed(0x0800_0000))
+        );
+        assert_eq!(subscribers.senders.len(), 1);
+    }
+}
@@ -0,0 +1,235 @@
+//! Timing analysis of exception trace packets.
+//!
+//! Local timestamps follow the packets whose time they give, so exception events are only
+//! applied once the next timestamp arrived. All times are in cycles of the timestamp clock.
+
+use super::{ExceptionAction, Packet};
+
+use std::collections::BTreeMap;
+
+/// Names of the system exceptions, indexed by exception number.
+const SYSTEM_EXCEPTIONS: [&str; 16] = [
+    "Thread",
+    "Reset",
+    "NMI",
+    "HardFault",
+    "MemManage",
+    "BusFault",
+    "UsageFault",
+    "SecureFault",
+    "Exception 8",
+    "Exception 9",
+    "Exception 10",
+    "SVCall",
+    "DebugMonitor",
+    "Exception 13",
+    "PendSV",
+    "SysTick",
+];
+
+/// Returns the name of an exception number, e.g. `SysTick` or `IRQ 3`.
+pub fn exception_name(number: u16) -> String {
+    match SYSTEM_EXCEPTIONS.get(number as usize) {
+        Some(name) => name.to_string(),
+        None => format!("IRQ {}", number - 16),
+    }
+}
+
+/// Timing of a single exception.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExceptionStatistics {
+    /// How often the exception was entered.
+    pub count: u64,
+    /// The shortest time from entry to exit, including preempting exceptions.
+    pub min_duration: u64,
+    /// The longest time from entry to exit, including preempting exceptions.
+    pub max_duration: u64,
+    /// The sum of the times from entry to exit.
+    pub total_duration: u64,
+    /// Number of exits, which can be lower than `count` at the end of the capture.
+    pub exits: u64,
+    /// The time spent in the handler itself, excluding preempting exceptions.
+    pub exclusive: u64,
+    /// The shortest time between two entries.
+    pub min_interval: Option<u64>,
+    /// The longest time between two entries.
+    pub max_interval: Option<u64>,
+    last_entry: Option<u64>,
+}
+
+impl ExceptionStatistics {
+    /// Returns the average time from entry to exit.
+    pub fn average_duration(&self) -> Option<u64> {
+        self.total_duration.checked_div(self.exits)
+    }
+}
+
+/// Collects the timing of all exceptions in an ITM stream.
+#[derive(Debug, Default)]
+pub struct ExceptionAnalysis {
+    /// The time of the last local timestamp.
+    time: u64,
+    /// The time of the first exception event.
+    start: Option<u64>,
+    /// Exception events waiting for the timestamp that follows them.
+    pending: Vec<(u16, ExceptionAction)>,
+    /// Exceptions being handled, the innermost last, with the time they were entered.
+    active: Vec<(u16, u64)>,
+    /// The time up to which the active exception was charged.
+    charged: u64,
+    statistics: BTreeMap<u16, ExceptionStatistics>,
+    overflows: u64,
+}
+
+impl ExceptionAnalysis {
+    pub fn new() -> Self {
+        ExceptionAnalysis::default()
+    }
+
+    pub fn process(&mut self, packet: &Packet) {
+        match packet {
+            Packet::ExceptionTrace { number, action } => self.pending.push((*number, *action)),
+            Packet::LocalTimestamp { delta, .. } => {
+                self.time += u64::from(*delta);
+                self.apply_pending();
+            }
+            Packet::Overflow => self.overflows += 1,
+            _ => (),
+        }
+    }
+
+    /// Applies the events after the last timestamp, at the end of the capture.
+    pub fn finish(&mut self) {
+        self.apply_pending();
+    }
+
+    /// Returns the time between the first exception event and the last timestamp.
+    pub fn window(&self) -> u64 {
+        self.start.map_or(0, |start| self.time - start)
+    }
+
+    /// Returns the number of overflow packets, each of which means that events were lost.
+    pub fn overflows(&self) -> u64 {
+        self.overflows
+    }
+
+    /// Returns the statistics of every exception that was seen, ordered by exception number.
+    pub fn statistics(&self) -> &BTreeMap<u16, ExceptionStatistics> {
+        &self.statistics
+    }
+
+    /// Returns the share of the capture window spent in the handler of an exception.
+    pub fn load(&self, number: u16) -> f64 {
+        match (self.statistics.get(&number), self.window()) {
+            (Some(statistics), window) if window > 0 => statistics.exclusive as f64 / window as f64,
+            _ => 0.0,
+        }
+    }
+
+    fn apply_pending(&mut self) {
+        let time = self.time;
+        let pending: Vec<_> = self.pending.drain(..).collect();
+
+        for (number, action) in pending {
+            if self.start.is_none() {
+                self.start = Some(time);
+                self.charged = time;
+            }
+
+            // The exception that ran until now is charged before the active one changes.
+            if let Some((active, _)) = self.active.last() {
+                let active = *active;
+                self.statistics.entry(active).or_default().exclusive += time - self.charged;
+            }
+            self.charged = time;
+
+            match action {
+                ExceptionAction::Entered => {
+                    let statistics = self.statistics.entry(number).or_default();
+                    statistics.count += 1;
+
+                    if let Some(last_entry) = statistics.last_entry {
+                        let interval = time - last_entry;
+                        statistics.min_interval = Some(
+                            statistics
+                                .min_interval
+                                .map_or(interval, |i| i.min(interval)),
+                        );
+                        statistics.max_interval = Some(
+                            statistics
+                                .max_interval
+                                .map_or(interval, |i| i.max(interval)),
+                        );
+                    }
+                    statistics.last_entry = Some(time);
+
+                    self.active.push((number, time));
+                }
+                ExceptionAction::Exited => {
+                    // Exits without a matching entry happen at the start of the capture.
+                    if let Some(position) = self.active.iter().rposition(|(n, _)| *n == number) {
+                        let (_, entry) = self.active.remove(position);
+                        let duration = time - entry;
+
+                        let statistics = self.statistics.entry(number).or_default();
+                        if statistics.exits == 0 || duration < statistics.min_duration {
+                            statistics.min_duration = duration;
+                        }
+                        statistics.max_duration = statistics.max_duration.max(duration);
+                        statistics.total_duration += duration;
+                        statistics.exits += 1;
+                    }
+                }
+                ExceptionAction::Returned => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{ExceptionAction, Packet, TimestampRelation};
+    use super::ExceptionAnalysis;
+
+    fn exception(number: u16, action: ExceptionAction) -> Packet {
+        Packet::ExceptionTrace { number, action }
+    }
+
+    fn timestamp(delta: u32) -> Packet {
+        Packet::LocalTimestamp {
+            delta,
+            relation: TimestampRelation::Synchronous,
+        }
+    }
+
+    #[test]
+    fn nested_exceptions() {
+        let mut analysis = ExceptionAnalysis::new();
+
+        for packet in &[
+            exception(16, ExceptionAction::Entered),
+            timestamp(100),
+            exception(15, ExceptionAction::Entered),
+            timestamp(10),
+            exception(15, ExceptionAction::Exited),
+            exception(16, ExceptionAction::Returned),
+            timestamp(5),
+            exception(16, ExceptionAction::Exited),
+            timestamp(5),
+        ] {
+            analysis.process(packet);
+        }
+        analysis.finish();
+
+        let irq = &analysis.statistics()[&16];
+        assert_eq!(irq.count, 1);
+        assert_eq!(irq.max_duration, 20);
+        assert_eq!(irq.exclusive, 15);
+
+        let systick = &analysis.statistics()[&15];
+        assert_eq!(systick.exclusive, 5);
+
+        assert_eq!(analysis.window(), 20);
+        assert!((analysis.load(16) - 0.75).abs() < 1e-9);
+    }
+}
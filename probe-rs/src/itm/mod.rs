@@ -0,0 +1,511 @@
+//! Decoding of the trace data stream of the Instrumentation Trace Macrocell (ITM).
+//!
+//! The ITM merges the data written to its stimulus ports by the firmware with the packets
+//! generated by the DWT, such as exception trace and PC samples, and adds timestamps.
+//! The stream is usually captured through the SWO pin.
+
+pub mod analysis;
+
+use std::error::Error;
+use std::fmt;
+
+/// A synchronization packet is at least 47 zero bits followed by a one bit.
+const SYNC_ZEROES: usize = 5;
+
+/// Timestamps and extension packets have at most this many payload bytes.
+const MAX_CONTINUATION: usize = 4;
+
+/// Header of a global timestamp packet carrying bits 0 to 25 of the timestamp.
+const GTS1_HEADER: u8 = 0x94;
+
+/// Header of a global timestamp packet carrying the upper bits of the timestamp.
+const GTS2_HEADER: u8 = 0xB4;
+
+const OVERFLOW_HEADER: u8 = 0x70;
+
+/// Global timestamp packets of the first format carry the lower 26 bits of the timestamp.
+const GTS1_BITS: u32 = 26;
+
+#[derive(Debug)]
+pub enum ItmError {
+    /// The stream contains a header which the architecture reserves.
+    ReservedHeader(u8),
+    /// The payload of the packet with this header is invalid.
+    MalformedPacket(u8),
+}
+
+impl Error for ItmError {}
+
+impl fmt::Display for ItmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ItmError::ReservedHeader(header) => {
+                write!(f, "Reserved ITM header 0x{:02x}.", header)
+            }
+            ItmError::MalformedPacket(header) => {
+                write!(f, "Malformed ITM packet with header 0x{:02x}.", header)
+            }
+        }
+    }
+}
+
+/// How a local timestamp relates to the packets it times.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TimestampRelation {
+    /// The timestamp is exact.
+    Synchronous,
+    /// The timestamp was delayed relative to the packets.
+    TimestampDelayed,
+    /// The packets were delayed relative to the event they describe.
+    PacketDelayed,
+    /// Both the timestamp and the packets were delayed.
+    BothDelayed,
+}
+
+/// What happened to an exception in an exception trace packet.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExceptionAction {
+    Entered,
+    Exited,
+    /// The core returned to the exception, e.g. after a preempting exception exited.
+    Returned,
+}
+
+/// A packet of the ITM stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Sync,
+    /// The ITM dropped packets because its FIFO was full.
+    Overflow,
+    /// Data the firmware wrote to a stimulus port.
+    Instrumentation {
+        port: u8,
+        payload: Vec<u8>,
+    },
+    /// The time since the last local timestamp, in cycles of the timestamp clock.
+    LocalTimestamp {
+        delta: u32,
+        relation: TimestampRelation,
+    },
+    /// The value of the global timestamp counter.
+    GlobalTimestamp {
+        value: u64,
+        /// Set if the clock of the timestamp counter changed since the last timestamp.
+        clock_changed: bool,
+    },
+    /// One of the DWT event counters wrapped around. Each bit of `counters` stands
+    /// for one counter: CPI, exception, sleep, LSU, folded and cycle counter.
+    EventCounter {
+        counters: u8,
+    },
+    ExceptionTrace {
+        number: u16,
+        action: ExceptionAction,
+    },
+    /// A sample of the program counter, `None` if the core was sleeping.
+    PcSample(Option<u32>),
+    /// The program counter of an access that matched a DWT comparator.
+    DataTracePc {
+        comparator: u8,
+        pc: u32,
+    },
+    /// The lower bits of the address of an access that matched a DWT comparator.
+    DataTraceAddress {
+        comparator: u8,
+        address: u16,
+    },
+    /// The value of an access that matched a DWT comparator.
+    DataTraceValue {
+        comparator: u8,
+        write: bool,
+        value: u32,
+    },
+    /// A packet of another hardware source.
+    Hardware {
+        discriminator: u8,
+        value: u32,
+    },
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Packet::Sync => write!(f, "sync"),
+            Packet::Overflow => write!(f, "overflow"),
+            Packet::Instrumentation { port, payload } => {
+                write!(f, "port {}:", port)?;
+                for byte in payload {
+                    write!(f, " {:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Packet::LocalTimestamp { delta, relation } => match relation {
+                TimestampRelation::Synchronous => write!(f, "timestamp +{}", delta),
+                _ => write!(f, "timestamp +{} ({:?})", delta, relation),
+            },
+            Packet::GlobalTimestamp {
+                value,
+                clock_changed,
+            } => {
+                write!(f, "global timestamp {}", value)?;
+                if *clock_changed {
+                    write!(f, " (clock changed)")?;
+                }
+                Ok(())
+            }
+            Packet::EventCounter { counters } => write!(f, "event counters {:06b}", counters),
+            Packet::ExceptionTrace { number, action } => {
+                write!(f, "{:?} {}", action, analysis::exception_name(*number))
+            }
+            Packet::PcSample(Some(pc)) => write!(f, "pc 0x{:08x}", pc),
+            Packet::PcSample(None) => write!(f, "pc sleeping"),
+            Packet::DataTracePc { comparator, pc } => {
+                write!(f, "comparator {} pc 0x{:08x}", comparator, pc)
+            }
+            Packet::DataTraceAddress {
+                comparator,
+                address,
+            } => write!(f, "comparator {} address 0x{:04x}", comparator, address),
+            Packet::DataTraceValue {
+                comparator,
+                write,
+                value,
+            } => write!(
+                f,
+                "comparator {} {} 0x{:x}",
+                comparator,
+                if *write { "write" } else { "read" },
+                value
+            ),
+            Packet::Hardware {
+                discriminator,
+                value,
+            } => write!(f, "hardware source {} 0x{:x}", discriminator, value),
+        }
+    }
+}
+
+/// Decodes packets from an ITM stream which arrives in arbitrary chunks.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    /// The page of stimulus ports selected by the last extension packet.
+    page: u8,
+    /// The bits of the global timestamp, which packets only update partially.
+    global_timestamp: u64,
+    /// A global timestamp waiting for its upper bits, with its clock change flag.
+    pending_global: Option<bool>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    /// Adds bytes received from the target.
+    pub fn received(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Decodes the next complete packet, if one was received.
+    pub fn decode(&mut self) -> Option<Result<Packet, ItmError>> {
+        loop {
+            let (length, packet) = self.parse()?;
+            self.buffer.drain(..length);
+
+            if let Some(packet) = packet {
+                return Some(packet);
+            }
+        }
+    }
+
+    /// Parses the packet at the start of the buffer.
+    ///
+    /// Returns the length of the packet, and the packet unless it only updated the state of
+    /// the decoder. Returns `None` if the packet is incomplete.
+    #[allow(clippy::type_complexity)]
+    fn parse(&mut self) -> Option<(usize, Option<Result<Packet, ItmError>>)> {
+        let header = *self.buffer.first()?;
+
+        if header == 0 {
+            let zeroes = self.buffer.iter().position(|b| *b != 0)?;
+
+            return if self.buffer[zeroes] == 0x80 && zeroes >= SYNC_ZEROES {
+                Some((zeroes + 1, Some(Ok(Packet::Sync))))
+            } else {
+                // Zeroes outside of a synchronization packet are padding.
+                Some((zeroes, None))
+            };
+        }
+
+        if header == OVERFLOW_HEADER {
+            return Some((1, Some(Ok(Packet::Overflow))));
+        }
+
+        // Source packets, with the size of the payload in the lowest bits.
+        if header & 0b11 != 0 {
+            let size = match header & 0b11 {
+                1 => 1,
+                2 => 2,
+                _ => 4,
+            };
+
+            let payload = self.buffer.get(1..=size)?;
+            let address = header >> 3;
+
+            let packet = if header & 0b100 == 0 {
+                Ok(Packet::Instrumentation {
+                    port: self.page * 32 + address,
+                    payload: payload.to_vec(),
+                })
+            } else {
+                hardware_packet(header, address, payload)
+            };
+
+            return Some((size + 1, Some(packet)));
+        }
+
+        if header & 0x0f == 0 {
+            return if header & 0x80 == 0 {
+                // The second format of a local timestamp fits into its header.
+                Some((
+                    1,
+                    Some(Ok(Packet::LocalTimestamp {
+                        delta: u32::from(header >> 4),
+                        relation: TimestampRelation::Synchronous,
+                    })),
+                ))
+            } else if header & 0x40 != 0 {
+                let length = continuation(&self.buffer[1..])?;
+
+                let relation = match (header >> 4) & 0b11 {
+                    0 => TimestampRelation::Synchronous,
+                    1 => TimestampRelation::TimestampDelayed,
+                    2 => TimestampRelation::PacketDelayed,
+                    _ => TimestampRelation::BothDelayed,
+                };
+
+                Some((
+                    length + 1,
+                    Some(Ok(Packet::LocalTimestamp {
+                        delta: seven_bit_value(&self.buffer[1..=length]) as u32,
+                        relation,
+                    })),
+                ))
+            } else {
+                Some((1, Some(Err(ItmError::ReservedHeader(header)))))
+            };
+        }
+
+        if header == GTS1_HEADER {
+            let length = continuation(&self.buffer[1..])?;
+            let payload = &self.buffer[1..=length];
+
+            // Only the transmitted bits change, the others keep their last value.
+            let bits = (7 * length as u32).min(GTS1_BITS);
+            let mask = (1u64 << bits) - 1;
+            let value = seven_bit_value(payload) & mask;
+
+            self.global_timestamp = (self.global_timestamp & !mask) | value;
+
+            let mut clock_changed = false;
+            let mut wrapped = false;
+
+            if length == MAX_CONTINUATION {
+                clock_changed = payload[3] & 0x20 != 0;
+                wrapped = payload[3] & 0x40 != 0;
+            }
+
+            return if wrapped {
+                // The upper bits changed, and follow in a second packet.
+                self.pending_global = Some(clock_changed);
+                Some((length + 1, None))
+            } else {
+                Some((
+                    length + 1,
+                    Some(Ok(Packet::GlobalTimestamp {
+                        value: self.global_timestamp,
+                        clock_changed,
+                    })),
+                ))
+            };
+        }
+
+        if header == GTS2_HEADER {
+            let length = continuation(&self.buffer[1..])?;
+            let high = seven_bit_value(&self.buffer[1..=length]);
+
+            let low_mask = (1u64 << GTS1_BITS) - 1;
+            self.global_timestamp = (self.global_timestamp & low_mask) | (high << GTS1_BITS);
+
+            let packet = self.pending_global.take().map(|clock_changed| {
+                Ok(Packet::GlobalTimestamp {
+                    value: self.global_timestamp,
+                    clock_changed,
+                })
+            });
+
+            return Some((length + 1, packet));
+        }
+
+        // Extension packets. The only one defined for the ITM selects the page of the stimulus ports.
+        if header & 0b1011 == 0b1000 {
+            let length = if header & 0x80 == 0 {
+                0
+            } else {
+                continuation(&self.buffer[1..])?
+            };
+
+            if header & 0b100 == 0 {
+                let value = u64::from((header >> 4) & 0b111)
+                    | seven_bit_value(&self.buffer[1..=length]) << 3;
+
+                self.page = value as u8;
+            }
+
+            return Some((length + 1, None));
+        }
+
+        Some((1, Some(Err(ItmError::ReservedHeader(header)))))
+    }
+}
+
+/// Decodes a packet of the DWT.
+fn hardware_packet(header: u8, discriminator: u8, payload: &[u8]) -> Result<Packet, ItmError> {
+    let value = payload
+        .iter()
+        .rev()
+        .fold(0u32, |value, byte| value << 8 | u32::from(*byte));
+
+    let packet = match discriminator {
+        0 => Packet::EventCounter {
+            counters: value as u8,
+        },
+        1 if payload.len() == 2 => {
+            let action = match (payload[1] >> 4) & 0b11 {
+                1 => ExceptionAction::Entered,
+                2 => ExceptionAction::Exited,
+                3 => ExceptionAction::Returned,
+                _ => return Err(ItmError::MalformedPacket(header)),
+            };
+
+            Packet::ExceptionTrace {
+                number: (value & 0x1ff) as u16,
+                action,
+            }
+        }
+        2 if payload.len() == 4 => Packet::PcSample(Some(value)),
+        2 => Packet::PcSample(None),
+        8..=15 if discriminator & 1 == 0 => Packet::DataTracePc {
+            comparator: (discriminator >> 1) & 0b11,
+            pc: value,
+        },
+        8..=15 => Packet::DataTraceAddress {
+            comparator: (discriminator >> 1) & 0b11,
+            address: value as u16,
+        },
+        16..=23 => Packet::DataTraceValue {
+            comparator: (discriminator >> 1) & 0b11,
+            write: discriminator & 1 != 0,
+            value,
+        },
+        _ => Packet::Hardware {
+            discriminator,
+            value,
+        },
+    };
+
+    Ok(packet)
+}
+
+/// Returns the number of payload bytes of a packet whose bytes have a continuation bit,
+/// or `None` if the payload is incomplete.
+fn continuation(data: &[u8]) -> Option<usize> {
+    for (i, byte) in data.iter().enumerate().take(MAX_CONTINUATION) {
+        if byte & 0x80 == 0 || i + 1 == MAX_CONTINUATION {
+            return Some(i + 1);
+        }
+    }
+
+    None
+}
+
+/// Assembles a value from payload bytes carrying seven bits each, least significant first.
+fn seven_bit_value(payload: &[u8]) -> u64 {
+    payload.iter().enumerate().fold(0, |value, (i, byte)| {
+        value | u64::from(byte & 0x7f) << (7 * i)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Decoder, ExceptionAction, Packet, TimestampRelation};
+
+    fn decode_all(data: &[u8]) -> Vec<Packet> {
+        let mut decoder = Decoder::new();
+        decoder.received(data);
+
+        let mut packets = vec![];
+        while let Some(packet) = decoder.decode() {
+            packets.push(packet.unwrap());
+        }
+        packets
+    }
+
+    #[test]
+    fn decodes_exception_trace_with_timestamps() {
+        let packets = decode_all(&[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // sync
+            0x0e, 0x1f, 0x10, // IRQ 15 entered
+            0xc0, 0x88, 0x01, // local timestamp 136
+            0x0e, 0x1f, 0x20, // IRQ 15 exited
+            0x30, // local timestamp 3
+        ]);
+
+        assert_eq!(
+            packets,
+            vec![
+                Packet::Sync,
+                Packet::ExceptionTrace {
+                    number: 31,
+                    action: ExceptionAction::Entered
+                },
+                Packet::LocalTimestamp {
+                    delta: 136,
+                    relation: TimestampRelation::Synchronous
+                },
+                Packet::ExceptionTrace {
+                    number: 31,
+                    action: ExceptionAction::Exited
+                },
+                Packet::LocalTimestamp {
+                    delta: 3,
+                    relation: TimestampRelation::Synchronous
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn combines_global_timestamps() {
+        let packets = decode_all(&[
+            0x94, 0x85, 0x80, 0x80, 0x40, // lower bits 5, wrapped
+            0xb4, 0x02, // upper bits 2
+            0x94, 0x07, // lower bits 7
+        ]);
+
+        assert_eq!(
+            packets,
+            vec![
+                Packet::GlobalTimestamp {
+                    value: 2 << 26 | 5,
+                    clock_changed: false
+                },
+                Packet::GlobalTimestamp {
+                    value: 2 << 26 | 7,
+                    clock_changed: false
+                },
+            ]
+        );
+    }
+}
@@ -6,6 +6,7 @@
 pub extern crate bitflags;
 #[macro_use]
 pub extern crate derivative;
+#[cfg(feature = "stlink")]
 #[macro_use]
 extern crate rental;
 #[macro_use]
@@ -14,9 +15,20 @@ extern crate maplit;
 extern crate serde_derive;
 
 pub mod collection;
+pub mod config;
 pub mod coresight;
+#[cfg(feature = "debug")]
 pub mod debug;
+#[cfg(feature = "debug")]
+pub mod defmt;
+pub mod event;
+pub mod itm;
 pub mod memory;
 pub mod probe;
+#[cfg(feature = "debug")]
+pub mod rtos;
+pub mod rtt;
 pub mod session;
+pub mod session_builder;
+pub mod swo;
 pub mod target;
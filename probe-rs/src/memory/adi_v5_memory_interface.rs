@@ -382,9 +382,9 @@ impl ADIMemoryInterface {
 
     /// Write a block of 8bit words at `addr`.
     ///
-    /// The number of words written is `data.len()`.
-    /// The address where the write should be performed at has to be word aligned.
-    /// Returns `AccessPortError::MemoryNotAligned` if this does not hold true.
+    /// The number of words written is `data.len()`. The address does not have to be aligned:
+    /// the words at either end, which are only partly written, are read, modified and written
+    /// back, and the words in between are written as a block.
     pub fn write_block8<AP>(
         &self,
         debug_port: &mut AP,
@@ -394,45 +394,63 @@ impl ADIMemoryInterface {
     where
         AP: APAccess<MemoryAP, CSW> + APAccess<MemoryAP, TAR> + APAccess<MemoryAP, DRW>,
     {
-        let pre_bytes = ((4 - (address % 4)) % 4) as usize;
-        let pre_address = address / 4;
-        let aligned_address = address + pre_bytes as u32;
-        let post_bytes = (data.len() - pre_bytes) % 4;
-        let post_address = address + (data.len() - post_bytes) as u32;
-
-        if pre_bytes != 0 {
-            let mut pre_data = self.read32(debug_port, pre_address)?;
-            for (i, shift) in (4 - pre_bytes..4).enumerate() {
-                pre_data &= !(0xFF << (shift * 8));
-                pre_data |= u32::from(data[i]) << (shift * 8);
-            }
+        address
+            .checked_add(data.len() as u32)
+            .ok_or(AccessPortError::OutOfBoundsError)?;
 
-            self.write32(debug_port, pre_address, pre_data)?;
+        let mut address = address;
+        let mut data = data;
+
+        // The bytes up to the first word boundary.
+        let offset = (address % 4) as usize;
+        if offset != 0 && !data.is_empty() {
+            let length = usize::min(4 - offset, data.len());
+            self.write_partial_word(debug_port, address - offset as u32, offset, &data[..length])?;
+
+            address += length as u32;
+            data = &data[length..];
         }
 
-        self.write_block32(
-            debug_port,
-            aligned_address,
-            data[pre_bytes..data.len() - post_bytes]
-                .chunks(4)
-                .map(|c| c.pread::<u32>(0).expect("This is a bug. Please report it."))
-                .collect::<Vec<_>>()
-                .as_slice(),
-        )?;
-
-        if post_bytes != 0 {
-            let mut post_data = self.read32(debug_port, post_address)?;
-            dbg!(post_bytes);
-            for shift in 0..post_bytes {
-                post_data &= !(0xFF << (shift * 8));
-                post_data |= u32::from(data[data.len() - post_bytes + shift]) << (shift * 8);
-            }
+        let aligned_length = data.len() - data.len() % 4;
+        if aligned_length > 0 {
+            self.write_block32(
+                debug_port,
+                address,
+                data[..aligned_length]
+                    .chunks(4)
+                    .map(|c| c.pread::<u32>(0).expect("This is a bug. Please report it."))
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            )?;
+
+            address += aligned_length as u32;
+            data = &data[aligned_length..];
+        }
 
-            self.write32(debug_port, post_address, post_data)?;
+        // The bytes after the last word boundary.
+        if !data.is_empty() {
+            self.write_partial_word(debug_port, address, 0, data)?;
         }
 
         Ok(())
     }
+
+    /// Replaces the bytes starting at `offset` of the word at the aligned `address` with `data`.
+    fn write_partial_word<AP>(
+        &self,
+        debug_port: &mut AP,
+        address: u32,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), AccessPortError>
+    where
+        AP: APAccess<MemoryAP, CSW> + APAccess<MemoryAP, TAR> + APAccess<MemoryAP, DRW>,
+    {
+        let mut word = self.read32(debug_port, address)?.to_le_bytes();
+        word[offset..offset + data.len()].copy_from_slice(data);
+
+        self.write32(debug_port, address, u32::from_le_bytes(word))
+    }
 }
 
 #[cfg(test)]
@@ -746,6 +764,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn write_block_u8_within_word() {
+        let mut mock = MockMemoryAP::default();
+        mock.data[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        let mi = ADIMemoryInterface::new(0x0);
+        debug_assert!(mi.write_block8(&mut mock, 5, &[0xEF, 0xBE]).is_ok());
+        debug_assert_eq!(mock.data[4..8], [1, 0xEF, 0xBE, 4]);
+    }
+
     #[test]
     fn write_block_u8_unaligned() {
         let mut mock = MockMemoryAP::default();
@@ -0,0 +1,159 @@
+//! Cache of the memory read while the core is halted.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Size of the blocks memory is cached in.
+pub const LINE_SIZE: u32 = 32;
+
+/// The memory read since the core halted, in aligned lines of `LINE_SIZE` bytes.
+///
+/// The memory of a halted core only changes when the debugger writes it, so repeated reads,
+/// e.g. of the stack when unwinding and of the code when disassembling, can be served from the
+/// cache. Every resume starts a new halt generation, which drops the cached lines. Only flash
+/// and RAM are cached, as reading peripheral registers may have side effects.
+#[derive(Debug, Clone)]
+pub struct MemoryCache {
+    regions: Vec<Range<u32>>,
+    halted: bool,
+    generation: u64,
+    lines: HashMap<u32, [u8; LINE_SIZE as usize]>,
+}
+
+impl MemoryCache {
+    /// Creates a cache for reads within `regions`.
+    pub fn new(regions: Vec<Range<u32>>) -> Self {
+        MemoryCache {
+            regions,
+            halted: false,
+            generation: 0,
+            lines: HashMap::new(),
+        }
+    }
+
+    /// Returns the current halt generation, which changes whenever the core resumes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Sets whether the core is halted. Resuming starts a new halt generation.
+    pub fn set_halted(&mut self, halted: bool) {
+        if self.halted && !halted {
+            self.generation += 1;
+            self.lines.clear();
+        }
+
+        self.halted = halted;
+    }
+
+    /// Returns the addresses of the lines covering `len` bytes at `address` which are not cached
+    /// yet, or `None` if the range cannot be cached.
+    pub fn missing_lines(&self, address: u32, len: usize) -> Option<Vec<u32>> {
+        let lines = self.lines_of(address, len)?;
+
+        if !self.halted
+            || !self
+                .regions
+                .iter()
+                .any(|region| region.start <= lines.start && lines.end <= region.end)
+        {
+            return None;
+        }
+
+        Some(
+            lines
+                .step_by(LINE_SIZE as usize)
+                .filter(|line| !self.lines.contains_key(line))
+                .collect(),
+        )
+    }
+
+    /// Adds a line read from the memory.
+    pub fn insert(&mut self, line: u32, data: [u8; LINE_SIZE as usize]) {
+        if self.halted {
+            self.lines.insert(line, data);
+        }
+    }
+
+    /// Copies the memory at `address` into `data`, if all of it is cached.
+    pub fn read(&self, address: u32, data: &mut [u8]) -> bool {
+        let lines = match self.lines_of(address, data.len()) {
+            Some(lines) if self.halted => lines,
+            _ => return false,
+        };
+
+        for line in lines.step_by(LINE_SIZE as usize) {
+            let cached = match self.lines.get(&line) {
+                Some(cached) => cached,
+                None => return false,
+            };
+
+            // The part of `data` and of the line which overlap.
+            let start = address.max(line);
+            let end = (address + data.len() as u32).min(line + LINE_SIZE);
+            data[(start - address) as usize..(end - address) as usize]
+                .copy_from_slice(&cached[(start - line) as usize..(end - line) as usize]);
+        }
+
+        true
+    }
+
+    /// Drops the lines covering `len` bytes at `address`, because they were written.
+    pub fn invalidate(&mut self, address: u32, len: usize) {
+        if let Some(lines) = self.lines_of(address, len) {
+            for line in lines.step_by(LINE_SIZE as usize) {
+                self.lines.remove(&line);
+            }
+        }
+    }
+
+    /// Returns the addresses from the first to the end of the last line covering the range.
+    fn lines_of(&self, address: u32, len: usize) -> Option<Range<u32>> {
+        if len == 0 {
+            return None;
+        }
+
+        let end = address.checked_add(len as u32)?;
+        let start = address - address % LINE_SIZE;
+        let end = end.checked_add(LINE_SIZE - 1)? / LINE_SIZE * LINE_SIZE;
+
+        Some(start..end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemoryCache, LINE_SIZE};
+
+    #[test]
+    fn serves_reads_while_halted() {
+        let mut cache = MemoryCache::new(vec![0x2000_0000..0x2001_0000]);
+        assert_eq!(cache.missing_lines(0x2000_0010, 4), None);
+
+        cache.set_halted(true);
+        assert_eq!(
+            cache.missing_lines(0x2000_0010, 0x20),
+            Some(vec![0x2000_0000, 0x2000_0020])
+        );
+        assert_eq!(cache.missing_lines(0x4000_0000, 4), None);
+
+        let mut line = [0u8; LINE_SIZE as usize];
+        for (i, byte) in line.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        cache.insert(0x2000_0000, line);
+        cache.insert(0x2000_0020, line);
+
+        let mut data = [0u8; 4];
+        assert!(cache.read(0x2000_001e, &mut data));
+        assert_eq!(data, [30, 31, 0, 1]);
+
+        cache.invalidate(0x2000_0024, 1);
+        assert!(!cache.read(0x2000_001e, &mut data));
+
+        cache.set_halted(false);
+        cache.set_halted(true);
+        assert_eq!(cache.generation(), 1);
+        assert!(!cache.read(0x2000_0000, &mut data));
+    }
+}
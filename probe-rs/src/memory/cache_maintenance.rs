@@ -0,0 +1,193 @@
+//! Keeping the caches of Cortex-M7 cores coherent with the memory the debugger writes.
+//!
+//! Writes through the debug port bypass the caches of the core. Without maintenance the core
+//! goes on executing stale instructions from its I-cache after code was written into RAM or
+//! flash, and its D-cache hides or, when evicted, overwrites the written data.
+
+use super::MI;
+use crate::coresight::access_ports::AccessPortError;
+
+use std::ops::Range;
+
+/// Configuration and Control Register, which enables the caches.
+const CCR: u32 = 0xE000_ED14;
+const CCR_DC: u32 = 1 << 16;
+const CCR_IC: u32 = 1 << 17;
+/// Cache Size ID Register, describing the cache selected by CSSELR.
+const CCSIDR: u32 = 0xE000_ED80;
+const CSSELR: u32 = 0xE000_ED84;
+/// Invalidates the whole I-cache.
+const ICIALLU: u32 = 0xE000_EF50;
+/// Invalidates a D-cache line by address.
+const DCIMVAC: u32 = 0xE000_EF5C;
+/// Invalidates a D-cache line by set and way.
+const DCISW: u32 = 0xE000_EF60;
+/// Cleans a D-cache line by address, writing it back to memory if dirty.
+const DCCMVAC: u32 = 0xE000_EF68;
+/// Cleans a D-cache line by set and way.
+const DCCSW: u32 = 0xE000_EF6C;
+
+/// The system region, which is never executed from and never cached.
+const SYSTEM_REGION: u32 = 0xE000_0000;
+
+/// Maintains the caches of the core around writes to the memory it executes code from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheMaintenance {
+    executable: Vec<Range<u32>>,
+}
+
+impl CacheMaintenance {
+    /// Maintains the caches around writes within `executable`, usually the flash and the RAM
+    /// of the target.
+    pub fn new(executable: Vec<Range<u32>>) -> Self {
+        CacheMaintenance { executable }
+    }
+
+    /// Whether a write of `len` bytes at `address` needs maintenance.
+    pub fn covers(&self, address: u32, len: usize) -> bool {
+        let end = address.saturating_add(len as u32);
+
+        address < SYSTEM_REGION
+            && self
+                .executable
+                .iter()
+                .any(|range| address < range.end && range.start < end)
+    }
+
+    /// Cleans the D-cache lines the write will overlap, so the data the core wrote into them
+    /// is in memory before the debugger writes its own.
+    pub fn before_write(
+        &self,
+        mi: &mut impl MI,
+        address: u32,
+        len: usize,
+    ) -> Result<(), AccessPortError> {
+        clean_data(mi, address, len)
+    }
+
+    /// Invalidates the D-cache lines the write overlapped and the whole I-cache, so the core
+    /// reads and executes what the debugger wrote.
+    pub fn after_write(
+        &self,
+        mi: &mut impl MI,
+        address: u32,
+        len: usize,
+    ) -> Result<(), AccessPortError> {
+        let ccr = mi.read32(CCR)?;
+
+        if ccr & CCR_DC != 0 {
+            maintain_data(mi, address, len, DCIMVAC, DCISW)?;
+        }
+        if ccr & CCR_IC != 0 {
+            mi.write32(ICIALLU, 0)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Cleans the D-cache lines overlapping `len` bytes at `address`, so the data the core wrote
+/// into them is in memory. Does nothing on cores without an enabled D-cache.
+pub fn clean_data(mi: &mut impl MI, address: u32, len: usize) -> Result<(), AccessPortError> {
+    if mi.read32(CCR)? & CCR_DC != 0 {
+        maintain_data(mi, address, len, DCCMVAC, DCCSW)?;
+    }
+
+    Ok(())
+}
+
+/// Applies a D-cache operation to the lines overlapping a write, by address, or to the whole
+/// cache by set and way if that takes fewer writes.
+fn maintain_data(
+    mi: &mut impl MI,
+    address: u32,
+    len: usize,
+    by_address: u32,
+    by_set_way: u32,
+) -> Result<(), AccessPortError> {
+    mi.write32(CSSELR, 0)?;
+    let geometry = CacheGeometry::from(mi.read32(CCSIDR)?);
+    let lines = lines(address, len, geometry.line_size);
+    let set_way_operands = geometry.set_way_operands();
+
+    if lines.len() <= set_way_operands.len() {
+        for line in lines {
+            mi.write32(by_address, line)?;
+        }
+    } else {
+        for operand in set_way_operands {
+            mi.write32(by_set_way, operand)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The layout of a cache, as described by CCSIDR.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct CacheGeometry {
+    /// The size of a line in bytes.
+    line_size: u32,
+    ways: u32,
+    sets: u32,
+}
+
+impl From<u32> for CacheGeometry {
+    fn from(ccsidr: u32) -> Self {
+        CacheGeometry {
+            line_size: 1 << ((ccsidr & 0x7) + 4),
+            ways: ((ccsidr >> 3) & 0x3ff) + 1,
+            sets: ((ccsidr >> 13) & 0x7fff) + 1,
+        }
+    }
+}
+
+impl CacheGeometry {
+    /// Returns the operands of DCISW and DCCSW addressing every line of the cache.
+    fn set_way_operands(self) -> Vec<u32> {
+        // The way is in the top bits, as many as needed to number the ways.
+        let way_bits = 32 - (self.ways - 1).leading_zeros();
+        let set_shift = self.line_size.trailing_zeros();
+
+        (0..self.ways)
+            .flat_map(|way| {
+                (0..self.sets)
+                    .map(move |set| way.checked_shl(32 - way_bits).unwrap_or(0) | set << set_shift)
+            })
+            .collect()
+    }
+}
+
+/// Returns the addresses of the cache lines overlapping `len` bytes at `address`.
+fn lines(address: u32, len: usize, line_size: u32) -> Vec<u32> {
+    let start = address & !(line_size - 1);
+    let end = address.saturating_add(len as u32);
+
+    (start..end).step_by(line_size as usize).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lines, CacheGeometry};
+
+    #[test]
+    fn addresses_lines() {
+        assert_eq!(lines(0x2000_001c, 8, 32), vec![0x2000_0000, 0x2000_0020]);
+
+        // The 16 KiB D-cache of a Cortex-M7: 4 ways of 128 sets of 32 byte lines.
+        let geometry = CacheGeometry::from(0xF00F_E019);
+        assert_eq!(
+            geometry,
+            CacheGeometry {
+                line_size: 32,
+                ways: 4,
+                sets: 128
+            }
+        );
+
+        let operands = geometry.set_way_operands();
+        assert_eq!(operands.len(), 512);
+        assert_eq!(operands[1], 1 << 5);
+        assert_eq!(operands[128], 1 << 30);
+    }
+}
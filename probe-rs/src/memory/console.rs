@@ -0,0 +1,125 @@
+//! Output of targets which write their log into a ring buffer in RAM, instead of using RTT.
+//!
+//! The target writes bytes into the buffer at its write index and then advances the index,
+//! wrapping around at the end of the buffer. The host reads the bytes between its own position
+//! and the write index. If the target also has a read index, the host advances it after
+//! reading, so the target can tell when the buffer is full; otherwise the target overwrites old
+//! output the host did not read in time.
+
+use super::MI;
+use crate::coresight::access_ports::AccessPortError;
+
+use std::ops::Range;
+
+/// An index of the ring buffer in target memory, an unsigned integer of 1, 2 or 4 bytes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RingIndex {
+    pub address: u32,
+    pub size: u32,
+}
+
+impl RingIndex {
+    fn read(self, mi: &mut impl MI) -> Result<u32, AccessPortError> {
+        let mut bytes = [0; 4];
+        mi.read_block8(self.address, &mut bytes[..self.size.min(4) as usize])?;
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write(self, mi: &mut impl MI, value: u32) -> Result<(), AccessPortError> {
+        match self.size {
+            1 => mi.write8(self.address, value as u8),
+            2 => mi.write_block8(self.address, &(value as u16).to_le_bytes()),
+            _ => mi.write32(self.address, value),
+        }
+    }
+}
+
+/// Reads the output a target writes into a ring buffer.
+#[derive(Debug)]
+pub struct MemoryConsole {
+    /// The address of the buffer.
+    buffer: u32,
+    /// The size of the buffer in bytes.
+    size: u32,
+    write_index: RingIndex,
+    read_index: Option<RingIndex>,
+    /// The position in the buffer up to which the output was read.
+    position: u32,
+}
+
+impl MemoryConsole {
+    /// Attaches to the ring buffer of `size` bytes at `buffer`.
+    ///
+    /// Only output written from now on is read, unless the target has a read index, in which
+    /// case the output it holds back for the host is read as well.
+    pub fn attach(
+        mi: &mut impl MI,
+        buffer: u32,
+        size: u32,
+        write_index: RingIndex,
+        read_index: Option<RingIndex>,
+    ) -> Result<Self, AccessPortError> {
+        let size = size.max(1);
+        let position = match read_index {
+            Some(read_index) => read_index.read(mi)?,
+            None => write_index.read(mi)?,
+        };
+
+        Ok(MemoryConsole {
+            buffer,
+            size,
+            write_index,
+            read_index,
+            position: position % size,
+        })
+    }
+
+    /// Reads the output the target wrote since the last call.
+    pub fn read_available(&mut self, mi: &mut impl MI) -> Result<Vec<u8>, AccessPortError> {
+        let write = self.write_index.read(mi)? % self.size;
+        let mut data = vec![];
+
+        for range in pending(self.position, write, self.size) {
+            let start = data.len();
+            data.resize(start + range.len(), 0);
+            mi.read_block8(self.buffer + range.start, &mut data[start..])?;
+        }
+
+        if write != self.position {
+            self.position = write;
+
+            if let Some(read_index) = self.read_index {
+                read_index.write(mi, write)?;
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+/// Returns the ranges of the buffer between the read position and the write index, which
+/// are two if the output wraps around the end of the buffer.
+fn pending(position: u32, write: u32, size: u32) -> Vec<Range<u32>> {
+    if write >= position {
+        vec![position..write]
+    } else {
+        vec![position..size, 0..write]
+    }
+    .into_iter()
+    .filter(|range| range.start != range.end)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::pending;
+
+    #[test]
+    fn splits_wrapped_output() {
+        assert_eq!(pending(4, 4, 16), vec![]);
+        assert_eq!(pending(4, 10, 16), vec![4..10]);
+        assert_eq!(pending(12, 3, 16), vec![12..16, 0..3]);
+        assert_eq!(pending(12, 0, 16), vec![12..16]);
+    }
+}
@@ -0,0 +1,210 @@
+//! Reading memory through the core instead of the access port.
+//!
+//! The AHB-AP does not always see the memory like the core does. Bus firewalls block it from
+//! secure peripherals, and its accesses race with DMA transfers the firmware synchronizes
+//! with. Regions of the memory map marked with `read_through_core` are read by a stub the
+//! Cortex-M core executes instead, whose loads have the rights and the ordering of those of
+//! the firmware.
+//!
+//! The stub and its buffer are placed at the start of the RAM, which is restored afterwards,
+//! as are the core registers the stub uses. The words read from there are the ones saved
+//! through the access port. A load which faults halts the core at the entry of the HardFault
+//! handler, where it is left for inspection.
+
+use crate::collection::cores::m4::{Demcr, Dfsr};
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::cache_maintenance::clean_data;
+use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+use crate::target::{Core, CoreRegister, CoreRegisterAddress, CoreStatus};
+
+use std::error::Error;
+use std::fmt;
+
+/// Loads `r1` words from `r0` on into the buffer at `r2`, then halts.
+const STUB: [u16; 8] = [
+    0x6803, // ldr r3, [r0]
+    0x3004, // adds r0, #4
+    0x6013, // str r3, [r2]
+    0x3204, // adds r2, #4
+    0x3901, // subs r1, #1
+    0xD1F9, // bne 0
+    0xBE00, // bkpt #0
+    0xBF00, // nop
+];
+/// The offset of the BKPT instruction in the stub, where the core halts once it is done.
+const STUB_DONE: u32 = 12;
+const STUB_WORDS: usize = STUB.len() / 2;
+/// The words read by one run of the stub.
+const BUFFER_WORDS: usize = 256;
+
+/// CONTROL, FAULTMASK, BASEPRI and PRIMASK, as selected in DCRSR.
+const SPECIAL_REGISTERS: CoreRegisterAddress = CoreRegisterAddress(0b001_0100);
+/// Masks the interrupts, so the stub runs without the firmware interfering.
+const PRIMASK: u32 = 1;
+const XPSR_THUMB: u32 = 1 << 24;
+const DFSR_BKPT: u32 = 1 << 1;
+
+#[derive(Debug)]
+pub enum CoreAccessError {
+    DebugProbe(DebugProbeError),
+    /// Only Cortex-M cores, which `Session::identify_core` found, can run the stub, and only
+    /// cores whose debug registers move data through the core can do without it.
+    Unsupported,
+    /// The memory map has no RAM to place the stub in.
+    NoRam,
+    /// A load from the memory starting at the given address faulted. The core is left halted
+    /// at the entry of the HardFault handler.
+    Fault(u32),
+}
+
+impl Error for CoreAccessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CoreAccessError::DebugProbe(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CoreAccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoreAccessError::DebugProbe(e) => e.fmt(f),
+            CoreAccessError::Unsupported => {
+                f.write_str("The core of the target cannot read memory through the core.")
+            }
+            CoreAccessError::NoRam => {
+                f.write_str("The memory map has no RAM to read memory through the core.")
+            }
+            CoreAccessError::Fault(address) => {
+                write!(f, "Reading 0x{:08x} through the core faulted.", address)
+            }
+        }
+    }
+}
+
+impl From<DebugProbeError> for CoreAccessError {
+    fn from(error: DebugProbeError) -> Self {
+        CoreAccessError::DebugProbe(error)
+    }
+}
+
+impl From<AccessPortError> for CoreAccessError {
+    fn from(error: AccessPortError) -> Self {
+        CoreAccessError::DebugProbe(error.into())
+    }
+}
+
+/// Reads words of memory by loading them with the core, using the RAM at `scratch` for the
+/// stub. A running core is halted for the read and resumed afterwards.
+pub fn read_through_core(
+    core: &dyn Core,
+    probe: &mut MasterProbe,
+    scratch: u32,
+    address: u32,
+    data: &mut [u32],
+) -> Result<(), CoreAccessError> {
+    let resume = match core.status(probe)? {
+        CoreStatus::Halted(_) => false,
+        _ => {
+            core.halt(probe)?;
+            true
+        }
+    };
+
+    let registers = core.registers();
+    let saved_registers = [
+        registers.R0,
+        registers.R1,
+        registers.R2,
+        registers.R3,
+        registers.PC,
+        registers.XPSR,
+        SPECIAL_REGISTERS,
+    ];
+    let mut saved_values = [0; 7];
+    for (value, register) in saved_values.iter_mut().zip(saved_registers.iter()) {
+        *value = core.read_core_reg(probe, *register)?;
+    }
+
+    let mut saved_ram = vec![0; STUB_WORDS + BUFFER_WORDS];
+    probe.read_block32(scratch, &mut saved_ram)?;
+    let stub: Vec<u32> = STUB
+        .chunks(2)
+        .map(|pair| u32::from(pair[0]) | u32::from(pair[1]) << 16)
+        .collect();
+    probe.write_block32(scratch, &stub)?;
+
+    // A load which faults halts the core right away, instead of running the firmware's handler.
+    let demcr = Demcr(probe.read32(Demcr::ADDRESS)?);
+    let mut catching = demcr;
+    catching.set_vc_harderr(true);
+    probe.write32(Demcr::ADDRESS, catching.into())?;
+    core.write_core_reg(probe, SPECIAL_REGISTERS, saved_values[6] | PRIMASK)?;
+
+    let buffer = scratch + 4 * STUB_WORDS as u32;
+    let mut chunk_address = address;
+    for chunk in data.chunks_mut(BUFFER_WORDS) {
+        core.write_core_reg(probe, registers.R0, chunk_address)?;
+        core.write_core_reg(probe, registers.R1, chunk.len() as u32)?;
+        core.write_core_reg(probe, registers.R2, buffer)?;
+        core.write_core_reg(probe, registers.PC, scratch)?;
+        core.write_core_reg(probe, registers.XPSR, XPSR_THUMB)?;
+
+        core.run(probe)?;
+        core.wait_for_core_halted(probe)?;
+        if core.read_core_reg(probe, registers.PC)? != scratch + STUB_DONE {
+            probe.write32(Demcr::ADDRESS, demcr.into())?;
+            return Err(CoreAccessError::Fault(chunk_address));
+        }
+
+        // The stored words may still be in the D-cache of a Cortex-M7.
+        clean_data(probe, buffer, 4 * chunk.len())?;
+        probe.read_block32(buffer, chunk)?;
+        chunk_address += 4 * chunk.len() as u32;
+    }
+
+    // The stub overwrote the words of the scratch RAM, which are read from the saved ones.
+    patch_scratch(address, data, scratch, &saved_ram);
+
+    probe.write32(Demcr::ADDRESS, demcr.into())?;
+    // The BKPT would be taken as the reason of the next halt otherwise.
+    probe.write32(Dfsr::ADDRESS, DFSR_BKPT)?;
+    probe.write_block32(scratch, &saved_ram)?;
+    for (value, register) in saved_values.iter().zip(saved_registers.iter()) {
+        core.write_core_reg(probe, *register, *value)?;
+    }
+
+    if resume {
+        core.run(probe)?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the words of `data`, read from `address` on, which are in the scratch RAM with the
+/// ones saved from there.
+fn patch_scratch(address: u32, data: &mut [u32], scratch: u32, saved_ram: &[u32]) {
+    let scratch_range = scratch..scratch + 4 * saved_ram.len() as u32;
+
+    for (index, word) in data.iter_mut().enumerate() {
+        let word_address = address + 4 * index as u32;
+        if scratch_range.contains(&word_address) {
+            *word = saved_ram[((word_address - scratch) / 4) as usize];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::patch_scratch;
+
+    #[test]
+    fn reads_scratch_ram_from_saved_words() {
+        let mut data = [0xAAAA_AAAA; 4];
+        patch_scratch(0x2000_0000, &mut data, 0x2000_0008, &[1, 2, 3]);
+
+        assert_eq!(data, [0xAAAA_AAAA, 0xAAAA_AAAA, 1, 2]);
+    }
+}
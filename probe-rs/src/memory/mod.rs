@@ -1,5 +1,10 @@
 pub mod adi_v5_memory_interface;
+pub mod cache;
+pub mod cache_maintenance;
+pub mod console;
+pub mod core_access;
 pub mod romtable;
+pub mod watcher;
 
 use crate::coresight::access_ports::AccessPortError;
 
@@ -88,9 +93,25 @@ pub trait MI {
     /// Write a block of 8bit words at `addr`.
     ///
     /// The number of words written is `data.len()`.
-    /// The address where the write should be performed at has to be word aligned.
-    /// Returns `AccessPortError::MemoryNotAligned` if this does not hold true.
+    /// The address does not have to be aligned, the bytes around the block in the words at
+    /// either end are kept.
     fn write_block8(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessPortError>;
+
+    /// Write a block of 8bit words at `addr` like `write_block8`, and read it back.
+    ///
+    /// Returns `AccessPortError::VerifyError` with the address of the first byte which reads
+    /// back differently, e.g. because the memory is not RAM.
+    fn write_block8_verified(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessPortError> {
+        self.write_block8(addr, data)?;
+
+        let mut read_back = vec![0; data.len()];
+        self.read_block8(addr, &mut read_back)?;
+
+        match data.iter().zip(&read_back).position(|(a, b)| a != b) {
+            Some(index) => Err(AccessPortError::VerifyError(addr + index as u32)),
+            None => Ok(()),
+        }
+    }
 }
 
 impl<T> MI for &mut T
@@ -4,6 +4,7 @@ use crate::coresight::{
     ap_access::*,
 };
 use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
 use enum_primitive_derive::Primitive;
 use log::{debug, info, warn};
 use num_traits::cast::FromPrimitive;
@@ -11,6 +12,9 @@ use std::cell::RefCell;
 use std::error::Error;
 use std::fmt;
 
+/// The JEP106 continuation code and identity of ARM.
+const ARM_JEP106: (u8, u8) = (0x4, 0x3B);
+
 #[derive(Debug, PartialEq)]
 pub enum RomTableError {
     NotARomtable,
@@ -45,6 +49,30 @@ impl From<access_ports::AccessPortError> for RomTableError {
     }
 }
 
+/// Reads the address of the ROM table of a MEM-AP from its BASE registers.
+///
+/// Returns `None` if the access port reports that it has no debug entries.
+pub fn rom_table_address(
+    probe: &mut MasterProbe,
+    access_port: MemoryAP,
+) -> Result<Option<u64>, DebugProbeError> {
+    let base_register = probe.read_register_ap(access_port, BASE::default())?;
+
+    let mut baseaddr = match base_register.Format {
+        BaseaddrFormat::ADIv5 => {
+            if let DebugEntryState::NotPresent = base_register.P {
+                return Ok(None);
+            }
+            let base2 = probe.read_register_ap(access_port, BASE2::default())?;
+            u64::from(base2.BASEADDR) << 32
+        }
+        BaseaddrFormat::Legacy => 0,
+    };
+    baseaddr |= u64::from(base_register.BASEADDR << 12);
+
+    Ok(Some(baseaddr))
+}
+
 #[derive(Debug)]
 pub struct RomTableReader<'p, P: MI> {
     base_address: u64,
@@ -155,6 +183,11 @@ impl RomTable {
                 .collect::<Vec<_>>(),
         }
     }
+
+    /// Returns the components listed in the table.
+    pub fn components(&self) -> impl Iterator<Item = &CSComponent> {
+        self.entries.iter().map(|entry| &entry.component_data)
+    }
 }
 
 /// A ROM table entry with raw information parsed.
@@ -228,6 +261,13 @@ pub struct CSComponentId {
     pub peripheral_id: PeripheralID,
 }
 
+impl CSComponentId {
+    /// Returns the address of the component.
+    pub fn base_address(&self) -> u64 {
+        self.base_address
+    }
+}
+
 /// A reader to extract infromation from a CoreSight component table.
 pub struct ComponentInformationReader<'p, P: MI> {
     base_address: u64,
@@ -400,6 +440,19 @@ impl CSComponent {
         Ok(class)
     }
 
+    /// Returns the identification of the component, if it has one.
+    pub fn id(&self) -> Option<&CSComponentId> {
+        match self {
+            CSComponent::GenericVerificationComponent(id)
+            | CSComponent::Class1RomTable(id, _)
+            | CSComponent::Class9RomTable(id)
+            | CSComponent::PeripheralTestBlock(id)
+            | CSComponent::GenericIPComponent(id)
+            | CSComponent::CoreLinkOrPrimeCellOrSystemComponent(id) => Some(id),
+            CSComponent::None => None,
+        }
+    }
+
     pub fn iter(&self) -> CSComponentIter {
         CSComponentIter {
             component: Some(self),
@@ -458,4 +511,39 @@ impl PeripheralID {
             SIZE: 2u32.pow((data[4] >> 4) & 0x0F) as u8,
         }
     }
+
+    /// Returns the name of the component, if it is a known one designed by ARM.
+    pub fn arm_part_name(&self) -> Option<&'static str> {
+        match self.JEP106 {
+            Some(jep106) if jep106.cc == ARM_JEP106.0 && jep106.id == ARM_JEP106.1 => {}
+            _ => return None,
+        }
+
+        let name = match self.PART {
+            0x000 => "Cortex-M3 SCS",
+            0x001 => "ITM",
+            0x002 => "DWT",
+            0x003 => "FPB",
+            0x008 => "Cortex-M0 SCS",
+            0x00A => "Cortex-M0 DWT",
+            0x00B => "Cortex-M0 BPU",
+            0x00C => "Cortex-M4 SCS",
+            0x00E => "Cortex-M7 FPB",
+            0x471 => "Cortex-M0 ROM table",
+            0x4C0 => "Cortex-M0+ ROM table",
+            0x4C3 => "Cortex-M3 ROM table",
+            0x4C4 => "Cortex-M4 ROM table",
+            0x4C7 => "Cortex-M7 PPB ROM table",
+            0x923 => "Cortex-M3 TPIU",
+            0x924 => "Cortex-M3 ETM",
+            0x925 => "Cortex-M4 ETM",
+            0x932 => "MTB-M0+",
+            0x975 => "Cortex-M7 ETM",
+            0x9A1 => "Cortex-M4 TPIU",
+            0x9A9 => "Cortex-M7 TPIU",
+            _ => return None,
+        };
+
+        Some(name)
+    }
 }
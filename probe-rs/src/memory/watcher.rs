@@ -0,0 +1,160 @@
+//! Polling of target memory on a background thread.
+//!
+//! A [`MemoryWatcher`](struct.MemoryWatcher.html) reads a set of memory regions at a fixed
+//! frequency and reports every change through a channel, which is all a GUI or a terminal
+//! needs to display live variables.
+
+use super::MI;
+use crate::coresight::access_ports::AccessPortError;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A memory region to watch.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WatchEntry {
+    pub address: u32,
+    /// The size of the region in bytes.
+    pub size: u32,
+}
+
+/// A notification sent by a [`MemoryWatcher`](struct.MemoryWatcher.html).
+#[derive(Debug)]
+pub enum WatchEvent<E> {
+    /// The contents of the entry at `index` changed.
+    ///
+    /// Sent for every entry after it was read for the first time, and after it could be read
+    /// again after failing.
+    Changed { index: usize, data: Vec<u8> },
+    /// Reading the entry at `index` started to fail. The entry is read again on the next poll,
+    /// but this is only sent again after it could be read in between.
+    ReadFailed {
+        index: usize,
+        error: AccessPortError,
+    },
+    /// The probe could not be opened, the watcher stopped.
+    OpenFailed(E),
+}
+
+/// What the last poll of an entry returned.
+#[derive(PartialEq)]
+enum EntryState {
+    Unread,
+    Read(Vec<u8>),
+    Failed,
+}
+
+/// Polls a set of memory regions on a background thread.
+///
+/// Debug probes cannot be moved between threads, so the watcher takes a function which
+/// opens the probe on the polling thread. Polling stops when the watcher is dropped.
+pub struct MemoryWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MemoryWatcher {
+    /// Starts polling `entries` `frequency` times per second with the probe returned by `open`.
+    ///
+    /// Returns the watcher and the receiving end of the channel the changes are delivered to.
+    pub fn start<F, P, E>(
+        open: F,
+        entries: Vec<WatchEntry>,
+        frequency: u32,
+    ) -> (Self, Receiver<WatchEvent<E>>)
+    where
+        F: FnOnce() -> Result<P, E> + Send + 'static,
+        P: MI,
+        E: Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let interval = Duration::from_secs(1) / frequency.max(1);
+
+        let thread = {
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                let mut probe = match open() {
+                    Ok(probe) => probe,
+                    Err(e) => {
+                        let _ = sender.send(WatchEvent::OpenFailed(e));
+                        return;
+                    }
+                };
+
+                let mut states: Vec<EntryState> =
+                    entries.iter().map(|_| EntryState::Unread).collect();
+                let mut next_poll = Instant::now();
+
+                while !stop.load(Ordering::SeqCst) {
+                    for (index, entry) in entries.iter().enumerate() {
+                        let mut data = vec![0u8; entry.size as usize];
+
+                        let event = match probe.read_block8(entry.address, &mut data) {
+                            Ok(()) => {
+                                if let EntryState::Read(last) = &states[index] {
+                                    if *last == data {
+                                        continue;
+                                    }
+                                }
+                                states[index] = EntryState::Read(data.clone());
+                                WatchEvent::Changed { index, data }
+                            }
+                            Err(_) if states[index] == EntryState::Failed => continue,
+                            Err(error) => {
+                                states[index] = EntryState::Failed;
+                                WatchEvent::ReadFailed { index, error }
+                            }
+                        };
+
+                        // Nobody is listening anymore.
+                        if sender.send(event).is_err() {
+                            return;
+                        }
+                    }
+
+                    next_poll += interval;
+                    let now = Instant::now();
+                    if next_poll > now {
+                        thread::sleep(next_poll - now);
+                    } else {
+                        // Polling takes longer than the interval, don't try to catch up.
+                        next_poll = now;
+                    }
+                }
+            })
+        };
+
+        (
+            MemoryWatcher {
+                stop,
+                thread: Some(thread),
+            },
+            receiver,
+        )
+    }
+
+    /// Stops polling and waits for the polling thread to exit.
+    pub fn stop(mut self) {
+        self.join();
+    }
+
+    /// Signals the polling thread to stop and joins it, if it was not joined yet.
+    fn join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MemoryWatcher {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
@@ -0,0 +1,313 @@
+//! The client side of the GDB remote serial protocol, as far as it is needed to drive the GDB
+//! server built into the Black Magic Probe.
+//!
+//! Every packet is sent as `$data#cc`, where `cc` is the modulo 256 sum of the data bytes in
+//! hex, and acknowledged by the receiver with `+`, or with `-` to have it sent again.
+
+use crate::probe::debug_probe::DebugProbeError;
+
+use serialport::{SerialPort, SerialPortSettings};
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+/// How often a packet is sent again after the probe answered it with `-`.
+const MAX_RESENDS: usize = 3;
+
+#[derive(Debug)]
+pub enum GdbError {
+    Io(io::Error),
+    /// The probe did not answer in time.
+    Timeout,
+    /// The probe kept rejecting a packet, or sent one with a wrong checksum.
+    Checksum,
+    /// The probe answered a command with the given error code.
+    ErrorReply(u8),
+    /// The probe sent an answer which does not fit the command.
+    UnexpectedReply(String),
+}
+
+impl Error for GdbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GdbError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GdbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GdbError::Io(ref e) => write!(f, "Communication with the GDB server failed: {}", e),
+            GdbError::Timeout => write!(f, "The GDB server did not answer in time."),
+            GdbError::Checksum => write!(f, "A GDB packet was corrupted on the way."),
+            GdbError::ErrorReply(code) => write!(f, "The GDB server reported error {:02x}.", code),
+            GdbError::UnexpectedReply(reply) => {
+                write!(f, "Unexpected answer from the GDB server: {}", reply)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for GdbError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::TimedOut {
+            GdbError::Timeout
+        } else {
+            GdbError::Io(e)
+        }
+    }
+}
+
+impl From<GdbError> for DebugProbeError {
+    fn from(e: GdbError) -> Self {
+        match e {
+            GdbError::Io(_) | GdbError::Checksum => DebugProbeError::USBError,
+            GdbError::Timeout => DebugProbeError::Timeout,
+            GdbError::ErrorReply(_) | GdbError::UnexpectedReply(_) => DebugProbeError::UnknownError,
+        }
+    }
+}
+
+/// The serial port the GDB server is reached through, so the client can be tested without a
+/// probe.
+pub(crate) trait Transport: Read + Write {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+}
+
+impl Transport for Box<dyn SerialPort> {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        (**self).set_timeout(timeout).map_err(io::Error::from)
+    }
+}
+
+/// A connection to a GDB server on a serial port.
+pub struct GdbRemote {
+    port: Box<dyn Transport>,
+    /// Received bytes which were not processed yet.
+    buffer: Vec<u8>,
+}
+
+impl GdbRemote {
+    pub fn open(path: &str, timeout: Duration) -> Result<Self, GdbError> {
+        let settings = SerialPortSettings {
+            timeout,
+            ..Default::default()
+        };
+        let port =
+            serialport::open_with_settings(path, &settings).map_err(|e| GdbError::Io(e.into()))?;
+
+        Ok(Self::new(Box::new(port)))
+    }
+
+    pub(crate) fn new(port: Box<dyn Transport>) -> Self {
+        Self {
+            port,
+            buffer: vec![],
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), GdbError> {
+        self.port.set_timeout(timeout).map_err(GdbError::Io)
+    }
+
+    /// Sends `packet` and returns the answer of the server.
+    pub fn command(&mut self, packet: &str) -> Result<String, GdbError> {
+        self.send_packet(packet)?;
+        self.read_packet()
+    }
+
+    /// Sends `packet` and checks that the server answers it with `OK`.
+    pub fn command_ok(&mut self, packet: &str) -> Result<(), GdbError> {
+        let reply = self.command(packet)?;
+        check_reply(&reply)?;
+
+        if reply == "OK" {
+            Ok(())
+        } else {
+            Err(GdbError::UnexpectedReply(reply))
+        }
+    }
+
+    /// Runs the `monitor` command `command` of the server and returns its output.
+    pub fn monitor(&mut self, command: &str) -> Result<String, GdbError> {
+        self.send_packet(&format!("qRcmd,{}", encode_hex(command.as_bytes())))?;
+
+        // The output is sent in `O` packets until the command completes.
+        let mut output = String::new();
+        loop {
+            let reply = self.read_packet()?;
+            if reply == "OK" {
+                return Ok(output);
+            }
+            check_reply(&reply)?;
+
+            match decode_hex(reply.get(1..).unwrap_or_default()) {
+                Some(text) if reply.starts_with('O') => {
+                    output.push_str(&String::from_utf8_lossy(&text))
+                }
+                _ => return Err(GdbError::UnexpectedReply(reply)),
+            }
+        }
+    }
+
+    pub fn read_memory(&mut self, address: u32, data: &mut [u8]) -> Result<(), GdbError> {
+        let reply = self.command(&format!("m{:x},{:x}", address, data.len()))?;
+        check_reply(&reply)?;
+
+        match decode_hex(&reply) {
+            Some(bytes) if bytes.len() == data.len() => {
+                data.copy_from_slice(&bytes);
+                Ok(())
+            }
+            _ => Err(GdbError::UnexpectedReply(reply)),
+        }
+    }
+
+    pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), GdbError> {
+        self.command_ok(&format!(
+            "M{:x},{:x}:{}",
+            address,
+            data.len(),
+            encode_hex(data)
+        ))
+    }
+
+    fn send_packet(&mut self, packet: &str) -> Result<(), GdbError> {
+        let frame = frame(packet);
+
+        for _ in 0..MAX_RESENDS {
+            self.port.write_all(&frame)?;
+
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    // Leftovers of an earlier answer which was not waited for.
+                    _ => continue,
+                }
+            }
+        }
+
+        Err(GdbError::Checksum)
+    }
+
+    fn read_packet(&mut self) -> Result<String, GdbError> {
+        for _ in 0..MAX_RESENDS {
+            while self.read_byte()? != b'$' {}
+
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(checksum_of(&data));
+
+            if valid {
+                self.port.write_all(b"+")?;
+                return Ok(expand_run_lengths(&String::from_utf8_lossy(&data)));
+            }
+
+            self.port.write_all(b"-")?;
+        }
+
+        Err(GdbError::Checksum)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, GdbError> {
+        if self.buffer.is_empty() {
+            let mut chunk = [0; 256];
+            let count = self.port.read(&mut chunk)?;
+            if count == 0 {
+                return Err(GdbError::Timeout);
+            }
+            // Kept in reverse, so bytes are taken from the end.
+            self.buffer.extend(chunk[..count].iter().rev());
+        }
+
+        Ok(self.buffer.pop().unwrap())
+    }
+}
+
+/// Returns the error code of an `Exx` answer as an error.
+fn check_reply(reply: &str) -> Result<(), GdbError> {
+    if reply.len() != 3 || !reply.starts_with('E') {
+        return Ok(());
+    }
+
+    match u8::from_str_radix(&reply[1..], 16) {
+        Ok(code) => Err(GdbError::ErrorReply(code)),
+        Err(_) => Ok(()),
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn frame(packet: &str) -> Vec<u8> {
+    format!("${}#{:02x}", packet, checksum_of(packet.as_bytes())).into_bytes()
+}
+
+/// Expands the run length encoding of answers, where `x*n` stands for `x` repeated `n - 29`
+/// more times.
+fn expand_run_lengths(data: &str) -> String {
+    let mut expanded = String::with_capacity(data.len());
+    let mut chars = data.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '*' {
+            if let (Some(last), Some(count)) = (expanded.chars().last(), chars.next()) {
+                for _ in 0..(count as usize).saturating_sub(29) {
+                    expanded.push(last);
+                }
+            }
+        } else {
+            expanded.push(c);
+        }
+    }
+
+    expanded
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_reply, expand_run_lengths, frame, GdbError};
+
+    #[test]
+    fn frames_and_decodes_packets() {
+        assert_eq!(frame("m20000000,4"), b"$m20000000,4#4f".to_vec());
+        assert_eq!(frame("qRcmd,"), b"$qRcmd,#23".to_vec());
+
+        assert_eq!(expand_run_lengths("0* "), "0000");
+        assert_eq!(expand_run_lengths("OK"), "OK");
+
+        assert!(matches!(check_reply("E01"), Err(GdbError::ErrorReply(1))));
+        assert!(check_reply("ff00").is_ok());
+    }
+}
@@ -0,0 +1,437 @@
+//! The Black Magic Probe, driven through the GDB server built into its firmware.
+//!
+//! The probe does not give access to the debug port itself, only to the memory of the target
+//! through `m` and `M` packets. So the driver emulates a debug port with a single MEM-AP on top
+//! of them, which lets `MasterProbe` and the memory interfaces use it like any other probe.
+
+mod gdb;
+pub mod tools;
+
+use self::gdb::GdbRemote;
+use crate::{
+    coresight::{
+        access_ports::{
+            generic_ap::IDR,
+            memory_ap::{AddressIncrement, DataSize, BASE, BD0, BD3, CFG, CSW, DRW, TAR},
+        },
+        common::Register,
+        debug_port::{Abort, Ctrl, Select, DPIDR},
+    },
+    probe::{
+        cancellation::CancellationToken,
+        debug_probe::{DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, Port, SwjPins},
+        jtag::JtagAccess,
+        protocol::{AttachMethod, WireProtocol},
+        retry::RetryPolicy,
+    },
+    target::quirks::Quirk,
+};
+
+use log::{debug, info};
+
+/// The IDR of the emulated MEM-AP, that of the AHB-AP of Cortex-M3 and M4 cores.
+const EMULATED_AP_IDR: u32 = 0x2477_0011;
+/// The IDR of the emulated debug port, an ARM SW-DP.
+const EMULATED_DPIDR: u32 = 0x2BA0_1477;
+/// The ROM table of Cortex-M cores, flagged as present.
+const EMULATED_AP_BASE: u32 = 0xE00F_F003;
+/// CSYSPWRUPACK and CDBGPWRUPACK, the debug domain is always powered while attached.
+const EMULATED_CTRL_STAT: u32 = 0xA000_0000;
+
+/// The address of RDBUFF, which has no register type in `debug_port`.
+const RDBUFF: u8 = 0xC;
+
+/// Consecutive writes through DRW are sent in packets of up to this many bytes.
+const MAX_WRITE_SIZE: usize = 1024;
+
+/// The Debug Halting Control and Status Register of Cortex-M cores, and the value which clears
+/// C_HALT while keeping debugging enabled.
+const DHCSR: u32 = 0xE000_EDF0;
+const DHCSR_RESUME: u32 = 0xA05F_0001;
+
+pub struct BlackMagicProbe {
+    gdb: GdbRemote,
+    /// The emulated CSW and TAR registers of the MEM-AP.
+    csw: u32,
+    tar: u32,
+    /// The last value read, returned by RDBUFF.
+    last_read: u32,
+    /// Writes through DRW which were not sent yet, starting at `pending_address`.
+    pending_address: u32,
+    pending: Vec<u8>,
+}
+
+impl BlackMagicProbe {
+    /// Sends the writes which were collected so far.
+    fn flush(&mut self) -> Result<(), DebugProbeError> {
+        if !self.pending.is_empty() {
+            let data = std::mem::replace(&mut self.pending, vec![]);
+            self.gdb.write_memory(self.pending_address, &data)?;
+        }
+
+        Ok(())
+    }
+
+    /// The number of bytes of a DRW access with the current CSW.
+    fn access_size(&self) -> Result<u32, DebugProbeError> {
+        match CSW::from(self.csw).SIZE {
+            DataSize::U8 => Ok(1),
+            DataSize::U16 => Ok(2),
+            DataSize::U32 => Ok(4),
+            _ => Err(DebugProbeError::DataAlignmentError),
+        }
+    }
+
+    fn increment_tar(&mut self, size: u32) {
+        if !matches!(CSW::from(self.csw).AddrInc, AddressIncrement::Off) {
+            self.tar = self.tar.wrapping_add(size);
+        }
+    }
+
+    fn read_drw(&mut self) -> Result<u32, DebugProbeError> {
+        let size = self.access_size()?;
+        let mut data = [0; 4];
+        self.gdb.read_memory(self.tar, &mut data[..size as usize])?;
+
+        // The data is placed in the byte lanes of its address, like a real MEM-AP does.
+        let value = u32::from_le_bytes(data) << ((self.tar & 3 & !(size - 1)) * 8);
+        self.increment_tar(size);
+
+        Ok(value)
+    }
+
+    fn write_drw(&mut self, value: u32) -> Result<(), DebugProbeError> {
+        let size = self.access_size()?;
+        let shift = (self.tar & 3 & !(size - 1)) * 8;
+        let bytes = (value >> shift).to_le_bytes();
+
+        let continues = self.pending_address.wrapping_add(self.pending.len() as u32) == self.tar;
+        if !continues || self.pending.len() + size as usize > MAX_WRITE_SIZE {
+            self.flush()?;
+            self.pending_address = self.tar;
+        }
+        self.pending.extend_from_slice(&bytes[..size as usize]);
+        self.increment_tar(size);
+
+        Ok(())
+    }
+
+    /// The address of the banked data register `addr`, one of BD0 to BD3.
+    fn banked_address(&self, addr: u16) -> u32 {
+        (self.tar & !0xF) + u32::from(addr - u16::from(BD0::ADDRESS))
+    }
+}
+
+impl DebugProbe for BlackMagicProbe {
+    fn new_from_probe_info(info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        let path = tools::find_gdb_port(info).ok_or(DebugProbeError::ProbeCouldNotBeCreated)?;
+        info!("Using the GDB server on {}", path);
+
+        let gdb = GdbRemote::open(&path, RetryPolicy::default().usb_timeout)
+            .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?;
+
+        Ok(Box::new(Self {
+            gdb,
+            csw: 0,
+            tar: 0,
+            last_read: 0,
+            pending_address: 0,
+            pending: vec![],
+        }))
+    }
+
+    fn get_name(&self) -> &str {
+        "Black Magic Probe"
+    }
+
+    /// Scans for targets and attaches to the first one.
+    ///
+    /// The GDB server halts the core when attaching, so with `AttachMethod::NoReset` it is
+    /// resumed right away.
+    fn attach(
+        &mut self,
+        protocol: Option<WireProtocol>,
+        method: AttachMethod,
+    ) -> Result<WireProtocol, DebugProbeError> {
+        let protocol = protocol.unwrap_or(WireProtocol::Swd);
+
+        let scan = match protocol {
+            WireProtocol::Swd => "swdp_scan",
+            WireProtocol::Jtag => "jtag_scan",
+        };
+        let output = self.gdb.monitor(scan)?;
+        debug!("Scan result: {}", output.trim());
+
+        let reply = self.gdb.command("vAttach;1")?;
+        if !reply.starts_with('T') && !reply.starts_with('S') {
+            info!("Attaching failed, no target found: {}", output.trim());
+            return Err(DebugProbeError::TargetPowerUpFailed);
+        }
+
+        match method {
+            AttachMethod::Reset => self.target_reset()?,
+            AttachMethod::NoReset => self.gdb.write_memory(DHCSR, &DHCSR_RESUME.to_le_bytes())?,
+        }
+
+        Ok(protocol)
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.flush()?;
+        self.gdb.command_ok("D")?;
+        Ok(())
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.flush()?;
+        self.gdb.monitor("hard_srst")?;
+        Ok(())
+    }
+
+    /// The GDB server only attaches to targets found by scanning.
+    fn set_multidrop_target(&mut self, target_sel: Option<u32>) -> Result<(), DebugProbeError> {
+        match target_sel {
+            Some(_) => Err(DebugProbeError::CommandNotSupportedByProbe),
+            None => Ok(()),
+        }
+    }
+
+    /// The GDB server initializes the debug port itself.
+    fn set_quirks(&mut self, _quirks: &[Quirk]) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        self.gdb.set_timeout(policy.usb_timeout)?;
+        Ok(())
+    }
+
+    /// The serial port times out after the `usb_timeout` of the retry policy.
+    fn set_cancellation_token(&mut self, _token: CancellationToken) {}
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.gdb
+            .monitor(&format!("frequency {}", speed_khz * 1000))
+            .map_err(|_| DebugProbeError::CommandNotSupportedByProbe)?;
+        Ok(speed_khz)
+    }
+
+    /// The target voltage is only reported as text by the scan, so it is not measured.
+    fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError> {
+        Ok(None)
+    }
+
+    /// The probe sends SWO data on a USB endpoint of its own, not through the GDB server.
+    fn enable_swo(&mut self, _baud_rate: u32) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn disable_swo(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn swj_pins(
+        &mut self,
+        _output: SwjPins,
+        _select: SwjPins,
+        _wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn jtag_access(&mut self) -> Option<&mut dyn JtagAccess> {
+        None
+    }
+
+    fn read_block16(&mut self, _address: u32, _data: &mut [u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+
+    fn write_block16(&mut self, _address: u32, _data: &[u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+}
+
+impl DAPAccess for BlackMagicProbe {
+    /// Reads the emulated DAP register on the specified port and address
+    fn read_register(&mut self, port: Port, addr: u16) -> Result<u32, DebugProbeError> {
+        self.flush()?;
+
+        let value = match port {
+            Port::DebugPort => match addr as u8 {
+                DPIDR::ADDRESS => EMULATED_DPIDR,
+                Ctrl::ADDRESS => EMULATED_CTRL_STAT,
+                RDBUFF => self.last_read,
+                _ => 0,
+            },
+            // No other AP exists, its IDR reads as zero.
+            Port::AccessPort(ap) if ap != 0 => 0,
+            Port::AccessPort(_) => match addr as u8 {
+                CSW::ADDRESS => self.csw,
+                TAR::ADDRESS => self.tar,
+                DRW::ADDRESS => self.read_drw()?,
+                BD0::ADDRESS..=BD3::ADDRESS => {
+                    let mut data = [0; 4];
+                    self.gdb.read_memory(self.banked_address(addr), &mut data)?;
+                    u32::from_le_bytes(data)
+                }
+                CFG::ADDRESS => 0,
+                BASE::ADDRESS => EMULATED_AP_BASE,
+                IDR::ADDRESS => EMULATED_AP_IDR,
+                _ => return Err(DebugProbeError::UnknownError),
+            },
+        };
+
+        self.last_read = value;
+        Ok(value)
+    }
+
+    /// Writes the emulated DAP register on the specified port and address
+    fn write_register(&mut self, port: Port, addr: u16, value: u32) -> Result<(), DebugProbeError> {
+        match port {
+            // ABORT, CTRL/STAT and SELECT have nothing to control.
+            Port::DebugPort => match addr as u8 {
+                Abort::ADDRESS | Ctrl::ADDRESS | Select::ADDRESS => Ok(()),
+                _ => Err(DebugProbeError::UnknownError),
+            },
+            Port::AccessPort(ap) if ap != 0 => Err(DebugProbeError::UnknownError),
+            Port::AccessPort(_) => match addr as u8 {
+                DRW::ADDRESS => self.write_drw(value),
+                CSW::ADDRESS => {
+                    self.flush()?;
+                    self.csw = value;
+                    Ok(())
+                }
+                TAR::ADDRESS => {
+                    self.tar = value;
+                    Ok(())
+                }
+                BD0::ADDRESS..=BD3::ADDRESS => {
+                    self.flush()?;
+                    self.gdb
+                        .write_memory(self.banked_address(addr), &value.to_le_bytes())?;
+                    Ok(())
+                }
+                _ => Err(DebugProbeError::UnknownError),
+            },
+        }
+    }
+}
+
+impl Drop for BlackMagicProbe {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::warn!("Failed to write the remaining data to the target: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        gdb::{GdbRemote, Transport},
+        BlackMagicProbe,
+    };
+    use crate::probe::{
+        debug_probe::DebugProbe,
+        protocol::{AttachMethod, WireProtocol},
+    };
+
+    use std::cell::RefCell;
+    use std::io::{self, Read, Write};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// Answers the packets like the GDB server of the probe does when a target is found, and
+    /// records them.
+    struct FakeServer {
+        packets: Rc<RefCell<Vec<String>>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for FakeServer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let count = buf.len().min(self.output.len());
+            buf[..count].copy_from_slice(&self.output[..count]);
+            self.output.drain(..count);
+
+            Ok(count)
+        }
+    }
+
+    impl Write for FakeServer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let data = String::from_utf8_lossy(buf);
+
+            // Acknowledgements of the answers are not recorded.
+            if let (Some(start), Some(end)) = (data.find('$'), data.find('#')) {
+                let packet = data[start + 1..end].to_string();
+                let reply = if packet.starts_with("vAttach") {
+                    "T05"
+                } else {
+                    "OK"
+                };
+                let checksum = reply.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+
+                self.output
+                    .extend(format!("+${}#{:02x}", reply, checksum).into_bytes());
+                self.packets.borrow_mut().push(packet);
+            }
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for FakeServer {
+        fn set_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn attach(method: AttachMethod) -> Vec<String> {
+        let packets = Rc::new(RefCell::new(vec![]));
+        let mut probe = BlackMagicProbe {
+            gdb: GdbRemote::new(Box::new(FakeServer {
+                packets: packets.clone(),
+                output: vec![],
+            })),
+            csw: 0,
+            tar: 0,
+            last_read: 0,
+            pending_address: 0,
+            pending: vec![],
+        };
+
+        probe.attach(Some(WireProtocol::Swd), method).unwrap();
+
+        let recorded = packets.borrow();
+        recorded.clone()
+    }
+
+    #[test]
+    fn resumes_the_core_without_reset() {
+        // `monitor hard_srst`, hex encoded.
+        let reset = "qRcmd,686172645f73727374";
+
+        let packets = attach(AttachMethod::NoReset);
+        assert!(!packets.iter().any(|packet| packet == reset));
+        assert!(packets
+            .iter()
+            .any(|packet| packet.starts_with("Me000edf0,4:")));
+
+        let packets = attach(AttachMethod::Reset);
+        assert!(packets.iter().any(|packet| packet == reset));
+        assert!(!packets.iter().any(|packet| packet.starts_with("Me000edf0")));
+    }
+}
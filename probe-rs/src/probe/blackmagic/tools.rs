@@ -0,0 +1,59 @@
+use crate::probe::debug_probe::{DebugProbeInfo, DebugProbeType};
+
+use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+
+/// The USB vendor and product ID of the Black Magic Probe in its application firmware.
+pub const BLACKMAGIC_VID: u16 = 0x1d50;
+pub const BLACKMAGIC_PID: u16 = 0x6018;
+
+pub fn list_blackmagic_devices() -> Vec<DebugProbeInfo> {
+    let mut probes: Vec<DebugProbeInfo> = vec![];
+
+    // Each probe has two serial ports, the GDB server and the UART of the target.
+    for (_, usb) in blackmagic_ports() {
+        if probes
+            .iter()
+            .any(|probe| probe.serial_number == usb.serial_number)
+        {
+            continue;
+        }
+
+        probes.push(DebugProbeInfo::new(
+            usb.product
+                .unwrap_or_else(|| "Black Magic Probe".to_owned()),
+            usb.vid,
+            usb.pid,
+            usb.serial_number,
+            DebugProbeType::BlackMagic,
+            None,
+        ));
+    }
+
+    probes
+}
+
+/// Returns the path of the serial port of the GDB server of the probe described by `info`.
+pub(crate) fn find_gdb_port(info: &DebugProbeInfo) -> Option<String> {
+    // The GDB server is the first interface, so its port is listed with the lower number.
+    blackmagic_ports()
+        .into_iter()
+        .filter(|(_, usb)| info.serial_number.is_none() || usb.serial_number == info.serial_number)
+        .map(|(port, _)| port.port_name)
+        .min()
+}
+
+fn blackmagic_ports() -> Vec<(SerialPortInfo, UsbPortInfo)> {
+    let ports = serialport::available_ports().unwrap_or_default();
+
+    ports
+        .into_iter()
+        .filter_map(|port| match port.port_type.clone() {
+            SerialPortType::UsbPort(usb)
+                if usb.vid == BLACKMAGIC_VID && usb.pid == BLACKMAGIC_PID =>
+            {
+                Some((port, usb))
+            }
+            _ => None,
+        })
+        .collect()
+}
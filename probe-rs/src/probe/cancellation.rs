@@ -0,0 +1,105 @@
+//! Aborting blocking operations, like waiting for the core to halt or for a flash algorithm to
+//! finish, which would hang forever on a misbehaving target.
+//!
+//! The `MasterProbe` holds a `CancellationToken` which all its waits check, and passes it to
+//! the probe driver, whose reads stop waiting for a probe which does not answer. A clone of it
+//! can be cancelled from another thread, e.g. by a Ctrl-C handler, and a token with a deadline
+//! cancels itself. Cancelled waits fail with `DebugProbeError::Timeout`.
+//!
+//! A probe which answers is still used after its token was cancelled, so the target can be
+//! detached once the aborted operation returned.
+
+use super::debug_probe::DebugProbeError;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Cancels the waits of a probe, for all of its clones.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<State>,
+}
+
+/// The state shared by the clones of a token.
+#[derive(Debug, Default)]
+struct State {
+    cancelled: AtomicBool,
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl CancellationToken {
+    /// Creates a token which is only cancelled by `cancel`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a token which cancels itself after `timeout`, or earlier by `cancel`.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        CancellationToken {
+            state: Arc::new(State {
+                cancelled: AtomicBool::new(false),
+                deadline: Mutex::new(Some(Instant::now() + timeout)),
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Takes back `cancel` and removes the deadline, so the probe can be used again after an
+    /// aborted operation. Returns whether the token was cancelled.
+    pub fn reset(&self) -> bool {
+        let deadline = self.deadline().take();
+        let cancelled = self.state.cancelled.swap(false, Ordering::SeqCst);
+
+        cancelled || deadline.map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+            || self
+                .deadline()
+                .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    /// Returns `DebugProbeError::Timeout` if the token was cancelled or its deadline passed.
+    pub fn check(&self) -> Result<(), DebugProbeError> {
+        if self.is_cancelled() {
+            Err(DebugProbeError::Timeout)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn deadline(&self) -> MutexGuard<Option<Instant>> {
+        // The lock is only held to copy or take the deadline, so it is never poisoned.
+        self.state.deadline.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CancellationToken;
+
+    use std::time::Duration;
+
+    #[test]
+    fn cancels_all_clones() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(clone.check().is_ok());
+
+        token.cancel();
+        assert!(clone.check().is_err());
+
+        assert!(clone.reset());
+        assert!(!token.is_cancelled());
+
+        let token = CancellationToken::with_timeout(Duration::from_secs(0));
+        assert!(token.clone().is_cancelled());
+        assert!(token.reset());
+        assert!(token.check().is_ok());
+    }
+}
@@ -84,7 +84,7 @@ impl Response for TargetDeviceName {
 pub struct Capabilities {
     swd_implemented: bool,
     jtag_implemented: bool,
-    swo_uart_implemented: bool,
+    pub(crate) swo_uart_implemented: bool,
     swo_manchester_implemented: bool,
     atomic_commands_implemented: bool,
     test_domain_timer_implemented: bool,
@@ -95,15 +95,16 @@ impl Response for Capabilities {
     fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
         // This response can contain two info bytes.
         // In the docs only the first byte is described, so for now we always will only parse that specific byte.
-        if buffer[offset + 1] > 0 {
+        // The length of the info is at `offset`, followed by the info.
+        if buffer[offset] > 0 {
             Ok(Capabilities {
-                swd_implemented: buffer[offset + 2] & 0x01 > 0,
-                jtag_implemented: buffer[offset + 2] & 0x02 > 0,
-                swo_uart_implemented: buffer[offset + 2] & 0x04 > 0,
-                swo_manchester_implemented: buffer[offset + 2] & 0x08 > 0,
-                atomic_commands_implemented: buffer[offset + 2] & 0x10 > 0,
-                test_domain_timer_implemented: buffer[offset + 2] & 0x20 > 0,
-                swo_streaming_trace_implemented: buffer[offset + 2] & 0x40 > 0,
+                swd_implemented: buffer[offset + 1] & 0x01 > 0,
+                jtag_implemented: buffer[offset + 1] & 0x02 > 0,
+                swo_uart_implemented: buffer[offset + 1] & 0x04 > 0,
+                swo_manchester_implemented: buffer[offset + 1] & 0x08 > 0,
+                atomic_commands_implemented: buffer[offset + 1] & 0x10 > 0,
+                test_domain_timer_implemented: buffer[offset + 1] & 0x20 > 0,
+                swo_streaming_trace_implemented: buffer[offset + 1] & 0x40 > 0,
             })
         } else {
             Err(Error::UnexpectedAnswer)
@@ -115,9 +116,9 @@ pub struct TestDomainTime(u32);
 
 impl Response for TestDomainTime {
     fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
-        if buffer[offset + 1] == 0x08 {
+        if buffer[offset] == 0x08 {
             let res = buffer
-                .pread::<u32>(offset + 2)
+                .pread::<u32>(offset + 1)
                 .expect("This is a bug. Please report it.");
             Ok(TestDomainTime(res))
         } else {
@@ -130,9 +131,9 @@ pub struct SWOTraceBufferSize(u32);
 
 impl Response for SWOTraceBufferSize {
     fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
-        if buffer[offset + 1] == 0x04 {
+        if buffer[offset] == 0x04 {
             let res = buffer
-                .pread::<u32>(offset + 2)
+                .pread::<u32>(offset + 1)
                 .expect("This is a bug. Please report it.");
             Ok(SWOTraceBufferSize(res))
         } else {
@@ -145,9 +146,9 @@ pub struct PacketCount(u8);
 
 impl Response for PacketCount {
     fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
-        if buffer[offset + 1] == 0x01 {
+        if buffer[offset] == 0x01 {
             let res = buffer
-                .pread::<u8>(offset + 2)
+                .pread::<u8>(offset + 1)
                 .expect("This is a bug. Please report it.");
             Ok(PacketCount(res))
         } else {
@@ -160,9 +161,9 @@ pub struct PacketSize(u16);
 
 impl Response for PacketSize {
     fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
-        if buffer[offset + 1] == 0x02 {
+        if buffer[offset] == 0x02 {
             let res = buffer
-                .pread::<u16>(offset + 2)
+                .pread::<u16>(offset + 1)
                 .expect("This is a bug. Please report it.");
             Ok(PacketSize(res))
         } else {
@@ -0,0 +1,76 @@
+/// Implementation of the DAP_JTAG_Sequence command
+///
+use super::super::{Category, Error, Request, Response, Result, Status};
+use crate::probe::jtag::JtagSequence;
+
+/// The space for the sequences in a request, and for the captured data in a response.
+pub(crate) const MAX_DATA_SIZE: usize = 21;
+
+/// The number of bytes holding the bits of `sequence`.
+pub(crate) fn byte_count(sequence: &JtagSequence) -> usize {
+    (usize::from(sequence.bit_count) + 7) / 8
+}
+
+pub struct SequenceRequest {
+    sequences: Vec<JtagSequence>,
+}
+
+impl SequenceRequest {
+    pub(crate) fn new(sequences: Vec<JtagSequence>) -> Result<SequenceRequest> {
+        // The sequence info byte can only encode 1 to 64 clock cycles.
+        if sequences
+            .iter()
+            .any(|s| s.bit_count == 0 || s.bit_count > 64)
+        {
+            return Err(Error::TooMuchData);
+        }
+
+        Ok(SequenceRequest { sequences })
+    }
+}
+
+impl Request for SequenceRequest {
+    const CATEGORY: Category = Category(0x14);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        let mut position = offset;
+
+        buffer[position] = self.sequences.len() as u8;
+        position += 1;
+
+        for sequence in &self.sequences {
+            // A bit count of 64 is encoded as zero.
+            let mut info = sequence.bit_count & 0x3F;
+            if sequence.tms {
+                info |= 0x40;
+            }
+            if sequence.capture {
+                info |= 0x80;
+            }
+
+            let byte_count = byte_count(sequence);
+            if position + 1 + byte_count > buffer.len() {
+                return Err(Error::NotEnoughSpace);
+            }
+
+            buffer[position] = info;
+            buffer[position + 1..position + 1 + byte_count]
+                .copy_from_slice(&sequence.tdi.to_le_bytes()[..byte_count]);
+            position += 1 + byte_count;
+        }
+
+        Ok(position - offset)
+    }
+}
+
+/// The status of the command and the data captured on TDO, in the order of the sequences.
+pub struct SequenceResponse(pub(crate) Status, pub(crate) Vec<u8>);
+
+impl Response for SequenceResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        Ok(SequenceResponse(
+            Status::from_byte(buffer[offset])?,
+            buffer[offset + 1..].to_vec(),
+        ))
+    }
+}
@@ -1,14 +1,20 @@
 pub mod general;
+pub mod jtag;
 pub mod swd;
 pub mod swj;
+pub mod swo;
 pub mod transfer;
 
+use super::usb_interface::DAPLinkUsb;
 use crate::probe::debug_probe::DebugProbeError;
 use core::ops::Deref;
 use log::debug;
 
 pub(crate) type Result<T> = std::result::Result<T, Error>;
 
+/// The size of the packets of full speed probes, which all probes handle.
+pub(crate) const PACKET_SIZE: usize = 64;
+
 #[derive(Debug)]
 pub(crate) enum Status {
     DAPOk = 0x00,
@@ -47,14 +53,14 @@ pub(crate) trait Response: Sized {
 
 #[derive(Clone, Debug)]
 pub(crate) enum Error {
-    #[allow(dead_code)]
     NotEnoughSpace,
-    #[allow(dead_code)]
     USB,
     UnexpectedAnswer,
     DAP,
     TooMuchData,
     HidApi,
+    /// The probe did not answer before the cancellation token was cancelled.
+    Cancelled,
 }
 
 impl From<Error> for DebugProbeError {
@@ -66,6 +72,7 @@ impl From<Error> for DebugProbeError {
             Error::DAP => DebugProbeError::UnknownError,
             Error::TooMuchData => DebugProbeError::UnknownError,
             Error::HidApi => DebugProbeError::USBError,
+            Error::Cancelled => DebugProbeError::Timeout,
         }
     }
 }
@@ -76,13 +83,20 @@ impl From<hidapi::HidError> for Error {
     }
 }
 
+impl From<rusb::Error> for Error {
+    fn from(_error: rusb::Error) -> Self {
+        Error::USB
+    }
+}
+
 pub(crate) fn send_command<Req: Request, Res: Response>(
-    device: &hidapi::HidDevice,
+    device: &dyn DAPLinkUsb,
     request: Req,
 ) -> Result<Res> {
     // Write the command & request to the buffer.
     // TODO: Error handling & real USB writing.
-    let buffer = &mut [0; 24];
+    // The first byte is the HID report ID.
+    let buffer = &mut [0; PACKET_SIZE + 1];
     buffer[1] = *Req::CATEGORY;
     let _size = request.to_bytes(buffer, 1 + 1)?;
     device.write(buffer)?;
@@ -90,7 +104,7 @@ pub(crate) fn send_command<Req: Request, Res: Response>(
 
     // Read back resonse.
     // TODO: Error handling & real USB reading.
-    let buffer = &mut [0; 24];
+    let buffer = &mut [0; PACKET_SIZE];
     device.read(buffer)?;
     debug!("Receive buffer: {:02X?}", &buffer[..]);
     if buffer[0] == *Req::CATEGORY {
@@ -1 +1,2 @@
 pub mod configure;
+pub mod sequence;
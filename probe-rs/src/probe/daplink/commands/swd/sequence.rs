@@ -0,0 +1,140 @@
+/// Implementation of the DAP_SWD_Sequence command
+///
+use super::super::{Category, Error, Request, Response, Result, Status};
+
+/// A single sequence of a DAP_SWD_Sequence command.
+#[derive(Clone, Copy)]
+pub enum Sequence {
+    /// Drives the given bits (LSB first) on SWDIO.
+    Output { bit_count: u8, data: u64 },
+    /// Samples SWDIO for the given number of clock cycles.
+    Input { bit_count: u8 },
+}
+
+impl Sequence {
+    fn bit_count(&self) -> u8 {
+        match self {
+            Sequence::Output { bit_count, .. } | Sequence::Input { bit_count } => *bit_count,
+        }
+    }
+
+    fn byte_count(&self) -> usize {
+        (usize::from(self.bit_count()) + 7) / 8
+    }
+}
+
+pub struct SequenceRequest {
+    sequences: Vec<Sequence>,
+}
+
+impl SequenceRequest {
+    pub(crate) fn new(sequences: Vec<Sequence>) -> Result<SequenceRequest> {
+        // The sequence info byte can only encode 1 to 64 clock cycles.
+        if sequences
+            .iter()
+            .any(|s| s.bit_count() == 0 || s.bit_count() > 64)
+        {
+            return Err(Error::TooMuchData);
+        }
+
+        Ok(SequenceRequest { sequences })
+    }
+
+    /// Creates the sequences writing `target_sel` to the TARGETSEL register, to select a
+    /// target on a multi-drop SWD bus.
+    ///
+    /// No target drives the ACK phase of this write, so it can not be a regular transfer.
+    pub(crate) fn target_sel(target_sel: u32) -> Result<SequenceRequest> {
+        let parity = u64::from(target_sel.count_ones() % 2);
+
+        Self::new(vec![
+            // Packet request for a DP write to address 0xC.
+            Sequence::Output {
+                bit_count: 8,
+                data: 0x99,
+            },
+            // Turnaround, ACK and turnaround.
+            Sequence::Input { bit_count: 5 },
+            Sequence::Output {
+                bit_count: 33,
+                data: u64::from(target_sel) | (parity << 32),
+            },
+        ])
+    }
+}
+
+impl Request for SequenceRequest {
+    const CATEGORY: Category = Category(0x1D);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        let mut position = offset;
+
+        buffer[position] = self.sequences.len() as u8;
+        position += 1;
+
+        for sequence in &self.sequences {
+            // A bit count of 64 is encoded as zero.
+            let mut info = sequence.bit_count() & 0x3F;
+
+            if let Sequence::Input { .. } = sequence {
+                info |= 0x80;
+            }
+
+            buffer[position] = info;
+            position += 1;
+
+            if let Sequence::Output { data, .. } = sequence {
+                let byte_count = sequence.byte_count();
+
+                if position + byte_count > buffer.len() {
+                    return Err(Error::NotEnoughSpace);
+                }
+
+                buffer[position..position + byte_count]
+                    .copy_from_slice(&data.to_le_bytes()[..byte_count]);
+                position += byte_count;
+            }
+        }
+
+        Ok(position - offset)
+    }
+}
+
+/// The status of the command. The data sampled by input sequences is discarded.
+pub struct SequenceResponse(pub(crate) Status);
+
+impl Response for SequenceResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        Ok(SequenceResponse(Status::from_byte(buffer[offset])?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Sequence, SequenceRequest};
+    use crate::probe::daplink::commands::Request;
+
+    #[test]
+    fn encodes_the_target_sel_write() {
+        let mut buffer = [0; 16];
+
+        // The first core of the RP2040, with an even number of bits set.
+        let request = SequenceRequest::target_sel(0x0100_2927).unwrap();
+        let length = request.to_bytes(&mut buffer, 0).unwrap();
+        assert_eq!(
+            &buffer[..length],
+            &[0x03, 0x08, 0x99, 0x85, 0x21, 0x27, 0x29, 0x00, 0x01, 0x00]
+        );
+
+        // The rescue debug port, with an odd number of bits set.
+        let request = SequenceRequest::target_sel(0xF000_2927).unwrap();
+        let length = request.to_bytes(&mut buffer, 0).unwrap();
+        assert_eq!(&buffer[5..length], &[0x27, 0x29, 0x00, 0xF0, 0x01]);
+    }
+
+    #[test]
+    fn rejects_empty_sequences() {
+        assert!(SequenceRequest::new(vec![Sequence::Input { bit_count: 0 }]).is_err());
+        assert!(SequenceRequest::new(vec![Sequence::Input { bit_count: 65 }]).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+use super::super::{Category, Request, Response, Result};
+
+#[derive(Debug)]
+pub struct PinsRequest {
+    /// The levels to drive the selected pins to.
+    pub(crate) output: u8,
+    /// The pins to drive.
+    pub(crate) select: u8,
+    /// The time to wait for the selected pins to settle, in microseconds.
+    pub(crate) wait_us: u32,
+}
+
+impl Request for PinsRequest {
+    const CATEGORY: Category = Category(0x10);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        use scroll::Pwrite;
+
+        buffer[offset] = self.output;
+        buffer[offset + 1] = self.select;
+        buffer
+            .pwrite(self.wait_us, offset + 2)
+            .expect("This is a bug. Please report it.");
+        Ok(6)
+    }
+}
+
+/// The levels of all pins after the selected ones were driven.
+#[derive(Debug)]
+pub(crate) struct PinsResponse(pub(crate) u8);
+
+impl Response for PinsResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        Ok(PinsResponse(buffer[offset]))
+    }
+}
@@ -0,0 +1,33 @@
+use super::super::{Category, Request, Response, Result};
+
+use scroll::Pread;
+
+#[derive(Debug)]
+pub struct BaudrateRequest(pub(crate) u32);
+
+impl Request for BaudrateRequest {
+    const CATEGORY: Category = Category(0x19);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        use scroll::Pwrite;
+
+        buffer
+            .pwrite(self.0, offset)
+            .expect("This is a bug. Please report it.");
+        Ok(4)
+    }
+}
+
+/// The baud rate the probe captures at, which may differ from the requested one, or 0 if
+/// the probe cannot capture at the requested one.
+#[derive(Debug)]
+pub(crate) struct BaudrateResponse(pub(crate) u32);
+
+impl Response for BaudrateResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        let baud_rate = buffer
+            .pread::<u32>(offset)
+            .expect("This is a bug. Please report it.");
+        Ok(BaudrateResponse(baud_rate))
+    }
+}
@@ -0,0 +1,25 @@
+use super::super::{Category, Request, Response, Result, Status};
+
+#[derive(Debug, Copy, Clone)]
+pub enum ControlRequest {
+    Stop = 0x00,
+    Start = 0x01,
+}
+
+impl Request for ControlRequest {
+    const CATEGORY: Category = Category(0x1A);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        buffer[offset] = *self as u8;
+        Ok(1)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ControlResponse(pub(crate) Status);
+
+impl Response for ControlResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        Ok(ControlResponse(Status::from_byte(buffer[offset])?))
+    }
+}
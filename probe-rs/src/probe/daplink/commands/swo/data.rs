@@ -0,0 +1,69 @@
+use super::super::{Category, Error, Request, Response, Result};
+
+use scroll::{Pread, Pwrite};
+
+/// Reads up to `max_count` bytes of the captured SWO data.
+#[derive(Debug)]
+pub struct DataRequest {
+    pub(crate) max_count: u16,
+}
+
+impl Request for DataRequest {
+    const CATEGORY: Category = Category(0x1C);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        buffer
+            .pwrite(self.max_count, offset)
+            .expect("This is a bug. Please report it.");
+        Ok(2)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct DataResponse {
+    /// Whether the probe is capturing.
+    pub(crate) active: bool,
+    /// Whether the capture buffer of the probe overflowed, so data was lost.
+    pub(crate) overrun: bool,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Response for DataResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        let status = buffer[offset];
+        let count = buffer
+            .pread::<u16>(offset + 1)
+            .expect("This is a bug. Please report it.") as usize;
+
+        let start = offset + 3;
+        let data = buffer
+            .get(start..start + count)
+            .ok_or(Error::UnexpectedAnswer)?;
+
+        Ok(DataResponse {
+            active: status & 0x01 != 0,
+            overrun: status & 0x80 != 0,
+            data: data.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DataResponse;
+    use crate::probe::daplink::commands::Response;
+
+    #[test]
+    fn parses_the_captured_data() {
+        let buffer = [0x1C, 0x81, 0x03, 0x00, 0x01, 0x02, 0x03, 0x00];
+
+        let response = DataResponse::from_bytes(&buffer, 1).unwrap();
+        assert!(response.active);
+        assert!(response.overrun);
+        assert_eq!(response.data, vec![0x01, 0x02, 0x03]);
+
+        // More bytes than the packet holds.
+        let buffer = [0x1C, 0x01, 0x10, 0x00, 0x01];
+        assert!(DataResponse::from_bytes(&buffer, 1).is_err());
+    }
+}
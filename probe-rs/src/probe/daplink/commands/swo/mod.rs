@@ -0,0 +1,5 @@
+pub mod baudrate;
+pub mod control;
+pub mod data;
+pub mod mode;
+pub mod transport;
@@ -0,0 +1,27 @@
+use super::super::{Category, Request, Response, Result, Status};
+
+/// The encoding of the SWO output of the target.
+#[derive(Debug, Copy, Clone)]
+pub enum ModeRequest {
+    Off = 0x00,
+    Uart = 0x01,
+    Manchester = 0x02,
+}
+
+impl Request for ModeRequest {
+    const CATEGORY: Category = Category(0x18);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        buffer[offset] = *self as u8;
+        Ok(1)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ModeResponse(pub(crate) Status);
+
+impl Response for ModeResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        Ok(ModeResponse(Status::from_byte(buffer[offset])?))
+    }
+}
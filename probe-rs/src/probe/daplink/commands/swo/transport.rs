@@ -0,0 +1,29 @@
+use super::super::{Category, Request, Response, Result, Status};
+
+/// How the probe sends the captured SWO data to the host.
+#[derive(Debug, Copy, Clone)]
+pub enum TransportRequest {
+    NoTransport = 0x00,
+    /// The data is read with `DAP_SWO_Data` commands.
+    DataCommand = 0x01,
+    /// The data is streamed on a separate endpoint of CMSIS-DAP v2 probes.
+    Endpoint = 0x02,
+}
+
+impl Request for TransportRequest {
+    const CATEGORY: Category = Category(0x17);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        buffer[offset] = *self as u8;
+        Ok(1)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TransportResponse(pub(crate) Status);
+
+impl Response for TransportResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        Ok(TransportResponse(Status::from_byte(buffer[offset])?))
+    }
+}
@@ -1,30 +1,49 @@
 pub mod commands;
 pub mod tools;
+mod usb_interface;
+
+pub use self::usb_interface::DAPLinkDevice;
+use self::usb_interface::DAPLinkUsb;
 
 use crate::{
     coresight::{
-        debug_port::DPRegister,
+        debug_port::{Abort, Ctrl, DPRegister, DPv1, DebugPortId, Select, DPIDR},
         dp_access::{DPAccess, DebugPort},
     },
     probe::{
-        debug_probe::{DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo},
-        protocol::WireProtocol,
+        cancellation::CancellationToken,
+        debug_probe::{DAPAccess, DapError, DebugProbe, DebugProbeError, DebugProbeInfo, SwjPins},
+        jtag::{JtagAccess, JtagSequence},
+        protocol::{AttachMethod, WireProtocol},
+        retry::RetryPolicy,
     },
+    target::quirks::{self, Quirk},
 };
 
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+
+use std::time::{Duration, Instant};
 
 use commands::{
     general::{
         connect::{ConnectRequest, ConnectResponse},
         disconnect::{DisconnectRequest, DisconnectResponse},
+        info::{self, Capabilities},
         reset::{ResetRequest, ResetResponse},
     },
-    swd,
+    jtag, swd,
     swj::{
         clock::{SWJClockRequest, SWJClockResponse},
+        pins::{PinsRequest, PinsResponse},
         sequence::{SequenceRequest, SequenceResponse},
     },
+    swo::{
+        baudrate::{BaudrateRequest, BaudrateResponse},
+        control::{ControlRequest, ControlResponse},
+        data::{DataRequest, DataResponse},
+        mode::{ModeRequest, ModeResponse},
+        transport::{TransportRequest, TransportResponse},
+    },
     transfer::{
         configure::{ConfigureRequest, ConfigureResponse},
         Ack, InnerTransferRequest, Port, TransferRequest, TransferResponse, RW,
@@ -32,27 +51,55 @@ use commands::{
     Status,
 };
 
+/// The SWJ clock used when no speed is set, in kHz.
+const DEFAULT_SPEED_KHZ: u32 = 1000;
+
+/// How long a debug port with `Quirk::StagedPowerUp` may take to acknowledge a power-up
+/// request.
+const STAGED_POWER_UP_TIMEOUT: Duration = Duration::from_millis(100);
+
 pub struct DAPLink {
-    pub device: hidapi::HidDevice,
+    device: Box<dyn DAPLinkUsb>,
     _hw_version: u8,
     _jtag_version: u8,
     _protocol: WireProtocol,
+    target_sel: Option<u32>,
+    quirks: Vec<Quirk>,
+    /// The SWJ clock in kHz.
+    speed_khz: u32,
+    retry_policy: RetryPolicy,
+    /// Set while recovering from a failed transfer, so the transfers of the recovery do not
+    /// recover themselves.
+    recovering: bool,
+    /// Set once the probe reported that it stopped capturing SWO, so this is only warned about
+    /// once until the capture is started again.
+    swo_stopped: bool,
 }
 
 impl DAPLink {
-    pub fn new_from_device(device: hidapi::HidDevice) -> Self {
+    pub fn new_from_device(device: DAPLinkDevice) -> Self {
+        Self::new_from_usb(Box::new(device))
+    }
+
+    fn new_from_usb(device: Box<dyn DAPLinkUsb>) -> Self {
         Self {
             device,
             _hw_version: 0,
             _jtag_version: 0,
             _protocol: WireProtocol::Swd,
+            target_sel: None,
+            quirks: vec![],
+            speed_khz: DEFAULT_SPEED_KHZ,
+            retry_policy: RetryPolicy::default(),
+            recovering: false,
+            swo_stopped: false,
         }
     }
 
     fn set_swj_clock(&self, clock: u32) -> Result<(), DebugProbeError> {
         use commands::Error;
         commands::send_command::<SWJClockRequest, SWJClockResponse>(
-            &self.device,
+            &*self.device,
             SWJClockRequest(clock),
         )
         .and_then(|v| match v {
@@ -64,7 +111,7 @@ impl DAPLink {
 
     fn transfer_configure(&self, request: ConfigureRequest) -> Result<(), DebugProbeError> {
         use commands::Error;
-        commands::send_command::<ConfigureRequest, ConfigureResponse>(&self.device, request)
+        commands::send_command::<ConfigureRequest, ConfigureResponse>(&*self.device, request)
             .and_then(|v| match v {
                 ConfigureResponse(Status::DAPOk) => Ok(()),
                 ConfigureResponse(Status::DAPError) => Err(Error::DAP),
@@ -72,6 +119,194 @@ impl DAPLink {
         Ok(())
     }
 
+    /// Configures how often the probe retries transfers the target answers with WAIT.
+    fn configure_wait_retries(&self) -> Result<(), DebugProbeError> {
+        self.transfer_configure(ConfigureRequest {
+            idle_cycles: 0,
+            wait_retry: self.retry_policy.wait_retries,
+            match_retry: 0,
+        })
+    }
+
+    /// Checks that the single transfer of `response` was executed, and recovers from the
+    /// failure if not.
+    fn check_transfer(&mut self, response: &TransferResponse) -> Result<(), DebugProbeError> {
+        if response.transfer_response.protocol_error {
+            return Err(DapError::Parity.into());
+        }
+
+        match response.transfer_response.ack {
+            Ack::Ok if response.transfer_count == 1 => Ok(()),
+            Ack::Ok => Err(DebugProbeError::UnknownError),
+            Ack::Wait => Err(DapError::Wait.into()),
+            Ack::Fault => Err(self.recover(DapError::Fault).into()),
+            Ack::NoAck => Err(self.recover(DapError::NoAcknowledge).into()),
+        }
+    }
+
+    /// Clears the sticky error flags of the debug port through ABORT.
+    fn clear_sticky_errors(&mut self) -> Result<(), DebugProbeError> {
+        let mut abort = Abort::from(0);
+        abort.set_orunerrclr(true);
+        abort.set_wderrclr(true);
+        abort.set_stkerrclr(true);
+        abort.set_stkcmpclr(true);
+
+        self.write_dp_register(&DPv1 {}, abort)
+    }
+
+    /// Resets the wire protocol, selects the target and powers up the debug domain.
+    fn initialize_debug_port(&mut self) -> Result<(), DebugProbeError> {
+        if self.target_sel.is_some() {
+            // Multi-drop targets start up in the dormant state.
+            self.dormant_to_swd()?;
+        } else {
+            self.send_swj_sequences(
+                SequenceRequest::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap(),
+            )?;
+
+            self.send_swj_sequences(SequenceRequest::new(&[0x9e, 0xe7]).unwrap())?;
+        }
+
+        // Line reset, followed by idle cycles.
+        self.send_swj_sequences(
+            SequenceRequest::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap(),
+        )?;
+
+        self.send_swj_sequences(SequenceRequest::new(&[0x00]).unwrap())?;
+
+        if let Some(target_sel) = self.target_sel {
+            info!("Selecting multi-drop target 0x{:08x}", target_sel);
+            self.write_target_sel(target_sel)?;
+        }
+
+        // assume a dpv1 port for now
+
+        let port = DPv1 {};
+
+        let dp_id: DPIDR = self.read_dp_register(&port)?;
+
+        let raw_dp_id = u32::from(dp_id);
+        if let Some(expected) = quirks::dpidr(&self.quirks) {
+            if raw_dp_id != expected {
+                warn!(
+                    "The debug port reports DPIDR 0x{:08x} instead of 0x{:08x}, the chip may not be the selected target.",
+                    raw_dp_id, expected
+                );
+            }
+        }
+
+        let dp_id: DebugPortId = dp_id.into();
+
+        info!("Debug Port Version:  {:x?}", dp_id.version);
+        info!(
+            "Debug Port Designer: {}",
+            dp_id.designer.get().unwrap_or("Unknown")
+        );
+
+        let mut abort_reg = Abort(0);
+        abort_reg.set_orunerrclr(true);
+        abort_reg.set_wderrclr(true);
+        abort_reg.set_stkerrclr(true);
+        abort_reg.set_stkcmpclr(true);
+
+        self.write_dp_register(&port, abort_reg)?; // clear errors
+
+        let mut select_reg = Select(0);
+        select_reg.set_dp_bank_sel(0);
+
+        self.write_dp_register(&port, select_reg)?; // select DBPANK 0
+
+        if self.quirks.contains(&Quirk::StagedPowerUp) {
+            return self.staged_power_up();
+        }
+
+        let mut ctrl_reg = Ctrl::default();
+
+        ctrl_reg.set_csyspwrupreq(true);
+        ctrl_reg.set_cdbgpwrupreq(true);
+
+        debug!("Requesting debug power");
+
+        self.write_dp_register(&port, ctrl_reg)?; // CSYSPWRUPREQ, CDBGPWRUPREQ
+
+        // TODO: Check return value if power up was ok
+        let ctrl_reg: Ctrl = self.read_dp_register(&port)?;
+
+        if !(ctrl_reg.csyspwrupack() && ctrl_reg.cdbgpwrupack()) {
+            error!("Debug power request failed");
+            return Err(DebugProbeError::TargetPowerUpFailed);
+        }
+
+        Ok(())
+    }
+
+    /// Requests debug power first and system power afterwards, and waits for each to be
+    /// acknowledged, for debug ports with `Quirk::StagedPowerUp`.
+    fn staged_power_up(&mut self) -> Result<(), DebugProbeError> {
+        let port = DPv1 {};
+        let mut ctrl_reg = Ctrl::default();
+
+        for system in &[false, true] {
+            ctrl_reg.set_cdbgpwrupreq(true);
+            ctrl_reg.set_csyspwrupreq(*system);
+
+            debug!(
+                "Requesting {} power",
+                if *system { "system" } else { "debug" }
+            );
+            self.write_dp_register(&port, ctrl_reg.clone())?;
+
+            let start = Instant::now();
+            loop {
+                let ack: Ctrl = self.read_dp_register(&port)?;
+                if ack.cdbgpwrupack() && (ack.csyspwrupack() || !system) {
+                    break;
+                }
+                if start.elapsed() > STAGED_POWER_UP_TIMEOUT {
+                    error!("Debug power request failed");
+                    return Err(DebugProbeError::TargetPowerUpFailed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds out what went wrong after a transfer the target answered with FAULT or not at all.
+    ///
+    /// Depending on the retry policy, the sticky error flags are cleared afterwards, and the
+    /// debug port is initialized again if the target did not answer.
+    fn recover(&mut self, error: DapError) -> DapError {
+        // Only the transfer which failed first is recovered from.
+        if self.recovering {
+            return error;
+        }
+        self.recovering = true;
+
+        let error = match error {
+            DapError::Fault => match self.read_dp_register(&DPv1 {}) {
+                Ok(ctrl) => DapError::from_ctrl_stat(&ctrl),
+                Err(_) => DapError::NoAcknowledge,
+            },
+            error => error,
+        };
+        warn!("Transfer with the debug port failed: {}", error);
+
+        if error == DapError::NoAcknowledge && self.retry_policy.reinitialize_debug_port {
+            if let Err(e) = self.initialize_debug_port() {
+                warn!("Failed to initialize the debug port again: {}", e);
+            }
+        } else if self.retry_policy.clear_sticky_errors {
+            if let Err(e) = self.clear_sticky_errors() {
+                warn!("Failed to clear the sticky errors: {}", e);
+            }
+        }
+
+        self.recovering = false;
+        error
+    }
+
     fn configure_swd(
         &self,
         request: swd::configure::ConfigureRequest,
@@ -79,7 +314,7 @@ impl DAPLink {
         use commands::Error;
 
         commands::send_command::<swd::configure::ConfigureRequest, swd::configure::ConfigureResponse>(
-            &self.device,
+            &*self.device,
             request
         )
         .and_then(|v| match v {
@@ -89,6 +324,44 @@ impl DAPLink {
         Ok(())
     }
 
+    /// Switches a multi-drop capable target from the dormant state to SWD.
+    fn dormant_to_swd(&self) -> Result<(), DebugProbeError> {
+        // At least 8 cycles with SWDIO high.
+        self.send_swj_sequences(SequenceRequest::new(&[0xff]).unwrap())?;
+
+        // Selection alert sequence.
+        self.send_swj_sequences(
+            SequenceRequest::new(&[
+                0x92, 0xf3, 0x09, 0x62, 0x95, 0x2d, 0x85, 0x86, 0xe9, 0xaf, 0xdd, 0xe3, 0xa2, 0x0e,
+                0xbc, 0x19,
+            ])
+            .unwrap(),
+        )?;
+
+        // 4 cycles with SWDIO low, followed by the SWD activation code 0x1a.
+        self.send_swj_sequences(SequenceRequest::with_bit_count(&[0xa0, 0x01], 12).unwrap())
+    }
+
+    /// Writes the TARGETSEL register to select a target on a multi-drop SWD bus.
+    ///
+    /// The selected target does not drive the ACK phase of this write,
+    /// so it is sent as a raw SWD sequence instead of a regular transfer.
+    fn write_target_sel(&self, target_sel: u32) -> Result<(), DebugProbeError> {
+        use commands::Error;
+
+        let request = swd::sequence::SequenceRequest::target_sel(target_sel)?;
+
+        commands::send_command::<swd::sequence::SequenceRequest, swd::sequence::SequenceResponse>(
+            &*self.device,
+            request,
+        )
+        .and_then(|v| match v {
+            swd::sequence::SequenceResponse(Status::DAPOk) => Ok(()),
+            swd::sequence::SequenceResponse(Status::DAPError) => Err(Error::DAP),
+        })?;
+        Ok(())
+    }
+
     fn send_swj_sequences(&self, request: SequenceRequest) -> Result<(), DebugProbeError> {
         /* 12 38 FF FF FF FF FF FF FF -> 12 00 // SWJ Sequence
         12 10 9E E7 -> 12 00 // SWJ Sequence
@@ -96,7 +369,7 @@ impl DAPLink {
         //let sequence_1 = SequenceRequest::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
         use commands::Error;
 
-        commands::send_command::<SequenceRequest, SequenceResponse>(&self.device, request)
+        commands::send_command::<SequenceRequest, SequenceResponse>(&*self.device, request)
             .and_then(|v| match v {
                 SequenceResponse(Status::DAPOk) => Ok(()),
                 SequenceResponse(Status::DAPError) => Err(Error::DAP),
@@ -137,21 +410,25 @@ impl DebugProbe for DAPLink {
     where
         Self: Sized,
     {
-        if let Some(serial_number) = &info.serial_number {
-            Ok(Box::new(Self::new_from_device(
-                hidapi::HidApi::new()
-                    .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?
-                    .open_serial(info.vendor_id, info.product_id, &serial_number)
-                    .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?,
-            )))
+        // Prefer the bulk interface of CMSIS-DAP v2 probes, it is a lot faster than HID.
+        if let Ok(device) = usb_interface::open_v2_device(info) {
+            info!("Using the CMSIS-DAP v2 interface");
+            return Ok(Box::new(Self::new_from_device(device)));
+        }
+
+        let api = hidapi::HidApi::new().map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?;
+
+        let device = if let Some(serial_number) = &info.serial_number {
+            api.open_serial(info.vendor_id, info.product_id, &serial_number)
         } else {
-            Ok(Box::new(Self::new_from_device(
-                hidapi::HidApi::new()
-                    .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?
-                    .open(info.vendor_id, info.product_id)
-                    .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?,
-            )))
+            api.open(info.vendor_id, info.product_id)
         }
+        .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?;
+
+        Ok(Box::new(Self::new_from_device(DAPLinkDevice::V1 {
+            device,
+            cancellation: CancellationToken::default(),
+        })))
     }
 
     fn get_name(&self) -> &str {
@@ -159,11 +436,18 @@ impl DebugProbe for DAPLink {
     }
 
     /// Enters debug mode.
-    fn attach(&mut self, protocol: Option<WireProtocol>) -> Result<WireProtocol, DebugProbeError> {
+    ///
+    /// Connecting only powers up the debug domain, it never drives nRESET or halts the core.
+    /// With `AttachMethod::Reset` the target is reset once the connection is established.
+    fn attach(
+        &mut self,
+        protocol: Option<WireProtocol>,
+        method: AttachMethod,
+    ) -> Result<WireProtocol, DebugProbeError> {
         use commands::Error;
 
         info!("Attaching to target system");
-        self.set_swj_clock(1_000_000)?;
+        self.set_swj_clock(self.speed_khz * 1000)?;
 
         let protocol = if let Some(protocol) = protocol {
             match protocol {
@@ -174,101 +458,183 @@ impl DebugProbe for DAPLink {
             ConnectRequest::UseDefaultPort
         };
 
-        let result = commands::send_command(&self.device, protocol).and_then(|v| match v {
+        let result = commands::send_command(&*self.device, protocol).and_then(|v| match v {
             ConnectResponse::SuccessfulInitForSWD => Ok(WireProtocol::Swd),
             ConnectResponse::SuccessfulInitForJTAG => Ok(WireProtocol::Jtag),
             ConnectResponse::InitFailed => Err(Error::DAP),
         })?;
 
-        self.set_swj_clock(1_000_000)?;
+        self.set_swj_clock(self.speed_khz * 1000)?;
 
-        self.transfer_configure(ConfigureRequest {
-            idle_cycles: 0,
-            wait_retry: 80,
-            match_retry: 0,
-        })?;
+        self.configure_wait_retries()?;
 
         self.configure_swd(swd::configure::ConfigureRequest {})?;
 
-        self.send_swj_sequences(
-            SequenceRequest::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap(),
-        )?;
+        self.initialize_debug_port()?;
 
-        self.send_swj_sequences(SequenceRequest::new(&[0x9e, 0xe7]).unwrap())?;
+        info!("Succesfully attached to system and entered debug mode");
 
-        self.send_swj_sequences(
-            SequenceRequest::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap(),
-        )?;
+        if method == AttachMethod::Reset {
+            self.target_reset()?;
+        }
 
-        self.send_swj_sequences(SequenceRequest::new(&[0x00]).unwrap())?;
+        Ok(result)
+    }
 
-        use crate::coresight::debug_port::{Abort, Ctrl, DPv1, DebugPortId, Select, DPIDR};
+    /// Leave debug mode.
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        commands::send_command(&*self.device, DisconnectRequest {})
+            .map_err(|_| DebugProbeError::USBError)
+            .and_then(|v: DisconnectResponse| match v {
+                DisconnectResponse(Status::DAPOk) => Ok(()),
+                DisconnectResponse(Status::DAPError) => Err(DebugProbeError::UnknownError),
+            })
+    }
 
-        // assume a dpv1 port for now
+    /// Asserts the nRESET pin.
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        let _: ResetResponse = commands::send_command(&*self.device, ResetRequest)?;
+        Ok(())
+    }
 
-        let port = DPv1 {};
+    fn set_multidrop_target(&mut self, target_sel: Option<u32>) -> Result<(), DebugProbeError> {
+        self.target_sel = target_sel;
+        Ok(())
+    }
 
-        let dp_id: DPIDR = self.read_dp_register(&port)?;
+    fn set_quirks(&mut self, quirks: &[Quirk]) -> Result<(), DebugProbeError> {
+        self.quirks = quirks.to_vec();
+        Ok(())
+    }
 
-        let dp_id: DebugPortId = dp_id.into();
+    fn set_retry_policy(&mut self, policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        self.retry_policy = policy;
+        self.device.set_timeout(policy.usb_timeout);
 
-        info!("Debug Port Version:  {:x?}", dp_id.version);
-        info!(
-            "Debug Port Designer: {}",
-            dp_id.designer.get().unwrap_or("Unknown")
-        );
+        self.configure_wait_retries()
+    }
 
-        let mut abort_reg = Abort(0);
-        abort_reg.set_orunerrclr(true);
-        abort_reg.set_wderrclr(true);
-        abort_reg.set_stkerrclr(true);
-        abort_reg.set_stkcmpclr(true);
+    fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.device.set_cancellation_token(token);
+    }
 
-        self.write_dp_register(&port, abort_reg)?; // clear errors
+    /// CMSIS-DAP probes divide their clock to the requested speed themselves.
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.speed_khz = speed_khz;
+        Ok(speed_khz)
+    }
 
-        let mut select_reg = Select(0);
-        select_reg.set_dp_bank_sel(0);
+    /// CMSIS-DAP does not define a command to read the target voltage.
+    fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError> {
+        Ok(None)
+    }
 
-        self.write_dp_register(&port, select_reg)?; // select DBPANK 0
+    /// Captures the UART encoded SWO output with the CMSIS-DAP SWO commands. The data is read
+    /// with `DAP_SWO_Data`, which all probes with SWO support implement.
+    ///
+    /// Fails with `DebugProbeError::CommandNotSupportedByProbe` if the probe does not report
+    /// UART SWO support in its capabilities.
+    fn enable_swo(&mut self, baud_rate: u32) -> Result<(), DebugProbeError> {
+        use commands::Error;
 
-        let mut ctrl_reg = Ctrl::default();
+        let capabilities: Capabilities =
+            commands::send_command(&*self.device, info::Command::Capabilities)?;
+        if !capabilities.swo_uart_implemented {
+            return Err(DebugProbeError::CommandNotSupportedByProbe);
+        }
 
-        ctrl_reg.set_csyspwrupreq(true);
-        ctrl_reg.set_cdbgpwrupreq(true);
+        // The capture has to be stopped while it is configured.
+        self.disable_swo()?;
 
-        debug!("Requesting debug power");
+        commands::send_command(&*self.device, TransportRequest::DataCommand).and_then(
+            |v| match v {
+                TransportResponse(Status::DAPOk) => Ok(()),
+                TransportResponse(Status::DAPError) => Err(Error::DAP),
+            },
+        )?;
+        commands::send_command(&*self.device, ModeRequest::Uart).and_then(|v| match v {
+            ModeResponse(Status::DAPOk) => Ok(()),
+            ModeResponse(Status::DAPError) => Err(Error::DAP),
+        })?;
 
-        self.write_dp_register(&port, ctrl_reg)?; // CSYSPWRUPREQ, CDBGPWRUPREQ
+        let BaudrateResponse(actual) =
+            commands::send_command(&*self.device, BaudrateRequest(baud_rate))?;
+        if actual == 0 {
+            error!("The probe cannot capture SWO at {} baud", baud_rate);
+            return Err(Error::DAP.into());
+        } else if actual != baud_rate {
+            warn!(
+                "The probe captures SWO at {} baud instead of {} baud",
+                actual, baud_rate
+            );
+        }
 
-        // TODO: Check return value if power up was ok
-        let ctrl_reg: Ctrl = self.read_dp_register(&port)?;
+        commands::send_command(&*self.device, ControlRequest::Start).and_then(|v| match v {
+            ControlResponse(Status::DAPOk) => Ok(()),
+            ControlResponse(Status::DAPError) => Err(Error::DAP),
+        })?;
+        self.swo_stopped = false;
 
-        if !(ctrl_reg.csyspwrupack() && ctrl_reg.cdbgpwrupack()) {
-            error!("Debug power request failed");
-            return Err(DebugProbeError::TargetPowerUpFailed);
+        Ok(())
+    }
+
+    fn disable_swo(&mut self) -> Result<(), DebugProbeError> {
+        use commands::Error;
+
+        commands::send_command(&*self.device, ControlRequest::Stop).and_then(|v| match v {
+            ControlResponse(Status::DAPOk) => Ok(()),
+            ControlResponse(Status::DAPError) => Err(Error::DAP),
+        })?;
+
+        Ok(())
+    }
+
+    /// Reads as much of the captured data as fits into a packet.
+    fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        // The response starts with the command, the trace status and the number of bytes.
+        let request = DataRequest {
+            max_count: (commands::PACKET_SIZE - 4) as u16,
+        };
+        let response: DataResponse = commands::send_command(&*self.device, request)?;
+
+        if response.overrun {
+            warn!("The SWO buffer of the probe overflowed, trace data was lost");
+        }
+        if !response.active && !self.swo_stopped {
+            warn!("The probe stopped capturing SWO");
+            self.swo_stopped = true;
         }
 
-        info!("Succesfully attached to system and entered debug mode");
+        Ok(response.data)
+    }
 
-        Ok(result)
+    fn swj_pins(
+        &mut self,
+        output: SwjPins,
+        select: SwjPins,
+        wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError> {
+        let request = PinsRequest {
+            output: output.bits(),
+            select: select.bits(),
+            wait_us,
+        };
+
+        let PinsResponse(pins) = commands::send_command(&*self.device, request)?;
+
+        Ok(SwjPins::from_bits_truncate(pins))
     }
 
-    /// Leave debug mode.
-    fn detach(&mut self) -> Result<(), DebugProbeError> {
-        commands::send_command(&self.device, DisconnectRequest {})
-            .map_err(|_| DebugProbeError::USBError)
-            .and_then(|v: DisconnectResponse| match v {
-                DisconnectResponse(Status::DAPOk) => Ok(()),
-                DisconnectResponse(Status::DAPError) => Err(DebugProbeError::UnknownError),
-            })
+    fn jtag_access(&mut self) -> Option<&mut dyn JtagAccess> {
+        Some(self)
     }
 
-    /// Asserts the nRESET pin.
-    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
-        commands::send_command(&self.device, ResetRequest).map(|v: ResetResponse| {
-            println!("Target reset response: {:?}", v);
-        })?;
-        Ok(())
+    fn read_block16(&mut self, _address: u32, _data: &mut [u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+
+    fn write_block16(&mut self, _address: u32, _data: &[u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
     }
 }
 
@@ -284,25 +650,15 @@ impl DAPAccess for DAPLink {
             crate::probe::debug_probe::Port::AccessPort(_) => Port::AP,
         };
 
-        commands::send_command::<TransferRequest, TransferResponse>(
-            &self.device,
+        let response = commands::send_command::<TransferRequest, TransferResponse>(
+            &*self.device,
             TransferRequest::new(InnerTransferRequest::new(port, RW::R, addr as u8), 0),
         )
-        .map_err(|_| DebugProbeError::UnknownError)
-        .and_then(|v| {
-            if v.transfer_count == 1 {
-                if v.transfer_response.protocol_error {
-                    Err(DebugProbeError::USBError)
-                } else {
-                    match v.transfer_response.ack {
-                        Ack::Ok => Ok(v.transfer_data),
-                        _ => Err(DebugProbeError::UnknownError),
-                    }
-                }
-            } else {
-                Err(DebugProbeError::UnknownError)
-            }
-        })
+        .map_err(|_| DebugProbeError::UnknownError)?;
+
+        self.check_transfer(&response)?;
+
+        Ok(response.transfer_data)
     }
 
     /// Writes a value to the DAP register on the specified port and address.
@@ -317,25 +673,89 @@ impl DAPAccess for DAPLink {
             crate::probe::debug_probe::Port::AccessPort(_) => Port::AP,
         };
 
-        commands::send_command::<TransferRequest, TransferResponse>(
-            &self.device,
+        let response = commands::send_command::<TransferRequest, TransferResponse>(
+            &*self.device,
             TransferRequest::new(InnerTransferRequest::new(port, RW::W, addr as u8), value),
         )
-        .map_err(|_| DebugProbeError::UnknownError)
-        .and_then(|v| {
-            if v.transfer_count == 1 {
-                if v.transfer_response.protocol_error {
-                    Err(DebugProbeError::USBError)
-                } else {
-                    match v.transfer_response.ack {
-                        Ack::Ok => Ok(()),
-                        _ => Err(DebugProbeError::UnknownError),
+        .map_err(|_| DebugProbeError::UnknownError)?;
+
+        self.check_transfer(&response)
+    }
+}
+
+impl JtagAccess for DAPLink {
+    fn attach_jtag(&mut self) -> Result<(), DebugProbeError> {
+        use commands::Error;
+
+        info!("Attaching to the JTAG scan chain");
+        self.set_swj_clock(self.speed_khz * 1000)?;
+
+        commands::send_command(&*self.device, ConnectRequest::UseJTAG).and_then(|v| match v {
+            ConnectResponse::SuccessfulInitForJTAG => Ok(()),
+            _ => Err(Error::DAP),
+        })?;
+
+        // Test-Logic-Reset, then Run-Test/Idle.
+        self.jtag_sequences(&[
+            JtagSequence {
+                tms: true,
+                capture: false,
+                bit_count: 5,
+                tdi: 0,
+            },
+            JtagSequence {
+                tms: false,
+                capture: false,
+                bit_count: 1,
+                tdi: 0,
+            },
+        ])?;
+
+        Ok(())
+    }
+
+    /// Sends the sequences in as few commands as fit into the packets.
+    fn jtag_sequences(&mut self, sequences: &[JtagSequence]) -> Result<Vec<u64>, DebugProbeError> {
+        use commands::Error;
+
+        let mut captured = vec![];
+        let mut remaining = sequences;
+
+        while !remaining.is_empty() {
+            let mut request_size = 0;
+            let mut response_size = 0;
+            let count = remaining
+                .iter()
+                .take_while(|sequence| {
+                    let bytes = jtag::sequence::byte_count(sequence);
+                    request_size += 1 + bytes;
+                    if sequence.capture {
+                        response_size += bytes;
                     }
-                }
-            } else {
-                Err(DebugProbeError::UnknownError)
+                    request_size <= jtag::sequence::MAX_DATA_SIZE
+                        && response_size <= jtag::sequence::MAX_DATA_SIZE
+                })
+                .count();
+            let (batch, rest) = remaining.split_at(count);
+            remaining = rest;
+
+            let request = jtag::sequence::SequenceRequest::new(batch.to_vec())?;
+            let data = commands::send_command(&*self.device, request).and_then(|v| match v {
+                jtag::sequence::SequenceResponse(Status::DAPOk, data) => Ok(data),
+                jtag::sequence::SequenceResponse(Status::DAPError, _) => Err(Error::DAP),
+            })?;
+
+            let mut position = 0;
+            for sequence in batch.iter().filter(|sequence| sequence.capture) {
+                let bytes = jtag::sequence::byte_count(sequence);
+                let mut value = [0; 8];
+                value[..bytes].copy_from_slice(&data[position..position + bytes]);
+                captured.push(u64::from_le_bytes(value));
+                position += bytes;
             }
-        })
+        }
+
+        Ok(captured)
     }
 }
 
@@ -346,3 +766,75 @@ impl Drop for DAPLink {
         let _ = self.detach();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{commands::Error, usb_interface::DAPLinkUsb, DAPLink};
+    use crate::probe::{
+        cancellation::CancellationToken,
+        debug_probe::DebugProbe,
+        protocol::{AttachMethod, WireProtocol},
+    };
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// The ID of the DAP_ResetTarget command.
+    const RESET_TARGET: u8 = 0x0A;
+
+    /// Answers every command successfully and records the command IDs.
+    struct FakeUsb {
+        commands: Rc<RefCell<Vec<u8>>>,
+        response: RefCell<Vec<u8>>,
+    }
+
+    impl DAPLinkUsb for FakeUsb {
+        fn set_timeout(&mut self, _timeout: Duration) {}
+
+        fn set_cancellation_token(&mut self, _token: CancellationToken) {}
+
+        fn write(&self, buffer: &[u8]) -> Result<usize, Error> {
+            // The first byte is the HID report ID.
+            let id = buffer[1];
+            self.commands.borrow_mut().push(id);
+            *self.response.borrow_mut() = match id {
+                // DAP_Connect, in SWD mode.
+                0x02 => vec![0x02, 0x01],
+                // DAP_Transfer of one register, reading the acknowledged power up of the debug port.
+                0x05 => vec![0x05, 0x01, 0x01, 0x00, 0x00, 0x00, 0xF0],
+                // DAP_ResetTarget, with a device specific reset sequence.
+                0x0A => vec![0x0A, 0x00, 0x01],
+                id => vec![id, 0x00],
+            };
+
+            Ok(buffer.len() - 1)
+        }
+
+        fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+            let response = self.response.borrow();
+            buffer[..response.len()].copy_from_slice(&response);
+
+            Ok(response.len())
+        }
+    }
+
+    #[test]
+    fn resets_only_when_asked_to() {
+        let commands = Rc::new(RefCell::new(vec![]));
+        let mut probe = DAPLink::new_from_usb(Box::new(FakeUsb {
+            commands: commands.clone(),
+            response: RefCell::new(vec![]),
+        }));
+
+        probe
+            .attach(Some(WireProtocol::Swd), AttachMethod::NoReset)
+            .unwrap();
+        assert!(!commands.borrow().contains(&RESET_TARGET));
+
+        probe
+            .attach(Some(WireProtocol::Swd), AttachMethod::Reset)
+            .unwrap();
+        assert!(commands.borrow().contains(&RESET_TARGET));
+    }
+}
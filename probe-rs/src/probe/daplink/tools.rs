@@ -1,8 +1,15 @@
+use super::usb_interface::{
+    find_v2_interface, read_product_string, read_serial_number, DAPLinkDevice,
+};
 use crate::probe::debug_probe::{DebugProbeInfo, DebugProbeType};
 
+use rusb::UsbContext;
+
 pub fn list_daplink_devices() -> Vec<DebugProbeInfo> {
-    match hidapi::HidApi::new() {
-        Ok(api) => api
+    let mut probes = list_v2_devices();
+
+    if let Ok(api) = hidapi::HidApi::new() {
+        let hid_probes = api
             .devices()
             .iter()
             .cloned()
@@ -15,11 +22,73 @@ pub fn list_daplink_devices() -> Vec<DebugProbeInfo> {
                     v.product_id,
                     v.serial_number,
                     DebugProbeType::DAPLink,
+                    Some(bcd_version(v.release_number)),
                 )
             })
-            .collect::<Vec<_>>(),
-        Err(_e) => vec![],
+            .collect::<Vec<_>>();
+
+        // Probes supporting v2 usually also expose the HID interface, only list them once.
+        for probe in hid_probes {
+            let already_listed = probes.iter().any(|v2| {
+                v2.vendor_id == probe.vendor_id
+                    && v2.product_id == probe.product_id
+                    && v2.serial_number == probe.serial_number
+            });
+
+            if !already_listed {
+                probes.push(probe);
+            }
+        }
     }
+
+    probes
+}
+
+/// Lists all probes which expose a CMSIS-DAP v2 bulk interface.
+fn list_v2_devices() -> Vec<DebugProbeInfo> {
+    let context = match rusb::Context::new() {
+        Ok(context) => context,
+        Err(_) => return vec![],
+    };
+
+    let devices = match context.devices() {
+        Ok(devices) => devices,
+        Err(_) => return vec![],
+    };
+
+    devices
+        .iter()
+        .filter_map(|device| {
+            let descriptor = device.device_descriptor().ok()?;
+            let handle = device.open().ok()?;
+
+            find_v2_interface(&device, &handle)?;
+            let version = descriptor.device_version();
+
+            Some(DebugProbeInfo::new(
+                read_product_string(&device, &handle)
+                    .unwrap_or_else(|| "Unknown CMSIS-DAP Probe".to_owned()),
+                descriptor.vendor_id(),
+                descriptor.product_id(),
+                read_serial_number(&device, &handle),
+                DebugProbeType::DAPLink,
+                Some(format!(
+                    "{}.{}.{}",
+                    version.major(),
+                    version.minor(),
+                    version.sub_minor()
+                )),
+            ))
+        })
+        .collect()
+}
+
+/// Formats the BCD encoded device release of a USB device descriptor, e.g. 0x0251 as 2.5.1,
+/// the way libusb decodes it for v2 probes.
+fn bcd_version(bcd: u16) -> String {
+    let digit = |shift: u16| (bcd >> shift) & 0xF;
+
+    format!("{}.{}.{}", digit(12) * 10 + digit(8), digit(4), digit(0))
 }
 
 pub fn is_daplink_device(device: &hidapi::HidDeviceInfo) -> bool {
@@ -30,7 +99,7 @@ pub fn is_daplink_device(device: &hidapi::HidDeviceInfo) -> bool {
     }
 }
 
-pub fn read_status(device: &hidapi::HidDevice) {
+pub fn read_status(device: &DAPLinkDevice) {
     let vendor_id: super::commands::general::info::VendorID =
         super::commands::send_command(device, super::commands::general::info::Command::VendorID)
             .unwrap();
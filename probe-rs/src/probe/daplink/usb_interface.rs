@@ -0,0 +1,280 @@
+use super::commands::Error;
+use crate::probe::cancellation::CancellationToken;
+use crate::probe::debug_probe::{DebugProbeError, DebugProbeInfo};
+
+use rusb::{Context, Device, DeviceHandle, Direction, TransferType, UsbContext};
+
+use std::time::{Duration, Instant};
+
+/// Timeout for reading the descriptors of CMSIS-DAP v2 probes, and of the commands until a
+/// retry policy is set.
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Maximum packet size of a high speed bulk endpoint.
+const MAX_PACKET_SIZE: usize = 512;
+
+/// How often a read waiting for the probe checks the cancellation token.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The transport used to talk to a CMSIS-DAP probe.
+pub enum DAPLinkDevice {
+    /// CMSIS-DAP v1, which transfers the commands in HID reports.
+    V1 {
+        device: hidapi::HidDevice,
+        cancellation: CancellationToken,
+    },
+    /// CMSIS-DAP v2, which transfers the commands over a pair of bulk endpoints.
+    V2 {
+        handle: DeviceHandle<Context>,
+        out_ep: u8,
+        in_ep: u8,
+        timeout: Duration,
+        cancellation: CancellationToken,
+    },
+}
+
+/// The USB transfers the CMSIS-DAP driver is built on, so it can be tested without a probe.
+pub(crate) trait DAPLinkUsb {
+    /// See `DAPLinkDevice::set_timeout`.
+    fn set_timeout(&mut self, timeout: Duration);
+
+    /// See `DAPLinkDevice::set_cancellation_token`.
+    fn set_cancellation_token(&mut self, token: CancellationToken);
+
+    /// See `DAPLinkDevice::write`.
+    fn write(&self, buffer: &[u8]) -> Result<usize, Error>;
+
+    /// See `DAPLinkDevice::read`.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Error>;
+}
+
+impl DAPLinkUsb for DAPLinkDevice {
+    fn set_timeout(&mut self, timeout: Duration) {
+        DAPLinkDevice::set_timeout(self, timeout)
+    }
+
+    fn set_cancellation_token(&mut self, token: CancellationToken) {
+        DAPLinkDevice::set_cancellation_token(self, token)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, Error> {
+        DAPLinkDevice::write(self, buffer)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        DAPLinkDevice::read(self, buffer)
+    }
+}
+
+impl DAPLinkDevice {
+    /// Sets the timeout of the USB transfers of v2 probes. Reads from HID devices block until
+    /// the probe answers.
+    pub(crate) fn set_timeout(&mut self, new_timeout: Duration) {
+        if let DAPLinkDevice::V2 { timeout, .. } = self {
+            *timeout = new_timeout;
+        }
+    }
+
+    /// Sets the token which aborts a read while the probe does not answer.
+    pub(crate) fn set_cancellation_token(&mut self, token: CancellationToken) {
+        match self {
+            DAPLinkDevice::V1 { cancellation, .. } | DAPLinkDevice::V2 { cancellation, .. } => {
+                *cancellation = token
+            }
+        }
+    }
+
+    /// Writes a command to the probe.
+    ///
+    /// The first byte of `buffer` is the HID report ID, it is not transmitted for v2 probes.
+    pub(crate) fn write(&self, buffer: &[u8]) -> Result<usize, Error> {
+        match self {
+            DAPLinkDevice::V1 { device, .. } => Ok(device.write(buffer)?),
+            DAPLinkDevice::V2 {
+                handle,
+                out_ep,
+                timeout,
+                ..
+            } => Ok(handle.write_bulk(*out_ep, &buffer[1..], *timeout)?),
+        }
+    }
+
+    /// Reads a response from the probe into `buffer`.
+    ///
+    /// While the probe does not answer, the read is aborted with `Error::Cancelled` once the
+    /// cancellation token was cancelled.
+    pub(crate) fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            DAPLinkDevice::V1 {
+                device,
+                cancellation,
+            } => loop {
+                // A read without a timeout would block until the probe answers.
+                let length = device.read_timeout(buffer, POLL_INTERVAL.as_millis() as i32)?;
+                if length > 0 {
+                    return Ok(length);
+                }
+                cancellation.check().map_err(|_| Error::Cancelled)?;
+            },
+            DAPLinkDevice::V2 {
+                handle,
+                in_ep,
+                timeout,
+                cancellation,
+                ..
+            } => {
+                // The probe may send a full packet, which must fit into the read buffer.
+                let mut packet = [0; MAX_PACKET_SIZE];
+                let start = Instant::now();
+                let length = loop {
+                    // A timeout of zero would wait forever.
+                    let poll = timeout
+                        .checked_sub(start.elapsed())
+                        .unwrap_or_default()
+                        .min(POLL_INTERVAL)
+                        .max(Duration::from_millis(1));
+                    match handle.read_bulk(*in_ep, &mut packet, poll) {
+                        Err(rusb::Error::Timeout) if start.elapsed() < *timeout => {
+                            cancellation.check().map_err(|_| Error::Cancelled)?
+                        }
+                        result => break result?,
+                    }
+                };
+                let length = length.min(buffer.len());
+
+                buffer[..length].copy_from_slice(&packet[..length]);
+
+                Ok(length)
+            }
+        }
+    }
+}
+
+/// Bulk endpoints of a CMSIS-DAP v2 interface.
+pub(crate) struct V2Interface {
+    pub number: u8,
+    pub out_ep: u8,
+    pub in_ep: u8,
+}
+
+/// Looks for the CMSIS-DAP v2 interface of a USB device.
+///
+/// The interface is a vendor specific interface whose string descriptor contains `CMSIS-DAP`,
+/// with a bulk OUT and a bulk IN endpoint.
+pub(crate) fn find_v2_interface(
+    device: &Device<Context>,
+    handle: &DeviceHandle<Context>,
+) -> Option<V2Interface> {
+    let config = device.active_config_descriptor().ok()?;
+    let language = *handle.read_languages(TIMEOUT).ok()?.first()?;
+
+    for interface in config.interfaces() {
+        for descriptor in interface.descriptors() {
+            if descriptor.class_code() != 0xFF || descriptor.sub_class_code() != 0 {
+                continue;
+            }
+
+            let is_cmsis_dap = handle
+                .read_interface_string(language, &descriptor, TIMEOUT)
+                .map(|name| name.contains("CMSIS-DAP"))
+                .unwrap_or(false);
+
+            if !is_cmsis_dap {
+                continue;
+            }
+
+            let bulk_endpoints: Vec<_> = descriptor
+                .endpoint_descriptors()
+                .filter(|ep| ep.transfer_type() == TransferType::Bulk)
+                .collect();
+
+            let out_ep = bulk_endpoints
+                .iter()
+                .find(|ep| ep.direction() == Direction::Out)?;
+            let in_ep = bulk_endpoints
+                .iter()
+                .find(|ep| ep.direction() == Direction::In)?;
+
+            return Some(V2Interface {
+                number: descriptor.interface_number(),
+                out_ep: out_ep.address(),
+                in_ep: in_ep.address(),
+            });
+        }
+    }
+
+    None
+}
+
+/// Reads the serial number string of a USB device.
+pub(crate) fn read_serial_number(
+    device: &Device<Context>,
+    handle: &DeviceHandle<Context>,
+) -> Option<String> {
+    let descriptor = device.device_descriptor().ok()?;
+    let language = *handle.read_languages(TIMEOUT).ok()?.first()?;
+
+    handle
+        .read_serial_number_string(language, &descriptor, TIMEOUT)
+        .ok()
+}
+
+/// Reads the product string of a USB device.
+pub(crate) fn read_product_string(
+    device: &Device<Context>,
+    handle: &DeviceHandle<Context>,
+) -> Option<String> {
+    let descriptor = device.device_descriptor().ok()?;
+    let language = *handle.read_languages(TIMEOUT).ok()?.first()?;
+
+    handle
+        .read_product_string(language, &descriptor, TIMEOUT)
+        .ok()
+}
+
+/// Tries to open the probe described by `info` as a CMSIS-DAP v2 device.
+pub(crate) fn open_v2_device(info: &DebugProbeInfo) -> Result<DAPLinkDevice, DebugProbeError> {
+    let context = Context::new().map_err(|_| DebugProbeError::USBError)?;
+
+    for device in context
+        .devices()
+        .map_err(|_| DebugProbeError::USBError)?
+        .iter()
+    {
+        let descriptor = match device.device_descriptor() {
+            Ok(descriptor) => descriptor,
+            Err(_) => continue,
+        };
+
+        if descriptor.vendor_id() != info.vendor_id || descriptor.product_id() != info.product_id {
+            continue;
+        }
+
+        let mut handle = match device.open() {
+            Ok(handle) => handle,
+            Err(_) => continue,
+        };
+
+        if info.serial_number.is_some()
+            && read_serial_number(&device, &handle) != info.serial_number
+        {
+            continue;
+        }
+
+        if let Some(interface) = find_v2_interface(&device, &handle) {
+            handle
+                .claim_interface(interface.number)
+                .map_err(|_| DebugProbeError::USBError)?;
+
+            return Ok(DAPLinkDevice::V2 {
+                handle,
+                out_ep: interface.out_ep,
+                in_ep: interface.in_ep,
+                timeout: TIMEOUT,
+                cancellation: CancellationToken::default(),
+            });
+        }
+    }
+
+    Err(DebugProbeError::ProbeCouldNotBeCreated)
+}
@@ -2,28 +2,58 @@ use crate::coresight::{
     access_ports::{generic_ap::GenericAP, memory_ap::MemoryAP, APRegister, AccessPortError},
     ap_access::{APAccess, AccessPort},
     common::Register,
+    debug_port::Ctrl,
 };
 
 use log::debug;
 
+use crate::collection::cores::xtensa::XtensaMemoryInterface;
 use crate::memory::adi_v5_memory_interface::ADIMemoryInterface;
+use crate::memory::cache::{MemoryCache, LINE_SIZE};
+use crate::memory::cache_maintenance::CacheMaintenance;
 use crate::memory::MI;
-use crate::probe::protocol::WireProtocol;
+#[cfg(feature = "serialport")]
+use crate::probe::blackmagic;
+use crate::probe::cancellation::CancellationToken;
+#[cfg(feature = "daplink")]
+use crate::probe::daplink;
+use crate::probe::jtag::JtagAccess;
+use crate::probe::protocol::{AttachMethod, ResetType, WireProtocol};
+use crate::probe::retry::RetryPolicy;
+#[cfg(feature = "stlink")]
+use crate::probe::stlink;
+use crate::probe::{gpio, registry, watch};
+use crate::target::{quirks::Quirk, Architecture};
 use std::error::Error;
 use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub enum DebugProbeError {
     USBError,
     JTAGNotSupportedOnProbe,
     ProbeFirmwareOutdated,
+    CommandNotSupportedByProbe,
+    /// The probe firmware is too old to support a required feature.
+    FirmwareUpgradeRequired {
+        feature: &'static str,
+        current: String,
+        required: String,
+    },
     VoltageDivisionByZero,
     UnknownMode,
     JTagDoesNotSupportMultipleAP,
     UnknownError,
+    /// A memory transfer of the given length faulted at the given address.
     TransferFault(u32, u16),
     DataAlignmentError,
     Access16BitNotSupported,
+    /// The probe cannot capture SWO at the requested baud rate, only up to the given one.
+    SwoBaudRateUnsupported {
+        baud_rate: u32,
+        max_baud_rate: u32,
+    },
     BlanksNotAllowedOnDPRegister,
     RegisterAddressMustBe16Bit,
     NotEnoughBytesRead,
@@ -33,12 +63,25 @@ pub enum DebugProbeError {
     TargetPowerUpFailed,
     Timeout,
     AccessPortError(AccessPortError),
+    /// All comparators of the breakpoint unit are in use.
+    BreakpointUnitsExhausted,
+    /// The breakpoint unit can not break at the given address.
+    BreakpointAddressUnsupported(u32),
+    /// The core can not be reset this way.
+    ResetTypeUnsupported(ResetType),
+    /// A transfer with the debug port failed.
+    Dap(DapError),
+    /// An instruction executed by the debug module of the core raised an exception.
+    InstructionFailed,
+    /// Driving or reading the GPIO lines of a `GpioProbe` failed.
+    Gpio(String),
 }
 
 impl Error for DebugProbeError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             DebugProbeError::AccessPortError(ref e) => Some(e),
+            DebugProbeError::Dap(ref e) => Some(e),
             _ => None,
         }
     }
@@ -47,7 +90,51 @@ impl Error for DebugProbeError {
 impl fmt::Display for DebugProbeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // TODO: Cleanup of Debug Probe Errors
-        write!(f, "{:?}", self)
+        match self {
+            DebugProbeError::FirmwareUpgradeRequired {
+                feature,
+                current,
+                required,
+            } => write!(
+                f,
+                "The probe firmware {} does not support {}. Please upgrade the probe firmware to {} or newer.",
+                current, feature, required
+            ),
+            DebugProbeError::BreakpointUnitsExhausted => {
+                write!(f, "All hardware breakpoints are in use.")
+            }
+            DebugProbeError::BreakpointAddressUnsupported(address) => write!(
+                f,
+                "The breakpoint unit can not break at 0x{:08x}, which is outside the code region.",
+                address
+            ),
+            DebugProbeError::ResetTypeUnsupported(reset_type) => {
+                write!(f, "The core does not support a {} reset.", reset_type)
+            }
+            DebugProbeError::Dap(e) => write!(f, "The transfer with the debug port failed: {}", e),
+            DebugProbeError::InstructionFailed => write!(
+                f,
+                "The core raised an exception while executing an instruction for the debugger."
+            ),
+            DebugProbeError::Gpio(e) => write!(f, "Accessing the GPIO lines failed: {}", e),
+            DebugProbeError::TransferFault(address, length) => write!(
+                f,
+                "The memory transfer of {} bytes faulted at 0x{:08x}.",
+                length, address
+            ),
+            DebugProbeError::Access16BitNotSupported => {
+                write!(f, "The probe does not support 16-bit memory accesses.")
+            }
+            DebugProbeError::SwoBaudRateUnsupported {
+                baud_rate,
+                max_baud_rate,
+            } => write!(
+                f,
+                "The probe cannot capture SWO at {} baud, only up to {} baud.",
+                baud_rate, max_baud_rate
+            ),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
@@ -57,6 +144,104 @@ impl From<AccessPortError> for DebugProbeError {
     }
 }
 
+impl From<DapError> for DebugProbeError {
+    fn from(e: DapError) -> Self {
+        DebugProbeError::Dap(e)
+    }
+}
+
+/// What went wrong with a transfer with the debug port.
+///
+/// After a FAULT the probes read CTRL/STAT to tell which sticky error flag was set, and clear
+/// the flags through ABORT if the retry policy asks for it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DapError {
+    /// The data of a write was corrupted on the wire (WDATAERR).
+    WriteData,
+    /// An access port transaction failed, e.g. a memory access with a bus fault (STICKYERR).
+    Sticky,
+    /// A transfer was started before the previous one finished (STICKYORUN).
+    Overrun,
+    /// The parity of the data read from the target was wrong.
+    Parity,
+    /// The target answered with FAULT, but CTRL/STAT showed no error.
+    Fault,
+    /// The target still answered with WAIT after all retries.
+    Wait,
+    /// The target did not answer, the debug port lost track of the wire protocol.
+    NoAcknowledge,
+}
+
+impl DapError {
+    /// Returns the error described by the sticky error flags of CTRL/STAT.
+    pub fn from_ctrl_stat(ctrl: &Ctrl) -> Self {
+        if ctrl.w_data_err() {
+            DapError::WriteData
+        } else if ctrl.sticky_err() {
+            DapError::Sticky
+        } else if ctrl.sticky_orun() {
+            DapError::Overrun
+        } else {
+            DapError::Fault
+        }
+    }
+}
+
+impl Error for DapError {}
+
+impl fmt::Display for DapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DapError::WriteData => write!(f, "The data of a write was corrupted (WDATAERR)."),
+            DapError::Sticky => write!(f, "An access port transaction failed (STICKYERR)."),
+            DapError::Overrun => write!(f, "A transfer overran the previous one (STICKYORUN)."),
+            DapError::Parity => write!(f, "The parity of the read data was wrong."),
+            DapError::Fault => write!(f, "The target answered with FAULT."),
+            DapError::Wait => write!(f, "The target kept answering with WAIT."),
+            DapError::NoAcknowledge => write!(f, "The target did not answer."),
+        }
+    }
+}
+
+bitflags! {
+    /// The pins of the debug connector, as numbered by the DAP_SWJ_Pins command of CMSIS-DAP.
+    pub struct SwjPins: u8 {
+        const SWCLK_TCK = 1 << 0;
+        const SWDIO_TMS = 1 << 1;
+        const TDI = 1 << 2;
+        const TDO = 1 << 3;
+        const NTRST = 1 << 5;
+        /// The reset of the target, which is active low.
+        const NRESET = 1 << 7;
+    }
+}
+
+bitflags! {
+    /// What probe-rs can use a probe for.
+    pub struct ProbeCapabilities: u8 {
+        const SWD = 1 << 0;
+        const JTAG = 1 << 1;
+        /// Capturing the SWO output of the target.
+        const SWO = 1 << 2;
+    }
+}
+
+impl ProbeCapabilities {
+    /// Returns the names of the capabilities, e.g. `["SWD", "JTAG"]`.
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            (ProbeCapabilities::SWD, "SWD"),
+            (ProbeCapabilities::JTAG, "JTAG"),
+            (ProbeCapabilities::SWO, "SWO"),
+        ]
+        .iter()
+        .filter(|(capability, _)| self.contains(*capability))
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Port {
     DebugPort,
@@ -75,6 +260,14 @@ pub struct MasterProbe {
     actual_probe: Box<dyn DebugProbe>,
     current_apsel: u8,
     current_apbanksel: u8,
+    memory_cache: Option<MemoryCache>,
+    cache_maintenance: Option<CacheMaintenance>,
+    retry_policy: RetryPolicy,
+    connection: Option<ProbeConnection>,
+    architecture: Architecture,
+    /// The TARGETSEL value of the debug port selected on a multi-drop SWD bus.
+    multidrop_target: Option<u32>,
+    cancellation: CancellationToken,
 }
 
 impl MasterProbe {
@@ -83,13 +276,317 @@ impl MasterProbe {
             actual_probe: probe,
             current_apbanksel: 0,
             current_apsel: 0,
+            memory_cache: None,
+            cache_maintenance: None,
+            retry_policy: RetryPolicy::default(),
+            connection: None,
+            architecture: Architecture::Arm,
+            multidrop_target: None,
+            cancellation: CancellationToken::default(),
         }
     }
 
+    /// Sets the architecture of the core, which tells how memory is accessed. Cortex-M
+    /// cores are accessed through the MEM-AP 0, Xtensa cores through their debug module.
+    pub fn set_architecture(&mut self, architecture: Architecture) {
+        self.architecture = architecture;
+    }
+
+    pub fn architecture(&self) -> Architecture {
+        self.architecture
+    }
+
+    /// Returns raw access to the JTAG scan chain, for cores which are not debugged through an
+    /// ARM debug port.
+    pub fn jtag(&mut self) -> Result<&mut dyn JtagAccess, DebugProbeError> {
+        self.actual_probe
+            .jtag_access()
+            .ok_or(DebugProbeError::JTAGNotSupportedOnProbe)
+    }
+
+    /// Caches the memory read within `regions` while the core is halted.
+    ///
+    /// The cores tell the probe when the core halts and resumes with `set_core_halted`.
+    pub fn enable_memory_cache(&mut self, regions: Vec<std::ops::Range<u32>>) {
+        self.memory_cache = Some(MemoryCache::new(regions));
+    }
+
+    /// Sets whether the core is halted, which tells whether memory reads can be cached.
+    pub fn set_core_halted(&mut self, halted: bool) {
+        if let Some(cache) = &mut self.memory_cache {
+            cache.set_halted(halted);
+        }
+    }
+
+    /// Returns the halt generation of the memory cache, which changes whenever the core
+    /// resumes, or `None` if the cache is not enabled.
+    pub fn halt_generation(&self) -> Option<u64> {
+        self.memory_cache.as_ref().map(MemoryCache::generation)
+    }
+
+    /// Serves a read from the memory cache, reading the missing lines into it.
+    ///
+    /// Returns `false` if the memory cannot be cached and has to be read directly.
+    fn read_cached(&mut self, address: u32, data: &mut [u8]) -> Result<bool, AccessPortError> {
+        let missing = match &self.memory_cache {
+            Some(cache) => cache.missing_lines(address, data.len()),
+            None => None,
+        };
+        let missing = match missing {
+            Some(missing) => missing,
+            None => return Ok(false),
+        };
+
+        for line in missing {
+            let mut words = [0u32; LINE_SIZE as usize / 4];
+            match self.architecture {
+                Architecture::Arm => {
+                    ADIMemoryInterface::new(0).read_block32(self, line, &mut words)?
+                }
+                Architecture::Xtensa => {
+                    XtensaMemoryInterface.read_block32(self, line, &mut words)?
+                }
+            }
+
+            let mut bytes = [0u8; LINE_SIZE as usize];
+            for (bytes, word) in bytes.chunks_mut(4).zip(words.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+
+            if let Some(cache) = &mut self.memory_cache {
+                cache.insert(line, bytes);
+            }
+        }
+
+        Ok(self
+            .memory_cache
+            .as_ref()
+            .map_or(false, |cache| cache.read(address, data)))
+    }
+
+    /// Drops the cached memory overlapping a write.
+    fn invalidate_cached(&mut self, address: u32, len: usize) {
+        if let Some(cache) = &mut self.memory_cache {
+            cache.invalidate(address, len);
+        }
+    }
+
+    /// Sets how the caches of the core are kept coherent with the memory written through the
+    /// probe, or disables the maintenance with `None`.
+    pub fn set_cache_maintenance(&mut self, maintenance: Option<CacheMaintenance>) {
+        self.cache_maintenance = maintenance;
+    }
+
+    /// Writes `len` bytes at `address` with `write`, maintaining the caches of the core
+    /// around it if the memory is executable.
+    fn write_maintained(
+        &mut self,
+        address: u32,
+        len: usize,
+        write: impl FnOnce(&mut Self) -> Result<(), AccessPortError>,
+    ) -> Result<(), AccessPortError> {
+        self.invalidate_cached(address, len);
+
+        let maintenance = match &self.cache_maintenance {
+            Some(maintenance) if maintenance.covers(address, len) => maintenance.clone(),
+            _ => return write(self),
+        };
+
+        maintenance.before_write(self, address, len)?;
+        write(self)?;
+        maintenance.after_write(self, address, len)
+    }
+
+    /// Reads halfwords with one 16-bit access each, for peripherals which do not accept other
+    /// access sizes.
+    ///
+    /// Only Cortex-M cores on probes with 16-bit memory commands support it, e.g. ST-Links
+    /// with a recent firmware.
+    pub fn read_block16(&mut self, address: u32, data: &mut [u16]) -> Result<(), DebugProbeError> {
+        if address % 2 != 0 {
+            return Err(DebugProbeError::DataAlignmentError);
+        }
+        if self.architecture != Architecture::Arm {
+            return Err(DebugProbeError::Access16BitNotSupported);
+        }
+
+        self.actual_probe.read_block16(address, data)
+    }
+
+    /// Writes halfwords with one 16-bit access each, see `read_block16`.
+    pub fn write_block16(&mut self, address: u32, data: &[u16]) -> Result<(), DebugProbeError> {
+        if address % 2 != 0 {
+            return Err(DebugProbeError::DataAlignmentError);
+        }
+        if self.architecture != Architecture::Arm {
+            return Err(DebugProbeError::Access16BitNotSupported);
+        }
+
+        self.invalidate_cached(address, data.len() * 2);
+        self.actual_probe.write_block16(address, data)
+    }
+
     pub fn target_reset(&mut self) -> Result<(), DebugProbeError> {
         self.actual_probe.target_reset()
     }
 
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// Sets how often and how long transactions with the target are retried.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        self.actual_probe.set_retry_policy(policy)?;
+        self.retry_policy = policy;
+
+        Ok(())
+    }
+
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Sets the token which aborts the blocking operations of the probe, like waiting for the
+    /// core to halt or for a flash algorithm to finish.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.actual_probe.set_cancellation_token(token.clone());
+        self.cancellation = token;
+    }
+
+    /// Records how the probe was opened and attached, which allows to `reattach`.
+    pub fn set_connection(&mut self, connection: ProbeConnection) {
+        self.multidrop_target = connection.target_sel;
+        self.connection = Some(connection);
+    }
+
+    /// Returns the TARGETSEL value of the selected debug port, if the probe was attached to
+    /// one on a multi-drop SWD bus.
+    pub fn multidrop_target(&self) -> Option<u32> {
+        self.multidrop_target
+    }
+
+    /// Attaches to another debug port on a multi-drop SWD bus, e.g. to debug the second core
+    /// of an RP2040. The target is not reset.
+    pub fn select_multidrop_target(
+        &mut self,
+        target_sel: Option<u32>,
+    ) -> Result<(), DebugProbeError> {
+        self.actual_probe.set_multidrop_target(target_sel)?;
+        self.actual_probe
+            .attach(Some(WireProtocol::Swd), AttachMethod::NoReset)?;
+
+        self.multidrop_target = target_sel;
+        if let Some(connection) = &mut self.connection {
+            connection.target_sel = target_sel;
+        }
+        // The other debug port has its own SELECT register.
+        self.current_apbanksel = 0xFF;
+        // The memory was cached for the core of the other debug port.
+        self.set_core_halted(false);
+
+        Ok(())
+    }
+
+    /// Opens the probe again and attaches to the target with the settings of the connection,
+    /// e.g. after the probe was enumerated again by USB because the target was power cycled.
+    ///
+    /// Waits up to `timeout` for the probe to show up. Returns
+    /// `DebugProbeError::CommandNotSupportedByProbe` if no connection was recorded.
+    pub fn reattach(&mut self, timeout: Duration) -> Result<(), DebugProbeError> {
+        let connection = self
+            .connection
+            .clone()
+            .ok_or(DebugProbeError::CommandNotSupportedByProbe)?;
+
+        let span = tracing::info_span!("probe", reattach = %connection.info);
+        let _enter = span.enter();
+
+        // The old handle has to be closed, the probe can not be opened twice.
+        self.actual_probe = Box::new(FakeProbe::new());
+
+        let start = Instant::now();
+        let mut probe = loop {
+            match reopen_probe(&connection.info) {
+                Ok(probe) => break probe,
+                Err(e) if start.elapsed() > timeout || self.cancellation.is_cancelled() => {
+                    return Err(e)
+                }
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        };
+
+        probe.set_multidrop_target(connection.target_sel)?;
+        probe.set_quirks(&connection.quirks)?;
+        if let Some(speed_khz) = connection.speed_khz {
+            probe.set_speed(speed_khz)?;
+        }
+        probe.set_retry_policy(self.retry_policy)?;
+        probe.set_cancellation_token(self.cancellation.clone());
+        match self.architecture {
+            Architecture::Arm => {
+                probe.attach(Some(connection.protocol), AttachMethod::NoReset)?;
+            }
+            Architecture::Xtensa => probe
+                .jtag_access()
+                .ok_or(DebugProbeError::JTAGNotSupportedOnProbe)?
+                .attach_jtag()?,
+        }
+
+        self.actual_probe = probe;
+        // No bank has this number, so the next access writes SELECT again.
+        self.current_apbanksel = 0xFF;
+        // The target may have been power cycled, so nothing cached is valid anymore.
+        self.set_core_halted(false);
+
+        Ok(())
+    }
+
+    /// Leaves debug mode on the probe.
+    pub fn detach(&mut self) -> Result<(), DebugProbeError> {
+        let span = tracing::info_span!("probe", detach = self.actual_probe.get_name());
+        let _enter = span.enter();
+
+        self.actual_probe.detach()
+    }
+
+    pub fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError> {
+        self.actual_probe.get_target_voltage()
+    }
+
+    pub fn enable_swo(&mut self, baud_rate: u32) -> Result<(), DebugProbeError> {
+        self.actual_probe.enable_swo(baud_rate)
+    }
+
+    pub fn disable_swo(&mut self) -> Result<(), DebugProbeError> {
+        self.actual_probe.disable_swo()
+    }
+
+    pub fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        self.actual_probe.read_swo()
+    }
+
+    pub fn swj_pins(
+        &mut self,
+        output: SwjPins,
+        select: SwjPins,
+        wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError> {
+        self.actual_probe.swj_pins(output, select, wait_us)
+    }
+
+    /// Holds the target in reset by driving nRESET low, or releases it.
+    pub fn set_reset_asserted(&mut self, asserted: bool) -> Result<(), DebugProbeError> {
+        let output = if asserted {
+            SwjPins::empty()
+        } else {
+            SwjPins::NRESET
+        };
+
+        self.swj_pins(output, SwjPins::NRESET, 0)?;
+
+        Ok(())
+    }
+
     fn select_ap_and_ap_bank(&mut self, port: u8, ap_bank: u8) -> Result<(), DebugProbeError> {
         let mut cache_changed = if self.current_apsel != port {
             self.current_apsel = port;
@@ -126,6 +623,20 @@ impl MasterProbe {
         Ok(())
     }
 
+    /// Forgets the selected AP bank if the probe initialized the debug port again after the
+    /// access of `result` failed, as that resets SELECT.
+    fn check_reinitialized<T>(
+        &mut self,
+        result: Result<T, DebugProbeError>,
+    ) -> Result<T, DebugProbeError> {
+        if let Err(DebugProbeError::Dap(DapError::NoAcknowledge)) = result {
+            // No bank has this number, so the next access writes SELECT again.
+            self.current_apbanksel = 0xFF;
+        }
+
+        result
+    }
+
     fn write_register_ap<AP, REGISTER>(
         &mut self,
         port: AP,
@@ -137,6 +648,13 @@ impl MasterProbe {
     {
         let register_value = register.into();
 
+        let span = tracing::trace_span!(
+            "dap",
+            ap = port.get_port_number(),
+            register = REGISTER::NAME
+        );
+        let _enter = span.enter();
+
         debug!(
             "Writing register {}, value=0x{:08X}",
             REGISTER::NAME,
@@ -145,13 +663,12 @@ impl MasterProbe {
 
         self.select_ap_and_ap_bank(port.get_port_number(), REGISTER::APBANKSEL)?;
 
-        let link = &mut self.actual_probe;
-        link.write_register(
+        let result = self.actual_probe.write_register(
             Port::AccessPort(u16::from(self.current_apsel)),
             u16::from(REGISTER::ADDRESS),
             register_value,
-        )?;
-        Ok(())
+        );
+        self.check_reinitialized(result)
     }
 
     fn read_register_ap<AP, REGISTER>(
@@ -163,15 +680,21 @@ impl MasterProbe {
         AP: AccessPort,
         REGISTER: APRegister<AP>,
     {
+        let span = tracing::trace_span!(
+            "dap",
+            ap = port.get_port_number(),
+            register = REGISTER::NAME
+        );
+        let _enter = span.enter();
+
         debug!("Reading register {}", REGISTER::NAME);
         self.select_ap_and_ap_bank(port.get_port_number(), REGISTER::APBANKSEL)?;
 
-        let link = &mut self.actual_probe;
-        //println!("{:?}, {:08X}", link.current_apsel, REGISTER::ADDRESS);
-        let result = link.read_register(
+        let result = self.actual_probe.read_register(
             Port::AccessPort(u16::from(self.current_apsel)),
             u16::from(REGISTER::ADDRESS),
-        )?;
+        );
+        let result = self.check_reinitialized(result)?;
 
         debug!(
             "Read register    {}, value=0x{:08x}",
@@ -182,13 +705,67 @@ impl MasterProbe {
         Ok(REGISTER::from(result))
     }
 
+    /// Reads a raw AP register of the AP `port`.
+    ///
+    /// `addr` is the full register address, the bank is selected automatically.
+    pub fn read_register_ap_raw(&mut self, port: u8, addr: u8) -> Result<u32, DebugProbeError> {
+        let span = tracing::trace_span!("dap", ap = port, address = addr);
+        let _enter = span.enter();
+
+        self.select_ap_and_ap_bank(port, addr >> 4)?;
+
+        let result = self
+            .actual_probe
+            .read_register(Port::AccessPort(u16::from(port)), u16::from(addr));
+        self.check_reinitialized(result)
+    }
+
+    /// Writes a raw AP register of the AP `port`.
+    ///
+    /// `addr` is the full register address, the bank is selected automatically.
+    pub fn write_register_ap_raw(
+        &mut self,
+        port: u8,
+        addr: u8,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        let span = tracing::trace_span!("dap", ap = port, address = addr);
+        let _enter = span.enter();
+
+        self.select_ap_and_ap_bank(port, addr >> 4)?;
+
+        let result = self.actual_probe.write_register(
+            Port::AccessPort(u16::from(port)),
+            u16::from(addr),
+            value,
+        );
+        self.check_reinitialized(result)
+    }
+
     pub fn read_register_dp(&mut self, offset: u16) -> Result<u32, DebugProbeError> {
+        let span = tracing::trace_span!("dap", dp_address = offset);
+        let _enter = span.enter();
+
         self.actual_probe.read_register(Port::DebugPort, offset)
     }
 
     pub fn write_register_dp(&mut self, offset: u16, val: u32) -> Result<(), DebugProbeError> {
+        use crate::coresight::debug_port::Select;
+
+        let span = tracing::trace_span!("dap", dp_address = offset);
+        let _enter = span.enter();
+
         self.actual_probe
-            .write_register(Port::DebugPort, offset, val)
+            .write_register(Port::DebugPort, offset, val)?;
+
+        // Keep the cached AP selection in sync if SELECT was written directly.
+        if offset == u16::from(Select::ADDRESS) {
+            let select = Select(val);
+            self.current_apsel = select.ap_sel();
+            self.current_apbanksel = select.ap_bank_sel();
+        }
+
+        Ok(())
     }
 }
 
@@ -241,35 +818,81 @@ where
 
 impl MI for MasterProbe {
     fn read32(&mut self, address: u32) -> Result<u32, AccessPortError> {
-        ADIMemoryInterface::new(0).read32(self, address)
+        let mut bytes = [0u8; 4];
+        if address % 4 == 0 && self.read_cached(address, &mut bytes)? {
+            return Ok(u32::from_le_bytes(bytes));
+        }
+
+        match self.architecture {
+            Architecture::Arm => ADIMemoryInterface::new(0).read32(self, address),
+            Architecture::Xtensa => XtensaMemoryInterface.read32(self, address),
+        }
     }
 
     fn read8(&mut self, address: u32) -> Result<u8, AccessPortError> {
-        ADIMemoryInterface::new(0).read8(self, address)
+        let mut byte = [0u8; 1];
+        if self.read_cached(address, &mut byte)? {
+            return Ok(byte[0]);
+        }
+
+        match self.architecture {
+            Architecture::Arm => ADIMemoryInterface::new(0).read8(self, address),
+            Architecture::Xtensa => XtensaMemoryInterface.read8(self, address),
+        }
     }
 
     fn read_block32(&mut self, address: u32, data: &mut [u32]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).read_block32(self, address, data)
+        let mut bytes = vec![0u8; data.len() * 4];
+        if address % 4 == 0 && self.read_cached(address, &mut bytes)? {
+            for (word, bytes) in data.iter_mut().zip(bytes.chunks(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            return Ok(());
+        }
+
+        match self.architecture {
+            Architecture::Arm => ADIMemoryInterface::new(0).read_block32(self, address, data),
+            Architecture::Xtensa => XtensaMemoryInterface.read_block32(self, address, data),
+        }
     }
 
     fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).read_block8(self, address, data)
+        if self.read_cached(address, data)? {
+            return Ok(());
+        }
+
+        match self.architecture {
+            Architecture::Arm => ADIMemoryInterface::new(0).read_block8(self, address, data),
+            Architecture::Xtensa => XtensaMemoryInterface.read_block8(self, address, data),
+        }
     }
 
     fn write32(&mut self, addr: u32, data: u32) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write32(self, addr, data)
+        self.write_maintained(addr, 4, |probe| match probe.architecture {
+            Architecture::Arm => ADIMemoryInterface::new(0).write32(probe, addr, data),
+            Architecture::Xtensa => XtensaMemoryInterface.write32(probe, addr, data),
+        })
     }
 
     fn write8(&mut self, addr: u32, data: u8) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write8(self, addr, data)
+        self.write_maintained(addr, 1, |probe| match probe.architecture {
+            Architecture::Arm => ADIMemoryInterface::new(0).write8(probe, addr, data),
+            Architecture::Xtensa => XtensaMemoryInterface.write8(probe, addr, data),
+        })
     }
 
     fn write_block32(&mut self, addr: u32, data: &[u32]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write_block32(self, addr, data)
+        self.write_maintained(addr, data.len() * 4, |probe| match probe.architecture {
+            Architecture::Arm => ADIMemoryInterface::new(0).write_block32(probe, addr, data),
+            Architecture::Xtensa => XtensaMemoryInterface.write_block32(probe, addr, data),
+        })
     }
 
     fn write_block8(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write_block8(self, addr, data)
+        self.write_maintained(addr, data.len(), |probe| match probe.architecture {
+            Architecture::Arm => ADIMemoryInterface::new(0).write_block8(probe, addr, data),
+            Architecture::Xtensa => XtensaMemoryInterface.write_block8(probe, addr, data),
+        })
     }
 }
 
@@ -282,43 +905,197 @@ pub trait DebugProbe: DAPAccess {
     fn get_name(&self) -> &str;
 
     /// Enters debug mode
-    fn attach(&mut self, protocol: Option<WireProtocol>) -> Result<WireProtocol, DebugProbeError>;
+    ///
+    /// With `AttachMethod::NoReset` the probe must neither drive nRESET nor halt the core.
+    fn attach(
+        &mut self,
+        protocol: Option<WireProtocol>,
+        method: AttachMethod,
+    ) -> Result<WireProtocol, DebugProbeError>;
 
     /// Leave debug mode
     fn detach(&mut self) -> Result<(), DebugProbeError>;
 
     /// Resets the target device.
     fn target_reset(&mut self) -> Result<(), DebugProbeError>;
+
+    /// Selects the target on a multi-drop SWD bus by its TARGETSEL value.
+    ///
+    /// Has to be called before `attach`. `None` disables multi-drop selection.
+    fn set_multidrop_target(&mut self, target_sel: Option<u32>) -> Result<(), DebugProbeError>;
+
+    /// Sets the quirks of the debug port of the target, which `attach` works around.
+    ///
+    /// Has to be called before `attach`. Probes which initialize the debug port in their
+    /// firmware ignore them.
+    fn set_quirks(&mut self, quirks: &[Quirk]) -> Result<(), DebugProbeError>;
+
+    /// Sets how often and how long transfers are retried.
+    ///
+    /// Can be called before or after `attach`, it applies to all following transfers.
+    fn set_retry_policy(&mut self, policy: RetryPolicy) -> Result<(), DebugProbeError>;
+
+    /// Sets the token which aborts the reads waiting for a probe which does not answer.
+    ///
+    /// Probes whose transfers time out on their own ignore it.
+    fn set_cancellation_token(&mut self, token: CancellationToken);
+
+    /// Sets the clock of the wire protocol in kHz.
+    ///
+    /// Has to be called before `attach`. Probes only support some speeds, so the fastest
+    /// supported speed not above `speed_khz` is used and returned.
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError>;
+
+    /// Measures the target supply voltage in volts.
+    ///
+    /// Returns `None` if the probe is not able to measure it.
+    fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError>;
+
+    /// Starts capturing the UART encoded SWO output of the target at `baud_rate`.
+    ///
+    /// Returns `DebugProbeError::CommandNotSupportedByProbe` if the probe cannot capture SWO.
+    fn enable_swo(&mut self, baud_rate: u32) -> Result<(), DebugProbeError>;
+
+    /// Stops capturing SWO data.
+    fn disable_swo(&mut self) -> Result<(), DebugProbeError>;
+
+    /// Reads the SWO data captured since the last call.
+    fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError>;
+
+    /// Drives the pins in `select` to the levels in `output`, waits up to `wait_us`
+    /// microseconds for them to settle, and returns the levels of all pins.
+    ///
+    /// Returns `DebugProbeError::CommandNotSupportedByProbe` if the probe cannot drive one of
+    /// the selected pins.
+    fn swj_pins(
+        &mut self,
+        output: SwjPins,
+        select: SwjPins,
+        wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError>;
+
+    /// Returns raw access to the JTAG scan chain, or `None` if the probe only supports ARM
+    /// debug ports.
+    fn jtag_access(&mut self) -> Option<&mut dyn JtagAccess>;
+
+    /// Reads halfwords through the MEM-AP 0 with the 16-bit memory commands of the probe.
+    ///
+    /// Returns `DebugProbeError::Access16BitNotSupported` if the probe has no such commands.
+    fn read_block16(&mut self, address: u32, data: &mut [u16]) -> Result<(), DebugProbeError>;
+
+    /// Writes halfwords through the MEM-AP 0 with the 16-bit memory commands of the probe.
+    ///
+    /// Returns `DebugProbeError::Access16BitNotSupported` if the probe has no such commands.
+    fn write_block16(&mut self, address: u32, data: &[u16]) -> Result<(), DebugProbeError>;
 }
 
 #[derive(Debug, Clone)]
 pub enum DebugProbeType {
     DAPLink,
     STLink,
+    BlackMagic,
+    /// The GPIO pins of the host, driving SWD bit by bit.
+    Gpio,
+    /// A probe of the driver with the given name, see `probe::registry`.
+    Registered(&'static str),
+}
+
+impl DebugProbeType {
+    pub fn capabilities(&self) -> ProbeCapabilities {
+        match self {
+            // SWO capture needs a probe with UART SWO support, which is checked when it starts.
+            DebugProbeType::DAPLink => ProbeCapabilities::all(),
+            DebugProbeType::STLink => ProbeCapabilities::all(),
+            // The probe captures SWO itself, but not through the GDB server.
+            DebugProbeType::BlackMagic => ProbeCapabilities::SWD | ProbeCapabilities::JTAG,
+            DebugProbeType::Gpio => ProbeCapabilities::SWD,
+            // Registered drivers set the capabilities of each probe.
+            DebugProbeType::Registered(_) => ProbeCapabilities::empty(),
+        }
+    }
+}
+
+impl fmt::Display for DebugProbeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DebugProbeType::DAPLink => write!(f, "CMSIS-DAP"),
+            DebugProbeType::STLink => write!(f, "ST-Link"),
+            DebugProbeType::BlackMagic => write!(f, "Black Magic Probe"),
+            DebugProbeType::Gpio => write!(f, "GPIO"),
+            DebugProbeType::Registered(name) => f.write_str(name),
+        }
+    }
+}
+
+/// How a probe was opened and attached to the target.
+#[derive(Debug, Clone)]
+pub struct ProbeConnection {
+    pub info: DebugProbeInfo,
+    pub protocol: WireProtocol,
+    /// The speed set with `DebugProbe::set_speed` in kHz, if any.
+    pub speed_khz: Option<u32>,
+    pub target_sel: Option<u32>,
+    pub quirks: Vec<Quirk>,
 }
 
-#[derive(Clone)]
+/// Enumerates the connected probes and opens the one described by `info`. Its serial number
+/// tells it apart from other probes of the same type, if it has one.
+fn reopen_probe(info: &DebugProbeInfo) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+    let device = watch::list_probes()
+        .into_iter()
+        .find(|device| {
+            device.vendor_id == info.vendor_id
+                && device.product_id == info.product_id
+                && (info.serial_number.is_none() || device.serial_number == info.serial_number)
+        })
+        .ok_or(DebugProbeError::ProbeCouldNotBeCreated)?;
+
+    open_probe(&device)
+}
+
+/// Opens the probe found when enumerating the connected probes with the driver of its type.
+///
+/// Fails with `DebugProbeError::ProbeCouldNotBeCreated` for probes whose driver is disabled
+/// by the features of the crate or not registered.
+pub fn open_probe(device: &DebugProbeInfo) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+    let probe: Box<dyn DebugProbe> = match device.probe_type {
+        #[cfg(feature = "daplink")]
+        DebugProbeType::DAPLink => daplink::DAPLink::new_from_probe_info(device)?,
+        #[cfg(feature = "stlink")]
+        DebugProbeType::STLink => stlink::STLink::new_from_probe_info(device)?,
+        #[cfg(feature = "serialport")]
+        DebugProbeType::BlackMagic => blackmagic::BlackMagicProbe::new_from_probe_info(device)?,
+        DebugProbeType::Gpio => gpio::GpioProbe::new_from_probe_info(device)?,
+        DebugProbeType::Registered(name) => registry::open_probe(name, device)?,
+        #[allow(unreachable_patterns)]
+        _ => return Err(DebugProbeError::ProbeCouldNotBeCreated),
+    };
+
+    Ok(probe)
+}
+
+/// A probe found when enumerating the connected probes.
+#[derive(Debug, Clone)]
 pub struct DebugProbeInfo {
+    /// The name of the probe, usually its USB product string.
     pub identifier: String,
     pub vendor_id: u16,
     pub product_id: u16,
     pub serial_number: Option<String>,
     pub probe_type: DebugProbeType,
+    /// The firmware version as reported in the USB device descriptor, for probes which
+    /// report it there.
+    pub firmware_version: Option<String>,
+    pub capabilities: ProbeCapabilities,
 }
 
-impl std::fmt::Debug for DebugProbeInfo {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "{} (VID: {}, PID: {}, {}{:?})",
-            self.identifier,
-            self.vendor_id,
-            self.product_id,
-            self.serial_number
-                .clone()
-                .map_or("".to_owned(), |v| format!("Serial: {},", v)),
-            self.probe_type
-        )
+impl fmt::Display for DebugProbeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}", self.identifier, self.stable_id())?;
+        if let Some(firmware_version) = &self.firmware_version {
+            write!(f, ", firmware {}", firmware_version)?;
+        }
+        write!(f, ") [{}]", self.capabilities.names().join(", "))
     }
 }
 
@@ -329,13 +1106,28 @@ impl DebugProbeInfo {
         product_id: u16,
         serial_number: Option<String>,
         probe_type: DebugProbeType,
+        firmware_version: Option<String>,
     ) -> Self {
         Self {
             identifier: identifier.into(),
             vendor_id,
             product_id,
             serial_number,
+            capabilities: probe_type.capabilities(),
             probe_type,
+            firmware_version,
+        }
+    }
+
+    /// Returns an identifier of the probe which is the same on all platforms and USB ports,
+    /// `VID:PID:SERIAL` in hex, or `VID:PID` for probes without a serial number.
+    pub fn stable_id(&self) -> String {
+        match &self.serial_number {
+            Some(serial_number) => format!(
+                "{:04x}:{:04x}:{}",
+                self.vendor_id, self.product_id, serial_number
+            ),
+            None => format!("{:04x}:{:04x}", self.vendor_id, self.product_id),
         }
     }
 }
@@ -363,7 +1155,11 @@ impl DebugProbe for FakeProbe {
     }
 
     /// Enters debug mode
-    fn attach(&mut self, protocol: Option<WireProtocol>) -> Result<WireProtocol, DebugProbeError> {
+    fn attach(
+        &mut self,
+        protocol: Option<WireProtocol>,
+        _method: AttachMethod,
+    ) -> Result<WireProtocol, DebugProbeError> {
         // attaching always work for the fake probe
         Ok(protocol.unwrap_or(WireProtocol::Swd))
     }
@@ -377,6 +1173,62 @@ impl DebugProbe for FakeProbe {
     fn target_reset(&mut self) -> Result<(), DebugProbeError> {
         Err(DebugProbeError::UnknownError)
     }
+
+    fn set_multidrop_target(&mut self, _target_sel: Option<u32>) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn set_quirks(&mut self, _quirks: &[Quirk]) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn set_retry_policy(&mut self, _policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn set_cancellation_token(&mut self, _token: CancellationToken) {}
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        Ok(speed_khz)
+    }
+
+    /// The fake probe has no target to measure.
+    fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError> {
+        Ok(None)
+    }
+
+    fn enable_swo(&mut self, _baud_rate: u32) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn disable_swo(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn swj_pins(
+        &mut self,
+        _output: SwjPins,
+        _select: SwjPins,
+        _wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn jtag_access(&mut self) -> Option<&mut dyn JtagAccess> {
+        None
+    }
+
+    fn read_block16(&mut self, _address: u32, _data: &mut [u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+
+    fn write_block16(&mut self, _address: u32, _data: &[u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
 }
 
 impl DAPAccess for FakeProbe {
@@ -395,3 +1247,32 @@ impl DAPAccess for FakeProbe {
         Err(DebugProbeError::UnknownError)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::DapError;
+    use crate::coresight::debug_port::Ctrl;
+
+    #[test]
+    fn dap_error_from_ctrl_stat() {
+        // STICKYERR and STICKYORUN
+        assert_eq!(
+            DapError::from_ctrl_stat(&Ctrl::from(0x22)),
+            DapError::Sticky
+        );
+        // WDATAERR comes first, it means that the wire itself is unreliable.
+        assert_eq!(
+            DapError::from_ctrl_stat(&Ctrl::from(0xA0)),
+            DapError::WriteData
+        );
+        assert_eq!(
+            DapError::from_ctrl_stat(&Ctrl::from(0x2)),
+            DapError::Overrun
+        );
+        // Only the power up acknowledges
+        assert_eq!(
+            DapError::from_ctrl_stat(&Ctrl::from(0xA000_0000)),
+            DapError::Fault
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! What went wrong when a routine of a flash algorithm did not return.
+//!
+//! A flash algorithm is code from a target description running on the target, with nothing
+//! keeping it from looping forever or faulting. The flasher gives each routine a timeout and
+//! catches the HardFault, and on failure captures the state of the core into a
+//! `FlashAlgorithmFault`, which tells the author of the target description where the routine
+//! got stuck.
+
+use super::flasher::FlashAlgorithm;
+use crate::collection::cores::fault::FaultStatus;
+use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+use crate::target::{Architecture, Core};
+
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// At most this many words of the stack are captured.
+const STACK_WORDS: u32 = 32;
+
+/// The routines of a flash algorithm, named like the functions of a CMSIS flash algorithm.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Routine {
+    Init,
+    Uninit,
+    EraseAll,
+    EraseSector,
+    ProgramPage,
+    /// The CRC analyzer, which the flasher loads next to the algorithm.
+    Analyzer,
+}
+
+impl Routine {
+    /// How long the routine may run before it is considered stuck. Erasing the whole flash of
+    /// a large chip takes minutes.
+    pub fn timeout(self) -> Duration {
+        match self {
+            Routine::Init | Routine::Uninit | Routine::ProgramPage => Duration::from_secs(5),
+            Routine::Analyzer => Duration::from_secs(10),
+            Routine::EraseSector => Duration::from_secs(20),
+            Routine::EraseAll => Duration::from_secs(300),
+        }
+    }
+}
+
+impl fmt::Display for Routine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Routine::Init => "Init()",
+            Routine::Uninit => "UnInit()",
+            Routine::EraseAll => "EraseChip()",
+            Routine::EraseSector => "EraseSector()",
+            Routine::ProgramPage => "ProgramPage()",
+            Routine::Analyzer => "The CRC analyzer",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Why a routine did not return.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FaultReason {
+    /// The routine was still running when its timeout ran out.
+    Timeout(Duration),
+    /// The routine faulted, and the core halted at the entry of the HardFault handler.
+    HardFault,
+    /// The routine stopped updating its progress word for the given time.
+    Stalled(Duration),
+    /// The core locked up, e.g. because the vector table it faulted with was erased.
+    LockedUp,
+    /// The core halted somewhere else than at the return address, e.g. at a breakpoint.
+    UnexpectedHalt,
+}
+
+impl fmt::Display for FaultReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultReason::Timeout(timeout) => write!(f, "did not return within {:?}", timeout),
+            FaultReason::Stalled(timeout) => {
+                write!(f, "stopped reporting progress for {:?}", timeout)
+            }
+            FaultReason::HardFault => f.write_str("faulted"),
+            FaultReason::LockedUp => f.write_str("locked up the core"),
+            FaultReason::UnexpectedHalt => f.write_str("halted before returning"),
+        }
+    }
+}
+
+/// The state of the core after a routine of the flash algorithm failed, halted for reading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlashAlgorithmFault {
+    pub routine: Routine,
+    pub reason: FaultReason,
+    /// The registers passed to and used by the routines, with their names.
+    pub registers: Vec<(&'static str, u32)>,
+    /// The stack pointer, and the words on the stack up to the top of the algorithm's stack.
+    pub stack_pointer: u32,
+    pub stack: Vec<u32>,
+    /// The fault status of Cortex-M cores. Baseline cores report no causes.
+    pub fault: Option<FaultStatus>,
+}
+
+impl FlashAlgorithmFault {
+    /// Halts the core and reads its state.
+    pub(crate) fn capture(
+        core: &dyn Core,
+        probe: &mut MasterProbe,
+        algorithm: &FlashAlgorithm,
+        routine: Routine,
+        reason: FaultReason,
+    ) -> Result<Self, DebugProbeError> {
+        core.halt(probe)?;
+
+        let addresses = core.registers();
+        let mut registers = vec![];
+        for (name, address) in &[
+            ("R0", addresses.R0),
+            ("R1", addresses.R1),
+            ("R2", addresses.R2),
+            ("R3", addresses.R3),
+            ("R4", addresses.R4),
+            ("R9", addresses.R9),
+            ("SP", addresses.SP),
+            ("LR", addresses.LR),
+            ("PC", addresses.PC),
+            ("XPSR", addresses.XPSR),
+        ] {
+            registers.push((*name, core.read_core_reg(probe, *address)?));
+        }
+
+        // A stack pointer outside of the algorithm's stack is only reported.
+        let stack_pointer = core.read_core_reg(probe, addresses.SP)?;
+        let stack_top = algorithm.begin_stack;
+        let stack = if stack_pointer % 4 == 0 && stack_pointer < stack_top {
+            let words = ((stack_top - stack_pointer) / 4).min(STACK_WORDS);
+            let mut stack = vec![0; words as usize];
+            probe.read_block32(stack_pointer, &mut stack)?;
+            stack
+        } else {
+            vec![]
+        };
+
+        let fault = match core.architecture() {
+            Architecture::Arm => Some(FaultStatus::read(probe)?),
+            Architecture::Xtensa => None,
+        };
+
+        Ok(FlashAlgorithmFault {
+            routine,
+            reason,
+            registers,
+            stack_pointer,
+            stack,
+            fault,
+        })
+    }
+}
+
+impl Error for FlashAlgorithmFault {}
+
+impl fmt::Display for FlashAlgorithmFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of the flash algorithm {}.",
+            self.routine, self.reason
+        )?;
+
+        if let Some(fault) = &self.fault {
+            write!(f, "\n  Fault: {}", fault)?;
+        }
+
+        for registers in self.registers.chunks(5) {
+            f.write_str("\n ")?;
+            for (name, value) in registers {
+                write!(f, " {:>4}=0x{:08x}", name, value)?;
+            }
+        }
+
+        if !self.stack.is_empty() {
+            write!(f, "\n  Stack at 0x{:08x}:", self.stack_pointer)?;
+            for (index, word) in self.stack.iter().enumerate() {
+                if index % 8 == 0 {
+                    f.write_str("\n   ")?;
+                }
+                write!(f, " {:08x}", word)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FaultReason, FlashAlgorithmFault, Routine};
+    use crate::collection::cores::fault::FaultStatus;
+
+    use std::time::Duration;
+
+    #[test]
+    fn shows_registers_and_stack() {
+        let fault = FlashAlgorithmFault {
+            routine: Routine::EraseSector,
+            reason: FaultReason::HardFault,
+            registers: vec![("R0", 0x0800_4000), ("PC", 0x2000_0124)],
+            stack_pointer: 0x2000_0ff8,
+            stack: vec![0x1, 0x2000_0041],
+            fault: Some(FaultStatus {
+                cfsr: 1 << 16,
+                hfsr: 1 << 30,
+                address: None,
+            }),
+        };
+
+        assert_eq!(
+            fault.to_string(),
+            "EraseSector() of the flash algorithm faulted.\n  \
+             Fault: fault escalated to a HardFault (FORCED), undefined instruction (UNDEFINSTR)\n  \
+             \x20 R0=0x08004000   PC=0x20000124\n  \
+             Stack at 0x20000ff8:\n    \
+             00000001 20000041"
+        );
+
+        let timeout = FaultReason::Timeout(Duration::from_secs(5));
+        assert_eq!(timeout.to_string(), "did not return within 5s");
+    }
+}
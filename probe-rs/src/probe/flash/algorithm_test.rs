@@ -0,0 +1,182 @@
+//! Self-test of a flash algorithm, which runs all of its functions against a scratch sector
+//! and checks the results. Useful when writing the flash algorithm of a new target.
+
+use super::*;
+use crate::probe::debug_probe::MasterProbe;
+use crate::target::Target;
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A data pattern programmed during the test.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TestPattern {
+    Zeroes,
+    /// Alternating 0x55 and 0xAA bytes, which toggle every bit between neighbouring bytes.
+    Alternating,
+    /// The offset of each byte, which catches pages written to the wrong address.
+    Incrementing,
+}
+
+impl TestPattern {
+    pub const ALL: [TestPattern; 3] = [
+        TestPattern::Zeroes,
+        TestPattern::Alternating,
+        TestPattern::Incrementing,
+    ];
+
+    /// Returns `length` bytes of the pattern.
+    pub fn data(self, length: usize) -> Vec<u8> {
+        (0..length)
+            .map(|offset| match self {
+                TestPattern::Zeroes => 0,
+                TestPattern::Alternating if offset % 2 == 0 => 0x55,
+                TestPattern::Alternating => 0xAA,
+                TestPattern::Incrementing => offset as u8,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for TestPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TestPattern::Zeroes => "zeroes",
+            TestPattern::Alternating => "0x55/0xAA",
+            TestPattern::Incrementing => "incrementing",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// The results of erasing the sector and programming one pattern into it.
+#[derive(Debug)]
+pub struct PatternResult {
+    pub pattern: TestPattern,
+    pub erase_time: Duration,
+    /// Whether the sector read back as erased after the erase.
+    pub erased: bool,
+    pub program_time: Duration,
+    /// The address of the first byte which read back differently than it was programmed.
+    pub first_mismatch: Option<u32>,
+    /// Whether the CRC computed by the analyzer on the target matched the pattern,
+    /// `None` if the algorithm does not support the analyzer.
+    pub crc_matches: Option<bool>,
+}
+
+impl PatternResult {
+    pub fn passed(&self) -> bool {
+        self.erased && self.first_mismatch.is_none() && self.crc_matches != Some(false)
+    }
+}
+
+#[derive(Debug)]
+pub struct AlgorithmTestReport {
+    pub sector_address: u32,
+    pub sector_size: u32,
+    /// The time to load the algorithm into RAM and run its `Init()` function.
+    pub init_time: Duration,
+    pub uninit_time: Duration,
+    pub patterns: Vec<PatternResult>,
+}
+
+impl AlgorithmTestReport {
+    pub fn passed(&self) -> bool {
+        self.patterns.iter().all(PatternResult::passed)
+    }
+}
+
+/// Tests `algorithm` on the sector of `region` which contains `address`.
+///
+/// Each pattern of `TestPattern::ALL` is programmed after erasing the sector, and read back
+/// afterwards. The sector is left erased, so its previous contents are lost.
+pub fn test_algorithm(
+    target: &Target,
+    probe: &mut MasterProbe,
+    algorithm: &FlashAlgorithm,
+    region: &FlashRegion,
+    address: u32,
+) -> Result<AlgorithmTestReport, FlasherError> {
+    let sector = region
+        .get_sector_info(address)
+        .ok_or_else(|| FlasherError::AddressNotInRegion(address, region.clone()))?;
+
+    let mut flasher = Flasher::new(target, probe, algorithm, region);
+
+    let start = Instant::now();
+    let mut active: ActiveFlasher<Erase> = flasher.init(None, None)?;
+    let init_time = start.elapsed();
+
+    let start = Instant::now();
+    active.uninit()?;
+    let uninit_time = start.elapsed();
+
+    let mut patterns = vec![];
+
+    for pattern in &TestPattern::ALL {
+        let (erase_time, erased) = flasher.run_erase(|active| -> Result<_, FlasherError> {
+            let start = Instant::now();
+            active.erase_sector(sector.base_address)?;
+            let erase_time = start.elapsed();
+
+            let mut data = vec![0; sector.size as usize];
+            active.read_block8(sector.base_address, &mut data)?;
+
+            Ok((erase_time, region.is_erased(&data)))
+        })?;
+
+        let data = pattern.data(sector.size as usize);
+
+        let program_time = flasher.run_program(|active| -> Result<_, FlasherError> {
+            let start = Instant::now();
+
+            for (i, page) in data.chunks(region.page_size as usize).enumerate() {
+                active.program_page(sector.base_address + i as u32 * region.page_size, page)?;
+            }
+
+            Ok(start.elapsed())
+        })?;
+
+        let mut read_back = vec![0; data.len()];
+        flasher.run_verify(|active| active.read_block8(sector.base_address, &mut read_back))?;
+
+        let first_mismatch = data
+            .iter()
+            .zip(&read_back)
+            .position(|(expected, actual)| expected != actual)
+            .map(|offset| sector.base_address + offset as u32);
+
+        let crc_matches = if algorithm.analyzer_supported {
+            match flasher
+                .run_erase(|active| active.compute_crcs(&[(sector.base_address, sector.size)]))
+            {
+                Ok(crcs) => Some(crcs.first() == Some(&crc::crc32::checksum_ieee(&data))),
+                // The analyzer only handles sectors with a size that is a power of 2.
+                Err(FlasherError::SizeNotPowerOf2) => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+
+        patterns.push(PatternResult {
+            pattern: *pattern,
+            erase_time,
+            erased,
+            program_time,
+            first_mismatch,
+            crc_matches,
+        });
+    }
+
+    flasher.run_erase(|active| active.erase_sector(sector.base_address))?;
+
+    Ok(AlgorithmTestReport {
+        sector_address: sector.base_address,
+        sector_size: sector.size,
+        init_time,
+        uninit_time,
+        patterns,
+    })
+}
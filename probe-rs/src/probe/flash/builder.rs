@@ -1,5 +1,7 @@
 use super::*;
+use crate::target::quirks::Quirk;
 use std::mem::swap;
+use std::time::Instant;
 
 const PAGE_ESTIMATE_SIZE: u32 = 32;
 const _PAGE_READ_WEIGHT: f32 = 0.3;
@@ -215,6 +217,8 @@ impl<'a> FlashBuilder<'a> {
     /// Determine fastest method of flashing and then run flash programming.
     ///
     /// Data must have already been added with add_data
+    /// `sector_programmed` is called with the address of each sector once it holds its new
+    /// contents.
     /// TODO: Not sure if this works as intended ...
     pub fn program(
         &self,
@@ -223,7 +227,8 @@ impl<'a> FlashBuilder<'a> {
         smart_flash: bool,
         fast_verify: bool,
         keep_unwritten: bool,
-    ) -> Result<(), FlashBuilderError> {
+        sector_programmed: &mut dyn FnMut(u32),
+    ) -> Result<FlashReport, FlashBuilderError> {
         // Disable smart options if attempting to read erased sectors will fail.
         let (smart_flash, fast_verify, keep_unwritten) =
             if !flash.region().are_erased_sectors_readable {
@@ -232,18 +237,21 @@ impl<'a> FlashBuilder<'a> {
                 (smart_flash, fast_verify, keep_unwritten)
             };
 
+        let mut report = FlashReport::default();
+
         if self.flash_operations.is_empty() {
             // Nothing to do.
-            return Ok(());
+            return Ok(report);
         }
 
+        let analysis_start = Instant::now();
         let mut sectors = vec![];
 
         // Convert the list of flash operations into flash sectors and pages.
         self.build_sectors_and_pages(&mut flash, &mut sectors, keep_unwritten)?;
         if sectors.is_empty() || sectors[0].pages.is_empty() {
             // Nothing to do.
-            return Ok(());
+            return Ok(report);
         }
 
         log::debug!("Smart Flash enabled: {:?}", smart_flash);
@@ -253,7 +261,10 @@ impl<'a> FlashBuilder<'a> {
         }
 
         // If the flash algo doesn't support erase all, disable chip erase.
-        if flash.flash_algorithm().pc_erase_all.is_none() {
+        // Chips which erase all sectors one by one instead do not benefit from it either.
+        if flash.flash_algorithm().pc_erase_all.is_none()
+            || flash.target().has_quirk(Quirk::NoChipErase)
+        {
             chip_erase = Some(false);
         }
 
@@ -276,25 +287,35 @@ impl<'a> FlashBuilder<'a> {
             }
         }
 
+        report.analysis_time = analysis_start.elapsed();
+
         log::debug!("Full Chip Erase enabled: {:?}", chip_erase);
         log::debug!(
             "Double Buffering enabled: {:?}",
             self.enable_double_buffering
         );
+        // Only sector erase programming completes one sector after another, the other methods
+        // complete all sectors at once.
         if Some(true) == chip_erase {
             if flash.double_buffering_supported() && self.enable_double_buffering {
-                self.chip_erase_program_double_buffer(&mut flash, &sectors)?;
+                self.chip_erase_program_double_buffer(&mut flash, &sectors, &mut report)?;
             } else {
-                self.chip_erase_program(&mut flash, &sectors)?;
+                self.chip_erase_program(&mut flash, &sectors, &mut report)?;
             };
+            sectors
+                .iter()
+                .for_each(|sector| sector_programmed(sector.address));
         } else if flash.double_buffering_supported() && self.enable_double_buffering {
-            self.sector_erase_program_double_buffer(&mut flash, &mut sectors)?;
+            self.sector_erase_program_double_buffer(&mut flash, &mut sectors, &mut report)?;
+            sectors
+                .iter()
+                .for_each(|sector| sector_programmed(sector.address));
         } else {
             // WORKING: We debug this atm.
-            self.sector_erase_program(&mut flash, &sectors)?;
+            self.sector_erase_program(&mut flash, &sectors, &mut report, sector_programmed)?;
         }
 
-        Ok(())
+        Ok(report)
     }
 
     fn build_sectors_and_pages(
@@ -636,20 +657,29 @@ impl<'a> FlashBuilder<'a> {
         &self,
         flash: &mut Flasher,
         sectors: &[FlashSector],
+        report: &mut FlashReport,
     ) -> Result<(), FlashBuilderError> {
+        let erase_start = Instant::now();
         flash.run_erase(|active| active.erase_all())?;
+        report.erase_time += erase_start.elapsed();
+        report.sectors_erased += sectors.len();
 
+        let program_start = Instant::now();
         let r: R = flash.run_program(|active| {
             for page in Self::pages(sectors) {
+                active.check_cancelled()?;
                 // TODO: Check this condition.
                 if let Some(true) = page.erased {
                     continue;
                 } else {
                     active.program_page(page.address, page.data.as_slice())?;
+                    report.pages_programmed += 1;
+                    report.bytes_programmed += page.data.len() as u64;
                 }
             }
             Ok(())
         });
+        report.program_time += program_start.elapsed();
 
         r
     }
@@ -670,22 +700,30 @@ impl<'a> FlashBuilder<'a> {
         &self,
         flash: &mut Flasher,
         sectors: &[FlashSector],
+        report: &mut FlashReport,
     ) -> Result<(), FlashBuilderError> {
+        let erase_start = Instant::now();
         flash.run_erase(|active| active.erase_all())?;
+        report.erase_time += erase_start.elapsed();
+        report.sectors_erased += sectors.len();
 
         let mut current_buf = 0;
         let mut next_buf = 1;
         let (first_page, i) = Self::next_unerased_page(sectors, 0);
 
         if let Some(page) = first_page {
-            flash.run_program(|active| {
+            let program_start = Instant::now();
+            let r: R = flash.run_program(|active| {
                 active.load_page_buffer(page.address, page.data.as_slice(), current_buf)?;
 
                 let mut current_page = first_page;
                 let mut i = i;
 
                 while let Some(page) = current_page {
+                    active.check_cancelled()?;
                     active.start_program_page_with_buffer(current_buf, page.address)?;
+                    report.pages_programmed += 1;
+                    report.bytes_programmed += page.data.len() as u64;
 
                     let r = Self::next_unerased_page(sectors, i);
                     current_page = r.0;
@@ -695,7 +733,7 @@ impl<'a> FlashBuilder<'a> {
                         active.load_page_buffer(page.address, page.data.as_slice(), next_buf)?;
                     }
 
-                    let result = active.wait_for_completion();
+                    let result = active.wait_for_completion(Routine::ProgramPage);
                     if let Ok(0) = result {
                     } else {
                         // TODO: Fix me.
@@ -706,7 +744,10 @@ impl<'a> FlashBuilder<'a> {
                 }
 
                 Ok(())
-            })
+            });
+            report.program_time += program_start.elapsed();
+
+            r
         } else {
             Ok(())
         }
@@ -717,6 +758,8 @@ impl<'a> FlashBuilder<'a> {
         &self,
         flash: &mut Flasher,
         sectors: &[FlashSector],
+        report: &mut FlashReport,
+        sector_programmed: &mut dyn FnMut(u32),
     ) -> Result<(), FlashBuilderError> {
         let number_of_sectors_to_be_programmed = sectors
             .iter()
@@ -726,16 +769,26 @@ impl<'a> FlashBuilder<'a> {
         let mut i = 0;
         for sector in sectors {
             if sector.is_pages_to_be_programmed() {
+                flash.check_cancelled()?;
                 log::debug!("Erasing sector {}", i);
+                let erase_start = Instant::now();
                 flash.run_erase(|active| active.erase_sector(sector.address))?;
+                report.erase_time += erase_start.elapsed();
+                report.sectors_erased += 1;
 
                 log::debug!("Programming sector {}", i);
+                let program_start = Instant::now();
                 for page in &sector.pages {
+                    flash.check_cancelled()?;
                     flash.run_program(|active| {
                         active.program_page(page.address, page.data.as_slice())
                     })?;
+                    report.pages_programmed += 1;
+                    report.bytes_programmed += page.data.len() as u64;
                 }
+                report.program_time += program_start.elapsed();
             }
+            sector_programmed(sector.address);
             i += 1;
         }
         Ok(())
@@ -757,17 +810,22 @@ impl<'a> FlashBuilder<'a> {
         &self,
         flash: &mut Flasher,
         sectors: &mut Vec<FlashSector>,
+        report: &mut FlashReport,
     ) -> Result<(), FlashBuilderError> {
         let mut actual_sector_erase_count = 0;
         let mut actual_sector_erase_weight = 0.0;
+        let erase_start = Instant::now();
         let r: R = flash.run_erase(|active| {
             for sector in sectors.iter_mut() {
                 if sector.is_pages_to_be_programmed() {
+                    active.check_cancelled()?;
                     active.erase_sector(sector.address)?;
+                    report.sectors_erased += 1;
                 }
             }
             Ok(())
         });
+        report.erase_time += erase_start.elapsed();
         r?;
 
         let mut current_buf = 0;
@@ -775,6 +833,7 @@ impl<'a> FlashBuilder<'a> {
         let (first_page, i) = Self::next_nonsame_page(&Self::pages(sectors), 0);
 
         if let Some(page) = first_page {
+            let program_start = Instant::now();
             let r: R = flash.run_program(|active| {
                 active.load_page_buffer(page.address, page.data.as_slice(), current_buf)?;
 
@@ -782,11 +841,14 @@ impl<'a> FlashBuilder<'a> {
                 let mut i = i;
 
                 while let Some(page) = current_page {
+                    active.check_cancelled()?;
                     if page.dirty.is_some() {
                         active.start_program_page_with_buffer(current_buf, page.address)?;
 
                         actual_sector_erase_count += 1;
                         actual_sector_erase_weight += page.get_program_weight();
+                        report.pages_programmed += 1;
+                        report.bytes_programmed += page.data.len() as u64;
 
                         let r = Self::next_nonsame_page(&Self::pages(sectors), i);
                         current_page = r.0;
@@ -800,7 +862,7 @@ impl<'a> FlashBuilder<'a> {
                             )?;
                         }
 
-                        let result = active.wait_for_completion();
+                        let result = active.wait_for_completion(Routine::ProgramPage);
                         if let Ok(0) = result {
                         } else {
                             // TODO: Fix me.
@@ -813,6 +875,7 @@ impl<'a> FlashBuilder<'a> {
 
                 Ok(())
             });
+            report.program_time += program_start.elapsed();
             r?
         }
 
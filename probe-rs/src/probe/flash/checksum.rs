@@ -0,0 +1,289 @@
+//! Checksums of memory ranges, e.g. to verify the contents of a device in production.
+//!
+//! The CRC32 is computed on the target by the analyzer of the flash algorithm where possible,
+//! which avoids reading the whole range over the probe. The analyzer only handles blocks
+//! which are a power of 2 in size and aligned to it, so the range is split into such blocks
+//! and their CRCs are combined. Blocks the analyzer cannot handle are read back instead.
+
+use super::flasher::{Flasher, FlasherError};
+use super::memory::MemoryRegion;
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::session::Session;
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// The analyzer stores the address of a block divided by its size in 16 bits.
+const ANALYZER_MAX_BLOCK_INDEX: u32 = 0xFFFF;
+
+#[derive(Debug)]
+pub enum ChecksumError {
+    Access(AccessPortError),
+    Flasher(FlasherError),
+}
+
+impl Error for ChecksumError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ChecksumError::Access(e) => Some(e),
+            ChecksumError::Flasher(e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChecksumError::Access(e) => e.fmt(f),
+            ChecksumError::Flasher(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<AccessPortError> for ChecksumError {
+    fn from(error: AccessPortError) -> Self {
+        ChecksumError::Access(error)
+    }
+}
+
+impl From<FlasherError> for ChecksumError {
+    fn from(error: FlasherError) -> Self {
+        ChecksumError::Flasher(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChecksumAlgorithm {
+    /// The CRC32 of zlib and Ethernet.
+    Crc32,
+    /// The sum of all bytes, modulo 2^32.
+    Sum32,
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "crc32" => Ok(ChecksumAlgorithm::Crc32),
+            "sum32" => Ok(ChecksumAlgorithm::Sum32),
+            _ => Err(format!("Unknown checksum algorithm '{}'.", s)),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChecksumAlgorithm::Crc32 => f.pad("CRC32"),
+            ChecksumAlgorithm::Sum32 => f.pad("SUM32"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Checksum {
+    pub value: u32,
+    /// The number of bytes the target computed the checksum of itself, instead of them being
+    /// read back.
+    pub on_target: u32,
+}
+
+/// Computes the checksum of `range`.
+///
+/// The CRC32 is computed on the target if `on_target` is set and the flash algorithm of the
+/// session supports the analyzer. Otherwise the range is read back.
+pub fn checksum(
+    session: &mut Session,
+    range: Range<u32>,
+    algorithm: ChecksumAlgorithm,
+    on_target: bool,
+) -> Result<Checksum, ChecksumError> {
+    // The analyzer is loaded along with the flash algorithm, which needs a flash region.
+    let region = session
+        .target
+        .memory_map
+        .iter()
+        .find_map(|region| match region {
+            MemoryRegion::Flash(flash) => Some(flash),
+            _ => None,
+        });
+    let analyzer = match (&session.flash_algorithm, region) {
+        (Some(flash_algorithm), Some(region))
+            if on_target
+                && flash_algorithm.analyzer_supported
+                && algorithm == ChecksumAlgorithm::Crc32 =>
+        {
+            Some((flash_algorithm, region))
+        }
+        _ => None,
+    };
+
+    let (analyzed, read): (Vec<_>, Vec<_>) =
+        blocks(range).into_iter().partition(|&(address, size)| {
+            analyzer.is_some() && address / size <= ANALYZER_MAX_BLOCK_INDEX
+        });
+
+    let mut crcs = vec![None; analyzed.len()];
+    if let (Some((flash_algorithm, region)), false) = (analyzer, analyzed.is_empty()) {
+        let mut flasher =
+            Flasher::new(&session.target, &mut session.probe, flash_algorithm, region);
+        let values = flasher.run_erase(|active| active.compute_crcs(&analyzed))?;
+        crcs = values.into_iter().map(Some).collect();
+    }
+
+    let on_target = analyzed.iter().map(|(_, size)| size).sum();
+
+    let mut blocks: Vec<_> = analyzed.into_iter().zip(crcs).collect();
+    blocks.extend(read.into_iter().map(|block| (block, None)));
+    blocks.sort_by_key(|((address, _), _)| *address);
+
+    let mut value = 0;
+    for ((address, size), crc) in blocks {
+        let data = match crc {
+            Some(crc) => {
+                value = crc32_combine(value, crc, u64::from(size));
+                continue;
+            }
+            None => {
+                let mut data = vec![0; size as usize];
+                session.probe.read_block8(address, &mut data)?;
+                data
+            }
+        };
+
+        value = match algorithm {
+            ChecksumAlgorithm::Crc32 => {
+                crc32_combine(value, crc::crc32::checksum_ieee(&data), u64::from(size))
+            }
+            ChecksumAlgorithm::Sum32 => data
+                .iter()
+                .fold(value, |sum, byte| sum.wrapping_add(u32::from(*byte))),
+        };
+    }
+
+    Ok(Checksum { value, on_target })
+}
+
+/// Splits a range into the fewest blocks which are a power of 2 in size and aligned to it.
+fn blocks(range: Range<u32>) -> Vec<(u32, u32)> {
+    let mut blocks = vec![];
+    let mut address = range.start;
+
+    while address < range.end {
+        let alignment = if address == 0 {
+            1 << 31
+        } else {
+            1 << address.trailing_zeros()
+        };
+        let mut size: u32 = alignment;
+        while size > range.end - address {
+            size >>= 1;
+        }
+
+        blocks.push((address, size));
+        address += size;
+    }
+
+    blocks
+}
+
+/// Returns the CRC32 of two concatenated blocks from their CRCs, like `crc32_combine` of zlib.
+fn crc32_combine(crc1: u32, crc2: u32, length2: u64) -> u32 {
+    fn times(matrix: &[u32; 32], mut vector: u32) -> u32 {
+        let mut sum = 0;
+        for row in matrix.iter() {
+            if vector == 0 {
+                break;
+            }
+            if vector & 1 != 0 {
+                sum ^= row;
+            }
+            vector >>= 1;
+        }
+        sum
+    }
+
+    fn square(matrix: &[u32; 32]) -> [u32; 32] {
+        let mut square = [0; 32];
+        for (row, value) in square.iter_mut().zip(matrix.iter()) {
+            *row = times(matrix, *value);
+        }
+        square
+    }
+
+    if length2 == 0 {
+        return crc1;
+    }
+
+    // The operator which appends a single zero bit to the CRC.
+    let mut odd = [0; 32];
+    odd[0] = 0xEDB8_8320;
+    for (n, row) in odd.iter_mut().enumerate().skip(1) {
+        *row = 1 << (n - 1);
+    }
+
+    // Appends 2 and 4 zero bits.
+    let mut even = square(&odd);
+    odd = square(&even);
+
+    // Appends the zero bytes of the second block, squaring the operator for each bit of the
+    // length, which starts with a single zero byte.
+    let mut crc1 = crc1;
+    let mut length2 = length2;
+    loop {
+        even = square(&odd);
+        if length2 & 1 != 0 {
+            crc1 = times(&even, crc1);
+        }
+        length2 >>= 1;
+        if length2 == 0 {
+            break;
+        }
+
+        odd = square(&even);
+        if length2 & 1 != 0 {
+            crc1 = times(&odd, crc1);
+        }
+        length2 >>= 1;
+        if length2 == 0 {
+            break;
+        }
+    }
+
+    crc1 ^ crc2
+}
+
+#[cfg(test)]
+mod test {
+    use super::{blocks, crc32_combine};
+
+    #[test]
+    fn combines_crcs_of_blocks() {
+        assert_eq!(
+            blocks(0x0800_0100..0x0800_1004),
+            vec![
+                (0x0800_0100, 0x100),
+                (0x0800_0200, 0x200),
+                (0x0800_0400, 0x400),
+                (0x0800_0800, 0x800),
+                (0x0800_1000, 0x4),
+            ]
+        );
+
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let (a, b) = data.split_at(300);
+
+        assert_eq!(
+            crc32_combine(
+                crc::crc32::checksum_ieee(a),
+                crc::crc32::checksum_ieee(b),
+                b.len() as u64
+            ),
+            crc::crc32::checksum_ieee(&data)
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Programming of the configuration regions of chips, which hold device unique data like
+//! serial numbers, calibration values and protection settings.
+//!
+//! These regions are not part of the memory map the flash algorithms program, and each vendor
+//! writes them differently. The UICR of the nRF5 chips is written word by word through the
+//! NVMC and can only be erased as a whole. The OTP area of the STM32F4 chips can be programmed
+//! once and never be erased.
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::probe::debug_probe::MasterProbe;
+use crate::target::Target;
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+const NRF_NVMC_READY: u32 = 0x4001_E400;
+const NRF_NVMC_CONFIG: u32 = 0x4001_E504;
+const NRF_NVMC_ERASEUICR: u32 = 0x4001_E514;
+const NRF_NVMC_CONFIG_REN: u32 = 0;
+const NRF_NVMC_CONFIG_WEN: u32 = 1;
+const NRF_NVMC_CONFIG_EEN: u32 = 2;
+
+const STM32_FLASH_KEYR: u32 = 0x4002_3C04;
+pub(super) const STM32_FLASH_SR: u32 = 0x4002_3C0C;
+const STM32_FLASH_CR: u32 = 0x4002_3C10;
+const STM32_FLASH_KEYS: [u32; 2] = [0x4567_0123, 0xCDEF_89AB];
+const STM32_FLASH_CR_PG: u32 = 1;
+const STM32_FLASH_CR_LOCK: u32 = 1 << 31;
+pub(super) const STM32_FLASH_SR_ERRORS: u32 = 0xF0;
+const STM32_FLASH_SR_BSY: u32 = 1 << 16;
+
+/// How long to wait for the flash controller to finish an operation.
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum ConfigRegionError {
+    Access(AccessPortError),
+    /// The given range is not inside the configuration region.
+    OutOfRange(Range<u32>),
+    /// The data clears bits which are set at the given address, and the region can not be
+    /// erased to set them again.
+    NotErasable(u32),
+    /// The flash controller reported an error with the given status.
+    Programming(u32),
+    Timeout,
+}
+
+impl Error for ConfigRegionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigRegionError::Access(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigRegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ConfigRegionError::*;
+
+        match self {
+            Access(e) => e.fmt(f),
+            OutOfRange(range) => write!(
+                f,
+                "The range {:#010x}..{:#010x} is not inside the configuration region.",
+                range.start, range.end
+            ),
+            NotErasable(address) => write!(
+                f,
+                "The memory at {:#010x} is already programmed and can not be erased.",
+                address
+            ),
+            Programming(status) => write!(f, "Programming failed with status {:#x}.", status),
+            Timeout => write!(f, "Timeout while waiting for the flash controller."),
+        }
+    }
+}
+
+impl From<AccessPortError> for ConfigRegionError {
+    fn from(error: AccessPortError) -> Self {
+        ConfigRegionError::Access(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConfigRegionKind {
+    /// The User Information Configuration Registers of the nRF51 and nRF52 chips.
+    NrfUicr,
+    /// The one time programmable area of the STM32F4 chips, including its lock bytes.
+    Stm32Otp,
+}
+
+impl fmt::Display for ConfigRegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigRegionKind::NrfUicr => write!(f, "UICR"),
+            ConfigRegionKind::Stm32Otp => write!(f, "OTP"),
+        }
+    }
+}
+
+/// The configuration region of a target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigRegion {
+    pub kind: ConfigRegionKind,
+    pub range: Range<u32>,
+}
+
+impl ConfigRegion {
+    /// Returns the configuration region of the target, or `None` if it is not supported.
+    pub fn for_target(target: &Target) -> Option<Self> {
+        let name = target.name.to_ascii_lowercase();
+
+        let (kind, range) = if name.starts_with("nrf51") {
+            (ConfigRegionKind::NrfUicr, 0x1000_1000..0x1000_1100)
+        } else if name.starts_with("nrf52") {
+            (ConfigRegionKind::NrfUicr, 0x1000_1000..0x1000_1310)
+        } else if name.starts_with("stm32f4") {
+            (ConfigRegionKind::Stm32Otp, 0x1FFF_7800..0x1FFF_7A10)
+        } else {
+            return None;
+        };
+
+        Some(ConfigRegion { kind, range })
+    }
+
+    pub fn contains(&self, address: u32, length: usize) -> bool {
+        self.range.start <= address && address as u64 + length as u64 <= self.range.end as u64
+    }
+
+    pub fn read(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), ConfigRegionError> {
+        self.check_range(address, data.len())?;
+
+        Ok(probe.read_block8(address, data)?)
+    }
+
+    /// Programs `data` at `address`.
+    ///
+    /// Bits can only be cleared by programming. If the data sets bits which are cleared, the
+    /// UICR is erased and its previous contents are programmed again along with the data.
+    /// The OTP area returns `ConfigRegionError::NotErasable` instead.
+    pub fn write(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), ConfigRegionError> {
+        self.check_range(address, data.len())?;
+
+        match self.kind {
+            ConfigRegionKind::NrfUicr => self.write_uicr(probe, address, data),
+            ConfigRegionKind::Stm32Otp => write_otp(probe, address, data),
+        }
+    }
+
+    /// Erases the whole region, which only the UICR supports.
+    pub fn erase(&self, probe: &mut MasterProbe) -> Result<(), ConfigRegionError> {
+        match self.kind {
+            ConfigRegionKind::NrfUicr => {
+                probe.write32(NRF_NVMC_CONFIG, NRF_NVMC_CONFIG_EEN)?;
+                probe.write32(NRF_NVMC_ERASEUICR, 1)?;
+                let ready = wait_nvmc_ready(probe);
+                probe.write32(NRF_NVMC_CONFIG, NRF_NVMC_CONFIG_REN)?;
+
+                ready
+            }
+            ConfigRegionKind::Stm32Otp => Err(ConfigRegionError::NotErasable(self.range.start)),
+        }
+    }
+
+    fn check_range(&self, address: u32, length: usize) -> Result<(), ConfigRegionError> {
+        if self.contains(address, length) {
+            Ok(())
+        } else {
+            Err(ConfigRegionError::OutOfRange(
+                address..address.wrapping_add(length as u32),
+            ))
+        }
+    }
+
+    /// Writes the words of the UICR covering the data, which need not be aligned.
+    fn write_uicr(
+        &self,
+        probe: &mut MasterProbe,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), ConfigRegionError> {
+        let start = address & !3;
+        let end = (address + data.len() as u32 + 3) & !3;
+
+        let mut current = vec![0; (end - start) as usize];
+        probe.read_block8(start, &mut current)?;
+        let mut contents = current.clone();
+        let offset = (address - start) as usize;
+        contents[offset..offset + data.len()].copy_from_slice(data);
+
+        let (start, current, contents) = if needs_erase(&current, &contents) {
+            let mut region = vec![0; (self.range.end - self.range.start) as usize];
+            probe.read_block8(self.range.start, &mut region)?;
+            let offset = (start - self.range.start) as usize;
+            region[offset..offset + contents.len()].copy_from_slice(&contents);
+
+            self.erase(probe)?;
+
+            let erased = vec![0xFF; region.len()];
+            (self.range.start, erased, region)
+        } else {
+            (start, current, contents)
+        };
+
+        probe.write32(NRF_NVMC_CONFIG, NRF_NVMC_CONFIG_WEN)?;
+        let mut result = Ok(());
+        for (i, (word, current)) in contents.chunks(4).zip(current.chunks(4)).enumerate() {
+            if word == current {
+                continue;
+            }
+
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            probe.write32(start + i as u32 * 4, word)?;
+            result = wait_nvmc_ready(probe);
+            if result.is_err() {
+                break;
+            }
+        }
+        probe.write32(NRF_NVMC_CONFIG, NRF_NVMC_CONFIG_REN)?;
+
+        result
+    }
+}
+
+/// Programs the OTP area byte by byte through the flash interface of the STM32F4.
+fn write_otp(probe: &mut MasterProbe, address: u32, data: &[u8]) -> Result<(), ConfigRegionError> {
+    let mut current = vec![0; data.len()];
+    probe.read_block8(address, &mut current)?;
+
+    if let Some(offset) = (0..data.len()).find(|&i| needs_erase(&current[i..=i], &data[i..=i])) {
+        return Err(ConfigRegionError::NotErasable(address + offset as u32));
+    }
+
+    if probe.read32(STM32_FLASH_CR)? & STM32_FLASH_CR_LOCK != 0 {
+        for key in &STM32_FLASH_KEYS {
+            probe.write32(STM32_FLASH_KEYR, *key)?;
+        }
+    }
+
+    // Clear the errors of earlier operations, which are cleared by writing ones.
+    probe.write32(STM32_FLASH_SR, STM32_FLASH_SR_ERRORS)?;
+    // A program size of 8 bits, which works at any supply voltage.
+    probe.write32(STM32_FLASH_CR, STM32_FLASH_CR_PG)?;
+
+    let mut result = Ok(());
+    for (i, (byte, current)) in data.iter().zip(&current).enumerate() {
+        if byte == current {
+            continue;
+        }
+
+        probe.write8(address + i as u32, *byte)?;
+        result = wait_stm32_ready(probe);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    probe.write32(STM32_FLASH_CR, STM32_FLASH_CR_LOCK)?;
+
+    result
+}
+
+fn wait_nvmc_ready(probe: &mut MasterProbe) -> Result<(), ConfigRegionError> {
+    let start = Instant::now();
+
+    while probe.read32(NRF_NVMC_READY)? & 1 == 0 {
+        if start.elapsed() > TIMEOUT {
+            return Err(ConfigRegionError::Timeout);
+        }
+    }
+
+    Ok(())
+}
+
+pub(super) fn wait_stm32_ready(probe: &mut MasterProbe) -> Result<(), ConfigRegionError> {
+    let start = Instant::now();
+
+    loop {
+        let status = probe.read32(STM32_FLASH_SR)?;
+
+        if status & STM32_FLASH_SR_ERRORS != 0 {
+            return Err(ConfigRegionError::Programming(status));
+        }
+        if status & STM32_FLASH_SR_BSY == 0 {
+            return Ok(());
+        }
+        if start.elapsed() > TIMEOUT {
+            return Err(ConfigRegionError::Timeout);
+        }
+    }
+}
+
+/// Returns whether programming `data` over `current` has to set bits, which needs an erase.
+fn needs_erase(current: &[u8], data: &[u8]) -> bool {
+    current
+        .iter()
+        .zip(data)
+        .any(|(current, data)| current & data != *data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::needs_erase;
+
+    #[test]
+    fn erase_is_needed_to_set_bits() {
+        assert!(!needs_erase(&[0xFF, 0xFF], &[0x12, 0xFF]));
+        assert!(!needs_erase(&[0x12, 0x34], &[0x12, 0x30]));
+        assert!(needs_erase(&[0x12, 0x34], &[0x12, 0x35]));
+    }
+}
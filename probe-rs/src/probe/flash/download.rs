@@ -1,13 +1,21 @@
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::probe::debug_probe::DebugProbeError;
 use crate::session::Session;
 use ihex;
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::Read;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use super::*;
 
+#[derive(Debug, Clone)]
 pub struct BinOptions {
     /// Memory address at which to program the binary data. If not set, the base
     /// of the boot memory will be used.
@@ -17,10 +25,71 @@ pub struct BinOptions {
     skip: u32,
 }
 
+impl BinOptions {
+    pub fn new(base_address: Option<u32>, skip: u32) -> Self {
+        BinOptions { base_address, skip }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Format {
     Bin(BinOptions),
     Hex,
     Elf,
+    /// Motorola S-records.
+    Srec,
+    Uf2,
+}
+
+impl Format {
+    /// Detects the format of a file from its first bytes, or from its extension for binary
+    /// files, which have no header.
+    pub fn detect(path: &Path) -> Result<Format, FileDownloadError> {
+        let mut start = [0; 4];
+        let length = File::open(path)?.read(&mut start)?;
+        let start = &start[..length];
+
+        if start == b"\x7FELF" {
+            return Ok(Format::Elf);
+        }
+        if start == uf2::MAGIC.to_le_bytes() {
+            return Ok(Format::Uf2);
+        }
+        match start {
+            [b':', ..] => return Ok(Format::Hex),
+            [b'S', digit, ..] if digit.is_ascii_digit() => return Ok(Format::Srec),
+            _ => {}
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_ref().map(String::as_str) {
+            Some("bin") => Ok(Format::Bin(BinOptions::new(None, 0))),
+            _ => Err(FileDownloadError::Object(
+                "The format of the file is unknown, please pass it with --format",
+            )),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "bin" => Ok(Format::Bin(BinOptions::new(None, 0))),
+            "hex" | "ihex" => Ok(Format::Hex),
+            "elf" => Ok(Format::Elf),
+            "srec" | "mot" | "s19" => Ok(Format::Srec),
+            "uf2" => Ok(Format::Uf2),
+            _ => Err(format!(
+                "'{}' is not a valid format, use 'elf', 'hex', 'srec', 'uf2' or 'bin'.",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -29,6 +98,11 @@ pub enum FileDownloadError {
     IhexRead(ihex::reader::ReaderError),
     IO(std::io::Error),
     Object(&'static str),
+    Srec(srec::SrecError),
+    /// A segment to be loaded into RAM starts at the given address outside of it.
+    NotInRam(u32),
+    Access(AccessPortError),
+    DebugProbe(DebugProbeError),
 }
 
 impl Error for FileDownloadError {}
@@ -42,6 +116,14 @@ impl fmt::Display for FileDownloadError {
             IhexRead(ref e) => e.fmt(f),
             IO(ref e) => e.fmt(f),
             Object(ref s) => write!(f, "Object Error: {}.", s),
+            Srec(ref e) => e.fmt(f),
+            NotInRam(address) => write!(
+                f,
+                "The segment at 0x{:08x} is not inside a RAM region.",
+                address
+            ),
+            Access(ref e) => e.fmt(f),
+            DebugProbe(ref e) => e.fmt(f),
         }
     }
 }
@@ -58,18 +140,333 @@ impl From<ihex::reader::ReaderError> for FileDownloadError {
     }
 }
 
+impl From<srec::SrecError> for FileDownloadError {
+    fn from(error: srec::SrecError) -> FileDownloadError {
+        FileDownloadError::Srec(error)
+    }
+}
+
 impl From<std::io::Error> for FileDownloadError {
     fn from(error: std::io::Error) -> FileDownloadError {
         FileDownloadError::IO(error)
     }
 }
 
+impl From<AccessPortError> for FileDownloadError {
+    fn from(error: AccessPortError) -> FileDownloadError {
+        FileDownloadError::Access(error)
+    }
+}
+
+impl From<DebugProbeError> for FileDownloadError {
+    fn from(error: DebugProbeError) -> FileDownloadError {
+        FileDownloadError::DebugProbe(error)
+    }
+}
+
 impl From<&'static str> for FileDownloadError {
     fn from(error: &'static str) -> FileDownloadError {
         FileDownloadError::Object(error)
     }
 }
 
+/// Returns the data a file places in memory, as the address and the data of each block.
+///
+/// This includes the segments of ELF files which are loaded into the RAM, which
+/// `read_flash_image` skips.
+pub fn read_image(path: &Path, format: Format) -> Result<Vec<(u32, Vec<u8>)>, FileDownloadError> {
+    let buffer = std::fs::read(path)?;
+
+    match format {
+        Format::Bin(options) => {
+            let data = buffer.get(options.skip as usize..).unwrap_or(&[]);
+
+            Ok(vec![(options.base_address.unwrap_or(0), data.to_vec())])
+        }
+        Format::Elf => {
+            let binary = parse_elf(&buffer)?;
+
+            Ok(elf_segments(&buffer, &binary))
+        }
+        Format::Hex | Format::Srec | Format::Uf2 => parse_blocks(&buffer, &format),
+    }
+}
+
+/// Returns the data a file programs into the flash, as the address and the data of each block.
+///
+/// Unlike `read_image`, this skips the segments of ELF files which are loaded into the RAM,
+/// like `FileDownloader::download_file` does, see `stream::is_programmed`.
+pub fn read_flash_image(
+    path: &Path,
+    format: Format,
+    memory_map: &[MemoryRegion],
+) -> Result<Vec<(u32, Vec<u8>)>, FileDownloadError> {
+    match format {
+        Format::Elf => {
+            let buffer = std::fs::read(path)?;
+            let binary = parse_elf(&buffer)?;
+
+            Ok(binary
+                .program_headers
+                .iter()
+                .filter(|ph| stream::is_programmed(ph, memory_map))
+                .map(|ph| {
+                    (
+                        ph.p_paddr as u32,
+                        buffer[ph.p_offset as usize..][..ph.p_filesz as usize].to_vec(),
+                    )
+                })
+                .collect())
+        }
+        format => read_image(path, format),
+    }
+}
+
+/// Returns the memory a file occupies on the target, e.g. to report its usage of the memory map.
+///
+/// The loadable segments of ELF files are placed where they run, with the initializers of data
+/// copied to the RAM at startup stored at their load address. The data of other formats lives
+/// where it is stored.
+pub fn read_allocations(path: &Path, format: Format) -> Result<Vec<Allocation>, FileDownloadError> {
+    use goblin::elf::program_header::PT_LOAD;
+
+    match format {
+        Format::Elf => {
+            let buffer = std::fs::read(path)?;
+            let binary = parse_elf(&buffer)?;
+
+            Ok(binary
+                .program_headers
+                .iter()
+                .filter(|ph| ph.p_type == PT_LOAD && ph.p_memsz > 0)
+                .map(|ph| Allocation {
+                    address: ph.p_vaddr as u32,
+                    size: ph.p_memsz as u32,
+                    load_address: ph.p_paddr as u32,
+                    load_size: ph.p_filesz as u32,
+                })
+                .collect())
+        }
+        format => Ok(Allocation::from_image(&read_image(path, format)?)),
+    }
+}
+
+/// Returns the blocks of data of the formats which hold the address with each of them.
+fn parse_blocks(buffer: &[u8], format: &Format) -> Result<Vec<(u32, Vec<u8>)>, FileDownloadError> {
+    match format {
+        Format::Hex => ihex_blocks(&String::from_utf8_lossy(buffer)),
+        Format::Srec => Ok(srec::parse(&String::from_utf8_lossy(buffer))?),
+        Format::Uf2 => Ok(uf2::parse(buffer)?),
+        Format::Bin(_) | Format::Elf => unreachable!(),
+    }
+}
+
+/// Returns the data of an Intel hex file, joining consecutive records into one block.
+fn ihex_blocks(contents: &str) -> Result<Vec<(u32, Vec<u8>)>, FileDownloadError> {
+    use ihex::record::Record;
+
+    let mut blocks: Vec<(u32, Vec<u8>)> = vec![];
+    let mut base = 0;
+
+    for record in ihex::reader::Reader::new(contents) {
+        match record? {
+            Record::Data { offset, value } => {
+                let address = base + u32::from(offset);
+
+                match blocks.last_mut() {
+                    Some((start, data)) if *start + data.len() as u32 == address => {
+                        data.extend_from_slice(&value)
+                    }
+                    _ => blocks.push((address, value)),
+                }
+            }
+            Record::ExtendedSegmentAddress(segment) => base = u32::from(segment) << 4,
+            Record::ExtendedLinearAddress(upper) => base = u32::from(upper) << 16,
+            Record::EndOfFile => break,
+            Record::StartSegmentAddress { .. } | Record::StartLinearAddress(_) => {}
+        }
+    }
+
+    Ok(blocks)
+}
+
+fn parse_elf(buffer: &[u8]) -> Result<goblin::elf::Elf, FileDownloadError> {
+    goblin::elf::Elf::parse(buffer)
+        .map_err(|_| FileDownloadError::Object("The file is not a valid ELF file."))
+}
+
+/// Returns the loadable segments of an ELF file, at their physical addresses.
+fn elf_segments(buffer: &[u8], binary: &goblin::elf::Elf) -> Vec<(u32, Vec<u8>)> {
+    use goblin::elf::program_header::PT_LOAD;
+
+    binary
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD && ph.p_filesz > 0)
+        .map(|ph| {
+            (
+                ph.p_paddr as u32,
+                buffer[ph.p_offset as usize..][..ph.p_filesz as usize].to_vec(),
+            )
+        })
+        .collect()
+}
+
+/// Loads an ELF file linked entirely to RAM and starts it, without using the flash algorithm,
+/// e.g. for RAM test stubs and second stage loaders.
+///
+/// The core is halted while the segments are written. Execution starts at the entry point of
+/// the file, with the stack pointer taken from the vector table at the start of the image if
+/// it points into RAM. Returns the entry point.
+pub fn run_from_ram(session: &mut Session, path: &Path) -> Result<u32, FileDownloadError> {
+    let buffer = std::fs::read(path)?;
+    let binary = parse_elf(&buffer)?;
+    let mut segments = elf_segments(&buffer, &binary);
+    segments.sort_by_key(|(address, _)| *address);
+
+    let memory_map = &session.target.memory_map;
+    if let Some((address, _)) = segments
+        .iter()
+        .find(|(address, data)| !in_ram(memory_map, *address, data.len()))
+    {
+        return Err(FileDownloadError::NotInRam(*address));
+    }
+
+    let stack_pointer = segments
+        .first()
+        .and_then(|(_, data)| data.get(..4))
+        .map(|sp| u32::from_le_bytes([sp[0], sp[1], sp[2], sp[3]]))
+        .filter(|sp| in_ram(memory_map, sp.wrapping_sub(4), 4));
+
+    let core = &session.target.core;
+    let probe = &mut session.probe;
+    core.halt(probe)?;
+
+    for (address, data) in &segments {
+        probe.write_block8_verified(*address, data)?;
+    }
+
+    let registers = core.registers();
+    if let Some(stack_pointer) = stack_pointer {
+        core.write_core_reg(probe, registers.SP, stack_pointer)?;
+    }
+
+    // The entry point has the Thumb bit set, which belongs into the XPSR instead of the PC.
+    let entry = binary.entry as u32;
+    let xpsr = core.read_core_reg(probe, registers.XPSR)?;
+    core.write_core_reg(probe, registers.XPSR, xpsr | 1 << 24)?;
+    core.write_core_reg(probe, registers.PC, entry & !1)?;
+    core.run(probe)?;
+
+    Ok(entry)
+}
+
+/// Whether the `length` bytes at `address` are in a RAM region.
+pub(crate) fn in_ram(memory_map: &[MemoryRegion], address: u32, length: usize) -> bool {
+    memory_map.iter().any(|region| match region {
+        MemoryRegion::Ram(ram) => {
+            ram.range.start <= address
+                && u64::from(address) + length as u64 <= u64::from(ram.range.end)
+        }
+        _ => false,
+    })
+}
+
+/// The stage of programming one of the targets of `flash_many`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlashStage {
+    Connecting,
+    Flashing,
+    Finished,
+    Failed,
+}
+
+/// Programs the same file to several targets at once, each in its own thread.
+///
+/// Each element of `sessions` opens the session of one target. They are called one after
+/// another, as the USB libraries cannot open devices from several threads at once, so only the
+/// programming runs concurrently. `finish` is called with each session once the file is
+/// programmed, e.g. to reset the target. `progress` is called with the index of the target
+/// whenever it reaches another stage. Returns the result for each target, with the time
+/// programming took.
+pub fn flash_many<F, G, E>(
+    sessions: Vec<F>,
+    path: &Path,
+    format: Format,
+    finish: G,
+    mut progress: impl FnMut(usize, FlashStage),
+) -> Vec<Result<Duration, E>>
+where
+    F: FnOnce() -> Result<Session, E> + Send + 'static,
+    G: Fn(&mut Session) -> Result<(), E> + Clone + Send + 'static,
+    E: From<FileDownloadError> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    let open_lock = Arc::new(Mutex::new(()));
+
+    let threads: Vec<_> = sessions
+        .into_iter()
+        .enumerate()
+        .map(|(i, open)| {
+            let sender = sender.clone();
+            let open_lock = open_lock.clone();
+            let path = path.to_path_buf();
+            let format = format.clone();
+            let finish = finish.clone();
+
+            thread::spawn(move || {
+                let flash = || -> Result<Duration, E> {
+                    let _ = sender.send((i, FlashStage::Connecting));
+                    let mut session = {
+                        let _lock = open_lock.lock();
+                        open()?
+                    };
+
+                    let _ = sender.send((i, FlashStage::Flashing));
+                    let instant = Instant::now();
+                    let memory_map = session.target.memory_map.clone();
+                    FileDownloader::new().download_file(
+                        &mut session,
+                        &path,
+                        format,
+                        &memory_map,
+                    )?;
+                    let elapsed = instant.elapsed();
+
+                    finish(&mut session)?;
+
+                    Ok(elapsed)
+                };
+
+                let result = flash();
+                let stage = if result.is_ok() {
+                    FlashStage::Finished
+                } else {
+                    FlashStage::Failed
+                };
+                let _ = sender.send((i, stage));
+
+                result
+            })
+        })
+        .collect();
+
+    // The loop ends when all threads are done and have dropped their senders.
+    drop(sender);
+    for (i, stage) in receiver {
+        progress(i, stage);
+    }
+
+    threads
+        .into_iter()
+        .map(|thread| {
+            thread
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e))
+        })
+        .collect()
+}
+
 /// This struct and impl bundle functionality to start the `Downloader` which then will flash
 /// the given data to the flash of the target.
 ///
@@ -78,132 +475,90 @@ impl From<&'static str> for FileDownloadError {
 /// - Intel Hex (.hex)
 /// - ELF (.elf or .axf)
 #[derive(Default)]
-pub struct FileDownloader;
+pub struct FileDownloader {
+    translation: Option<AddressTranslation>,
+}
 
 impl<'a> FileDownloader {
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Downloads a file at `path` into flash.
+    /// Creates a downloader which moves the data of the file, e.g. behind a bootloader.
+    pub fn with_translation(translation: AddressTranslation) -> Self {
+        FileDownloader {
+            translation: Some(translation),
+        }
+    }
+
+    /// Downloads a file at `path` into flash and returns what was erased and programmed.
+    ///
+    /// Binary and ELF files are streamed, see `stream`, so they are not read into memory whole.
     pub fn download_file(
         self,
         session: &mut Session,
         path: &Path,
         format: Format,
         memory_map: &[MemoryRegion],
-    ) -> Result<(), FileDownloadError> {
+    ) -> Result<FlashReport, FileDownloadError> {
+        let span = tracing::info_span!("flash", path = %path.display());
+        let _enter = span.enter();
+
         let mut file = match File::open(path) {
             Ok(file) => file,
             Err(e) => return Err(FileDownloadError::IO(e)),
         };
-        let mut buffer = vec![];
-        // IMPORTANT: Change this to an actual memory map of a real chip
-        let mut loader = FlashLoader::new(memory_map, false, false, false);
 
-        match format {
-            Format::Bin(options) => self.download_bin(&mut buffer, &mut file, &mut loader, options),
-            Format::Elf => self.download_elf(&mut buffer, &mut file, &mut loader),
-            Format::Hex => self.download_hex(&mut file, &mut loader),
-        }?;
+        let segments = match &format {
+            Format::Bin(options) => {
+                let length = file.metadata()?.len();
 
-        loader
-            .commit(session)
-            .map_err(FileDownloadError::FlashLoader)
-    }
+                vec![Segment {
+                    // If no base address is specified use the start of the boot memory.
+                    // TODO: Implement this as soon as we know targets.
+                    // self._session.target.memory_map.get_boot_memory().start
+                    address: options.base_address.unwrap_or(0),
+                    // Skip the specified bytes.
+                    offset: u64::from(options.skip),
+                    size: length.saturating_sub(u64::from(options.skip)) as u32,
+                }]
+            }
+            Format::Elf => stream::elf_segments(&mut file, memory_map)?,
+            // The other formats are text or hold the address with each block of data, in any
+            // order, so they are parsed whole.
+            Format::Hex | Format::Srec | Format::Uf2 => {
+                let mut buffer = vec![];
+                file.read_to_end(&mut buffer)?;
+                let blocks = parse_blocks(&buffer, &format)?;
 
-    /// Starts the download of a binary file.
-    fn download_bin<'b, T: Read + Seek>(
-        self,
-        buffer: &'b mut Vec<u8>,
-        file: &'b mut T,
-        loader: &mut FlashLoader<'_, 'b>,
-        options: BinOptions,
-    ) -> Result<(), FileDownloadError> {
-        // Skip the specified bytes.
-        file.seek(SeekFrom::Start(u64::from(options.skip)))?;
-
-        file.read_to_end(buffer)?;
-
-        loader.add_data(
-            if let Some(address) = options.base_address {
-                address
-            } else {
-                // If no base address is specified use the start of the boot memory.
-                // TODO: Implement this as soon as we know targets.
-                // self._session.target.memory_map.get_boot_memory().start
-                0
-            },
-            buffer.as_slice(),
-        )?;
+                let mut loader = FlashLoader::new(memory_map, false, false, false);
+                if let Some(translation) = &self.translation {
+                    loader.set_translation(translation.clone());
+                }
+                for (address, data) in &blocks {
+                    loader.add_data(*address, data)?;
+                }
 
-        Ok(())
-    }
+                return loader
+                    .commit(session)
+                    .map_err(FileDownloadError::FlashLoader);
+            }
+        };
 
-    /// Starts the download of a hex file.
-    fn download_hex<T: Read + Seek>(
-        self,
-        file: &mut T,
-        _loader: &mut FlashLoader,
-    ) -> Result<(), FileDownloadError> {
-        let mut data = String::new();
-        file.read_to_string(&mut data)?;
-
-        for item in ihex::reader::Reader::new(&data) {
-            println!("{:?}", item?);
+        for segment in &segments {
+            log::debug!(
+                "Found loadable segment of {} bytes at 0x{:08x}.",
+                segment.size,
+                segment.address
+            );
         }
-        Ok(())
 
-        // hexfile = IntelHex(file_obj)
-        // addresses = hexfile.addresses()
-        // addresses.sort()
-
-        // data_list = list(ranges(addresses))
-        // for start, end in data_list:
-        //     size = end - start + 1
-        //     data = list(hexfile.tobinarray(start=start, size=size))
-        //     self._loader.add_data(start, data)
-    }
-
-    /// Starts the download of a elf file.
-    fn download_elf<'b, T: Read + Seek>(
-        self,
-        buffer: &'b mut Vec<u8>,
-        file: &'b mut T,
-        loader: &mut FlashLoader<'_, 'b>,
-    ) -> Result<(), FileDownloadError> {
-        file.read_to_end(buffer)?;
-
-        use goblin::elf::program_header::*;
-
-        if let Ok(binary) = goblin::elf::Elf::parse(&buffer.as_slice()) {
-            for ph in &binary.program_headers {
-                if ph.p_type == PT_LOAD && ph.p_filesz > 0 {
-                    log::debug!("Found loadable segment containing:");
-
-                    let sector: core::ops::Range<u32> =
-                        ph.p_offset as u32..ph.p_offset as u32 + ph.p_filesz as u32;
-
-                    for sh in &binary.section_headers {
-                        if sector.contains_range(
-                            &(sh.sh_offset as u32..sh.sh_offset as u32 + sh.sh_size as u32),
-                        ) {
-                            log::debug!("{:?}", &binary.shdr_strtab[sh.sh_name]);
-                            for line in hexdump::hexdump_iter(
-                                &buffer[sh.sh_offset as usize..][..sh.sh_size as usize],
-                            ) {
-                                log::trace!("{}", line);
-                            }
-                        }
-                    }
-
-                    loader.add_data(
-                        ph.p_paddr as u32,
-                        &buffer[ph.p_offset as usize..][..ph.p_filesz as usize],
-                    )?;
-                }
-            }
-        }
-        Ok(())
+        stream::download_segments(
+            session,
+            &mut file,
+            &segments,
+            memory_map,
+            self.translation.as_ref(),
+        )
     }
 }
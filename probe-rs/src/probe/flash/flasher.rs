@@ -1,13 +1,20 @@
+use super::algorithm_fault::{FaultReason, FlashAlgorithmFault, Routine};
+use super::progress::{ProgressMailbox, STALL_TIMEOUT};
+use crate::collection::cores::m4::{Demcr, Dfsr};
 use crate::coresight::access_ports::AccessPortError;
 use crate::memory::MI;
 use crate::probe::debug_probe::DebugProbeError;
 use crate::probe::debug_probe::MasterProbe;
-use crate::target::Target;
+use crate::target::{quirks::Quirk, Architecture, CoreStatus, Target};
 use std::error::Error;
 use std::fmt;
+use std::time::Instant;
 
 use super::*;
 
+/// The vector catch bit of DFSR, cleared by writing it.
+const DFSR_VCATCH: u32 = 1 << 3;
+
 const ANALYZER: [u32; 49] = [
     0x2780_b5f0,
     0x2500_4684,
@@ -76,6 +83,8 @@ pub struct FlashAlgorithm {
     pub pc_erase_sector: u32,
     /// Address of the `EraseAll()` entry point. Optional.
     pub pc_erase_all: Option<u32>,
+    /// Address of a word in RAM `EraseAll()` reports its progress in, see `progress`. Optional.
+    pub progress_address: Option<u32>,
     /// Initial value of the R9 register for calling flash algo entry points, which
     /// determines where the position-independant data resides.
     pub static_base: u32,
@@ -186,6 +195,76 @@ pub enum FlasherError {
     AccessPort(AccessPortError),
     DebugProbe(DebugProbeError),
     AddressNotInRegion(u32, FlashRegion),
+    /// A routine of the flash algorithm faulted or did not return.
+    AlgorithmFault(FlashAlgorithmFault),
+}
+
+impl Error for FlasherError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FlasherError::AccessPort(ref e) => Some(e),
+            FlasherError::DebugProbe(ref e) => Some(e),
+            FlasherError::AlgorithmFault(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FlasherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FlasherError::*;
+
+        match self {
+            Init(code) => write!(f, "Init() of the flash algorithm failed with {}.", code),
+            Uninit(code) => write!(f, "UnInit() of the flash algorithm failed with {}.", code),
+            EraseAll(code) => write!(f, "Erasing the whole flash failed with {}.", code),
+            EraseAllNotSupported => write!(
+                f,
+                "The flash algorithm does not support erasing the whole flash."
+            ),
+            EraseSector(code, address) => write!(
+                f,
+                "Erasing the sector at 0x{:08x} failed with {}.",
+                address, code
+            ),
+            ProgramPage(code, address) => write!(
+                f,
+                "Programming the page at 0x{:08x} failed with {}.",
+                address, code
+            ),
+            InvalidBufferNumber(number, count) => write!(
+                f,
+                "Page buffer {} does not exist, the flash algorithm has {}.",
+                number, count
+            ),
+            UnalignedFlashWriteAddress => write!(
+                f,
+                "The address is not aligned to the minimum program length."
+            ),
+            UnalignedPhraseLength => write!(
+                f,
+                "The length is not a multiple of the minimum program length."
+            ),
+            ProgramPhrase(code, address) => write!(
+                f,
+                "Programming the phrase at 0x{:08x} failed with {}.",
+                address, code
+            ),
+            AnalyzerNotSupported => {
+                write!(f, "The flash algorithm does not support the CRC analyzer.")
+            }
+            SizeNotPowerOf2 => write!(f, "The size is not a power of 2."),
+            AddressNotMultipleOfSize => write!(f, "The address is not a multiple of the size."),
+            AccessPort(ref e) => e.fmt(f),
+            DebugProbe(ref e) => e.fmt(f),
+            AddressNotInRegion(address, region) => write!(
+                f,
+                "The address 0x{:08x} is not in the flash region 0x{:08x}..0x{:08x}.",
+                address, region.range.start, region.range.end
+            ),
+            AlgorithmFault(ref e) => e.fmt(f),
+        }
+    }
 }
 
 impl From<DebugProbeError> for FlasherError {
@@ -232,10 +311,20 @@ impl<'a> Flasher<'a> {
         &self.flash_algorithm
     }
 
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
     pub fn double_buffering_supported(&self) -> bool {
         self.double_buffering_supported
     }
 
+    /// Fails with `DebugProbeError::Timeout` once the cancellation token of the probe was
+    /// cancelled, so the loops over many sectors stop between them.
+    pub fn check_cancelled(&self) -> Result<(), FlasherError> {
+        Ok(self.probe.cancellation_token().check()?)
+    }
+
     pub fn init<'b, 's: 'b, O: Operation>(
         &'s mut self,
         mut address: Option<u32>,
@@ -245,36 +334,40 @@ impl<'a> Flasher<'a> {
         let flasher = self;
         let algo = flasher.flash_algorithm;
 
-        use capstone::arch::*;
-        let cs = capstone::Capstone::new()
-            .arm()
-            .mode(arm::ArchMode::Thumb)
-            .endian(capstone::Endian::Little)
-            .build()
-            .unwrap();
-        let i = algo
-            .instructions
-            .iter()
-            .map(|i| {
-                [
-                    *i as u8,
-                    (*i >> 8) as u8,
-                    (*i >> 16) as u8,
-                    (*i >> 24) as u8,
-                ]
-            })
-            .collect::<Vec<[u8; 4]>>()
-            .iter()
-            .flatten()
-            .copied()
-            .collect::<Vec<u8>>();
-
-        let instructions = cs
-            .disasm_all(i.as_slice(), u64::from(algo.load_address))
-            .unwrap();
-
-        for instruction in instructions.iter() {
-            log::debug!("{}", instruction);
+        // Capstone is set up for Thumb code, the algorithms of other cores are not logged.
+        #[cfg(feature = "debug")]
+        if flasher.target.core.architecture() == Architecture::Arm {
+            use capstone::arch::*;
+            let cs = capstone::Capstone::new()
+                .arm()
+                .mode(arm::ArchMode::Thumb)
+                .endian(capstone::Endian::Little)
+                .build()
+                .unwrap();
+            let i = algo
+                .instructions
+                .iter()
+                .map(|i| {
+                    [
+                        *i as u8,
+                        (*i >> 8) as u8,
+                        (*i >> 16) as u8,
+                        (*i >> 24) as u8,
+                    ]
+                })
+                .collect::<Vec<[u8; 4]>>()
+                .iter()
+                .flatten()
+                .copied()
+                .collect::<Vec<u8>>();
+
+            let instructions = cs
+                .disasm_all(i.as_slice(), u64::from(algo.load_address))
+                .unwrap();
+
+            for instruction in instructions.iter() {
+                log::debug!("{}", instruction);
+            }
         }
 
         if address.is_none() {
@@ -286,6 +379,14 @@ impl<'a> Flasher<'a> {
             );
         }
 
+        // The other cores could run code from the flash while it is programmed.
+        log::debug!("Halting the secondary cores.");
+        flasher
+            .target
+            .for_each_secondary_core(&mut flasher.probe, |core, probe| {
+                core.halt(probe).map(|_| ())
+            })?;
+
         // TODO: Halt & reset target.
         log::debug!("Halting core.");
         let cpu_info = flasher.target.core.halt(&mut flasher.probe);
@@ -297,7 +398,14 @@ impl<'a> Flasher<'a> {
         log::debug!("Reset and halt");
         flasher.target.core.reset_and_halt(&mut flasher.probe)?;
 
-        // TODO: Possible special preparation of the target such as enabling faster clocks for the flash e.g.
+        for action in &flasher.target.pre_program {
+            log::debug!("Running pre-program action {:?}", action);
+            action.run(
+                flasher.target.core.as_ref(),
+                &mut flasher.probe,
+                algo.begin_stack,
+            )?;
+        }
 
         // Load flash algorithm code into target RAM.
         log::debug!(
@@ -314,6 +422,16 @@ impl<'a> Flasher<'a> {
         assert_eq!(&algo.instructions, &data.as_slice());
         log::debug!("RAM contents match flashing algo blob.");
 
+        // A fault of the algorithm halts the core, instead of running the HardFault handler of
+        // the firmware. Only Cortex-M cores have flash algorithms.
+        if flasher.target.core.architecture() == Architecture::Arm {
+            let mut demcr = Demcr(flasher.probe.read32(Demcr::ADDRESS)?);
+            demcr.set_vc_harderr(true);
+            flasher.probe.write32(Demcr::ADDRESS, demcr.into())?;
+            // A vector catch before flashing would be taken for one of the algorithm.
+            flasher.probe.write32(Dfsr::ADDRESS, DFSR_VCATCH)?;
+        }
+
         log::debug!("Preparing Flasher for region:");
         log::debug!("{:#?}", &flasher.region);
         log::debug!(
@@ -388,8 +506,15 @@ impl<'a> Flasher<'a> {
 
         let mut fb = FlashBuilder::new(self.region.range.start);
         fb.add_data(address, data).expect("Add Data failed");
-        fb.program(self, chip_erase, smart_flash, fast_verify, true)
-            .expect("Add Data failed");
+        fb.program(
+            self,
+            chip_erase,
+            smart_flash,
+            fast_verify,
+            true,
+            &mut |_| {},
+        )
+        .expect("Add Data failed");
 
         Ok(())
     }
@@ -405,6 +530,12 @@ pub struct ActiveFlasher<'a, O: Operation> {
 }
 
 impl<'a, O: Operation> ActiveFlasher<'a, O> {
+    /// Fails with `DebugProbeError::Timeout` once the cancellation token of the probe was
+    /// cancelled, so the loops over many pages stop between them.
+    pub fn check_cancelled(&self) -> Result<(), FlasherError> {
+        Ok(self.probe.cancellation_token().check()?)
+    }
+
     pub fn init(&mut self, address: Option<u32>, clock: Option<u32>) -> Result<(), FlasherError> {
         let algo = &self.flash_algorithm;
 
@@ -412,6 +543,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
         if let Some(pc_init) = algo.pc_init {
             log::debug!("Running init routine.");
             let result = self.call_function_and_wait(
+                Routine::Init,
                 pc_init,
                 address,
                 clock.or(Some(0)),
@@ -446,6 +578,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
 
         if let Some(pc_uninit) = algo.pc_uninit {
             let result = self.call_function_and_wait(
+                Routine::Uninit,
                 pc_uninit,
                 Some(O::operation()),
                 None,
@@ -459,6 +592,21 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
             }
         }
 
+        if self.target.core.architecture() == Architecture::Arm {
+            let mut demcr = Demcr(self.probe.read32(Demcr::ADDRESS)?);
+            demcr.set_vc_harderr(false);
+            self.probe.write32(Demcr::ADDRESS, demcr.into())?;
+        }
+
+        for action in &self.target.post_program {
+            log::debug!("Running post-program action {:?}", action);
+            action.run(
+                self.target.core.as_ref(),
+                &mut self.probe,
+                self.flash_algorithm.begin_stack,
+            )?;
+        }
+
         Ok(Flasher {
             target: self.target,
             probe: self.probe,
@@ -470,6 +618,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
 
     fn call_function_and_wait(
         &mut self,
+        routine: Routine,
         pc: u32,
         r0: Option<u32>,
         r1: Option<u32>,
@@ -478,7 +627,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
         init: bool,
     ) -> Result<u32, FlasherError> {
         self.call_function(pc, r0, r1, r2, r3, init)?;
-        self.wait_for_completion()
+        self.wait_for_completion(routine)
     }
 
     fn call_function(
@@ -502,6 +651,11 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
 
         let algo = &self.flash_algorithm;
         let regs = self.target.core.registers();
+        // The algorithm returns to the breakpoint at its start, in Thumb state on Cortex-M cores.
+        let return_address = match self.target.core.architecture() {
+            Architecture::Arm => algo.load_address + 1,
+            Architecture::Xtensa => algo.load_address,
+        };
 
         [
             (regs.PC, Some(pc)),
@@ -511,7 +665,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
             (regs.R3, r3),
             (regs.R9, if init { Some(algo.static_base) } else { None }),
             (regs.SP, if init { Some(algo.begin_stack) } else { None }),
-            (regs.LR, Some(algo.load_address + 1)),
+            (regs.LR, Some(return_address)),
         ]
         .iter()
         .map(|(addr, value)| {
@@ -537,21 +691,98 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
         Ok(())
     }
 
-    pub fn wait_for_completion(&mut self) -> Result<u32, FlasherError> {
+    /// Waits for the routine called last to return, and returns its result.
+    ///
+    /// If the routine faults or runs longer than its timeout, the state of the core is
+    /// returned as a `FlasherError::AlgorithmFault`.
+    pub fn wait_for_completion(&mut self, routine: Routine) -> Result<u32, FlasherError> {
+        self.wait_with_progress(routine, None, &mut |_| {})
+    }
+
+    /// Waits for the routine called last to return like `wait_for_completion`, polling the
+    /// progress it writes to `mailbox`, if given, and passing it to `progress` when it changes.
+    fn wait_with_progress(
+        &mut self,
+        routine: Routine,
+        mut mailbox: Option<ProgressMailbox>,
+        progress: &mut dyn FnMut(u32),
+    ) -> Result<u32, FlasherError> {
         log::debug!("Waiting for routine call completion.");
         let regs = self.target.core.registers();
+        let deadline = Instant::now() + routine.timeout();
+
+        // Erasing a large flash takes longer than the core usually takes to halt, so the
+        // wait only ends when the routine is done, gets stuck, or the cancellation token of
+        // the probe aborts it.
+        loop {
+            match self.target.core.wait_for_core_halted(&mut self.probe) {
+                Ok(()) => break,
+                Err(DebugProbeError::Timeout) => {
+                    self.probe.cancellation_token().check()?;
+
+                    if self.target.core.status(&mut self.probe)? == CoreStatus::LockedUp {
+                        return Err(self.fault(routine, FaultReason::LockedUp));
+                    }
 
-        while self
-            .target
-            .core
-            .wait_for_core_halted(&mut self.probe)
-            .is_err()
-        {}
+                    // A routine reporting its progress may take as long as it keeps reporting.
+                    let now = Instant::now();
+                    let reason = match &mut mailbox {
+                        Some(mailbox) => {
+                            let value = self.probe.read32(mailbox.address)?;
+                            if let Some(percent) = mailbox.update(value, now) {
+                                progress(percent);
+                            }
+                            if mailbox.is_stalled(now) {
+                                Some(FaultReason::Stalled(STALL_TIMEOUT))
+                            } else {
+                                None
+                            }
+                        }
+                        None if now > deadline => Some(FaultReason::Timeout(routine.timeout())),
+                        None => None,
+                    };
+                    if let Some(reason) = reason {
+                        return Err(self.fault(routine, reason));
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // The routine returns to the breakpoint at the start of the algorithm, a Cortex-M core
+        // halting anywhere else caught a HardFault or hit another breakpoint.
+        if self.target.core.architecture() == Architecture::Arm {
+            let pc = self.target.core.read_core_reg(&mut self.probe, regs.PC)?;
+            if pc != self.flash_algorithm.load_address {
+                let reason = if Dfsr(self.probe.read32(Dfsr::ADDRESS)?).vcatch() {
+                    FaultReason::HardFault
+                } else {
+                    FaultReason::UnexpectedHalt
+                };
+                return Err(self.fault(routine, reason));
+            }
+        }
 
         let r = self.target.core.read_core_reg(&mut self.probe, regs.R0)?;
         Ok(r)
     }
 
+    /// Captures the state of the core after `routine` failed.
+    fn fault(&mut self, routine: Routine, reason: FaultReason) -> FlasherError {
+        log::debug!("{} of the flash algorithm {}.", routine, reason);
+
+        match FlashAlgorithmFault::capture(
+            self.target.core.as_ref(),
+            &mut self.probe,
+            self.flash_algorithm,
+            routine,
+            reason,
+        ) {
+            Ok(fault) => FlasherError::AlgorithmFault(fault),
+            Err(e) => e.into(),
+        }
+    }
+
     pub fn read_block32(&mut self, address: u32, data: &mut [u32]) -> Result<(), FlasherError> {
         self.probe.read_block32(address, data)?;
         Ok(())
@@ -565,12 +796,46 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
 
 impl<'a> ActiveFlasher<'a, Erase> {
     pub fn erase_all(&mut self) -> Result<(), FlasherError> {
+        self.erase_all_with_progress(&mut |_| {})
+    }
+
+    /// Erases the whole flash like `erase_all`, passing the progress in percent to `progress`
+    /// if the flash algorithm reports it.
+    pub fn erase_all_with_progress(
+        &mut self,
+        progress: &mut dyn FnMut(u32),
+    ) -> Result<(), FlasherError> {
+        let span = tracing::debug_span!("flash", operation = "erase_all");
+        let _enter = span.enter();
+
         let flasher = self;
         let algo = flasher.flash_algorithm;
 
+        // The flash controller of the chip can not mass erase, but the sectors can be erased.
+        if flasher.target.has_quirk(Quirk::NoChipErase) {
+            let sector_size = flasher.region.sector_size as usize;
+            return flasher
+                .region
+                .range
+                .clone()
+                .step_by(sector_size)
+                .try_for_each(|address| {
+                    flasher.check_cancelled()?;
+                    flasher.erase_sector(address)
+                });
+        }
+
         if let Some(pc_erase_all) = algo.pc_erase_all {
-            let result =
-                flasher.call_function_and_wait(pc_erase_all, None, None, None, None, false)?;
+            let mailbox = match algo.progress_address {
+                Some(address) => {
+                    flasher.probe.write32(address, 0)?;
+                    Some(ProgressMailbox::new(address, Instant::now()))
+                }
+                None => None,
+            };
+
+            flasher.call_function(pc_erase_all, None, None, None, None, false)?;
+            let result = flasher.wait_with_progress(Routine::EraseAll, mailbox, progress)?;
 
             if result != 0 {
                 Err(FlasherError::EraseAll(result))
@@ -583,11 +848,15 @@ impl<'a> ActiveFlasher<'a, Erase> {
     }
 
     pub fn erase_sector(&mut self, address: u32) -> Result<(), FlasherError> {
+        let span = tracing::debug_span!("flash", operation = "erase_sector", address);
+        let _enter = span.enter();
+
         log::debug!("Erasing sector at address 0x{:08x}.", address);
         let flasher = self;
         let algo = flasher.flash_algorithm;
 
         let result = flasher.call_function_and_wait(
+            Routine::EraseSector,
             algo.pc_erase_sector,
             Some(address),
             None,
@@ -615,24 +884,8 @@ impl<'a> ActiveFlasher<'a, Erase> {
                 .probe
                 .write_block32(algo.analyzer_address, &ANALYZER)?;
 
-            for (address, mut size) in sectors {
-                let size_value = {
-                    let mut ndx = 0;
-                    while 1 < size {
-                        size >>= 1;
-                        ndx += 1;
-                    }
-                    ndx
-                };
-                let address_value = address / size;
-                if 1 << size_value != size {
-                    return Err(FlasherError::SizeNotPowerOf2);
-                }
-                if address % size != 0 {
-                    return Err(FlasherError::AddressNotMultipleOfSize);
-                }
-                let value = size_value | (address_value << 16);
-                data.push(value);
+            for &(address, size) in sectors {
+                data.push(analyzer_request(address, size)?);
             }
 
             flasher
@@ -642,6 +895,7 @@ impl<'a> ActiveFlasher<'a, Erase> {
             let analyzer_address = algo.analyzer_address;
             let begin_data = algo.begin_data;
             let result = flasher.call_function_and_wait(
+                Routine::Analyzer,
                 analyzer_address,
                 Some(begin_data),
                 Some(data.len() as u32),
@@ -662,8 +916,24 @@ impl<'a> ActiveFlasher<'a, Erase> {
     }
 }
 
+/// Encodes a block for the analyzer as the log2 of its size in the lower half word and the
+/// block number in the upper one.
+fn analyzer_request(address: u32, size: u32) -> Result<u32, FlasherError> {
+    if !size.is_power_of_two() {
+        return Err(FlasherError::SizeNotPowerOf2);
+    }
+    if address % size != 0 {
+        return Err(FlasherError::AddressNotMultipleOfSize);
+    }
+
+    Ok(size.trailing_zeros() | ((address / size) << 16))
+}
+
 impl<'a> ActiveFlasher<'a, Program> {
     pub fn program_page(&mut self, address: u32, bytes: &[u8]) -> Result<(), FlasherError> {
+        let span = tracing::debug_span!("flash", operation = "program_page", address);
+        let _enter = span.enter();
+
         let flasher = self;
         let algo = flasher.flash_algorithm;
 
@@ -673,6 +943,7 @@ impl<'a> ActiveFlasher<'a, Program> {
         flasher.probe.write_block8(algo.begin_data, bytes)?;
 
         let result = flasher.call_function_and_wait(
+            Routine::ProgramPage,
             algo.pc_program_page,
             Some(address),
             Some(bytes.len() as u32),
@@ -768,6 +1039,7 @@ impl<'a> ActiveFlasher<'a, Program> {
         flasher.probe.write_block8(algo.begin_data, bytes)?;
 
         let result = flasher.call_function_and_wait(
+            Routine::ProgramPage,
             algo.pc_program_page,
             Some(address),
             Some(bytes.len() as u32),
@@ -783,3 +1055,24 @@ impl<'a> ActiveFlasher<'a, Program> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{analyzer_request, FlasherError};
+
+    #[test]
+    fn encodes_analyzer_requests() {
+        assert_eq!(
+            analyzer_request(0x0800_4000, 0x1000).unwrap(),
+            12 | (0x8004 << 16)
+        );
+        assert!(matches!(
+            analyzer_request(0x0800_4000, 0x1800),
+            Err(FlasherError::SizeNotPowerOf2)
+        ));
+        assert!(matches!(
+            analyzer_request(0x0800_0800, 0x1000),
+            Err(FlasherError::AddressNotMultipleOfSize)
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+//! Downloads which resume where an interrupted one left off.
+//!
+//! Programming a large image, e.g. to external flash, takes minutes. A journal on the host
+//! records each sector once it holds its new contents, so when the download is interrupted,
+//! a download of the same image to the same target only programs the remaining sectors.
+//!
+//! The journal is a text file, starting with a header which identifies the target and the
+//! image, followed by the address of each completed sector on its own line.
+
+use super::download::FileDownloadError;
+use super::loader::FlashLoader;
+use super::patch::{sector_contents, PatchSummary};
+use crate::session::Session;
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "probe-rs download journal";
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    completed: HashSet<u32>,
+}
+
+impl Journal {
+    /// Opens the journal at `path` for downloading `image` to `target`. A journal of another
+    /// image or target is started over.
+    pub fn open(path: &Path, target: &str, image: &[(u32, Vec<u8>)]) -> io::Result<Self> {
+        let header = format!("{} {} {:08x}", HEADER, target, image_checksum(image));
+
+        let completed = match fs::read_to_string(path) {
+            Ok(contents) => parse(&contents, &header),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let file = match completed {
+            Some(_) => OpenOptions::new().append(true).open(path)?,
+            None => {
+                let mut file = File::create(path)?;
+                writeln!(file, "{}", header)?;
+                file
+            }
+        };
+
+        Ok(Journal {
+            path: path.to_path_buf(),
+            file,
+            completed: completed.unwrap_or_default(),
+        })
+    }
+
+    /// The number of sectors completed by previous downloads.
+    pub fn completed(&self) -> usize {
+        self.completed.len()
+    }
+
+    pub fn is_completed(&self, sector: u32) -> bool {
+        self.completed.contains(&sector)
+    }
+
+    /// Records that the sector at `sector` holds its new contents.
+    pub fn record(&mut self, sector: u32) -> io::Result<()> {
+        if self.completed.insert(sector) {
+            writeln!(self.file, "{:08x}", sector)?;
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the journal once the download is complete.
+    pub fn finish(self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
+
+/// Programs the sectors of `image` which the journal does not record as completed yet, and
+/// records each of them once it is programmed.
+pub fn download_resumable(
+    session: &mut Session,
+    image: &[(u32, Vec<u8>)],
+    journal: &mut Journal,
+) -> Result<PatchSummary, FileDownloadError> {
+    let memory_map = session.target.memory_map.clone();
+    let sectors = sector_contents(&memory_map, image)?;
+
+    let remaining: Vec<_> = sectors
+        .iter()
+        .filter(|(address, _)| !journal.is_completed(**address))
+        .map(|(address, (_, data))| (*address, data))
+        .collect();
+
+    let mut loader = FlashLoader::new(&memory_map, false, false, false);
+    for (address, data) in &remaining {
+        loader.add_data(*address, data)?;
+    }
+    loader.on_sector_programmed(|address| {
+        // The download goes on, it just cannot resume after this sector if interrupted.
+        if let Err(e) = journal.record(address) {
+            log::warn!(
+                "Failed to record the sector at 0x{:08x} in the journal: {}",
+                address,
+                e
+            );
+        }
+    });
+    loader.commit(session)?;
+
+    Ok(PatchSummary {
+        sectors: sectors.len(),
+        changed: remaining.len(),
+    })
+}
+
+/// Returns the CRC32 of the addresses and the data of all blocks of an image.
+fn image_checksum(image: &[(u32, Vec<u8>)]) -> u32 {
+    let mut bytes = vec![];
+    for (address, data) in image {
+        bytes.extend_from_slice(&address.to_le_bytes());
+        bytes.extend_from_slice(data);
+    }
+
+    crc::crc32::checksum_ieee(&bytes)
+}
+
+/// Returns the completed sectors of a journal, or `None` if it has another header.
+fn parse(contents: &str, header: &str) -> Option<HashSet<u32>> {
+    let mut lines = contents.lines();
+
+    if lines.next()? != header {
+        return None;
+    }
+
+    // A line cut short by the interruption is skipped.
+    Some(
+        lines
+            .filter(|line| line.len() == 8)
+            .filter_map(|line| u32::from_str_radix(line, 16).ok())
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    #[test]
+    fn parses_journal() {
+        let header = "probe-rs download journal nrf52 12345678";
+        let completed = parse(&format!("{}\n00001000\n00002000\n0000", header), header).unwrap();
+
+        assert_eq!(completed.len(), 2);
+        assert!(completed.contains(&0x2000));
+        assert!(parse("probe-rs download journal nrf52 87654321\n", header).is_none());
+        assert!(parse("", header).is_none());
+    }
+}
@@ -1,7 +1,10 @@
+use crate::event::SessionEvent;
 use crate::session::Session;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::ops::Range;
+use std::time::Instant;
 
 use super::*;
 
@@ -85,12 +88,56 @@ pub struct FlashLoader<'a, 'b> {
     smart_flash: bool,
     trust_crc: bool,
     keep_unwritten: bool,
+    translation: Option<AddressTranslation>,
+    sector_programmed: Option<Box<dyn FnMut(u32) + 'a>>,
+}
+
+/// Moves the data added to a loader by an offset, e.g. to place an image linked for address 0
+/// behind a bootloader or an nRF SoftDevice.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressTranslation {
+    pub offset: u32,
+    /// The region of the bootloader, which the moved data must not overlap.
+    pub protected: Range<u32>,
+}
+
+impl AddressTranslation {
+    /// Moves the data to `base`, protecting the flash before it, which holds the bootloader.
+    pub fn behind_bootloader(memory_map: &[MemoryRegion], base: u32) -> Self {
+        let start = match FlashLoader::get_region_for_address(memory_map, base) {
+            Some(MemoryRegion::Flash(region)) => region.range.start,
+            _ => base,
+        };
+
+        AddressTranslation {
+            offset: base,
+            protected: start..base,
+        }
+    }
+
+    /// Returns the address `length` bytes at `address` are moved to.
+    pub fn apply(&self, address: u32, length: usize) -> Result<u32, FlashLoaderError> {
+        // The moved data must still fit into the address space.
+        let address = match address.checked_add(self.offset) {
+            Some(moved) if u64::from(moved) + length as u64 <= 1 << 32 => moved,
+            _ => return Err(FlashLoaderError::AddressOverflow(address)),
+        };
+        let end = u64::from(address) + length as u64;
+
+        if u64::from(self.protected.start) < end && address < self.protected.end {
+            Err(FlashLoaderError::ProtectedRegion(address))
+        } else {
+            Ok(address)
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum FlashLoaderError {
     MemoryRegionNotDefined(u32), // Contains the faulty address.
     MemoryRegionNotFlash(u32),   // Contains the faulty address.
+    ProtectedRegion(u32),        // Contains the faulty address.
+    AddressOverflow(u32),        // Contains the faulty address.
     NoFlashLoaderAlgorithmAttached,
 }
 
@@ -103,6 +150,8 @@ impl fmt::Display for FlashLoaderError {
         match self {
             MemoryRegionNotDefined(addr) => write!(f, "Trying to access memory at address {:#08x}, which is not inside any defined memory region.", addr),
             MemoryRegionNotFlash(addr) => write!(f, "Trying to access flash at address {:#08x}, which is not inside any defined flash region.", addr),
+            ProtectedRegion(addr) => write!(f, "Trying to write flash at address {:#08x}, which overlaps the protected bootloader region.", addr),
+            AddressOverflow(addr) => write!(f, "Trying to move the data at address {:#08x} past the end of the address space.", addr),
             NoFlashLoaderAlgorithmAttached => write!(f, "Trying to write flash, but no flash loader algorithm is attached."),
         }
     }
@@ -123,9 +172,22 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
             smart_flash,
             trust_crc,
             keep_unwritten,
+            translation: None,
+            sector_programmed: None,
         }
     }
 
+    /// Moves all data added afterwards as given by `translation`.
+    pub fn set_translation(&mut self, translation: AddressTranslation) {
+        self.translation = Some(translation);
+    }
+
+    /// Calls `callback` with the address of each sector once it holds its new contents, e.g. to
+    /// record the progress of a long download.
+    pub fn on_sector_programmed(&mut self, callback: impl FnMut(u32) + 'a) {
+        self.sector_programmed = Some(Box::new(callback));
+    }
+
     /// Clear all state variables.
     fn reset_state(&mut self) {
         self.builders = HashMap::new();
@@ -138,6 +200,10 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
     /// `address` is the address where the first byte of `data` is located.
     /// `data` is an iterator of u8 bytes to be written at given `address` and onwards.
     pub fn add_data(&mut self, mut address: u32, data: &'b [u8]) -> Result<(), FlashLoaderError> {
+        if let Some(translation) = &self.translation {
+            address = translation.apply(address, data.len())?;
+        }
+
         let size = data.len();
         let mut remaining = size;
         while remaining > 0 {
@@ -205,11 +271,24 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
     /// algorithm for the first region doesn't actually erase the entire chip (all regions).
 
     /// After calling this method, the loader instance can be reused to program more data.
-    pub fn commit(&mut self, session: &mut Session) -> Result<(), FlashLoaderError> {
+    /// Returns what was erased and programmed.
+    pub fn commit(&mut self, session: &mut Session) -> Result<FlashReport, FlashLoaderError> {
+        let start = Instant::now();
         let target = &session.target;
         let probe = &mut session.probe;
+        let events = &mut session.events;
         if let Some(flash_algorithm) = session.flash_algorithm.as_ref() {
             let mut did_chip_erase = false;
+            let mut report = FlashReport::default();
+            let mut ignore_sector = |_| {};
+            let callback: &mut dyn FnMut(u32) = match &mut self.sector_programmed {
+                Some(callback) => callback.as_mut(),
+                None => &mut ignore_sector,
+            };
+            let mut sector_programmed = |address| {
+                callback(address);
+                events.emit(SessionEvent::SectorProgrammed(address));
+            };
 
             // Iterate over builders we've created and program the data.
             let mut builders: Vec<(&FlashRegion, &FlashBuilder)> = self.builders.iter().collect();
@@ -227,7 +306,7 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
                 } else {
                     false
                 });
-                builder
+                report += builder
                     .1
                     .program(
                         Flasher::new(target, probe, flash_algorithm, builder.0),
@@ -235,6 +314,7 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
                         self.smart_flash,
                         self.trust_crc,
                         self.keep_unwritten,
+                        &mut sector_programmed,
                     )
                     .unwrap();
                 did_chip_erase = true;
@@ -243,13 +323,50 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
             // Clear state to allow reuse.
             self.reset_state();
 
-            Ok(())
+            report.total_time = start.elapsed();
+            Ok(report)
         } else {
             Err(FlashLoaderError::NoFlashLoaderAlgorithmAttached)
         }
     }
 }
 
+#[test]
+fn translation_rejects_protected_region() {
+    let translation = AddressTranslation {
+        offset: 0x0800_4000,
+        protected: 0x0800_0000..0x0800_4000,
+    };
+
+    assert_eq!(translation.apply(0x100, 4).unwrap(), 0x0800_4100);
+
+    let translation = AddressTranslation {
+        offset: 0x1000,
+        protected: 0x0000..0x2000,
+    };
+
+    assert!(translation.apply(0x0FF0, 0x20).is_err());
+    assert_eq!(translation.apply(0x1000, 0x20).unwrap(), 0x2000);
+}
+
+#[test]
+fn translation_rejects_overflow() {
+    let translation = AddressTranslation {
+        offset: 0x0800_4000,
+        protected: 0x0800_0000..0x0800_4000,
+    };
+
+    match translation.apply(0xFFFF_FFF0, 0x20) {
+        Err(FlashLoaderError::AddressOverflow(0xFFFF_FFF0)) => {}
+        result => panic!("Unexpected result: {:?}", result),
+    }
+    match translation.apply(0xF7FF_BFF0, 0x20) {
+        Err(FlashLoaderError::AddressOverflow(0xF7FF_BFF0)) => {}
+        result => panic!("Unexpected result: {:?}", result),
+    }
+    assert_eq!(translation.apply(0xF7FF_BFF0, 0x10).unwrap(), 0xFFFF_FFF0);
+}
+
 #[test]
 fn ranges_works() {
     let r = ranges([0, 1, 3, 5, 6, 7].iter().cloned());
@@ -106,11 +106,42 @@ impl FlashRegion {
     }
 }
 
+#[cfg(test)]
+impl FlashRegion {
+    /// A readable boot flash of `range`, erased to 0xFF, with blocks and sectors of
+    /// `sector_size` and pages of 0x100 bytes.
+    pub(crate) fn for_test(range: core::ops::Range<u32>, sector_size: u32) -> Self {
+        FlashRegion {
+            range,
+            is_boot_memory: true,
+            is_testable: true,
+            blocksize: sector_size,
+            sector_size,
+            page_size: 0x100,
+            phrase_size: 0x100,
+            erase_all_weight: 0.0,
+            erase_sector_weight: 0.0,
+            program_page_weight: 0.0,
+            erased_byte_value: 0xFF,
+            access: Access::RX,
+            are_erased_sectors_readable: true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct RamRegion {
     pub range: core::ops::Range<u32>,
     pub is_boot_memory: bool,
     pub is_testable: bool,
+    /// Whether the region is read through the core, see `memory::core_access`.
+    #[serde(default)]
+    pub read_through_core: bool,
+    /// The address the access port reaches a tightly-coupled memory at, if the system bus has
+    /// an alias of it, like the TCMs the Cortex-R5 cores of the Zynq UltraScale+ see at 0 and
+    /// the system bus at 0xFFE00000. TCMs without an alias are read through the core.
+    #[serde(default)]
+    pub bus_address: Option<u32>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -121,6 +152,17 @@ pub struct RomRegion {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct DeviceRegion {
     pub range: core::ops::Range<u32>,
+    /// Whether the peripherals in the region can be read without harm. Reading some of them
+    /// locks up the bus or has side effects, like popping a FIFO.
+    #[serde(default = "default_readable")]
+    pub readable: bool,
+    /// Whether the region is read through the core, see `memory::core_access`.
+    #[serde(default)]
+    pub read_through_core: bool,
+}
+
+fn default_readable() -> bool {
+    true
 }
 
 pub struct SectorInfo {
@@ -164,3 +206,132 @@ pub enum MemoryRegion {
     Flash(FlashRegion),
     Device(DeviceRegion),
 }
+
+impl MemoryRegion {
+    pub fn range(&self) -> &core::ops::Range<u32> {
+        match self {
+            MemoryRegion::Ram(region) => &region.range,
+            MemoryRegion::Rom(region) => &region.range,
+            MemoryRegion::Flash(region) => &region.range,
+            MemoryRegion::Device(region) => &region.range,
+        }
+    }
+
+    /// The kind of the region, as shown next to the memory read from it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MemoryRegion::Ram(_) => "RAM",
+            MemoryRegion::Rom(_) => "ROM",
+            MemoryRegion::Flash(_) => "Flash",
+            MemoryRegion::Device(_) => "Peripheral",
+        }
+    }
+
+    /// Whether the region is read through the core instead of the access port.
+    pub fn reads_through_core(&self) -> bool {
+        match self {
+            MemoryRegion::Ram(region) => region.read_through_core,
+            MemoryRegion::Device(region) => region.read_through_core,
+            _ => false,
+        }
+    }
+
+    /// Returns the address the access port reaches `address` of the region at, which differs
+    /// for tightly-coupled memories with an alias on the system bus.
+    pub fn bus_address(&self, address: u32) -> u32 {
+        match self {
+            MemoryRegion::Ram(RamRegion {
+                range,
+                bus_address: Some(bus_address),
+                ..
+            }) => bus_address + (address - range.start),
+            _ => address,
+        }
+    }
+
+    /// Whether the region can be read without harm, see `DeviceRegion::readable`.
+    pub fn is_readable(&self) -> bool {
+        match self {
+            MemoryRegion::Device(region) => region.readable,
+            _ => true,
+        }
+    }
+}
+
+/// Splits `range` at the borders of the regions of the memory map, returning each part with
+/// the region it is in, or `None` for parts no region covers.
+pub fn regions_in(
+    memory_map: &[MemoryRegion],
+    range: core::ops::Range<u32>,
+) -> Vec<(core::ops::Range<u32>, Option<&MemoryRegion>)> {
+    let mut parts = vec![];
+    let mut start = range.start;
+
+    while start < range.end {
+        let part = match memory_map
+            .iter()
+            .find(|region| region.range().contains(&start))
+        {
+            Some(region) => (start..region.range().end.min(range.end), Some(region)),
+            None => {
+                let next = memory_map
+                    .iter()
+                    .map(|region| region.range().start)
+                    .filter(|&region_start| region_start > start)
+                    .min()
+                    .unwrap_or(range.end);
+
+                (start..next.min(range.end), None)
+            }
+        };
+
+        start = part.0.end;
+        parts.push(part);
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod test {
+    use super::{regions_in, DeviceRegion, MemoryRegion, RamRegion};
+
+    #[test]
+    fn splits_ranges_at_regions() {
+        let ram = MemoryRegion::Ram(RamRegion {
+            range: 0x2000_0000..0x2000_1000,
+            is_boot_memory: false,
+            is_testable: true,
+            read_through_core: false,
+            bus_address: None,
+        });
+        let device = MemoryRegion::Device(DeviceRegion {
+            range: 0x2000_2000..0x2000_3000,
+            readable: false,
+            read_through_core: false,
+        });
+        let memory_map = vec![ram.clone(), device.clone()];
+
+        assert_eq!(
+            regions_in(&memory_map, 0x2000_0800..0x2000_2800),
+            vec![
+                (0x2000_0800..0x2000_1000, Some(&ram)),
+                (0x2000_1000..0x2000_2000, None),
+                (0x2000_2000..0x2000_2800, Some(&device)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reaches_tcms_at_their_alias() {
+        let atcm = MemoryRegion::Ram(RamRegion {
+            range: 0..0x1_0000,
+            is_boot_memory: true,
+            is_testable: true,
+            read_through_core: false,
+            bus_address: Some(0xFFE0_0000),
+        });
+
+        assert_eq!(atcm.bus_address(0x100), 0xFFE0_0100);
+    }
+}
@@ -2,14 +2,40 @@
 // which follows the logic of the [pyOCD debugger](https://github.com/mbedmicro/pyOCD) project.
 // Copyright (c) for that code 2015-2019 Arm Limited under the the Apache 2.0 license.
 
+pub mod algorithm_fault;
+pub mod algorithm_test;
 pub mod builder;
+pub mod checksum;
+pub mod config_region;
 pub mod download;
 pub mod flasher;
+pub mod journal;
 pub mod loader;
 pub mod memory;
+pub mod patch;
+pub mod plan;
+pub mod progress;
+pub mod protection;
+pub mod report;
+pub mod srec;
+pub mod stream;
+pub mod uf2;
+pub mod usage;
 
+pub use algorithm_fault::*;
+pub use algorithm_test::*;
 pub use builder::*;
+pub use checksum::*;
+pub use config_region::*;
 pub use download::*;
 pub use flasher::*;
+pub use journal::*;
 pub use loader::*;
 pub use memory::*;
+pub use patch::*;
+pub use plan::*;
+pub use progress::*;
+pub use protection::*;
+pub use report::*;
+pub use stream::*;
+pub use usage::*;
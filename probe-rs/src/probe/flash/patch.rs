@@ -0,0 +1,146 @@
+//! Programming only the sectors an image changes.
+//!
+//! Erasing and programming take much longer than reading, so when a large image changes little
+//! between builds, comparing each sector first and skipping the unchanged ones is a lot faster.
+//! The flash is either read back from the target or assumed to hold a previously programmed
+//! image.
+
+use super::download::FileDownloadError;
+use super::loader::{FlashLoader, FlashLoaderError};
+use super::memory::{Access, FlashRegion, MemoryRegion};
+use crate::memory::MI;
+use crate::session::Session;
+
+use std::collections::BTreeMap;
+
+/// What the flash is compared with.
+#[derive(Debug, Clone, Copy)]
+pub enum PatchBase<'a> {
+    /// The flash is read back from the target.
+    ReadBack,
+    /// The flash holds this image, as the address and the data of each block. Sectors it does
+    /// not touch are assumed to be erased.
+    Previous(&'a [(u32, Vec<u8>)]),
+}
+
+/// The number of sectors an image touches and how many of them were programmed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatchSummary {
+    pub sectors: usize,
+    pub changed: usize,
+}
+
+/// Programs the sectors in which `image` differs from `base`.
+///
+/// Each changed sector is erased and programmed as a whole, with the bytes the image does not
+/// cover erased, so the flash ends up the same as when programming the whole image.
+pub fn download_patch(
+    session: &mut Session,
+    image: &[(u32, Vec<u8>)],
+    base: PatchBase,
+) -> Result<PatchSummary, FileDownloadError> {
+    let memory_map = session.target.memory_map.clone();
+    let sectors = sector_contents(&memory_map, image)?;
+
+    let previous = match base {
+        PatchBase::Previous(previous) => Some(sector_contents(&memory_map, previous)?),
+        PatchBase::ReadBack => None,
+    };
+
+    let mut changed = vec![];
+    for (&address, (region, data)) in &sectors {
+        let current = match &previous {
+            Some(previous) => Some(
+                previous
+                    .get(&address)
+                    .map(|(_, data)| data.clone())
+                    .unwrap_or_else(|| vec![region.erased_byte_value; data.len()]),
+            ),
+            None if region.access.contains(Access::R) && region.are_erased_sectors_readable => {
+                let mut current = vec![0; data.len()];
+                session.probe.read_block8(address, &mut current)?;
+                Some(current)
+            }
+            // Sectors which cannot be read are always programmed, as are those of chips on which
+            // reading an erased sector faults, e.g. because of ECC.
+            None => None,
+        };
+
+        if current.as_ref() != Some(data) {
+            changed.push((address, data));
+        }
+    }
+
+    let mut loader = FlashLoader::new(&memory_map, false, false, false);
+    for (address, data) in &changed {
+        loader.add_data(*address, data)?;
+    }
+    loader.commit(session)?;
+
+    Ok(PatchSummary {
+        sectors: sectors.len(),
+        changed: changed.len(),
+    })
+}
+
+/// Returns the contents the image gives each flash sector it touches, by sector address,
+/// with the bytes it does not cover erased.
+pub(crate) fn sector_contents<'a>(
+    memory_map: &'a [MemoryRegion],
+    image: &[(u32, Vec<u8>)],
+) -> Result<BTreeMap<u32, (&'a FlashRegion, Vec<u8>)>, FlashLoaderError> {
+    let mut sectors = BTreeMap::new();
+
+    for (address, data) in image {
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let address = address + offset as u32;
+            let region = match FlashLoader::get_region_for_address(memory_map, address) {
+                Some(MemoryRegion::Flash(region)) => region,
+                Some(_) => return Err(FlashLoaderError::MemoryRegionNotFlash(address)),
+                None => return Err(FlashLoaderError::MemoryRegionNotDefined(address)),
+            };
+            let sector = region
+                .get_sector_info(address)
+                .ok_or(FlashLoaderError::MemoryRegionNotFlash(address))?;
+
+            let start = (address - sector.base_address) as usize;
+            let length = (sector.size as usize - start).min(data.len() - offset);
+            let (_, contents) = sectors
+                .entry(sector.base_address)
+                .or_insert_with(|| (region, vec![region.erased_byte_value; sector.size as usize]));
+            contents[start..start + length].copy_from_slice(&data[offset..offset + length]);
+
+            offset += length;
+        }
+    }
+
+    Ok(sectors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::sector_contents;
+    use crate::probe::flash::memory::{FlashRegion, MemoryRegion};
+
+    #[test]
+    fn splits_image_into_sectors() {
+        let memory_map = vec![MemoryRegion::Flash(FlashRegion::for_test(
+            0..0x4000,
+            0x1000,
+        ))];
+        let image = vec![(0xFFE, vec![1, 2, 3, 4]), (0x3000, vec![5])];
+
+        let sectors = sector_contents(&memory_map, &image).unwrap();
+
+        assert_eq!(
+            sectors.keys().cloned().collect::<Vec<_>>(),
+            vec![0, 0x1000, 0x3000]
+        );
+        assert_eq!(sectors[&0].1[0xFFD..], [0xFF, 1, 2]);
+        assert_eq!(sectors[&0x1000].1[..3], [3, 4, 0xFF]);
+        assert_eq!(sectors[&0x3000].1[0], 5);
+        assert!(sector_contents(&memory_map, &[(0x4000, vec![0])]).is_err());
+    }
+}
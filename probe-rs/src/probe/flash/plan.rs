@@ -0,0 +1,172 @@
+//! What downloading an image would erase and program, worked out without a target.
+//!
+//! The plan follows the flash loader: each sector the image touches is erased, and each page
+//! it touches is programmed, with the bytes of the page the image does not cover filled with
+//! the erased value. It lets linker scripts and offsets behind bootloaders be checked before
+//! anything is written.
+
+use super::loader::{AddressTranslation, FlashLoader, FlashLoaderError};
+use super::memory::MemoryRegion;
+
+use std::collections::BTreeMap;
+
+/// The sectors a download erases, with the pages it programs in them, by address.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FlashPlan {
+    pub sectors: Vec<SectorPlan>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SectorPlan {
+    pub address: u32,
+    pub size: u32,
+    /// The value the bytes of the pages the image does not cover are filled with.
+    pub fill_value: u8,
+    pub pages: Vec<PagePlan>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PagePlan {
+    pub address: u32,
+    pub size: u32,
+    /// The bytes of the page the image covers.
+    pub data_bytes: u32,
+    /// The bytes of the page filled with the erased value.
+    pub fill_bytes: u32,
+}
+
+impl FlashPlan {
+    /// Plans the download of `image`, as the address and the data of each block, moved by
+    /// `translation` like the flash loader does.
+    pub fn new(
+        memory_map: &[MemoryRegion],
+        image: &[(u32, Vec<u8>)],
+        translation: Option<&AddressTranslation>,
+    ) -> Result<Self, FlashLoaderError> {
+        // The bytes covered in each page, with the region of the page.
+        let mut pages = BTreeMap::new();
+
+        for (address, data) in image {
+            let address = match translation {
+                Some(translation) => translation.apply(*address, data.len())?,
+                None => *address,
+            };
+            let mut offset = 0;
+
+            while offset < data.len() {
+                let address = address + offset as u32;
+                let region = match FlashLoader::get_region_for_address(memory_map, address) {
+                    Some(MemoryRegion::Flash(region)) => region,
+                    Some(_) => return Err(FlashLoaderError::MemoryRegionNotFlash(address)),
+                    None => return Err(FlashLoaderError::MemoryRegionNotDefined(address)),
+                };
+                let page = region
+                    .get_page_info(address)
+                    .ok_or(FlashLoaderError::MemoryRegionNotFlash(address))?;
+
+                let start = address - page.base_address;
+                let length = ((page.size - start) as usize).min(data.len() - offset);
+                let (_, covered) = pages.entry(page.base_address).or_insert((region, 0));
+                *covered += length as u32;
+
+                offset += length;
+            }
+        }
+
+        let mut plan = FlashPlan::default();
+        for (address, (region, covered)) in pages {
+            let page_size = region.page_size;
+            let page = PagePlan {
+                address,
+                size: page_size,
+                data_bytes: covered.min(page_size),
+                fill_bytes: page_size.saturating_sub(covered),
+            };
+
+            let sector_address = address - (address % region.sector_size);
+            match plan.sectors.last_mut() {
+                Some(sector) if sector.address == sector_address => sector.pages.push(page),
+                _ => plan.sectors.push(SectorPlan {
+                    address: sector_address,
+                    size: region.sector_size,
+                    fill_value: region.erased_byte_value,
+                    pages: vec![page],
+                }),
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// The bytes of all sectors which are erased.
+    pub fn erased_bytes(&self) -> u64 {
+        self.sectors
+            .iter()
+            .map(|sector| u64::from(sector.size))
+            .sum()
+    }
+
+    /// The pages which are programmed.
+    pub fn pages(&self) -> impl Iterator<Item = &PagePlan> {
+        self.sectors.iter().flat_map(|sector| sector.pages.iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FlashPlan, PagePlan};
+    use crate::probe::flash::loader::{AddressTranslation, FlashLoaderError};
+    use crate::probe::flash::memory::{FlashRegion, MemoryRegion};
+
+    #[test]
+    fn plans_sectors_and_pages() {
+        let memory_map = vec![MemoryRegion::Flash(FlashRegion::for_test(
+            0..0x4000,
+            0x1000,
+        ))];
+        let image = vec![(0xF80, vec![0; 0x100]), (0x2010, vec![0; 0x10])];
+
+        let plan = FlashPlan::new(&memory_map, &image, None).unwrap();
+
+        assert_eq!(
+            plan.sectors
+                .iter()
+                .map(|sector| sector.address)
+                .collect::<Vec<_>>(),
+            vec![0, 0x1000, 0x2000]
+        );
+        assert_eq!(plan.erased_bytes(), 0x3000);
+        assert_eq!(
+            plan.pages().cloned().collect::<Vec<_>>(),
+            vec![
+                PagePlan {
+                    address: 0xF00,
+                    size: 0x100,
+                    data_bytes: 0x80,
+                    fill_bytes: 0x80,
+                },
+                PagePlan {
+                    address: 0x1000,
+                    size: 0x100,
+                    data_bytes: 0x80,
+                    fill_bytes: 0x80,
+                },
+                PagePlan {
+                    address: 0x2000,
+                    size: 0x100,
+                    data_bytes: 0x10,
+                    fill_bytes: 0xF0,
+                },
+            ]
+        );
+
+        let translation = AddressTranslation {
+            offset: 0x3000,
+            protected: 0..0x3000,
+        };
+        match FlashPlan::new(&memory_map, &image, Some(&translation)) {
+            Err(FlashLoaderError::MemoryRegionNotDefined(0x4000)) => {}
+            plan => panic!("Expected the image to overflow the flash, got {:?}", plan),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Progress reported by a flash algorithm through a word in RAM.
+//!
+//! Erasing the whole flash of a large chip takes minutes, in a single call of `EraseChip()`.
+//! An algorithm with a `progress_address` writes how far it got to the word there, in percent,
+//! while the flasher polls it. The flasher writes 0 to the word before the call.
+//!
+//! As long as the word changes, the routine is making progress, so instead of its timeout it
+//! only fails when the word stays the same for `STALL_TIMEOUT`.
+
+use std::time::{Duration, Instant};
+
+/// How long the progress may stay the same before the routine is considered stuck.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The progress word of a routine which is running.
+#[derive(Debug, Clone)]
+pub struct ProgressMailbox {
+    pub address: u32,
+    last: u32,
+    changed: Instant,
+}
+
+impl ProgressMailbox {
+    pub fn new(address: u32, now: Instant) -> Self {
+        ProgressMailbox {
+            address,
+            last: 0,
+            changed: now,
+        }
+    }
+
+    /// Takes the value read from the word, returning the progress in percent if it changed.
+    pub fn update(&mut self, value: u32, now: Instant) -> Option<u32> {
+        if value == self.last {
+            return None;
+        }
+
+        self.last = value;
+        self.changed = now;
+        Some(value.min(100))
+    }
+
+    /// Whether the progress stayed the same for longer than `STALL_TIMEOUT`.
+    pub fn is_stalled(&self, now: Instant) -> bool {
+        now.duration_since(self.changed) > STALL_TIMEOUT
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ProgressMailbox, STALL_TIMEOUT};
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn detects_stalls() {
+        let start = Instant::now();
+        let mut mailbox = ProgressMailbox::new(0x2000_1000, start);
+
+        assert_eq!(mailbox.update(0, start), None);
+        assert_eq!(mailbox.update(40, start + STALL_TIMEOUT), Some(40));
+        assert!(!mailbox.is_stalled(start + STALL_TIMEOUT + Duration::from_secs(1)));
+
+        assert_eq!(mailbox.update(40, start + 2 * STALL_TIMEOUT), None);
+        assert!(mailbox.is_stalled(start + 2 * STALL_TIMEOUT + Duration::from_secs(1)));
+    }
+}
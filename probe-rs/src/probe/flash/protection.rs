@@ -0,0 +1,218 @@
+//! Enabling the readout protection of a target, which production devices are shipped with.
+//!
+//! Once enabled, the debugger can no longer read the flash, and unlocking the target erases it.
+//! Each vendor stores the protection setting differently: the nRF5 chips in the UICR, the
+//! STM32F4 chips in their option bytes and the Kinetis chips in the flash configuration field
+//! of the image itself.
+
+use super::config_region::{
+    wait_stm32_ready, ConfigRegion, ConfigRegionError, STM32_FLASH_SR, STM32_FLASH_SR_ERRORS,
+};
+use super::loader::{FlashLoader, FlashLoaderError};
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::probe::debug_probe::MasterProbe;
+use crate::session::Session;
+use crate::target::Target;
+
+use std::error::Error;
+use std::fmt;
+
+/// The RBPCONF register of the nRF51, whose PALL byte protects all of the flash when cleared.
+const NRF51_UICR_RBPCONF: u32 = 0x1000_1004;
+const NRF52_UICR_APPROTECT: u32 = 0x1000_1208;
+
+const STM32_FLASH_OPTKEYR: u32 = 0x4002_3C08;
+const STM32_FLASH_OPTCR: u32 = 0x4002_3C14;
+const STM32_FLASH_OPTKEYS: [u32; 2] = [0x0819_2A3B, 0x4C5D_6E7F];
+const STM32_FLASH_OPTCR_OPTLOCK: u32 = 1;
+const STM32_FLASH_OPTCR_OPTSTRT: u32 = 1 << 1;
+const STM32_RDP_LEVEL_0: u32 = 0xAA;
+const STM32_RDP_LEVEL_1: u32 = 0x55;
+
+/// The FSEC byte of the flash configuration field of the Kinetis chips.
+const KINETIS_FSEC: u32 = 0x40C;
+/// The SEC bits of FSEC, which only leave the chip unsecured if they are `0b10`.
+const KINETIS_FSEC_SEC: u8 = 0b11;
+const KINETIS_FSEC_UNSECURE: u8 = 0b10;
+
+#[derive(Debug)]
+pub enum ProtectionError {
+    /// The target, given by name, has no known readout protection.
+    Unsupported(String),
+    /// The protection is enabled, so the flash can not be read.
+    Enabled(ReadoutProtection),
+    Access(AccessPortError),
+    ConfigRegion(ConfigRegionError),
+    FlashLoader(FlashLoaderError),
+}
+
+impl Error for ProtectionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProtectionError::Unsupported(_) => None,
+            ProtectionError::Enabled(_) => None,
+            ProtectionError::Access(e) => Some(e),
+            ProtectionError::ConfigRegion(e) => Some(e),
+            ProtectionError::FlashLoader(e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for ProtectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ProtectionError::*;
+
+        match self {
+            Unsupported(name) => write!(f, "The readout protection of {} is not supported.", name),
+            Enabled(protection) => write!(
+                f,
+                "The flash can not be read, {} is enabled. Disabling it erases the flash.",
+                protection
+            ),
+            Access(e) => e.fmt(f),
+            ConfigRegion(e) => e.fmt(f),
+            FlashLoader(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<AccessPortError> for ProtectionError {
+    fn from(error: AccessPortError) -> Self {
+        ProtectionError::Access(error)
+    }
+}
+
+impl From<ConfigRegionError> for ProtectionError {
+    fn from(error: ConfigRegionError) -> Self {
+        ProtectionError::ConfigRegion(error)
+    }
+}
+
+impl From<FlashLoaderError> for ProtectionError {
+    fn from(error: FlashLoaderError) -> Self {
+        ProtectionError::FlashLoader(error)
+    }
+}
+
+/// The kinds of readout protection.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReadoutProtection {
+    /// PALL in the RBPCONF register of the nRF51.
+    Nrf51Pall,
+    /// APPROTECT in the UICR of the nRF52.
+    Nrf52Approtect,
+    /// Level 1 of the RDP option byte of the STM32F4, which can be reverted with a mass erase.
+    Stm32Rdp,
+    /// The SEC bits of FSEC in the flash configuration field of the Kinetis chips.
+    KinetisFsec,
+}
+
+impl fmt::Display for ReadoutProtection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ReadoutProtection::Nrf51Pall => "PALL",
+            ReadoutProtection::Nrf52Approtect => "APPROTECT",
+            ReadoutProtection::Stm32Rdp => "RDP level 1",
+            ReadoutProtection::KinetisFsec => "FSEC",
+        };
+
+        f.pad(name)
+    }
+}
+
+impl ReadoutProtection {
+    /// Returns the readout protection of the target, or `None` if it is not supported.
+    pub fn for_target(target: &Target) -> Option<Self> {
+        let name = target.name.to_ascii_lowercase();
+
+        if name.starts_with("nrf51") {
+            Some(ReadoutProtection::Nrf51Pall)
+        } else if name.starts_with("nrf52") {
+            Some(ReadoutProtection::Nrf52Approtect)
+        } else if name.starts_with("stm32f4") {
+            Some(ReadoutProtection::Stm32Rdp)
+        } else if name.starts_with("mk") {
+            Some(ReadoutProtection::KinetisFsec)
+        } else {
+            None
+        }
+    }
+
+    /// Checks whether the protection is set, which blocks reading the flash after the next
+    /// reset.
+    pub fn is_enabled(self, probe: &mut MasterProbe) -> Result<bool, ProtectionError> {
+        let enabled = match self {
+            ReadoutProtection::Nrf51Pall => probe.read8(NRF51_UICR_RBPCONF + 1)? == 0x00,
+            ReadoutProtection::Nrf52Approtect => probe.read8(NRF52_UICR_APPROTECT)? == 0x00,
+            ReadoutProtection::Stm32Rdp => {
+                (probe.read32(STM32_FLASH_OPTCR)? >> 8) & 0xFF != STM32_RDP_LEVEL_0
+            }
+            ReadoutProtection::KinetisFsec => {
+                probe.read8(KINETIS_FSEC)? & KINETIS_FSEC_SEC != KINETIS_FSEC_UNSECURE
+            }
+        };
+
+        Ok(enabled)
+    }
+
+    /// Enables the protection, which takes effect after the next reset of the target.
+    ///
+    /// The flash configuration field of the Kinetis chips is reprogrammed with the flash
+    /// algorithm, keeping the rest of its sector.
+    pub fn enable(self, session: &mut Session) -> Result<(), ProtectionError> {
+        match self {
+            ReadoutProtection::Nrf51Pall => {
+                let region = config_region(&session.target)?;
+                region.write(&mut session.probe, NRF51_UICR_RBPCONF + 1, &[0x00])?;
+            }
+            ReadoutProtection::Nrf52Approtect => {
+                let region = config_region(&session.target)?;
+                region.write(&mut session.probe, NRF52_UICR_APPROTECT, &[0x00])?;
+            }
+            ReadoutProtection::Stm32Rdp => enable_stm32_rdp(&mut session.probe)?,
+            ReadoutProtection::KinetisFsec => {
+                let fsec = session.probe.read8(KINETIS_FSEC)? | KINETIS_FSEC_SEC;
+                let memory_map = session.target.memory_map.clone();
+                let data = [fsec];
+
+                let mut loader = FlashLoader::new(&memory_map, false, false, true);
+                loader.add_data(KINETIS_FSEC, &data)?;
+                loader.commit(session)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn config_region(target: &Target) -> Result<ConfigRegion, ProtectionError> {
+    ConfigRegion::for_target(target)
+        .ok_or_else(|| ProtectionError::Unsupported(target.name.clone()))
+}
+
+/// Sets the RDP option byte of the STM32F4 to level 1, unless it is already protected.
+fn enable_stm32_rdp(probe: &mut MasterProbe) -> Result<(), ProtectionError> {
+    let optcr = probe.read32(STM32_FLASH_OPTCR)?;
+    if (optcr >> 8) & 0xFF != STM32_RDP_LEVEL_0 {
+        return Ok(());
+    }
+
+    if optcr & STM32_FLASH_OPTCR_OPTLOCK != 0 {
+        for key in &STM32_FLASH_OPTKEYS {
+            probe.write32(STM32_FLASH_OPTKEYR, *key)?;
+        }
+    }
+
+    probe.write32(STM32_FLASH_SR, STM32_FLASH_SR_ERRORS)?;
+    wait_stm32_ready(probe)?;
+
+    let optcr = (probe.read32(STM32_FLASH_OPTCR)? & !0xFF00) | (STM32_RDP_LEVEL_1 << 8);
+    probe.write32(STM32_FLASH_OPTCR, optcr)?;
+    probe.write32(STM32_FLASH_OPTCR, optcr | STM32_FLASH_OPTCR_OPTSTRT)?;
+    let result = wait_stm32_ready(probe);
+
+    probe.write32(STM32_FLASH_OPTCR, optcr | STM32_FLASH_OPTCR_OPTLOCK)?;
+
+    result.map_err(ProtectionError::from)
+}
@@ -0,0 +1,93 @@
+//! Statistics of programming the flash, which tell where the time went.
+
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// What the flash loader did, and how long each phase took.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FlashReport {
+    /// The number of bytes in the programmed pages.
+    pub bytes_programmed: u64,
+    pub sectors_erased: usize,
+    pub pages_programmed: usize,
+    /// The time spent finding the pages which need to be programmed.
+    pub analysis_time: Duration,
+    pub erase_time: Duration,
+    pub program_time: Duration,
+    /// The time of the whole operation, including loading the flash algorithm.
+    pub total_time: Duration,
+}
+
+impl FlashReport {
+    /// Returns the programmed bytes per second over the whole operation.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.total_time.as_secs_f64();
+
+        if seconds > 0.0 {
+            self.bytes_programmed as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+impl AddAssign for FlashReport {
+    fn add_assign(&mut self, other: Self) {
+        self.bytes_programmed += other.bytes_programmed;
+        self.sectors_erased += other.sectors_erased;
+        self.pages_programmed += other.pages_programmed;
+        self.analysis_time += other.analysis_time;
+        self.erase_time += other.erase_time;
+        self.program_time += other.program_time;
+        self.total_time += other.total_time;
+    }
+}
+
+impl fmt::Display for FlashReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Erased {} sectors and programmed {} pages ({} bytes) in {:.2}s, {:.2} KiB/s \
+             (analysis {:.2}s, erase {:.2}s, program {:.2}s)",
+            self.sectors_erased,
+            self.pages_programmed,
+            self.bytes_programmed,
+            self.total_time.as_secs_f64(),
+            self.throughput() / 1024.0,
+            self.analysis_time.as_secs_f64(),
+            self.erase_time.as_secs_f64(),
+            self.program_time.as_secs_f64(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FlashReport;
+    use std::time::Duration;
+
+    #[test]
+    fn sums_reports() {
+        let mut report = FlashReport {
+            bytes_programmed: 1024,
+            sectors_erased: 1,
+            pages_programmed: 4,
+            erase_time: Duration::from_millis(100),
+            total_time: Duration::from_millis(500),
+            ..Default::default()
+        };
+        report += FlashReport {
+            bytes_programmed: 1024,
+            sectors_erased: 1,
+            pages_programmed: 4,
+            total_time: Duration::from_millis(500),
+            ..Default::default()
+        };
+
+        assert_eq!(report.sectors_erased, 2);
+        assert_eq!(report.erase_time, Duration::from_millis(100));
+        assert_eq!(report.throughput(), 2048.0);
+        assert_eq!(FlashReport::default().throughput(), 0.0);
+    }
+}
@@ -0,0 +1,127 @@
+//! Motorola S-record files, which are emitted as `.srec`, `.mot` or `.s19` by many vendor
+//! toolchains and bootloader tools.
+//!
+//! Each line is a record of a type `S0` to `S9`, followed by the hex encoded byte count,
+//! address, data and checksum. Only the data records `S1`, `S2` and `S3`, which differ in the
+//! width of the address, place data in memory. The others hold a header, a record count or the
+//! start address, and are only checked.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum SrecError {
+    /// The record on the given line is malformed.
+    InvalidRecord(usize),
+    /// The checksum of the record on the given line does not match its contents.
+    Checksum(usize),
+}
+
+impl Error for SrecError {}
+
+impl fmt::Display for SrecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SrecError::InvalidRecord(line) => write!(f, "Invalid S-record on line {}.", line),
+            SrecError::Checksum(line) => {
+                write!(f, "The checksum of the S-record on line {} is wrong.", line)
+            }
+        }
+    }
+}
+
+/// Returns the data of an S-record file, as the address and the data of each block.
+/// Consecutive records are joined into one block.
+pub fn parse(contents: &str) -> Result<Vec<(u32, Vec<u8>)>, SrecError> {
+    let mut blocks: Vec<(u32, Vec<u8>)> = vec![];
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let number = index + 1;
+        let invalid = SrecError::InvalidRecord(number);
+
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            return Err(invalid);
+        }
+        let address_size = match chars.next() {
+            Some('0') | Some('1') | Some('5') | Some('9') => 2,
+            Some('2') | Some('6') | Some('8') => 3,
+            Some('3') | Some('7') => 4,
+            _ => return Err(invalid),
+        };
+
+        let bytes = decode_hex(&line[2..]).ok_or(invalid)?;
+        let count = *bytes.first().ok_or(SrecError::InvalidRecord(number))? as usize;
+        if bytes.len() != count + 1 || count < address_size + 1 {
+            return Err(SrecError::InvalidRecord(number));
+        }
+
+        // The checksum is the ones' complement of the sum of all other bytes.
+        let sum = bytes[..count]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if !sum != bytes[count] {
+            return Err(SrecError::Checksum(number));
+        }
+
+        if !matches!(&line[1..2], "1" | "2" | "3") {
+            continue;
+        }
+
+        let address = bytes[1..=address_size]
+            .iter()
+            .fold(0, |address, byte| address << 8 | u32::from(*byte));
+        let data = &bytes[address_size + 1..count];
+
+        match blocks.last_mut() {
+            Some((start, block)) if *start + block.len() as u32 == address => {
+                block.extend_from_slice(data)
+            }
+            _ => blocks.push((address, data.to_vec())),
+        }
+    }
+
+    Ok(blocks)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, SrecError};
+
+    #[test]
+    fn parses_records() {
+        let contents = "S00F000068656C6C6F202020202000003C\n\
+                        S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026\n\
+                        S11F001C4BFFFFE5398000007D83637880010014382100107C0803A64E800020E9\n\
+                        S3090800000001020304E4\n\
+                        S5030003F9\n\
+                        S9030000FC\n";
+
+        let blocks = parse(contents).unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0, 0);
+        assert_eq!(blocks[0].1.len(), 56);
+        assert_eq!(blocks[0].1[..4], [0x7C, 0x08, 0x02, 0xA6]);
+        assert_eq!(blocks[1], (0x0800_0000, vec![1, 2, 3, 4]));
+
+        assert_eq!(parse("S9030000FD\n"), Err(SrecError::Checksum(1)));
+        assert_eq!(parse("\nS1030000\n"), Err(SrecError::InvalidRecord(2)));
+    }
+}
@@ -0,0 +1,277 @@
+//! Programming of files without reading them whole.
+//!
+//! Only the headers of an ELF file are parsed up front. The data of its segments is then read
+//! sector by sector and programmed in chunks of about `CHUNK_SIZE` bytes, so programming starts
+//! before the whole file is read, and the memory needed on the host does not grow with the
+//! image. The debug info, usually most of an ELF file, is not read at all.
+
+use super::download::{in_ram, FileDownloadError};
+use super::loader::{AddressTranslation, FlashLoader, FlashLoaderError};
+use super::memory::MemoryRegion;
+use super::report::FlashReport;
+use crate::session::Session;
+
+use goblin::elf::program_header::{ProgramHeader, PT_LOAD};
+use std::io::{Read, Seek, SeekFrom};
+
+/// The amount of data programmed at once. A chunk only ends between two sectors, so it exceeds
+/// this by up to a sector.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Data of a file which is placed at `address`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub address: u32,
+    /// The offset of the data in the file.
+    pub offset: u64,
+    pub size: u32,
+}
+
+/// Whether the data of a segment of an ELF file is programmed into the flash, decided by its
+/// program header rather than by the names of the sections in it.
+///
+/// The `p_filesz` bytes of a loadable segment are stored at its load address `p_paddr`. For
+/// initialized data and functions copied to the RAM at startup, that is in the flash, while
+/// the segment runs at `p_vaddr` in the RAM. The rest of the segment up to `p_memsz`, like
+/// `.bss` and `.noinit`, has no contents. A segment whose load address is in the RAM, like a
+/// `.noinit` section which is not `NOLOAD`, has no copy the firmware could initialize it
+/// from, and is skipped.
+pub fn is_programmed(header: &ProgramHeader, memory_map: &[MemoryRegion]) -> bool {
+    if header.p_type != PT_LOAD || header.p_filesz == 0 {
+        return false;
+    }
+
+    let address = header.p_paddr as u32;
+    let size = header.p_filesz as usize;
+    if in_ram(memory_map, address, size) {
+        log::warn!(
+            "Skipping the segment of {} bytes at 0x{:08x}, which is loaded into the RAM.",
+            size,
+            address
+        );
+        return false;
+    }
+
+    true
+}
+
+/// Returns the segments of an ELF file which are programmed into the flash at their physical
+/// addresses, see `is_programmed`, reading only the headers of the file.
+pub fn elf_segments<T: Read + Seek>(
+    file: &mut T,
+    memory_map: &[MemoryRegion],
+) -> Result<Vec<Segment>, FileDownloadError> {
+    use goblin::container::Ctx;
+    use goblin::elf::Elf;
+
+    let invalid = |_| FileDownloadError::Object("The file is not a valid ELF file.");
+
+    // Enough for the header of a 64-bit file, and every file with segments is longer.
+    let mut header = [0; 64];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    let header = Elf::parse_header(&header).map_err(invalid)?;
+    let ctx = Ctx::new(
+        header.container().map_err(invalid)?,
+        header.endianness().map_err(invalid)?,
+    );
+
+    let mut program_headers = vec![0; header.e_phnum as usize * header.e_phentsize as usize];
+    file.seek(SeekFrom::Start(header.e_phoff))?;
+    file.read_exact(&mut program_headers)?;
+    let program_headers =
+        ProgramHeader::parse(&program_headers, 0, header.e_phnum as usize, ctx).map_err(invalid)?;
+
+    Ok(program_headers
+        .iter()
+        .filter(|ph| is_programmed(ph, memory_map))
+        .map(|ph| Segment {
+            address: ph.p_paddr as u32,
+            offset: ph.p_offset,
+            size: ph.p_filesz as u32,
+        })
+        .collect())
+}
+
+/// Programs the segments of `file` chunk by chunk, and returns the sum of the reports of the
+/// chunks.
+pub fn download_segments<T: Read + Seek>(
+    session: &mut Session,
+    file: &mut T,
+    segments: &[Segment],
+    memory_map: &[MemoryRegion],
+    translation: Option<&AddressTranslation>,
+) -> Result<FlashReport, FileDownloadError> {
+    let mut report = FlashReport::default();
+
+    for_each_chunk(file, segments, memory_map, translation, |chunk| {
+        let mut loader = FlashLoader::new(memory_map, false, false, false);
+        if let Some(translation) = translation {
+            loader.set_translation(translation.clone());
+        }
+        for (address, data) in chunk {
+            loader.add_data(*address, data)?;
+        }
+
+        report += loader.commit(session)?;
+
+        Ok(())
+    })?;
+
+    Ok(report)
+}
+
+/// Reads the segments in the order of their addresses and calls `program` with chunks of their
+/// data, as the address and the data of each block. All data of a sector is in the same chunk,
+/// as programming a chunk erases the sectors it covers.
+fn for_each_chunk<T: Read + Seek>(
+    file: &mut T,
+    segments: &[Segment],
+    memory_map: &[MemoryRegion],
+    translation: Option<&AddressTranslation>,
+    mut program: impl FnMut(&[(u32, Vec<u8>)]) -> Result<(), FileDownloadError>,
+) -> Result<(), FileDownloadError> {
+    let mut segments = segments.to_vec();
+    segments.sort_by_key(|segment| segment.address);
+
+    let mut chunk: Vec<(u32, Vec<u8>)> = vec![];
+    let mut chunk_size = 0;
+    let mut last_sector = None;
+
+    for segment in &segments {
+        file.seek(SeekFrom::Start(segment.offset))?;
+
+        let mut offset = 0;
+        while offset < segment.size {
+            let address = segment.address + offset;
+            let moved = match translation {
+                Some(translation) => translation.apply(address, 1)?,
+                None => address,
+            };
+            let sector = match FlashLoader::get_region_for_address(memory_map, moved) {
+                Some(MemoryRegion::Flash(region)) => region
+                    .get_sector_info(moved)
+                    .ok_or(FlashLoaderError::MemoryRegionNotFlash(moved))?,
+                Some(_) => return Err(FlashLoaderError::MemoryRegionNotFlash(moved).into()),
+                None => return Err(FlashLoaderError::MemoryRegionNotDefined(moved).into()),
+            };
+            let length = (sector.base_address + sector.size - moved).min(segment.size - offset);
+
+            if last_sector != Some(sector.base_address) && chunk_size >= CHUNK_SIZE {
+                program(&chunk)?;
+                chunk.clear();
+                chunk_size = 0;
+            }
+
+            // Contiguous data is kept in one block, so the flash builder gets few operations.
+            match chunk.last_mut() {
+                Some((start, data)) if *start + data.len() as u32 == address => {
+                    let end = data.len();
+                    data.resize(end + length as usize, 0);
+                    file.read_exact(&mut data[end..])?;
+                }
+                _ => {
+                    let mut data = vec![0; length as usize];
+                    file.read_exact(&mut data)?;
+                    chunk.push((address, data));
+                }
+            }
+
+            chunk_size += length as usize;
+            last_sector = Some(sector.base_address);
+            offset += length;
+        }
+    }
+
+    if !chunk.is_empty() {
+        program(&chunk)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{for_each_chunk, is_programmed, Segment, CHUNK_SIZE};
+    use crate::probe::flash::memory::{FlashRegion, MemoryRegion, RamRegion};
+
+    use goblin::elf::program_header::{ProgramHeader, PT_LOAD};
+    use std::io::Cursor;
+
+    #[test]
+    fn splits_segments_between_sectors() {
+        let memory_map = vec![MemoryRegion::Flash(FlashRegion::for_test(
+            0..0x10_0000,
+            0x1_0000,
+        ))];
+        let file: Vec<u8> = (0..0x8_0000u32).map(|i| (i % 251) as u8).collect();
+        let segments = vec![
+            // Continues the first segment, so it ends up in the same block.
+            Segment {
+                address: 0x4_8000,
+                offset: 0x4_8000,
+                size: 0x3_0000,
+            },
+            Segment {
+                address: 0,
+                offset: 0,
+                size: 0x4_8000,
+            },
+        ];
+
+        let mut chunks = vec![];
+        for_each_chunk(
+            &mut Cursor::new(&file),
+            &segments,
+            &memory_map,
+            None,
+            |chunk| {
+                chunks.push(chunk.to_vec());
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), 1);
+        assert_eq!(chunks[0][0].0, 0);
+        assert_eq!(chunks[0][0].1.len(), CHUNK_SIZE);
+        assert_eq!(chunks[1][0].0, CHUNK_SIZE as u32);
+        assert_eq!(chunks[1][0].1[..], file[CHUNK_SIZE..0x7_8000]);
+    }
+
+    #[test]
+    fn programs_segments_at_their_load_address_in_the_flash() {
+        let memory_map = vec![MemoryRegion::Ram(RamRegion {
+            range: 0x2000_0000..0x2000_8000,
+            is_boot_memory: false,
+            is_testable: true,
+            read_through_core: false,
+            bus_address: None,
+        })];
+        let segment = |vaddr, paddr, filesz, memsz| ProgramHeader {
+            p_type: PT_LOAD,
+            p_vaddr: vaddr,
+            p_paddr: paddr,
+            p_filesz: filesz,
+            p_memsz: memsz,
+            ..Default::default()
+        };
+
+        // The code, and the data with its initializers in the flash.
+        assert!(is_programmed(&segment(0, 0, 0x1000, 0x1000), &memory_map));
+        assert!(is_programmed(
+            &segment(0x2000_0000, 0x1000, 0x100, 0x400),
+            &memory_map
+        ));
+        // The .bss and .noinit sections, the latter once without NOLOAD.
+        assert!(!is_programmed(
+            &segment(0x2000_0400, 0x2000_0400, 0, 0x400),
+            &memory_map
+        ));
+        assert!(!is_programmed(
+            &segment(0x2000_0800, 0x2000_0800, 0x100, 0x100),
+            &memory_map
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+//! UF2 files, the format of the USB mass storage bootloaders of many boards.
+//!
+//! A UF2 file is a sequence of 512 byte blocks, each of which holds up to 476 bytes of data for
+//! a target address, framed by magic numbers.
+
+/// The first word of a file, which is "UF2\n".
+pub const MAGIC: u32 = 0x0A32_4655;
+const MAGIC_2: u32 = 0x9E5D_5157;
+const MAGIC_END: u32 = 0x0AB1_6F30;
+
+const BLOCK_SIZE: usize = 512;
+const HEADER_SIZE: usize = 32;
+const MAX_PAYLOAD: usize = 476;
+
+/// Set for blocks which are not meant for the main flash, e.g. comments.
+const FLAG_NOT_MAIN_FLASH: u32 = 0x1;
+
+/// Returns the data of a UF2 file, as the address and the data of each block.
+/// Consecutive blocks are joined.
+pub fn parse(data: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, &'static str> {
+    if data.len() % BLOCK_SIZE != 0 {
+        return Err("The UF2 file is not made of 512 byte blocks");
+    }
+
+    let mut blocks: Vec<(u32, Vec<u8>)> = vec![];
+
+    for block in data.chunks(BLOCK_SIZE) {
+        let word = |index: usize| {
+            let bytes = &block[4 * index..][..4];
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        };
+
+        if word(0) != MAGIC || word(1) != MAGIC_2 || word(127) != MAGIC_END {
+            return Err("A block of the UF2 file has no valid magic numbers");
+        }
+        if word(2) & FLAG_NOT_MAIN_FLASH != 0 {
+            continue;
+        }
+
+        let address = word(3);
+        let size = word(4) as usize;
+        if size > MAX_PAYLOAD {
+            return Err("A block of the UF2 file holds too much data");
+        }
+        let payload = &block[HEADER_SIZE..][..size];
+
+        match blocks.last_mut() {
+            Some((start, data)) if *start + data.len() as u32 == address => {
+                data.extend_from_slice(payload)
+            }
+            _ => blocks.push((address, payload.to_vec())),
+        }
+    }
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, MAGIC, MAGIC_2, MAGIC_END};
+
+    fn block(flags: u32, address: u32, payload: &[u8]) -> Vec<u8> {
+        let mut block = vec![0; 512];
+        for (index, word) in [MAGIC, MAGIC_2, flags, address, payload.len() as u32]
+            .iter()
+            .enumerate()
+        {
+            block[4 * index..][..4].copy_from_slice(&word.to_le_bytes());
+        }
+        block[32..][..payload.len()].copy_from_slice(payload);
+        block[508..].copy_from_slice(&MAGIC_END.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn parses_blocks() {
+        let mut file = block(0, 0x1000, &[1; 256]);
+        file.extend(block(0, 0x1100, &[2; 256]));
+        file.extend(block(1, 0x1200, &[3; 256]));
+        file.extend(block(0, 0x4000, &[4; 16]));
+
+        let blocks = parse(&file).unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0, 0x1000);
+        assert_eq!(blocks[0].1.len(), 512);
+        assert_eq!(blocks[0].1[256], 2);
+        assert_eq!(blocks[1], (0x4000, vec![4; 16]));
+        assert!(parse(&file[..100]).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! How much of each region of the memory map an image uses, like `arm-none-eabi-size` but
+//! with the regions of the target instead of the sections of the file.
+//!
+//! An image occupies memory twice for data which is copied at startup: its initializers are
+//! stored in the flash at the load address, and the data lives in the RAM at the run address,
+//! together with the zeroed memory after it. Both are counted in the region they start in, so
+//! an image which runs past the end of a region uses more than all of it.
+
+use super::loader::{AddressTranslation, FlashLoader};
+use super::memory::MemoryRegion;
+
+use std::fmt;
+use std::ops::Range;
+
+/// A block of memory an image occupies, e.g. a loadable segment of an ELF file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+    /// Where the block lives while the firmware runs.
+    pub address: u32,
+    /// The bytes at `address`, including the zeroed ones.
+    pub size: u32,
+    /// Where the block is stored in the image, which differs from `address` for initialized
+    /// data copied to the RAM at startup.
+    pub load_address: u32,
+    /// The bytes stored in the image.
+    pub load_size: u32,
+}
+
+impl Allocation {
+    /// The blocks of an image without run addresses, e.g. an Intel hex file, where the data
+    /// lives where it is stored.
+    pub fn from_image(image: &[(u32, Vec<u8>)]) -> Vec<Allocation> {
+        image
+            .iter()
+            .map(|(address, data)| Allocation {
+                address: *address,
+                size: data.len() as u32,
+                load_address: *address,
+                load_size: data.len() as u32,
+            })
+            .collect()
+    }
+
+    /// Whether the block is copied from its load address to another one at startup.
+    pub fn is_relocated(&self) -> bool {
+        self.address != self.load_address && self.load_size > 0
+    }
+}
+
+/// The bytes an image uses in one region of the memory map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionUsage {
+    pub kind: &'static str,
+    pub range: Range<u32>,
+    pub used: u64,
+}
+
+impl RegionUsage {
+    pub fn size(&self) -> u64 {
+        u64::from(self.range.end - self.range.start)
+    }
+
+    pub fn percent(&self) -> f64 {
+        match self.size() {
+            0 => 0.0,
+            size => 100.0 * self.used as f64 / size as f64,
+        }
+    }
+}
+
+/// Something about the memory use of an image which keeps it from working on the target.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UsageWarning {
+    /// The image uses more bytes than the region has.
+    Overflow(RegionUsage),
+    /// A block of the image starts outside of the memory map.
+    Unmapped { address: u32, size: u32 },
+    /// The initializers of relocated data are stored in a reserved part of the memory, a
+    /// peripheral or the protected bootloader.
+    ReservedInitializers {
+        address: u32,
+        size: u32,
+        reserved: Range<u32>,
+    },
+}
+
+impl fmt::Display for UsageWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UsageWarning::Overflow(usage) => write!(
+                f,
+                "The image uses {} bytes of the {} at 0x{:08x}..0x{:08x}, which has {} ({:.1}%).",
+                usage.used,
+                usage.kind,
+                usage.range.start,
+                usage.range.end,
+                usage.size(),
+                usage.percent()
+            ),
+            UsageWarning::Unmapped { address, size } => write!(
+                f,
+                "The image places {} bytes at 0x{:08x}, outside of the memory map.",
+                size, address
+            ),
+            UsageWarning::ReservedInitializers {
+                address,
+                size,
+                reserved,
+            } => write!(
+                f,
+                "The {} bytes of data initializers at 0x{:08x} overlap the reserved memory at 0x{:08x}..0x{:08x}.",
+                size, address, reserved.start, reserved.end
+            ),
+        }
+    }
+}
+
+/// The memory an image uses in each region of the memory map, in the order of the map.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryUsage {
+    pub regions: Vec<RegionUsage>,
+    pub warnings: Vec<UsageWarning>,
+}
+
+impl MemoryUsage {
+    /// Counts the memory of `allocations`, with the stored blocks moved by `translation` like
+    /// the flash loader does. Blocks which live where they are stored move with them.
+    pub fn new(
+        memory_map: &[MemoryRegion],
+        allocations: &[Allocation],
+        translation: Option<&AddressTranslation>,
+    ) -> Self {
+        let mut usage = MemoryUsage {
+            regions: memory_map
+                .iter()
+                .map(|region| RegionUsage {
+                    kind: region.kind(),
+                    range: region.range().clone(),
+                    used: 0,
+                })
+                .collect(),
+            warnings: vec![],
+        };
+
+        let mut reserved: Vec<Range<u32>> = memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Device(region) => Some(region.range.clone()),
+                _ => None,
+            })
+            .collect();
+        if let Some(translation) = translation {
+            if translation.protected.start < translation.protected.end {
+                reserved.push(translation.protected.clone());
+            }
+        }
+
+        let offset = translation.map_or(0, |translation| translation.offset);
+        for allocation in allocations {
+            let load_address = allocation.load_address.wrapping_add(offset);
+
+            if allocation.is_relocated() {
+                usage.add(memory_map, allocation.address, allocation.size);
+                usage.add(memory_map, load_address, allocation.load_size);
+
+                let end = u64::from(load_address) + u64::from(allocation.load_size);
+                if let Some(range) = reserved
+                    .iter()
+                    .find(|range| u64::from(range.start) < end && load_address < range.end)
+                {
+                    usage.warnings.push(UsageWarning::ReservedInitializers {
+                        address: load_address,
+                        size: allocation.load_size,
+                        reserved: range.clone(),
+                    });
+                }
+            } else if allocation.load_size > 0 {
+                usage.add(memory_map, load_address, allocation.size);
+            } else {
+                usage.add(memory_map, allocation.address, allocation.size);
+            }
+        }
+
+        let overflows: Vec<_> = usage
+            .regions
+            .iter()
+            .filter(|region| region.used > region.size())
+            .cloned()
+            .map(UsageWarning::Overflow)
+            .collect();
+        usage.warnings.extend(overflows);
+
+        usage
+    }
+
+    /// The regions the image uses any memory in.
+    pub fn used_regions(&self) -> impl Iterator<Item = &RegionUsage> {
+        self.regions.iter().filter(|region| region.used > 0)
+    }
+
+    fn add(&mut self, memory_map: &[MemoryRegion], address: u32, size: u32) {
+        if size == 0 {
+            return;
+        }
+
+        let range =
+            FlashLoader::get_region_for_address(memory_map, address).map(MemoryRegion::range);
+        match self
+            .regions
+            .iter_mut()
+            .find(|region| Some(&region.range) == range)
+        {
+            Some(region) => region.used += u64::from(size),
+            None => self.warnings.push(UsageWarning::Unmapped { address, size }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Allocation, MemoryUsage, UsageWarning};
+    use crate::probe::flash::loader::AddressTranslation;
+    use crate::probe::flash::memory::{FlashRegion, MemoryRegion, RamRegion};
+
+    #[test]
+    fn counts_data_in_flash_and_ram() {
+        let memory_map = vec![
+            MemoryRegion::Flash(FlashRegion::for_test(0..0x1000, 0x400)),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2000_0400,
+                is_boot_memory: false,
+                is_testable: true,
+                read_through_core: false,
+                bus_address: None,
+            }),
+        ];
+        let allocations = vec![
+            // The code, the initialized data with the zeroed data after it, and the stack.
+            Allocation {
+                address: 0,
+                size: 0x800,
+                load_address: 0,
+                load_size: 0x800,
+            },
+            Allocation {
+                address: 0x2000_0000,
+                size: 0x300,
+                load_address: 0x800,
+                load_size: 0x100,
+            },
+            Allocation {
+                address: 0x2000_0300,
+                size: 0x200,
+                load_address: 0x2000_0300,
+                load_size: 0,
+            },
+        ];
+
+        let usage = MemoryUsage::new(&memory_map, &allocations, None);
+        assert_eq!(
+            usage
+                .regions
+                .iter()
+                .map(|region| region.used)
+                .collect::<Vec<_>>(),
+            vec![0x900, 0x500]
+        );
+        assert_eq!(
+            usage.warnings,
+            vec![UsageWarning::Overflow(usage.regions[1].clone())]
+        );
+
+        let translation = AddressTranslation {
+            offset: 0x400,
+            protected: 0xC00..0x1000,
+        };
+        let usage = MemoryUsage::new(&memory_map, &allocations[..2], Some(&translation));
+        assert_eq!(usage.regions[0].used, 0x900);
+        assert_eq!(
+            usage.warnings,
+            vec![UsageWarning::ReservedInitializers {
+                address: 0xC00,
+                size: 0x100,
+                reserved: 0xC00..0x1000,
+            }]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! The pins of the wire as lines of a Linux GPIO chip, driven through its character device.
+
+use super::swd::SwdIo;
+use super::tools::GpioPins;
+use crate::probe::debug_probe::DebugProbeError;
+
+use gpio_cdev::{Chip, Line, LineHandle, LineRequestFlags};
+
+/// The consumer the lines are requested for, shown by `gpioinfo`.
+const CONSUMER: &str = "probe-rs";
+
+pub(crate) struct CdevPins {
+    swclk: LineHandle,
+    swdio: Line,
+    /// SWDIO requested as an output, or as an input while it is released. It is `None` only
+    /// while switching between the two.
+    swdio_handle: Option<LineHandle>,
+    /// The level SWDIO is driven to, so it is only set when it changes.
+    swdio_level: bool,
+    reset: Option<LineHandle>,
+}
+
+impl CdevPins {
+    pub(crate) fn open(pins: &GpioPins) -> Result<Self, DebugProbeError> {
+        let mut chip = Chip::new(pins.path()).map_err(gpio_error)?;
+
+        let swclk = chip
+            .get_line(pins.swclk)
+            .and_then(|line| line.request(LineRequestFlags::OUTPUT, 1, CONSUMER))
+            .map_err(gpio_error)?;
+        let swdio = chip.get_line(pins.swdio).map_err(gpio_error)?;
+        let swdio_handle = swdio
+            .request(LineRequestFlags::OUTPUT, 1, CONSUMER)
+            .map_err(gpio_error)?;
+        // nRESET is open drain, the target may pull it low itself.
+        let reset = match pins.reset {
+            Some(offset) => Some(
+                chip.get_line(offset)
+                    .and_then(|line| {
+                        line.request(
+                            LineRequestFlags::OUTPUT | LineRequestFlags::OPEN_DRAIN,
+                            1,
+                            CONSUMER,
+                        )
+                    })
+                    .map_err(gpio_error)?,
+            ),
+            None => None,
+        };
+
+        Ok(CdevPins {
+            swclk,
+            swdio,
+            swdio_handle: Some(swdio_handle),
+            swdio_level: true,
+            reset,
+        })
+    }
+
+    fn swdio_handle(&self) -> Result<&LineHandle, DebugProbeError> {
+        self.swdio_handle
+            .as_ref()
+            .ok_or_else(|| DebugProbeError::Gpio("SWDIO is not requested".to_owned()))
+    }
+}
+
+impl SwdIo for CdevPins {
+    fn set_swclk(&mut self, high: bool) -> Result<(), DebugProbeError> {
+        self.swclk.set_value(high as u8).map_err(gpio_error)
+    }
+
+    fn set_swdio(&mut self, high: bool) -> Result<(), DebugProbeError> {
+        if high != self.swdio_level {
+            self.swdio_handle()?
+                .set_value(high as u8)
+                .map_err(gpio_error)?;
+            self.swdio_level = high;
+        }
+
+        Ok(())
+    }
+
+    /// The direction of a line is fixed when requesting it, so SWDIO is requested again.
+    fn release_swdio(&mut self, released: bool) -> Result<(), DebugProbeError> {
+        // The line is busy until the previous request is dropped.
+        self.swdio_handle = None;
+
+        let handle = if released {
+            self.swdio
+                .request(LineRequestFlags::INPUT, 0, CONSUMER)
+                .map_err(gpio_error)?
+        } else {
+            self.swdio
+                .request(LineRequestFlags::OUTPUT, self.swdio_level as u8, CONSUMER)
+                .map_err(gpio_error)?
+        };
+        self.swdio_handle = Some(handle);
+
+        Ok(())
+    }
+
+    fn swdio(&mut self) -> Result<bool, DebugProbeError> {
+        Ok(self.swdio_handle()?.get_value().map_err(gpio_error)? != 0)
+    }
+
+    fn set_reset(&mut self, asserted: bool) -> Result<(), DebugProbeError> {
+        match &self.reset {
+            Some(reset) => reset.set_value(!asserted as u8).map_err(gpio_error),
+            None => Err(DebugProbeError::CommandNotSupportedByProbe),
+        }
+    }
+}
+
+fn gpio_error(e: gpio_cdev::errors::Error) -> DebugProbeError {
+    DebugProbeError::Gpio(e.to_string())
+}
@@ -0,0 +1,396 @@
+//! A probe made of the GPIO pins of the host, e.g. of a Raspberry Pi, which drives SWD bit by
+//! bit.
+//!
+//! The lines are named by the `PROBE_RS_GPIO` environment variable, see `tools::GpioPins`. On
+//! Linux they are driven through the character device of the GPIO chip. Other pins, e.g. of a
+//! USB to GPIO bridge, can be used by implementing `swd::SwdIo` and `GpioProbe::new`.
+
+#[cfg(target_os = "linux")]
+mod cdev;
+pub mod swd;
+pub mod tools;
+
+use self::swd::{SwdBitBang, SwdIo};
+use self::tools::GpioPins;
+use crate::{
+    coresight::{
+        common::Register,
+        debug_port::{Abort, Ctrl, DebugPortId, Select, DPIDR},
+    },
+    probe::{
+        cancellation::CancellationToken,
+        debug_probe::{
+            DAPAccess, DapError, DebugProbe, DebugProbeError, DebugProbeInfo, Port, SwjPins,
+        },
+        jtag::JtagAccess,
+        protocol::{AttachMethod, WireProtocol},
+        retry::RetryPolicy,
+    },
+    target::quirks::{self, Quirk},
+};
+
+use log::{debug, error, info, warn};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The clock used when no speed is set, in kHz.
+const DEFAULT_SPEED_KHZ: u32 = 100;
+
+/// Each edge of the clock takes a system call, which limits it to about this speed in kHz.
+const MAX_SPEED_KHZ: u32 = 1000;
+
+/// How long nRESET is asserted, and how long the target gets to start afterwards.
+const RESET_DURATION: Duration = Duration::from_millis(20);
+
+/// How long the debug port may take to acknowledge a power-up request.
+const POWER_UP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The address of RDBUFF, which has no register type in `debug_port`.
+const RDBUFF: u8 = 0xC;
+
+pub struct GpioProbe {
+    swd: SwdBitBang<Box<dyn SwdIo>>,
+    target_sel: Option<u32>,
+    quirks: Vec<Quirk>,
+    retry_policy: RetryPolicy,
+    /// Set while recovering from a failed transfer, so the transfers of the recovery do not
+    /// recover themselves.
+    recovering: bool,
+}
+
+impl GpioProbe {
+    /// Creates a probe driving the wire through `io`.
+    pub fn new(io: Box<dyn SwdIo>) -> Self {
+        let retry_policy = RetryPolicy::default();
+
+        GpioProbe {
+            swd: SwdBitBang::new(io, DEFAULT_SPEED_KHZ, retry_policy.wait_retries),
+            target_sel: None,
+            quirks: vec![],
+            retry_policy,
+            recovering: false,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open(pins: &GpioPins) -> Result<Self, DebugProbeError> {
+        Ok(Self::new(Box::new(cdev::CdevPins::open(pins)?)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn open(_pins: &GpioPins) -> Result<Self, DebugProbeError> {
+        Err(DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    /// Resets the wire protocol, selects the target and powers up the debug domain.
+    fn initialize_debug_port(&mut self) -> Result<(), DebugProbeError> {
+        if self.target_sel.is_some() {
+            // Multi-drop targets start up in the dormant state.
+            self.dormant_to_swd()?;
+        } else {
+            self.swd.sequence(!0, 56)?;
+            self.swd.sequence(0xe79e, 16)?;
+        }
+
+        // Line reset, followed by idle cycles.
+        self.swd.sequence(!0, 56)?;
+        self.swd.sequence(0, 8)?;
+
+        if let Some(target_sel) = self.target_sel {
+            info!("Selecting multi-drop target 0x{:08x}", target_sel);
+            self.swd.write_target_sel(target_sel)?;
+        }
+
+        let raw_dp_id = self.swd.read(false, DPIDR::ADDRESS)?;
+        if let Some(expected) = quirks::dpidr(&self.quirks) {
+            if raw_dp_id != expected {
+                warn!(
+                    "The debug port reports DPIDR 0x{:08x} instead of 0x{:08x}, the chip may not be the selected target.",
+                    raw_dp_id, expected
+                );
+            }
+        }
+
+        let dp_id = DebugPortId::from(DPIDR::from(raw_dp_id));
+        info!("Debug Port Version:  {:x?}", dp_id.version);
+        info!(
+            "Debug Port Designer: {}",
+            dp_id.designer.get().unwrap_or("Unknown")
+        );
+
+        self.clear_sticky_errors()?;
+        self.swd.write(false, Select::ADDRESS, Select(0).into())?;
+
+        let system_steps: &[bool] = if self.quirks.contains(&Quirk::StagedPowerUp) {
+            &[false, true]
+        } else {
+            &[true]
+        };
+
+        // Debug power is requested first and system power afterwards for debug ports with
+        // `Quirk::StagedPowerUp`, both at once for the others.
+        for system in system_steps {
+            let mut ctrl = Ctrl::default();
+            ctrl.set_cdbgpwrupreq(true);
+            ctrl.set_csyspwrupreq(*system);
+
+            debug!(
+                "Requesting {} power",
+                if *system { "debug and system" } else { "debug" }
+            );
+            self.swd.write(false, Ctrl::ADDRESS, ctrl.into())?;
+
+            let start = Instant::now();
+            loop {
+                let ack = Ctrl::from(self.swd.read(false, Ctrl::ADDRESS)?);
+                if ack.cdbgpwrupack() && (ack.csyspwrupack() || !system) {
+                    break;
+                }
+                if start.elapsed() > POWER_UP_TIMEOUT {
+                    error!("Debug power request failed");
+                    return Err(DebugProbeError::TargetPowerUpFailed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switches a multi-drop capable target from the dormant state to SWD.
+    fn dormant_to_swd(&mut self) -> Result<(), DebugProbeError> {
+        // At least 8 cycles with SWDIO high.
+        self.swd.sequence(0xff, 8)?;
+
+        // Selection alert sequence.
+        self.swd.sequence_bytes(&[
+            0x92, 0xf3, 0x09, 0x62, 0x95, 0x2d, 0x85, 0x86, 0xe9, 0xaf, 0xdd, 0xe3, 0xa2, 0x0e,
+            0xbc, 0x19,
+        ])?;
+
+        // 4 cycles with SWDIO low, followed by the SWD activation code 0x1a.
+        self.swd.sequence(0x1a0, 12)
+    }
+
+    /// Clears the sticky error flags of the debug port through ABORT.
+    fn clear_sticky_errors(&mut self) -> Result<(), DebugProbeError> {
+        let mut abort = Abort::from(0);
+        abort.set_orunerrclr(true);
+        abort.set_wderrclr(true);
+        abort.set_stkerrclr(true);
+        abort.set_stkcmpclr(true);
+
+        self.swd.write(false, Abort::ADDRESS, abort.into())
+    }
+
+    /// Finds out what went wrong after a transfer the target answered with FAULT or not at all.
+    ///
+    /// Depending on the retry policy, the sticky error flags are cleared afterwards, and the
+    /// debug port is initialized again if the target did not answer.
+    fn recover(&mut self, error: DebugProbeError) -> DebugProbeError {
+        let error = match error {
+            DebugProbeError::Dap(error @ DapError::Fault)
+            | DebugProbeError::Dap(error @ DapError::NoAcknowledge)
+                if !self.recovering =>
+            {
+                error
+            }
+            error => return error,
+        };
+        self.recovering = true;
+
+        let error = match error {
+            DapError::Fault => match self.swd.read(false, Ctrl::ADDRESS) {
+                Ok(ctrl) => DapError::from_ctrl_stat(&Ctrl::from(ctrl)),
+                Err(_) => DapError::NoAcknowledge,
+            },
+            error => error,
+        };
+        warn!("Transfer with the debug port failed: {}", error);
+
+        if error == DapError::NoAcknowledge && self.retry_policy.reinitialize_debug_port {
+            if let Err(e) = self.initialize_debug_port() {
+                warn!("Failed to initialize the debug port again: {}", e);
+            }
+        } else if self.retry_policy.clear_sticky_errors {
+            if let Err(e) = self.clear_sticky_errors() {
+                warn!("Failed to clear the sticky errors: {}", e);
+            }
+        }
+
+        self.recovering = false;
+        error.into()
+    }
+}
+
+impl DebugProbe for GpioProbe {
+    fn new_from_probe_info(info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        let pins: GpioPins = info
+            .serial_number
+            .as_ref()
+            .and_then(|pins| pins.parse().ok())
+            .ok_or(DebugProbeError::ProbeCouldNotBeCreated)?;
+        info!("Using the GPIO lines {}", pins);
+
+        Ok(Box::new(Self::open(&pins)?))
+    }
+
+    fn get_name(&self) -> &str {
+        "GPIO"
+    }
+
+    /// Enters debug mode.
+    ///
+    /// Only SWD is supported, JTAG would need two more pins.
+    fn attach(
+        &mut self,
+        protocol: Option<WireProtocol>,
+        method: AttachMethod,
+    ) -> Result<WireProtocol, DebugProbeError> {
+        if protocol == Some(WireProtocol::Jtag) {
+            return Err(DebugProbeError::JTAGNotSupportedOnProbe);
+        }
+
+        info!("Attaching to target system");
+        self.initialize_debug_port()?;
+        info!("Succesfully attached to system and entered debug mode");
+
+        if method == AttachMethod::Reset {
+            self.target_reset()?;
+        }
+
+        Ok(WireProtocol::Swd)
+    }
+
+    /// Releases SWDIO, so the pin does not drive against anything connected later.
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.swd.io().release_swdio(true)
+    }
+
+    /// Pulses the nRESET line, if it is connected.
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.swd.io().set_reset(true)?;
+        thread::sleep(RESET_DURATION);
+        self.swd.io().set_reset(false)?;
+        thread::sleep(RESET_DURATION);
+
+        Ok(())
+    }
+
+    fn set_multidrop_target(&mut self, target_sel: Option<u32>) -> Result<(), DebugProbeError> {
+        self.target_sel = target_sel;
+        Ok(())
+    }
+
+    fn set_quirks(&mut self, quirks: &[Quirk]) -> Result<(), DebugProbeError> {
+        self.quirks = quirks.to_vec();
+        Ok(())
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        self.retry_policy = policy;
+        self.swd.set_wait_retries(policy.wait_retries);
+        Ok(())
+    }
+
+    /// The pins are driven by the host, so the transfers never wait for the probe.
+    fn set_cancellation_token(&mut self, _token: CancellationToken) {}
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        let speed_khz = speed_khz.min(MAX_SPEED_KHZ);
+        self.swd.set_speed(speed_khz);
+        Ok(speed_khz)
+    }
+
+    fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError> {
+        Ok(None)
+    }
+
+    fn enable_swo(&mut self, _baud_rate: u32) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn disable_swo(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    /// Drives SWCLK, SWDIO and nRESET. The pins are not read back, the levels they are driven
+    /// to are returned.
+    fn swj_pins(
+        &mut self,
+        output: SwjPins,
+        select: SwjPins,
+        wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError> {
+        let connected = SwjPins::SWCLK_TCK | SwjPins::SWDIO_TMS | SwjPins::NRESET;
+        if !connected.contains(select) {
+            return Err(DebugProbeError::CommandNotSupportedByProbe);
+        }
+
+        let io = self.swd.io();
+        if select.contains(SwjPins::SWCLK_TCK) {
+            io.set_swclk(output.contains(SwjPins::SWCLK_TCK))?;
+        }
+        if select.contains(SwjPins::SWDIO_TMS) {
+            io.release_swdio(false)?;
+            io.set_swdio(output.contains(SwjPins::SWDIO_TMS))?;
+        }
+        if select.contains(SwjPins::NRESET) {
+            io.set_reset(!output.contains(SwjPins::NRESET))?;
+        }
+        thread::sleep(Duration::from_micros(u64::from(wait_us)));
+
+        Ok(output & select)
+    }
+
+    fn jtag_access(&mut self) -> Option<&mut dyn JtagAccess> {
+        None
+    }
+
+    fn read_block16(&mut self, _address: u32, _data: &mut [u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+
+    fn write_block16(&mut self, _address: u32, _data: &[u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+}
+
+impl DAPAccess for GpioProbe {
+    /// Reads the DAP register on the specified port and address.
+    ///
+    /// AP reads are posted, so the value is read from RDBUFF afterwards.
+    fn read_register(&mut self, port: Port, addr: u16) -> Result<u32, DebugProbeError> {
+        let result = match port {
+            Port::DebugPort => self.swd.read(false, addr as u8),
+            Port::AccessPort(_) => self
+                .swd
+                .read(true, addr as u8)
+                .and_then(|_| self.swd.read(false, RDBUFF)),
+        };
+
+        result.map_err(|e| self.recover(e))
+    }
+
+    /// Writes a value to the DAP register on the specified port and address.
+    ///
+    /// AP writes are followed by a read of RDBUFF, which fails if the write did.
+    fn write_register(&mut self, port: Port, addr: u16, value: u32) -> Result<(), DebugProbeError> {
+        let result = match port {
+            Port::DebugPort => self.swd.write(false, addr as u8, value),
+            Port::AccessPort(_) => self
+                .swd
+                .write(true, addr as u8, value)
+                .and_then(|_| self.swd.read(false, RDBUFF).map(|_| ())),
+        };
+
+        result.map_err(|e| self.recover(e))
+    }
+}
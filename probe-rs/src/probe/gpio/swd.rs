@@ -0,0 +1,367 @@
+//! SWD transfers driven bit by bit, for probes which toggle the pins of the wire themselves
+//! instead of leaving the protocol to their firmware.
+//!
+//! The timing follows the SWD implementation of CMSIS-DAP: the probe changes SWDIO while SWCLK
+//! is low, the target samples it on the rising edge, and the probe samples the bits the target
+//! drives right before the rising edge.
+
+use crate::coresight::{common::Register, debug_port::TargetSel};
+use crate::probe::debug_probe::{DapError, DebugProbeError};
+
+use std::time::{Duration, Instant};
+
+/// The idle cycles after each transfer, which clock a write through the debug port.
+const IDLE_CYCLES: u32 = 8;
+
+/// The pins of the wire, driven and sampled one clock edge at a time.
+pub trait SwdIo {
+    fn set_swclk(&mut self, high: bool) -> Result<(), DebugProbeError>;
+
+    fn set_swdio(&mut self, high: bool) -> Result<(), DebugProbeError>;
+
+    /// Releases SWDIO so the target can drive it, or drives it again.
+    fn release_swdio(&mut self, released: bool) -> Result<(), DebugProbeError>;
+
+    /// Samples SWDIO while it is released.
+    fn swdio(&mut self) -> Result<bool, DebugProbeError>;
+
+    /// Asserts or releases the reset of the target.
+    ///
+    /// Returns `DebugProbeError::CommandNotSupportedByProbe` if it is not connected.
+    fn set_reset(&mut self, asserted: bool) -> Result<(), DebugProbeError>;
+}
+
+impl<IO: SwdIo + ?Sized> SwdIo for Box<IO> {
+    fn set_swclk(&mut self, high: bool) -> Result<(), DebugProbeError> {
+        (**self).set_swclk(high)
+    }
+
+    fn set_swdio(&mut self, high: bool) -> Result<(), DebugProbeError> {
+        (**self).set_swdio(high)
+    }
+
+    fn release_swdio(&mut self, released: bool) -> Result<(), DebugProbeError> {
+        (**self).release_swdio(released)
+    }
+
+    fn swdio(&mut self) -> Result<bool, DebugProbeError> {
+        (**self).swdio()
+    }
+
+    fn set_reset(&mut self, asserted: bool) -> Result<(), DebugProbeError> {
+        (**self).set_reset(asserted)
+    }
+}
+
+/// The answer of the target to the request of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ack {
+    Ok,
+    Wait,
+    Fault,
+    /// The target did not drive a valid ACK.
+    None,
+}
+
+/// Executes SWD transfers and sequences on the pins of `IO`.
+pub struct SwdBitBang<IO> {
+    io: IO,
+    /// Half of a period of SWCLK.
+    half_period: Duration,
+    /// How often a transfer the target answers with WAIT is retried.
+    wait_retries: u16,
+}
+
+impl<IO: SwdIo> SwdBitBang<IO> {
+    pub fn new(io: IO, speed_khz: u32, wait_retries: u16) -> Self {
+        SwdBitBang {
+            io,
+            half_period: half_period(speed_khz),
+            wait_retries,
+        }
+    }
+
+    pub fn io(&mut self) -> &mut IO {
+        &mut self.io
+    }
+
+    pub fn set_speed(&mut self, speed_khz: u32) {
+        self.half_period = half_period(speed_khz);
+    }
+
+    pub fn set_wait_retries(&mut self, wait_retries: u16) {
+        self.wait_retries = wait_retries;
+    }
+
+    /// Clocks out the lowest `bit_count` bits of `bits`, LSB first.
+    pub fn sequence(&mut self, bits: u64, bit_count: u32) -> Result<(), DebugProbeError> {
+        for bit in 0..bit_count {
+            self.cycle_out(bits >> bit & 1 != 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Clocks out `bytes`, each LSB first, e.g. the selection alert sequence.
+    pub fn sequence_bytes(&mut self, bytes: &[u8]) -> Result<(), DebugProbeError> {
+        for byte in bytes {
+            self.sequence(u64::from(*byte), 8)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a DP register, or an AP register if `ap` is set.
+    ///
+    /// AP reads are posted, the value returned is the one of the previous AP read.
+    pub fn read(&mut self, ap: bool, address: u8) -> Result<u32, DebugProbeError> {
+        self.transfer(ap, true, address, 0)
+    }
+
+    pub fn write(&mut self, ap: bool, address: u8, value: u32) -> Result<(), DebugProbeError> {
+        self.transfer(ap, false, address, value).map(|_| ())
+    }
+
+    /// Writes the TARGETSEL register to select a target on a multi-drop SWD bus.
+    ///
+    /// No target drives the ACK of this write, so the probe clocks it without looking at it.
+    pub fn write_target_sel(&mut self, target_sel: u32) -> Result<(), DebugProbeError> {
+        self.sequence(u64::from(request(false, false, TargetSel::ADDRESS)), 8)?;
+
+        // Turnaround, ACK and turnaround.
+        self.io.release_swdio(true)?;
+        for _ in 0..5 {
+            self.cycle_in()?;
+        }
+        self.io.release_swdio(false)?;
+
+        self.sequence(with_parity(target_sel), 33)?;
+        self.sequence(0, IDLE_CYCLES)
+    }
+
+    fn transfer(
+        &mut self,
+        ap: bool,
+        read: bool,
+        address: u8,
+        value: u32,
+    ) -> Result<u32, DebugProbeError> {
+        for _ in 0..=self.wait_retries {
+            match self.transfer_once(ap, read, address, value)? {
+                (Ack::Ok, data) => return Ok(data),
+                (Ack::Wait, _) => continue,
+                (Ack::Fault, _) => return Err(DapError::Fault.into()),
+                (Ack::None, _) => return Err(DapError::NoAcknowledge.into()),
+            }
+        }
+
+        Err(DapError::Wait.into())
+    }
+
+    fn transfer_once(
+        &mut self,
+        ap: bool,
+        read: bool,
+        address: u8,
+        value: u32,
+    ) -> Result<(Ack, u32), DebugProbeError> {
+        self.sequence(u64::from(request(ap, read, address)), 8)?;
+
+        self.io.release_swdio(true)?;
+        self.cycle_in()?;
+        let ack = match self.read_bits(3)? {
+            0b001 => Ack::Ok,
+            0b010 => Ack::Wait,
+            0b100 => Ack::Fault,
+            _ => Ack::None,
+        };
+
+        let mut data = 0;
+        match ack {
+            Ack::Ok if read => {
+                let bits = self.read_bits(33)?;
+                self.cycle_in()?;
+                self.io.release_swdio(false)?;
+
+                data = bits as u32;
+                if with_parity(data) != bits {
+                    self.sequence(0, IDLE_CYCLES)?;
+                    return Err(DapError::Parity.into());
+                }
+            }
+            Ack::Ok => {
+                self.cycle_in()?;
+                self.io.release_swdio(false)?;
+                self.sequence(with_parity(value), 33)?;
+            }
+            Ack::Wait | Ack::Fault => {
+                self.cycle_in()?;
+                self.io.release_swdio(false)?;
+            }
+            Ack::None => {
+                // The target may still drive the data phase of a read, so it is clocked
+                // through before taking over SWDIO again.
+                for _ in 0..34 {
+                    self.cycle_in()?;
+                }
+                self.io.release_swdio(false)?;
+            }
+        }
+
+        self.sequence(0, IDLE_CYCLES)?;
+
+        Ok((ack, data))
+    }
+
+    /// Samples `bit_count` bits the target drives, LSB first.
+    fn read_bits(&mut self, bit_count: u32) -> Result<u64, DebugProbeError> {
+        let mut bits = 0;
+        for bit in 0..bit_count {
+            if self.cycle_in()? {
+                bits |= 1 << bit;
+            }
+        }
+
+        Ok(bits)
+    }
+
+    fn cycle_out(&mut self, bit: bool) -> Result<(), DebugProbeError> {
+        self.io.set_swdio(bit)?;
+        self.io.set_swclk(false)?;
+        self.delay();
+        self.io.set_swclk(true)?;
+        self.delay();
+
+        Ok(())
+    }
+
+    fn cycle_in(&mut self) -> Result<bool, DebugProbeError> {
+        self.io.set_swclk(false)?;
+        self.delay();
+        let bit = self.io.swdio()?;
+        self.io.set_swclk(true)?;
+        self.delay();
+
+        Ok(bit)
+    }
+
+    /// Waits for half a clock period. Sleeping is far too coarse for it, so this spins.
+    fn delay(&self) {
+        let start = Instant::now();
+        while start.elapsed() < self.half_period {}
+    }
+}
+
+fn half_period(speed_khz: u32) -> Duration {
+    Duration::from_nanos(500_000 / u64::from(speed_khz.max(1)))
+}
+
+/// Returns the request of a transfer: start bit, APnDP, RnW, A[3:2], parity, stop and park.
+fn request(ap: bool, read: bool, address: u8) -> u8 {
+    let header = (ap as u8) | (read as u8) << 1 | (address & 0xC) >> 2 << 2;
+    let parity = (header.count_ones() % 2) as u8;
+
+    0x81 | header << 1 | parity << 5
+}
+
+/// Returns `value` followed by its parity bit.
+fn with_parity(value: u32) -> u64 {
+    u64::from(value) | u64::from(value.count_ones() % 2) << 32
+}
+
+#[cfg(test)]
+mod test {
+    use super::{request, SwdBitBang, SwdIo};
+    use crate::probe::debug_probe::DebugProbeError;
+
+    use std::collections::VecDeque;
+
+    /// Records the bits the probe drives and answers with the bits queued in `response`.
+    #[derive(Default)]
+    struct Wire {
+        swclk: bool,
+        swdio: bool,
+        released: bool,
+        driven: Vec<bool>,
+        response: VecDeque<bool>,
+    }
+
+    impl Wire {
+        fn queue(&mut self, bits: u64, bit_count: u32) {
+            self.response
+                .extend((0..bit_count).map(|bit| bits >> bit & 1 != 0));
+        }
+    }
+
+    impl SwdIo for Wire {
+        fn set_swclk(&mut self, high: bool) -> Result<(), DebugProbeError> {
+            if high && !self.swclk {
+                if self.released {
+                    self.response.pop_front();
+                } else {
+                    self.driven.push(self.swdio);
+                }
+            }
+            self.swclk = high;
+            Ok(())
+        }
+
+        fn set_swdio(&mut self, high: bool) -> Result<(), DebugProbeError> {
+            self.swdio = high;
+            Ok(())
+        }
+
+        fn release_swdio(&mut self, released: bool) -> Result<(), DebugProbeError> {
+            self.released = released;
+            Ok(())
+        }
+
+        fn swdio(&mut self) -> Result<bool, DebugProbeError> {
+            Ok(self.response.front().cloned().unwrap_or(true))
+        }
+
+        fn set_reset(&mut self, _asserted: bool) -> Result<(), DebugProbeError> {
+            Ok(())
+        }
+    }
+
+    fn bits(driven: &[bool]) -> u64 {
+        driven
+            .iter()
+            .rev()
+            .fold(0, |bits, bit| bits << 1 | *bit as u64)
+    }
+
+    #[test]
+    fn transfers_registers() {
+        assert_eq!(request(false, true, 0x0), 0xA5);
+        assert_eq!(request(false, false, 0x0), 0x81);
+        assert_eq!(request(true, true, 0xC), 0x9F);
+
+        // Reading DPIDR: turnaround, OK, the value with its parity and turnaround.
+        let mut wire = Wire::default();
+        wire.queue(0, 1);
+        wire.queue(0b001, 3);
+        wire.queue(0x2BA0_1477, 33);
+        wire.queue(0, 1);
+        let mut swd = SwdBitBang::new(wire, 1_000_000, 0);
+
+        assert_eq!(swd.read(false, 0x0).unwrap(), 0x2BA0_1477);
+        assert_eq!(bits(&swd.io().driven[..8]), 0xA5);
+
+        // Writing SELECT after a WAIT.
+        swd.io().driven.clear();
+        swd.io().queue(0, 1);
+        swd.io().queue(0b010, 3);
+        swd.io().queue(0, 2);
+        swd.io().queue(0b001, 3);
+        swd.io().queue(0, 1);
+        swd.set_wait_retries(1);
+
+        swd.write(false, 0x8, 0xF0).unwrap();
+        let driven = swd.io().driven.clone();
+        assert_eq!(driven.len(), 8 + 8 + 8 + 33 + 8);
+        assert_eq!(bits(&driven[16..24]), u64::from(request(false, false, 0x8)));
+        assert_eq!(bits(&driven[24..57]), 0xF0);
+    }
+}
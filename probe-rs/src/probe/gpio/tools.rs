@@ -0,0 +1,134 @@
+use crate::probe::debug_probe::{DebugProbeInfo, DebugProbeType};
+
+use log::warn;
+
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The environment variable naming the GPIO lines the wire is connected to.
+///
+/// GPIO lines can not be detected like USB devices, so the probe is only listed if it is set.
+pub const GPIO_VARIABLE: &str = "PROBE_RS_GPIO";
+
+/// The GPIO lines the wire is connected to, written as `CHIP:SWCLK,SWDIO[,NRESET]`, e.g.
+/// `gpiochip0:25,24,18` for the lines of the Raspberry Pi with these BCM numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpioPins {
+    /// The name of the GPIO chip in `/dev`, or the path of its character device.
+    pub chip: String,
+    pub swclk: u32,
+    pub swdio: u32,
+    pub reset: Option<u32>,
+}
+
+impl GpioPins {
+    pub fn path(&self) -> PathBuf {
+        if self.chip.starts_with('/') {
+            PathBuf::from(&self.chip)
+        } else {
+            PathBuf::from("/dev").join(&self.chip)
+        }
+    }
+}
+
+impl FromStr for GpioPins {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let chip = parts.next().unwrap_or_default();
+        let lines = parts
+            .next()
+            .ok_or_else(|| format!("'{}' does not name the lines after the chip", s))?
+            .split(',')
+            .map(|line| {
+                line.trim()
+                    .parse::<u32>()
+                    .map_err(|_| format!("'{}' is not a line number", line))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match lines.as_slice() {
+            [swclk, swdio] | [swclk, swdio, _] if !chip.is_empty() => Ok(GpioPins {
+                chip: chip.to_owned(),
+                swclk: *swclk,
+                swdio: *swdio,
+                reset: lines.get(2).cloned(),
+            }),
+            _ => Err(format!(
+                "'{}' is not of the form CHIP:SWCLK,SWDIO[,NRESET]",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for GpioPins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{},{}", self.chip, self.swclk, self.swdio)?;
+        if let Some(reset) = self.reset {
+            write!(f, ",{}", reset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the probe on the GPIO lines named by `PROBE_RS_GPIO`, if the chip exists.
+///
+/// The lines are stored as the serial number of the probe, so they are found again when it is
+/// opened.
+pub fn list_gpio_devices() -> Vec<DebugProbeInfo> {
+    let value = match env::var(GPIO_VARIABLE) {
+        Ok(value) => value,
+        Err(_) => return vec![],
+    };
+
+    match value.parse::<GpioPins>() {
+        Ok(pins) if pins.path().exists() => vec![DebugProbeInfo::new(
+            format!("GPIO {}", pins.chip),
+            0,
+            0,
+            Some(pins.to_string()),
+            DebugProbeType::Gpio,
+            None,
+        )],
+        Ok(pins) => {
+            warn!("The GPIO chip {} does not exist", pins.path().display());
+            vec![]
+        }
+        Err(e) => {
+            warn!("Ignoring {}: {}", GPIO_VARIABLE, e);
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GpioPins;
+
+    #[test]
+    fn parses_pins() {
+        let pins: GpioPins = "gpiochip0:25,24,18".parse().unwrap();
+        assert_eq!(
+            pins,
+            GpioPins {
+                chip: "gpiochip0".to_owned(),
+                swclk: 25,
+                swdio: 24,
+                reset: Some(18),
+            }
+        );
+        assert_eq!(pins.to_string(), "gpiochip0:25,24,18");
+
+        let pins: GpioPins = "/dev/gpiochip1:2,3".parse().unwrap();
+        assert_eq!(pins.path().to_str(), Some("/dev/gpiochip1"));
+        assert_eq!(pins.reset, None);
+
+        assert!("gpiochip0".parse::<GpioPins>().is_err());
+        assert!("gpiochip0:25".parse::<GpioPins>().is_err());
+        assert!("gpiochip0:25,x".parse::<GpioPins>().is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! Raw access to the JTAG scan chain, for cores which are not debugged through an ARM debug
+//! port, like the Xtensa cores of the ESP32 chips.
+//!
+//! All scans start and end in the Run-Test/Idle state of the TAPs. The TAPs of the chain are
+//! given from TDO to TDI, the order in which their IDCODEs are shifted out after a reset.
+
+use super::debug_probe::DebugProbeError;
+
+/// The longest sequence a probe has to clock at once.
+pub const MAX_SEQUENCE_BITS: u8 = 64;
+
+/// Clocks `bit_count` bits of `tdi` out on TDI, LSB first, with TMS held at `tms`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JtagSequence {
+    pub tms: bool,
+    /// Whether the bits on TDO are returned.
+    pub capture: bool,
+    /// 1 to `MAX_SEQUENCE_BITS` bits.
+    pub bit_count: u8,
+    pub tdi: u64,
+}
+
+impl JtagSequence {
+    /// Moves the TAPs through the states selected by `bit_count` clocks with TMS at `tms`.
+    fn tms(tms: bool, bit_count: u8) -> Self {
+        Self {
+            tms,
+            capture: false,
+            bit_count,
+            tdi: 0,
+        }
+    }
+}
+
+pub trait JtagAccess {
+    /// Connects to the target in JTAG mode without initializing an ARM debug port, and moves
+    /// the TAPs to Run-Test/Idle.
+    fn attach_jtag(&mut self) -> Result<(), DebugProbeError>;
+
+    /// Clocks the sequences, and returns the bits sampled on TDO for each sequence which
+    /// captures them.
+    fn jtag_sequences(&mut self, sequences: &[JtagSequence]) -> Result<Vec<u64>, DebugProbeError>;
+}
+
+/// A scan of an instruction or data register of the selected TAP.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scan {
+    /// Loads the instruction into the selected TAP, and BYPASS into all others.
+    Ir(u32),
+    /// Shifts `len` bits of `data` through the data register of the selected TAP.
+    Dr { data: u64, len: u8 },
+}
+
+/// A scan chain with the TAP at `tap` selected.
+#[derive(Debug, Clone, Copy)]
+pub struct JtagChain {
+    /// The IR lengths of the TAPs, from TDO to TDI.
+    pub ir_lengths: &'static [u8],
+    pub tap: usize,
+}
+
+impl JtagChain {
+    /// Runs the scans at once and returns the data shifted out of the selected TAP by each
+    /// data register scan.
+    pub fn scan(
+        &self,
+        probe: &mut dyn JtagAccess,
+        scans: &[Scan],
+    ) -> Result<Vec<u64>, DebugProbeError> {
+        let mut sequences = vec![];
+        let mut data_scans = vec![];
+
+        for scan in scans {
+            let (bits, offset) = match *scan {
+                Scan::Ir(instruction) => {
+                    // Select-DR-Scan, Select-IR-Scan, Capture-IR, Shift-IR.
+                    sequences.push(JtagSequence::tms(true, 2));
+                    sequences.push(JtagSequence::tms(false, 2));
+                    (self.ir_bits(instruction), None)
+                }
+                Scan::Dr { data, len } => {
+                    // Select-DR-Scan, Capture-DR, Shift-DR.
+                    sequences.push(JtagSequence::tms(true, 1));
+                    sequences.push(JtagSequence::tms(false, 2));
+                    let (bits, offset) = self.dr_bits(data, len);
+                    (bits, Some((offset, len)))
+                }
+            };
+
+            let first = sequences.len();
+            sequences.extend(shift_sequences(&bits, offset.is_some()));
+            if let Some((offset, len)) = offset {
+                data_scans.push((first, sequences.len(), offset, len));
+            }
+
+            // Update, then back to Run-Test/Idle.
+            sequences.push(JtagSequence::tms(true, 1));
+            sequences.push(JtagSequence::tms(false, 1));
+        }
+
+        let captured = probe.jtag_sequences(&sequences)?;
+
+        // The captured values are in the order of the sequences which capture.
+        let mut captured = captured.into_iter();
+        Ok(data_scans
+            .into_iter()
+            .map(|(first, end, offset, len)| {
+                let mut bits = vec![];
+                for sequence in &sequences[first..end] {
+                    let value = captured.next().unwrap_or(0);
+                    bits.extend((0..sequence.bit_count).map(|i| value >> i & 1 == 1));
+                }
+                (0..len as usize)
+                    .filter(|i| bits.get(offset + i) == Some(&true))
+                    .fold(0, |value, i| value | 1 << i)
+            })
+            .collect())
+    }
+
+    /// The bits shifted into the IRs of the chain, with all TAPs but the selected one in
+    /// BYPASS.
+    fn ir_bits(&self, instruction: u32) -> Vec<bool> {
+        let mut bits = vec![];
+        for (index, &len) in self.ir_lengths.iter().enumerate() {
+            let value = if index == self.tap {
+                instruction
+            } else {
+                u32::max_value()
+            };
+            bits.extend((0..len).map(|i| value >> i & 1 == 1));
+        }
+        bits
+    }
+
+    /// The bits shifted into the DRs of the chain, where the TAPs in BYPASS have a single bit,
+    /// and the offset of the data of the selected TAP.
+    fn dr_bits(&self, data: u64, len: u8) -> (Vec<bool>, usize) {
+        let mut bits = vec![false; self.tap];
+        bits.extend((0..len).map(|i| data >> i & 1 == 1));
+        bits.extend(vec![false; self.ir_lengths.len() - self.tap - 1]);
+        (bits, self.tap)
+    }
+}
+
+/// Splits the bits shifted in Shift-IR or Shift-DR into sequences. TMS is raised with the
+/// last bit, which moves the TAPs to Exit1.
+fn shift_sequences(bits: &[bool], capture: bool) -> Vec<JtagSequence> {
+    let (last, bits) = match bits.split_last() {
+        Some(split) => split,
+        None => return vec![],
+    };
+
+    let mut sequences: Vec<_> = bits
+        .chunks(MAX_SEQUENCE_BITS as usize)
+        .map(|chunk| JtagSequence {
+            tms: false,
+            capture,
+            bit_count: chunk.len() as u8,
+            tdi: chunk
+                .iter()
+                .enumerate()
+                .fold(0, |tdi, (i, bit)| tdi | u64::from(*bit) << i),
+        })
+        .collect();
+
+    sequences.push(JtagSequence {
+        tms: true,
+        capture,
+        bit_count: 1,
+        tdi: u64::from(*last),
+    });
+
+    sequences
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JtagAccess, JtagChain, JtagSequence, Scan};
+    use crate::probe::debug_probe::DebugProbeError;
+
+    /// Echoes TDI on TDO, as if the chain was a single wire.
+    struct Loopback(Vec<JtagSequence>);
+
+    impl JtagAccess for Loopback {
+        fn attach_jtag(&mut self) -> Result<(), DebugProbeError> {
+            Ok(())
+        }
+
+        fn jtag_sequences(
+            &mut self,
+            sequences: &[JtagSequence],
+        ) -> Result<Vec<u64>, DebugProbeError> {
+            self.0.extend_from_slice(sequences);
+            Ok(sequences
+                .iter()
+                .filter(|sequence| sequence.capture)
+                .map(|sequence| sequence.tdi)
+                .collect())
+        }
+    }
+
+    #[test]
+    fn scans_selected_tap() {
+        let chain = JtagChain {
+            ir_lengths: &[5, 5],
+            tap: 1,
+        };
+        let mut probe = Loopback(vec![]);
+
+        let data = chain
+            .scan(
+                &mut probe,
+                &[Scan::Ir(0x1C), Scan::Dr { data: 0x8B, len: 8 }],
+            )
+            .unwrap();
+
+        assert_eq!(data, vec![0x8B]);
+        // The IR of the other TAP holds BYPASS, and it is shifted first.
+        let ir: Vec<_> = probe.0[2..4].iter().map(|s| (s.bit_count, s.tdi)).collect();
+        assert_eq!(ir, vec![(9, 0b1_1001_1111), (1, 1)]);
+        // The data follows the bit of the TAP in BYPASS.
+        assert_eq!(probe.0[8].tdi, (0x8B << 1) & 0xFF);
+    }
+}
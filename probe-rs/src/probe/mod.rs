@@ -1,6 +1,17 @@
+#[cfg(feature = "serialport")]
+pub mod blackmagic;
+#[cfg(feature = "daplink")]
 pub mod daplink;
+pub mod gpio;
+#[cfg(feature = "stlink")]
 pub mod stlink;
 
+pub mod cancellation;
 pub mod debug_probe;
 pub mod flash;
+pub mod jtag;
 pub mod protocol;
+pub mod recording;
+pub mod registry;
+pub mod retry;
+pub mod watch;
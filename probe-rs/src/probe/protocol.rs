@@ -1,5 +1,93 @@
-#[derive(Copy, Clone, Debug)]
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum WireProtocol {
     Swd,
     Jtag,
 }
+
+impl FromStr for WireProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "swd" => Ok(WireProtocol::Swd),
+            "jtag" => Ok(WireProtocol::Jtag),
+            _ => Err(format!(
+                "'{}' is not a valid protocol, use 'swd' or 'jtag'.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WireProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireProtocol::Swd => write!(f, "SWD"),
+            WireProtocol::Jtag => write!(f, "JTAG"),
+        }
+    }
+}
+
+/// How a probe connects to the target.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AttachMethod {
+    /// Connect and reset the target afterwards using the nRESET pin.
+    Reset,
+    /// Connect to a running target without touching nRESET and without halting the core.
+    ///
+    /// This allows inspecting live systems without disturbing them.
+    NoReset,
+}
+
+/// How the target is reset.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetType {
+    /// Pulse the nRESET pin, which resets the whole chip unless the board does not wire it.
+    Hardware,
+    /// Request a reset of the core and the peripherals with AIRCR.SYSRESETREQ.
+    SysResetReq,
+    /// Reset only the core with AIRCR.VECTRESET, which only ARMv7-M cores support.
+    VectReset,
+    /// Emulate a reset of the core by loading the stack pointer and the program counter from
+    /// the vector table, without touching the peripherals.
+    Core,
+}
+
+impl Default for ResetType {
+    fn default() -> Self {
+        ResetType::SysResetReq
+    }
+}
+
+impl FromStr for ResetType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "hardware" => Ok(ResetType::Hardware),
+            "sysresetreq" => Ok(ResetType::SysResetReq),
+            "vectreset" => Ok(ResetType::VectReset),
+            "core" => Ok(ResetType::Core),
+            _ => Err(format!(
+                "'{}' is not a valid reset type, use 'hardware', 'sysresetreq', 'vectreset' or 'core'.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ResetType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResetType::Hardware => write!(f, "hardware"),
+            ResetType::SysResetReq => write!(f, "SYSRESETREQ"),
+            ResetType::VectReset => write!(f, "VECTRESET"),
+            ResetType::Core => write!(f, "core"),
+        }
+    }
+}
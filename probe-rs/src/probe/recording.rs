@@ -0,0 +1,616 @@
+//! Recording and replaying the traffic between the host and the debug port of the target, for
+//! regression tests of the attach sequence, the flash loader and core control without hardware.
+//!
+//! `RecordingProbe` wraps a real probe and writes each DAP register transfer it passes on to a
+//! fixture file, one JSON object per line. `ReplayProbe` answers the transfers from such a
+//! file instead of a target, and panics as soon as the host sends a request the recording does
+//! not have next, so a test fails at the first transfer which changed:
+//!
+//! ```no_run
+//! use probe_rs::probe::debug_probe::{DebugProbe, MasterProbe};
+//! use probe_rs::probe::protocol::AttachMethod;
+//! use probe_rs::probe::recording::ReplayProbe;
+//!
+//! let mut replay = ReplayProbe::open("tests/captures/nrf52832_attach.jsonl").unwrap();
+//! replay.attach(None, AttachMethod::NoReset).unwrap();
+//! let mut probe = MasterProbe::from_specific_probe(Box::new(replay));
+//! assert_eq!(probe.read_register_dp(0).unwrap(), 0x2ba0_1477);
+//! ```
+//!
+//! A transfer which failed is replayed with the error the probe returned, as far as
+//! `TransferError` tells them apart. The tests of this module replay the captures of an
+//! nRF52832 in `tests/captures`.
+//!
+//! Only the transfers with the debug port are recorded. What the probe does on its own, like
+//! measuring the target voltage or capturing SWO, is not, and replays as in `FakeProbe`.
+
+use super::cancellation::CancellationToken;
+use super::debug_probe::{
+    DAPAccess, DapError, DebugProbe, DebugProbeError, DebugProbeInfo, Port, SwjPins,
+};
+use super::jtag::JtagAccess;
+use super::protocol::{AttachMethod, WireProtocol};
+use super::retry::RetryPolicy;
+use crate::target::quirks::Quirk;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+
+/// A transfer with the debug port, or a request which changes its state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Transaction {
+    Attach,
+    Detach,
+    TargetReset,
+    /// A read of a register of the debug port, or of the access port `ap` if given.
+    Read {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ap: Option<u16>,
+        address: u16,
+        value: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<TransferError>,
+    },
+    Write {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ap: Option<u16>,
+        address: u16,
+        value: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<TransferError>,
+    },
+}
+
+/// Why a recorded transfer failed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferError {
+    /// The target answered with an error, or did not answer.
+    Dap(DapError),
+    Timeout,
+    /// Any other error of the probe, replayed as `DebugProbeError::UnknownError`.
+    Other,
+}
+
+impl From<&DebugProbeError> for TransferError {
+    fn from(e: &DebugProbeError) -> Self {
+        match e {
+            DebugProbeError::Dap(e) => TransferError::Dap(*e),
+            DebugProbeError::Timeout => TransferError::Timeout,
+            _ => TransferError::Other,
+        }
+    }
+}
+
+impl From<TransferError> for DebugProbeError {
+    fn from(e: TransferError) -> Self {
+        match e {
+            TransferError::Dap(e) => DebugProbeError::Dap(e),
+            TransferError::Timeout => DebugProbeError::Timeout,
+            TransferError::Other => DebugProbeError::UnknownError,
+        }
+    }
+}
+
+impl Transaction {
+    /// Whether the host sent the same request in both transactions, regardless of the answer.
+    fn same_request(&self, other: &Transaction) -> bool {
+        match (self, other) {
+            (
+                Transaction::Read { ap, address, .. },
+                Transaction::Read {
+                    ap: other_ap,
+                    address: other_address,
+                    ..
+                },
+            ) => ap == other_ap && address == other_address,
+            (
+                Transaction::Write {
+                    ap, address, value, ..
+                },
+                Transaction::Write {
+                    ap: other_ap,
+                    address: other_address,
+                    value: other_value,
+                    ..
+                },
+            ) => ap == other_ap && address == other_address && value == other_value,
+            (transaction, other) => transaction == other,
+        }
+    }
+}
+
+fn access_port(port: &Port) -> Option<u16> {
+    match port {
+        Port::DebugPort => None,
+        Port::AccessPort(ap) => Some(*ap),
+    }
+}
+
+/// Passes everything on to a real probe and records the transfers with the debug port.
+pub struct RecordingProbe {
+    probe: Box<dyn DebugProbe>,
+    file: BufWriter<File>,
+}
+
+impl RecordingProbe {
+    /// Records the traffic of `probe` into a new file at `path`.
+    pub fn create(probe: Box<dyn DebugProbe>, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(RecordingProbe {
+            probe,
+            file: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    fn record(&mut self, transaction: Transaction) {
+        let line = serde_json::to_string(&transaction).expect("Failed to serialize a transaction.");
+
+        // The recording is not worth failing the transfer for.
+        if let Err(e) = writeln!(self.file, "{}", line) {
+            log::warn!("Failed to record the probe traffic: {}", e);
+        }
+    }
+}
+
+impl DebugProbe for RecordingProbe {
+    fn new_from_probe_info(_info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    fn get_name(&self) -> &str {
+        self.probe.get_name()
+    }
+
+    fn attach(
+        &mut self,
+        protocol: Option<WireProtocol>,
+        method: AttachMethod,
+    ) -> Result<WireProtocol, DebugProbeError> {
+        let protocol = self.probe.attach(protocol, method)?;
+        self.record(Transaction::Attach);
+
+        Ok(protocol)
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.probe.detach()?;
+        self.record(Transaction::Detach);
+
+        if let Err(e) = self.file.flush() {
+            log::warn!("Failed to record the probe traffic: {}", e);
+        }
+
+        Ok(())
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.probe.target_reset()?;
+        self.record(Transaction::TargetReset);
+
+        Ok(())
+    }
+
+    fn set_multidrop_target(&mut self, target_sel: Option<u32>) -> Result<(), DebugProbeError> {
+        self.probe.set_multidrop_target(target_sel)
+    }
+
+    fn set_quirks(&mut self, quirks: &[Quirk]) -> Result<(), DebugProbeError> {
+        self.probe.set_quirks(quirks)
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        self.probe.set_retry_policy(policy)
+    }
+
+    fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.probe.set_cancellation_token(token)
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.probe.set_speed(speed_khz)
+    }
+
+    fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError> {
+        self.probe.get_target_voltage()
+    }
+
+    fn enable_swo(&mut self, baud_rate: u32) -> Result<(), DebugProbeError> {
+        self.probe.enable_swo(baud_rate)
+    }
+
+    fn disable_swo(&mut self) -> Result<(), DebugProbeError> {
+        self.probe.disable_swo()
+    }
+
+    fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        self.probe.read_swo()
+    }
+
+    fn swj_pins(
+        &mut self,
+        output: SwjPins,
+        select: SwjPins,
+        wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError> {
+        self.probe.swj_pins(output, select, wait_us)
+    }
+
+    fn jtag_access(&mut self) -> Option<&mut dyn JtagAccess> {
+        self.probe.jtag_access()
+    }
+
+    /// Only DAP register transfers are recorded, so the memory commands of the probe are not
+    /// used.
+    fn read_block16(&mut self, _address: u32, _data: &mut [u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+
+    fn write_block16(&mut self, _address: u32, _data: &[u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+}
+
+impl DAPAccess for RecordingProbe {
+    fn read_register(&mut self, port: Port, addr: u16) -> Result<u32, DebugProbeError> {
+        let ap = access_port(&port);
+        let result = self.probe.read_register(port, addr);
+
+        self.record(Transaction::Read {
+            ap,
+            address: addr,
+            value: *result.as_ref().unwrap_or(&0),
+            error: result.as_ref().err().map(TransferError::from),
+        });
+
+        result
+    }
+
+    fn write_register(&mut self, port: Port, addr: u16, value: u32) -> Result<(), DebugProbeError> {
+        let ap = access_port(&port);
+        let result = self.probe.write_register(port, addr, value);
+
+        self.record(Transaction::Write {
+            ap,
+            address: addr,
+            value,
+            error: result.as_ref().err().map(TransferError::from),
+        });
+
+        result
+    }
+}
+
+/// Answers the transfers with the debug port from a recording instead of a target.
+#[derive(Debug)]
+pub struct ReplayProbe {
+    transactions: VecDeque<Transaction>,
+    /// The number of transactions replayed so far.
+    position: usize,
+}
+
+impl ReplayProbe {
+    pub fn new(transactions: Vec<Transaction>) -> Self {
+        ReplayProbe {
+            transactions: transactions.into(),
+            position: 0,
+        }
+    }
+
+    /// Reads a recording made with `RecordingProbe`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut transactions = vec![];
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                transactions.push(serde_json::from_str(&line)?);
+            }
+        }
+
+        Ok(ReplayProbe::new(transactions))
+    }
+
+    /// Returns the recorded transaction for the request of the host.
+    ///
+    /// # Panics
+    ///
+    /// If the recording has a different request next.
+    fn replay(&mut self, request: Transaction) -> Transaction {
+        match self.transactions.pop_front() {
+            Some(transaction) if transaction.same_request(&request) => {
+                self.position += 1;
+                transaction
+            }
+            recorded => panic!(
+                "The probe traffic differs from the recording at transaction {}: recorded {:?}, requested {:?}",
+                self.position, recorded, request
+            ),
+        }
+    }
+
+    /// Replays a request the recording has no answer for.
+    fn expect(&mut self, request: Transaction) -> Result<(), DebugProbeError> {
+        self.replay(request);
+
+        Ok(())
+    }
+}
+
+impl Drop for ReplayProbe {
+    /// Fails the test if the host stopped before the end of the recording, unless it already
+    /// failed.
+    fn drop(&mut self) {
+        if !self.transactions.is_empty() && !thread::panicking() {
+            panic!(
+                "The probe traffic ended at transaction {}, but the recording has {} more, starting with {:?}",
+                self.position,
+                self.transactions.len(),
+                self.transactions.front()
+            );
+        }
+    }
+}
+
+impl DebugProbe for ReplayProbe {
+    fn new_from_probe_info(_info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    fn get_name(&self) -> &str {
+        "Replay of recorded probe traffic"
+    }
+
+    fn attach(
+        &mut self,
+        protocol: Option<WireProtocol>,
+        _method: AttachMethod,
+    ) -> Result<WireProtocol, DebugProbeError> {
+        self.replay(Transaction::Attach);
+
+        Ok(protocol.unwrap_or(WireProtocol::Swd))
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.expect(Transaction::Detach)
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.expect(Transaction::TargetReset)
+    }
+
+    fn set_multidrop_target(&mut self, _target_sel: Option<u32>) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn set_quirks(&mut self, _quirks: &[Quirk]) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn set_retry_policy(&mut self, _policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn set_cancellation_token(&mut self, _token: CancellationToken) {}
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        Ok(speed_khz)
+    }
+
+    fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError> {
+        Ok(None)
+    }
+
+    fn enable_swo(&mut self, _baud_rate: u32) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn disable_swo(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn swj_pins(
+        &mut self,
+        _output: SwjPins,
+        _select: SwjPins,
+        _wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn jtag_access(&mut self) -> Option<&mut dyn JtagAccess> {
+        None
+    }
+
+    /// The recording only holds DAP register transfers.
+    fn read_block16(&mut self, _address: u32, _data: &mut [u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+
+    fn write_block16(&mut self, _address: u32, _data: &[u16]) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::Access16BitNotSupported)
+    }
+}
+
+impl DAPAccess for ReplayProbe {
+    fn read_register(&mut self, port: Port, addr: u16) -> Result<u32, DebugProbeError> {
+        let recorded = self.replay(Transaction::Read {
+            ap: access_port(&port),
+            address: addr,
+            value: 0,
+            error: None,
+        });
+
+        match recorded {
+            Transaction::Read {
+                error: Some(error), ..
+            } => Err(error.into()),
+            Transaction::Read { value, .. } => Ok(value),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_register(&mut self, port: Port, addr: u16, value: u32) -> Result<(), DebugProbeError> {
+        let recorded = self.replay(Transaction::Write {
+            ap: access_port(&port),
+            address: addr,
+            value,
+            error: None,
+        });
+
+        match recorded {
+            Transaction::Write {
+                error: Some(error), ..
+            } => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ReplayProbe, Transaction, TransferError};
+    use crate::collection::cores::m4::{M4, REGISTERS};
+    use crate::probe::debug_probe::{DapError, DebugProbe, DebugProbeError, MasterProbe};
+    use crate::probe::flash::{FlashAlgorithm, Flasher, MemoryRegion};
+    use crate::probe::protocol::{AttachMethod, WireProtocol};
+    use crate::target::{info::ChipInfo, Core, Target};
+
+    use std::path::Path;
+
+    /// Opens a capture of `tests/captures` and attaches to the target like a session.
+    fn replay(capture: &str) -> MasterProbe {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/captures")
+            .join(capture);
+        let mut replay = ReplayProbe::open(path).unwrap();
+        replay
+            .attach(Some(WireProtocol::Swd), AttachMethod::NoReset)
+            .unwrap();
+
+        MasterProbe::from_specific_probe(Box::new(replay))
+    }
+
+    #[test]
+    fn replays_recorded_transfers() {
+        let recording = [
+            r#"{"op":"read","address":0,"value":732959863}"#,
+            r#"{"op":"write","address":4,"value":1342177280,"error":{"dap":"sticky"}}"#,
+        ];
+        let transactions = recording
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let mut probe = MasterProbe::from_specific_probe(Box::new(ReplayProbe::new(transactions)));
+
+        assert_eq!(probe.read_register_dp(0).unwrap(), 0x2ba0_1477);
+        assert!(matches!(
+            probe.write_register_dp(4, 0x5000_0000),
+            Err(DebugProbeError::Dap(DapError::Sticky))
+        ));
+    }
+
+    #[test]
+    fn records_the_error_of_a_transfer() {
+        let transaction = Transaction::Read {
+            ap: Some(0),
+            address: 0x0C,
+            value: 0,
+            error: Some(TransferError::from(&DebugProbeError::Timeout)),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&transaction).unwrap(),
+            r#"{"op":"read","ap":0,"address":12,"value":0,"error":"timeout"}"#
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_on_different_requests() {
+        let mut probe = ReplayProbe::new(vec![Transaction::TargetReset]);
+
+        let _ = probe.detach();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_on_transactions_not_replayed() {
+        drop(ReplayProbe::new(vec![Transaction::Detach]));
+    }
+
+    #[test]
+    fn replays_the_attach_sequence() {
+        let mut probe = replay("nrf52832_attach.jsonl");
+
+        // The nRF52832 has the ROM table of the Cortex-M4 designed by ARM.
+        let info = ChipInfo::read_from_rom_table(&mut probe).unwrap();
+        assert_eq!(info.dpidr, Some(0x2ba0_1477));
+        assert_eq!((info.manufacturer.cc, info.manufacturer.id), (0x04, 0x3b));
+        assert_eq!(info.part, 0x4c4);
+
+        probe.detach().unwrap();
+    }
+
+    #[test]
+    fn replays_core_control() {
+        let mut probe = replay("nrf52832_core.jsonl");
+        let core = M4::default();
+
+        assert_eq!(core.halt(&mut probe).unwrap().pc, 0x0a3c);
+        assert_eq!(core.step(&mut probe).unwrap().pc, 0x0a3e);
+
+        // The write is only transferred when the core resumes.
+        core.write_core_reg(&mut probe, REGISTERS.R0, 0x2000_0100)
+            .unwrap();
+        assert_eq!(
+            core.read_core_reg(&mut probe, REGISTERS.R0).unwrap(),
+            0x2000_0100
+        );
+        core.run(&mut probe).unwrap();
+
+        probe.detach().unwrap();
+    }
+
+    #[test]
+    fn replays_erasing_and_programming_the_flash() {
+        let mut probe = replay("nrf52832_flash.jsonl");
+        let target = Target::with_bases(
+            include_str!("../../../probe-rs-targets/targets/nRF52832.yaml"),
+            |_| Some(include_str!("../../../probe-rs-targets/bases/nRF52.yaml").to_owned()),
+        )
+        .unwrap();
+        let algorithm = FlashAlgorithm::new(include_str!(
+            "../../../probe-rs-targets/algorithms/nRF52832.yaml"
+        ))
+        .unwrap();
+        let region = match &target.memory_map[0] {
+            MemoryRegion::Flash(region) => region.clone(),
+            region => panic!("Expected the flash first, found {:?}", region),
+        };
+        let page: Vec<u8> = (0..16).collect();
+
+        let mut flasher = Flasher::new(&target, &mut probe, &algorithm, &region);
+        flasher
+            .run_erase(|flasher| flasher.erase_sector(0))
+            .unwrap();
+        flasher
+            .run_program(|flasher| flasher.program_page(0, &page))
+            .unwrap();
+
+        probe.detach().unwrap();
+    }
+}
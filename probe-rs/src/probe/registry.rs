@@ -0,0 +1,134 @@
+//! Drivers of probes from other crates, e.g. of in-house probes, which are listed and opened
+//! like the built-in ones.
+//!
+//! A driver is registered once, before the probes are listed. Its probes have the type
+//! `DebugProbeType::Registered` with the name of the driver, by which `open_probe` finds the
+//! driver again, so they can be selected like any other probe, e.g. by `SessionBuilder`.
+//!
+//! ```no_run
+//! # use probe_rs::probe::debug_probe::{DebugProbe, DebugProbeError, DebugProbeInfo};
+//! # use probe_rs::probe::registry::{register_driver, ProbeDriver};
+//! struct LabProbeDriver;
+//!
+//! impl ProbeDriver for LabProbeDriver {
+//!     fn name(&self) -> &'static str {
+//!         "Lab probe"
+//!     }
+//!
+//!     fn list_probes(&self) -> Vec<DebugProbeInfo> {
+//!         // Enumerate the probes of the driver, with `DebugProbeType::Registered("Lab probe")`.
+//!         vec![]
+//!     }
+//!
+//!     fn open(&self, info: &DebugProbeInfo) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+//!         Err(DebugProbeError::ProbeCouldNotBeCreated)
+//!     }
+//! }
+//!
+//! register_driver(LabProbeDriver);
+//! ```
+
+use super::debug_probe::{DebugProbe, DebugProbeError, DebugProbeInfo};
+
+use lazy_static::lazy_static;
+
+use std::sync::{Arc, RwLock};
+
+lazy_static! {
+    static ref DRIVERS: RwLock<Vec<Arc<dyn ProbeDriver>>> = RwLock::new(vec![]);
+}
+
+/// A driver of probes, which lists the connected probes and opens them.
+pub trait ProbeDriver: Send + Sync {
+    /// The name of the driver, which tells its probes apart from the ones of other drivers.
+    fn name(&self) -> &'static str;
+
+    /// The connected probes of the driver, with the type `DebugProbeType::Registered` and
+    /// the name of the driver.
+    ///
+    /// `DebugProbeInfo::new` gives probes of registered drivers no capabilities, the driver
+    /// sets the ones of each probe.
+    fn list_probes(&self) -> Vec<DebugProbeInfo>;
+
+    /// Opens a probe found by `list_probes`.
+    fn open(&self, info: &DebugProbeInfo) -> Result<Box<dyn DebugProbe>, DebugProbeError>;
+}
+
+/// Registers a driver, replacing the one with the same name, if any.
+pub fn register_driver(driver: impl ProbeDriver + 'static) {
+    let mut drivers = DRIVERS.write().unwrap();
+
+    drivers.retain(|registered| registered.name() != driver.name());
+    drivers.push(Arc::new(driver));
+}
+
+/// The connected probes of all registered drivers.
+pub(crate) fn list_probes() -> Vec<DebugProbeInfo> {
+    // The lock is not held while enumerating, so drivers can be registered meanwhile.
+    let drivers = DRIVERS.read().unwrap().clone();
+
+    drivers
+        .iter()
+        .flat_map(|driver| driver.list_probes())
+        .collect()
+}
+
+/// Opens a probe with the registered driver named `name`.
+pub(crate) fn open_probe(
+    name: &str,
+    info: &DebugProbeInfo,
+) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+    let driver = DRIVERS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|driver| driver.name() == name)
+        .cloned()
+        .ok_or(DebugProbeError::ProbeCouldNotBeCreated)?;
+
+    driver.open(info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{list_probes, open_probe, register_driver, ProbeDriver};
+    use crate::probe::debug_probe::{
+        DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeType, FakeProbe,
+    };
+
+    struct TestDriver;
+
+    impl ProbeDriver for TestDriver {
+        fn name(&self) -> &'static str {
+            "Test driver"
+        }
+
+        fn list_probes(&self) -> Vec<DebugProbeInfo> {
+            vec![DebugProbeInfo::new(
+                "Test probe",
+                0x1209,
+                0x0001,
+                Some("42".to_string()),
+                DebugProbeType::Registered(self.name()),
+                None,
+            )]
+        }
+
+        fn open(&self, _info: &DebugProbeInfo) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+            Ok(Box::new(FakeProbe::new()))
+        }
+    }
+
+    #[test]
+    fn lists_and_opens_the_probes_of_registered_drivers() {
+        register_driver(TestDriver);
+
+        let probe = list_probes()
+            .into_iter()
+            .find(|probe| probe.identifier == "Test probe")
+            .expect("The probe of the registered driver is not listed.");
+        assert_eq!(probe.to_string(), "Test probe (1209:0001:42) []");
+        assert!(open_probe("Test driver", &probe).is_ok());
+        assert!(open_probe("Unknown driver", &probe).is_err());
+    }
+}
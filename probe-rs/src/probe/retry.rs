@@ -0,0 +1,35 @@
+//! How persistent the probes are with targets which are slow to answer.
+
+use std::time::Duration;
+
+/// How often and how long transactions with the target are retried before they fail.
+///
+/// Targets which stall their bus, e.g. with heavy DMA traffic or in low power modes, answer
+/// transfers with WAIT for longer than usual, and slow clocks need longer timeouts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// How often a transfer the target answers with WAIT is retried.
+    pub wait_retries: u16,
+    /// Whether the sticky error flags of the debug port are cleared through ABORT after a
+    /// transfer faulted. Otherwise all further transfers fault as well.
+    pub clear_sticky_errors: bool,
+    /// Whether the debug port is initialized again after the target did not answer a transfer,
+    /// e.g. because a reset or a low power mode disturbed the wire protocol.
+    pub reinitialize_debug_port: bool,
+    /// The timeout of a single USB transfer to or from the probe.
+    pub usb_timeout: Duration,
+    /// How long to wait for the core to halt.
+    pub halt_timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            wait_retries: 80,
+            clear_sticky_errors: true,
+            reinitialize_debug_port: true,
+            usb_timeout: Duration::from_millis(1000),
+            halt_timeout: Duration::from_millis(500),
+        }
+    }
+}
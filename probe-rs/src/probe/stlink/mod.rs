@@ -4,22 +4,37 @@ pub mod tools;
 mod usb_interface;
 
 pub use self::usb_interface::STLinkUSBDevice;
+use self::usb_interface::StLinkUsb;
 
 use crate::coresight::ap_access::AccessPort;
+use crate::coresight::common::Register;
+use crate::coresight::debug_port::{Abort, Ctrl};
+use crate::probe::cancellation::CancellationToken;
 use crate::probe::debug_probe::{DebugProbeInfo, Port};
 use scroll::{Pread, BE};
 
-use crate::probe::debug_probe::{DAPAccess, DebugProbe, DebugProbeError};
-use crate::probe::protocol::WireProtocol;
+use crate::probe::debug_probe::{DAPAccess, DapError, DebugProbe, DebugProbeError, SwjPins};
+use crate::probe::jtag::JtagAccess;
+use crate::probe::protocol::{AttachMethod, WireProtocol};
+use crate::probe::retry::RetryPolicy;
+use crate::target::quirks::Quirk;
+
+use log::{info, warn};
 
 use constants::{commands, JTagFrequencyToDivider, Status, SwdFrequencyToDelayCount};
-use usb_interface::TIMEOUT;
 
 pub struct STLink {
-    device: STLinkUSBDevice,
+    device: Box<dyn StLinkUsb>,
     hw_version: u8,
     jtag_version: u8,
+    swim_version: u8,
     protocol: WireProtocol,
+    /// The speed set with `set_speed` in kHz, applied when attaching.
+    speed_khz: Option<u32>,
+    retry_policy: RetryPolicy,
+    /// Set while recovering from a failed access, so the accesses of the recovery do not
+    /// recover themselves.
+    recovering: bool,
 }
 
 impl DebugProbe for STLink {
@@ -28,10 +43,14 @@ impl DebugProbe for STLink {
         Self: Sized,
     {
         let mut stlink = Self {
-            device: STLinkUSBDevice::new_from_info(info)?,
+            device: Box::new(STLinkUSBDevice::new_from_info(info)?),
             hw_version: 0,
             jtag_version: 0,
+            swim_version: 0,
             protocol: WireProtocol::Swd,
+            speed_khz: None,
+            retry_policy: RetryPolicy::default(),
+            recovering: false,
         };
 
         stlink.init()?;
@@ -44,34 +63,23 @@ impl DebugProbe for STLink {
     }
 
     /// Enters debug mode.
-    fn attach(&mut self, protocol: Option<WireProtocol>) -> Result<WireProtocol, DebugProbeError> {
-        self.enter_idle()?;
-
-        let (param, protocol) = if let Some(protocol) = protocol {
-            (
-                match protocol {
-                    WireProtocol::Jtag => commands::JTAG_ENTER_JTAG_NO_CORE_RESET,
-                    WireProtocol::Swd => commands::JTAG_ENTER_SWD,
-                },
-                protocol,
-            )
-        } else {
-            (commands::JTAG_ENTER_SWD, WireProtocol::Swd)
-        };
+    ///
+    /// Entering SWD or JTAG mode (without the connect under reset variants)
+    /// does not drive nRESET or halt the core.
+    /// With `AttachMethod::Reset` the target is reset once the connection is established.
+    fn attach(
+        &mut self,
+        protocol: Option<WireProtocol>,
+        method: AttachMethod,
+    ) -> Result<WireProtocol, DebugProbeError> {
+        let protocol = protocol.unwrap_or(WireProtocol::Swd);
+        self.enter_debug_mode(protocol)?;
+
+        if method == AttachMethod::Reset {
+            self.target_reset()?;
+        }
 
-        let mut buf = [0; 2];
-        self.device.write(
-            vec![commands::JTAG_COMMAND, commands::JTAG_ENTER2, param, 0],
-            &[],
-            &mut buf,
-            TIMEOUT,
-        )?;
-        Self::check_status(&buf).and_then(|_| {
-            // After we checked the status with success,
-            // We store the current protocol.
-            self.protocol = protocol;
-            Ok(protocol)
-        })
+        Ok(protocol)
     }
 
     /// Leave debug mode.
@@ -90,10 +98,181 @@ impl DebugProbe for STLink {
             ],
             &[],
             &mut buf,
-            TIMEOUT,
+            self.retry_policy.usb_timeout,
         )?;
         Self::check_status(&buf)
     }
+
+    /// The ST-Link firmware does not support SWD multi-drop.
+    fn set_multidrop_target(&mut self, target_sel: Option<u32>) -> Result<(), DebugProbeError> {
+        match target_sel {
+            Some(_) => Err(DebugProbeError::CommandNotSupportedByProbe),
+            None => Ok(()),
+        }
+    }
+
+    /// The firmware powers up the debug port itself.
+    fn set_quirks(&mut self, _quirks: &[Quirk]) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        self.retry_policy = policy;
+
+        Ok(())
+    }
+
+    /// The USB transfers time out after the `usb_timeout` of the retry policy.
+    fn set_cancellation_token(&mut self, _token: CancellationToken) {}
+
+    /// The speed is applied when attaching, as it is set separately for SWD and JTAG.
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.speed_khz = Some(speed_khz);
+
+        Ok(match self.protocol {
+            WireProtocol::Swd => swd_frequency(speed_khz).1,
+            WireProtocol::Jtag => jtag_frequency(speed_khz).1,
+        })
+    }
+
+    /// Reads the target voltage measured by the ST-Link.
+    fn get_target_voltage(&mut self) -> Result<Option<f32>, DebugProbeError> {
+        STLink::get_target_voltage(self).map(Some)
+    }
+
+    /// Fails with `DebugProbeError::SwoBaudRateUnsupported` above the maximum baud rate of
+    /// the hardware version, see `max_swo_baud_rate`.
+    fn enable_swo(&mut self, baud_rate: u32) -> Result<(), DebugProbeError> {
+        let max_baud_rate = self.max_swo_baud_rate();
+        if baud_rate > max_baud_rate {
+            return Err(DebugProbeError::SwoBaudRateUnsupported {
+                baud_rate,
+                max_baud_rate,
+            });
+        }
+
+        let mut command = vec![commands::JTAG_COMMAND, commands::SWV_START_TRACE_RECEPTION];
+        command.extend_from_slice(&Self::SWV_BUFFER_SIZE.to_le_bytes());
+        command.extend_from_slice(&baud_rate.to_le_bytes());
+
+        let mut buf = [0; 2];
+        self.device
+            .write(command, &[], &mut buf, self.retry_policy.usb_timeout)?;
+        Self::check_status(&buf)
+    }
+
+    fn disable_swo(&mut self) -> Result<(), DebugProbeError> {
+        let mut buf = [0; 2];
+        self.device.write(
+            vec![commands::JTAG_COMMAND, commands::SWV_STOP_TRACE_RECEPTION],
+            &[],
+            &mut buf,
+            self.retry_policy.usb_timeout,
+        )?;
+        Self::check_status(&buf)
+    }
+
+    /// Asks the ST-Link how many bytes it captured and reads them from the trace endpoint.
+    fn read_swo(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        let mut buf = [0; 2];
+        self.device.write(
+            vec![
+                commands::JTAG_COMMAND,
+                commands::SWV_GET_TRACE_NEW_RECORD_NB,
+            ],
+            &[],
+            &mut buf,
+            self.retry_policy.usb_timeout,
+        )?;
+
+        match u16::from_le_bytes(buf) {
+            0 => Ok(vec![]),
+            length => self
+                .device
+                .read_swv(length as usize, self.retry_policy.usb_timeout),
+        }
+    }
+
+    /// The ST-Link only drives nRESET and cannot read any pins, so the driven levels are
+    /// returned.
+    fn swj_pins(
+        &mut self,
+        output: SwjPins,
+        select: SwjPins,
+        _wait_us: u32,
+    ) -> Result<SwjPins, DebugProbeError> {
+        if !SwjPins::NRESET.contains(select) {
+            return Err(DebugProbeError::CommandNotSupportedByProbe);
+        }
+
+        if select.contains(SwjPins::NRESET) {
+            self.drive_nreset(!output.contains(SwjPins::NRESET))?;
+        }
+
+        Ok(output & select)
+    }
+
+    /// The ST-Link only talks to ARM debug ports, it has no command to clock raw JTAG.
+    fn jtag_access(&mut self) -> Option<&mut dyn JtagAccess> {
+        None
+    }
+
+    fn read_block16(&mut self, address: u32, data: &mut [u16]) -> Result<(), DebugProbeError> {
+        if !self.supports_16bit_access() {
+            return Err(self.firmware_upgrade_required(
+                "16-bit memory access",
+                Self::MIN_JTAG_VERSION_16BIT_XFER,
+            ));
+        }
+
+        let chunk_len = self.max_transfer_size() as usize / 2;
+        for (index, chunk) in data.chunks_mut(chunk_len).enumerate() {
+            let address = address + (index * chunk_len * 2) as u32;
+            let mut bytes = vec![0; chunk.len() * 2];
+
+            self.device.write(
+                Self::memory_command(commands::JTAG_READMEM_16BIT, address, bytes.len()),
+                &[],
+                &mut bytes,
+                self.retry_policy.usb_timeout,
+            )?;
+            self.check_rw_status(bytes.len())?;
+
+            for (halfword, bytes) in chunk.iter_mut().zip(bytes.chunks(2)) {
+                *halfword = u16::from_le_bytes([bytes[0], bytes[1]]);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_block16(&mut self, address: u32, data: &[u16]) -> Result<(), DebugProbeError> {
+        if !self.supports_16bit_access() {
+            return Err(self.firmware_upgrade_required(
+                "16-bit memory access",
+                Self::MIN_JTAG_VERSION_16BIT_XFER,
+            ));
+        }
+
+        let chunk_len = self.max_transfer_size() as usize / 2;
+        for (index, chunk) in data.chunks(chunk_len).enumerate() {
+            let address = address + (index * chunk_len * 2) as u32;
+            let bytes: Vec<u8> = chunk
+                .iter()
+                .flat_map(|halfword| halfword.to_le_bytes().to_vec())
+                .collect();
+
+            self.device.write(
+                Self::memory_command(commands::JTAG_WRITEMEM_16BIT, address, bytes.len()),
+                &bytes,
+                &mut [],
+                self.retry_policy.usb_timeout,
+            )?;
+            self.check_rw_status(bytes.len())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl DAPAccess for STLink {
@@ -114,8 +293,7 @@ impl DAPAccess for STLink {
                 ((addr >> 8) & 0xFF) as u8,
             ];
             let mut buf = [0; 8];
-            self.device.write(cmd, &[], &mut buf, TIMEOUT)?;
-            Self::check_status(&buf)?;
+            self.dap_transfer(cmd, &mut buf)?;
             // Unwrap is ok!
             Ok((&buf[4..8]).pread(0).unwrap())
         } else {
@@ -144,9 +322,7 @@ impl DAPAccess for STLink {
                 ((value >> 24) & 0xFF) as u8,
             ];
             let mut buf = [0; 2];
-            self.device.write(cmd, &[], &mut buf, TIMEOUT)?;
-            Self::check_status(&buf)?;
-            Ok(())
+            self.dap_transfer(cmd, &mut buf)
         } else {
             Err(DebugProbeError::BlanksNotAllowedOnDPRegister)
         }
@@ -164,25 +340,99 @@ impl STLink {
     /// Maximum number of bytes to send or receive for 32- and 16- bit transfers.
     ///
     /// 8-bit transfers have a maximum size of the maximum USB packet size (64 bytes for full speed).
-    const _MAXIMUM_TRANSFER_SIZE: u32 = 1024;
+    const MAXIMUM_TRANSFER_SIZE: u32 = 1024;
+
+    /// Maximum number of bytes for 32- and 16-bit transfers on the high speed ST-Link V3.
+    const MAXIMUM_TRANSFER_SIZE_V3: u32 = 4096;
 
     /// Minimum required STLink firmware version.
     const MIN_JTAG_VERSION: u8 = 24;
 
     /// Firmware version that adds 16-bit transfers.
-    const _MIN_JTAG_VERSION_16BIT_XFER: u8 = 26;
+    const MIN_JTAG_VERSION_16BIT_XFER: u8 = 26;
 
     /// Firmware version that adds multiple AP support.
     const MIN_JTAG_VERSION_MULTI_AP: u8 = 28;
 
+    /// Size of the buffer the ST-Link captures SWO data into.
+    const SWV_BUFFER_SIZE: u16 = 4096;
+
+    /// Maximum SWO baud rate of the ST-Link V2.
+    const MAX_SWO_BAUD_RATE: u32 = 2_000_000;
+
+    /// Maximum SWO baud rate of the ST-Link V3.
+    const MAX_SWO_BAUD_RATE_V3: u32 = 24_000_000;
+
+    /// Returns the hardware version of the ST-Link, e.g. `2` for an ST-Link V2 or V2-1.
+    pub fn hw_version(&self) -> u8 {
+        self.hw_version
+    }
+
+    /// Returns the firmware version string as printed by ST tools, e.g. `V2J29S7`.
+    pub fn firmware_version(&self) -> String {
+        if self.swim_version != 0 {
+            format!(
+                "V{}J{}S{}",
+                self.hw_version, self.jtag_version, self.swim_version
+            )
+        } else {
+            format!("V{}J{}", self.hw_version, self.jtag_version)
+        }
+    }
+
+    /// Whether the firmware supports 16-bit memory transfers.
+    pub fn supports_16bit_access(&self) -> bool {
+        self.hw_version >= 3 || self.jtag_version >= Self::MIN_JTAG_VERSION_16BIT_XFER
+    }
+
+    /// Whether the firmware supports accessing APs other than AP 0.
+    pub fn supports_multiple_aps(&self) -> bool {
+        self.hw_version >= 3 || self.jtag_version >= Self::MIN_JTAG_VERSION_MULTI_AP
+    }
+
+    /// Returns the maximum number of bytes which can be transferred in a single 16- or 32-bit transfer.
+    pub fn max_transfer_size(&self) -> u32 {
+        if self.hw_version >= 3 {
+            Self::MAXIMUM_TRANSFER_SIZE_V3
+        } else {
+            Self::MAXIMUM_TRANSFER_SIZE
+        }
+    }
+
+    /// Returns the highest baud rate at which the ST-Link captures SWO.
+    pub fn max_swo_baud_rate(&self) -> u32 {
+        if self.hw_version >= 3 {
+            Self::MAX_SWO_BAUD_RATE_V3
+        } else {
+            Self::MAX_SWO_BAUD_RATE
+        }
+    }
+
+    /// Builds the error returned when a feature needs a newer ST-Link V2 firmware.
+    ///
+    /// `min_jtag_version` is the JTAG version of the first firmware supporting `feature`.
+    fn firmware_upgrade_required(
+        &self,
+        feature: &'static str,
+        min_jtag_version: u8,
+    ) -> DebugProbeError {
+        DebugProbeError::FirmwareUpgradeRequired {
+            feature,
+            current: self.firmware_version(),
+            required: format!("V{}J{}", self.hw_version, min_jtag_version),
+        }
+    }
+
     /// Reads the target voltage.
     /// For the china fake variants this will always read a nonzero value!
     pub fn get_target_voltage(&mut self) -> Result<f32, DebugProbeError> {
         let mut buf = [0; 8];
-        match self
-            .device
-            .write(vec![commands::GET_TARGET_VOLTAGE], &[], &mut buf, TIMEOUT)
-        {
+        match self.device.write(
+            vec![commands::GET_TARGET_VOLTAGE],
+            &[],
+            &mut buf,
+            self.retry_policy.usb_timeout,
+        ) {
             Ok(_) => {
                 // The next two unwraps are safe!
                 let a0 = (&buf[0..4]).pread::<u32>(0).unwrap() as f32;
@@ -202,31 +452,33 @@ impl STLink {
     /// Internal helper.
     fn enter_idle(&mut self) -> Result<(), DebugProbeError> {
         let mut buf = [0; 2];
-        match self
-            .device
-            .write(vec![commands::GET_CURRENT_MODE], &[], &mut buf, TIMEOUT)
-        {
+        match self.device.write(
+            vec![commands::GET_CURRENT_MODE],
+            &[],
+            &mut buf,
+            self.retry_policy.usb_timeout,
+        ) {
             Ok(_) => {
                 if buf[0] == commands::DEV_DFU_MODE {
                     self.device.write(
                         vec![commands::DFU_COMMAND, commands::DFU_EXIT],
                         &[],
                         &mut [],
-                        TIMEOUT,
+                        self.retry_policy.usb_timeout,
                     )
                 } else if buf[0] == commands::DEV_JTAG_MODE {
                     self.device.write(
                         vec![commands::JTAG_COMMAND, commands::JTAG_EXIT],
                         &[],
                         &mut [],
-                        TIMEOUT,
+                        self.retry_policy.usb_timeout,
                     )
                 } else if buf[0] == commands::DEV_SWIM_MODE {
                     self.device.write(
                         vec![commands::SWIM_COMMAND, commands::SWIM_EXIT],
                         &[],
                         &mut [],
-                        TIMEOUT,
+                        self.retry_policy.usb_timeout,
                     )
                 } else {
                     Ok(())
@@ -246,6 +498,7 @@ impl STLink {
         const HW_VERSION_MASK: u8 = 0x0F;
         const JTAG_VERSION_SHIFT: u8 = 6;
         const JTAG_VERSION_MASK: u8 = 0x3F;
+        const SWIM_VERSION_MASK: u8 = 0x3F;
         // GET_VERSION response structure:
         //   Byte 0-1:
         //     [15:12] Major/HW version
@@ -254,14 +507,17 @@ impl STLink {
         //   Byte 2-3: ST_VID
         //   Byte 4-5: STLINK_PID
         let mut buf = [0; 6];
-        match self
-            .device
-            .write(vec![commands::GET_VERSION], &[], &mut buf, TIMEOUT)
-        {
+        match self.device.write(
+            vec![commands::GET_VERSION],
+            &[],
+            &mut buf,
+            self.retry_policy.usb_timeout,
+        ) {
             Ok(_) => {
                 let version: u16 = (&buf[0..2]).pread_with(0, BE).unwrap();
                 self.hw_version = (version >> HW_VERSION_SHIFT) as u8 & HW_VERSION_MASK;
                 self.jtag_version = (version >> JTAG_VERSION_SHIFT) as u8 & JTAG_VERSION_MASK;
+                self.swim_version = version as u8 & SWIM_VERSION_MASK;
             }
             Err(e) => return Err(e),
         }
@@ -278,13 +534,16 @@ impl STLink {
             //  8-9: ST_VID
             //  10-11: STLINK_PID
             let mut buf = [0; 12];
-            match self
-                .device
-                .write(vec![commands::GET_VERSION_EXT], &[], &mut buf, TIMEOUT)
-            {
+            match self.device.write(
+                vec![commands::GET_VERSION_EXT],
+                &[],
+                &mut buf,
+                self.retry_policy.usb_timeout,
+            ) {
                 Ok(_) => {
                     let version: u8 = (&buf[2..3]).pread(0).unwrap();
                     self.jtag_version = version;
+                    self.swim_version = (&buf[1..2]).pread(0).unwrap();
                 }
                 Err(e) => return Err(e),
             }
@@ -295,9 +554,11 @@ impl STLink {
             return Err(DebugProbeError::JTAGNotSupportedOnProbe);
         }
         if self.hw_version < 3 && self.jtag_version < Self::MIN_JTAG_VERSION {
-            return Err(DebugProbeError::ProbeFirmwareOutdated);
+            return Err(self.firmware_upgrade_required("debugging", Self::MIN_JTAG_VERSION));
         }
 
+        info!("ST-Link firmware version: {}", self.firmware_version());
+
         Ok((self.hw_version, self.jtag_version))
     }
 
@@ -323,7 +584,7 @@ impl STLink {
             ],
             &[],
             &mut buf,
-            TIMEOUT,
+            self.retry_policy.usb_timeout,
         )?;
         Self::check_status(&buf)
     }
@@ -342,14 +603,17 @@ impl STLink {
             ],
             &[],
             &mut buf,
-            TIMEOUT,
+            self.retry_policy.usb_timeout,
         )?;
         Self::check_status(&buf)
     }
 
     pub fn open_ap(&mut self, apsel: impl AccessPort) -> Result<(), DebugProbeError> {
-        if self.jtag_version < Self::MIN_JTAG_VERSION_MULTI_AP {
-            Err(DebugProbeError::JTagDoesNotSupportMultipleAP)
+        if !self.supports_multiple_aps() {
+            Err(self.firmware_upgrade_required(
+                "multiple access ports",
+                Self::MIN_JTAG_VERSION_MULTI_AP,
+            ))
         } else {
             let mut buf = [0; 2];
             self.device.write(
@@ -361,15 +625,18 @@ impl STLink {
                 ],
                 &[],
                 &mut buf,
-                TIMEOUT,
+                self.retry_policy.usb_timeout,
             )?;
             Self::check_status(&buf)
         }
     }
 
     pub fn close_ap(&mut self, apsel: impl AccessPort) -> Result<(), DebugProbeError> {
-        if self.jtag_version < Self::MIN_JTAG_VERSION_MULTI_AP {
-            Err(DebugProbeError::JTagDoesNotSupportMultipleAP)
+        if !self.supports_multiple_aps() {
+            Err(self.firmware_upgrade_required(
+                "multiple access ports",
+                Self::MIN_JTAG_VERSION_MULTI_AP,
+            ))
         } else {
             let mut buf = [0; 2];
             self.device.write(
@@ -380,7 +647,7 @@ impl STLink {
                 ],
                 &[],
                 &mut buf,
-                TIMEOUT,
+                self.retry_policy.usb_timeout,
             )?;
             Self::check_status(&buf)
         }
@@ -399,11 +666,143 @@ impl STLink {
             vec![commands::JTAG_COMMAND, commands::JTAG_DRIVE_NRST, state],
             &[],
             &mut buf,
-            TIMEOUT,
+            self.retry_policy.usb_timeout,
         )?;
         Self::check_status(&buf)
     }
 
+    /// Enters SWD or JTAG mode, which also powers up the debug domain of the target.
+    fn enter_debug_mode(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        self.enter_idle()?;
+
+        let param = match protocol {
+            WireProtocol::Jtag => commands::JTAG_ENTER_JTAG_NO_CORE_RESET,
+            WireProtocol::Swd => commands::JTAG_ENTER_SWD,
+        };
+
+        let mut buf = [0; 2];
+        self.device.write(
+            vec![commands::JTAG_COMMAND, commands::JTAG_ENTER2, param, 0],
+            &[],
+            &mut buf,
+            self.retry_policy.usb_timeout,
+        )?;
+        Self::check_status(&buf)?;
+
+        // After we checked the status with success,
+        // We store the current protocol.
+        self.protocol = protocol;
+
+        if let Some(speed_khz) = self.speed_khz {
+            match protocol {
+                WireProtocol::Swd => self.set_swd_frequency(swd_frequency(speed_khz).0)?,
+                WireProtocol::Jtag => self.set_jtag_frequency(jtag_frequency(speed_khz).0)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends a command which accesses a DAP register and checks its status.
+    ///
+    /// The command is repeated while the target answers with WAIT, as often as the retry
+    /// policy allows. Other failures of the debug port are recovered from with `recover`.
+    fn dap_transfer(&mut self, cmd: Vec<u8>, buf: &mut [u8]) -> Result<(), DebugProbeError> {
+        let mut retries = 0;
+        loop {
+            self.device
+                .write(cmd.clone(), &[], buf, self.retry_policy.usb_timeout)?;
+
+            if dap_error(buf[0]) != Some(DapError::Wait)
+                || retries == self.retry_policy.wait_retries
+            {
+                break;
+            }
+            retries += 1;
+        }
+
+        match dap_error(buf[0]) {
+            Some(DapError::Wait) => Err(DapError::Wait.into()),
+            Some(error) => Err(self.recover(error).into()),
+            None => Self::check_status(buf),
+        }
+    }
+
+    /// Finds out what went wrong after an access failed.
+    ///
+    /// Depending on the retry policy, the sticky error flags are cleared afterwards, and the
+    /// probe enters debug mode again if the target did not answer.
+    fn recover(&mut self, error: DapError) -> DapError {
+        // Only the access which failed first is recovered from.
+        if self.recovering {
+            return error;
+        }
+        self.recovering = true;
+
+        let error = match error {
+            DapError::Fault => match self.read_register(Port::DebugPort, Ctrl::ADDRESS.into()) {
+                Ok(ctrl) => DapError::from_ctrl_stat(&Ctrl::from(ctrl)),
+                Err(_) => DapError::NoAcknowledge,
+            },
+            error => error,
+        };
+        warn!("Access to the debug port failed: {}", error);
+
+        if error == DapError::NoAcknowledge && self.retry_policy.reinitialize_debug_port {
+            if let Err(e) = self.enter_debug_mode(self.protocol) {
+                warn!("Failed to enter debug mode again: {}", e);
+            }
+        } else if self.retry_policy.clear_sticky_errors {
+            let mut abort = Abort::from(0);
+            abort.set_orunerrclr(true);
+            abort.set_wderrclr(true);
+            abort.set_stkerrclr(true);
+            abort.set_stkcmpclr(true);
+
+            if let Err(e) =
+                self.write_register(Port::DebugPort, Abort::ADDRESS.into(), abort.into())
+            {
+                warn!("Failed to clear the sticky errors: {}", e);
+            }
+        }
+
+        self.recovering = false;
+        error
+    }
+
+    /// Builds a memory command accessing `length` bytes at `address` through the MEM-AP 0.
+    /// Internal helper.
+    fn memory_command(command: u8, address: u32, length: usize) -> Vec<u8> {
+        let mut cmd = vec![commands::JTAG_COMMAND, command];
+        cmd.extend_from_slice(&address.to_le_bytes());
+        cmd.extend_from_slice(&(length as u16).to_le_bytes());
+        cmd
+    }
+
+    /// Checks whether the last memory command succeeded, as they do not return a status.
+    ///
+    /// Returns `DebugProbeError::TransferFault` with the address the transfer faulted at.
+    fn check_rw_status(&mut self, length: usize) -> Result<(), DebugProbeError> {
+        // JTAG_GETLASTRWSTATUS2 response structure (byte offsets):
+        //  0: Status
+        //  4-7: Fault address
+        let mut buf = [0; 12];
+        self.device.write(
+            vec![commands::JTAG_COMMAND, commands::JTAG_GETLASTRWSTATUS2],
+            &[],
+            &mut buf,
+            self.retry_policy.usb_timeout,
+        )?;
+
+        if buf[0] == Status::JtagOk as u8 {
+            Ok(())
+        } else {
+            // Unwrap is ok!
+            let address = (&buf[4..8]).pread(0).unwrap();
+            Err(DebugProbeError::TransferFault(address, length as u16))
+        }
+    }
+
     /// Validates the status given.
     /// Returns an `Err(DebugProbeError::UnknownError)` if the status is not `Status::JtagOk`.
     /// Returns Ok(()) otherwise.
@@ -416,3 +815,139 @@ impl STLink {
         }
     }
 }
+
+/// Returns the error of the debug port an access failed with, or `None` if it did not fail
+/// because of the debug port.
+fn dap_error(status: u8) -> Option<DapError> {
+    const AP_WAIT: u8 = Status::SwdApWait as u8;
+    const DP_WAIT: u8 = Status::SwdDpWait as u8;
+    const AP_FAULT: u8 = Status::SwdApFault as u8;
+    const DP_FAULT: u8 = Status::SwdDpFault as u8;
+    const AP_ERROR: u8 = Status::SwdApError as u8;
+    const DP_ERROR: u8 = Status::SwdDpError as u8;
+    const AP_PARITY_ERROR: u8 = Status::SwdApParityError as u8;
+    const DP_PARITY_ERROR: u8 = Status::SwdDpParityError as u8;
+    const AP_WDATA_ERROR: u8 = Status::SwdApWdataError as u8;
+    const AP_STICKY_ERROR: u8 = Status::SwdApStickyError as u8;
+    const AP_STICKY_ORUN_ERROR: u8 = Status::SwdApStickyorunError as u8;
+
+    match status {
+        AP_WAIT | DP_WAIT => Some(DapError::Wait),
+        AP_FAULT | DP_FAULT => Some(DapError::Fault),
+        AP_ERROR | DP_ERROR => Some(DapError::NoAcknowledge),
+        AP_PARITY_ERROR | DP_PARITY_ERROR => Some(DapError::Parity),
+        AP_WDATA_ERROR => Some(DapError::WriteData),
+        AP_STICKY_ERROR => Some(DapError::Sticky),
+        AP_STICKY_ORUN_ERROR => Some(DapError::Overrun),
+        _ => None,
+    }
+}
+
+/// Returns the fastest SWD frequency not above `speed_khz`, or the slowest one, with its speed in kHz.
+fn swd_frequency(speed_khz: u32) -> (SwdFrequencyToDelayCount, u32) {
+    use SwdFrequencyToDelayCount::*;
+
+    match speed_khz {
+        4600..=std::u32::MAX => (Hz4600000, 4600),
+        1800..=4599 => (Hz1800000, 1800),
+        1200..=1799 => (Hz1200000, 1200),
+        950..=1199 => (Hz950000, 950),
+        650..=949 => (Hz650000, 650),
+        480..=649 => (Hz480000, 480),
+        400..=479 => (Hz400000, 400),
+        360..=399 => (Hz360000, 360),
+        240..=359 => (Hz240000, 240),
+        150..=239 => (Hz150000, 150),
+        125..=149 => (Hz125000, 125),
+        _ => (Hz100000, 100),
+    }
+}
+
+/// Returns the fastest JTAG frequency not above `speed_khz`, or the slowest one, with its speed in kHz.
+fn jtag_frequency(speed_khz: u32) -> (JTagFrequencyToDivider, u32) {
+    use JTagFrequencyToDivider::*;
+
+    match speed_khz {
+        18000..=std::u32::MAX => (Hz18000000, 18000),
+        9000..=17999 => (Hz9000000, 9000),
+        4500..=8999 => (Hz4500000, 4500),
+        2250..=4499 => (Hz2250000, 2250),
+        1120..=2249 => (Hz1120000, 1120),
+        560..=1119 => (Hz560000, 560),
+        280..=559 => (Hz280000, 280),
+        _ => (Hz140000, 140),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{commands, usb_interface::StLinkUsb, STLink, Status};
+    use crate::probe::debug_probe::{DebugProbe, DebugProbeError};
+    use crate::probe::protocol::{AttachMethod, WireProtocol};
+    use crate::probe::retry::RetryPolicy;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// Answers every command with `JTAG_OK` and records it.
+    struct FakeUsb {
+        commands: Rc<RefCell<Vec<Vec<u8>>>>,
+    }
+
+    impl StLinkUsb for FakeUsb {
+        fn write(
+            &mut self,
+            cmd: Vec<u8>,
+            _write_data: &[u8],
+            read_data: &mut [u8],
+            _timeout: Duration,
+        ) -> Result<(), DebugProbeError> {
+            self.commands.borrow_mut().push(cmd);
+            if let Some(status) = read_data.first_mut() {
+                *status = Status::JtagOk as u8;
+            }
+
+            Ok(())
+        }
+
+        fn read_swv(
+            &mut self,
+            size: usize,
+            _timeout: Duration,
+        ) -> Result<Vec<u8>, DebugProbeError> {
+            Ok(vec![0; size])
+        }
+    }
+
+    fn drives_nreset(cmd: &[u8]) -> bool {
+        cmd.starts_with(&[commands::JTAG_COMMAND, commands::JTAG_DRIVE_NRST])
+    }
+
+    #[test]
+    fn resets_only_when_asked_to() {
+        let commands = Rc::new(RefCell::new(vec![]));
+        let mut stlink = STLink {
+            device: Box::new(FakeUsb {
+                commands: commands.clone(),
+            }),
+            hw_version: 2,
+            jtag_version: STLink::MIN_JTAG_VERSION,
+            swim_version: 0,
+            protocol: WireProtocol::Swd,
+            speed_khz: None,
+            retry_policy: RetryPolicy::default(),
+            recovering: false,
+        };
+
+        stlink
+            .attach(Some(WireProtocol::Swd), AttachMethod::NoReset)
+            .unwrap();
+        assert!(!commands.borrow().iter().any(|cmd| drives_nreset(cmd)));
+
+        stlink
+            .attach(Some(WireProtocol::Swd), AttachMethod::Reset)
+            .unwrap();
+        assert!(commands.borrow().iter().any(|cmd| drives_nreset(cmd)));
+    }
+}
@@ -3,8 +3,8 @@ use rusb::UsbContext;
 
 use crate::probe::debug_probe::{DebugProbeInfo, DebugProbeType};
 
-use super::usb_interface::USB_PID_EP_MAP;
 use super::usb_interface::USB_VID;
+use super::usb_interface::{read_serial_number, USB_PID_EP_MAP};
 
 fn is_stlink_device<T: UsbContext>(device: &Device<T>) -> bool {
     // Check the VID/PID.
@@ -31,8 +31,9 @@ pub fn list_stlink_devices() -> Vec<DebugProbeInfo> {
                             + &USB_PID_EP_MAP[&descriptor.product_id()].version_name,
                         descriptor.vendor_id(),
                         descriptor.product_id(),
-                        None,
+                        read_serial_number(&d),
                         DebugProbeType::STLink,
+                        None,
                     )
                 })
                 .collect::<Vec<_>>()
@@ -13,7 +13,8 @@ const CMD_LEN: usize = 16;
 /// The USB VendorID.
 pub const USB_VID: u16 = 0x0483;
 
-pub const TIMEOUT: Duration = Duration::from_millis(1000);
+/// The timeout for reading string descriptors.
+const DESCRIPTOR_TIMEOUT: Duration = Duration::from_millis(100);
 
 lazy_static! {
     /// Map of USB PID to firmware version name and device endpoints.
@@ -80,6 +81,37 @@ pub struct STLinkUSBDevice {
     info: STLinkInfo,
 }
 
+/// The USB transfers the ST-Link driver is built on, so it can be tested without a probe.
+pub(crate) trait StLinkUsb {
+    /// See `STLinkUSBDevice::write`.
+    fn write(
+        &mut self,
+        cmd: Vec<u8>,
+        write_data: &[u8],
+        read_data: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(), DebugProbeError>;
+
+    /// See `STLinkUSBDevice::read_swv`.
+    fn read_swv(&mut self, size: usize, timeout: Duration) -> Result<Vec<u8>, DebugProbeError>;
+}
+
+impl StLinkUsb for STLinkUSBDevice {
+    fn write(
+        &mut self,
+        cmd: Vec<u8>,
+        write_data: &[u8],
+        read_data: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(), DebugProbeError> {
+        STLinkUSBDevice::write(self, cmd, write_data, read_data, timeout)
+    }
+
+    fn read_swv(&mut self, size: usize, timeout: Duration) -> Result<Vec<u8>, DebugProbeError> {
+        STLinkUSBDevice::read_swv(self, size, timeout)
+    }
+}
+
 impl STLinkUSBDevice {
     /// Creates and initializes a new USB device.
     pub fn new_from_info(probe_info: &DebugProbeInfo) -> Result<Self, DebugProbeError> {
@@ -99,6 +131,8 @@ impl STLinkUSBDevice {
                             if let Ok(descriptor) = device.device_descriptor() {
                                 probe_info.vendor_id == descriptor.vendor_id()
                                     && probe_info.product_id == descriptor.product_id()
+                                    && (probe_info.serial_number.is_none()
+                                        || read_serial_number(device) == probe_info.serial_number)
                             } else {
                                 false
                             }
@@ -222,10 +256,10 @@ impl STLinkUSBDevice {
         Ok(())
     }
 
-    /// Special read, TODO: for later.
+    /// Reads captured SWO data from the trace EP.
     pub fn read_swv(&mut self, size: usize, timeout: Duration) -> Result<Vec<u8>, DebugProbeError> {
         let ep_swv = self.info.ep_swv;
-        let mut buf = Vec::with_capacity(size as usize);
+        let mut buf = vec![0; size];
         let read_bytes = self
             .renter
             .rent(|dh| dh.read_bulk(ep_swv, buf.as_mut_slice(), timeout))
@@ -250,3 +284,39 @@ impl Drop for STLinkUSBDevice {
         let _ = self.close();
     }
 }
+
+/// Reads the serial number of an ST-Link.
+pub(crate) fn read_serial_number<T: UsbContext>(device: &Device<T>) -> Option<String> {
+    let descriptor = device.device_descriptor().ok()?;
+    let handle = device.open().ok()?;
+    let language = *handle.read_languages(DESCRIPTOR_TIMEOUT).ok()?.first()?;
+
+    handle
+        .read_serial_number_string(language, &descriptor, DESCRIPTOR_TIMEOUT)
+        .ok()
+        .map(|serial| normalize_serial(&serial))
+}
+
+/// Older ST-Link/V2 firmwares report the serial number as raw bytes, which are hex encoded the
+/// way newer firmwares report it, so it is printable and the same on all platforms.
+fn normalize_serial(serial: &str) -> String {
+    if serial.chars().all(|c| c.is_ascii_alphanumeric()) {
+        serial.to_owned()
+    } else {
+        serial
+            .chars()
+            .map(|c| format!("{:02X}", c as u32 & 0xFF))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::normalize_serial;
+
+    #[test]
+    fn normalizes_raw_serials() {
+        assert_eq!(normalize_serial("0671FF545"), "0671FF545");
+        assert_eq!(normalize_serial("\u{55}\u{ff}\u{6b}\u{06}"), "55FF6B06");
+    }
+}
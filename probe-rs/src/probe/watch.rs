@@ -0,0 +1,222 @@
+//! Notifications about probes being connected and disconnected.
+//!
+//! The probes are enumerated again whenever libusb reports a USB device arriving or leaving.
+//! Where libusb does not support hotplug events, e.g. on Windows, they are enumerated
+//! periodically instead.
+//!
+//! Watching needs libusb, so `watch_probes` is only built with the `daplink` or the `stlink`
+//! feature. Black Magic Probes are only listed with the `serialport` feature.
+
+// Without libusb, only the enumeration of the probes is built.
+#![cfg_attr(not(feature = "rusb"), allow(dead_code, unused_imports))]
+
+#[cfg(feature = "serialport")]
+use super::blackmagic;
+#[cfg(feature = "daplink")]
+use super::daplink;
+use super::debug_probe::{DebugProbeError, DebugProbeInfo};
+use super::gpio;
+use super::registry;
+#[cfg(feature = "stlink")]
+use super::stlink;
+
+#[cfg(feature = "rusb")]
+use rusb::UsbContext;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How often the probes are enumerated without hotplug events, and how long the events are
+/// waited for at once.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The time the drivers of a new device get to set up its interfaces before enumerating.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone)]
+pub enum ProbeEvent {
+    Attached(DebugProbeInfo),
+    Removed(DebugProbeInfo),
+}
+
+/// Lists all connected probes.
+pub fn list_probes() -> Vec<DebugProbeInfo> {
+    let mut probes = vec![];
+    #[cfg(feature = "daplink")]
+    probes.extend(daplink::tools::list_daplink_devices());
+    #[cfg(feature = "stlink")]
+    probes.extend(stlink::tools::list_stlink_devices());
+    #[cfg(feature = "serialport")]
+    probes.extend(blackmagic::tools::list_blackmagic_devices());
+    probes.extend(gpio::tools::list_gpio_devices());
+    probes.extend(registry::list_probes());
+    probes
+}
+
+/// Calls `callback` for each probe which is connected or disconnected, and with `None` each
+/// time the probes were polled, until it returns `false`. The probes which are connected
+/// already are reported as attached first.
+#[cfg(feature = "rusb")]
+pub fn watch_probes<F>(callback: F) -> Result<(), DebugProbeError>
+where
+    F: FnMut(Option<ProbeEvent>) -> bool,
+{
+    let mut context = rusb::Context::new().map_err(|_| DebugProbeError::USBError)?;
+    let changed = Arc::new(AtomicBool::new(false));
+
+    let registration = if rusb::has_hotplug() {
+        let notifier = Box::new(Notifier(changed.clone()));
+        Some(
+            context
+                .register_callback(None, None, None, notifier)
+                .map_err(|_| DebugProbeError::USBError)?,
+        )
+    } else {
+        log::debug!("libusb has no hotplug support, polling for probes");
+        None
+    };
+
+    let wait = || {
+        if registration.is_some() {
+            context
+                .handle_events(Some(POLL_INTERVAL))
+                .map_err(|_| DebugProbeError::USBError)?;
+            if changed.load(Ordering::SeqCst) {
+                thread::sleep(SETTLE_TIME);
+            }
+        } else {
+            thread::sleep(POLL_INTERVAL);
+            changed.store(true, Ordering::SeqCst);
+        }
+
+        Ok(changed.swap(false, Ordering::SeqCst))
+    };
+
+    watch(list_probes, wait, callback)
+}
+
+/// Reports the changes of the probes listed by `list`, first and whenever `wait` returns that
+/// they changed. `callback` is asked after each poll whether to go on.
+fn watch<L, W, F>(mut list: L, mut wait: W, mut callback: F) -> Result<(), DebugProbeError>
+where
+    L: FnMut() -> Vec<DebugProbeInfo>,
+    W: FnMut() -> Result<bool, DebugProbeError>,
+    F: FnMut(Option<ProbeEvent>) -> bool,
+{
+    let mut known = vec![];
+    let mut changed = true;
+    loop {
+        if changed {
+            let probes = list();
+            for event in changes(&known, &probes) {
+                if !callback(Some(event)) {
+                    return Ok(());
+                }
+            }
+            known = probes;
+        }
+
+        if !callback(None) {
+            return Ok(());
+        }
+        changed = wait()?;
+    }
+}
+
+/// Returns the probes of `current` which are not `known` as attached, and the probes which
+/// are only `known` as removed.
+fn changes(known: &[DebugProbeInfo], current: &[DebugProbeInfo]) -> Vec<ProbeEvent> {
+    let removed = known
+        .iter()
+        .filter(|probe| !current.iter().any(|other| same_probe(probe, other)))
+        .map(|probe| ProbeEvent::Removed(probe.clone()));
+    let attached = current
+        .iter()
+        .filter(|probe| !known.iter().any(|other| same_probe(probe, other)))
+        .map(|probe| ProbeEvent::Attached(probe.clone()));
+
+    removed.chain(attached).collect()
+}
+
+fn same_probe(a: &DebugProbeInfo, b: &DebugProbeInfo) -> bool {
+    a.identifier == b.identifier
+        && a.vendor_id == b.vendor_id
+        && a.product_id == b.product_id
+        && a.serial_number == b.serial_number
+}
+
+/// Flags that the connected USB devices changed.
+#[cfg(feature = "rusb")]
+struct Notifier(Arc<AtomicBool>);
+
+#[cfg(feature = "rusb")]
+impl<T: UsbContext> rusb::Hotplug<T> for Notifier {
+    fn device_arrived(&mut self, _device: rusb::Device<T>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn device_left(&mut self, _device: rusb::Device<T>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{changes, watch, ProbeEvent};
+    use crate::probe::debug_probe::{DebugProbeInfo, DebugProbeType};
+
+    fn probe(serial: &str) -> DebugProbeInfo {
+        DebugProbeInfo::new(
+            "DAPLink",
+            0x0d28,
+            0x0204,
+            Some(serial.to_owned()),
+            DebugProbeType::DAPLink,
+            None,
+        )
+    }
+
+    #[test]
+    fn reports_changed_probes() {
+        let known = vec![probe("1"), probe("2")];
+        let current = vec![probe("2"), probe("3")];
+
+        let events = changes(&known, &current);
+
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            ProbeEvent::Removed(info) => assert_eq!(info.serial_number.as_deref(), Some("1")),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        match &events[1] {
+            ProbeEvent::Attached(info) => assert_eq!(info.serial_number.as_deref(), Some("3")),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        assert!(changes(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn stops_watching_while_nothing_changes() {
+        let mut attached = 0;
+        let mut polls = 0;
+
+        watch(
+            || vec![probe("1")],
+            || Ok(false),
+            |event| {
+                match event {
+                    Some(ProbeEvent::Attached(_)) => attached += 1,
+                    Some(event) => panic!("Unexpected event {:?}", event),
+                    None => polls += 1,
+                }
+                polls < 3
+            },
+        )
+        .unwrap();
+
+        assert_eq!(attached, 1);
+        assert_eq!(polls, 3);
+    }
+}
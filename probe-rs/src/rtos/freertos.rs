@@ -0,0 +1,162 @@
+//! Threads of FreeRTOS, which it calls tasks.
+//!
+//! Each task is in one of the state lists of the kernel, and the owner of its list item is its
+//! task control block. The first member of the control block is the top of the stack of the
+//! task, where the port saved R4 to R11 when it switched the task out. The ports for the cores
+//! with a floating point unit save the EXC_RETURN value of the task after them, and S16 to S31
+//! if it used the unit.
+
+use super::{
+    find_type, offset, read_name, symbol, unstack, RtosError, RtosProvider, Thread, ThreadState,
+    MAX_THREADS,
+};
+use crate::debug::{DebugInfo, Layout};
+use crate::memory::MI;
+use crate::session::Session;
+
+/// Clear in EXC_RETURN if the frame of the exception is extended by the FPU registers.
+const EXC_RETURN_STANDARD_FRAME: u32 = 1 << 4;
+
+/// The size of S16 to S31, which the port saves below the exception frame.
+const HIGH_FP_REGISTERS_SIZE: u32 = 16 * 4;
+
+/// The state lists besides the ready lists, which are one per priority.
+const STATE_LISTS: [(&str, ThreadState); 5] = [
+    ("xPendingReadyList", ThreadState::Ready),
+    ("xDelayedTaskList1", ThreadState::Blocked),
+    ("xDelayedTaskList2", ThreadState::Blocked),
+    ("xSuspendedTaskList", ThreadState::Suspended),
+    ("xTasksWaitingTermination", ThreadState::Inactive),
+];
+
+pub struct FreeRtos;
+
+/// The offsets of the members of the lists which link the tasks.
+struct ListOffsets {
+    list_end: u32,
+    next: u32,
+    owner: u32,
+}
+
+impl ListOffsets {
+    fn find(debug_info: &DebugInfo) -> Result<Self, RtosError> {
+        let list = find_type(debug_info, &["List_t", "xLIST"])?;
+        let item = find_type(debug_info, &["ListItem_t", "xLIST_ITEM"])?;
+
+        Ok(ListOffsets {
+            list_end: offset(&list, "xListEnd")?,
+            next: offset(&item, "pxNext")?,
+            owner: offset(&item, "pvOwner")?,
+        })
+    }
+
+    /// Returns the owners of the items of the list at `list`.
+    fn owners(&self, mi: &mut impl MI, list: u32) -> Result<Vec<u32>, RtosError> {
+        let end = list + self.list_end;
+        let mut owners = vec![];
+
+        let mut item = mi.read32(end + self.next)?;
+        while item != end && item != 0 && owners.len() < MAX_THREADS {
+            owners.push(mi.read32(item + self.owner)?);
+            item = mi.read32(item + self.next)?;
+        }
+
+        Ok(owners)
+    }
+}
+
+impl RtosProvider for FreeRtos {
+    fn name(&self) -> &'static str {
+        "FreeRTOS"
+    }
+
+    fn detect(&self, debug_info: &DebugInfo) -> bool {
+        debug_info.find_static("pxCurrentTCB").is_some()
+    }
+
+    fn threads(
+        &self,
+        debug_info: &DebugInfo,
+        session: &mut Session,
+    ) -> Result<Vec<Thread>, RtosError> {
+        let lists = ListOffsets::find(debug_info)?;
+        let tcb = find_type(debug_info, &["TCB_t", "tskTCB", "tskTaskControlBlock"])?;
+        let priority = offset(&tcb, "uxPriority")?;
+        let name = tcb
+            .member("pcTaskName")
+            .ok_or_else(|| RtosError::MissingSymbol("pcTaskName".to_string()))?;
+
+        let ready_lists = debug_info
+            .find_static("pxReadyTasksLists")
+            .ok_or_else(|| RtosError::MissingSymbol("pxReadyTasksLists".to_string()))?;
+        let (list_size, priorities) = match &ready_lists.layout {
+            Layout::Array { element, count } => (element.size(), *count),
+            _ => return Err(RtosError::MissingSymbol("pxReadyTasksLists".to_string())),
+        };
+
+        let mi = &mut session.probe;
+        let current = mi.read32(symbol(debug_info, "pxCurrentTCB")?)?;
+
+        let mut tasks = vec![];
+        for i in 0..priorities {
+            for task in lists.owners(mi, ready_lists.address + i * list_size)? {
+                tasks.push((task, ThreadState::Ready));
+            }
+        }
+        for (list, state) in STATE_LISTS.iter() {
+            // Some lists only exist if the kernel was configured with the API which needs them.
+            if let Some(list) = debug_info.find_static(list) {
+                for task in lists.owners(mi, list.address)? {
+                    tasks.push((task, *state));
+                }
+            }
+        }
+
+        let fpu = session
+            .core_info
+            .map_or(false, |info| info.capabilities.fpu);
+
+        let mut threads = vec![];
+        for (task, state) in tasks {
+            let (state, registers) = if task == current {
+                (ThreadState::Running, None)
+            } else {
+                let top_of_stack = mi.read32(task)?;
+                (state, Some(saved_registers(mi, top_of_stack, fpu)?))
+            };
+
+            threads.push(Thread {
+                id: task,
+                name: read_name(mi, task + name.offset, name.layout.size())?,
+                state,
+                priority: Some(mi.read32(task + priority)? as i32),
+                registers,
+            });
+        }
+
+        Ok(threads)
+    }
+}
+
+/// Restores the registers of a task from the top of its stack.
+fn saved_registers(mi: &mut impl MI, top_of_stack: u32, fpu: bool) -> Result<[u32; 16], RtosError> {
+    let mut saved = [0; 9];
+    mi.read_block32(top_of_stack, &mut saved)?;
+
+    let mut callee_saved = [0; 8];
+    callee_saved.copy_from_slice(&saved[..8]);
+
+    // The ports for cores without an FPU can be used on cores with one, so the EXC_RETURN
+    // value is only assumed to be saved if it looks like one.
+    let exc_return = saved[8];
+    let (frame, extended) = if fpu && exc_return >> 24 == 0xFF {
+        let extended = exc_return & EXC_RETURN_STANDARD_FRAME == 0;
+        let fp_size = if extended { HIGH_FP_REGISTERS_SIZE } else { 0 };
+
+        (top_of_stack + 9 * 4 + fp_size, extended)
+    } else {
+        (top_of_stack + 8 * 4, false)
+    };
+
+    Ok(unstack(mi, callee_saved, frame, extended)?)
+}
@@ -0,0 +1,301 @@
+//! Awareness of the threads of a real-time operating system.
+//!
+//! The debugger only sees the registers of the core, which belong to the thread that is
+//! running. The other threads saved their registers on their stacks when they were switched
+//! out, and the kernel keeps track of them in its control blocks. A provider knows where a
+//! kernel keeps these, finds them with the debug info of the program, and restores the
+//! registers of each thread so its stack can be unwound like that of the core.
+
+pub mod freertos;
+pub mod rtic;
+pub mod zephyr;
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::debug::{DebugInfo, Layout};
+use crate::memory::MI;
+use crate::probe::debug_probe::DebugProbeError;
+use crate::session::Session;
+
+use std::error::Error;
+use std::fmt;
+
+/// The number of bytes the exception entry pushes, without and with the registers of the
+/// floating point unit.
+const BASIC_FRAME_SIZE: u32 = 0x20;
+const EXTENDED_FRAME_SIZE: u32 = 0x68;
+
+/// Set in the stacked xPSR if the stack was realigned to 8 bytes by a padding word.
+const XPSR_STACK_ALIGN: u32 = 1 << 9;
+
+/// Control blocks and lists are walked at most this far, in case they are corrupted.
+const MAX_THREADS: usize = 256;
+
+#[derive(Debug)]
+pub enum RtosError {
+    Access(AccessPortError),
+    DebugProbe(DebugProbeError),
+    /// The debug info lacks a symbol or a type the kernel is expected to have, or a member of it.
+    MissingSymbol(String),
+}
+
+impl Error for RtosError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RtosError::Access(e) => Some(e),
+            RtosError::DebugProbe(e) => Some(e),
+            RtosError::MissingSymbol(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for RtosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RtosError::Access(e) => e.fmt(f),
+            RtosError::DebugProbe(e) => e.fmt(f),
+            RtosError::MissingSymbol(name) => {
+                write!(f, "The debug info does not describe '{}'.", name)
+            }
+        }
+    }
+}
+
+impl From<AccessPortError> for RtosError {
+    fn from(error: AccessPortError) -> Self {
+        RtosError::Access(error)
+    }
+}
+
+impl From<DebugProbeError> for RtosError {
+    fn from(error: DebugProbeError) -> Self {
+        RtosError::DebugProbe(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ThreadState {
+    /// The thread runs on the core.
+    Running,
+    /// The thread can run once the scheduler picks it.
+    Ready,
+    /// The thread waits for an event or a timeout.
+    Blocked,
+    Suspended,
+    /// The thread was not started yet or has terminated.
+    Inactive,
+}
+
+impl fmt::Display for ThreadState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ThreadState::Running => "running",
+            ThreadState::Ready => "ready",
+            ThreadState::Blocked => "blocked",
+            ThreadState::Suspended => "suspended",
+            ThreadState::Inactive => "inactive",
+        };
+
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thread {
+    /// Identifies the thread, usually the address of its control block.
+    pub id: u32,
+    pub name: String,
+    pub state: ThreadState,
+    pub priority: Option<i32>,
+    /// The registers R0 to R15 the thread saved, or `None` if it is running and they are those
+    /// of the core, or if they are not known.
+    pub registers: Option<[u32; 16]>,
+}
+
+/// Finds the threads of a kernel.
+pub trait RtosProvider {
+    fn name(&self) -> &'static str;
+
+    /// Whether the program uses the kernel.
+    fn detect(&self, debug_info: &DebugInfo) -> bool;
+
+    /// Lists the threads of the halted core.
+    fn threads(
+        &self,
+        debug_info: &DebugInfo,
+        session: &mut Session,
+    ) -> Result<Vec<Thread>, RtosError>;
+}
+
+/// Returns the providers of all supported kernels.
+pub fn providers() -> Vec<Box<dyn RtosProvider>> {
+    vec![
+        Box::new(freertos::FreeRtos),
+        Box::new(zephyr::Zephyr),
+        Box::new(rtic::Rtic),
+    ]
+}
+
+/// Returns the provider of the kernel the program uses, if it is a supported one.
+pub fn detect(debug_info: &DebugInfo) -> Option<Box<dyn RtosProvider>> {
+    providers()
+        .into_iter()
+        .find(|provider| provider.detect(debug_info))
+}
+
+/// Restores the registers of a thread which was switched out by an exception.
+///
+/// The exception entry pushed R0 to R3, R12, LR, the return address and xPSR to the stack at
+/// `sp`, and the kernel saved R4 to R11 as `callee_saved`. If the thread used the floating
+/// point unit, the frame is `extended` by its registers.
+pub fn unstack(
+    mi: &mut impl MI,
+    callee_saved: [u32; 8],
+    sp: u32,
+    extended: bool,
+) -> Result<[u32; 16], AccessPortError> {
+    let mut frame = [0; 8];
+    mi.read_block32(sp, &mut frame)?;
+
+    let mut registers = [0; 16];
+    registers[0..4].copy_from_slice(&frame[0..4]);
+    registers[4..12].copy_from_slice(&callee_saved);
+    registers[12] = frame[4];
+    registers[14] = frame[5];
+    registers[15] = frame[6];
+
+    let size = if extended {
+        EXTENDED_FRAME_SIZE
+    } else {
+        BASIC_FRAME_SIZE
+    };
+    let padding = if frame[7] & XPSR_STACK_ALIGN != 0 {
+        4
+    } else {
+        0
+    };
+    registers[13] = sp + size + padding;
+
+    Ok(registers)
+}
+
+/// Returns the address of a static variable of the kernel.
+fn symbol(debug_info: &DebugInfo, name: &str) -> Result<u32, RtosError> {
+    debug_info
+        .find_static(name)
+        .map(|variable| variable.address)
+        .ok_or_else(|| RtosError::MissingSymbol(name.to_string()))
+}
+
+/// Returns the layout of the first type found under one of `names`, which differ between
+/// versions of a kernel.
+fn find_type(debug_info: &DebugInfo, names: &[&str]) -> Result<Layout, RtosError> {
+    names
+        .iter()
+        .find_map(|name| debug_info.find_type(name))
+        .ok_or_else(|| RtosError::MissingSymbol(names[0].to_string()))
+}
+
+/// Returns the offset of a member of a struct.
+fn offset(layout: &Layout, member: &str) -> Result<u32, RtosError> {
+    layout
+        .member(member)
+        .map(|member| member.offset)
+        .ok_or_else(|| RtosError::MissingSymbol(member.to_string()))
+}
+
+/// Reads a string of at most `length` bytes, which ends at the first zero byte.
+fn read_name(mi: &mut impl MI, address: u32, length: u32) -> Result<String, AccessPortError> {
+    let mut data = vec![0; length as usize];
+    mi.read_block8(address, &mut data)?;
+
+    let end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+
+    Ok(String::from_utf8_lossy(&data[..end]).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::unstack;
+    use crate::coresight::access_ports::AccessPortError;
+    use crate::memory::MI;
+
+    /// Memory holding a single stack, starting at 0x2000_0000.
+    struct Stack(Vec<u32>);
+
+    impl MI for Stack {
+        fn read32(&mut self, address: u32) -> Result<u32, AccessPortError> {
+            Ok(self.0[((address - 0x2000_0000) / 4) as usize])
+        }
+
+        fn read8(&mut self, address: u32) -> Result<u8, AccessPortError> {
+            Ok(self.read32(address & !3)?.to_le_bytes()[(address & 3) as usize])
+        }
+
+        fn read_block32(&mut self, address: u32, data: &mut [u32]) -> Result<(), AccessPortError> {
+            for (i, value) in data.iter_mut().enumerate() {
+                *value = self.read32(address + 4 * i as u32)?;
+            }
+            Ok(())
+        }
+
+        fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), AccessPortError> {
+            for (i, value) in data.iter_mut().enumerate() {
+                *value = self.read8(address + i as u32)?;
+            }
+            Ok(())
+        }
+
+        fn write32(&mut self, _addr: u32, _data: u32) -> Result<(), AccessPortError> {
+            unimplemented!()
+        }
+
+        fn write8(&mut self, _addr: u32, _data: u8) -> Result<(), AccessPortError> {
+            unimplemented!()
+        }
+
+        fn write_block32(&mut self, _addr: u32, _data: &[u32]) -> Result<(), AccessPortError> {
+            unimplemented!()
+        }
+
+        fn write_block8(&mut self, _addr: u32, _data: &[u8]) -> Result<(), AccessPortError> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn unstacks_exception_frames() {
+        let mut stack = Stack(vec![0, 1, 2, 3, 12, 0x0800_0101, 0x0800_0200, 0x0100_0200]);
+        let callee_saved = [4, 5, 6, 7, 8, 9, 10, 11];
+
+        let registers = unstack(&mut stack, callee_saved, 0x2000_0000, false).unwrap();
+
+        assert_eq!(
+            registers,
+            [
+                0,
+                1,
+                2,
+                3,
+                4,
+                5,
+                6,
+                7,
+                8,
+                9,
+                10,
+                11,
+                12,
+                0x2000_0024,
+                0x0800_0101,
+                0x0800_0200
+            ]
+        );
+
+        stack.0[7] = 0x0100_0000;
+        let registers = unstack(&mut stack, callee_saved, 0x2000_0000, true).unwrap();
+        assert_eq!(registers[13], 0x2000_0068);
+    }
+}
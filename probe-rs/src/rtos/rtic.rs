@@ -0,0 +1,121 @@
+//! Tasks of RTIC, the Real-Time Interrupt-driven Concurrency framework.
+//!
+//! RTIC has no threads of its own: its hardware tasks are interrupt handlers, its software
+//! tasks are run by dispatcher interrupts, and everything else runs in `idle`. The tasks are
+//! therefore the enabled interrupts, whose state is read from the NVIC. A task which is
+//! preempted has its registers pushed onto the shared main stack, where they are not told
+//! apart, so they are not restored.
+
+use super::{RtosError, RtosProvider, Thread, ThreadState};
+use crate::debug::DebugInfo;
+use crate::memory::MI;
+use crate::session::Session;
+
+/// The Interrupt Controller Type Register, which tells the number of interrupt lines.
+const ICTR: u32 = 0xE000_E004;
+/// The Interrupt Set-Enable, Set-Pending, Active Bit and Priority Registers of the NVIC.
+const NVIC_ISER: u32 = 0xE000_E100;
+const NVIC_ISPR: u32 = 0xE000_E200;
+const NVIC_IABR: u32 = 0xE000_E300;
+const NVIC_IPR: u32 = 0xE000_E400;
+/// The Vector Table Offset Register.
+const VTOR: u32 = 0xE000_ED08;
+
+/// The exception number of the first interrupt, which follows the system exceptions.
+const FIRST_INTERRUPT: u32 = 16;
+
+/// The id of `idle`, which is not an interrupt.
+const IDLE: u32 = 0xFFFF_FFFF;
+
+pub struct Rtic;
+
+impl RtosProvider for Rtic {
+    fn name(&self) -> &'static str {
+        "RTIC"
+    }
+
+    fn detect(&self, debug_info: &DebugInfo) -> bool {
+        // RTIC was called RTFM before version 0.5.
+        debug_info.has_namespace("rtic") || debug_info.has_namespace("rtfm")
+    }
+
+    fn threads(
+        &self,
+        debug_info: &DebugInfo,
+        session: &mut Session,
+    ) -> Result<Vec<Thread>, RtosError> {
+        let core = session.target.core.as_ref();
+        let mi = &mut session.probe;
+
+        let xpsr = core.read_core_reg(mi, core.registers().XPSR)?;
+        let exception = xpsr & 0x1FF;
+
+        // The baseline cores have at most 32 interrupts, and neither ICTR nor the active bits.
+        let baseline = session
+            .core_info
+            .map_or(false, |info| info.core_type.is_baseline());
+        let words = if baseline {
+            1
+        } else {
+            (mi.read32(ICTR)? & 0xF) + 1
+        };
+        let vector_table = if baseline { 0 } else { mi.read32(VTOR)? };
+
+        let mut threads = vec![Thread {
+            id: IDLE,
+            name: "idle".to_string(),
+            state: if exception == 0 {
+                ThreadState::Running
+            } else {
+                ThreadState::Ready
+            },
+            priority: None,
+            registers: None,
+        }];
+
+        for word in 0..words {
+            let enabled = mi.read32(NVIC_ISER + 4 * word)?;
+            if enabled == 0 {
+                continue;
+            }
+            let pending = mi.read32(NVIC_ISPR + 4 * word)?;
+            let active = if baseline {
+                0
+            } else {
+                mi.read32(NVIC_IABR + 4 * word)?
+            };
+
+            for bit in (0..32).filter(|bit| enabled & (1 << bit) != 0) {
+                let interrupt = 32 * word + bit;
+
+                let state = if interrupt + FIRST_INTERRUPT == exception {
+                    ThreadState::Running
+                } else if (active | pending) & (1 << bit) != 0 {
+                    ThreadState::Ready
+                } else {
+                    ThreadState::Inactive
+                };
+
+                // The priority registers only allow word accesses on the baseline cores.
+                let priorities = mi.read32(NVIC_IPR + (interrupt & !3))?;
+                let priority = priorities.to_le_bytes()[(interrupt & 3) as usize];
+
+                let handler = mi.read32(vector_table + 4 * (interrupt + FIRST_INTERRUPT))?;
+                let name = debug_info
+                    .function_name(u64::from(handler & !1))
+                    .unwrap_or_else(|| format!("IRQ{}", interrupt));
+
+                threads.push(Thread {
+                    id: interrupt,
+                    name,
+                    state,
+                    // As in the NVIC, where lower values are higher priorities.
+                    priority: Some(i32::from(priority)),
+                    registers: None,
+                });
+            }
+        }
+
+        Ok(threads)
+    }
+}
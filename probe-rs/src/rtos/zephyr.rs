@@ -0,0 +1,141 @@
+//! Threads of the Zephyr kernel.
+//!
+//! The kernel links all threads through their `next_thread` members if it was built with
+//! `CONFIG_THREAD_MONITOR`, and notes the thread running on each CPU. A thread which is switched
+//! out keeps R4 to R11 and its stack pointer in its `callee_saved` member, the other registers
+//! are in the exception frame on its stack.
+
+use super::{
+    find_type, offset, read_name, unstack, RtosError, RtosProvider, Thread, ThreadState,
+    MAX_THREADS,
+};
+use crate::debug::{DebugInfo, Layout, Member};
+use crate::memory::MI;
+use crate::session::Session;
+
+/// The bits of the `thread_state` of a thread.
+const THREAD_PENDING: u8 = 1 << 1;
+const THREAD_PRESTART: u8 = 1 << 2;
+const THREAD_DEAD: u8 = 1 << 3;
+const THREAD_SUSPENDED: u8 = 1 << 4;
+
+/// Clear in EXC_RETURN if the frame of the exception is extended by the FPU registers.
+const EXC_RETURN_STANDARD_FRAME: u8 = 1 << 4;
+
+pub struct Zephyr;
+
+impl RtosProvider for Zephyr {
+    fn name(&self) -> &'static str {
+        "Zephyr"
+    }
+
+    fn detect(&self, debug_info: &DebugInfo) -> bool {
+        debug_info.find_static("_kernel").is_some() && debug_info.find_type("k_thread").is_some()
+    }
+
+    fn threads(
+        &self,
+        debug_info: &DebugInfo,
+        session: &mut Session,
+    ) -> Result<Vec<Thread>, RtosError> {
+        let kernel = debug_info
+            .find_static("_kernel")
+            .ok_or_else(|| RtosError::MissingSymbol("_kernel".to_string()))?;
+        let thread = find_type(debug_info, &["k_thread"])?;
+
+        let current = current_offset(&kernel.layout)?;
+        let first = offset(&kernel.layout, "threads")?;
+        let next = offset(&thread, "next_thread")?;
+
+        let base = member(&thread, "base")?;
+        let state = base.offset + offset(&base.layout, "thread_state")?;
+        let priority = base.offset + offset(&base.layout, "prio")?;
+
+        let callee_saved = member(&thread, "callee_saved")?;
+        let v1 = callee_saved.offset + offset(&callee_saved.layout, "v1")?;
+        let psp = callee_saved.offset + offset(&callee_saved.layout, "psp")?;
+
+        // Only kernels built with CONFIG_THREAD_NAME and CONFIG_ARM_STORE_EXC_RETURN have these.
+        let name = thread.member("name");
+        let exc_return = thread.member("arch").and_then(|arch| {
+            arch.layout
+                .member("mode_exc_return")
+                .map(|member| arch.offset + member.offset)
+        });
+
+        let mi = &mut session.probe;
+        let current = mi.read32(kernel.address + current)?;
+
+        let mut threads = vec![];
+        let mut address = mi.read32(kernel.address + first)?;
+        while address != 0 && threads.len() < MAX_THREADS {
+            let (state, registers) = if address == current {
+                (ThreadState::Running, None)
+            } else {
+                let mut callee_saved = [0; 8];
+                mi.read_block32(address + v1, &mut callee_saved)?;
+                let sp = mi.read32(address + psp)?;
+                let extended = match exc_return {
+                    Some(exc_return) => {
+                        mi.read8(address + exc_return)? & EXC_RETURN_STANDARD_FRAME == 0
+                    }
+                    None => false,
+                };
+
+                (
+                    thread_state(mi.read8(address + state)?),
+                    Some(unstack(mi, callee_saved, sp, extended)?),
+                )
+            };
+
+            let name = match name {
+                Some(name) => read_name(mi, address + name.offset, name.layout.size())?,
+                None => format!("0x{:08x}", address),
+            };
+
+            threads.push(Thread {
+                id: address,
+                name,
+                state,
+                priority: Some(i32::from(mi.read8(address + priority)? as i8)),
+                registers,
+            });
+
+            address = mi.read32(address + next)?;
+        }
+
+        Ok(threads)
+    }
+}
+
+fn member<'a>(layout: &'a Layout, name: &str) -> Result<&'a Member, RtosError> {
+    layout
+        .member(name)
+        .ok_or_else(|| RtosError::MissingSymbol(name.to_string()))
+}
+
+/// Returns the offset of the running thread in the kernel, which moved into the data of the
+/// first CPU with the support for multiple CPUs.
+fn current_offset(kernel: &Layout) -> Result<u32, RtosError> {
+    if let Ok(current) = offset(kernel, "current") {
+        return Ok(current);
+    }
+
+    let cpus = member(kernel, "cpus")?;
+    match &cpus.layout {
+        Layout::Array { element, .. } => Ok(cpus.offset + offset(element, "current")?),
+        _ => Err(RtosError::MissingSymbol("cpus".to_string())),
+    }
+}
+
+fn thread_state(state: u8) -> ThreadState {
+    if state & (THREAD_DEAD | THREAD_PRESTART) != 0 {
+        ThreadState::Inactive
+    } else if state & THREAD_SUSPENDED != 0 {
+        ThreadState::Suspended
+    } else if state & THREAD_PENDING != 0 {
+        ThreadState::Blocked
+    } else {
+        ThreadState::Ready
+    }
+}
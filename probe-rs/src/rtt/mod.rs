@@ -0,0 +1,407 @@
+//! Real-Time Transfer (RTT), SEGGER's protocol to exchange data with the target
+//! through ring buffers in its RAM.
+//!
+//! The target firmware places a control block in RAM, which starts with the ID `SEGGER RTT`
+//! and describes a number of up channels (target to host) and down channels (host to target).
+//! Each channel is a ring buffer with a write offset, which is advanced by the writing side,
+//! and a read offset, which is advanced by the reading side.
+
+pub mod virtual_terminal;
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+
+#[cfg(feature = "debug")]
+use object::read::Object;
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// The ID at the start of the control block.
+const CONTROL_BLOCK_ID: &[u8] = b"SEGGER RTT\0";
+
+/// Name of the symbol of the control block in the SEGGER RTT implementation.
+const CONTROL_BLOCK_SYMBOL: &str = "_SEGGER_RTT";
+
+/// Size of the control block header: the 16 byte ID followed by the number of up and down channels.
+const HEADER_SIZE: u32 = 24;
+
+/// Size of a channel descriptor.
+const CHANNEL_SIZE: u32 = 24;
+
+/// Offset of the write offset within a channel descriptor.
+const WRITE_OFFSET: u32 = 12;
+
+/// Offset of the read offset within a channel descriptor.
+const READ_OFFSET: u32 = 16;
+
+/// Offset of the flags within a channel descriptor.
+const FLAGS_OFFSET: u32 = 20;
+
+/// The bits of the flags which select the mode of a channel.
+const MODE_MASK: u32 = 0b11;
+
+/// Control blocks declaring more channels than this are considered corrupt.
+const MAX_CHANNELS: u32 = 32;
+
+/// Channel names are read up to this length.
+const MAX_NAME_LENGTH: usize = 32;
+
+/// RAM is searched for the control block in chunks of this size.
+const SCAN_CHUNK_SIZE: u32 = 1024;
+
+#[derive(Debug)]
+pub enum RttError {
+    /// The control block was not found in the searched memory.
+    ControlBlockNotFound,
+    /// The memory at the given address does not contain a valid control block.
+    InvalidControlBlock(u32),
+    /// The control block does not describe a channel with this number.
+    NoSuchChannel(usize),
+    AccessPort(AccessPortError),
+}
+
+impl Error for RttError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RttError::AccessPort(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RttError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RttError::ControlBlockNotFound => write!(f, "The RTT control block was not found."),
+            RttError::InvalidControlBlock(address) => write!(
+                f,
+                "There is no valid RTT control block at 0x{:08x}.",
+                address
+            ),
+            RttError::NoSuchChannel(number) => write!(f, "RTT channel {} does not exist.", number),
+            RttError::AccessPort(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<AccessPortError> for RttError {
+    fn from(e: AccessPortError) -> Self {
+        RttError::AccessPort(e)
+    }
+}
+
+/// What the target does when it writes to an up channel that has not enough free space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelMode {
+    /// Drop the data of the write.
+    NoBlockSkip,
+    /// Write as much data as fits and drop the rest.
+    NoBlockTrim,
+    /// Wait until the host has read enough data.
+    BlockIfFull,
+}
+
+impl FromStr for ChannelMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ChannelMode::NoBlockSkip),
+            "trim" => Ok(ChannelMode::NoBlockTrim),
+            "block" => Ok(ChannelMode::BlockIfFull),
+            _ => Err(format!(
+                "Unknown channel mode '{}', use 'skip', 'trim' or 'block'.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ChannelMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelMode::NoBlockSkip => write!(f, "skip"),
+            ChannelMode::NoBlockTrim => write!(f, "trim"),
+            ChannelMode::BlockIfFull => write!(f, "block"),
+        }
+    }
+}
+
+/// A ring buffer described by the control block.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    number: usize,
+    name: Option<String>,
+    /// Address of the channel descriptor in the control block.
+    descriptor_address: u32,
+    buffer_address: u32,
+    size: u32,
+}
+
+impl Channel {
+    /// Reads the descriptor of a channel at `descriptor_address`.
+    fn read(mi: &mut impl MI, number: usize, descriptor_address: u32) -> Result<Channel, RttError> {
+        let mut descriptor = [0u32; 3];
+        mi.read_block32(descriptor_address, &mut descriptor)?;
+
+        let name = match descriptor[0] {
+            0 => None,
+            address => Some(read_string(mi, address)?),
+        };
+
+        Ok(Channel {
+            number,
+            name,
+            descriptor_address,
+            buffer_address: descriptor[1],
+            size: descriptor[2],
+        })
+    }
+
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(String::as_str)
+    }
+
+    /// Returns the size of the ring buffer in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Reads all data the target has written to an up channel so far.
+    ///
+    /// The read offset is advanced, so every byte is returned only once.
+    pub fn read_available(&self, mi: &mut impl MI) -> Result<Vec<u8>, RttError> {
+        let write_offset = mi.read32(self.descriptor_address + WRITE_OFFSET)?;
+        let read_offset = mi.read32(self.descriptor_address + READ_OFFSET)?;
+
+        if write_offset >= self.size || read_offset >= self.size {
+            return Err(RttError::InvalidControlBlock(self.descriptor_address));
+        }
+
+        let mut data = vec![];
+
+        if read_offset <= write_offset {
+            data.resize((write_offset - read_offset) as usize, 0);
+            mi.read_block8(self.buffer_address + read_offset, &mut data)?;
+        } else {
+            // The data wraps around the end of the buffer.
+            let first = (self.size - read_offset) as usize;
+            data.resize(first + write_offset as usize, 0);
+
+            mi.read_block8(self.buffer_address + read_offset, &mut data[..first])?;
+            mi.read_block8(self.buffer_address, &mut data[first..])?;
+        }
+
+        if !data.is_empty() {
+            mi.write32(self.descriptor_address + READ_OFFSET, write_offset)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Writes data to a down channel.
+    ///
+    /// Returns the number of bytes written, which is less than the length of `data`
+    /// when the buffer does not have enough free space.
+    pub fn write(&self, mi: &mut impl MI, data: &[u8]) -> Result<usize, RttError> {
+        let write_offset = mi.read32(self.descriptor_address + WRITE_OFFSET)?;
+        let read_offset = mi.read32(self.descriptor_address + READ_OFFSET)?;
+
+        if write_offset >= self.size || read_offset >= self.size {
+            return Err(RttError::InvalidControlBlock(self.descriptor_address));
+        }
+
+        // One byte always stays free, as equal offsets mean that the buffer is empty.
+        let free = if read_offset > write_offset {
+            read_offset - write_offset - 1
+        } else {
+            self.size - write_offset + read_offset - 1
+        };
+
+        let length = data.len().min(free as usize);
+
+        if length == 0 {
+            return Ok(0);
+        }
+
+        // The data wraps around the end of the buffer if it does not fit behind the write offset.
+        let first = length.min((self.size - write_offset) as usize);
+
+        mi.write_block8(self.buffer_address + write_offset, &data[..first])?;
+
+        if first < length {
+            mi.write_block8(self.buffer_address, &data[first..length])?;
+        }
+
+        let write_offset = (write_offset + length as u32) % self.size;
+        mi.write32(self.descriptor_address + WRITE_OFFSET, write_offset)?;
+
+        Ok(length)
+    }
+
+    /// Reads the mode of the channel from its flags.
+    pub fn mode(&self, mi: &mut impl MI) -> Result<ChannelMode, RttError> {
+        let flags = mi.read32(self.descriptor_address + FLAGS_OFFSET)?;
+
+        match flags & MODE_MASK {
+            0 => Ok(ChannelMode::NoBlockSkip),
+            1 => Ok(ChannelMode::NoBlockTrim),
+            2 => Ok(ChannelMode::BlockIfFull),
+            _ => Err(RttError::InvalidControlBlock(self.descriptor_address)),
+        }
+    }
+
+    /// Changes the mode of the channel, keeping the other flags.
+    pub fn set_mode(&self, mi: &mut impl MI, mode: ChannelMode) -> Result<(), RttError> {
+        let flags = mi.read32(self.descriptor_address + FLAGS_OFFSET)?;
+
+        let bits = match mode {
+            ChannelMode::NoBlockSkip => 0,
+            ChannelMode::NoBlockTrim => 1,
+            ChannelMode::BlockIfFull => 2,
+        };
+
+        mi.write32(
+            self.descriptor_address + FLAGS_OFFSET,
+            (flags & !MODE_MASK) | bits,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// An RTT control block on the target.
+#[derive(Debug, Clone)]
+pub struct Rtt {
+    address: u32,
+    up_channels: Vec<Channel>,
+    down_channels: Vec<Channel>,
+}
+
+impl Rtt {
+    /// Reads the control block at `address`.
+    pub fn attach(mi: &mut impl MI, address: u32) -> Result<Rtt, RttError> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        mi.read_block8(address, &mut header)?;
+
+        if !header.starts_with(CONTROL_BLOCK_ID) {
+            return Err(RttError::InvalidControlBlock(address));
+        }
+
+        let up_count = read_u32(&header[16..20]);
+        let down_count = read_u32(&header[20..24]);
+
+        if up_count > MAX_CHANNELS || down_count > MAX_CHANNELS {
+            return Err(RttError::InvalidControlBlock(address));
+        }
+
+        let descriptors_address = address + HEADER_SIZE;
+
+        let up_channels = (0..up_count)
+            .map(|i| Channel::read(mi, i as usize, descriptors_address + i * CHANNEL_SIZE))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let down_descriptors_address = descriptors_address + up_count * CHANNEL_SIZE;
+
+        let down_channels = (0..down_count)
+            .map(|i| Channel::read(mi, i as usize, down_descriptors_address + i * CHANNEL_SIZE))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Rtt {
+            address,
+            up_channels,
+            down_channels,
+        })
+    }
+
+    /// Searches `ranges` for the control block and reads it.
+    pub fn find(mi: &mut impl MI, ranges: &[Range<u32>]) -> Result<Rtt, RttError> {
+        for range in ranges {
+            if let Some(address) = scan(mi, range.clone())? {
+                return Rtt::attach(mi, address);
+            }
+        }
+
+        Err(RttError::ControlBlockNotFound)
+    }
+
+    /// Returns the address of the control block.
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    pub fn up_channels(&self) -> &[Channel] {
+        &self.up_channels
+    }
+
+    pub fn down_channels(&self) -> &[Channel] {
+        &self.down_channels
+    }
+
+    pub fn up_channel(&self, number: usize) -> Result<&Channel, RttError> {
+        self.up_channels
+            .get(number)
+            .ok_or(RttError::NoSuchChannel(number))
+    }
+
+    pub fn down_channel(&self, number: usize) -> Result<&Channel, RttError> {
+        self.down_channels
+            .get(number)
+            .ok_or(RttError::NoSuchChannel(number))
+    }
+}
+
+/// Returns the address of the control block symbol in an ELF file, if it has one.
+#[cfg(feature = "debug")]
+pub fn control_block_address(elf: &[u8]) -> Option<u32> {
+    let file = object::File::parse(elf).ok()?;
+
+    file.symbols()
+        .find(|(_, symbol)| symbol.name() == Some(CONTROL_BLOCK_SYMBOL))
+        .map(|(_, symbol)| symbol.address() as u32)
+}
+
+/// Searches a memory range for the control block ID.
+fn scan(mi: &mut impl MI, range: Range<u32>) -> Result<Option<u32>, RttError> {
+    let mut chunk = vec![0u8; (SCAN_CHUNK_SIZE + HEADER_SIZE) as usize];
+
+    let mut address = range.start;
+
+    while address < range.end {
+        // Chunks overlap, so an ID crossing a chunk boundary is found as well.
+        let length = (range.end - address).min(SCAN_CHUNK_SIZE + HEADER_SIZE) as usize;
+        mi.read_block8(address, &mut chunk[..length])?;
+
+        if let Some(offset) = chunk[..length]
+            .windows(CONTROL_BLOCK_ID.len())
+            .position(|window| window == CONTROL_BLOCK_ID)
+        {
+            return Ok(Some(address + offset as u32));
+        }
+
+        address += SCAN_CHUNK_SIZE;
+    }
+
+    Ok(None)
+}
+
+/// Reads a NUL terminated string of at most `MAX_NAME_LENGTH` bytes.
+fn read_string(mi: &mut impl MI, address: u32) -> Result<String, RttError> {
+    let mut data = [0u8; MAX_NAME_LENGTH];
+    mi.read_block8(address, &mut data)?;
+
+    let length = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+
+    Ok(String::from_utf8_lossy(&data[..length]).to_string())
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
@@ -0,0 +1,102 @@
+//! SEGGER's virtual terminals, which multiplex several text streams over one up channel.
+//!
+//! The target switches to another terminal by sending `0xff` followed by the number of the
+//! terminal as a hexadecimal digit (`'0'` to `'9'` and `'A'` to `'F'`). All data up to the next
+//! switch belongs to that terminal. Terminal 0 is active until the first switch.
+
+/// The byte starting a terminal switch.
+const ESCAPE: u8 = 0xff;
+
+/// Splits the data of an up channel into the output of the virtual terminals.
+#[derive(Debug, Default)]
+pub struct Demultiplexer {
+    terminal: u8,
+    /// Whether the last received byte was an escape byte.
+    escaped: bool,
+}
+
+impl Demultiplexer {
+    pub fn new() -> Self {
+        Demultiplexer::default()
+    }
+
+    /// Returns the terminal that received data belongs to.
+    pub fn terminal(&self) -> u8 {
+        self.terminal
+    }
+
+    /// Splits received data into chunks for each terminal, in the order it was sent.
+    ///
+    /// Terminal switches that are split between two calls are handled.
+    pub fn process(&mut self, data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut output = vec![];
+
+        for &byte in data {
+            if self.escaped {
+                self.escaped = false;
+
+                if let Some(terminal) = terminal_number(byte) {
+                    self.terminal = terminal;
+                    continue;
+                }
+
+                // Not a terminal switch, so the escape byte is part of the data.
+                push(&mut output, self.terminal, ESCAPE);
+            }
+
+            if byte == ESCAPE {
+                self.escaped = true;
+            } else {
+                push(&mut output, self.terminal, byte);
+            }
+        }
+
+        output
+    }
+}
+
+/// Appends a byte to the output, starting a new chunk when the terminal changed.
+fn push(output: &mut Vec<(u8, Vec<u8>)>, terminal: u8, byte: u8) {
+    match output.last_mut() {
+        Some((last, data)) if *last == terminal => data.push(byte),
+        _ => output.push((terminal, vec![byte])),
+    }
+}
+
+fn terminal_number(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Demultiplexer;
+
+    #[test]
+    fn switches_terminals() {
+        let mut demultiplexer = Demultiplexer::new();
+
+        let output = demultiplexer.process(b"boot\n\xff1one\n\xffAten\n");
+
+        assert_eq!(
+            output,
+            vec![
+                (0, b"boot\n".to_vec()),
+                (1, b"one\n".to_vec()),
+                (10, b"ten\n".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_split_switch() {
+        let mut demultiplexer = Demultiplexer::new();
+
+        assert_eq!(demultiplexer.process(b"a\xff"), vec![(0, b"a".to_vec())]);
+        assert_eq!(demultiplexer.process(b"2b"), vec![(2, b"b".to_vec())]);
+        assert_eq!(demultiplexer.terminal(), 2);
+    }
+}
@@ -1,24 +1,551 @@
-use crate::probe::debug_probe::MasterProbe;
+use crate::collection::cores::cpuid::{CoreInfo, CoreType, CpuidError};
+use crate::collection::cores::fault::{FaultStatus, Lockup};
+use crate::collection::cores::m4::{Demcr, Dfsr};
+use crate::collection::cores::FpRegisters;
+use crate::coresight::access_ports::AccessPortError;
+use crate::event::{HaltReason, SessionEvent, Subscribers};
+use crate::memory::cache_maintenance::CacheMaintenance;
+use crate::memory::core_access::{self, CoreAccessError};
+use crate::memory::MI;
+use crate::probe::debug_probe::{CpuInformation, DebugProbeError, MasterProbe};
 use crate::probe::flash::flasher::FlashAlgorithm;
-use crate::target::Target;
+use crate::probe::flash::memory::{regions_in, MemoryRegion};
+use crate::probe::protocol::ResetType;
+use crate::probe::retry::RetryPolicy;
+use crate::rtt::{Channel, RttError};
+use crate::session_builder::SessionBuilder;
+use crate::target::{hooks, Architecture, CoreRegister, CoreStatus, Target};
+
+use std::ops::Range;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+/// How long `reattach` waits for the probe to show up again.
+const REATTACH_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Session {
     pub target: Target,
     pub probe: MasterProbe,
     pub flash_algorithm: Option<FlashAlgorithm>,
+    /// The core found by `identify_core`.
+    pub core_info: Option<CoreInfo>,
+    /// The breakpoints set with `set_breakpoint`, which are set again by `reattach`.
+    breakpoints: Vec<u32>,
+    /// The vector catch settings of the core when the session started, see `detach`.
+    vector_catch: Option<u32>,
+    pub(crate) events: Subscribers,
 }
 
 impl Session {
     /// Open a new session with a given debug target
     pub fn new(
         target: Target,
-        probe: MasterProbe,
+        mut probe: MasterProbe,
         flash_algorithm: Option<FlashAlgorithm>,
     ) -> Self {
+        probe.set_architecture(target.core.architecture());
+
+        let vector_catch = target.core.vector_catch(&mut probe).unwrap_or_else(|e| {
+            log::warn!(
+                "Failed to read the vector catch settings of the core: {}",
+                e
+            );
+            None
+        });
+
         Self {
             target,
             probe,
             flash_algorithm,
+            core_info: None,
+            breakpoints: vec![],
+            vector_catch,
+            events: Subscribers::default(),
+        }
+    }
+
+    /// Starts describing the probe and the target of a session, see `SessionBuilder`.
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::new()
+    }
+
+    /// Returns a receiver of the events of the session from now on, see `SessionEvent`.
+    pub fn subscribe(&mut self) -> Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Sends an event to the subscribers, e.g. one noticed by a tool using the probe directly.
+    pub fn emit(&mut self, event: SessionEvent) {
+        self.events.emit(event)
+    }
+
+    /// Halts the core and tells the subscribers.
+    pub fn halt(&mut self) -> Result<CpuInformation, DebugProbeError> {
+        let span = tracing::debug_span!("debug", operation = "halt");
+        let _enter = span.enter();
+
+        let info = self.reported(|session| session.target.core.halt(&mut session.probe))?;
+        self.halt_reason(info.pc)?;
+        self.emit(SessionEvent::CoreHalted {
+            pc: info.pc,
+            reason: HaltReason::Request,
+        });
+
+        Ok(info)
+    }
+
+    /// Executes a single instruction and tells the subscribers.
+    pub fn step(&mut self) -> Result<CpuInformation, DebugProbeError> {
+        let span = tracing::debug_span!("debug", operation = "step");
+        let _enter = span.enter();
+
+        let info = self.reported(|session| session.target.core.step(&mut session.probe))?;
+        self.halt_reason(info.pc)?;
+        self.emit(SessionEvent::CoreHalted {
+            pc: info.pc,
+            reason: HaltReason::Step,
+        });
+
+        Ok(info)
+    }
+
+    /// Waits until the core halts, e.g. at a breakpoint after it was resumed, and tells the
+    /// subscribers why.
+    ///
+    /// Fails with `DebugProbeError::Timeout` if the core is still running after the halt
+    /// timeout of the retry policy, so the caller can keep waiting or halt the core.
+    pub fn wait_for_core_halted(&mut self) -> Result<(), DebugProbeError> {
+        let span = tracing::debug_span!("debug", operation = "wait_for_core_halted");
+        let _enter = span.enter();
+
+        match self.target.core.wait_for_core_halted(&mut self.probe) {
+            Err(DebugProbeError::Timeout) => return Err(DebugProbeError::Timeout),
+            result => self.reported(|_| result)?,
+        }
+
+        let pc_register = self.target.core.registers().PC;
+        let pc = self.reported(|session| {
+            session
+                .target
+                .core
+                .read_core_reg(&mut session.probe, pc_register)
+        })?;
+        let reason = self.halt_reason(pc)?;
+
+        self.emit(SessionEvent::CoreHalted { pc, reason });
+        if reason == HaltReason::Breakpoint && self.breakpoints.contains(&pc) {
+            self.emit(SessionEvent::BreakpointHit(pc));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the data available in an RTT up channel and passes it on to the subscribers.
+    pub fn read_rtt(&mut self, channel: &Channel) -> Result<Vec<u8>, RttError> {
+        let data = channel.read_available(&mut self.probe)?;
+
+        if !data.is_empty() {
+            self.emit(SessionEvent::RttData {
+                channel: channel.number(),
+                data: data.clone(),
+            });
+        }
+
+        Ok(data)
+    }
+
+    /// Returns why the core halted and clears the reason, so the next halt reports its own.
+    ///
+    /// Only Cortex-M cores, which `identify_core` found, record the reason. Of the others, only
+    /// halts at breakpoints set with `set_breakpoint` are recognized.
+    fn halt_reason(&mut self, pc: u32) -> Result<HaltReason, DebugProbeError> {
+        if self.core_info.is_none() {
+            return Ok(if self.breakpoints.contains(&pc) {
+                HaltReason::Breakpoint
+            } else {
+                HaltReason::Unknown
+            });
+        }
+
+        let dfsr = Dfsr(self.probe.read32(Dfsr::ADDRESS)?);
+        self.probe.write32(Dfsr::ADDRESS, dfsr.into())?;
+
+        Ok(dfsr.into())
+    }
+
+    /// Tells the subscribers if talking to the probe failed.
+    fn reported<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, DebugProbeError>,
+    ) -> Result<T, DebugProbeError> {
+        let result = f(self);
+
+        if let Err(e) = &result {
+            self.emit(SessionEvent::ProbeError(e.to_string()));
+        }
+
+        result
+    }
+
+    /// Reads the CPUID of the core and replaces the core implementation of the target
+    /// with the one matching it, instead of relying on the target description.
+    pub fn identify_core(&mut self) -> Result<CoreInfo, CpuidError> {
+        let info = CoreInfo::read(&mut self.probe)?;
+        log::debug!("Identified core: {}", info);
+
+        self.target.core = info.core_type.core();
+        self.core_info = Some(info);
+
+        if info.core_type == CoreType::M7 {
+            self.set_cache_maintenance(true);
+        }
+
+        Ok(info)
+    }
+
+    /// Sets whether the caches of the core are cleaned and invalidated around writes to its
+    /// flash and RAM, so it does not execute stale code after the debugger wrote new code.
+    ///
+    /// Cores with caches, like the Cortex-M7, have it enabled by `identify_core`.
+    pub fn set_cache_maintenance(&mut self, enabled: bool) {
+        let maintenance = if enabled {
+            Some(CacheMaintenance::new(self.executable_ranges()))
+        } else {
+            None
+        };
+
+        self.probe.set_cache_maintenance(maintenance);
+    }
+
+    /// Returns the ranges of the flash and the RAM of the target.
+    fn executable_ranges(&self) -> Vec<Range<u32>> {
+        self.target
+            .memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Flash(flash) => Some(flash.range.clone()),
+                MemoryRegion::Ram(ram) => Some(ram.range.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sets how often and how long transactions with the target are retried before they fail.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) -> Result<(), DebugProbeError> {
+        self.probe.set_retry_policy(policy)
+    }
+
+    /// Caches the flash and the RAM of the target read while the core is halted.
+    ///
+    /// This speeds up interactive debugging, which reads the same memory repeatedly.
+    pub fn enable_memory_cache(&mut self) {
+        let regions = self.executable_ranges();
+
+        self.probe.enable_memory_cache(regions);
+    }
+
+    /// Reads words of memory, through the core for the regions marked with `read_through_core`
+    /// and through the access port for everything else. The words are the values the core
+    /// reads, in the byte order of the target.
+    ///
+    /// Cortex-M cores need to be identified by `identify_core` first to read through them.
+    /// Tightly-coupled memories with an alias on the system bus are read at the alias.
+    pub fn read_memory32(&mut self, address: u32, data: &mut [u32]) -> Result<(), CoreAccessError> {
+        let range = address..address + 4 * data.len() as u32;
+        let parts = self.bus_parts(range);
+
+        for (part, through_core, bus_address) in parts {
+            let words = &mut data
+                [((part.start - address) / 4) as usize..((part.end - address) / 4) as usize];
+            if through_core {
+                self.read_through_core(part.start, words)?;
+            } else {
+                self.probe.read_block32(bus_address, words)?;
+            }
+        }
+
+        let endian = self.target.endian;
+        for word in data.iter_mut() {
+            *word = endian.swap_access_port_word(*word);
         }
+
+        Ok(())
+    }
+
+    /// Writes words of memory through the access port, as the values the core writes in the
+    /// byte order of the target. Tightly-coupled memories are written at their alias.
+    pub fn write_memory32(&mut self, address: u32, data: &[u32]) -> Result<(), AccessPortError> {
+        let endian = self.target.endian;
+        let words: Vec<u32> = data
+            .iter()
+            .map(|word| endian.swap_access_port_word(*word))
+            .collect();
+
+        let range = address..address + 4 * data.len() as u32;
+        for (part, _, bus_address) in self.bus_parts(range) {
+            let words =
+                &words[((part.start - address) / 4) as usize..((part.end - address) / 4) as usize];
+            self.probe.write_block32(bus_address, words)?;
+        }
+
+        Ok(())
+    }
+
+    /// Splits `range` at the borders of the regions of the memory map, returning each part
+    /// with whether it is read through the core, and the address the access port reaches it at.
+    fn bus_parts(&self, range: Range<u32>) -> Vec<(Range<u32>, bool, u32)> {
+        regions_in(&self.target.memory_map, range)
+            .into_iter()
+            .map(|(part, region)| match region {
+                Some(region) => {
+                    let bus_address = region.bus_address(part.start);
+                    (part, region.reads_through_core(), bus_address)
+                }
+                None => {
+                    let bus_address = part.start;
+                    (part, false, bus_address)
+                }
+            })
+            .collect()
+    }
+
+    /// Reads words through the core: Cortex-M cores found by `identify_core` run a stub,
+    /// cores whose debug registers move data through the core, like Cortex-R cores, load
+    /// them themselves.
+    fn read_through_core(&mut self, address: u32, data: &mut [u32]) -> Result<(), CoreAccessError> {
+        let core = self.target.core.as_ref();
+
+        if self.core_info.is_some() {
+            let scratch = self.scratch_ram().ok_or(CoreAccessError::NoRam)?;
+            return core_access::read_through_core(core, &mut self.probe, scratch, address, data);
+        }
+
+        match core.read_block32_through_core(&mut self.probe, address, data) {
+            Some(result) => Ok(result?),
+            None => Err(CoreAccessError::Unsupported),
+        }
+    }
+
+    /// Returns the start of the RAM to run the stub reading through the core in, preferring
+    /// RAM which is read through the access port, as the firmware is less likely to use it for
+    /// DMA.
+    fn scratch_ram(&self) -> Option<u32> {
+        let ram = self
+            .target
+            .memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Ram(ram) => Some(ram),
+                _ => None,
+            });
+
+        ram.clone()
+            .find(|ram| !ram.read_through_core)
+            .or_else(|| ram.clone().next())
+            .map(|ram| ram.range.start)
+    }
+
+    /// Reads the registers of the floating point unit, or returns `None` if the core was not
+    /// identified to have one.
+    pub fn read_fp_registers(&mut self) -> Result<Option<FpRegisters>, DebugProbeError> {
+        match &self.core_info {
+            Some(info) if info.capabilities.fpu => {
+                FpRegisters::read(self.target.core.as_ref(), &mut self.probe).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Resets the target in the given way, usually the `reset_type` of the target.
+    pub fn target_reset(&mut self, reset_type: ResetType) -> Result<(), DebugProbeError> {
+        // ARMv8-M dropped VECTRESET, but shares the core implementation with ARMv7-M.
+        let armv8m = self
+            .core_info
+            .map_or(false, |info| info.core_type.is_armv8m());
+        if reset_type == ResetType::VectReset && armv8m {
+            return Err(DebugProbeError::ResetTypeUnsupported(reset_type));
+        }
+
+        // The secondary cores were halted for flashing, so they are resumed and reset as well.
+        // A hardware reset resets them along with the first core.
+        self.target
+            .for_each_secondary_core(&mut self.probe, |core, probe| {
+                core.detach(probe, None, true)?;
+                match reset_type {
+                    ResetType::Hardware => Ok(()),
+                    _ => core.reset(probe),
+                }
+            })?;
+
+        self.target.core.reset_with(&mut self.probe, reset_type)
+    }
+
+    /// Resets the target in the given way and halts the core at the reset vector, by catching
+    /// it for the reset.
+    pub fn target_reset_and_halt(&mut self, reset_type: ResetType) -> Result<(), DebugProbeError> {
+        // Xtensa cores have no vector catch, they are halted by their debug module instead.
+        if self.target.core.architecture() == Architecture::Xtensa {
+            return match reset_type {
+                ResetType::SysResetReq => self.target.core.reset_and_halt(&mut self.probe),
+                _ => Err(DebugProbeError::ResetTypeUnsupported(reset_type)),
+            };
+        }
+
+        // Halting enables halting debug, without which the reset vector is not caught.
+        self.target.core.halt(&mut self.probe)?;
+
+        let demcr = Demcr(self.probe.read32(Demcr::ADDRESS)?);
+        let mut catching = demcr;
+        catching.set_vc_corereset(true);
+        self.probe.write32(Demcr::ADDRESS, catching.into())?;
+
+        let result = self
+            .target_reset(reset_type)
+            .and_then(|_| self.target.core.wait_for_core_halted(&mut self.probe));
+
+        self.probe.write32(Demcr::ADDRESS, demcr.into())?;
+
+        result
+    }
+
+    /// Checks whether the core is locked up, and if so halts it to read its registers and why
+    /// it faulted. The core is left halted, it would lock up again right away otherwise.
+    pub fn check_lockup(&mut self) -> Result<Option<Lockup>, DebugProbeError> {
+        if self.target.core.status(&mut self.probe)? != CoreStatus::LockedUp {
+            return Ok(None);
+        }
+
+        let pc = self.halt()?.pc;
+        let registers = self.target.core.registers();
+        let lr = self
+            .target
+            .core
+            .read_core_reg(&mut self.probe, registers.LR)?;
+        let sp = self
+            .target
+            .core
+            .read_core_reg(&mut self.probe, registers.SP)?;
+        let xpsr = self
+            .target
+            .core
+            .read_core_reg(&mut self.probe, registers.XPSR)?;
+
+        // Baseline cores have no fault status registers.
+        let fault = match self.core_info {
+            Some(info) if info.core_type.is_baseline() => None,
+            _ => Some(FaultStatus::read(&mut self.probe)?),
+        };
+
+        Ok(Some(Lockup {
+            pc,
+            lr,
+            sp,
+            xpsr,
+            fault,
+        }))
+    }
+
+    /// Gets the core out of a lockup by resetting the target with its reset type. The core is
+    /// halted at the reset vector, before it can run into the fault again.
+    pub fn recover_lockup(&mut self) -> Result<(), DebugProbeError> {
+        let reset_type = self.target.reset_type;
+        self.target_reset_and_halt(reset_type)
+    }
+
+    /// Halts the core and stops or services the known watchdogs of the target, so they do not
+    /// reset it in the middle of flashing. The flasher does so again after it resets the target.
+    ///
+    /// Returns `false` if no watchdog of the target is known.
+    pub fn prepare_watchdog_safe_flashing(&mut self) -> Result<bool, DebugProbeError> {
+        self.target.core.halt(&mut self.probe)?;
+
+        let actions = hooks::watchdog_actions(&self.target);
+        let stack = match &self.flash_algorithm {
+            Some(flash_algorithm) => Some(flash_algorithm.begin_stack),
+            None => self
+                .target
+                .memory_map
+                .iter()
+                .find_map(|region| match region {
+                    MemoryRegion::Ram(ram) => Some(ram.range.end),
+                    _ => None,
+                }),
+        };
+        for action in &actions {
+            action.run(
+                self.target.core.as_ref(),
+                &mut self.probe,
+                stack.unwrap_or_default(),
+            )?;
+        }
+
+        let known = !actions.is_empty();
+        let mut pre_program = actions;
+        pre_program.append(&mut self.target.pre_program);
+        self.target.pre_program = pre_program;
+
+        Ok(known)
+    }
+
+    /// Restores the debug state of the target and disconnects the probe.
+    ///
+    /// All breakpoints are removed and the vector catch settings the core had when the session
+    /// started are restored. If `resume` is set, the core is resumed, so the target is not left
+    /// halted after the debugger exits.
+    pub fn detach(&mut self, resume: bool) -> Result<(), DebugProbeError> {
+        self.target
+            .core
+            .detach(&mut self.probe, self.vector_catch, resume)?;
+        self.breakpoints.clear();
+        self.probe.detach()
+    }
+
+    /// Sets a hardware breakpoint at `address`, enabling the breakpoint unit first.
+    pub fn set_breakpoint(&mut self, address: u32) -> Result<(), DebugProbeError> {
+        let span = tracing::debug_span!("debug", operation = "set_breakpoint", address);
+        let _enter = span.enter();
+
+        self.target.core.enable_breakpoints(&mut self.probe, true)?;
+        self.target.core.set_breakpoint(&mut self.probe, address)?;
+
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the breakpoint at `address` set with `set_breakpoint`.
+    pub fn clear_breakpoint(&mut self, address: u32) -> Result<(), DebugProbeError> {
+        let span = tracing::debug_span!("debug", operation = "clear_breakpoint", address);
+        let _enter = span.enter();
+
+        self.target
+            .core
+            .disable_breakpoint(&mut self.probe, address)?;
+        self.breakpoints.retain(|breakpoint| *breakpoint != address);
+
+        Ok(())
+    }
+
+    /// Opens the probe again and attaches to the target, after the probe was disconnected.
+    ///
+    /// USB probes disappear for a moment when they are enumerated again, e.g. when the target
+    /// was power cycled, so this waits a few seconds for the probe to show up. The clock and
+    /// protocol of the connection are restored, and the breakpoints set with
+    /// `set_breakpoint` are set again, as the target may have lost them. Registers written
+    /// before the disconnect are not written to the core anymore.
+    pub fn reattach(&mut self) -> Result<(), DebugProbeError> {
+        self.reported(|session| session.probe.reattach(REATTACH_TIMEOUT))?;
+        self.target.core.forget_state();
+
+        if !self.breakpoints.is_empty() {
+            self.target.core.enable_breakpoints(&mut self.probe, true)?;
+            for address in &self.breakpoints {
+                self.target.core.set_breakpoint(&mut self.probe, *address)?;
+            }
+        }
+
+        Ok(())
     }
 }
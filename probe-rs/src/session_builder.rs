@@ -0,0 +1,531 @@
+//! Opening a session from a description of the probe and the target, see `Session::builder`.
+//!
+//! The builder collects the options first and checks that they fit together before a probe is
+//! opened, e.g. that a multi-drop target is not selected on a JTAG connection. A target given
+//! by name is looked up before the probe is opened, so a misspelled name does not touch the
+//! target. Targets which are not named are detected through the ROM table once attached.
+
+use crate::collection;
+use crate::config::ProbeConfig;
+use crate::probe::debug_probe::{
+    open_probe, DebugProbe, DebugProbeError, DebugProbeInfo, MasterProbe, ProbeConnection,
+};
+use crate::probe::flash::flasher::{AlgorithmSelectionError, FlashAlgorithm};
+use crate::probe::protocol::{AttachMethod, ResetType, WireProtocol};
+use crate::probe::recording::RecordingProbe;
+use crate::probe::watch;
+use crate::session::Session;
+use crate::target::info::{ChipInfo, ReadError};
+use crate::target::{Architecture, Target, TargetSelectionError};
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Below this target voltage in volts, the target is considered unpowered.
+const MIN_TARGET_VOLTAGE: f32 = 1.5;
+
+/// Which of the connected probes to open.
+#[derive(Debug, Clone)]
+pub enum ProbeSelector {
+    /// The probe with the given number in the list of connected probes.
+    Index(usize),
+    /// The probe with the given serial number, which stays the same when other probes are
+    /// connected.
+    Serial(String),
+    /// A probe found with `probe::watch::list_probes`.
+    Info(DebugProbeInfo),
+}
+
+impl From<usize> for ProbeSelector {
+    fn from(index: usize) -> Self {
+        ProbeSelector::Index(index)
+    }
+}
+
+impl From<DebugProbeInfo> for ProbeSelector {
+    fn from(info: DebugProbeInfo) -> Self {
+        ProbeSelector::Info(info)
+    }
+}
+
+impl fmt::Display for ProbeSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeSelector::Index(index) => write!(f, "number {}", index),
+            ProbeSelector::Serial(serial) => write!(f, "with the serial number {}", serial),
+            ProbeSelector::Info(info) => write!(f, "{}", info.identifier),
+        }
+    }
+}
+
+/// The target of a session.
+#[derive(Debug, Clone)]
+pub enum TargetSelector {
+    /// The target with the given name, looked up in the `TargetSource` of the builder.
+    Name(String),
+    /// A target description, e.g. parsed from a file of the project.
+    Target(Target),
+}
+
+impl From<&str> for TargetSelector {
+    fn from(name: &str) -> Self {
+        TargetSelector::Name(name.to_string())
+    }
+}
+
+impl From<String> for TargetSelector {
+    fn from(name: String) -> Self {
+        TargetSelector::Name(name)
+    }
+}
+
+impl From<Target> for TargetSelector {
+    fn from(target: Target) -> Self {
+        TargetSelector::Target(target)
+    }
+}
+
+/// Where the builder looks up targets and flash algorithms.
+///
+/// The targets built into the `probe-rs-targets` crate are one source, which the crate
+/// implements this for. Without one, only the targets registered in `~/.config/probe-rs`
+/// are found.
+pub trait TargetSource {
+    /// The target named `name`.
+    fn target(&self, name: &str) -> Result<Target, TargetSelectionError>;
+
+    /// The target of the chip detected through the ROM table.
+    fn detect(&self, chip_info: &ChipInfo) -> Result<Target, TargetSelectionError>;
+
+    /// The flash algorithm named `name`.
+    fn algorithm(&self, name: &str) -> Result<FlashAlgorithm, AlgorithmSelectionError>;
+}
+
+/// The targets and flash algorithms registered in `~/.config/probe-rs`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RegisteredTargets;
+
+impl TargetSource for RegisteredTargets {
+    fn target(&self, name: &str) -> Result<Target, TargetSelectionError> {
+        collection::get_target(name)
+            .ok_or_else(|| TargetSelectionError::TargetNotFound(name.to_string()))
+    }
+
+    fn detect(&self, chip_info: &ChipInfo) -> Result<Target, TargetSelectionError> {
+        collection::get_targets()
+            .into_iter()
+            .find(|target| {
+                target.manufacturer == chip_info.manufacturer && target.part == chip_info.part
+            })
+            .ok_or_else(|| {
+                TargetSelectionError::TargetNotFound(format!(
+                    "No target info found for device: {}",
+                    chip_info
+                ))
+            })
+    }
+
+    fn algorithm(&self, name: &str) -> Result<FlashAlgorithm, AlgorithmSelectionError> {
+        collection::get_algorithm(name)
+            .ok_or_else(|| AlgorithmSelectionError::AlgorithmNotFound(name.to_string()))
+    }
+}
+
+/// Why a session could not be opened.
+#[derive(Debug)]
+pub enum SessionBuilderError {
+    /// No probe is connected.
+    NoProbeFound,
+    /// The selected probe is not connected.
+    ProbeNotFound(ProbeSelector),
+    /// No probe was selected, and the given number of probes is connected.
+    AmbiguousProbe(usize),
+    /// A speed of 0 kHz was given.
+    InvalidSpeed,
+    /// The target is not debugged through the given wire protocol.
+    ProtocolUnsupported(WireProtocol, Architecture),
+    /// A multi-drop target was selected, which only exists on SWD connections.
+    MultidropRequiresSwd,
+    /// The target can not be attached to under reset.
+    AttachUnderResetUnsupported(Architecture),
+    Target(TargetSelectionError),
+    TargetInfo(ReadError),
+    DebugProbe(DebugProbeError),
+    /// The file to record the traffic to could not be created.
+    Recording(io::Error),
+}
+
+impl Error for SessionBuilderError {}
+
+impl fmt::Display for SessionBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SessionBuilderError::*;
+
+        match self {
+            NoProbeFound => write!(f, "No probe is connected."),
+            ProbeNotFound(selector) => write!(f, "The probe {} is not connected.", selector),
+            AmbiguousProbe(count) => write!(
+                f,
+                "{} probes are connected. Select one by its number or serial number.",
+                count
+            ),
+            InvalidSpeed => write!(f, "The speed of the wire protocol must not be 0 kHz."),
+            ProtocolUnsupported(protocol, architecture) => write!(
+                f,
+                "{:?} cores are not debugged through {}.",
+                architecture, protocol
+            ),
+            MultidropRequiresSwd => write!(
+                f,
+                "A multi-drop target can only be selected on an SWD connection."
+            ),
+            AttachUnderResetUnsupported(architecture) => write!(
+                f,
+                "{:?} cores can not be attached to under reset.",
+                architecture
+            ),
+            Target(e) => e.fmt(f),
+            TargetInfo(e) => write!(f, "Failed to detect the target: {}", e),
+            DebugProbe(e) => e.fmt(f),
+            Recording(e) => write!(f, "Failed to create the recording: {}", e),
+        }
+    }
+}
+
+impl From<TargetSelectionError> for SessionBuilderError {
+    fn from(error: TargetSelectionError) -> Self {
+        SessionBuilderError::Target(error)
+    }
+}
+
+impl From<ReadError> for SessionBuilderError {
+    fn from(error: ReadError) -> Self {
+        SessionBuilderError::TargetInfo(error)
+    }
+}
+
+impl From<DebugProbeError> for SessionBuilderError {
+    fn from(error: DebugProbeError) -> Self {
+        SessionBuilderError::DebugProbe(error)
+    }
+}
+
+/// Opens a session, see the module documentation.
+///
+/// ```no_run
+/// # use probe_rs::session::Session;
+/// # use probe_rs::session_builder::ProbeSelector;
+/// let session = Session::builder()
+///     .probe(ProbeSelector::Index(0))
+///     .target("nrf52840")
+///     .speed(4_000)
+///     .attach_under_reset()
+///     .open()?;
+/// # Ok::<(), probe_rs::session_builder::SessionBuilderError>(())
+/// ```
+pub struct SessionBuilder {
+    targets: Box<dyn TargetSource>,
+    probe: Option<ProbeSelector>,
+    target: Option<TargetSelector>,
+    protocol: Option<WireProtocol>,
+    speed: Option<u32>,
+    target_sel: Option<u32>,
+    attach_method: AttachMethod,
+    reset_type: Option<ResetType>,
+    record: Option<PathBuf>,
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        SessionBuilder {
+            targets: Box::new(RegisteredTargets),
+            probe: None,
+            target: None,
+            protocol: None,
+            speed: None,
+            target_sel: None,
+            attach_method: AttachMethod::NoReset,
+            reset_type: None,
+            record: None,
+        }
+    }
+}
+
+impl SessionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks up targets and flash algorithms in `targets`.
+    pub fn targets(mut self, targets: impl TargetSource + 'static) -> Self {
+        self.targets = Box::new(targets);
+        self
+    }
+
+    /// Opens the selected probe. Without one, the only connected probe is opened.
+    pub fn probe(mut self, probe: impl Into<ProbeSelector>) -> Self {
+        self.probe = Some(probe.into());
+        self
+    }
+
+    /// Debugs the given target. Without one, the target is detected through the ROM table.
+    pub fn target(mut self, target: impl Into<TargetSelector>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Connects through `protocol`, instead of SWD for Arm targets.
+    pub fn protocol(mut self, protocol: WireProtocol) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Clocks the wire protocol at `speed_khz`, or the closest speed the probe supports.
+    pub fn speed(mut self, speed_khz: u32) -> Self {
+        self.speed = Some(speed_khz);
+        self
+    }
+
+    /// Selects the target with the given TARGETSEL value on a multi-drop SWD bus, instead of
+    /// the one of the target description.
+    pub fn target_sel(mut self, target_sel: u32) -> Self {
+        self.target_sel = Some(target_sel);
+        self
+    }
+
+    /// Attaches while the target is held in reset through nRESET, for targets which disable
+    /// the debug port or sleep right after starting.
+    pub fn attach_under_reset(mut self) -> Self {
+        self.attach_method = AttachMethod::Reset;
+        self
+    }
+
+    /// Resets the target this way, instead of the default of the target.
+    pub fn reset_type(mut self, reset_type: ResetType) -> Self {
+        self.reset_type = Some(reset_type);
+        self
+    }
+
+    /// Records the traffic with the debug port to `path`, see `probe::recording`.
+    pub fn record(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record = Some(path.into());
+        self
+    }
+
+    /// Takes the options of a probe configuration which are set.
+    pub fn probe_config(mut self, config: &ProbeConfig) -> Self {
+        match (&config.serial, config.index) {
+            (Some(serial), _) => self.probe = Some(ProbeSelector::Serial(serial.clone())),
+            (None, Some(index)) => self.probe = Some(ProbeSelector::Index(index)),
+            (None, None) => (),
+        }
+        self.protocol = config.protocol.or(self.protocol);
+        self.speed = config.speed.or(self.speed);
+        self.target_sel = config.target_sel.or(self.target_sel);
+        self.reset_type = config.reset_type.or(self.reset_type);
+        self.record = config.record.clone().or(self.record);
+        self
+    }
+
+    /// Opens the probe, attaches to the target and selects its flash algorithm.
+    ///
+    /// A target without a flash algorithm is no error, the session just can not flash it.
+    pub fn open(self) -> Result<Session, SessionBuilderError> {
+        let target = match &self.target {
+            Some(TargetSelector::Name(name)) => Some(self.targets.target(name)?),
+            Some(TargetSelector::Target(target)) => Some(target.clone()),
+            None => None,
+        };
+        let architecture = target
+            .as_ref()
+            .map_or(Architecture::Arm, |target| target.core.architecture());
+        self.check(target.as_ref().map(|target| target.core.architecture()))?;
+
+        let device = self.select_probe()?;
+        let mut link = open_probe(&device)?;
+        if let Some(path) = &self.record {
+            link = Box::new(
+                RecordingProbe::create(link, path).map_err(SessionBuilderError::Recording)?,
+            );
+        }
+
+        let target_sel = self
+            .target_sel
+            .or_else(|| target.as_ref().and_then(|target| target.target_sel));
+        let quirks = target
+            .as_ref()
+            .map_or_else(Vec::new, |target| target.quirks.clone());
+        let protocol = match architecture {
+            Architecture::Arm => self.protocol.unwrap_or(WireProtocol::Swd),
+            Architecture::Xtensa => WireProtocol::Jtag,
+        };
+
+        {
+            let span = tracing::info_span!("probe", attach = link.get_name());
+            let _enter = span.enter();
+
+            warn_about_voltage(&mut *link);
+            link.set_multidrop_target(target_sel)?;
+            link.set_quirks(&quirks)?;
+            if let Some(speed) = self.speed {
+                let actual = link.set_speed(speed)?;
+                if actual != speed {
+                    tracing::warn!(
+                        "The probe does not support {} kHz, using {} kHz instead.",
+                        speed,
+                        actual
+                    );
+                }
+            }
+
+            match architecture {
+                Architecture::Arm => {
+                    link.attach(Some(protocol), self.attach_method)?;
+                }
+                Architecture::Xtensa => link
+                    .jtag_access()
+                    .ok_or(DebugProbeError::JTAGNotSupportedOnProbe)?
+                    .attach_jtag()?,
+            }
+        }
+
+        let mut probe = MasterProbe::from_specific_probe(link);
+        probe.set_architecture(architecture);
+        probe.set_connection(ProbeConnection {
+            info: device,
+            protocol,
+            speed_khz: self.speed,
+            target_sel,
+            quirks,
+        });
+
+        let mut target = match target {
+            Some(target) => target,
+            None => self
+                .targets
+                .detect(&ChipInfo::read_from_rom_table(&mut probe)?)?,
+        };
+        if let Some(reset_type) = self.reset_type {
+            target.reset_type = reset_type;
+        }
+
+        let flash_algorithm = match &target.flash_algorithm {
+            Some(name) => match self.targets.algorithm(name) {
+                Ok(algorithm) => Some(algorithm),
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to load the flash algorithm of {}: {}",
+                        target.name,
+                        e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Ok(Session::new(target, probe, flash_algorithm))
+    }
+
+    /// Checks that the options fit together and to a target of the given architecture, if it
+    /// is known.
+    fn check(&self, architecture: Option<Architecture>) -> Result<(), SessionBuilderError> {
+        if self.speed == Some(0) {
+            return Err(SessionBuilderError::InvalidSpeed);
+        }
+        if self.target_sel.is_some() && self.protocol == Some(WireProtocol::Jtag) {
+            return Err(SessionBuilderError::MultidropRequiresSwd);
+        }
+
+        match architecture {
+            Some(Architecture::Xtensa) => {
+                if let Some(WireProtocol::Swd) = self.protocol {
+                    return Err(SessionBuilderError::ProtocolUnsupported(
+                        WireProtocol::Swd,
+                        Architecture::Xtensa,
+                    ));
+                }
+                if self.target_sel.is_some() {
+                    return Err(SessionBuilderError::MultidropRequiresSwd);
+                }
+                if self.attach_method == AttachMethod::Reset {
+                    return Err(SessionBuilderError::AttachUnderResetUnsupported(
+                        Architecture::Xtensa,
+                    ));
+                }
+            }
+            Some(Architecture::Arm) | None => (),
+        }
+
+        Ok(())
+    }
+
+    /// Finds the selected probe among the connected ones.
+    fn select_probe(&self) -> Result<DebugProbeInfo, SessionBuilderError> {
+        let mut list = watch::list_probes();
+
+        match &self.probe {
+            Some(ProbeSelector::Info(info)) => Ok(info.clone()),
+            Some(ProbeSelector::Index(index)) if *index < list.len() => {
+                Ok(list.swap_remove(*index))
+            }
+            Some(ProbeSelector::Serial(serial)) => list
+                .into_iter()
+                .find(|device| device.serial_number.as_ref() == Some(serial))
+                .ok_or_else(|| {
+                    SessionBuilderError::ProbeNotFound(ProbeSelector::Serial(serial.clone()))
+                }),
+            Some(selector) => Err(SessionBuilderError::ProbeNotFound(selector.clone())),
+            None => match list.len() {
+                0 => Err(SessionBuilderError::NoProbeFound),
+                1 => Ok(list.remove(0)),
+                count => Err(SessionBuilderError::AmbiguousProbe(count)),
+            },
+        }
+    }
+}
+
+/// Warns if the target appears to be unpowered.
+fn warn_about_voltage(link: &mut dyn DebugProbe) {
+    if let Ok(Some(voltage)) = link.get_target_voltage() {
+        if voltage < MIN_TARGET_VOLTAGE {
+            tracing::warn!(
+                "Target voltage is {:.2} V, the target appears to be unpowered.",
+                voltage
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SessionBuilder, SessionBuilderError};
+    use crate::probe::protocol::WireProtocol;
+    use crate::target::Architecture;
+
+    #[test]
+    fn rejects_options_which_do_not_fit_together() {
+        let builder = SessionBuilder::new().speed(4_000).attach_under_reset();
+        assert!(builder.check(Some(Architecture::Arm)).is_ok());
+        match builder.check(Some(Architecture::Xtensa)) {
+            Err(SessionBuilderError::AttachUnderResetUnsupported(Architecture::Xtensa)) => {}
+            result => panic!("Expected attaching under reset to fail, got {:?}", result),
+        }
+
+        let builder = SessionBuilder::new()
+            .protocol(WireProtocol::Jtag)
+            .target_sel(0x0100_2927);
+        match builder.check(Some(Architecture::Arm)) {
+            Err(SessionBuilderError::MultidropRequiresSwd) => {}
+            result => panic!("Expected multi-drop over JTAG to fail, got {:?}", result),
+        }
+
+        let builder = SessionBuilder::new().speed(0);
+        match builder.check(None) {
+            Err(SessionBuilderError::InvalidSpeed) => {}
+            result => panic!("Expected a speed of 0 kHz to fail, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Capture of the Serial Wire Output (SWO), the pin through which the TPIU of a Cortex-M
+//! outputs the ITM stream.
+//!
+//! Probes with SWO support capture it themselves. For all others, the SWO pin can be
+//! connected to the RX pin of a serial adapter on the host, as the TPIU is configured to
+//! use the UART encoding.
+
+#[cfg(feature = "serialport")]
+mod serial;
+
+#[cfg(feature = "serialport")]
+pub use serial::SerialSwoReader;
+
+use crate::collection::cores::dwt::{DwtCtrl, DEMCR, DEMCR_TRCENA};
+use crate::coresight::access_ports::AccessPortError;
+use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+use crate::target::CoreRegister;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Current parallel port size register of the TPIU.
+const TPIU_CSPSR: u32 = 0xE004_0004;
+
+/// Asynchronous clock prescaler register of the TPIU.
+const TPIU_ACPR: u32 = 0xE004_0010;
+
+/// Selected pin protocol register of the TPIU.
+const TPIU_SPPR: u32 = 0xE004_00F0;
+
+/// Formatter and flush control register of the TPIU.
+const TPIU_FFCR: u32 = 0xE004_0304;
+
+/// The NRZ, or UART, encoding of SWO.
+const SPPR_NRZ: u32 = 2;
+
+/// Disables the formatter, so the ITM stream is output unchanged.
+const FFCR_TRIGIN: u32 = 1 << 8;
+
+const ITM_TER: u32 = 0xE000_0E00;
+const ITM_TCR: u32 = 0xE000_0E80;
+
+/// Lock access register of the ITM, which has to be unlocked before the ITM is configured.
+const ITM_LAR: u32 = 0xE000_0FB0;
+
+const ITM_LAR_KEY: u32 = 0xC5AC_CE55;
+
+/// Enables the ITM, local timestamps, synchronization packets and DWT packets,
+/// and sets the trace bus ID to 1.
+const ITM_TCR_ENABLE: u32 = 0b1111 | 1 << 16;
+
+#[derive(Debug)]
+pub enum SwoError {
+    /// The baud rate cannot be derived from the trace clock.
+    InvalidBaudRate(u32),
+    DebugProbe(DebugProbeError),
+    AccessPort(AccessPortError),
+    Io(io::Error),
+}
+
+impl Error for SwoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SwoError::DebugProbe(ref e) => Some(e),
+            SwoError::AccessPort(ref e) => Some(e),
+            SwoError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SwoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SwoError::InvalidBaudRate(baud_rate) => write!(
+                f,
+                "The SWO baud rate {} cannot be derived from the trace clock.",
+                baud_rate
+            ),
+            SwoError::DebugProbe(ref e) => e.fmt(f),
+            SwoError::AccessPort(ref e) => e.fmt(f),
+            SwoError::Io(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<DebugProbeError> for SwoError {
+    fn from(e: DebugProbeError) -> Self {
+        SwoError::DebugProbe(e)
+    }
+}
+
+impl From<AccessPortError> for SwoError {
+    fn from(e: AccessPortError) -> Self {
+        SwoError::AccessPort(e)
+    }
+}
+
+impl From<io::Error> for SwoError {
+    fn from(e: io::Error) -> Self {
+        SwoError::Io(e)
+    }
+}
+
+/// How the target outputs the ITM stream through SWO.
+#[derive(Debug, Copy, Clone)]
+pub struct SwoConfig {
+    /// Frequency of the clock of the TPIU in Hz, usually the core clock.
+    pub trace_clock: u32,
+    pub baud_rate: u32,
+    /// Whether the DWT sends exception trace packets.
+    pub exception_trace: bool,
+}
+
+/// Configures the TPIU, ITM and DWT of the target to output the ITM stream through SWO.
+///
+/// All stimulus ports are enabled, and local timestamps are added to the stream.
+pub fn configure_target(mi: &mut impl MI, config: &SwoConfig) -> Result<(), SwoError> {
+    let prescaler = match config.trace_clock.checked_div(config.baud_rate) {
+        Some(divider) if divider > 0 => divider - 1,
+        _ => return Err(SwoError::InvalidBaudRate(config.baud_rate)),
+    };
+
+    let demcr = mi.read32(DEMCR)?;
+    mi.write32(DEMCR, demcr | DEMCR_TRCENA)?;
+
+    mi.write32(TPIU_CSPSR, 1)?;
+    mi.write32(TPIU_ACPR, prescaler)?;
+    mi.write32(TPIU_SPPR, SPPR_NRZ)?;
+    mi.write32(TPIU_FFCR, FFCR_TRIGIN)?;
+
+    mi.write32(ITM_LAR, ITM_LAR_KEY)?;
+    mi.write32(ITM_TCR, ITM_TCR_ENABLE)?;
+    mi.write32(ITM_TER, 0xFFFF_FFFF)?;
+
+    let mut ctrl = DwtCtrl::from(mi.read32(DwtCtrl::ADDRESS)?);
+    ctrl.set_exctrcena(config.exception_trace);
+    mi.write32(DwtCtrl::ADDRESS, ctrl.into())?;
+
+    Ok(())
+}
+
+/// A source of captured SWO data.
+pub trait SwoReader {
+    /// Returns the data captured since the last call, which is empty if nothing arrived.
+    fn read(&mut self) -> Result<Vec<u8>, SwoError>;
+}
+
+/// Captures SWO with the debug probe. Capturing stops when the reader is dropped.
+pub struct ProbeSwoReader<'p> {
+    probe: &'p mut MasterProbe,
+}
+
+impl<'p> ProbeSwoReader<'p> {
+    /// Starts capturing with the probe.
+    ///
+    /// Fails with `DebugProbeError::CommandNotSupportedByProbe` if the probe has no SWO support.
+    pub fn start(probe: &'p mut MasterProbe, baud_rate: u32) -> Result<Self, SwoError> {
+        probe.enable_swo(baud_rate)?;
+
+        Ok(ProbeSwoReader { probe })
+    }
+}
+
+impl<'p> SwoReader for ProbeSwoReader<'p> {
+    fn read(&mut self) -> Result<Vec<u8>, SwoError> {
+        Ok(self.probe.read_swo()?)
+    }
+}
+
+impl<'p> Drop for ProbeSwoReader<'p> {
+    fn drop(&mut self) {
+        // The capture ends anyway when the probe is closed.
+        let _ = self.probe.disable_swo();
+    }
+}
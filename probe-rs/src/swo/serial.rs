@@ -0,0 +1,48 @@
+use super::{SwoError, SwoReader};
+
+use serialport::{SerialPort, SerialPortSettings};
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+/// How long a read waits for data before returning nothing.
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+const BUFFER_SIZE: usize = 4096;
+
+/// Captures SWO through a serial port of the host, for probes without SWO support.
+pub struct SerialSwoReader {
+    port: Box<dyn SerialPort>,
+}
+
+impl SerialSwoReader {
+    /// Opens the serial port at `path`, e.g. `/dev/ttyUSB1` or `COM3`.
+    pub fn open(path: &Path, baud_rate: u32) -> Result<Self, SwoError> {
+        let settings = SerialPortSettings {
+            baud_rate,
+            timeout: READ_TIMEOUT,
+            ..Default::default()
+        };
+
+        let port =
+            serialport::open_with_settings(path, &settings).map_err(|e| SwoError::Io(e.into()))?;
+
+        Ok(SerialSwoReader { port })
+    }
+}
+
+impl SwoReader for SerialSwoReader {
+    fn read(&mut self) -> Result<Vec<u8>, SwoError> {
+        let mut buffer = vec![0; BUFFER_SIZE];
+
+        match self.port.read(&mut buffer) {
+            Ok(length) => {
+                buffer.truncate(length);
+                Ok(buffer)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Validation of target description files.
+//!
+//! Parsing a description only fails on syntax and type errors. Misspelled optional fields
+//! are ignored, and overlapping or misaligned memory regions are only noticed when flashing.
+//! `TargetDescription::validate` finds these problems and reports them with line numbers.
+
+use super::Target;
+use crate::probe::flash::memory::MemoryRegion;
+
+use std::fmt;
+use std::ops::Range;
+
+/// The fields allowed in a description, by name.
+enum Schema {
+    Value,
+    Fields(&'static [(&'static str, Schema)]),
+}
+
+const RANGE: Schema = Schema::Fields(&[("start", Schema::Value), ("end", Schema::Value)]);
+
+const FLASH_REGION: Schema = Schema::Fields(&[
+    ("range", RANGE),
+    ("is_boot_memory", Schema::Value),
+    ("is_testable", Schema::Value),
+    ("blocksize", Schema::Value),
+    ("sector_size", Schema::Value),
+    ("page_size", Schema::Value),
+    ("phrase_size", Schema::Value),
+    ("erase_all_weight", Schema::Value),
+    ("erase_sector_weight", Schema::Value),
+    ("program_page_weight", Schema::Value),
+    ("erased_byte_value", Schema::Value),
+    ("access", Schema::Value),
+    ("are_erased_sectors_readable", Schema::Value),
+]);
+
+const RAM_REGION: Schema = Schema::Fields(&[
+    ("range", RANGE),
+    ("is_boot_memory", Schema::Value),
+    ("is_testable", Schema::Value),
+    ("read_through_core", Schema::Value),
+    ("bus_address", Schema::Value),
+]);
+
+/// The memory map is a list of single field maps, named after the type of the region.
+const MEMORY_MAP: Schema = Schema::Fields(&[
+    ("Flash", FLASH_REGION),
+    ("Ram", RAM_REGION),
+    ("Rom", Schema::Fields(&[("range", RANGE)])),
+    (
+        "Device",
+        Schema::Fields(&[
+            ("range", RANGE),
+            ("readable", Schema::Value),
+            ("read_through_core", Schema::Value),
+        ]),
+    ),
+]);
+
+/// The actions are a list of single field maps, named after the kind of the action.
+const FLASH_ACTIONS: Schema = Schema::Fields(&[
+    (
+        "Write",
+        Schema::Fields(&[("address", Schema::Value), ("value", Schema::Value)]),
+    ),
+    (
+        "Modify",
+        Schema::Fields(&[
+            ("address", Schema::Value),
+            ("mask", Schema::Value),
+            ("value", Schema::Value),
+        ]),
+    ),
+    (
+        "Run",
+        Schema::Fields(&[("address", Schema::Value), ("instructions", Schema::Value)]),
+    ),
+]);
+
+const TARGET: Schema = Schema::Fields(&[
+    ("name", Schema::Value),
+    ("inherits", Schema::Value),
+    (
+        "manufacturer",
+        Schema::Fields(&[("cc", Schema::Value), ("id", Schema::Value)]),
+    ),
+    ("part", Schema::Value),
+    ("flash_algorithm", Schema::Value),
+    ("memory_map", MEMORY_MAP),
+    ("core", Schema::Value),
+    ("target_sel", Schema::Value),
+    ("secondary_cores", Schema::Value),
+    ("reset_type", Schema::Value),
+    ("pre_program", FLASH_ACTIONS),
+    ("post_program", FLASH_ACTIONS),
+    ("quirks", Schema::Value),
+    ("endian", Schema::Value),
+]);
+
+/// A problem in a target description.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The line the problem is on, starting at 1, if it is known.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The text of a target description, as it is stored in a YAML file.
+#[derive(Debug, Clone)]
+pub struct TargetDescription {
+    definition: String,
+}
+
+impl TargetDescription {
+    pub fn new(definition: impl Into<String>) -> Self {
+        TargetDescription {
+            definition: definition.into(),
+        }
+    }
+
+    /// Checks the description and returns all problems found, which is empty if it is valid.
+    ///
+    /// `algorithm_exists` is asked whether the flash algorithm the description refers to exists,
+    /// and `base` returns the definition of a base the description inherits from.
+    pub fn validate(
+        &self,
+        algorithm_exists: impl Fn(&str) -> bool,
+        base: impl Fn(&str) -> Option<String>,
+    ) -> Vec<Diagnostic> {
+        let keys = Key::parse(&self.definition);
+        let mut diagnostics = vec![];
+
+        check_fields(&keys, &TARGET, "the target", &mut diagnostics);
+
+        let target = match Target::with_bases(&self.definition, base) {
+            Ok(target) => target,
+            Err(e) => {
+                diagnostics.push(Diagnostic {
+                    line: e.location().map(|location| location.line()),
+                    message: e.to_string(),
+                });
+                return diagnostics;
+            }
+        };
+
+        let line_of = |name: &str| keys.iter().find(|key| key.name == name).map(|key| key.line);
+
+        if let Some(algorithm) = &target.flash_algorithm {
+            if !algorithm_exists(algorithm) {
+                diagnostics.push(Diagnostic {
+                    line: line_of("flash_algorithm"),
+                    message: format!("The flash algorithm {} does not exist.", algorithm),
+                });
+            }
+        }
+
+        // The lines of the regions are only known if the memory map is a block list, and is
+        // not merged with the one of a base.
+        let inherits = keys.iter().any(|key| key.name == "inherits");
+        let region_keys = keys
+            .iter()
+            .find(|key| key.name == "memory_map" && !inherits)
+            .map(|key| &key.children[..])
+            .filter(|regions| regions.len() == target.memory_map.len());
+        let region_line = |i: usize| region_keys.map(|regions| regions[i].line);
+
+        for (i, region) in target.memory_map.iter().enumerate() {
+            let line = region_line(i);
+            let mut error = |message: String| {
+                diagnostics.push(Diagnostic { line, message });
+            };
+
+            let range = range(region);
+            if range.start >= range.end {
+                error(format!(
+                    "The region {:#010x}..{:#010x} is empty.",
+                    range.start, range.end
+                ));
+            }
+
+            if let MemoryRegion::Flash(flash) = region {
+                if flash.sector_size == 0 || flash.page_size == 0 {
+                    error("The sector and page size of a flash region must not be 0.".into());
+                    continue;
+                }
+
+                if range.start % flash.sector_size != 0 || range.end % flash.sector_size != 0 {
+                    error(format!(
+                        "The flash region {:#010x}..{:#010x} is not aligned to its sector size {:#x}.",
+                        range.start, range.end, flash.sector_size
+                    ));
+                }
+
+                if flash.sector_size % flash.page_size != 0 {
+                    error(format!(
+                        "The sector size {:#x} is not a multiple of the page size {:#x}.",
+                        flash.sector_size, flash.page_size
+                    ));
+                }
+            }
+        }
+
+        for (i, region) in target.memory_map.iter().enumerate() {
+            for (j, other) in target.memory_map.iter().enumerate().skip(i + 1) {
+                let (a, b) = (range(region), range(other));
+
+                if a.start < b.end && b.start < a.end {
+                    diagnostics.push(Diagnostic {
+                        line: region_line(j),
+                        message: format!(
+                            "The region {:#010x}..{:#010x} overlaps the region {:#010x}..{:#010x}{}.",
+                            b.start,
+                            b.end,
+                            a.start,
+                            a.end,
+                            region_line(i)
+                                .map(|line| format!(" on line {}", line))
+                                .unwrap_or_default()
+                        ),
+                    });
+                }
+            }
+        }
+
+        diagnostics
+    }
+}
+
+fn range(region: &MemoryRegion) -> Range<u32> {
+    match region {
+        MemoryRegion::Ram(ram) => ram.range.clone(),
+        MemoryRegion::Rom(rom) => rom.range.clone(),
+        MemoryRegion::Flash(flash) => flash.range.clone(),
+        MemoryRegion::Device(device) => device.range.clone(),
+    }
+}
+
+/// Reports the keys not in the schema, and recurses into the known ones.
+fn check_fields(keys: &[Key], schema: &Schema, parent: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let fields = match schema {
+        Schema::Fields(fields) => fields,
+        Schema::Value => return,
+    };
+
+    for key in keys {
+        match fields.iter().find(|(name, _)| *name == key.name) {
+            Some((name, schema)) => check_fields(&key.children, schema, name, diagnostics),
+            None => diagnostics.push(Diagnostic {
+                line: Some(key.line),
+                message: format!("Unknown field `{}` in {}.", key.name, parent),
+            }),
+        }
+    }
+}
+
+/// A key of a block style YAML mapping, with the keys nested in it.
+#[derive(Debug)]
+struct Key {
+    name: String,
+    line: usize,
+    indent: usize,
+    children: Vec<Key>,
+}
+
+impl Key {
+    /// Collects the keys by their indentation. Keys of list items are children of the list.
+    fn parse(definition: &str) -> Vec<Key> {
+        let mut keys = vec![];
+
+        for (i, line) in definition.lines().enumerate() {
+            let content = line.trim_start();
+            let mut indent = line.len() - content.len();
+
+            // The key of a list item is indented by the dash.
+            let content = if content.starts_with("- ") {
+                let item = content[1..].trim_start();
+                indent += content.len() - item.len();
+                item
+            } else {
+                content
+            };
+
+            let name = match content.find(':') {
+                Some(end) => &content[..end],
+                None => continue,
+            };
+
+            if content.starts_with('#')
+                || name.is_empty()
+                || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            {
+                continue;
+            }
+
+            insert(
+                &mut keys,
+                Key {
+                    name: name.to_string(),
+                    line: i + 1,
+                    indent,
+                    children: vec![],
+                },
+            );
+        }
+
+        keys
+    }
+}
+
+/// Adds a key as child of the last key that is less indented.
+fn insert(keys: &mut Vec<Key>, key: Key) {
+    match keys.last_mut() {
+        Some(last) if last.indent < key.indent => insert(&mut last.children, key),
+        _ => keys.push(key),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Diagnostic, TargetDescription};
+
+    const DEFINITION: &str = r#"name: "Test"
+manufacturer:
+  cc: 0x02
+  id: 0x44
+part: 0x000111
+flash_algorithm: "missing.yaml"
+memory_map:
+    - Flash:
+        range:
+          start: 0
+          end: 0x80000
+        is_boot_memory: true
+        is_testable: true
+        blocksize: 0x1000
+        sector_size: 0x1000
+        page_size: 0x1000
+        phrase_size: 0x100
+        erase_all_weight: 0.174
+        erase_sector_weight: 0.048
+        program_page_weight: 0.130
+        erased_byte_value: 0xFF
+        access: 0b00000101
+        are_erased_sectors_readable: true
+    - Ram:
+        range:
+          start: 0x70000
+          end: 0x20010000
+        is_boot_memory: false
+        is_testable: true
+        cached: true
+core: "M4"
+"#;
+
+    #[test]
+    fn reports_problems_with_lines() {
+        let diagnostics = TargetDescription::new(DEFINITION).validate(|_| false, |_| None);
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    line: Some(30),
+                    message: "Unknown field `cached` in Ram.".into(),
+                },
+                Diagnostic {
+                    line: Some(6),
+                    message: "The flash algorithm missing.yaml does not exist.".into(),
+                },
+                Diagnostic {
+                    line: Some(24),
+                    message: "The region 0x00070000..0x20010000 overlaps the region 0x00000000..0x00080000 on line 8.".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_the_bus_address_of_ram() {
+        let definition = r#"name: "Test"
+manufacturer:
+  cc: 0x02
+  id: 0x44
+part: 0x000111
+memory_map:
+    - Ram:
+        range:
+          start: 0
+          end: 0x10000
+        is_boot_memory: true
+        is_testable: true
+        bus_address: 0xFFE00000
+core: "M4"
+"#;
+
+        let diagnostics = TargetDescription::new(definition).validate(|_| false, |_| None);
+
+        assert_eq!(diagnostics, vec![]);
+    }
+
+    #[test]
+    fn accepts_the_byte_order() {
+        let definition = r#"name: "Test"
+manufacturer:
+  cc: 0x02
+  id: 0x44
+part: 0x000111
+memory_map:
+    - Ram:
+        range:
+          start: 0
+          end: 0x10000
+        is_boot_memory: true
+        is_testable: true
+core: "M4"
+endian: "big"
+"#;
+
+        let diagnostics = TargetDescription::new(definition).validate(|_| false, |_| None);
+
+        assert_eq!(diagnostics, vec![]);
+    }
+}
@@ -0,0 +1,263 @@
+//! Actions the flasher runs before and after the flash algorithm, as given by the target
+//! description.
+//!
+//! Some chips need preparation before their flash can be programmed, like faster clocks or a
+//! disabled watchdog, which would otherwise reset the chip in the middle of an operation.
+//! The actions are listed like the memory map, e.g. for the watchdog of the Kinetis chips,
+//! whose unlock sequence is too fast for the probe and runs as a small stub instead:
+//!
+//! ```yaml
+//! pre_program:
+//!     - Run:
+//!         address: 0x20000000
+//!         instructions: [
+//!             0x4a054904, 0x800a4b05, 0x4a05800b, 0x800a390e, 0xbe00be00,
+//!             0x4005200e, 0xc520, 0xd928, 0x1d2,
+//!         ]
+//! post_program:
+//!     - Modify:
+//!         address: 0x40048044
+//!         mask: 0x000f0000
+//!         value: 0x00010000
+//! ```
+
+use super::{Core, Target};
+use crate::memory::MI;
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+use crate::probe::flash::memory::MemoryRegion;
+
+/// The reload registers RR[0] to RR[7] of the watchdog of the nRF5 chips.
+const NRF_WDT_RR: u32 = 0x4001_0600;
+const NRF_WDT_RELOAD: u32 = 0x6E52_4635;
+
+/// The DBGMCU_APB1_FZ registers of the STM32F0 and STM32F4 chips, whose DBG_WWDG_STOP and
+/// DBG_IWDG_STOP bits freeze the watchdogs while the core is halted.
+const STM32F0_DBGMCU_APB1_FZ: u32 = 0x4001_5808;
+const STM32F4_DBGMCU_APB1_FZ: u32 = 0xE004_2008;
+const STM32_DBG_WDG_STOP: u32 = (1 << 11) | (1 << 12);
+
+/// Unlocks the watchdog of the Kinetis chips and disables it, which has to happen within 20
+/// cycles and is too fast for the probe.
+const KINETIS_DISABLE_WDOG: [u32; 9] = [
+    0x4a05_4904, // ldr r1, =WDOG_UNLOCK; ldr r2, =0xc520
+    0x800a_4b05, // ldr r3, =0xd928; strh r2, [r1]
+    0x4a05_800b, // strh r3, [r1]; ldr r2, =0x1d2
+    0x800a_390e, // subs r1, #14; strh r2, [r1] (WDOG_STCTRLH)
+    0xbe00_be00, // bkpt
+    0x4005_200e,
+    0xc520,
+    0xd928,
+    0x01d2,
+];
+
+/// The write protection and configuration registers of the RTC watchdog and the watchdog of
+/// timer group 0 of the ESP32 chips, which are enabled by the ROM bootloader. Writing the key
+/// unlocks the configuration registers.
+const ESP32_RTC_WDT_WPROTECT: u32 = 0x3FF4_80A4;
+const ESP32_RTC_WDT_CONFIG0: u32 = 0x3FF4_808C;
+const ESP32_TIMG0_WDT_WPROTECT: u32 = 0x3FF5_F064;
+const ESP32_TIMG0_WDT_CONFIG0: u32 = 0x3FF5_F048;
+const ESP32S3_RTC_WDT_WPROTECT: u32 = 0x6000_80B0;
+const ESP32S3_RTC_WDT_CONFIG0: u32 = 0x6000_8098;
+const ESP32S3_TIMG0_WDT_WPROTECT: u32 = 0x6001_F064;
+const ESP32S3_TIMG0_WDT_CONFIG0: u32 = 0x6001_F048;
+const ESP32_WDT_KEY: u32 = 0x50D8_3AA1;
+
+/// The super watchdog of the ESP32-S3, which can not be disabled but feeds itself with
+/// SWD_AUTO_FEED_EN set.
+const ESP32S3_SWD_WPROTECT: u32 = 0x6000_80B8;
+const ESP32S3_SWD_CONF: u32 = 0x6000_80B4;
+const ESP32S3_SWD_KEY: u32 = 0x8F1D_312A;
+const ESP32S3_SWD_AUTO_FEED_EN: u32 = 1 << 31;
+
+/// An action which prepares the target for the flash algorithm, or restores it afterwards.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum FlashAction {
+    /// Writes a 32 bit register.
+    Write { address: u32, value: u32 },
+    /// Replaces the bits of a 32 bit register which are set in `mask` with those of `value`.
+    Modify { address: u32, mask: u32, value: u32 },
+    /// Loads a stub of Thumb code to `address` in RAM and runs it. The stub has to end with a
+    /// breakpoint instruction, at which the flasher continues.
+    Run {
+        address: u32,
+        instructions: Vec<u32>,
+    },
+}
+
+impl FlashAction {
+    /// Runs the action on the halted core. Stubs get `stack` as their stack pointer.
+    pub fn run(
+        &self,
+        core: &dyn Core,
+        probe: &mut MasterProbe,
+        stack: u32,
+    ) -> Result<(), DebugProbeError> {
+        match self {
+            FlashAction::Write { address, value } => probe.write32(*address, *value)?,
+            FlashAction::Modify {
+                address,
+                mask,
+                value,
+            } => {
+                let current = probe.read32(*address)?;
+                probe.write32(*address, modify(current, *mask, *value))?;
+            }
+            FlashAction::Run {
+                address,
+                instructions,
+            } => {
+                log::debug!("Running the stub at 0x{:08x}.", address);
+                probe.write_block32(*address, instructions)?;
+
+                let regs = core.registers();
+                core.write_core_reg(probe, regs.PC, *address)?;
+                core.write_core_reg(probe, regs.SP, stack)?;
+                core.run(probe)?;
+                core.wait_for_core_halted(probe)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the actions which stop or service the known watchdogs of the target.
+///
+/// The watchdog of the nRF5 chips can not be stopped once it runs, so it is only reloaded.
+pub fn watchdog_actions(target: &Target) -> Vec<FlashAction> {
+    let name = target.name.to_ascii_lowercase();
+
+    if name.starts_with("nrf5") {
+        (0..8)
+            .map(|i| FlashAction::Write {
+                address: NRF_WDT_RR + i * 4,
+                value: NRF_WDT_RELOAD,
+            })
+            .collect()
+    } else if name.starts_with("stm32f0") || name.starts_with("stm32f4") {
+        let address = if name.starts_with("stm32f0") {
+            STM32F0_DBGMCU_APB1_FZ
+        } else {
+            STM32F4_DBGMCU_APB1_FZ
+        };
+
+        vec![FlashAction::Modify {
+            address,
+            mask: STM32_DBG_WDG_STOP,
+            value: STM32_DBG_WDG_STOP,
+        }]
+    } else if name.starts_with("esp32-s3") || name.starts_with("esp32s3") {
+        let mut actions = disable_esp32_watchdogs(
+            (ESP32S3_RTC_WDT_WPROTECT, ESP32S3_RTC_WDT_CONFIG0),
+            (ESP32S3_TIMG0_WDT_WPROTECT, ESP32S3_TIMG0_WDT_CONFIG0),
+        );
+        actions.extend(vec![
+            FlashAction::Write {
+                address: ESP32S3_SWD_WPROTECT,
+                value: ESP32S3_SWD_KEY,
+            },
+            FlashAction::Modify {
+                address: ESP32S3_SWD_CONF,
+                mask: ESP32S3_SWD_AUTO_FEED_EN,
+                value: ESP32S3_SWD_AUTO_FEED_EN,
+            },
+            FlashAction::Write {
+                address: ESP32S3_SWD_WPROTECT,
+                value: 0,
+            },
+        ]);
+        actions
+    } else if name.starts_with("esp32") {
+        disable_esp32_watchdogs(
+            (ESP32_RTC_WDT_WPROTECT, ESP32_RTC_WDT_CONFIG0),
+            (ESP32_TIMG0_WDT_WPROTECT, ESP32_TIMG0_WDT_CONFIG0),
+        )
+    } else if name.starts_with("mk") {
+        let ram = target.memory_map.iter().find_map(|region| match region {
+            MemoryRegion::Ram(ram) => Some(ram.range.start),
+            _ => None,
+        });
+
+        ram.map(|address| FlashAction::Run {
+            address,
+            instructions: KINETIS_DISABLE_WDOG.to_vec(),
+        })
+        .into_iter()
+        .collect()
+    } else {
+        vec![]
+    }
+}
+
+/// Disables the RTC and timer group 0 watchdogs, given by their write protection and
+/// configuration registers, and locks their configuration again.
+fn disable_esp32_watchdogs(rtc: (u32, u32), timg0: (u32, u32)) -> Vec<FlashAction> {
+    [rtc, timg0]
+        .iter()
+        .flat_map(|&(wprotect, config0)| {
+            vec![
+                FlashAction::Write {
+                    address: wprotect,
+                    value: ESP32_WDT_KEY,
+                },
+                FlashAction::Write {
+                    address: config0,
+                    value: 0,
+                },
+                FlashAction::Write {
+                    address: wprotect,
+                    value: 0,
+                },
+            ]
+        })
+        .collect()
+}
+
+fn modify(current: u32, mask: u32, value: u32) -> u32 {
+    (current & !mask) | (value & mask)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{modify, FlashAction};
+
+    #[test]
+    fn parses_actions() {
+        let actions: Vec<FlashAction> = serde_yaml::from_str(
+            "
+- Write:
+    address: 0x40048100
+    value: 0
+- Modify:
+    address: 0x40048044
+    mask: 0x000f0000
+    value: 0x00010000
+- Run:
+    address: 0x20000000
+    instructions: [0xbe00be00]
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                FlashAction::Write {
+                    address: 0x4004_8100,
+                    value: 0
+                },
+                FlashAction::Modify {
+                    address: 0x4004_8044,
+                    mask: 0x000f_0000,
+                    value: 0x0001_0000
+                },
+                FlashAction::Run {
+                    address: 0x2000_0000,
+                    instructions: vec![0xbe00_be00]
+                },
+            ]
+        );
+        assert_eq!(modify(0x1234_5678, 0x000f_0000, 0x0001_0000), 0x1231_5678);
+    }
+}
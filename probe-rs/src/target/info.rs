@@ -0,0 +1,113 @@
+use crate::probe::debug_probe::{DebugProbeError, MasterProbe};
+use crate::{
+    coresight::{
+        access_ports::{
+            generic_ap::{APClass, IDR},
+            memory_ap::MemoryAP,
+        },
+        ap_access::{valid_access_ports, APAccess},
+    },
+    memory::romtable::{
+        rom_table_address, CSComponent, CSComponentId, PeripheralID, RomTableError,
+    },
+};
+use jep106::JEP106Code;
+use log::debug;
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+pub enum ReadError {
+    DebugProbeError(DebugProbeError),
+    RomTableError(RomTableError),
+    NotFound,
+}
+
+impl From<DebugProbeError> for ReadError {
+    fn from(e: DebugProbeError) -> Self {
+        ReadError::DebugProbeError(e)
+    }
+}
+
+impl From<RomTableError> for ReadError {
+    fn from(e: RomTableError) -> Self {
+        ReadError::RomTableError(e)
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::DebugProbeError(e) => write!(f, "failed to access target: {}", e),
+            ReadError::RomTableError(e) => write!(f, "failed to parse ROM table: {}", e),
+            ReadError::NotFound => f.write_str("chip info not found in IDR"),
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+pub struct ChipInfo {
+    pub manufacturer: JEP106Code,
+    pub part: u16,
+    /// The DPIDR of the debug port, which tells clones apart from the part they copy.
+    pub dpidr: Option<u32>,
+}
+
+impl ChipInfo {
+    pub fn read_from_rom_table(probe: &mut MasterProbe) -> Result<Self, ReadError> {
+        let dpidr = probe.read_register_dp(0).ok();
+
+        for access_port in valid_access_ports(probe) {
+            let idr = probe.read_register_ap(access_port, IDR::default())?;
+            debug!("{:#x?}", idr);
+
+            if idr.CLASS == APClass::MEMAP {
+                let access_port: MemoryAP = access_port.into();
+
+                let baseaddr = match rom_table_address(probe, access_port)? {
+                    Some(baseaddr) => baseaddr,
+                    None => continue,
+                };
+
+                let component_table = CSComponent::try_parse(&probe.into(), baseaddr)?;
+
+                match component_table {
+                    CSComponent::Class1RomTable(
+                        CSComponentId {
+                            peripheral_id:
+                                PeripheralID {
+                                    JEP106: Some(jep106),
+                                    PART: part,
+                                    ..
+                                },
+                            ..
+                        },
+                        ..,
+                    ) => {
+                        return Ok(ChipInfo {
+                            manufacturer: jep106,
+                            part,
+                            dpidr,
+                        });
+                    }
+                    _ => continue,
+                }
+            }
+        }
+
+        Err(ReadError::NotFound)
+    }
+}
+
+impl fmt::Display for ChipInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let manu = match self.manufacturer.get() {
+            Some(name) => name.to_string(),
+            None => format!(
+                "<unknown manufacturer (cc={:2x}, id={:2x})>",
+                self.manufacturer.cc, self.manufacturer.id
+            ),
+        };
+        write!(f, "{} 0x{:04x}", manu, self.part)
+    }
+}
@@ -0,0 +1,197 @@
+//! Target descriptions inheriting from a base description.
+//!
+//! The variants of a chip family mostly differ in the sizes of their memories. A description
+//! with an `inherits: <base>` field only contains what differs from the base, e.g. the name, the
+//! part and the ends of the memory regions. Its fields replace those of the base, and mappings
+//! are merged field by field. A region of its memory map is merged into the region of the base
+//! of the same type starting at the same address, or added to the memory map if there is none.
+
+use super::TargetParseError;
+
+use serde::de::Error;
+use serde_yaml::Value;
+
+/// How many bases can inherit from each other, to stop on cycles.
+const MAX_DEPTH: usize = 8;
+
+/// Returns the description with the bases it inherits from merged into it.
+///
+/// `base` returns the definition of a base by its name.
+pub fn resolve(
+    definition: &str,
+    base: &dyn Fn(&str) -> Option<String>,
+) -> Result<Value, TargetParseError> {
+    let mut description: Value = serde_yaml::from_str(definition)?;
+
+    for _ in 0..MAX_DEPTH {
+        let name = match take_inherits(&mut description)? {
+            Some(name) => name,
+            None => return Ok(description),
+        };
+
+        let definition = base(&name).ok_or_else(|| {
+            TargetParseError::custom(format!("The base description {} does not exist.", name))
+        })?;
+
+        description = merge(serde_yaml::from_str(&definition)?, description);
+    }
+
+    Err(TargetParseError::custom(format!(
+        "The description inherits from more than {} bases, they might inherit from each other.",
+        MAX_DEPTH
+    )))
+}
+
+/// Removes the `inherits` field and returns the name of the base.
+fn take_inherits(description: &mut Value) -> Result<Option<String>, TargetParseError> {
+    let fields = match description {
+        Value::Mapping(fields) => fields,
+        _ => return Ok(None),
+    };
+
+    match fields.remove(&Value::String("inherits".into())) {
+        Some(Value::String(name)) => Ok(Some(name)),
+        Some(_) => Err(TargetParseError::custom(
+            "`inherits` must be the name of a base description.",
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Returns `base` with the fields of `description` replacing its own.
+fn merge(base: Value, description: Value) -> Value {
+    match (base, description) {
+        (Value::Mapping(mut base), Value::Mapping(description)) => {
+            for (key, value) in description {
+                let value = match base.remove(&key) {
+                    Some(Value::Sequence(regions)) if key == Value::String("memory_map".into()) => {
+                        merge_regions(regions, value)
+                    }
+                    Some(base) => merge(base, value),
+                    None => value,
+                };
+                base.insert(key, value);
+            }
+
+            Value::Mapping(base)
+        }
+        (_, description) => description,
+    }
+}
+
+fn merge_regions(mut regions: Vec<Value>, description: Value) -> Value {
+    let description = match description {
+        Value::Sequence(description) => description,
+        description => return description,
+    };
+
+    for region in description {
+        let key = region_key(&region);
+        match regions
+            .iter()
+            .position(|base| key.is_some() && region_key(base) == key)
+        {
+            Some(i) => {
+                let base = std::mem::replace(&mut regions[i], Value::Null);
+                regions[i] = merge(base, region);
+            }
+            None => regions.push(region),
+        }
+    }
+
+    Value::Sequence(regions)
+}
+
+/// Returns the type and the start address of a memory region.
+fn region_key(region: &Value) -> Option<(&Value, u64)> {
+    let (kind, fields) = region.as_mapping()?.iter().next()?;
+    let start = fields
+        .as_mapping()?
+        .get(&Value::String("range".into()))?
+        .as_mapping()?
+        .get(&Value::String("start".into()))?
+        .as_u64()?;
+
+    Some((kind, start))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::probe::flash::memory::MemoryRegion;
+    use crate::target::Target;
+
+    const BASE: &str = r#"
+manufacturer:
+  cc: 0x02
+  id: 0x44
+part: 0x000111
+memory_map:
+    - Flash:
+        range:
+          start: 0
+          end: 0x80000
+        is_boot_memory: true
+        is_testable: true
+        blocksize: 0x1000
+        sector_size: 0x1000
+        page_size: 0x1000
+        phrase_size: 0x100
+        erase_all_weight: 0.174
+        erase_sector_weight: 0.048
+        program_page_weight: 0.130
+        erased_byte_value: 0xFF
+        access: 0b00000101
+        are_erased_sectors_readable: true
+    - Ram:
+        range:
+          start: 0x20000000
+          end: 0x20010000
+        is_boot_memory: false
+        is_testable: true
+core: "M4"
+"#;
+
+    const VARIANT: &str = r#"
+name: "nRF52840"
+inherits: "nRF52"
+part: 0x000008
+memory_map:
+    - Flash:
+        range:
+          start: 0
+          end: 0x100000
+    - Ram:
+        range:
+          start: 0x20000000
+          end: 0x20040000
+"#;
+
+    #[test]
+    fn overrides_base() {
+        let target = Target::with_bases(VARIANT, |name| {
+            if name == "nRF52" {
+                Some(BASE.to_string())
+            } else {
+                None
+            }
+        })
+        .unwrap();
+
+        assert_eq!(target.name, "nRF52840");
+        assert_eq!(target.part, 0x8);
+        assert_eq!(target.memory_map.len(), 2);
+        match &target.memory_map[0] {
+            MemoryRegion::Flash(flash) => {
+                assert_eq!(flash.range, 0..0x10_0000);
+                assert_eq!(flash.sector_size, 0x1000);
+            }
+            region => panic!("Unexpected region {:?}", region),
+        }
+        match &target.memory_map[1] {
+            MemoryRegion::Ram(ram) => assert_eq!(ram.range, 0x2000_0000..0x2004_0000),
+            region => panic!("Unexpected region {:?}", region),
+        }
+
+        assert!(Target::with_bases(VARIANT, |_| None).is_err());
+    }
+}
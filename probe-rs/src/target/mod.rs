@@ -0,0 +1,448 @@
+pub mod description;
+pub mod hooks;
+pub mod info;
+pub mod inheritance;
+pub mod quirks;
+
+use serde::de::{Error, Unexpected};
+
+use self::hooks::FlashAction;
+use self::info::ReadError;
+use self::quirks::Quirk;
+use crate::{
+    collection::{get_core, get_core_at},
+    event::HaltReason,
+    probe::{
+        debug_probe::{CpuInformation, DebugProbeError, MasterProbe},
+        flash::memory::MemoryRegion,
+        protocol::ResetType,
+    },
+};
+
+use std::fmt;
+
+pub trait CoreRegister: Clone + From<u32> + Into<u32> + Sized + std::fmt::Debug {
+    const ADDRESS: u32;
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CoreRegisterAddress(pub u8);
+
+impl From<CoreRegisterAddress> for u32 {
+    fn from(value: CoreRegisterAddress) -> Self {
+        u32::from(value.0)
+    }
+}
+
+impl From<u8> for CoreRegisterAddress {
+    fn from(value: u8) -> Self {
+        CoreRegisterAddress(value)
+    }
+}
+
+/// The architecture of a core, which tells how it is debugged.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Architecture {
+    /// Cortex-M and Cortex-A cores, debugged through an ARM debug port.
+    Arm,
+    /// Xtensa cores, debugged through the On-Chip Debug module behind their JTAG TAP.
+    Xtensa,
+}
+
+/// The byte order of the data in the memory of a target.
+///
+/// Big-endian Arm cores, like Cortex-R cores configured for it, use BE-8: the bytes are at
+/// the same addresses in both byte orders, only the order in which they make up halfwords and
+/// words differs. The access port always puts the byte at the lowest address into the lowest
+/// byte of a word, so the words it reads from a big-endian target are byte-swapped.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Default for Endian {
+    fn default() -> Self {
+        Endian::Little
+    }
+}
+
+impl Endian {
+    /// Turns a word read through the access port into the value the core reads from the
+    /// same address, and the other way round.
+    pub fn swap_access_port_word(self, word: u32) -> u32 {
+        match self {
+            Endian::Little => word,
+            Endian::Big => word.swap_bytes(),
+        }
+    }
+
+    /// Returns the value of the word the core reads from the bytes in memory.
+    pub fn u32_from_bytes(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    /// Returns the bytes in memory of a word the core writes.
+    pub fn u32_to_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        }
+    }
+
+    /// Returns the value of a halfword, a word or a doubleword in memory.
+    pub fn uint_from_bytes(self, bytes: &[u8]) -> u64 {
+        let fold = |value: u64, byte: &u8| (value << 8) | u64::from(*byte);
+
+        match self {
+            Endian::Little => bytes.iter().take(8).rev().fold(0, fold),
+            Endian::Big => bytes.iter().take(8).fold(0, fold),
+        }
+    }
+}
+
+/// What a core is doing, as far as the debugger can tell without halting it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CoreStatus {
+    Running,
+    Halted(HaltReason),
+    /// The core waits for an interrupt or event, e.g. after a WFI instruction.
+    Sleeping,
+    /// The core hit a fault it could not handle, e.g. in the HardFault handler, and only a
+    /// reset or the debugger gets it out.
+    LockedUp,
+    /// The core does not tell, e.g. a core from a dump.
+    Unknown,
+}
+
+impl fmt::Display for CoreStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoreStatus::Running => f.write_str("running"),
+            CoreStatus::Halted(reason) => write!(f, "halted ({})", reason),
+            CoreStatus::Sleeping => f.write_str("sleeping"),
+            CoreStatus::LockedUp => f.write_str("locked up"),
+            CoreStatus::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Copy, Clone)]
+pub struct BasicRegisterAddresses {
+    pub R0: CoreRegisterAddress,
+    pub R1: CoreRegisterAddress,
+    pub R2: CoreRegisterAddress,
+    pub R3: CoreRegisterAddress,
+    pub R4: CoreRegisterAddress,
+    pub R9: CoreRegisterAddress,
+    pub PC: CoreRegisterAddress,
+    pub LR: CoreRegisterAddress,
+    pub SP: CoreRegisterAddress,
+    pub XPSR: CoreRegisterAddress,
+}
+
+pub trait Core: std::fmt::Debug + objekt::Clone {
+    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Reads what the core is doing, without changing its state. The reason of a halt is not
+    /// cleared.
+    fn status(&self, mi: &mut MasterProbe) -> Result<CoreStatus, DebugProbeError>;
+
+    fn halt(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError>;
+
+    fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Resets the target in the given way, see `ResetType`. `reset` is a `SysResetReq`.
+    fn reset_with(
+        &self,
+        mi: &mut MasterProbe,
+        reset_type: ResetType,
+    ) -> Result<(), DebugProbeError>;
+
+    fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Steps one instruction and then enters halted state again.
+    fn step(&self, mi: &mut MasterProbe) -> Result<CpuInformation, DebugProbeError>;
+
+    fn read_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+    ) -> Result<u32, DebugProbeError>;
+
+    fn write_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+        value: u32,
+    ) -> Result<(), DebugProbeError>;
+
+    fn get_available_breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError>;
+
+    fn enable_breakpoints(&self, mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError>;
+
+    fn set_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError>;
+
+    fn enable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError>;
+
+    fn disable_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError>;
+
+    fn read_block8(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), DebugProbeError>;
+
+    fn registers<'a>(&self) -> &'a BasicRegisterAddresses;
+
+    /// Reads words of memory as the core sees them, for cores whose debug registers move data
+    /// through the core, like Cortex-A and Cortex-R cores. A running core is halted for the
+    /// read and resumed afterwards. Other cores return `None`.
+    fn read_block32_through_core(
+        &self,
+        _mi: &mut MasterProbe,
+        _address: u32,
+        _data: &mut [u32],
+    ) -> Option<Result<(), DebugProbeError>> {
+        None
+    }
+
+    /// Reads the vector catch settings of the core, which `detach` restores, so the debugger
+    /// leaves them as it found them. Cores without vector catching return `None`.
+    fn vector_catch(&self, _mi: &mut MasterProbe) -> Result<Option<u32>, DebugProbeError> {
+        Ok(None)
+    }
+
+    /// Restores the debug state of the core before the debugger disconnects.
+    ///
+    /// Removes all breakpoints, restores the vector catch settings `vector_catch` returned
+    /// when the session started, if given, and, if `resume` is set, resumes the core and
+    /// disables halting debug.
+    fn detach(
+        &self,
+        mi: &mut MasterProbe,
+        vector_catch: Option<u32>,
+        resume: bool,
+    ) -> Result<(), DebugProbeError>;
+
+    /// Forgets everything cached about the core, including register values not written yet,
+    /// as the core may have been reset or resumed while the probe was disconnected.
+    fn forget_state(&self) {}
+
+    fn architecture(&self) -> Architecture;
+}
+
+objekt::clone_trait_object!(Core);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub manufacturer: jep106::JEP106Code,
+    pub part: u16,
+    pub flash_algorithm: Option<String>,
+    pub memory_map: Vec<MemoryRegion>,
+    pub core: Box<dyn Core>,
+    /// TARGETSEL value used to select the target on a multi-drop SWD bus.
+    #[serde(default)]
+    pub target_sel: Option<u32>,
+    /// TARGETSEL values of the other cores of a multi-drop chip, like the second core of the
+    /// RP2040. They are halted while flashing and reset along with the first core.
+    #[serde(default)]
+    pub secondary_cores: Vec<u32>,
+    /// How the target is reset by default, with SYSRESETREQ unless the description says
+    /// otherwise, as many boards do not wire nRESET.
+    #[serde(default)]
+    pub reset_type: ResetType,
+    /// Actions run before the flash algorithm, e.g. to disable a watchdog.
+    #[serde(default)]
+    pub pre_program: Vec<FlashAction>,
+    /// Actions run after the flash algorithm.
+    #[serde(default)]
+    pub post_program: Vec<FlashAction>,
+    /// How the chip deviates from the part it copies, if it is a clone.
+    #[serde(default)]
+    pub quirks: Vec<Quirk>,
+    /// The byte order of the data in memory, `little` unless the core is configured for
+    /// big-endian.
+    #[serde(default)]
+    pub endian: Endian,
+}
+
+pub type TargetParseError = serde_yaml::Error;
+
+impl Target {
+    pub fn new(definition: &str) -> Result<Self, TargetParseError> {
+        serde_yaml::from_str(definition)
+    }
+
+    /// Parses a definition which may inherit from a base definition with `inherits: <base>`.
+    ///
+    /// `base` returns the definition of a base by its name.
+    pub fn with_bases(
+        definition: &str,
+        base: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, TargetParseError> {
+        serde_yaml::from_value(inheritance::resolve(definition, &base)?)
+    }
+
+    pub fn has_quirk(&self, quirk: Quirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+
+    /// Calls `f` with the debug port of each secondary core selected, and selects the one of
+    /// the first core again afterwards, also if `f` fails.
+    pub fn for_each_secondary_core<F>(
+        &self,
+        probe: &mut MasterProbe,
+        mut f: F,
+    ) -> Result<(), DebugProbeError>
+    where
+        F: FnMut(&dyn Core, &mut MasterProbe) -> Result<(), DebugProbeError>,
+    {
+        if self.secondary_cores.is_empty() {
+            return Ok(());
+        }
+
+        let primary = probe.multidrop_target();
+        let result = self.secondary_cores.iter().try_for_each(|&target_sel| {
+            probe.select_multidrop_target(Some(target_sel))?;
+            f(self.core.as_ref(), probe)
+        });
+        probe.select_multidrop_target(primary)?;
+
+        result
+    }
+}
+
+struct CoreVisitor;
+
+impl<'de> serde::de::Visitor<'de> for CoreVisitor {
+    type Value = Box<dyn Core>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter,
+            "an existing core name, or a map with the name, ap and debug_base of the core"
+        )
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        if let Some(core) = get_core(s) {
+            Ok(core)
+        } else {
+            Err(Error::invalid_value(
+                Unexpected::Other(&format!("Core {} does not exist.", s)),
+                &self,
+            ))
+        }
+    }
+
+    /// Cores with their debug registers at a chip specific address, given as
+    /// `{ name: CortexA, ap: 1, debug_base: 0x80090000 }` or
+    /// `{ name: CortexR, ap: 1, debug_base: 0x80110000 }`.
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut name: Option<String> = None;
+        let mut ap = None;
+        let mut debug_base = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "name" => name = Some(map.next_value()?),
+                "ap" => ap = Some(map.next_value()?),
+                "debug_base" => debug_base = Some(map.next_value()?),
+                _ => return Err(A::Error::unknown_field(&key, &["name", "ap", "debug_base"])),
+            }
+        }
+
+        let name = name.ok_or_else(|| A::Error::missing_field("name"))?;
+        let debug_base = debug_base.ok_or_else(|| A::Error::missing_field("debug_base"))?;
+
+        if let Some(core) = get_core_at(&name, ap.unwrap_or(0), debug_base) {
+            Ok(core)
+        } else {
+            Err(A::Error::invalid_value(
+                Unexpected::Other(&format!("Core {} does not exist.", name)),
+                &self,
+            ))
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Box<dyn Core> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(CoreVisitor)
+    }
+}
+
+#[derive(Debug)]
+pub enum TargetSelectionError {
+    InfoReadError(ReadError),
+    TargetNotFound(String),
+    TargetCouldNotBeParsed(TargetParseError),
+}
+
+impl fmt::Display for TargetSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use TargetSelectionError::*;
+
+        match self {
+            InfoReadError(e) => write!(f, "Failed to read target into: {}", e),
+            TargetNotFound(ref t) => write!(f, "Failed to find target defintion for target {}", t),
+            TargetCouldNotBeParsed(ref e) => {
+                write!(f, "Failed to parse target definition for target: ")?;
+                e.fmt(f)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TargetSelectionError {}
+
+impl From<TargetParseError> for TargetSelectionError {
+    fn from(error: TargetParseError) -> Self {
+        TargetSelectionError::TargetCouldNotBeParsed(error)
+    }
+}
+
+impl From<ReadError> for TargetSelectionError {
+    fn from(e: ReadError) -> Self {
+        TargetSelectionError::InfoReadError(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Endian;
+
+    #[test]
+    fn decodes_words_in_the_byte_order_of_the_target() {
+        let bytes = [0x12, 0x34, 0x56, 0x78];
+
+        assert_eq!(Endian::Little.u32_from_bytes(bytes), 0x7856_3412);
+        assert_eq!(Endian::Big.u32_from_bytes(bytes), 0x1234_5678);
+        assert_eq!(Endian::Big.uint_from_bytes(&bytes[..2]), 0x1234);
+        assert_eq!(Endian::Big.u32_to_bytes(0x1234_5678), bytes);
+
+        // The access port reads the bytes in the order of a little-endian word.
+        let read = u32::from_le_bytes(bytes);
+        assert_eq!(
+            Endian::Big.swap_access_port_word(read),
+            Endian::Big.u32_from_bytes(bytes)
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Deviations of chips from the ARM debug architecture and from the flash controllers they
+//! copy, as found on many clones of STM32 parts.
+//!
+//! They are listed in the `quirks` of a target description, e.g.
+//! `quirks: [{ Dpidr: 0x2BA01477 }, StagedPowerUp, NoChipErase]`, so the generic attach and
+//! flashing sequences handle these chips without a copy of their own.
+
+/// A deviation of a chip from the part it copies.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Quirk {
+    /// The debug port reports this DPIDR instead of the one of an ARM debug port.
+    ///
+    /// Clones usually copy the ROM table of the original part as well, so when detecting the
+    /// chip, the target with the DPIDR read from the debug port is preferred.
+    Dpidr(u32),
+    /// The debug port only powers up if debug power is requested before system power, and
+    /// acknowledges the requests with a delay.
+    StagedPowerUp,
+    /// The mass erase of the flash controller does not work, so the sectors are always erased
+    /// one by one.
+    NoChipErase,
+}
+
+/// Returns the DPIDR the chip reports instead of a standard one, if it does.
+pub fn dpidr(quirks: &[Quirk]) -> Option<u32> {
+    quirks.iter().find_map(|quirk| match quirk {
+        Quirk::Dpidr(dpidr) => Some(*dpidr),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dpidr, Quirk};
+
+    #[test]
+    fn parses_quirks() {
+        let quirks: Vec<Quirk> =
+            serde_yaml::from_str("[{ Dpidr: 0x2BA01477 }, StagedPowerUp, NoChipErase]").unwrap();
+
+        assert_eq!(
+            quirks,
+            vec![
+                Quirk::Dpidr(0x2BA0_1477),
+                Quirk::StagedPowerUp,
+                Quirk::NoChipErase
+            ]
+        );
+        assert_eq!(dpidr(&quirks), Some(0x2BA0_1477));
+        assert_eq!(dpidr(&[Quirk::NoChipErase]), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! Python bindings of probe-rs, so test suites written in Python can drive targets without
+//! running the CLI and parsing its output.
+//!
+//! ```python
+//! import pyprobe_rs
+//!
+//! session = pyprobe_rs.Session(chip="nRF52832")
+//! session.flash("target/thumbv7em-none-eabihf/debug/firmware")
+//! session.reset()
+//! assert session.read32(0x2000_0000) == 0xCAFE_F00D
+//! ```
+//!
+//! All failures raise `pyprobe_rs.ProbeRsError`.
+
+use probe_rs::{
+    memory::MI,
+    probe::{
+        flash::download::{FileDownloader, Format},
+        watch,
+    },
+    session,
+    target::CoreRegisterAddress,
+};
+use probe_rs_targets::BuiltInTargets;
+
+use pyo3::create_exception;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use pyo3::wrap_pyfunction;
+
+use std::fmt::Display;
+use std::path::Path;
+
+create_exception!(pyprobe_rs, ProbeRsError, pyo3::exceptions::Exception);
+
+fn error(e: impl Display) -> PyErr {
+    ProbeRsError::py_err(e.to_string())
+}
+
+/// A session with a target, attached through a probe.
+#[pyclass]
+struct Session {
+    session: session::Session,
+}
+
+#[pymethods]
+impl Session {
+    /// Opens the probe with the given index in `list_probes()`, or the only one connected, and
+    /// attaches to the chip with the given name, or the one detected through the probe.
+    #[new]
+    #[args(chip = "None", probe = "None")]
+    fn new(obj: &PyRawObject, chip: Option<String>, probe: Option<usize>) -> PyResult<()> {
+        let mut builder = session::Session::builder().targets(BuiltInTargets);
+        if let Some(index) = probe {
+            builder = builder.probe(index);
+        }
+        if let Some(name) = chip {
+            builder = builder.target(name);
+        }
+
+        obj.init(Session {
+            session: builder.open().map_err(error)?,
+        });
+
+        Ok(())
+    }
+
+    /// The name of the target.
+    #[getter]
+    fn target(&self) -> String {
+        self.session.target.name.clone()
+    }
+
+    fn read(&mut self, py: Python, address: u32, length: usize) -> PyResult<PyObject> {
+        let mut data = vec![0; length];
+        self.session
+            .probe
+            .read_block8(address, &mut data)
+            .map_err(error)?;
+
+        Ok(PyBytes::new(py, &data).into())
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> PyResult<()> {
+        self.session
+            .probe
+            .write_block8(address, data)
+            .map_err(error)
+    }
+
+    fn read32(&mut self, address: u32) -> PyResult<u32> {
+        self.session.probe.read32(address).map_err(error)
+    }
+
+    fn write32(&mut self, address: u32, value: u32) -> PyResult<()> {
+        self.session.probe.write32(address, value).map_err(error)
+    }
+
+    /// Halts the core and returns the program counter.
+    fn halt(&mut self) -> PyResult<u32> {
+        Ok(self.session.halt().map_err(error)?.pc)
+    }
+
+    /// Returns what the core is doing, e.g. `running` or `halted (breakpoint)`.
+    fn status(&mut self) -> PyResult<String> {
+        let status = self
+            .session
+            .target
+            .core
+            .status(&mut self.session.probe)
+            .map_err(error)?;
+
+        Ok(status.to_string())
+    }
+
+    fn resume(&mut self) -> PyResult<()> {
+        self.session
+            .target
+            .core
+            .run(&mut self.session.probe)
+            .map_err(error)
+    }
+
+    /// Executes a single instruction and returns the program counter.
+    fn step(&mut self) -> PyResult<u32> {
+        Ok(self.session.step().map_err(error)?.pc)
+    }
+
+    /// Resets the target with its reset type.
+    fn reset(&mut self) -> PyResult<()> {
+        let reset_type = self.session.target.reset_type;
+        self.session.target_reset(reset_type).map_err(error)
+    }
+
+    /// Reads a core register, `r0` to `r15`, `sp`, `lr`, `pc` or `xpsr`.
+    fn read_register(&mut self, name: &str) -> PyResult<u32> {
+        let address = self.register(name)?;
+        self.session
+            .target
+            .core
+            .read_core_reg(&mut self.session.probe, address)
+            .map_err(error)
+    }
+
+    fn write_register(&mut self, name: &str, value: u32) -> PyResult<()> {
+        let address = self.register(name)?;
+        self.session
+            .target
+            .core
+            .write_core_reg(&mut self.session.probe, address, value)
+            .map_err(error)
+    }
+
+    /// Programs a file into the flash of the target, detecting its format.
+    fn flash(&mut self, path: &str) -> PyResult<()> {
+        let path = Path::new(path);
+        let format = Format::detect(path).map_err(error)?;
+        let memory_map = self.session.target.memory_map.clone();
+
+        FileDownloader::new()
+            .download_file(&mut self.session, path, format, &memory_map)
+            .map_err(error)?;
+
+        Ok(())
+    }
+}
+
+impl Session {
+    fn register(&self, name: &str) -> PyResult<CoreRegisterAddress> {
+        let registers = self.session.target.core.registers();
+
+        match name.to_lowercase().as_str() {
+            "sp" => Ok(registers.SP),
+            "lr" => Ok(registers.LR),
+            "pc" => Ok(registers.PC),
+            "xpsr" => Ok(registers.XPSR),
+            name if name.starts_with('r') => match name[1..].parse::<u8>() {
+                Ok(index) if index < 16 => Ok(CoreRegisterAddress(index)),
+                _ => Err(error(format!("There is no register {}.", name))),
+            },
+            name => Err(error(format!("There is no register {}.", name))),
+        }
+    }
+}
+
+/// Returns a description of each connected probe, in the order of the `probe` argument of
+/// `Session`.
+#[pyfunction]
+fn list_probes() -> Vec<String> {
+    watch::list_probes()
+        .iter()
+        .map(|probe| probe.to_string())
+        .collect()
+}
+
+#[pymodule]
+fn pyprobe_rs(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Session>()?;
+    m.add_wrapped(wrap_pyfunction!(list_probes))?;
+    m.add("ProbeRsError", py.get_type::<ProbeRsError>())?;
+
+    Ok(())
+}
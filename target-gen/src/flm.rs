@@ -0,0 +1,261 @@
+//! Extraction of CMSIS flash algorithms from FLM files.
+//!
+//! An FLM is an ELF file with the position independent code of the algorithm in the
+//! `PrgCode` section, its data in the `PrgData` sections and a `FlashDevice` struct in the
+//! `DevDscr` section, which describes the flash the algorithm programs.
+
+use goblin::elf::{section_header::SHT_NOBITS, Elf};
+
+use std::convert::TryInto;
+use std::fmt;
+
+/// Size of the `DevName` field of the `FlashDevice` struct.
+const DEVICE_NAME_SIZE: usize = 128;
+/// Offset of the sector list in the `FlashDevice` struct.
+const SECTORS_OFFSET: usize = 160;
+/// Marks the end of the sector list.
+const SECTORS_END: u32 = 0xFFFF_FFFF;
+/// `DevType` of flash inside the microcontroller.
+const DEVICE_TYPE_ON_CHIP: u16 = 1;
+
+#[derive(Debug)]
+pub enum FlmError {
+    Elf(goblin::error::Error),
+    MissingSection(&'static str),
+    MissingSymbol(&'static str),
+    TruncatedSection(String),
+    /// The `FlashDevice` struct is truncated or its sector list is not terminated.
+    InvalidDevice,
+}
+
+impl std::error::Error for FlmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FlmError::Elf(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FlmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlmError::Elf(e) => write!(f, "The FLM is not a valid ELF file: {}", e),
+            FlmError::MissingSection(name) => write!(f, "The FLM has no {} section.", name),
+            FlmError::MissingSymbol(name) => {
+                write!(f, "The FLM does not define the {} function.", name)
+            }
+            FlmError::TruncatedSection(name) => {
+                write!(f, "The {} section is truncated.", name)
+            }
+            FlmError::InvalidDevice => write!(f, "The FlashDevice description is invalid."),
+        }
+    }
+}
+
+impl From<goblin::error::Error> for FlmError {
+    fn from(e: goblin::error::Error) -> Self {
+        FlmError::Elf(e)
+    }
+}
+
+/// The flash described by the `FlashDevice` struct.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlashDevice {
+    pub name: String,
+    pub on_chip: bool,
+    pub address: u32,
+    pub size: u32,
+    pub page_size: u32,
+    pub erased_byte_value: u8,
+    /// Timeout of programming a page in ms.
+    pub program_timeout: u32,
+    /// Timeout of erasing a sector in ms.
+    pub erase_timeout: u32,
+    /// The sector sizes with the offset at which they start, in ascending order.
+    pub sectors: Vec<(u32, u32)>,
+}
+
+impl FlashDevice {
+    fn parse(data: &[u8]) -> Result<Self, FlmError> {
+        let u16_at = |offset: usize| -> Result<u16, FlmError> {
+            data.get(offset..offset + 2)
+                .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+                .ok_or(FlmError::InvalidDevice)
+        };
+        let u32_at = |offset: usize| -> Result<u32, FlmError> {
+            data.get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .ok_or(FlmError::InvalidDevice)
+        };
+
+        let name = data
+            .get(2..2 + DEVICE_NAME_SIZE)
+            .ok_or(FlmError::InvalidDevice)?;
+        let name = name.split(|b| *b == 0).next().unwrap_or_default();
+
+        let mut sectors = vec![];
+        let mut offset = SECTORS_OFFSET;
+        loop {
+            let size = u32_at(offset)?;
+            let address = u32_at(offset + 4)?;
+
+            if size == SECTORS_END && address == SECTORS_END {
+                break;
+            }
+
+            sectors.push((size, address));
+            offset += 8;
+        }
+
+        if sectors.is_empty() {
+            return Err(FlmError::InvalidDevice);
+        }
+
+        Ok(FlashDevice {
+            name: String::from_utf8_lossy(name).trim().to_string(),
+            on_chip: u16_at(130)? == DEVICE_TYPE_ON_CHIP,
+            address: u32_at(132)?,
+            size: u32_at(136)?,
+            page_size: u32_at(140)?,
+            erased_byte_value: *data.get(148).ok_or(FlmError::InvalidDevice)?,
+            program_timeout: u32_at(152)?,
+            erase_timeout: u32_at(156)?,
+            sectors,
+        })
+    }
+
+    /// Returns the flash as regions of equally sized sectors, as `(start, end, sector size)`.
+    pub fn regions(&self) -> Vec<(u32, u32, u32)> {
+        self.sectors
+            .iter()
+            .enumerate()
+            .map(|(i, (size, offset))| {
+                let end = self.sectors.get(i + 1).map_or(self.size, |(_, next)| *next);
+                (self.address + offset, self.address + end, *size)
+            })
+            .collect()
+    }
+}
+
+/// The contents of an FLM.
+#[derive(Debug)]
+pub struct Flm {
+    pub device: FlashDevice,
+    /// Code and data of the algorithm, as it is laid out in RAM, with the bss zeroed.
+    pub image: Vec<u8>,
+    /// Offset of the data in the image.
+    pub data_offset: u32,
+    pub init: Option<u32>,
+    pub uninit: Option<u32>,
+    pub program_page: u32,
+    pub erase_sector: u32,
+    pub erase_chip: Option<u32>,
+    /// Offset of the `FlashProgress` word `EraseChip` reports its progress in, if it does.
+    pub progress: Option<u32>,
+}
+
+impl Flm {
+    pub fn parse(buffer: &[u8]) -> Result<Self, FlmError> {
+        let elf = Elf::parse(buffer)?;
+
+        let mut device = None;
+        let mut image = vec![];
+        let mut code = false;
+        let mut data_offset = None;
+
+        for sh in &elf.section_headers {
+            let name = &elf.shdr_strtab[sh.sh_name];
+            let start = sh.sh_addr as usize;
+            let end = start + sh.sh_size as usize;
+            let contents = || {
+                let offset = sh.sh_offset as usize;
+                buffer
+                    .get(offset..offset + sh.sh_size as usize)
+                    .ok_or_else(|| FlmError::TruncatedSection(name.to_string()))
+            };
+
+            match name {
+                "DevDscr" => {
+                    device = Some(FlashDevice::parse(contents()?)?);
+                }
+                "PrgCode" | "PrgData" => {
+                    if image.len() < end {
+                        image.resize(end, 0);
+                    }
+
+                    // The bss has no contents in the file and stays zeroed.
+                    if sh.sh_type != SHT_NOBITS {
+                        image[start..end].copy_from_slice(contents()?);
+                    }
+
+                    if name == "PrgCode" {
+                        code = true;
+                    } else {
+                        data_offset = Some(data_offset.map_or(start, |offset| start.min(offset)));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if !code {
+            return Err(FlmError::MissingSection("PrgCode"));
+        }
+        let device = device.ok_or(FlmError::MissingSection("DevDscr"))?;
+
+        // Keep the image word aligned, as it is loaded as a list of words.
+        image.resize((image.len() + 3) & !3, 0);
+
+        let symbol = |name: &str| {
+            elf.syms
+                .iter()
+                .find(|sym| &elf.strtab[sym.st_name] == name)
+                .map(|sym| sym.st_value as u32)
+        };
+
+        Ok(Flm {
+            device,
+            data_offset: data_offset.unwrap_or(image.len()) as u32,
+            init: symbol("Init"),
+            uninit: symbol("UnInit"),
+            program_page: symbol("ProgramPage").ok_or(FlmError::MissingSymbol("ProgramPage"))?,
+            erase_sector: symbol("EraseSector").ok_or(FlmError::MissingSymbol("EraseSector"))?,
+            erase_chip: symbol("EraseChip"),
+            progress: symbol("FlashProgress"),
+            image,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FlashDevice;
+
+    #[test]
+    fn parses_flash_device() {
+        let mut data = vec![0; 176];
+        data[2..10].copy_from_slice(b"Test 1MB");
+        data[130] = 1;
+        data[132..136].copy_from_slice(&0x0800_0000u32.to_le_bytes());
+        data[136..140].copy_from_slice(&0x0010_0000u32.to_le_bytes());
+        data[140..144].copy_from_slice(&0x400u32.to_le_bytes());
+        data[148] = 0xFF;
+        data[160..164].copy_from_slice(&0x4000u32.to_le_bytes());
+        data[168..172].copy_from_slice(&0x2_0000u32.to_le_bytes());
+        data[172..176].copy_from_slice(&0x1_0000u32.to_le_bytes());
+        data.extend_from_slice(&[0xFF; 8]);
+
+        let device = FlashDevice::parse(&data).unwrap();
+
+        assert_eq!(device.name, "Test 1MB");
+        assert!(device.on_chip);
+        assert_eq!(
+            device.regions(),
+            vec![
+                (0x0800_0000, 0x0801_0000, 0x4000),
+                (0x0801_0000, 0x0810_0000, 0x2_0000),
+            ]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Generation of the target and flash algorithm descriptions, in the format of the files in
+//! `probe-rs-targets`.
+
+use crate::flm::Flm;
+
+use probe_rs::probe::flash::{ERASE_ALL_WEIGHT, ERASE_SECTOR_WEIGHT, PROGRAM_PAGE_WEIGHT};
+
+use std::fmt::Write;
+
+/// Breakpoint stub placed in front of the algorithm. The algorithm functions return to it,
+/// which halts the core.
+const HEADER: [u32; 8] = [
+    0xE00A_BE00,
+    0x062D_780D,
+    0x2408_4068,
+    0xD300_0040,
+    0x1E64_4058,
+    0x1C49_D1FA,
+    0x2A00_1E52,
+    0x4770_D1F2,
+];
+
+/// Size of the stack of the algorithm.
+const STACK_SIZE: u32 = 0x800;
+/// Space the CRC32 analyzer needs.
+const ANALYZER_SIZE: u32 = 0x600;
+/// Words per line of the instructions.
+const WORDS_PER_LINE: usize = 8;
+
+/// Where the parts of the algorithm are placed in RAM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub load_address: u32,
+    pub static_base: u32,
+    pub begin_stack: u32,
+    pub page_buffers: [u32; 2],
+    pub analyzer_address: u32,
+    /// The first address after the analyzer.
+    pub end: u32,
+}
+
+impl Layout {
+    /// Places the algorithm at the start of the RAM, followed by its stack, two page buffers
+    /// for double buffered programming and the analyzer.
+    pub fn new(flm: &Flm, ram_start: u32) -> Self {
+        let load_address = ram_start;
+        let code_start = load_address + HEADER.len() as u32 * 4;
+        let begin_stack = align(code_start + flm.image.len() as u32 + STACK_SIZE);
+        let page_size = align(flm.device.page_size);
+        let page_buffers = [begin_stack, begin_stack + page_size];
+        let analyzer_address = page_buffers[1] + page_size;
+
+        Layout {
+            load_address,
+            static_base: code_start + flm.data_offset,
+            begin_stack,
+            page_buffers,
+            analyzer_address,
+            end: analyzer_address + ANALYZER_SIZE,
+        }
+    }
+
+    /// Returns the address of a function or a variable of the algorithm.
+    fn address(&self, offset: u32) -> u32 {
+        self.load_address + HEADER.len() as u32 * 4 + offset
+    }
+}
+
+fn align(address: u32) -> u32 {
+    (address + 0xFF) & !0xFF
+}
+
+/// Returns the description of the flash algorithm.
+pub fn algorithm(flm: &Flm, layout: &Layout) -> String {
+    let words: Vec<u32> = HEADER
+        .iter()
+        .cloned()
+        .chain(flm.image.chunks(4).map(|word| {
+            word.iter()
+                .rev()
+                .fold(0, |value, byte| value << 8 | u32::from(*byte))
+        }))
+        .collect();
+
+    let optional = |offset: Option<u32>| match offset {
+        Some(offset) => format!("{:#010x}", layout.address(offset)),
+        None => "null".to_string(),
+    };
+
+    let mut yaml = String::new();
+    writeln!(yaml, "load_address: {:#010x}", layout.load_address).unwrap();
+    writeln!(yaml, "instructions: [").unwrap();
+    for line in words.chunks(WORDS_PER_LINE) {
+        let line: Vec<_> = line.iter().map(|word| format!("{:#010x}", word)).collect();
+        writeln!(yaml, "    {},", line.join(", ")).unwrap();
+    }
+    writeln!(yaml, "]").unwrap();
+    writeln!(yaml, "pc_init: {}", optional(flm.init)).unwrap();
+    writeln!(yaml, "pc_uninit: {}", optional(flm.uninit)).unwrap();
+    writeln!(
+        yaml,
+        "pc_program_page: {:#010x}",
+        layout.address(flm.program_page)
+    )
+    .unwrap();
+    writeln!(
+        yaml,
+        "pc_erase_sector: {:#010x}",
+        layout.address(flm.erase_sector)
+    )
+    .unwrap();
+    writeln!(yaml, "pc_erase_all: {}", optional(flm.erase_chip)).unwrap();
+    writeln!(yaml, "progress_address: {}", optional(flm.progress)).unwrap();
+    writeln!(yaml, "static_base: {:#010x}", layout.static_base).unwrap();
+    writeln!(yaml, "begin_stack: {:#010x}", layout.begin_stack).unwrap();
+    writeln!(yaml, "begin_data: {:#010x}", layout.page_buffers[0]).unwrap();
+    writeln!(
+        yaml,
+        "page_buffers: [{:#010x}, {:#010x}]",
+        layout.page_buffers[0], layout.page_buffers[1]
+    )
+    .unwrap();
+    writeln!(yaml, "min_program_length: 4").unwrap();
+    writeln!(yaml, "analyzer_supported: true").unwrap();
+    writeln!(yaml, "analyzer_address: {:#010x}", layout.analyzer_address).unwrap();
+
+    yaml
+}
+
+/// Returns the description of a target using the flash algorithm.
+///
+/// The manufacturer and the part are not part of the FLM and are left as placeholders.
+pub fn target(flm: &Flm, name: &str, core: &str, ram: (u32, u32)) -> String {
+    let mut yaml = String::new();
+    writeln!(yaml, "name: \"{}\"", name).unwrap();
+    writeln!(
+        yaml,
+        "# TODO: Manufacturer and Part are not known yet. They are only needed for autodetection."
+    )
+    .unwrap();
+    writeln!(yaml, "manufacturer:\n  cc: 0x00\n  id: 0x00").unwrap();
+    writeln!(yaml, "part: 0x0000").unwrap();
+    writeln!(yaml, "flash_algorithm: \"{}.yaml\"", name).unwrap();
+    writeln!(yaml, "memory_map:").unwrap();
+
+    let page_size = flm.device.page_size;
+    for (i, (start, end, sector_size)) in flm.device.regions().into_iter().enumerate() {
+        writeln!(yaml, "    - Flash:").unwrap();
+        writeln!(yaml, "        range:").unwrap();
+        writeln!(yaml, "          start: {:#x}", start).unwrap();
+        writeln!(yaml, "          end: {:#x}", end).unwrap();
+        writeln!(
+            yaml,
+            "        is_boot_memory: {}",
+            flm.device.on_chip && i == 0
+        )
+        .unwrap();
+        writeln!(yaml, "        is_testable: true").unwrap();
+        writeln!(yaml, "        blocksize: {:#x}", sector_size).unwrap();
+        writeln!(yaml, "        sector_size: {:#x}", sector_size).unwrap();
+        writeln!(yaml, "        page_size: {:#x}", page_size).unwrap();
+        writeln!(yaml, "        phrase_size: {:#x}", page_size).unwrap();
+        writeln!(yaml, "        erase_all_weight: {:.3}", ERASE_ALL_WEIGHT).unwrap();
+        writeln!(
+            yaml,
+            "        erase_sector_weight: {:.3}",
+            ERASE_SECTOR_WEIGHT
+        )
+        .unwrap();
+        writeln!(
+            yaml,
+            "        program_page_weight: {:.3}",
+            PROGRAM_PAGE_WEIGHT
+        )
+        .unwrap();
+        writeln!(
+            yaml,
+            "        erased_byte_value: {:#04X}",
+            flm.device.erased_byte_value
+        )
+        .unwrap();
+        writeln!(yaml, "        access: 0b00000101").unwrap();
+        writeln!(yaml, "        are_erased_sectors_readable: true").unwrap();
+    }
+
+    writeln!(yaml, "    - Ram:").unwrap();
+    writeln!(yaml, "        range:").unwrap();
+    writeln!(yaml, "          start: {:#x}", ram.0).unwrap();
+    writeln!(yaml, "          end: {:#x}", ram.1).unwrap();
+    writeln!(yaml, "        is_boot_memory: false").unwrap();
+    writeln!(yaml, "        is_testable: true").unwrap();
+    writeln!(yaml, "core: \"{}\"", core).unwrap();
+
+    yaml
+}
+
+#[cfg(test)]
+mod test {
+    use super::Layout;
+    use crate::flm::{FlashDevice, Flm};
+
+    #[test]
+    fn places_algorithm_at_ram_start() {
+        let flm = Flm {
+            device: FlashDevice {
+                name: "Test".to_string(),
+                on_chip: true,
+                address: 0,
+                size: 0x8_0000,
+                page_size: 0x1000,
+                erased_byte_value: 0xFF,
+                program_timeout: 100,
+                erase_timeout: 3000,
+                sectors: vec![(0x1000, 0)],
+            },
+            image: vec![0; 0x150],
+            data_offset: 0x140,
+            init: Some(1),
+            uninit: None,
+            program_page: 0x51,
+            erase_sector: 0x29,
+            erase_chip: None,
+            progress: Some(0x148),
+        };
+
+        let layout = Layout::new(&flm, 0x2000_0000);
+
+        assert_eq!(layout.static_base, 0x2000_0160);
+        assert_eq!(layout.address(0x51), 0x2000_0071);
+        assert_eq!(layout.address(0x148), 0x2000_0168);
+        assert_eq!(layout.begin_stack, 0x2000_0a00);
+        assert_eq!(layout.page_buffers, [0x2000_0a00, 0x2000_1a00]);
+        assert_eq!(layout.analyzer_address, 0x2000_2a00);
+    }
+}
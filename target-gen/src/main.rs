@@ -0,0 +1,160 @@
+mod flm;
+mod generate;
+
+use flm::{Flm, FlmError};
+use generate::Layout;
+
+use probe_rs::{
+    probe::flash::FlashAlgorithm,
+    target::{Target, TargetParseError},
+};
+
+use std::{error::Error, fmt, fs, io, num::ParseIntError, path::PathBuf, process};
+use structopt::StructOpt;
+
+fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
+    u32::from_str_radix(src.trim_start_matches("0x"), 16)
+}
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "target-gen",
+    about = "Generates probe-rs target descriptions.",
+    author = "Noah Hüsser <yatekii@yatekii.ch> / Dominik Böhi <dominik.boehi@gmail.ch>"
+)]
+enum Opt {
+    /// Generates a flash algorithm and a target description from a CMSIS flash algorithm (FLM)
+    #[structopt(name = "elf")]
+    Elf {
+        /// The FLM file
+        #[structopt(parse(from_os_str))]
+        flm: PathBuf,
+        /// The name of the target, the device name in the FLM by default
+        #[structopt(long)]
+        name: Option<String>,
+        /// The core of the target, e.g. `M0` or `M4`
+        #[structopt(long)]
+        core: String,
+        /// The start of the RAM the algorithm is loaded to (in hex)
+        #[structopt(long, parse(try_from_str = parse_hex), default_value = "20000000")]
+        ram_start: u32,
+        /// The size of the RAM (in hex)
+        #[structopt(long, parse(try_from_str = parse_hex))]
+        ram_size: u32,
+        /// The directory with the `targets` and `algorithms` directories to write the
+        /// descriptions to. They are printed if it is not given
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+fn main() {
+    let result = match Opt::from_args() {
+        Opt::Elf {
+            flm,
+            name,
+            core,
+            ram_start,
+            ram_size,
+            output,
+        } => elf(flm, name, &core, (ram_start, ram_start + ram_size), output),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn elf(
+    path: PathBuf,
+    name: Option<String>,
+    core: &str,
+    ram: (u32, u32),
+    output: Option<PathBuf>,
+) -> Result<(), TargetGenError> {
+    let flm = Flm::parse(&fs::read(path)?)?;
+    let name = name.unwrap_or_else(|| flm.device.name.replace(' ', "_"));
+
+    let layout = Layout::new(&flm, ram.0);
+    if layout.end > ram.1 {
+        return Err(TargetGenError::RamTooSmall(layout.end - ram.0));
+    }
+
+    let algorithm = generate::algorithm(&flm, &layout);
+    let target = generate::target(&flm, &name, core, ram);
+
+    // Make sure that probe-rs can load what was generated, this also checks the core name.
+    FlashAlgorithm::new(&algorithm).map_err(TargetGenError::InvalidDescription)?;
+    Target::new(&target).map_err(TargetGenError::InvalidDescription)?;
+
+    match output {
+        Some(output) => {
+            let file = format!("{}.yaml", name);
+            let algorithm_path = output.join("algorithms").join(&file);
+            let target_path = output.join("targets").join(&file);
+
+            fs::write(&algorithm_path, algorithm)?;
+            fs::write(&target_path, target)?;
+
+            println!("Wrote {:?} and {:?}.", algorithm_path, target_path);
+        }
+        None => {
+            println!("# algorithms/{}.yaml", name);
+            println!("{}", algorithm);
+            println!("# targets/{}.yaml", name);
+            print!("{}", target);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+enum TargetGenError {
+    Io(io::Error),
+    Flm(FlmError),
+    /// The RAM is smaller than the given number of bytes the algorithm needs.
+    RamTooSmall(u32),
+    InvalidDescription(TargetParseError),
+}
+
+impl Error for TargetGenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TargetGenError::Io(e) => Some(e),
+            TargetGenError::Flm(e) => Some(e),
+            TargetGenError::RamTooSmall(_) => None,
+            TargetGenError::InvalidDescription(e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for TargetGenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetGenError::Io(e) => write!(f, "{}", e),
+            TargetGenError::Flm(e) => write!(f, "{}", e),
+            TargetGenError::RamTooSmall(size) => write!(
+                f,
+                "The algorithm needs {:#x} bytes of RAM, which is more than the RAM size.",
+                size
+            ),
+            TargetGenError::InvalidDescription(e) => {
+                write!(f, "The generated description is invalid: {}", e)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for TargetGenError {
+    fn from(e: io::Error) -> Self {
+        TargetGenError::Io(e)
+    }
+}
+
+impl From<FlmError> for TargetGenError {
+    fn from(e: FlmError) -> Self {
+        TargetGenError::Flm(e)
+    }
+}